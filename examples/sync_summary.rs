@@ -0,0 +1,42 @@
+//! Sync two directories one way and print a summary of what changed,
+//! using only `fman::ops`.
+//!
+//! ```text
+//! cargo run --example sync_summary -- SOURCE REPLICA
+//! ```
+
+use std::process::ExitCode;
+
+use fman::ops::{self, SyncAction, SyncRequest};
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let [source, replica] = args.as_slice() else {
+        eprintln!("usage: sync_summary SOURCE REPLICA");
+        return ExitCode::FAILURE;
+    };
+    let report = match ops::sync(&SyncRequest::new(source, replica)) {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("sync failed: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    if report.actions.is_empty() {
+        println!("{replica} is up to date");
+        return ExitCode::SUCCESS;
+    }
+    let mut copied = 0;
+    for action in &report.actions {
+        match action {
+            SyncAction::Copy { path, .. } => {
+                copied += 1;
+                println!("  + {path}");
+            }
+            SyncAction::Delete { path, .. } => println!("  - {path}"),
+            SyncAction::Conflict { path, .. } => println!("  ! {path}"),
+        }
+    }
+    println!("{copied} of {} changes were copies", report.actions.len());
+    ExitCode::SUCCESS
+}
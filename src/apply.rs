@@ -0,0 +1,350 @@
+//! `fman apply`: carrying out a plan of filesystem operations, running the
+//! independent ones at once.
+//!
+//! A plan is JSON Lines, one [`FsOp`] per line in the form `fman` records
+//! them, such as `{"op":"copy","from":"a","to":"b","create_new":true}`.
+//! Each step's paths are worked out from its operation and the steps are
+//! ordered with [`schedule`]: a step waits for every earlier one whose
+//! paths overlap its own, so a directory is created before anything is
+//! copied into it and a file is copied before its source is removed.
+
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+
+#[cfg(feature = "json")]
+use serde::Serialize;
+
+use crate::budget::{BudgetUsage, ByteBudget};
+use crate::copy::{CopyOptions, copy_file};
+use crate::error::{FmanError, FmanResult};
+use crate::fs::{FsOp, SharedFs};
+use crate::schedule::{self, Dag, StepPaths};
+
+/// What carrying out a plan took.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "json", derive(Serialize))]
+pub struct ApplyReport {
+    pub steps: usize,
+    /// The longest chain of steps that had to wait for one another.
+    pub waves: usize,
+    /// Most steps allowed to run at once.
+    pub jobs: usize,
+    /// Use of the write budget, if the plan had one.
+    pub budget: Option<BudgetUsage>,
+}
+
+/// Read the plan at `path`, or from stdin if `path` is `-`.
+pub fn read_plan(path: &Path) -> FmanResult<Vec<FsOp>> {
+    let text = if path == Path::new("-") {
+        let mut text = String::new();
+        io::stdin().read_to_string(&mut text)?;
+        text
+    } else {
+        fs::read_to_string(path).map_err(|e| FmanError::io_at(path, e))?
+    };
+    parse_plan(&text)
+}
+
+/// The steps of a plan in JSON Lines form; blank lines are skipped.
+///
+/// Fails with `InvalidInput` naming the line of a step that cannot be
+/// read, or that cannot be carried out: a recorded `set-xattr` has no
+/// value to set.
+pub fn parse_plan(text: &str) -> FmanResult<Vec<FsOp>> {
+    let mut steps = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let op: FsOp = serde_json::from_str(line)
+            .map_err(|e| FmanError::InvalidInput(format!("plan line {}: {e}", number + 1)))?;
+        if let FsOp::SetXattr { name, .. } = &op {
+            return Err(FmanError::InvalidInput(format!(
+                "plan line {}: set-xattr {name} does not record the value to set",
+                number + 1
+            )));
+        }
+        steps.push(op);
+    }
+    Ok(steps)
+}
+
+/// The paths `op` reads and writes, for ordering it against other steps.
+pub fn step_paths(op: &FsOp) -> StepPaths {
+    let (reads, writes): (Vec<&Path>, Vec<&Path>) = match op {
+        FsOp::Copy { from, to, .. } => (vec![from], vec![to]),
+        FsOp::HardLink { original, link } => (vec![original], vec![link]),
+        FsOp::Rename { from, to } => (vec![], vec![from, to]),
+        FsOp::Symlink { link, .. } => (vec![], vec![link]),
+        FsOp::CreateFile { path, .. }
+        | FsOp::RemoveFile { path }
+        | FsOp::RemoveDir { path }
+        | FsOp::RemoveDirAll { path }
+        | FsOp::CreateDir { path }
+        | FsOp::CreateDirAll { path }
+        | FsOp::SetPermissions { path, .. }
+        | FsOp::SetTimes { path, .. }
+        | FsOp::SetOwner { path, .. }
+        | FsOp::SetXattr { path, .. }
+        | FsOp::RemoveXattr { path, .. } => (vec![], vec![path]),
+    };
+    StepPaths {
+        reads: reads.into_iter().map(normalize).collect(),
+        writes: writes.into_iter().map(normalize).collect(),
+    }
+}
+
+/// `path` without `.` components or a trailing separator, so that `./a/`
+/// and `a` overlap.
+fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| *component != Component::CurDir)
+        .collect()
+}
+
+/// Carry out `plan` through `filesystem`, up to `jobs` steps at a time;
+/// with one job, strictly in plan order.
+///
+/// Cycles are impossible in an inferred graph, but it is still checked
+/// before anything runs. After a failure no new step starts and the first
+/// error is returned. The data copied is charged to `budget`, if given.
+pub fn apply_plan(
+    plan: &[FsOp],
+    jobs: usize,
+    filesystem: &SharedFs,
+    budget: Option<&ByteBudget>,
+) -> FmanResult<ApplyReport> {
+    let paths: Vec<StepPaths> = plan.iter().map(step_paths).collect();
+    let dag = Dag::infer(&paths);
+    let waves = dag.waves()?.len();
+    schedule::run(&dag, jobs, |step| {
+        apply_step(&plan[step], filesystem, budget)
+    })?;
+    Ok(ApplyReport {
+        steps: plan.len(),
+        waves,
+        jobs: jobs.max(1),
+        budget: budget.map(ByteBudget::usage),
+    })
+}
+
+fn apply_step(op: &FsOp, filesystem: &SharedFs, budget: Option<&ByteBudget>) -> FmanResult<()> {
+    let at = |path: &Path| {
+        let path = path.to_path_buf();
+        move |e: io::Error| FmanError::io_at(&path, e)
+    };
+    match op {
+        FsOp::CreateFile { path, create_new } => filesystem
+            .create_file(path, *create_new)
+            .map(drop)
+            .map_err(at(path)),
+        FsOp::Copy {
+            from,
+            to,
+            create_new,
+        } => {
+            // A source an earlier step only planned, as in a dry run, has
+            // nothing to read yet: the copy can only be recorded.
+            if filesystem.exists(from) && fs::symlink_metadata(from).is_err() {
+                return filesystem
+                    .create_copy(from, to, *create_new)
+                    .map(drop)
+                    .map_err(at(to));
+            }
+            let mut options = CopyOptions::new().force(!create_new).fs(filesystem.clone());
+            if let Some(budget) = budget {
+                options = options.budget(budget.clone());
+            }
+            copy_file(from, to, &options).map(drop)
+        }
+        FsOp::Rename { from, to } => filesystem.rename(from, to).map_err(at(from)),
+        FsOp::RemoveFile { path } => filesystem.remove_file(path).map_err(at(path)),
+        FsOp::RemoveDir { path } => filesystem.remove_dir(path).map_err(at(path)),
+        FsOp::RemoveDirAll { path } => filesystem.remove_dir_all(path).map_err(at(path)),
+        FsOp::CreateDir { path } => filesystem.create_dir(path).map_err(at(path)),
+        FsOp::CreateDirAll { path } => filesystem.create_dir_all(path).map_err(at(path)),
+        FsOp::SetPermissions { path, readonly } => {
+            let mut permissions = fs::metadata(path).map_err(at(path))?.permissions();
+            permissions.set_readonly(*readonly);
+            filesystem
+                .set_permissions(path, permissions)
+                .map_err(at(path))
+        }
+        FsOp::SetTimes {
+            path,
+            accessed,
+            modified,
+        } => filesystem
+            .set_times(path, *accessed, *modified)
+            .map_err(at(path)),
+        FsOp::SetOwner { path, uid, gid } => {
+            filesystem.set_owner(path, *uid, *gid).map_err(at(path))
+        }
+        FsOp::SetXattr { name, .. } => Err(FmanError::InvalidInput(format!(
+            "set-xattr {name} does not record the value to set"
+        ))),
+        FsOp::RemoveXattr { path, name } => filesystem.remove_xattr(path, name).map_err(at(path)),
+        FsOp::Symlink { target, link } => filesystem.symlink(target, link).map_err(at(link)),
+        FsOp::HardLink { original, link } => filesystem.hard_link(original, link).map_err(at(link)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+
+    use tempfile::TempDir;
+
+    use crate::fs::{DryRunFs, RealFs};
+
+    #[test]
+    fn a_plan_is_one_recorded_operation_per_line() {
+        let plan = parse_plan(concat!(
+            "{\"op\":\"create-dir-all\",\"path\":\"d\"}\n",
+            "\n",
+            "{\"op\":\"copy\",\"from\":\"a\",\"to\":\"d/a\",\"create_new\":true}\n",
+        ))
+        .unwrap();
+        assert_eq!(
+            plan,
+            vec![
+                FsOp::CreateDirAll { path: "d".into() },
+                FsOp::Copy {
+                    from: "a".into(),
+                    to: "d/a".into(),
+                    create_new: true
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn a_bad_line_is_named() {
+        let err = parse_plan("{\"op\":\"remove-file\",\"path\":\"a\"}\n{\"op\":\"explode\"}\n")
+            .unwrap_err();
+        assert!(matches!(&err, FmanError::InvalidInput(m) if m.starts_with("plan line 2:")));
+        let err =
+            parse_plan("{\"op\":\"set-xattr\",\"path\":\"a\",\"name\":\"user.x\"}").unwrap_err();
+        assert!(matches!(&err, FmanError::InvalidInput(m) if m.contains("user.x")));
+    }
+
+    #[test]
+    fn steps_read_their_sources_and_write_their_destinations() {
+        let copy = step_paths(&FsOp::Copy {
+            from: "./a".into(),
+            to: "d/".into(),
+            create_new: false,
+        });
+        assert_eq!(copy.reads, vec![PathBuf::from("a")]);
+        assert_eq!(copy.writes, vec![PathBuf::from("d")]);
+        let rename = step_paths(&FsOp::Rename {
+            from: "a".into(),
+            to: "b".into(),
+        });
+        assert!(rename.reads.is_empty());
+        assert_eq!(rename.writes, vec![PathBuf::from("a"), PathBuf::from("b")]);
+        let link = step_paths(&FsOp::Symlink {
+            target: "a".into(),
+            link: "l".into(),
+        });
+        assert!(link.reads.is_empty());
+        assert_eq!(link.writes, vec![PathBuf::from("l")]);
+    }
+
+    #[test]
+    fn a_plan_runs_in_order_of_its_paths() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        fs::write(root.join("s"), "x").unwrap();
+        let plan = vec![
+            FsOp::CreateDirAll {
+                path: root.join("d/e"),
+            },
+            FsOp::Copy {
+                from: root.join("s"),
+                to: root.join("d/e/a"),
+                create_new: true,
+            },
+            FsOp::Rename {
+                from: root.join("d/e/a"),
+                to: root.join("d/b"),
+            },
+            FsOp::RemoveFile {
+                path: root.join("s"),
+            },
+        ];
+        let filesystem: SharedFs = Arc::new(RealFs);
+        let report = apply_plan(&plan, 4, &filesystem, None).unwrap();
+        assert_eq!((report.steps, report.waves, report.jobs), (4, 3, 4));
+        assert_eq!(fs::read_to_string(root.join("d/b")).unwrap(), "x");
+        assert!(!root.join("s").exists());
+        assert!(!root.join("d/e/a").exists());
+    }
+
+    #[test]
+    fn a_dry_run_plan_changes_nothing() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        let dry_run = Arc::new(DryRunFs::new());
+        let filesystem: SharedFs = dry_run.clone();
+        let plan = vec![
+            FsOp::CreateDir {
+                path: root.join("d"),
+            },
+            FsOp::CreateFile {
+                path: root.join("d/a"),
+                create_new: true,
+            },
+        ];
+        apply_plan(&plan, 2, &filesystem, None).unwrap();
+        assert!(!root.join("d").exists());
+        assert_eq!(dry_run.ops(), plan);
+    }
+
+    #[test]
+    fn a_budget_stops_the_copy_that_would_cross_it() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        fs::write(root.join("s"), [b'x'; 10]).unwrap();
+        let plan: Vec<FsOp> = ["a", "b", "c"]
+            .into_iter()
+            .map(|name| FsOp::Copy {
+                from: root.join("s"),
+                to: root.join(name),
+                create_new: true,
+            })
+            .collect();
+        let filesystem: SharedFs = Arc::new(RealFs);
+        let budget = ByteBudget::new(25);
+        let err = apply_plan(&plan, 1, &filesystem, Some(&budget)).unwrap_err();
+        assert!(
+            matches!(
+                err,
+                FmanError::QuotaExceeded {
+                    limit: 25,
+                    written: 20
+                }
+            ),
+            "{err:?}"
+        );
+        assert!(root.join("a").exists() && root.join("b").exists());
+        assert!(!root.join("c").exists());
+
+        let plan = [FsOp::Copy {
+            from: root.join("s"),
+            to: root.join("d"),
+            create_new: true,
+        }];
+        let report = apply_plan(&plan, 1, &filesystem, Some(&ByteBudget::new(10))).unwrap();
+        assert_eq!(
+            report.budget,
+            Some(BudgetUsage {
+                limit: 10,
+                written: 10
+            })
+        );
+    }
+}
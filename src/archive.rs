@@ -0,0 +1,1127 @@
+//! Packing a file or directory tree into a tar, gzipped tar or zip archive,
+//! and unpacking one, for `fman archive` and `fman extract`.
+
+use std::collections::HashSet;
+use std::fs::{self, File, Metadata, Permissions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+#[cfg(feature = "json")]
+use serde::Serialize;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::error::{Failures, FmanError, FmanResult};
+use crate::format::{format_time, parse_time};
+use crate::fs::Fs;
+use crate::list::mode_bits;
+use crate::validate::{ensure_exists, ensure_not_exists, ensure_parents_are_dirs, same_inode};
+use crate::walk::{SymlinkPolicy, Walk};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(Serialize), serde(rename_all = "kebab-case"))]
+pub enum ArchiveFormat {
+    Tar,
+    TarGz,
+    Zip,
+}
+
+impl ArchiveFormat {
+    /// The format named by `path`'s extension: `.tar`, `.tar.gz` (or
+    /// `.tgz`) or `.zip`, in any case.
+    pub fn from_path(path: &Path) -> FmanResult<Self> {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Ok(ArchiveFormat::TarGz)
+        } else if name.ends_with(".tar") {
+            Ok(ArchiveFormat::Tar)
+        } else if name.ends_with(".zip") {
+            Ok(ArchiveFormat::Zip)
+        } else {
+            Err(FmanError::InvalidInput(format!(
+                "cannot tell the archive format of {}; use .tar, .tar.gz or .zip",
+                path.display()
+            )))
+        }
+    }
+
+    /// The format of the archive at `path`, from its first bytes; an old
+    /// tar without the `ustar` magic falls back to the extension.
+    pub fn detect(path: &Path) -> FmanResult<Self> {
+        let mut head = Vec::with_capacity(512);
+        File::open(path)?.take(512).read_to_end(&mut head)?;
+        if head.starts_with(&[0x1f, 0x8b]) {
+            Ok(ArchiveFormat::TarGz)
+        } else if head.starts_with(b"PK\x03\x04") || head.starts_with(b"PK\x05\x06") {
+            Ok(ArchiveFormat::Zip)
+        } else if head.get(257..262) == Some(b"ustar") {
+            Ok(ArchiveFormat::Tar)
+        } else {
+            Self::from_path(path)
+        }
+    }
+}
+
+/// Outcome of [`create_archive`].
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "json", derive(Serialize))]
+pub struct ArchiveReport {
+    pub archive: PathBuf,
+    pub format: ArchiveFormat,
+    /// Files, directories and symlinks stored, the root included.
+    pub entries: u64,
+    /// Total size of the regular files stored, before compression.
+    pub bytes: u64,
+    /// Special files (sockets, FIFOs, devices), which are left out.
+    pub skipped: Vec<PathBuf>,
+}
+
+/// One path to store and its name inside the archive.
+struct Member {
+    path: PathBuf,
+    name: String,
+    meta: Metadata,
+}
+
+/// Pack `src` into `output`, in the format its extension names.
+///
+/// Names inside the archive start with `src`'s own name, so a directory
+/// unpacks into a directory of the same name. Symlinks are stored as
+/// links, not followed. An existing `output` fails with `AlreadyExists`
+/// unless `force` is set, and its directory must exist. An archive
+/// written inside `src` leaves itself out. A failed archive is removed,
+/// but a path below `src` that cannot be read only goes into `failures`,
+/// returned together once the rest is stored.
+pub fn create_archive(
+    src: &Path,
+    output: &Path,
+    force: bool,
+    filesystem: &dyn Fs,
+    mut failures: Failures,
+) -> FmanResult<ArchiveReport> {
+    ensure_exists(src)?;
+    let format = ArchiveFormat::from_path(output)?;
+    if output.is_dir() {
+        return Err(FmanError::InvalidInput(format!(
+            "{} is a directory; give the archive's file name",
+            output.display()
+        )));
+    }
+    ensure_parents_are_dirs(output)?;
+    if let Some(dir) = output.parent().filter(|p| !p.as_os_str().is_empty())
+        && fs::metadata(dir).is_err()
+    {
+        return Err(FmanError::DestinationDirMissing(dir.display().to_string()));
+    }
+    if !force {
+        ensure_not_exists(output)?;
+    }
+    let root_name = match src.file_name() {
+        Some(name) => name.to_os_string(),
+        None => src
+            .canonicalize()?
+            .file_name()
+            .ok_or_else(|| FmanError::InvalidInput(format!("cannot archive {}", src.display())))?
+            .to_os_string(),
+    };
+
+    let mut report = ArchiveReport {
+        archive: output.to_path_buf(),
+        format,
+        entries: 0,
+        bytes: 0,
+        skipped: Vec::new(),
+    };
+    let file = filesystem.create_file(output, !force)?;
+    let own = match &file {
+        Some(file) => Some(file.metadata()?),
+        None => None,
+    };
+    let members = members(
+        src,
+        &root_name.to_string_lossy(),
+        own.as_ref(),
+        &mut report,
+        &mut failures,
+    );
+    let written = members.and_then(|members| match file {
+        Some(file) => write_archive(file, format, &members),
+        // A dry run: go through the motions without keeping anything.
+        None => write_archive(Discard::default(), format, &members),
+    });
+    if let Err(e) = written {
+        if own.is_some() {
+            let _ = filesystem.remove_file(output);
+        }
+        return Err(e);
+    }
+    failures.into_result(report)
+}
+
+/// Everything to store, in walk order, tallied into `report`. A path that
+/// cannot be read goes into `failures` and is left out.
+fn members(
+    src: &Path,
+    root_name: &str,
+    own: Option<&Metadata>,
+    report: &mut ArchiveReport,
+    failures: &mut Failures,
+) -> FmanResult<Vec<Member>> {
+    let root_meta = fs::symlink_metadata(src)?;
+    let is_dir = root_meta.is_dir();
+    let mut members = vec![Member {
+        path: src.to_path_buf(),
+        name: root_name.to_string(),
+        meta: root_meta,
+    }];
+    if is_dir {
+        for entry in Walk::new(src).symlinks(SymlinkPolicy::Never) {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    failures.push(src, e)?;
+                    continue;
+                }
+            };
+            let meta = match entry.metadata() {
+                Ok(meta) => meta,
+                Err(e) => {
+                    failures.push(entry.path(), e)?;
+                    continue;
+                }
+            };
+            if own.is_some_and(|own| same_inode(own, &meta)) {
+                continue;
+            }
+            let relative = entry
+                .path()
+                .strip_prefix(src)
+                .expect("walk yields paths below its root");
+            let mut name = root_name.to_string();
+            for component in relative.components() {
+                name.push('/');
+                name.push_str(&component.as_os_str().to_string_lossy());
+            }
+            members.push(Member {
+                path: entry.path().to_path_buf(),
+                name,
+                meta,
+            });
+        }
+    }
+    members.retain(|member| {
+        let file_type = member.meta.file_type();
+        let stored = file_type.is_file() || file_type.is_dir() || file_type.is_symlink();
+        if !stored {
+            report.skipped.push(member.path.clone());
+        }
+        stored
+    });
+    report.entries = members.len() as u64;
+    report.bytes = members
+        .iter()
+        .filter(|member| member.meta.is_file())
+        .map(|member| member.meta.len())
+        .sum();
+    Ok(members)
+}
+
+fn write_archive<W: Write + Seek>(
+    out: W,
+    format: ArchiveFormat,
+    members: &[Member],
+) -> FmanResult<()> {
+    match format {
+        ArchiveFormat::Tar => {
+            write_tar(out, members)?.flush()?;
+        }
+        ArchiveFormat::TarGz => {
+            let encoder = GzEncoder::new(out, Compression::default());
+            write_tar(encoder, members)?.finish()?.flush()?;
+        }
+        ArchiveFormat::Zip => {
+            write_zip(out, members).map_err(io::Error::other)?.flush()?;
+        }
+    }
+    Ok(())
+}
+
+fn write_tar<W: Write>(out: W, members: &[Member]) -> io::Result<W> {
+    let mut builder = tar::Builder::new(out);
+    builder.follow_symlinks(false);
+    for member in members {
+        builder.append_path_with_name(&member.path, &member.name)?;
+    }
+    builder.into_inner()
+}
+
+fn write_zip<W: Write + Seek>(out: W, members: &[Member]) -> zip::result::ZipResult<W> {
+    let mut zip = ZipWriter::new(out);
+    for member in members {
+        let mut options = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Deflated)
+            .unix_permissions(mode_bits(&member.meta));
+        if let Some(time) = member.meta.modified().ok().and_then(zip_time) {
+            options = options.last_modified_time(time);
+        }
+        let file_type = member.meta.file_type();
+        if file_type.is_dir() {
+            zip.add_directory(member.name.as_str(), options)?;
+        } else if file_type.is_symlink() {
+            let target = fs::read_link(&member.path)?;
+            zip.add_symlink(member.name.as_str(), target.to_string_lossy(), options)?;
+        } else {
+            let options = options.large_file(member.meta.len() >= u64::from(u32::MAX));
+            zip.start_file(member.name.as_str(), options)?;
+            io::copy(&mut File::open(&member.path)?, &mut zip)?;
+        }
+    }
+    zip.finish()
+}
+
+/// `time` as a zip timestamp, in UTC; zip cannot hold times before 1980.
+fn zip_time(time: SystemTime) -> Option<zip::DateTime> {
+    let stamp = format_time(time, "%Y %m %d %H %M %S");
+    let parts: Vec<u16> = stamp
+        .split(' ')
+        .map(|part| part.parse().ok())
+        .collect::<Option<_>>()?;
+    let [year, month, day, hour, minute, second] = parts[..] else {
+        return None;
+    };
+    zip::DateTime::from_date_and_time(
+        year,
+        month as u8,
+        day as u8,
+        hour as u8,
+        minute as u8,
+        second as u8,
+    )
+    .ok()
+}
+
+/// `time` from a zip timestamp, taken as UTC.
+fn system_time(time: zip::DateTime) -> Option<SystemTime> {
+    parse_time(&format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        time.year(),
+        time.month(),
+        time.day(),
+        time.hour(),
+        time.minute(),
+        time.second()
+    ))
+}
+
+/// Outcome of [`extract_archive`].
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "json", derive(Serialize))]
+pub struct ExtractReport {
+    pub archive: PathBuf,
+    pub destination: PathBuf,
+    pub format: ArchiveFormat,
+    /// Files, directories and links written.
+    pub entries: u64,
+    /// Total size of the files written.
+    pub bytes: u64,
+    /// Entries of other kinds, such as devices, which are left out.
+    pub skipped: Vec<String>,
+}
+
+/// What an archive entry holds.
+enum Stored {
+    Dir,
+    File,
+    /// A symlink holding this target.
+    Symlink(PathBuf),
+    /// A hard link to this earlier entry.
+    HardLink(PathBuf),
+    Other,
+}
+
+/// One entry as read from an archive, before any checks.
+struct Unpacked<'a> {
+    name: PathBuf,
+    stored: Stored,
+    mode: Option<u32>,
+    modified: Option<SystemTime>,
+    contents: &'a mut dyn Read,
+}
+
+/// Unpack `archive` into `dest`, which is created if missing.
+///
+/// The format comes from the archive's contents, not its name. Every
+/// entry is checked before anything is written: one whose path, or whose
+/// link target, would land outside `dest` (such as `../../etc/passwd`),
+/// or that would be written through a symlink, fails with `InvalidInput`,
+/// and one that would replace an existing file fails with `AlreadyExists`
+/// unless `force` is set. Every change is made through `filesystem`.
+pub fn extract_archive(
+    archive: &Path,
+    dest: &Path,
+    force: bool,
+    filesystem: &dyn Fs,
+) -> FmanResult<ExtractReport> {
+    ensure_exists(archive)?;
+    ensure_parents_are_dirs(dest)?;
+    let format = ArchiveFormat::detect(archive)?;
+    let mut report = ExtractReport {
+        archive: archive.to_path_buf(),
+        destination: dest.to_path_buf(),
+        format,
+        entries: 0,
+        bytes: 0,
+        skipped: Vec::new(),
+    };
+
+    let mut seen = HashSet::new();
+    let mut links = HashSet::new();
+    each_entry(archive, format, &mut |entry| {
+        let relative = checked_path(&entry)?;
+        // A symlink, whether from the archive or already in `dest`, can
+        // point anywhere once followed, so nothing is written through one.
+        let through_link = |path: &Path| {
+            path.ancestors()
+                .skip(1)
+                .find(|dir| {
+                    !dir.as_os_str().is_empty()
+                        && (links.contains(*dir)
+                            || fs::symlink_metadata(dest.join(dir)).is_ok_and(|m| m.is_symlink()))
+                })
+                .map(Path::to_path_buf)
+        };
+        let mut paths = vec![relative.clone()];
+        if let Stored::HardLink(original) = &entry.stored {
+            paths.extend(normalized(original));
+        }
+        for path in &paths {
+            if let Some(link) = through_link(path) {
+                return Err(FmanError::InvalidInput(format!(
+                    "archive entry {} goes through the symlink {}; not extracting",
+                    entry.name.display(),
+                    link.display()
+                )));
+            }
+        }
+        if let Stored::Symlink(_) = entry.stored {
+            links.insert(relative.clone());
+        }
+        if matches!(entry.stored, Stored::Dir | Stored::Other) {
+            return Ok(());
+        }
+        let target = dest.join(&relative);
+        let taken = fs::symlink_metadata(&target).is_ok_and(|meta| !meta.is_dir());
+        if (!seen.insert(relative) || taken) && !force {
+            return Err(FmanError::AlreadyExists(target.display().to_string()));
+        }
+        Ok(())
+    })?;
+
+    filesystem.create_dir_all(dest)?;
+    let mut made = HashSet::from([dest.to_path_buf()]);
+    let mut make_dir = |dir: &Path| -> io::Result<()> {
+        if made.insert(dir.to_path_buf()) && fs::metadata(dir).is_err() {
+            filesystem.create_dir_all(dir)?;
+        }
+        Ok(())
+    };
+    // Directory attributes go on last, once nothing more is written below.
+    let mut dirs = Vec::new();
+    each_entry(archive, format, &mut |entry| {
+        let relative = checked_path(&entry)?;
+        let target = dest.join(&relative);
+        if let Stored::Dir | Stored::Other = entry.stored {
+            if let Stored::Dir = entry.stored {
+                make_dir(&target)?;
+                dirs.push((target, entry.mode, entry.modified));
+                report.entries += 1;
+            } else {
+                report.skipped.push(entry.name.display().to_string());
+            }
+            return Ok(());
+        }
+        if let Some(parent) = target.parent() {
+            make_dir(parent)?;
+        }
+        if fs::symlink_metadata(&target).is_ok() {
+            // Removed rather than overwritten, so a symlink there is
+            // replaced instead of written through.
+            filesystem.remove_file(&target)?;
+        }
+        match entry.stored {
+            Stored::File => {
+                let bytes = match filesystem.create_file(&target, true)? {
+                    Some(mut file) => io::copy(entry.contents, &mut file)?,
+                    None => io::copy(entry.contents, &mut io::sink())?,
+                };
+                report.bytes += bytes;
+                if let Some(permissions) = entry.mode.and_then(|mode| permissions(&target, mode)) {
+                    filesystem.set_permissions(&target, permissions)?;
+                }
+                if entry.modified.is_some() {
+                    filesystem.set_times(&target, None, entry.modified)?;
+                }
+            }
+            Stored::Symlink(link) => filesystem.symlink(&link, &target)?,
+            Stored::HardLink(original) => {
+                let original = normalized(&original).expect("checked before extracting");
+                filesystem.hard_link(&dest.join(original), &target)?;
+            }
+            Stored::Dir | Stored::Other => unreachable!("handled above"),
+        }
+        report.entries += 1;
+        Ok(())
+    })?;
+    for (dir, mode, modified) in dirs.into_iter().rev() {
+        if let Some(permissions) = mode.and_then(|mode| permissions(&dir, mode)) {
+            filesystem.set_permissions(&dir, permissions)?;
+        }
+        if modified.is_some() {
+            filesystem.set_times(&dir, None, modified)?;
+        }
+    }
+    Ok(report)
+}
+
+/// Call `visit` with each entry of `archive` in order.
+fn each_entry(
+    archive: &Path,
+    format: ArchiveFormat,
+    visit: &mut dyn FnMut(Unpacked<'_>) -> FmanResult<()>,
+) -> FmanResult<()> {
+    let file = File::open(archive)?;
+    match format {
+        ArchiveFormat::Tar => each_tar_entry(file, visit),
+        ArchiveFormat::TarGz => each_tar_entry(GzDecoder::new(file), visit),
+        ArchiveFormat::Zip => {
+            let mut zip = ZipArchive::new(file).map_err(io::Error::other)?;
+            for i in 0..zip.len() {
+                let mut member = zip.by_index(i).map_err(io::Error::other)?;
+                let stored = if member.is_dir() {
+                    Stored::Dir
+                } else if member.is_symlink() {
+                    let mut link = String::new();
+                    member.read_to_string(&mut link)?;
+                    Stored::Symlink(PathBuf::from(link))
+                } else {
+                    Stored::File
+                };
+                visit(Unpacked {
+                    name: PathBuf::from(member.name()),
+                    stored,
+                    mode: member.unix_mode(),
+                    modified: member.last_modified().and_then(system_time),
+                    contents: &mut member,
+                })?;
+            }
+            Ok(())
+        }
+    }
+}
+
+fn each_tar_entry(
+    reader: impl Read,
+    visit: &mut dyn FnMut(Unpacked<'_>) -> FmanResult<()>,
+) -> FmanResult<()> {
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let header = entry.header();
+        let link = || -> io::Result<PathBuf> {
+            let link = header.link_name()?;
+            link.map(|link| link.into_owned())
+                .ok_or_else(|| io::Error::other("link entry without a target"))
+        };
+        let stored = match header.entry_type() {
+            tar::EntryType::Directory => Stored::Dir,
+            tar::EntryType::Regular | tar::EntryType::Continuous => Stored::File,
+            tar::EntryType::Symlink => Stored::Symlink(link()?),
+            tar::EntryType::Link => Stored::HardLink(link()?),
+            // Long names and pax attributes are applied by `tar` itself.
+            tar::EntryType::XGlobalHeader => continue,
+            _ => Stored::Other,
+        };
+        let mode = header.mode().ok();
+        let modified = header
+            .mtime()
+            .ok()
+            .map(|secs| SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(secs));
+        visit(Unpacked {
+            name: entry.path()?.into_owned(),
+            stored,
+            mode,
+            modified,
+            contents: &mut entry,
+        })?;
+    }
+    Ok(())
+}
+
+/// `entry`'s path relative to the destination, failing with
+/// `InvalidInput` if it or a link target would point outside it.
+fn checked_path(entry: &Unpacked<'_>) -> FmanResult<PathBuf> {
+    let escapes = |what: &str, path: &Path| {
+        FmanError::InvalidInput(format!(
+            "archive entry {} has {what} {} outside the destination; not extracting",
+            entry.name.display(),
+            path.display()
+        ))
+    };
+    let relative = normalized(&entry.name)
+        .filter(|relative| !relative.as_os_str().is_empty() || matches!(entry.stored, Stored::Dir))
+        .ok_or_else(|| escapes("path", &entry.name))?;
+    match &entry.stored {
+        Stored::Symlink(link) => {
+            let from = relative.parent().unwrap_or(Path::new(""));
+            if link.is_absolute() || normalized(&from.join(link)).is_none() {
+                return Err(escapes("link target", link));
+            }
+        }
+        Stored::HardLink(original) => {
+            normalized(original).ok_or_else(|| escapes("link target", original))?;
+        }
+        _ => {}
+    }
+    Ok(relative)
+}
+
+/// `path` with `.` and `..` resolved, or `None` if it is absolute or
+/// climbs above where it starts.
+fn normalized(path: &Path) -> Option<PathBuf> {
+    let mut normal = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => normal.push(name),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !normal.pop() {
+                    return None;
+                }
+            }
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(normal)
+}
+
+/// The permissions an entry stored with `mode` gets at `path`.
+#[cfg(unix)]
+fn permissions(_path: &Path, mode: u32) -> Option<Permissions> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(Permissions::from_mode(mode & 0o7777))
+}
+
+/// Only the read-only attribute carries over, onto `path`'s permissions.
+#[cfg(not(unix))]
+fn permissions(path: &Path, mode: u32) -> Option<Permissions> {
+    let mut permissions = fs::metadata(path).ok()?.permissions();
+    permissions.set_readonly(mode & 0o200 == 0);
+    Some(permissions)
+}
+
+/// A writer that keeps nothing but its length, for dry runs.
+#[derive(Default)]
+struct Discard {
+    position: u64,
+    len: u64,
+}
+
+impl Write for Discard {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.position += buf.len() as u64;
+        self.len = self.len.max(self.position);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for Discard {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        self.position = position
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "seek before the start"))?;
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    use crate::error::DEFAULT_MAX_ERRORS;
+    use crate::fs::RealFs;
+
+    fn pack(
+        src: &Path,
+        output: &Path,
+        force: bool,
+        filesystem: &dyn Fs,
+    ) -> FmanResult<ArchiveReport> {
+        let failures = Failures::new(DEFAULT_MAX_ERRORS, None)?;
+        create_archive(src, output, force, filesystem, failures)
+    }
+
+    /// A tar holding `entries` as given, bypassing the checks `tar` makes
+    /// on names so that hostile ones can be written.
+    fn tar_with(path: &Path, entries: &[(&str, tar::EntryType, &str)]) {
+        let mut builder = tar::Builder::new(File::create(path).unwrap());
+        for &(name, kind, link_or_contents) in entries {
+            let mut header = tar::Header::new_gnu();
+            let slot = &mut header.as_gnu_mut().unwrap().name;
+            slot[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_entry_type(kind);
+            header.set_mode(0o644);
+            let contents = if kind == tar::EntryType::Regular {
+                link_or_contents.as_bytes()
+            } else {
+                header.set_link_name(link_or_contents).unwrap();
+                &[]
+            };
+            header.set_size(contents.len() as u64);
+            header.set_cksum();
+            builder.append(&header, contents).unwrap();
+        }
+        builder.finish().unwrap();
+    }
+
+    fn zip_with(path: &Path, name: &str) {
+        let mut zip = ZipWriter::new(File::create(path).unwrap());
+        zip.start_file(name, SimpleFileOptions::default()).unwrap();
+        zip.write_all(b"evil").unwrap();
+        zip.finish().unwrap();
+    }
+
+    /// Extract `archive` into `root/out`, expecting a refusal that left
+    /// nothing behind.
+    #[track_caller]
+    fn refused(root: &Path, archive: &Path) -> String {
+        let err = extract_archive(archive, &root.join("out"), false, &RealFs).unwrap_err();
+        assert!(!root.join("out").exists(), "extracted before refusing");
+        assert!(!root.join("evil").exists());
+        match err {
+            FmanError::InvalidInput(message) => message,
+            other => panic!("expected InvalidInput, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn an_archive_round_trips() {
+        let dir = TempDir::new().unwrap();
+        let src = dir.path().join("s");
+        fs::create_dir_all(src.join("sub")).unwrap();
+        fs::write(src.join("a"), "1").unwrap();
+        fs::write(src.join("sub/b"), "22").unwrap();
+        for name in ["t.tar", "t.tar.gz", "t.zip"] {
+            let archive = dir.path().join(name);
+            let report = pack(&src, &archive, false, &RealFs).unwrap();
+            assert_eq!((report.entries, report.bytes), (4, 3));
+            let out = dir.path().join(format!("out-{name}"));
+            let report = extract_archive(&archive, &out, false, &RealFs).unwrap();
+            assert_eq!(report.entries, 4, "{name}");
+            assert_eq!(fs::read_to_string(out.join("s/sub/b")).unwrap(), "22");
+        }
+    }
+
+    #[test]
+    fn a_parent_directory_entry_is_refused() {
+        let dir = TempDir::new().unwrap();
+        let archive = dir.path().join("t.tar");
+        tar_with(
+            &archive,
+            &[
+                ("fine", tar::EntryType::Regular, "ok"),
+                ("../evil", tar::EntryType::Regular, "evil"),
+            ],
+        );
+        let message = refused(dir.path(), &archive);
+        assert!(message.contains("../evil"), "{message}");
+
+        tar_with(
+            &archive,
+            &[("a/../../evil", tar::EntryType::Regular, "evil")],
+        );
+        refused(dir.path(), &archive);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn an_absolute_entry_is_refused() {
+        let dir = TempDir::new().unwrap();
+        let archive = dir.path().join("t.tar");
+        let absolute = dir.path().join("evil");
+        tar_with(
+            &archive,
+            &[(absolute.to_str().unwrap(), tar::EntryType::Regular, "evil")],
+        );
+        refused(dir.path(), &archive);
+    }
+
+    #[test]
+    fn a_symlink_out_of_the_destination_is_refused() {
+        let dir = TempDir::new().unwrap();
+        let archive = dir.path().join("t.tar");
+        for target in ["../evil", "sub/../../evil", "/etc/passwd"] {
+            tar_with(&archive, &[("link", tar::EntryType::Symlink, target)]);
+            let message = refused(dir.path(), &archive);
+            assert!(message.contains("link target"), "{message}");
+        }
+    }
+
+    #[test]
+    fn writing_through_a_planted_symlink_is_refused() {
+        // The classic two-step escape: a link to outside, then a file
+        // written through it.
+        let dir = TempDir::new().unwrap();
+        let archive = dir.path().join("t.tar");
+        tar_with(
+            &archive,
+            &[
+                ("up", tar::EntryType::Symlink, ".."),
+                ("up/evil", tar::EntryType::Regular, "evil"),
+            ],
+        );
+        refused(dir.path(), &archive);
+    }
+
+    #[test]
+    fn chained_symlinks_out_of_the_destination_are_refused() {
+        // Each link stays inside on its own, but `a/x` really sits in the
+        // destination itself, so `..` from there is outside it.
+        let dir = TempDir::new().unwrap();
+        let archive = dir.path().join("t.tar");
+        tar_with(
+            &archive,
+            &[
+                ("a", tar::EntryType::Symlink, "."),
+                ("a/x", tar::EntryType::Symlink, ".."),
+                ("a/x/evil", tar::EntryType::Regular, "evil"),
+            ],
+        );
+        let message = refused(dir.path(), &archive);
+        assert!(message.contains("goes through the symlink a"), "{message}");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_symlink_already_in_the_destination_is_not_written_through() {
+        let dir = TempDir::new().unwrap();
+        let archive = dir.path().join("t.tar");
+        tar_with(&archive, &[("up/evil", tar::EntryType::Regular, "evil")]);
+        let out = dir.path().join("out");
+        fs::create_dir(&out).unwrap();
+        std::os::unix::fs::symlink("..", out.join("up")).unwrap();
+        let err = extract_archive(&archive, &out, false, &RealFs).unwrap_err();
+        assert!(matches!(err, FmanError::InvalidInput(_)), "{err:?}");
+        assert!(!dir.path().join("evil").exists());
+    }
+
+    #[test]
+    fn a_hard_link_out_of_the_destination_is_refused() {
+        let dir = TempDir::new().unwrap();
+        let archive = dir.path().join("t.tar");
+        tar_with(
+            &archive,
+            &[("link", tar::EntryType::Link, "../../etc/passwd")],
+        );
+        refused(dir.path(), &archive);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_symlink_inside_the_destination_is_kept() {
+        let dir = TempDir::new().unwrap();
+        let archive = dir.path().join("t.tar");
+        tar_with(
+            &archive,
+            &[
+                ("d/a", tar::EntryType::Regular, "1"),
+                ("d/link", tar::EntryType::Symlink, "a"),
+                ("up", tar::EntryType::Symlink, "d/../d"),
+            ],
+        );
+        let out = dir.path().join("out");
+        extract_archive(&archive, &out, false, &RealFs).unwrap();
+        assert_eq!(fs::read_link(out.join("d/link")).unwrap(), Path::new("a"));
+        assert_eq!(fs::read_to_string(out.join("up/link")).unwrap(), "1");
+    }
+
+    #[test]
+    fn a_zip_entry_out_of_the_destination_is_refused() {
+        let dir = TempDir::new().unwrap();
+        let archive = dir.path().join("t.zip");
+        zip_with(&archive, "../evil");
+        refused(dir.path(), &archive);
+    }
+
+    #[test]
+    fn an_existing_file_is_kept_unless_forced() {
+        let dir = TempDir::new().unwrap();
+        let archive = dir.path().join("t.tar");
+        tar_with(&archive, &[("a", tar::EntryType::Regular, "new")]);
+        let out = dir.path().join("out");
+        fs::create_dir(&out).unwrap();
+        fs::write(out.join("a"), "old").unwrap();
+        let err = extract_archive(&archive, &out, false, &RealFs).unwrap_err();
+        assert_eq!(err.exit_code(), 3);
+        assert_eq!(fs::read_to_string(out.join("a")).unwrap(), "old");
+        extract_archive(&archive, &out, true, &RealFs).unwrap();
+        assert_eq!(fs::read_to_string(out.join("a")).unwrap(), "new");
+    }
+
+    /// The names and kinds stored in the tar at `path`.
+    fn tar_entries(path: &Path) -> Vec<(String, tar::EntryType, Option<PathBuf>)> {
+        let mut archive = tar::Archive::new(File::open(path).unwrap());
+        archive
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                let name = entry.path().unwrap().to_string_lossy().into_owned();
+                let link = entry.link_name().unwrap().map(|link| link.into_owned());
+                (name, entry.header().entry_type(), link)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn the_extension_picks_the_format() {
+        for (name, format) in [
+            ("a.tar", ArchiveFormat::Tar),
+            ("a.TAR", ArchiveFormat::Tar),
+            ("a.tar.gz", ArchiveFormat::TarGz),
+            ("a.tgz", ArchiveFormat::TarGz),
+            ("a.Zip", ArchiveFormat::Zip),
+        ] {
+            assert_eq!(ArchiveFormat::from_path(Path::new(name)).unwrap(), format);
+        }
+        for name in ["a.gz", "a.rar", "tar", "a"] {
+            let err = ArchiveFormat::from_path(Path::new(name)).unwrap_err();
+            assert!(matches!(err, FmanError::InvalidInput(_)), "{name}: {err}");
+        }
+    }
+
+    #[test]
+    fn the_magic_bytes_pick_the_format_over_the_extension() {
+        let dir = TempDir::new().unwrap();
+        let src = dir.path().join("s");
+        fs::write(&src, "x").unwrap();
+        for (name, format) in [
+            ("t.tar", ArchiveFormat::Tar),
+            ("t.tar.gz", ArchiveFormat::TarGz),
+            ("t.zip", ArchiveFormat::Zip),
+        ] {
+            let archive = dir.path().join(name);
+            pack(&src, &archive, false, &RealFs).unwrap();
+            let renamed = dir.path().join(format!("{name}.bin"));
+            fs::rename(&archive, &renamed).unwrap();
+            assert_eq!(ArchiveFormat::detect(&renamed).unwrap(), format, "{name}");
+        }
+    }
+
+    #[test]
+    fn names_are_rooted_at_the_source_name() {
+        let dir = TempDir::new().unwrap();
+        let src = dir.path().join("s");
+        fs::create_dir_all(src.join("sub")).unwrap();
+        fs::write(src.join("sub/b"), "2").unwrap();
+        let archive = dir.path().join("t.tar");
+        pack(&src, &archive, false, &RealFs).unwrap();
+        let mut names: Vec<_> = tar_entries(&archive)
+            .into_iter()
+            .map(|(name, _, _)| name.trim_end_matches('/').to_string())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["s", "s/sub", "s/sub/b"]);
+
+        let single = dir.path().join("one.zip");
+        let report = pack(&src.join("sub/b"), &single, false, &RealFs).unwrap();
+        assert_eq!((report.entries, report.bytes), (1, 1));
+        let zip = ZipArchive::new(File::open(&single).unwrap()).unwrap();
+        assert_eq!(zip.file_names().collect::<Vec<_>>(), ["b"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_are_stored_as_symlinks() {
+        let dir = TempDir::new().unwrap();
+        let src = dir.path().join("s");
+        fs::create_dir(&src).unwrap();
+        fs::write(dir.path().join("outside"), "big contents").unwrap();
+        std::os::unix::fs::symlink("../outside", src.join("link")).unwrap();
+        let archive = dir.path().join("t.tar");
+        let report = pack(&src, &archive, false, &RealFs).unwrap();
+        assert_eq!(report.bytes, 0);
+        let link = tar_entries(&archive)
+            .into_iter()
+            .find(|(name, _, _)| name == "s/link")
+            .unwrap();
+        assert_eq!(link.1, tar::EntryType::Symlink);
+        assert_eq!(link.2.as_deref(), Some(Path::new("../outside")));
+    }
+
+    #[test]
+    fn an_archive_inside_its_source_leaves_itself_out() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("a"), "1").unwrap();
+        let archive = dir.path().join("t.tar");
+        let report = pack(dir.path(), &archive, false, &RealFs).unwrap();
+        assert_eq!(report.entries, 2);
+        assert!(
+            tar_entries(&archive)
+                .iter()
+                .all(|(name, _, _)| !name.ends_with("t.tar"))
+        );
+    }
+
+    #[test]
+    fn a_bad_output_is_refused_before_writing() {
+        let dir = TempDir::new().unwrap();
+        let src = dir.path().join("s");
+        fs::write(&src, "x").unwrap();
+        let err = pack(&src, &dir.path().join("t.rar"), false, &RealFs).unwrap_err();
+        assert!(matches!(err, FmanError::InvalidInput(_)), "{err}");
+        assert!(!dir.path().join("t.rar").exists());
+
+        let err = pack(&src, &dir.path().join("no/t.zip"), false, &RealFs).unwrap_err();
+        assert!(matches!(err, FmanError::DestinationDirMissing(_)), "{err}");
+
+        let err = pack(
+            &dir.path().join("missing"),
+            &dir.path().join("t.zip"),
+            false,
+            &RealFs,
+        )
+        .unwrap_err();
+        assert!(err.is_not_found(), "{err}");
+    }
+
+    #[test]
+    fn a_dry_run_archive_is_not_written() {
+        let dir = TempDir::new().unwrap();
+        let src = dir.path().join("s");
+        fs::create_dir(&src).unwrap();
+        fs::write(src.join("a"), "123").unwrap();
+        let archive = dir.path().join("t.tar.gz");
+        let dry_run = crate::fs::DryRunFs::new();
+        let report = pack(&src, &archive, false, &dry_run).unwrap();
+        assert_eq!((report.entries, report.bytes), (2, 3));
+        assert!(!archive.exists());
+    }
+
+    #[test]
+    fn a_compressed_parent_directory_entry_is_refused() {
+        let dir = TempDir::new().unwrap();
+        let tar = dir.path().join("plain.tar");
+        tar_with(
+            &tar,
+            &[("../../etc/passwd", tar::EntryType::Regular, "evil")],
+        );
+        let archive = dir.path().join("t.tar.gz");
+        let mut encoder = GzEncoder::new(File::create(&archive).unwrap(), Compression::default());
+        io::copy(&mut File::open(&tar).unwrap(), &mut encoder).unwrap();
+        encoder.finish().unwrap();
+        let message = refused(dir.path(), &archive);
+        assert!(message.contains("../../etc/passwd"), "{message}");
+    }
+
+    #[test]
+    fn an_absolute_zip_entry_is_refused() {
+        let dir = TempDir::new().unwrap();
+        let archive = dir.path().join("t.zip");
+        zip_with(&archive, "/tmp/evil");
+        refused(dir.path(), &archive);
+    }
+
+    #[test]
+    fn the_format_comes_from_the_contents() {
+        let dir = TempDir::new().unwrap();
+        let src = dir.path().join("s");
+        fs::write(&src, "x").unwrap();
+        let archive = dir.path().join("t.zip");
+        pack(&src, &archive, false, &RealFs).unwrap();
+        let misnamed = dir.path().join("t.tar");
+        fs::rename(&archive, &misnamed).unwrap();
+        let out = dir.path().join("out");
+        let report = extract_archive(&misnamed, &out, false, &RealFs).unwrap();
+        assert_eq!(report.format, ArchiveFormat::Zip);
+        assert_eq!(fs::read_to_string(out.join("s")).unwrap(), "x");
+    }
+
+    #[test]
+    fn a_missing_destination_is_created() {
+        let dir = TempDir::new().unwrap();
+        let archive = dir.path().join("t.tar");
+        tar_with(&archive, &[("a", tar::EntryType::Regular, "1")]);
+        let out = dir.path().join("x/y/z");
+        extract_archive(&archive, &out, false, &RealFs).unwrap();
+        assert_eq!(fs::read_to_string(out.join("a")).unwrap(), "1");
+    }
+
+    #[test]
+    fn a_hard_link_inside_the_destination_is_kept() {
+        let dir = TempDir::new().unwrap();
+        let archive = dir.path().join("t.tar");
+        tar_with(
+            &archive,
+            &[
+                ("d/a", tar::EntryType::Regular, "1"),
+                ("d/b", tar::EntryType::Link, "d/a"),
+            ],
+        );
+        let out = dir.path().join("out");
+        let report = extract_archive(&archive, &out, false, &RealFs).unwrap();
+        assert_eq!(report.entries, 2);
+        assert!(same_inode(
+            &fs::metadata(out.join("d/a")).unwrap(),
+            &fs::metadata(out.join("d/b")).unwrap()
+        ));
+    }
+
+    #[test]
+    fn a_repeated_entry_needs_force() {
+        let dir = TempDir::new().unwrap();
+        let archive = dir.path().join("t.tar");
+        tar_with(
+            &archive,
+            &[
+                ("a", tar::EntryType::Regular, "first"),
+                ("./a", tar::EntryType::Regular, "second"),
+            ],
+        );
+        let err = extract_archive(&archive, &dir.path().join("out"), false, &RealFs).unwrap_err();
+        assert_eq!(err.exit_code(), 3);
+        assert!(!dir.path().join("out").exists());
+        let out = dir.path().join("forced");
+        extract_archive(&archive, &out, true, &RealFs).unwrap();
+        assert_eq!(fs::read_to_string(out.join("a")).unwrap(), "second");
+    }
+
+    #[test]
+    fn a_dry_run_extracts_nothing() {
+        let dir = TempDir::new().unwrap();
+        let archive = dir.path().join("t.tar");
+        tar_with(&archive, &[("d/a", tar::EntryType::Regular, "123")]);
+        let out = dir.path().join("out");
+        let dry_run = crate::fs::DryRunFs::new();
+        let report = extract_archive(&archive, &out, false, &dry_run).unwrap();
+        assert_eq!((report.entries, report.bytes), (1, 3));
+        assert!(!out.exists());
+        assert!(!dry_run.ops().is_empty());
+    }
+}
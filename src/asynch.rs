@@ -0,0 +1,52 @@
+//! The crate-root copy functions for async code, on tokio.
+//!
+//! Each call runs the blocking copy, validation included, on tokio's
+//! blocking thread pool, so a slow filesystem never stalls the executor
+//! and the semantics are exactly those of the synchronous functions. A
+//! runtime must be running when they are polled.
+
+use std::path::{Path, PathBuf};
+
+use crate::copy::{CopyOptions, CopyReport};
+use crate::error::{FmanError, FmanResult};
+
+/// [`crate::copy_file_with_options`] off the async executor.
+pub async fn copy_file_with_options(
+    src: impl AsRef<Path>,
+    dst: impl AsRef<Path>,
+    options: &CopyOptions,
+) -> FmanResult<CopyReport> {
+    let (src, dst) = (src.as_ref().to_path_buf(), dst.as_ref().to_path_buf());
+    let options = options.clone();
+    blocking(move || crate::copy_file_with_options(src, dst, &options)).await
+}
+
+/// [`crate::copy_file_safe`] off the async executor.
+pub async fn copy_file_safe(src: impl AsRef<Path>, dst: impl AsRef<Path>) -> FmanResult<PathBuf> {
+    Ok(copy_file_with_options(src, dst, &CopyOptions::new())
+        .await?
+        .destination)
+}
+
+/// [`crate::copy_file_force`] off the async executor.
+pub async fn copy_file_force(src: impl AsRef<Path>, dst: impl AsRef<Path>) -> FmanResult<PathBuf> {
+    Ok(
+        copy_file_with_options(src, dst, &CopyOptions::new().force(true))
+            .await?
+            .destination,
+    )
+}
+
+/// Run `work` on the blocking pool. A panic in it is passed on; a task
+/// cancelled by a runtime shutting down is `Cancelled`.
+async fn blocking<T, F>(work: F) -> FmanResult<T>
+where
+    F: FnOnce() -> FmanResult<T> + Send + 'static,
+    T: Send + 'static,
+{
+    match tokio::task::spawn_blocking(work).await {
+        Ok(result) => result,
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        Err(_) => Err(FmanError::Cancelled),
+    }
+}
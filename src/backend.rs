@@ -0,0 +1,448 @@
+//! How file data is moved: the strategies available to the copy engine and
+//! the size-based selection between them.
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+#[cfg(feature = "json")]
+use serde::Serialize;
+
+/// Default size below which files are copied with a small buffer.
+pub const DEFAULT_SMALL_FILE_THRESHOLD: u64 = 64 * 1024;
+
+/// Default size from which files are copied in parallel chunks.
+pub const DEFAULT_HUGE_FILE_THRESHOLD: u64 = 1024 * 1024 * 1024;
+
+const SMALL_BUFFER_SIZE: usize = 8 * 1024;
+const CHUNK_BUFFER_SIZE: usize = 1024 * 1024;
+const CHUNK_WORKERS: u64 = 4;
+
+/// Granularity at which [`CopyStrategy::Sparse`] looks for zeros; runs
+/// shorter than this are written out.
+const SPARSE_BLOCK: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(Serialize), serde(rename_all = "kebab-case"))]
+pub enum CopyStrategy {
+    /// Read/write loop over a small buffer; cheapest setup for tiny files.
+    Buffered,
+    /// `std::io::copy` between the two handles, which offloads to the kernel
+    /// where the platform can (`copy_file_range`/`sendfile` on Linux).
+    Kernel,
+    /// Several threads each copying a contiguous range with positional
+    /// I/O. Falls back to [`CopyStrategy::Kernel`] off Unix.
+    ParallelChunks,
+    /// Read/write loop that seeks past blocks of zeros in the destination
+    /// instead of writing them, leaving holes, and on Linux skips the
+    /// source's own holes without reading them.
+    Sparse,
+    /// The destination was made to share the source's data blocks; see
+    /// [`Reflink`](crate::copy::Reflink).
+    Reflink,
+}
+
+impl CopyStrategy {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CopyStrategy::Buffered => "buffered",
+            CopyStrategy::Kernel => "kernel",
+            CopyStrategy::ParallelChunks => "parallel-chunks",
+            CopyStrategy::Sparse => "sparse",
+            CopyStrategy::Reflink => "reflink",
+        }
+    }
+}
+
+/// Picks a [`CopyStrategy`] from a file's size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StrategySelector {
+    /// Files strictly smaller than this use [`CopyStrategy::Buffered`].
+    pub small_file_threshold: u64,
+    /// Files of at least this size use [`CopyStrategy::ParallelChunks`].
+    pub huge_file_threshold: u64,
+    /// When set, every file uses [`CopyStrategy::Buffered`] with a buffer
+    /// of this many bytes.
+    pub buffer_size: Option<usize>,
+    /// When set, every file uses [`CopyStrategy::Sparse`].
+    pub sparse: bool,
+}
+
+impl Default for StrategySelector {
+    fn default() -> Self {
+        Self {
+            small_file_threshold: DEFAULT_SMALL_FILE_THRESHOLD,
+            huge_file_threshold: DEFAULT_HUGE_FILE_THRESHOLD,
+            buffer_size: None,
+            sparse: false,
+        }
+    }
+}
+
+impl StrategySelector {
+    pub fn select(&self, len: u64) -> CopyStrategy {
+        if self.sparse {
+            CopyStrategy::Sparse
+        } else if self.buffer_size.is_some() || len < self.small_file_threshold {
+            CopyStrategy::Buffered
+        } else if len >= self.huge_file_threshold {
+            CopyStrategy::ParallelChunks
+        } else {
+            CopyStrategy::Kernel
+        }
+    }
+
+    /// The buffer [`CopyStrategy::Buffered`] and [`CopyStrategy::Sparse`]
+    /// copy through.
+    pub fn buffer_size(&self) -> usize {
+        self.buffer_size.unwrap_or(SMALL_BUFFER_SIZE).max(1)
+    }
+}
+
+/// Copy the contents of `src` to `dst` with `strategy`, returning the
+/// number of bytes written. A buffered copy goes through `buffer_size`
+/// bytes at a time.
+///
+/// Both handles must be positioned at the start and `dst` must be empty;
+/// permissions are left to the caller.
+pub(crate) fn copy_with(
+    strategy: CopyStrategy,
+    buffer_size: usize,
+    src: &File,
+    dst: &File,
+) -> io::Result<u64> {
+    copy_at_most(strategy, buffer_size, src, dst, u64::MAX)
+}
+
+/// Like [`copy_with`], but stop after `limit` bytes. Both handles are left
+/// positioned after the data copied, so a buffered or kernel copy can
+/// carry on from there.
+pub(crate) fn copy_at_most(
+    strategy: CopyStrategy,
+    buffer_size: usize,
+    src: &File,
+    dst: &File,
+    limit: u64,
+) -> io::Result<u64> {
+    match strategy {
+        CopyStrategy::Buffered => copy_contents(src.take(limit), dst, buffer_size),
+        CopyStrategy::Kernel => io::copy(&mut src.take(limit), &mut &*dst),
+        CopyStrategy::ParallelChunks => {
+            let copied = copy_parallel_chunks(src, dst, limit)?;
+            // Positional I/O leaves the offsets at the start.
+            (&*src).seek(SeekFrom::Start(copied))?;
+            (&*dst).seek(SeekFrom::Start(copied))?;
+            Ok(copied)
+        }
+        CopyStrategy::Sparse => copy_sparse(src, dst, buffer_size, limit),
+        // Never selected, only reported.
+        CopyStrategy::Reflink => copy_contents(src.take(limit), dst, buffer_size),
+    }
+}
+
+/// Read `reader` to the end and write it all to `writer`, reusing one
+/// buffer of `buf_size` bytes. Short reads and writes are carried on from
+/// rather than taken as the end. Returns the number of bytes written.
+pub(crate) fn copy_contents(
+    mut reader: impl Read,
+    mut writer: impl Write,
+    buf_size: usize,
+) -> io::Result<u64> {
+    let mut buf = vec![0u8; buf_size.max(1)];
+    let mut total = 0;
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        writer.write_all(&buf[..n])?;
+        total += n as u64;
+    }
+    Ok(total)
+}
+
+/// Copy up to `limit` bytes from the current offsets, seeking over every
+/// [`SPARSE_BLOCK`] of zeros in `dst` instead of writing it, and setting
+/// the length at the end so that a trailing hole counts. A source that can
+/// say where its holes are has them skipped without being read.
+fn copy_sparse(mut src: &File, mut dst: &File, buffer_size: usize, limit: u64) -> io::Result<u64> {
+    let mut buf = vec![0u8; buffer_size.max(SPARSE_BLOCK)];
+    let mut total = 0;
+    let mut hole = 0;
+    // Pipes have no offset and no holes to ask about.
+    let mut offset = src.stream_position().ok();
+    while total < limit {
+        if let Some(at) = offset {
+            let skip = match crate::platform::next_data(src, at) {
+                Ok(Some(data)) => data.saturating_sub(at),
+                Ok(None) => src.metadata()?.len().saturating_sub(at),
+                Err(_) => 0,
+            }
+            .min(limit - total);
+            offset = Some(src.seek(SeekFrom::Start(at + skip))?);
+            total += skip;
+            hole += skip;
+            if total == limit {
+                break;
+            }
+        }
+        let want = buf
+            .len()
+            .min(usize::try_from(limit - total).unwrap_or(usize::MAX));
+        let n = match src.read(&mut buf[..want]) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        for block in buf[..n].chunks(SPARSE_BLOCK) {
+            if block.iter().all(|&b| b == 0) {
+                hole += block.len() as u64;
+                continue;
+            }
+            if hole > 0 {
+                dst.seek(SeekFrom::Current(hole as i64))?;
+                hole = 0;
+            }
+            dst.write_all(block)?;
+        }
+        total += n as u64;
+        offset = offset.map(|at| at + n as u64);
+    }
+    if hole > 0 {
+        let end = dst.seek(SeekFrom::Current(hole as i64))?;
+        dst.set_len(end)?;
+    }
+    Ok(total)
+}
+
+#[cfg(unix)]
+fn copy_parallel_chunks(reader: &File, writer: &File, limit: u64) -> io::Result<u64> {
+    use std::os::unix::fs::FileExt;
+
+    let len = reader.metadata()?.len().min(limit);
+    writer.set_len(len)?;
+
+    let chunk = len.div_ceil(CHUNK_WORKERS).max(1);
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..CHUNK_WORKERS)
+            .map(|i| (i * chunk, ((i + 1) * chunk).min(len)))
+            .filter(|(start, end)| start < end)
+            .map(|(start, end)| {
+                scope.spawn(move || -> io::Result<()> {
+                    let mut buf = vec![0u8; CHUNK_BUFFER_SIZE];
+                    let mut offset = start;
+                    while offset < end {
+                        let want = ((end - offset) as usize).min(buf.len());
+                        let n = reader.read_at(&mut buf[..want], offset)?;
+                        if n == 0 {
+                            return Err(io::Error::new(
+                                io::ErrorKind::UnexpectedEof,
+                                "source shrank during copy",
+                            ));
+                        }
+                        writer.write_all_at(&buf[..n], offset)?;
+                        offset += n as u64;
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+        workers.into_iter().try_for_each(|worker| {
+            worker
+                .join()
+                .unwrap_or_else(|e| std::panic::resume_unwind(e))
+        })
+    })?;
+    Ok(len)
+}
+
+#[cfg(not(unix))]
+fn copy_parallel_chunks(src: &File, dst: &File, limit: u64) -> io::Result<u64> {
+    io::copy(&mut src.take(limit), &mut &*dst)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    fn selector(small: u64, huge: u64) -> StrategySelector {
+        StrategySelector {
+            small_file_threshold: small,
+            huge_file_threshold: huge,
+            ..StrategySelector::default()
+        }
+    }
+
+    #[test]
+    fn strategies_change_at_the_thresholds() {
+        let selector = selector(100, 1000);
+        assert_eq!(selector.select(0), CopyStrategy::Buffered);
+        assert_eq!(selector.select(99), CopyStrategy::Buffered);
+        assert_eq!(selector.select(100), CopyStrategy::Kernel);
+        assert_eq!(selector.select(999), CopyStrategy::Kernel);
+        assert_eq!(selector.select(1000), CopyStrategy::ParallelChunks);
+        assert_eq!(selector.select(u64::MAX), CopyStrategy::ParallelChunks);
+    }
+
+    #[test]
+    fn defaults_keep_mid_sized_files_in_the_kernel() {
+        let selector = StrategySelector::default();
+        assert_eq!(selector.select(1024), CopyStrategy::Buffered);
+        assert_eq!(
+            selector.select(DEFAULT_SMALL_FILE_THRESHOLD),
+            CopyStrategy::Kernel
+        );
+        assert_eq!(
+            selector.select(DEFAULT_HUGE_FILE_THRESHOLD - 1),
+            CopyStrategy::Kernel
+        );
+        assert_eq!(
+            selector.select(DEFAULT_HUGE_FILE_THRESHOLD),
+            CopyStrategy::ParallelChunks
+        );
+        assert_eq!(selector.buffer_size(), SMALL_BUFFER_SIZE);
+    }
+
+    #[test]
+    fn a_buffer_size_or_sparse_overrides_the_size() {
+        let buffered = StrategySelector {
+            buffer_size: Some(0),
+            ..selector(100, 1000)
+        };
+        assert_eq!(buffered.select(5000), CopyStrategy::Buffered);
+        assert_eq!(buffered.buffer_size(), 1);
+        let sparse = StrategySelector {
+            sparse: true,
+            buffer_size: Some(4096),
+            ..selector(100, 1000)
+        };
+        assert_eq!(sparse.select(0), CopyStrategy::Sparse);
+        assert_eq!(sparse.select(5000), CopyStrategy::Sparse);
+    }
+
+    #[test]
+    fn every_strategy_copies_the_same_bytes() {
+        let dir = TempDir::new().unwrap();
+        let src_path = dir.path().join("src");
+        // Runs of zeros longer than a sparse block among data.
+        let mut data: Vec<u8> = (0..300_000u32).map(|i| (i % 251) as u8).collect();
+        data[10_000..30_000].fill(0);
+        data[290_000..].fill(0);
+        fs::write(&src_path, &data).unwrap();
+        for strategy in [
+            CopyStrategy::Buffered,
+            CopyStrategy::Kernel,
+            CopyStrategy::ParallelChunks,
+            CopyStrategy::Sparse,
+        ] {
+            let dst_path = dir.path().join(strategy.as_str());
+            let src = File::open(&src_path).unwrap();
+            let dst = File::create(&dst_path).unwrap();
+            let copied = copy_with(strategy, 1000, &src, &dst).unwrap();
+            drop(dst);
+            assert_eq!(copied, data.len() as u64, "{strategy:?}");
+            assert!(fs::read(&dst_path).unwrap() == data, "{strategy:?}");
+        }
+    }
+
+    /// Reads at most `step` bytes at a time, failing with `Interrupted`
+    /// before every other read.
+    struct Trickle<'a> {
+        data: &'a [u8],
+        step: usize,
+        interrupt: bool,
+    }
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.interrupt = !self.interrupt;
+            if self.interrupt {
+                return Err(io::ErrorKind::Interrupted.into());
+            }
+            let n = self.step.min(buf.len()).min(self.data.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            Ok(n)
+        }
+    }
+
+    /// Accepts at most `step` bytes per write.
+    struct Narrow {
+        written: Vec<u8>,
+        step: usize,
+    }
+
+    impl Write for Narrow {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let n = self.step.min(buf.len());
+            self.written.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn short_reads_and_writes_are_carried_on_from() {
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+        for (buf_size, read_step, write_step) in [(4096, 7, 5), (3, 100, 2), (1, 1, 1), (0, 9, 4)] {
+            let reader = Trickle {
+                data: &data,
+                step: read_step,
+                interrupt: false,
+            };
+            let mut writer = Narrow {
+                written: Vec::new(),
+                step: write_step,
+            };
+            let copied = copy_contents(reader, &mut writer, buf_size).unwrap();
+            assert_eq!(copied, data.len() as u64, "{buf_size}");
+            assert!(writer.written == data, "{buf_size}");
+        }
+    }
+
+    #[test]
+    fn a_failed_read_or_write_is_passed_on() {
+        struct Broken;
+        impl Read for Broken {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                Err(io::ErrorKind::BrokenPipe.into())
+            }
+        }
+        let err = copy_contents(Broken, io::sink(), 16).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+        let mut full = [0u8; 4];
+        let err = copy_contents(&b"0123456789"[..], &mut full[..], 3).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WriteZero);
+        assert_eq!(&full, b"0123");
+    }
+
+    #[test]
+    fn a_limited_copy_stops_and_leaves_the_handles_after_it() {
+        let dir = TempDir::new().unwrap();
+        let src_path = dir.path().join("src");
+        fs::write(&src_path, b"0123456789").unwrap();
+        for strategy in [
+            CopyStrategy::Buffered,
+            CopyStrategy::Kernel,
+            CopyStrategy::ParallelChunks,
+        ] {
+            let dst_path = dir.path().join(strategy.as_str());
+            let mut src = File::open(&src_path).unwrap();
+            let dst = File::create(&dst_path).unwrap();
+            assert_eq!(copy_at_most(strategy, 3, &src, &dst, 4).unwrap(), 4);
+            assert_eq!(copy_with(CopyStrategy::Kernel, 3, &src, &dst).unwrap(), 6);
+            assert_eq!(src.stream_position().unwrap(), 10);
+            drop(dst);
+            assert_eq!(fs::read(&dst_path).unwrap(), b"0123456789");
+        }
+    }
+}
@@ -0,0 +1,150 @@
+//! Cap on the bytes an operation writes, shared across files and threads.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[cfg(feature = "json")]
+use serde::Serialize;
+
+use crate::error::{FmanError, FmanResult};
+
+/// Running total of bytes written against a limit. Clones share the total,
+/// so parallel workers charge the same budget.
+///
+/// Data is charged before it is written: a file's known size before its
+/// copy starts, and any growth as it is met.
+#[derive(Debug, Clone)]
+pub struct ByteBudget {
+    limit: u64,
+    written: Arc<AtomicU64>,
+}
+
+/// How much of a [`ByteBudget`] was used, for reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(Serialize))]
+pub struct BudgetUsage {
+    pub limit: u64,
+    pub written: u64,
+}
+
+impl ByteBudget {
+    pub fn new(limit: u64) -> Self {
+        ByteBudget {
+            limit,
+            written: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    /// Bytes charged so far.
+    pub fn written(&self) -> u64 {
+        self.written.load(Ordering::Relaxed)
+    }
+
+    pub fn usage(&self) -> BudgetUsage {
+        BudgetUsage {
+            limit: self.limit,
+            written: self.written(),
+        }
+    }
+
+    /// Charge `bytes`, or fail with [`FmanError::QuotaExceeded`] and leave
+    /// the total unchanged if they would take it past the limit.
+    pub fn charge(&self, bytes: u64) -> FmanResult<()> {
+        self.written
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |written| {
+                written
+                    .checked_add(bytes)
+                    .filter(|&total| total <= self.limit)
+            })
+            .map(|_| ())
+            .map_err(|written| FmanError::QuotaExceeded {
+                limit: self.limit,
+                written,
+            })
+    }
+
+    /// Give back `bytes` charged for data that was not written after all.
+    pub fn refund(&self, bytes: u64) {
+        let _ = self
+            .written
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |written| {
+                Some(written.saturating_sub(bytes))
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    #[test]
+    fn charges_up_to_the_limit() {
+        let budget = ByteBudget::new(10);
+        budget.charge(4).unwrap();
+        budget.charge(6).unwrap();
+        assert_eq!(
+            budget.usage(),
+            BudgetUsage {
+                limit: 10,
+                written: 10
+            }
+        );
+    }
+
+    #[test]
+    fn a_charge_past_the_limit_fails_and_changes_nothing() {
+        let budget = ByteBudget::new(10);
+        budget.charge(7).unwrap();
+        let err = budget.charge(4).unwrap_err();
+        assert!(
+            matches!(
+                err,
+                FmanError::QuotaExceeded {
+                    limit: 10,
+                    written: 7
+                }
+            ),
+            "{err:?}"
+        );
+        assert_eq!(budget.written(), 7);
+        budget.charge(3).unwrap();
+    }
+
+    #[test]
+    fn a_charge_that_would_overflow_fails() {
+        let budget = ByteBudget::new(u64::MAX);
+        budget.charge(1).unwrap();
+        assert!(budget.charge(u64::MAX).is_err());
+        assert_eq!(budget.written(), 1);
+    }
+
+    #[test]
+    fn refunds_give_bytes_back_but_never_below_zero() {
+        let budget = ByteBudget::new(10);
+        budget.charge(8).unwrap();
+        budget.refund(5);
+        assert_eq!(budget.written(), 3);
+        budget.refund(100);
+        assert_eq!(budget.written(), 0);
+    }
+
+    #[test]
+    fn clones_share_one_total_across_threads() {
+        let budget = ByteBudget::new(1000);
+        let workers: Vec<_> = (0..8)
+            .map(|_| {
+                let budget = budget.clone();
+                thread::spawn(move || (0..200).filter(|_| budget.charge(1).is_ok()).count())
+            })
+            .collect();
+        let charged: usize = workers.into_iter().map(|w| w.join().unwrap()).sum();
+        assert_eq!(charged, 1000);
+        assert_eq!(budget.written(), 1000);
+    }
+}
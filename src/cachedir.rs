@@ -0,0 +1,129 @@
+//! The Cache Directory Tagging convention: applications mark a directory
+//! holding regenerable data with a `CACHEDIR.TAG` file that starts with a
+//! fixed signature, and backup tools leave such directories out.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// Name of the tag file inside a cache directory.
+pub const TAG_NAME: &str = "CACHEDIR.TAG";
+
+/// The bytes a tag file must begin with; anything after them is free text.
+pub const SIGNATURE: &[u8; 43] = b"Signature: 8a477f597d28d172789f06886806bc55";
+
+/// What is left of a tagged directory, like tar's `--exclude-caches-all`,
+/// `--exclude-caches` and `--exclude-caches-under`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExcludeCaches {
+    /// Nothing: the directory itself is left out too.
+    #[default]
+    All,
+    /// The directory with only its tag file in it.
+    Tag,
+    /// The directory, empty.
+    Contents,
+}
+
+/// Whether `dir` holds a tag file with a valid signature. Only the
+/// signature's length is read from the file; one that cannot be read does
+/// not count.
+pub fn is_cache_dir(dir: &Path) -> bool {
+    let Ok(file) = File::open(dir.join(TAG_NAME)) else {
+        return false;
+    };
+    let mut start = [0u8; SIGNATURE.len()];
+    file.take(SIGNATURE.len() as u64)
+        .read_exact(&mut start)
+        .is_ok_and(|()| &start == SIGNATURE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    fn tagged(contents: &[u8]) -> TempDir {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join(TAG_NAME), contents).unwrap();
+        dir
+    }
+
+    #[test]
+    fn the_bare_signature_tags_a_directory() {
+        assert!(is_cache_dir(tagged(SIGNATURE).path()));
+    }
+
+    #[test]
+    fn text_after_the_signature_is_ignored() {
+        let mut contents = SIGNATURE.to_vec();
+        contents.extend_from_slice(b"\n# This file is a cache directory tag.\n");
+        assert!(is_cache_dir(tagged(&contents).path()));
+    }
+
+    #[test]
+    fn a_wrong_or_short_signature_does_not_tag() {
+        let mut wrong = SIGNATURE.to_vec();
+        *wrong.last_mut().unwrap() = b'6';
+        for contents in [&wrong[..], &SIGNATURE[..42], b"", b"Signature: nope"] {
+            assert!(!is_cache_dir(tagged(contents).path()), "{contents:?}");
+        }
+    }
+
+    #[test]
+    fn a_lowercase_name_does_not_tag() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("cachedir.tag"), SIGNATURE).unwrap();
+        // Case-insensitive filesystems see the proper name here too.
+        let insensitive = dir.path().join(TAG_NAME).exists();
+        assert_eq!(is_cache_dir(dir.path()), insensitive);
+    }
+
+    #[test]
+    fn an_untagged_or_missing_directory_is_not_a_cache() {
+        let dir = TempDir::new().unwrap();
+        assert!(!is_cache_dir(dir.path()));
+        assert!(!is_cache_dir(&dir.path().join("missing")));
+    }
+
+    #[test]
+    fn a_tag_that_is_a_directory_does_not_tag() {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir(dir.path().join(TAG_NAME)).unwrap();
+        assert!(!is_cache_dir(dir.path()));
+    }
+
+    /// A tag that is a pipe whose writer stays open after the signature
+    /// would block any read past it.
+    #[cfg(unix)]
+    #[test]
+    fn only_the_signature_is_read() {
+        use std::io::Write;
+        use std::os::unix::ffi::OsStrExt;
+        use std::sync::mpsc;
+        use std::time::Duration;
+
+        let dir = TempDir::new().unwrap();
+        let tag = dir.path().join(TAG_NAME);
+        let c_path = std::ffi::CString::new(tag.as_os_str().as_bytes()).unwrap();
+        // SAFETY: `c_path` is a valid NUL-terminated string.
+        assert_eq!(unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) }, 0);
+
+        let (done, finished) = mpsc::channel();
+        let writer = std::thread::spawn(move || {
+            let mut pipe = fs::OpenOptions::new().write(true).open(tag).unwrap();
+            pipe.write_all(SIGNATURE).unwrap();
+            // Hold the pipe open until the check is over.
+            finished.recv().ok();
+        });
+        let (result, checked) = mpsc::channel();
+        let path = dir.path().to_path_buf();
+        std::thread::spawn(move || result.send(is_cache_dir(&path)).unwrap());
+        let tagged = checked.recv_timeout(Duration::from_secs(10));
+        done.send(()).unwrap();
+        writer.join().unwrap();
+        assert_eq!(tagged, Ok(true));
+    }
+}
@@ -0,0 +1,296 @@
+//! Record of the files a recursive copy has finished, so that a rerun after
+//! an interruption can skip them.
+//!
+//! The file is JSON lines: a [`CheckpointHeader`] describing the copy, then
+//! one line per completed file with the source's size and mtime at the time
+//! it was copied. Lines are appended as files complete and flushed at most
+//! a second apart; a line torn by a crash is ignored on the next run.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{FmanError, FmanResult};
+use crate::fs::Fs;
+use crate::sync::FileState;
+
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What a checkpoint was written for. Resuming needs an exact match.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CheckpointHeader {
+    /// Canonical source root.
+    pub source: PathBuf,
+    /// Canonical destination root.
+    pub destination: PathBuf,
+    /// Options that change which files are copied, or how or where to.
+    pub options: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Completed {
+    path: String,
+    #[serde(flatten)]
+    state: FileState,
+}
+
+pub struct Checkpoint {
+    path: PathBuf,
+    done: HashMap<String, FileState>,
+    /// `None` when the filesystem only records.
+    writer: Option<BufWriter<File>>,
+    last_flush: Instant,
+}
+
+/// The header of the checkpoint at `path`, if there is a readable one.
+pub fn read_header(path: &Path) -> Option<CheckpointHeader> {
+    let text = fs::read_to_string(path).ok()?;
+    serde_json::from_str(text.lines().next()?).ok()
+}
+
+impl Checkpoint {
+    /// Open the checkpoint at `path` for the copy described by `header`,
+    /// resuming from it if it exists. A checkpoint written for a different
+    /// copy is refused rather than overwritten.
+    ///
+    /// The file is rewritten through `filesystem` with the entries read
+    /// back, dropping any torn line.
+    pub fn open(path: &Path, header: CheckpointHeader, filesystem: &dyn Fs) -> FmanResult<Self> {
+        let done = match fs::read_to_string(path) {
+            Ok(text) if text.trim().is_empty() => HashMap::new(),
+            Ok(text) => read(path, &text, &header)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let mut checkpoint = Checkpoint {
+            path: path.to_path_buf(),
+            done,
+            writer: filesystem.create_file(path, false)?.map(BufWriter::new),
+            last_flush: Instant::now(),
+        };
+        checkpoint.write_line(&header)?;
+        let done: Vec<_> = checkpoint
+            .done
+            .iter()
+            .map(|(path, &state)| Completed {
+                path: path.clone(),
+                state,
+            })
+            .collect();
+        for completed in &done {
+            checkpoint.write_line(completed)?;
+        }
+        checkpoint.flush()?;
+        Ok(checkpoint)
+    }
+
+    /// Whether `relative` was copied on an earlier run from a source that
+    /// still has the same size and mtime.
+    pub fn is_done(&self, relative: &str, state: &FileState) -> bool {
+        self.done.get(relative) == Some(state)
+    }
+
+    /// Files listed as done, including those from earlier runs.
+    pub fn len(&self) -> usize {
+        self.done.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.done.is_empty()
+    }
+
+    /// Note `relative` as copied from a source in `state`.
+    pub fn record(&mut self, relative: &str, state: FileState) -> FmanResult<()> {
+        self.done.insert(relative.to_string(), state);
+        self.write_line(&Completed {
+            path: relative.to_string(),
+            state,
+        })?;
+        if self.last_flush.elapsed() >= FLUSH_INTERVAL {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> FmanResult<()> {
+        if let Some(writer) = &mut self.writer {
+            writer.flush()?;
+        }
+        self.last_flush = Instant::now();
+        Ok(())
+    }
+
+    /// The copy completed: remove the checkpoint through `filesystem`, or
+    /// only flush it if `keep` is set.
+    pub fn finish(mut self, keep: bool, filesystem: &dyn Fs) -> FmanResult<()> {
+        self.flush()?;
+        if !keep {
+            drop(self.writer.take());
+            filesystem.remove_file(&self.path)?;
+        }
+        Ok(())
+    }
+
+    fn write_line(&mut self, value: &impl Serialize) -> FmanResult<()> {
+        let Some(writer) = &mut self.writer else {
+            return Ok(());
+        };
+        let line = serde_json::to_string(value)
+            .map_err(|e| FmanError::InvalidInput(format!("cannot encode checkpoint: {e}")))?;
+        writeln!(writer, "{line}")?;
+        Ok(())
+    }
+}
+
+fn read(
+    path: &Path,
+    text: &str,
+    header: &CheckpointHeader,
+) -> FmanResult<HashMap<String, FileState>> {
+    let mut lines = text.lines();
+    let written: Option<CheckpointHeader> = lines
+        .next()
+        .and_then(|line| serde_json::from_str(line).ok());
+    match written {
+        Some(written) if written == *header => {}
+        Some(written) => {
+            return Err(FmanError::InvalidInput(format!(
+                "checkpoint {} belongs to a different copy ({} to {}); remove it or pick another",
+                path.display(),
+                written.source.display(),
+                written.destination.display()
+            )));
+        }
+        None => {
+            return Err(FmanError::InvalidInput(format!(
+                "{} is not a copy checkpoint",
+                path.display()
+            )));
+        }
+    }
+    Ok(lines
+        .filter_map(|line| serde_json::from_str::<Completed>(line).ok())
+        .map(|completed| (completed.path, completed.state))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::UNIX_EPOCH;
+
+    use tempfile::TempDir;
+
+    use crate::fs::{DryRunFs, RealFs};
+
+    fn header(destination: &str) -> CheckpointHeader {
+        CheckpointHeader {
+            source: "/src".into(),
+            destination: destination.into(),
+            options: BTreeMap::from([("symlinks".to_string(), "Never".to_string())]),
+        }
+    }
+
+    fn state(size: u64, secs: u64) -> FileState {
+        FileState {
+            size,
+            modified: UNIX_EPOCH + Duration::from_secs(secs),
+        }
+    }
+
+    #[test]
+    fn a_reopened_checkpoint_lists_recorded_files() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("cp");
+        let mut checkpoint = Checkpoint::open(&path, header("/dst"), &RealFs).unwrap();
+        assert!(checkpoint.is_empty());
+        checkpoint.record("a", state(1, 10)).unwrap();
+        checkpoint.record("sub/b", state(2, 20)).unwrap();
+        checkpoint.flush().unwrap();
+        drop(checkpoint);
+
+        assert_eq!(read_header(&path), Some(header("/dst")));
+        let checkpoint = Checkpoint::open(&path, header("/dst"), &RealFs).unwrap();
+        assert_eq!(checkpoint.len(), 2);
+        assert!(checkpoint.is_done("sub/b", &state(2, 20)));
+        assert!(!checkpoint.is_done("sub/b", &state(3, 20)));
+        assert!(!checkpoint.is_done("a", &state(1, 11)));
+        assert!(!checkpoint.is_done("c", &state(1, 10)));
+    }
+
+    #[test]
+    fn a_torn_last_line_is_dropped() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("cp");
+        let mut checkpoint = Checkpoint::open(&path, header("/dst"), &RealFs).unwrap();
+        checkpoint.record("a", state(1, 10)).unwrap();
+        checkpoint.flush().unwrap();
+        drop(checkpoint);
+        let mut text = fs::read_to_string(&path).unwrap();
+        text.push_str("{\"path\":\"b\",\"si");
+        fs::write(&path, text).unwrap();
+
+        let checkpoint = Checkpoint::open(&path, header("/dst"), &RealFs).unwrap();
+        assert_eq!(checkpoint.len(), 1);
+        drop(checkpoint);
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 2);
+    }
+
+    #[test]
+    fn a_checkpoint_for_another_copy_is_refused() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("cp");
+        Checkpoint::open(&path, header("/dst"), &RealFs).unwrap();
+        let err = Checkpoint::open(&path, header("/elsewhere"), &RealFs)
+            .err()
+            .unwrap();
+        assert!(matches!(err, FmanError::InvalidInput(_)), "{err}");
+        assert!(err.to_string().contains("different copy"), "{err}");
+        assert_eq!(read_header(&path), Some(header("/dst")));
+
+        let mut other = header("/dst");
+        other
+            .options
+            .insert("transform".to_string(), "true".to_string());
+        assert!(Checkpoint::open(&path, other, &RealFs).is_err());
+    }
+
+    #[test]
+    fn a_file_that_is_not_a_checkpoint_is_refused() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("notes.txt");
+        fs::write(&path, "hello\n").unwrap();
+        let err = Checkpoint::open(&path, header("/dst"), &RealFs)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("not a copy checkpoint"), "{err}");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "hello\n");
+    }
+
+    #[test]
+    fn finish_removes_the_checkpoint_unless_kept() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("cp");
+        let checkpoint = Checkpoint::open(&path, header("/dst"), &RealFs).unwrap();
+        checkpoint.finish(true, &RealFs).unwrap();
+        assert!(path.exists());
+        let checkpoint = Checkpoint::open(&path, header("/dst"), &RealFs).unwrap();
+        checkpoint.finish(false, &RealFs).unwrap();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn a_dry_run_writes_no_checkpoint() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("cp");
+        let mut checkpoint = Checkpoint::open(&path, header("/dst"), &DryRunFs::new()).unwrap();
+        checkpoint.record("a", state(1, 10)).unwrap();
+        checkpoint.flush().unwrap();
+        assert!(!path.exists());
+    }
+}
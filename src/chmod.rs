@@ -0,0 +1,258 @@
+//! Changing permission bits, like `chmod`.
+
+use std::fs::{self, Metadata, Permissions};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::error::{Failures, FmanError, FmanResult};
+use crate::fs::Fs;
+use crate::validate::ensure_exists;
+use crate::walk::{SymlinkPolicy, Walk};
+
+/// A permission change [`chmod_path`] applies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModeChange {
+    /// Set every permission bit, as in `644`. Unix only.
+    Octal(u32),
+    /// Grant (`u+w`) or revoke (`u-w`) the owner's write permission; off
+    /// Unix this clears or sets the read-only attribute.
+    OwnerWrite(bool),
+}
+
+impl ModeChange {
+    /// Parse an octal mode of up to four digits, or `u+w` / `u-w`.
+    pub fn parse(mode: &str) -> FmanResult<Self> {
+        match mode {
+            "u+w" => return Ok(ModeChange::OwnerWrite(true)),
+            "u-w" => return Ok(ModeChange::OwnerWrite(false)),
+            _ => {}
+        }
+        let octal =
+            !mode.is_empty() && mode.len() <= 4 && mode.bytes().all(|b| (b'0'..=b'7').contains(&b));
+        match u32::from_str_radix(mode, 8) {
+            Ok(bits) if octal => Ok(ModeChange::Octal(bits)),
+            _ => Err(FmanError::InvalidInput(format!(
+                "invalid mode {mode:?}: expected octal such as 644, or u+w / u-w"
+            ))),
+        }
+    }
+
+    /// `meta`'s permissions with this change made.
+    #[cfg(unix)]
+    fn apply(self, meta: &Metadata) -> io::Result<Permissions> {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = meta.permissions().mode();
+        Ok(Permissions::from_mode(match self {
+            ModeChange::Octal(bits) => bits,
+            ModeChange::OwnerWrite(true) => mode | 0o200,
+            ModeChange::OwnerWrite(false) => mode & !0o200,
+        }))
+    }
+
+    #[cfg(not(unix))]
+    fn apply(self, meta: &Metadata) -> io::Result<Permissions> {
+        let ModeChange::OwnerWrite(writable) = self else {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "numeric modes are only supported on Unix; use u+w or u-w",
+            ));
+        };
+        let mut permissions = meta.permissions();
+        permissions.set_readonly(!writable);
+        Ok(permissions)
+    }
+}
+
+/// Apply `change` to `path` (following it if it is a symlink) and, with
+/// `recursive`, to everything below it, returning the paths changed.
+///
+/// Symlinks below `path` are skipped, like `chmod -R`. Failures below
+/// `path` do not stop the rest; they go into `failures` and are returned
+/// together.
+pub fn chmod_path(
+    path: &Path,
+    change: ModeChange,
+    recursive: bool,
+    filesystem: &dyn Fs,
+    mut failures: Failures,
+) -> FmanResult<Vec<PathBuf>> {
+    ensure_exists(path)?;
+    let set = |path: &Path, meta: &Metadata| filesystem.set_permissions(path, change.apply(meta)?);
+    let meta = fs::metadata(path)?;
+    set(path, &meta)?;
+    let mut changed = vec![path.to_path_buf()];
+    if !recursive || !meta.is_dir() {
+        return Ok(changed);
+    }
+
+    for entry in Walk::new(path).symlinks(SymlinkPolicy::Never) {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                failures.push(path, e)?;
+                continue;
+            }
+        };
+        if entry.file_type().is_symlink() {
+            continue;
+        }
+        let result = entry
+            .metadata()
+            .and_then(|meta| Ok(set(entry.path(), &meta)?));
+        match result {
+            Ok(()) => changed.push(entry.path().to_path_buf()),
+            Err(e) => failures.push(entry.path(), e)?,
+        }
+    }
+    failures.into_result(changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    use crate::error::DEFAULT_MAX_ERRORS;
+    use crate::fs::{DryRunFs, RealFs};
+
+    fn chmod(
+        path: &Path,
+        change: ModeChange,
+        recursive: bool,
+        filesystem: &dyn Fs,
+    ) -> FmanResult<Vec<PathBuf>> {
+        let failures = Failures::new(DEFAULT_MAX_ERRORS, None)?;
+        chmod_path(path, change, recursive, filesystem, failures)
+    }
+
+    #[test]
+    fn modes_parse_as_octal_or_owner_write() {
+        assert_eq!(ModeChange::parse("644").unwrap(), ModeChange::Octal(0o644));
+        assert_eq!(ModeChange::parse("0755").unwrap(), ModeChange::Octal(0o755));
+        assert_eq!(
+            ModeChange::parse("1777").unwrap(),
+            ModeChange::Octal(0o1777)
+        );
+        assert_eq!(ModeChange::parse("0").unwrap(), ModeChange::Octal(0));
+        assert_eq!(
+            ModeChange::parse("u+w").unwrap(),
+            ModeChange::OwnerWrite(true)
+        );
+        assert_eq!(
+            ModeChange::parse("u-w").unwrap(),
+            ModeChange::OwnerWrite(false)
+        );
+    }
+
+    #[test]
+    fn bad_modes_are_invalid_input() {
+        for mode in ["", "8", "648", "12345", "+644", "-1", "rwx", "u+x", "0x1f"] {
+            let err = ModeChange::parse(mode).unwrap_err();
+            assert!(matches!(err, FmanError::InvalidInput(_)), "{mode:?}: {err}");
+        }
+    }
+
+    #[test]
+    fn a_missing_path_is_not_found() {
+        let dir = TempDir::new().unwrap();
+        let err = chmod(
+            &dir.path().join("missing"),
+            ModeChange::OwnerWrite(true),
+            false,
+            &RealFs,
+        )
+        .unwrap_err();
+        assert!(err.is_not_found(), "{err}");
+    }
+
+    #[test]
+    fn owner_write_toggles_read_only() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("f");
+        fs::write(&path, "x").unwrap();
+        chmod(&path, ModeChange::OwnerWrite(false), false, &RealFs).unwrap();
+        assert!(fs::metadata(&path).unwrap().permissions().readonly());
+        chmod(&path, ModeChange::OwnerWrite(true), false, &RealFs).unwrap();
+        assert!(!fs::metadata(&path).unwrap().permissions().readonly());
+    }
+
+    #[test]
+    fn changes_go_through_the_filesystem() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("f");
+        fs::write(&path, "x").unwrap();
+        let dry_run = DryRunFs::new();
+        let changed = chmod(&path, ModeChange::OwnerWrite(false), false, &dry_run).unwrap();
+        assert_eq!(changed, [path.as_path()]);
+        assert_eq!(dry_run.ops().len(), 1);
+        assert!(!fs::metadata(&path).unwrap().permissions().readonly());
+    }
+
+    #[cfg(unix)]
+    mod unix {
+        use super::*;
+
+        use std::os::unix::fs::PermissionsExt;
+
+        fn mode(path: &Path) -> u32 {
+            fs::metadata(path).unwrap().permissions().mode() & 0o7777
+        }
+
+        #[test]
+        fn an_octal_mode_is_read_back() {
+            let dir = TempDir::new().unwrap();
+            let path = dir.path().join("f");
+            fs::write(&path, "x").unwrap();
+            for bits in [0o644, 0o600, 0o755, 0o4711] {
+                chmod(&path, ModeChange::Octal(bits), false, &RealFs).unwrap();
+                assert_eq!(mode(&path), bits);
+            }
+        }
+
+        #[test]
+        fn owner_write_keeps_the_other_bits() {
+            let dir = TempDir::new().unwrap();
+            let path = dir.path().join("f");
+            fs::write(&path, "x").unwrap();
+            fs::set_permissions(&path, Permissions::from_mode(0o754)).unwrap();
+            chmod(&path, ModeChange::OwnerWrite(false), false, &RealFs).unwrap();
+            assert_eq!(mode(&path), 0o554);
+        }
+
+        #[test]
+        fn recursive_changes_everything_but_symlinks() {
+            let dir = TempDir::new().unwrap();
+            let root = dir.path().join("r");
+            fs::create_dir_all(root.join("sub")).unwrap();
+            fs::write(root.join("a"), "").unwrap();
+            fs::write(root.join("sub/b"), "").unwrap();
+            let outside = dir.path().join("outside");
+            fs::write(&outside, "").unwrap();
+            fs::set_permissions(&outside, Permissions::from_mode(0o600)).unwrap();
+            std::os::unix::fs::symlink(&outside, root.join("link")).unwrap();
+
+            let changed = chmod(&root, ModeChange::Octal(0o750), true, &RealFs).unwrap();
+            assert_eq!(changed.len(), 4);
+            for path in [
+                root.clone(),
+                root.join("a"),
+                root.join("sub"),
+                root.join("sub/b"),
+            ] {
+                assert_eq!(mode(&path), 0o750, "{}", path.display());
+            }
+            assert_eq!(mode(&outside), 0o600);
+        }
+
+        #[test]
+        fn without_recursive_only_the_directory_changes() {
+            let dir = TempDir::new().unwrap();
+            fs::write(dir.path().join("a"), "").unwrap();
+            fs::set_permissions(dir.path().join("a"), Permissions::from_mode(0o600)).unwrap();
+            chmod(dir.path(), ModeChange::Octal(0o700), false, &RealFs).unwrap();
+            assert_eq!(mode(dir.path()), 0o700);
+            assert_eq!(mode(&dir.path().join("a")), 0o600);
+        }
+    }
+}
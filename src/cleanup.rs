@@ -0,0 +1,347 @@
+//! Undoing what an operation created when it stops part way.
+//!
+//! An operation registers each artifact as it creates it (a file being
+//! written, a directory, a temporary file) and forgets it again once the
+//! artifact is complete. If the operation fails or is cancelled first, the
+//! registry is run and undoes whatever is still registered, newest first.
+//! Callers that undo things themselves, such as a transaction spanning
+//! several operations, can hand their own registry in and [`take`] the
+//! actions instead of running them.
+//!
+//! [`take`]: CleanupRegistry::take
+
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::fs::Fs;
+
+/// One way of undoing an artifact.
+pub enum Cleanup {
+    /// Remove a file fman created.
+    RemoveFile(PathBuf),
+    /// Remove a directory fman created, if nothing has been put in it.
+    RemoveEmptyDir(PathBuf),
+    /// Move something fman moved out of the way back to where it was.
+    Restore { from: PathBuf, to: PathBuf },
+    /// Anything else, such as releasing a lock, with a description for
+    /// the log.
+    Custom {
+        description: String,
+        undo: Box<dyn FnOnce() -> io::Result<()> + Send>,
+    },
+}
+
+impl fmt::Debug for Cleanup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Cleanup::RemoveFile(path) => f.debug_tuple("RemoveFile").field(path).finish(),
+            Cleanup::RemoveEmptyDir(path) => f.debug_tuple("RemoveEmptyDir").field(path).finish(),
+            Cleanup::Restore { from, to } => f
+                .debug_struct("Restore")
+                .field("from", from)
+                .field("to", to)
+                .finish(),
+            Cleanup::Custom { description, .. } => f
+                .debug_struct("Custom")
+                .field("description", description)
+                .finish_non_exhaustive(),
+        }
+    }
+}
+
+impl fmt::Display for Cleanup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Cleanup::RemoveFile(path) => write!(f, "remove {}", path.display()),
+            Cleanup::RemoveEmptyDir(path) => write!(f, "remove directory {}", path.display()),
+            Cleanup::Restore { from, to } => {
+                write!(f, "move {} back to {}", from.display(), to.display())
+            }
+            Cleanup::Custom { description, .. } => f.write_str(description),
+        }
+    }
+}
+
+impl Cleanup {
+    fn run(self, filesystem: &dyn Fs) -> io::Result<()> {
+        let result = match self {
+            Cleanup::RemoveFile(path) => filesystem.remove_file(&path),
+            Cleanup::RemoveEmptyDir(path) => match filesystem.remove_dir(&path) {
+                // Holds something that is not ours to remove.
+                Err(e) if e.kind() == io::ErrorKind::DirectoryNotEmpty => Ok(()),
+                result => result,
+            },
+            Cleanup::Restore { from, to } => filesystem.rename(&from, &to),
+            Cleanup::Custom { undo, .. } => undo(),
+        };
+        match result {
+            // Already gone is as good as removed.
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+}
+
+/// Handle for a registered action, to [`forget`](CleanupRegistry::forget)
+/// it once its artifact is complete.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CleanupId(u64);
+
+/// An action that failed while cleaning up.
+#[derive(Debug)]
+pub struct CleanupFailure {
+    /// What the action was, as [`Cleanup`] displays it.
+    pub action: String,
+    pub error: io::Error,
+}
+
+/// Actions undoing what an operation has created so far. Clones share
+/// them, so one registry can be handed to several operations.
+#[derive(Debug, Clone, Default)]
+pub struct CleanupRegistry {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    next: u64,
+    /// Oldest first.
+    actions: Vec<(CleanupId, Cleanup)>,
+}
+
+impl CleanupRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register `action` to run if the operation fails.
+    pub fn push(&self, action: Cleanup) -> CleanupId {
+        self.with_inner(|inner| {
+            let id = CleanupId(inner.next);
+            inner.next += 1;
+            inner.actions.push((id, action));
+            id
+        })
+    }
+
+    /// Drop the action registered as `id`: its artifact is complete and
+    /// stays.
+    pub fn forget(&self, id: CleanupId) {
+        self.with_inner(|inner| inner.actions.retain(|(other, _)| *other != id));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.with_inner(|inner| inner.actions.is_empty())
+    }
+
+    /// Remove every registered action without running it, oldest first.
+    pub fn take(&self) -> Vec<Cleanup> {
+        self.with_inner(|inner| inner.actions.drain(..).map(|(_, action)| action).collect())
+    }
+
+    /// Run every registered action through `filesystem`, newest first,
+    /// carrying on past failures and returning them.
+    pub fn run(&self, filesystem: &dyn Fs) -> Vec<CleanupFailure> {
+        let mut failures = Vec::new();
+        for action in self.take().into_iter().rev() {
+            let description = action.to_string();
+            if let Err(error) = action.run(filesystem) {
+                failures.push(CleanupFailure {
+                    action: description,
+                    error,
+                });
+            }
+        }
+        failures
+    }
+
+    /// Run the registry because the operation failed with `error`, which
+    /// is returned unchanged. Failures while cleaning up are appended to
+    /// the error log at `log`, if any, and never replace `error`.
+    pub fn unwind<T, E>(&self, filesystem: &dyn Fs, log: Option<&Path>, error: E) -> Result<T, E> {
+        let failures = self.run(filesystem);
+        if let Some(log) = log
+            && !failures.is_empty()
+        {
+            let _ = log_failures(log, &failures);
+        }
+        Err(error)
+    }
+
+    fn with_inner<T>(&self, f: impl FnOnce(&mut Inner) -> T) -> T {
+        f(&mut self.inner.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+fn log_failures(log: &Path, failures: &[CleanupFailure]) -> io::Result<()> {
+    let mut log = OpenOptions::new().create(true).append(true).open(log)?;
+    for failure in failures {
+        writeln!(log, "cleanup: {}: {}", failure.action, failure.error)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    use crate::error::FmanError;
+    use crate::fs::RealFs;
+
+    /// A custom action that appends `name` to `order` when run.
+    fn note(order: &Arc<Mutex<Vec<&'static str>>>, name: &'static str) -> Cleanup {
+        let order = order.clone();
+        Cleanup::Custom {
+            description: name.to_string(),
+            undo: Box::new(move || {
+                order.lock().unwrap().push(name);
+                Ok(())
+            }),
+        }
+    }
+
+    fn failing(description: &str) -> Cleanup {
+        Cleanup::Custom {
+            description: description.to_string(),
+            undo: Box::new(|| Err(io::Error::other("stuck"))),
+        }
+    }
+
+    #[test]
+    fn actions_run_newest_first() {
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let registry = CleanupRegistry::new();
+        for name in ["first", "second", "third"] {
+            registry.push(note(&order, name));
+        }
+        assert!(registry.run(&RealFs).is_empty());
+        assert_eq!(*order.lock().unwrap(), ["third", "second", "first"]);
+        assert!(registry.is_empty());
+    }
+
+    #[test]
+    fn a_forgotten_action_does_not_run() {
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let registry = CleanupRegistry::new();
+        registry.push(note(&order, "kept"));
+        let done = registry.push(note(&order, "done"));
+        registry.forget(done);
+        registry.run(&RealFs);
+        assert_eq!(*order.lock().unwrap(), ["kept"]);
+    }
+
+    #[test]
+    fn taking_hands_over_the_actions_without_running_them() {
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let registry = CleanupRegistry::new();
+        registry.push(note(&order, "a"));
+        registry.push(Cleanup::RemoveFile(PathBuf::from("f")));
+        let taken = registry.take();
+        assert_eq!(
+            taken.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            ["a", "remove f"]
+        );
+        assert!(order.lock().unwrap().is_empty());
+        assert!(registry.is_empty());
+    }
+
+    #[test]
+    fn clones_share_the_actions() {
+        let registry = CleanupRegistry::new();
+        registry
+            .clone()
+            .push(Cleanup::RemoveFile(PathBuf::from("f")));
+        assert!(!registry.is_empty());
+    }
+
+    #[test]
+    fn files_and_directories_are_removed() {
+        let dir = TempDir::new().unwrap();
+        let (file, empty) = (dir.path().join("f"), dir.path().join("empty"));
+        fs::write(&file, "").unwrap();
+        fs::create_dir(&empty).unwrap();
+        let registry = CleanupRegistry::new();
+        registry.push(Cleanup::RemoveEmptyDir(empty.clone()));
+        registry.push(Cleanup::RemoveFile(file.clone()));
+        assert!(registry.run(&RealFs).is_empty());
+        assert!(!file.exists() && !empty.exists());
+    }
+
+    #[test]
+    fn a_directory_that_gained_contents_stays() {
+        let dir = TempDir::new().unwrap();
+        let full = dir.path().join("full");
+        fs::create_dir(&full).unwrap();
+        fs::write(full.join("not-ours"), "").unwrap();
+        let registry = CleanupRegistry::new();
+        registry.push(Cleanup::RemoveEmptyDir(full.clone()));
+        assert!(registry.run(&RealFs).is_empty());
+        assert!(full.join("not-ours").exists());
+    }
+
+    #[test]
+    fn what_is_already_gone_counts_as_cleaned_up() {
+        let dir = TempDir::new().unwrap();
+        let registry = CleanupRegistry::new();
+        registry.push(Cleanup::RemoveFile(dir.path().join("gone")));
+        registry.push(Cleanup::RemoveEmptyDir(dir.path().join("gone-dir")));
+        assert!(registry.run(&RealFs).is_empty());
+    }
+
+    #[test]
+    fn a_moved_file_is_restored() {
+        let dir = TempDir::new().unwrap();
+        let (original, aside) = (dir.path().join("f"), dir.path().join("f.aside"));
+        fs::write(&aside, "old").unwrap();
+        let registry = CleanupRegistry::new();
+        registry.push(Cleanup::Restore {
+            from: aside.clone(),
+            to: original.clone(),
+        });
+        registry.run(&RealFs);
+        assert_eq!(fs::read_to_string(&original).unwrap(), "old");
+        assert!(!aside.exists());
+    }
+
+    #[test]
+    fn failures_are_collected_and_the_rest_still_runs() {
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let registry = CleanupRegistry::new();
+        registry.push(note(&order, "older"));
+        registry.push(failing("release lock"));
+        let failures = registry.run(&RealFs);
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].action, "release lock");
+        assert_eq!(*order.lock().unwrap(), ["older"]);
+    }
+
+    #[test]
+    fn unwinding_returns_the_original_error_and_logs_cleanup_failures() {
+        let dir = TempDir::new().unwrap();
+        let log = dir.path().join("errors.log");
+        let registry = CleanupRegistry::new();
+        registry.push(failing("release lock"));
+        let result: Result<(), FmanError> =
+            registry.unwind(&RealFs, Some(&log), FmanError::Cancelled);
+        assert!(matches!(result, Err(FmanError::Cancelled)));
+        assert_eq!(
+            fs::read_to_string(&log).unwrap(),
+            "cleanup: release lock: stuck\n"
+        );
+    }
+
+    #[test]
+    fn unwinding_without_a_log_still_keeps_the_error() {
+        let registry = CleanupRegistry::new();
+        registry.push(failing("x"));
+        let result: Result<(), &str> = registry.unwind(&RealFs, None, "original");
+        assert_eq!(result, Err("original"));
+        assert!(registry.is_empty());
+    }
+}
@@ -0,0 +1,3315 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{BufRead, IsTerminal, Write};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use clap::{ArgGroup, Args, CommandFactory, Parser, Subcommand, ValueEnum, ValueHint};
+use fman::cancel::CancelToken;
+use fman::clock::SystemClock;
+use fman::config::{Config, Overwrite};
+use fman::error::{DEFAULT_MAX_ERRORS, ErrorList};
+use fman::format::{self as fmt, FormatTemplate};
+use fman::fs::{DryRunFs, RealFs, SharedFs};
+use fman::glob;
+use fman::guard::{DEFAULT_MAX_MATCHES_WITHOUT_CONFIRM, MatchGuard, Prompter};
+use fman::lock::{FileLock, LockMode};
+use fman::metadata::{MetadataPolicy, MetadataSummary};
+use fman::naming::NamingContext;
+use fman::ops::{
+    self, ApplyRequest, BudgetUsage, ByteBudget, Capability, ChmodRequest, CopyOptions,
+    CopyRequest, CopyStatus, DedupeOptions, DedupeRequest, DeleteOptions, DeleteReport,
+    DeleteRequest, DuRequest, DuWatchRequest, EmptyFilter, EntryKind, ExcludeCaches, FileInfo,
+    FindOptions, FindRequest, FsInfo, HashAlgorithm, HashRequest, Immutability, LinkKind, LinkMode,
+    LinkRequest, ListEntry, ListOptions, ListRequest, MirrorChange, MirrorRequest, ModeChange,
+    MoveOptions, MoveRequest, OverwritePolicy, OwnershipMap, PathStyle, ProcessRunner,
+    RacingPolicy, Reflink, RenameRequest, RewriteRule, RunReport, SidecarRequest, SlowFile,
+    SyncMode, SyncRequest, TemplateOptions, TemplateRequest, TouchRequest, Touched, WatchOptions,
+    WatchRequest, WatchSample,
+};
+use fman::preserve::Attribute;
+use fman::units::{self, format_size};
+use fman::walk::{DEFAULT_MAX_ENTRIES_IN_MEMORY, SymlinkPolicy, WalkOrder};
+use fman::{FmanError, FmanResult};
+
+use crate::help_json;
+
+#[derive(Parser)]
+#[command(
+    name = "fman",
+    version,
+    about = "A lightweight command-line file manager",
+    after_long_help = AFTER_LONG_HELP
+)]
+pub struct Cli {
+    /// Print the result as a single JSON object on stdout
+    #[cfg_attr(feature = "json", arg(long, global = true))]
+    #[cfg_attr(not(feature = "json"), arg(skip))]
+    pub json: bool,
+
+    /// Keep at most N failures of a recursive operation for the final
+    /// report; later ones are only counted
+    #[arg(long, global = true, value_name = "N", default_value_t = DEFAULT_MAX_ERRORS)]
+    pub max_errors: usize,
+
+    /// Check and list what would be done without changing anything; ln,
+    /// mirror-permissions and trash empty show their own preview
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+
+    /// Print each completed copy, move, rename or delete, with the
+    /// destination as resolved [env: FMAN_VERBOSE]
+    #[arg(short, long, global = true)]
+    pub verbose: bool,
+
+    /// Print nothing but the result and errors: no verbose lines, progress,
+    /// summaries or warnings [env: FMAN_QUIET]
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Log what copies and their checks do on stderr, at LEVEL and above
+    #[cfg(feature = "tracing")]
+    #[arg(long, global = true, value_name = "LEVEL")]
+    pub log_level: Option<LogLevelArg>,
+
+    /// Read flag defaults from FILE instead of ~/.config/fman/config.toml
+    /// [env: FMAN_CONFIG]
+    #[arg(long, global = true, value_name = "FILE", value_hint = ValueHint::FilePath)]
+    pub config: Option<PathBuf>,
+
+    /// Append every failure of a recursive operation to FILE
+    #[arg(long, global = true, value_name = "FILE", value_hint = ValueHint::FilePath)]
+    pub error_log: Option<PathBuf>,
+
+    /// Seed generated names (temporary files, conflict suffixes) so a run
+    /// can be reproduced
+    #[arg(long, global = true, hide = true)]
+    pub seed: Option<u64>,
+
+    /// Describe every subcommand and argument as JSON and exit
+    #[arg(long, global = true, hide = true)]
+    pub help_json: bool,
+
+    #[command(subcommand)]
+    pub command: Commands,
+}
+
+#[derive(Subcommand)]
+pub enum Commands {
+    /// Copy files into a path or a directory
+    #[command(group = ArgGroup::new("ownership").multiple(true).args(["ownership_map", "ownership_map_file"]))]
+    Copy {
+        /// A path, or a quoted wildcard pattern whose matches are copied
+        /// into the existing directory DST; several sources are all copied
+        /// into DST, which must then be an existing directory
+        #[arg(required = true, value_name = "SRC", value_hint = ValueHint::AnyPath)]
+        srcs: Vec<String>,
+        #[arg(value_hint = ValueHint::AnyPath)]
+        dst: String,
+        /// Overwrite the destination if it exists [env: FMAN_FORCE]
+        #[arg(short, long, overrides_with = "no_force")]
+        force: bool,
+        #[arg(long, hide = true)]
+        no_force: bool,
+        /// What to do with an existing destination file; --force is short
+        /// for --on-conflict overwrite
+        #[arg(long, value_enum, value_name = "POLICY", conflicts_with_all = ["force", "no_force", "interactive", "update", "no_clobber"])]
+        on_conflict: Option<OnConflictArg>,
+        /// Never replace an existing destination file; short for
+        /// --on-conflict skip
+        #[arg(short, long, conflicts_with_all = ["force", "interactive", "update"])]
+        no_clobber: bool,
+        /// Ask before replacing an existing destination file; anything
+        /// but y or yes skips it
+        #[arg(short, long)]
+        interactive: bool,
+        /// Move an existing destination file to NAME.bak (or NAME.bak.N)
+        /// before replacing it; it is moved back if the copy fails
+        /// [env: FMAN_BACKUP]
+        #[arg(short, long, overrides_with = "no_backup")]
+        backup: bool,
+        #[arg(long, hide = true)]
+        no_backup: bool,
+        /// Detect sources that change while being read (size or mtime)
+        #[arg(long)]
+        detect_racing_writes: bool,
+        /// What to do with a file that kept changing; implies --detect-racing-writes
+        #[arg(long, value_enum, value_name = "POLICY")]
+        racing: Option<RacingArg>,
+        /// Files smaller than this are copied with a small buffer
+        #[arg(long, value_name = "SIZE", default_value = "64K", value_parser = parse_size)]
+        small_file_threshold: u64,
+        /// Files at least this large are copied in parallel chunks
+        #[arg(long, value_name = "SIZE", default_value = "1G", value_parser = parse_size)]
+        huge_file_threshold: u64,
+        /// Copy every file through a buffer of SIZE, e.g. 4K for a slow USB
+        /// stick, instead of picking a method by file size
+        #[arg(long, value_name = "SIZE", value_parser = parse_buffer_size)]
+        buffer_size: Option<usize>,
+        /// Leave holes in the copy where the source has blocks of zeros, so
+        /// sparse files such as disk images stay sparse
+        #[arg(long)]
+        sparse: bool,
+        /// Make the copy share the source's data blocks (Btrfs, XFS), which
+        /// is instant and takes no space until either file changes
+        #[arg(long, value_enum, value_name = "WHEN", num_args = 0..=1, require_equals = true, default_value = "never", default_missing_value = "always")]
+        reflink: ReflinkArg,
+        /// Remove write permission from the destination after copying
+        #[arg(long)]
+        read_only: bool,
+        /// Read each copy back and compare its SHA-256 with the source's,
+        /// removing it and failing on a mismatch
+        #[arg(long)]
+        verify: bool,
+        /// Write each file under a temporary name and rename it into place
+        /// once it is on disk, so a crash never leaves a truncated copy
+        #[arg(long, conflicts_with = "immutable")]
+        atomic: bool,
+        /// Keep the source's access and modification times as well as its
+        /// permission bits [env: FMAN_PRESERVE]
+        #[arg(long, overrides_with = "no_preserve")]
+        preserve: bool,
+        #[arg(long, hide = true)]
+        no_preserve: bool,
+        /// Create the destination directory and any missing parents
+        #[arg(short, long)]
+        parents: bool,
+        /// Set the immutable attribute on the destination (Linux, privileged)
+        #[arg(long, value_enum, value_name = "MODE", num_args = 0..=1, require_equals = true, default_missing_value = "required")]
+        immutable: Option<ImmutableArg>,
+        /// Copy a symlink source as a link instead of following it
+        #[arg(short = 'P', long, overrides_with_all = ["dereference_command_line", "dereference"])]
+        no_dereference: bool,
+        /// Follow symlinks given on the command line only (the default)
+        #[arg(short = 'H', long, overrides_with_all = ["no_dereference", "dereference"])]
+        dereference_command_line: bool,
+        /// Follow every symlink
+        #[arg(short = 'L', long, overrides_with_all = ["no_dereference", "dereference_command_line"])]
+        dereference: bool,
+        /// Copy directories and their contents
+        #[arg(short, long)]
+        recursive: bool,
+        /// With --recursive, count files that disappear mid-copy instead of failing
+        #[arg(long, overrides_with = "no_ignore_vanished")]
+        ignore_vanished: bool,
+        #[arg(long, hide = true)]
+        no_ignore_vanished: bool,
+        /// Copy only when the destination is missing or older than the source
+        #[arg(short, long)]
+        update: bool,
+        /// Treat mtimes at most this far apart as equal, e.g. 2s for FAT
+        #[arg(long, value_name = "DURATION", default_value = "0", value_parser = parse_duration)]
+        modify_window: Duration,
+        /// With --recursive, make names valid on Windows (replace <>:"|?* and
+        /// drop trailing dots and spaces)
+        #[arg(long)]
+        sanitize_windows_names: bool,
+        /// With --recursive, lowercase every name
+        #[arg(long)]
+        lowercase_names: bool,
+        /// With --recursive, record finished files in FILE and skip them when
+        /// an interrupted copy is rerun with the same FILE
+        #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+        checkpoint: Option<PathBuf>,
+        /// Make hard links to the sources instead of copying data
+        #[arg(short = 'l', long, conflicts_with = "symlink")]
+        hardlink: bool,
+        /// Make symlinks to the sources (by absolute path) instead of copying
+        #[arg(short = 's', long)]
+        symlink: bool,
+        /// With --symlink, point links at the sources by relative path
+        #[arg(long, requires = "symlink")]
+        relative: bool,
+        /// Keep the checkpoint after the copy succeeds
+        #[arg(long, requires = "checkpoint")]
+        keep_checkpoint: bool,
+        /// Report each file that takes at least DURATION to copy
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+        min_duration_report: Option<Duration>,
+        /// With --recursive, skip files modified less than DURATION ago,
+        /// which are likely still being written
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+        skip_active: Option<Duration>,
+        /// Retry the files --skip-active skipped once the rest is copied
+        #[arg(long, requires = "skip_active")]
+        retry_active_at_end: bool,
+        /// Stop before writing more than SIZE bytes in total
+        #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+        max_total_bytes: Option<u64>,
+        /// Copy a file's data again up to N times when it fails with an
+        /// error that may pass, such as a timeout on a network mount
+        #[arg(long, value_name = "N", default_value = "0")]
+        retries: u32,
+        /// Wait DURATION before each retry
+        #[arg(long, value_name = "DURATION", default_value = "1s", value_parser = parse_duration)]
+        retry_delay: Duration,
+        /// Copy no data, only apply the source's attributes (mode,
+        /// ownership, times, xattr, or all) to existing destinations; with
+        /// --recursive, DST is the counterpart of SRC itself
+        #[arg(long, value_name = "LIST", num_args = 0..=1, require_equals = true, default_missing_value = "all")]
+        attributes_only: Option<String>,
+        /// With --attributes-only, skip sources missing from the destination
+        #[arg(long, requires = "attributes_only")]
+        ignore_missing: bool,
+        /// Read sources to the end instead of trusting their size; chosen
+        /// automatically for pipes and files under /proc or /sys
+        #[arg(long)]
+        stream: bool,
+        /// Give files owned by OLD_UID (and OLD_GID) the new ids at the
+        /// destination; NEW may be a name on this system
+        #[arg(long, value_name = "OLD_UID:NEW_UID[,OLD_GID:NEW_GID]")]
+        ownership_map: Vec<String>,
+        /// Read ownership mappings from a TOML file with [users] and
+        /// [groups] tables
+        #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+        ownership_map_file: Option<PathBuf>,
+        /// Fail on owners the ownership map has no entry for
+        #[arg(long, requires = "ownership")]
+        require_mapped: bool,
+        /// Fail a file whose mode, owner or times cannot be set, instead of
+        /// warning and giving up on what the filesystem does not support
+        #[arg(long)]
+        strict_metadata: bool,
+        /// With --recursive, leave out directories tagged with a
+        /// CACHEDIR.TAG: all of it, all but the tag file, or just the contents
+        #[arg(long, value_enum, value_name = "MODE", num_args = 0..=1, require_equals = true, default_missing_value = "all")]
+        exclude_caches: Option<ExcludeCachesArg>,
+        /// Fail before copying anything unless the destination filesystem has each
+        /// of these: reflink, xattr, symlink, ownership, sub-second-times,
+        /// sparse
+        #[arg(long, value_name = "CAPABILITIES")]
+        require: Option<String>,
+        /// With several sources or a pattern, copy up to N of them at once
+        /// and report every failure rather than stopping at the first
+        #[arg(
+            short,
+            long,
+            value_name = "N",
+            default_value = "1",
+            conflicts_with = "interactive"
+        )]
+        jobs: NonZeroUsize,
+    },
+    /// Move or rename a file or directory
+    Move {
+        /// A path, or a quoted wildcard pattern moved into DST
+        #[arg(value_hint = ValueHint::AnyPath)]
+        src: String,
+        #[arg(value_hint = ValueHint::AnyPath)]
+        dst: String,
+        /// Overwrite the destination if it exists [env: FMAN_FORCE]
+        #[arg(short, long, overrides_with = "no_force")]
+        force: bool,
+        #[arg(long, hide = true)]
+        no_force: bool,
+        /// When moving across filesystems, hash each copy and keep any
+        /// source whose copy does not match
+        #[arg(long, overrides_with = "no_copy_verify")]
+        copy_verify: bool,
+        #[arg(long, hide = true)]
+        no_copy_verify: bool,
+        #[command(flatten)]
+        guard: GuardArgs,
+    },
+    /// Make a hard link to a file, or a symlink with --symbolic
+    Link {
+        #[arg(value_hint = ValueHint::AnyPath)]
+        src: PathBuf,
+        #[arg(value_hint = ValueHint::AnyPath)]
+        dst: PathBuf,
+        /// Make a symlink instead of a hard link
+        #[arg(short, long)]
+        symbolic: bool,
+        /// Point the symlink at the source by a path relative to the
+        /// link's directory
+        #[arg(short, long, requires = "symbolic")]
+        relative: bool,
+        /// Replace an existing destination that is not a directory
+        #[arg(short, long)]
+        force: bool,
+    },
+    /// Give a file or directory a new name in the same directory
+    Rename {
+        #[arg(value_hint = ValueHint::AnyPath)]
+        path: PathBuf,
+        /// The new file name, without any directory
+        new_name: String,
+        /// Replace an existing entry with that name
+        #[arg(short, long)]
+        force: bool,
+    },
+    /// Delete a file, or a directory tree with --recursive
+    Delete {
+        /// A path, or a quoted wildcard pattern such as 'logs/*.tmp'
+        #[arg(value_hint = ValueHint::AnyPath)]
+        target: PathBuf,
+        /// Remove directories and their contents
+        #[arg(short, long)]
+        recursive: bool,
+        /// Make read-only directories writable so their contents can be
+        /// removed, and succeed when the target does not exist, like rm -rf
+        #[arg(short, long)]
+        force: bool,
+        /// Fail instead of counting entries that disappear mid-delete
+        #[arg(long, overrides_with = "ignore_vanished")]
+        no_ignore_vanished: bool,
+        #[arg(long, hide = true)]
+        ignore_vanished: bool,
+        /// Move the target into the trash can instead of removing it
+        #[arg(long)]
+        trash: bool,
+        /// Afterwards, list processes still holding removed files open
+        #[cfg(target_os = "linux")]
+        #[arg(long, conflicts_with = "trash")]
+        report_open: bool,
+        #[command(flatten)]
+        guard: GuardArgs,
+    },
+    /// Repair symlinks left dangling after what they pointed into moved
+    Ln {
+        /// Look for dangling symlinks below ROOT
+        #[arg(long, value_name = "ROOT", value_hint = ValueHint::DirPath)]
+        fix_dangling: PathBuf,
+        /// Point targets under OLD at the same place under NEW; the first
+        /// matching rule wins
+        #[arg(long, value_name = "OLD=NEW", required = true, value_parser = parse_rewrite)]
+        rewrite: Vec<RewriteRule>,
+    },
+    /// Run a command while holding an advisory lock on a lock file
+    Lock {
+        /// The lock file; created if missing and left in place afterwards
+        #[arg(value_hint = ValueHint::FilePath)]
+        lockfile: PathBuf,
+        /// The command to run, after --
+        #[arg(last = true, required = true, value_name = "COMMAND", value_hint = ValueHint::CommandWithArguments)]
+        command: Vec<OsString>,
+        /// Give up if the lock is not free within DURATION
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+        timeout: Option<Duration>,
+        /// Give up at once if the lock is held
+        #[arg(short, long, conflicts_with = "timeout")]
+        nonblocking: bool,
+        /// Take a shared lock, which other shared holders may hold too
+        #[arg(short, long)]
+        shared: bool,
+    },
+    /// Apply a reference tree's permissions and other metadata to a target tree
+    MirrorPermissions {
+        #[arg(value_hint = ValueHint::AnyPath)]
+        reference: PathBuf,
+        #[arg(value_hint = ValueHint::AnyPath)]
+        target: PathBuf,
+        /// Attributes to apply: mode, ownership, times, xattr, or all
+        #[arg(long, value_name = "LIST", default_value = "mode,ownership")]
+        what: String,
+    },
+    /// Show the type, size, permissions and modification time of a path
+    Info {
+        #[arg(value_hint = ValueHint::AnyPath)]
+        path: PathBuf,
+    },
+    /// Pack a file or directory into a .tar, .tar.gz or .zip archive
+    #[cfg(feature = "archive")]
+    Archive {
+        #[arg(value_hint = ValueHint::AnyPath)]
+        src: PathBuf,
+        /// The archive to write; its extension picks the format
+        #[arg(value_hint = ValueHint::FilePath)]
+        output: PathBuf,
+        /// Overwrite an existing archive
+        #[arg(short, long)]
+        force: bool,
+    },
+    /// Unpack a .tar, .tar.gz or .zip archive
+    #[cfg(feature = "archive")]
+    Extract {
+        #[arg(value_hint = ValueHint::FilePath)]
+        archive: PathBuf,
+        /// Directory to unpack into, created if missing
+        #[arg(default_value = ".", value_hint = ValueHint::DirPath)]
+        dest: PathBuf,
+        /// Replace existing files
+        #[arg(short, long)]
+        force: bool,
+    },
+    /// Set permission bits, as an octal mode or u+w / u-w
+    Chmod {
+        /// Octal such as 644 (Unix only), or u+w / u-w
+        mode: String,
+        #[arg(required = true, value_hint = ValueHint::AnyPath)]
+        paths: Vec<PathBuf>,
+        /// Change directories' contents too
+        #[arg(short = 'R', long)]
+        recursive: bool,
+    },
+    /// Create empty files, or set the times of existing ones to now
+    Touch {
+        #[arg(required = true, value_hint = ValueHint::AnyPath)]
+        paths: Vec<PathBuf>,
+        /// Do not create missing files
+        #[arg(short = 'c', long)]
+        no_create: bool,
+    },
+    /// Show the filesystem a path lives on and what it supports
+    FsInfo {
+        #[arg(default_value = ".", value_hint = ValueHint::AnyPath)]
+        path: PathBuf,
+    },
+    /// Bring two directories in step
+    Sync {
+        #[arg(value_hint = ValueHint::DirPath)]
+        a: PathBuf,
+        #[arg(value_hint = ValueHint::DirPath)]
+        b: PathBuf,
+        /// Propagate changes both ways, keeping both versions on conflict
+        #[arg(long)]
+        bidirectional: bool,
+        /// Baseline from the previous --bidirectional run, updated after this
+        /// one; needed to propagate deletions. Keep it outside both trees.
+        #[arg(long, value_name = "PATH", requires = "bidirectional", value_hint = ValueHint::FilePath)]
+        state_file: Option<PathBuf>,
+        /// Treat mtimes at most this far apart as equal, e.g. 2s for FAT
+        #[arg(long, value_name = "DURATION", default_value = "0", value_parser = parse_duration)]
+        modify_window: Duration,
+        /// Stop before writing more than SIZE bytes in total
+        #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+        max_total_bytes: Option<u64>,
+        /// Leave out directories tagged with a CACHEDIR.TAG: all of it,
+        /// all but the tag file, or just the contents
+        #[arg(long, value_enum, value_name = "MODE", num_args = 0..=1, require_equals = true, default_missing_value = "all")]
+        exclude_caches: Option<ExcludeCachesArg>,
+        /// Fail before syncing anything unless the filesystems written to have
+        /// each of these: reflink, xattr, symlink, ownership, sub-second-times,
+        /// sparse
+        #[arg(long, value_name = "CAPABILITIES")]
+        require: Option<String>,
+        /// Hold an exclusive lock on FILE while running, waiting for it if
+        /// another fman holds it
+        #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+        lock: Option<PathBuf>,
+    },
+    /// Check whether two files have the same contents; exits 1 if not
+    Compare {
+        #[arg(value_hint = ValueHint::AnyPath)]
+        a: PathBuf,
+        #[arg(value_hint = ValueHint::AnyPath)]
+        b: PathBuf,
+    },
+    /// Find files with identical contents and print them in groups
+    Dedupe {
+        #[arg(default_value = ".", value_hint = ValueHint::DirPath)]
+        root: PathBuf,
+        /// Remove all but the first file, in path order, of each group
+        #[arg(long)]
+        delete: bool,
+        /// With --delete, do not ask for confirmation
+        #[arg(short, long, requires = "delete")]
+        force: bool,
+        /// Group empty files too
+        #[arg(long)]
+        include_empty: bool,
+    },
+    /// Show disk usage of a directory's children
+    Du {
+        #[arg(default_value = ".", value_hint = ValueHint::AnyPath)]
+        path: PathBuf,
+        /// Only show the N largest (or, with --watch, fastest growing) children
+        #[arg(long, value_name = "N")]
+        top: Option<usize>,
+        /// Sample repeatedly and show growth since the previous sample
+        #[arg(long)]
+        watch: bool,
+        /// Time between samples in --watch mode
+        #[arg(long, value_name = "DURATION", default_value = "5s", value_parser = parse_duration)]
+        interval: Duration,
+        /// Re-list every directory on each sample instead of only changed ones
+        #[arg(long)]
+        no_cache: bool,
+        /// In --watch mode, print one JSON object per sample
+        #[cfg_attr(feature = "json", arg(long))]
+        #[cfg_attr(not(feature = "json"), arg(skip))]
+        json_stream: bool,
+        /// Keep each directory's file total in an extended attribute and
+        /// reuse it while the directory is unchanged
+        #[arg(long, conflicts_with = "watch")]
+        cache_xattr: bool,
+        /// Remove the attributes written by --cache-xattr, recursively
+        #[arg(long, conflicts_with_all = ["watch", "cache_xattr", "top"])]
+        clear_cache: bool,
+        /// Count nothing for directories tagged with a CACHEDIR.TAG (all), or
+        /// only their tag file (tag) or nothing but the directory (contents)
+        #[arg(long, value_enum, value_name = "MODE", num_args = 0..=1, require_equals = true, default_missing_value = "all")]
+        exclude_caches: Option<ExcludeCachesArg>,
+    },
+    /// Print changes below a directory as they happen, one
+    /// EVENT<TAB>PATH line each, or run a command after each batch of them
+    Watch {
+        #[arg(value_hint = ValueHint::AnyPath)]
+        path: PathBuf,
+        /// The command to run, split into words at whitespace (not run by a
+        /// shell); an argument {}... is replaced by the changed paths, and
+        /// {} in an argument runs the command once per path
+        #[arg(long, value_name = "COMMAND", conflicts_with = "argv", value_hint = ValueHint::CommandString)]
+        run: Option<String>,
+        /// The command as separate arguments, after --
+        #[arg(last = true, value_name = "ARGV", value_hint = ValueHint::CommandWithArguments)]
+        argv: Vec<OsString>,
+        /// Run once the tree has been quiet for DURATION after a change
+        #[arg(long, value_name = "DURATION", default_value = "500ms", value_parser = parse_duration)]
+        batch: Duration,
+        /// How often to look for changes where the platform's change
+        /// notifications are not available
+        #[arg(long, value_name = "DURATION", default_value = "250ms", value_parser = parse_duration)]
+        poll: Duration,
+        /// Ignore paths with a component matching PATTERN (repeatable)
+        #[arg(long, value_name = "PATTERN")]
+        ignore: Vec<String>,
+        /// Also run the command once at startup
+        #[arg(long)]
+        initial_run: bool,
+        /// Also watch subdirectories when printing events; a command always
+        /// watches the whole tree
+        #[arg(short, long)]
+        recursive: bool,
+        /// Only print events for file names matching PATTERN
+        #[arg(long, value_name = "PATTERN")]
+        pattern: Option<String>,
+    },
+    /// Recursively list paths below a directory
+    Find {
+        #[arg(default_value = ".", value_hint = ValueHint::DirPath)]
+        root: PathBuf,
+        /// Print paths relative to the search root
+        #[arg(long, conflicts_with = "absolute")]
+        relative: bool,
+        /// Print canonical absolute paths
+        #[arg(long)]
+        absolute: bool,
+        /// Print each entry with a template. Fields: path, relpath, name,
+        /// depth, size, mtime[:strftime], kind, mode
+        #[arg(long, value_name = "TEMPLATE")]
+        format: Option<String>,
+        /// Terminate each record with NUL instead of newline
+        #[arg(long)]
+        print0: bool,
+        /// Also report the search root itself
+        #[arg(long)]
+        include_root: bool,
+        /// Only entries whose file name matches a wildcard pattern, e.g.
+        /// '*.log'
+        #[arg(long, value_name = "PATTERN")]
+        name: Option<String>,
+        /// Only entries of this type: f (file), d (directory) or l (symlink)
+        #[arg(long = "type", value_enum, value_name = "TYPE")]
+        kind: Option<EntryKindArg>,
+        /// Descend at most N levels below the root
+        #[arg(long, value_name = "N")]
+        max_depth: Option<usize>,
+    },
+    /// Print SHA-256 digests of files
+    Hash {
+        #[arg(required_unless_present = "verify_sidecars", value_hint = ValueHint::AnyPath)]
+        paths: Vec<PathBuf>,
+        /// Succeed only if every path has the same contents; exits 1 if not
+        #[arg(long)]
+        compare: bool,
+        /// With --compare and two files, compare bytes directly instead of hashing
+        #[arg(long, requires = "compare")]
+        fast: bool,
+        /// Hash directories as a whole tree (relative paths plus file digests)
+        #[arg(short, long)]
+        recursive: bool,
+        /// Digest to compute
+        #[arg(
+            long,
+            value_enum,
+            value_name = "ALGO",
+            default_value = "sha256",
+            conflicts_with = "verify_sidecars"
+        )]
+        algo: HashAlgorithmArg,
+        /// Check every .sha256 sidecar under ROOT against the file next to
+        /// it; fails if any does not match
+        #[arg(long, value_name = "ROOT", conflicts_with_all = ["paths", "compare", "recursive"], value_hint = ValueHint::DirPath)]
+        verify_sidecars: Option<PathBuf>,
+        /// With --verify-sidecars, also report and fail on files without a
+        /// sidecar
+        #[arg(long, requires = "verify_sidecars", conflicts_with_all = ["paths", "compare", "recursive"])]
+        require_coverage: bool,
+    },
+    /// List directory entries
+    #[command(visible_alias = "list")]
+    Ls {
+        #[arg(default_value = ".", value_hint = ValueHint::AnyPath)]
+        path: PathBuf,
+        /// List subdirectories recursively
+        #[arg(short = 'R', long)]
+        recursive: bool,
+        /// Include entries whose names start with a dot
+        #[arg(short, long)]
+        all: bool,
+        /// Show each entry's size in bytes and modification time
+        #[arg(short, long, conflicts_with = "format")]
+        long: bool,
+        /// Print each entry with a template, e.g. '{size}\t{mtime:%s}\t{path}'.
+        /// Fields: path, name, size, mtime[:strftime], kind, mode, depth
+        #[arg(long, value_name = "TEMPLATE")]
+        format: Option<String>,
+        /// Print entries in directory order as they are read, holding none
+        /// of them in memory
+        #[arg(long, conflicts_with_all = ["sorted", "low_memory"])]
+        unsorted: bool,
+        /// Sort entries by name within each directory (the default)
+        #[arg(long)]
+        sorted: bool,
+        /// Sort through temporary files instead of holding whole directories
+        /// in memory
+        #[arg(long)]
+        low_memory: bool,
+        /// With --low-memory, names held in memory per directory before
+        /// spilling to disk
+        #[arg(long, value_name = "N", default_value_t = DEFAULT_MAX_ENTRIES_IN_MEMORY, requires = "low_memory")]
+        max_entries_in_memory: usize,
+    },
+    /// Manage the trash can
+    Trash {
+        #[command(subcommand)]
+        command: TrashCommand,
+    },
+    /// Instantiate a directory skeleton, substituting {{name}} placeholders
+    Template {
+        /// Template directory
+        #[arg(value_hint = ValueHint::DirPath)]
+        template: PathBuf,
+        /// Directory to create
+        #[arg(value_hint = ValueHint::DirPath)]
+        dest: PathBuf,
+        /// Variable definition, repeatable
+        #[arg(long = "var", value_name = "KEY=VALUE", value_parser = parse_var)]
+        vars: Vec<(String, String)>,
+        /// Fail on placeholders with no value instead of warning
+        #[arg(long)]
+        strict_vars: bool,
+        /// Only substitute inside files up to this many bytes
+        #[arg(long, value_name = "BYTES", default_value_t = ops::DEFAULT_MAX_SUBSTITUTE_SIZE)]
+        max_size: u64,
+        /// Overwrite existing files in the destination
+        #[arg(short, long)]
+        force: bool,
+    },
+    /// Carry out a plan of recorded operations, one JSON object per line
+    Apply {
+        /// Plan file, or - to read it from stdin
+        #[arg(value_hint = ValueHint::FilePath)]
+        plan: PathBuf,
+        /// Run independent steps at once, as many as there are CPUs unless
+        /// --jobs says otherwise; steps touching the same paths still run in
+        /// plan order
+        #[arg(long)]
+        parallel: bool,
+        /// Run up to N independent steps at once; implies --parallel
+        #[arg(short, long, value_name = "N")]
+        jobs: Option<NonZeroUsize>,
+        /// Run one step at a time in plan order (the default)
+        #[arg(long, conflicts_with_all = ["parallel", "jobs"])]
+        sequential: bool,
+        /// Stop before writing more than SIZE bytes in total
+        #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+        max_total_bytes: Option<u64>,
+        /// Hold an exclusive lock on FILE while running, waiting for it if
+        /// another fman holds it
+        #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+        lock: Option<PathBuf>,
+    },
+    /// Print a completion script for SHELL on stdout
+    Completions {
+        #[arg(value_enum)]
+        shell: ShellArg,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum TrashCommand {
+    /// Permanently remove items from the trash
+    Empty {
+        /// Only items deleted at least this long ago, e.g. 30d
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+        older_than: Option<Duration>,
+        /// Only items larger than this, e.g. 1G
+        #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+        larger_than: Option<u64>,
+        /// Do not ask for confirmation
+        #[arg(short, long)]
+        yes: bool,
+        /// Also remove items whose .trashinfo is missing or unparsable
+        #[arg(short, long)]
+        force: bool,
+    },
+}
+
+/// How a wildcard pattern's matches are vetted before acting on them.
+#[derive(Args)]
+pub struct GuardArgs {
+    /// Show how a pattern expanded before acting (default when interactive)
+    #[arg(long, overrides_with = "no_preview")]
+    preview: bool,
+    #[arg(long)]
+    no_preview: bool,
+    /// Ask before acting on more matches than this
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_MAX_MATCHES_WITHOUT_CONFIRM)]
+    max_matches_without_confirm: usize,
+    /// Act on any number of matches without asking
+    #[arg(short, long)]
+    yes: bool,
+}
+
+impl GuardArgs {
+    fn guard(&self) -> MatchGuard {
+        MatchGuard::new()
+            .preview(self.preview || (!self.no_preview && std::io::stdin().is_terminal()))
+            .max_without_confirm(self.max_matches_without_confirm)
+            .assume_yes(self.yes)
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ShellArg {
+    Bash,
+    Zsh,
+    Fish,
+    Powershell,
+}
+
+impl From<ShellArg> for clap_complete::Shell {
+    fn from(arg: ShellArg) -> Self {
+        match arg {
+            ShellArg::Bash => clap_complete::Shell::Bash,
+            ShellArg::Zsh => clap_complete::Shell::Zsh,
+            ShellArg::Fish => clap_complete::Shell::Fish,
+            ShellArg::Powershell => clap_complete::Shell::PowerShell,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ExcludeCachesArg {
+    /// Leave the directory out entirely
+    All,
+    /// Keep the directory and its CACHEDIR.TAG only
+    Tag,
+    /// Keep the directory, empty
+    Contents,
+}
+
+impl From<ExcludeCachesArg> for ExcludeCaches {
+    fn from(arg: ExcludeCachesArg) -> Self {
+        match arg {
+            ExcludeCachesArg::All => ExcludeCaches::All,
+            ExcludeCachesArg::Tag => ExcludeCaches::Tag,
+            ExcludeCachesArg::Contents => ExcludeCaches::Contents,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum EntryKindArg {
+    F,
+    D,
+    L,
+}
+
+impl From<EntryKindArg> for EntryKind {
+    fn from(arg: EntryKindArg) -> Self {
+        match arg {
+            EntryKindArg::F => EntryKind::File,
+            EntryKindArg::D => EntryKind::Dir,
+            EntryKindArg::L => EntryKind::Symlink,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum HashAlgorithmArg {
+    Md5,
+    Sha1,
+    Sha256,
+}
+
+impl From<HashAlgorithmArg> for HashAlgorithm {
+    fn from(arg: HashAlgorithmArg) -> Self {
+        match arg {
+            HashAlgorithmArg::Md5 => HashAlgorithm::Md5,
+            HashAlgorithmArg::Sha1 => HashAlgorithm::Sha1,
+            HashAlgorithmArg::Sha256 => HashAlgorithm::Sha256,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ImmutableArg {
+    /// Fail if the attribute cannot be set
+    Required,
+    /// Keep the copy without the attribute if it cannot be set
+    BestEffort,
+}
+
+impl From<ImmutableArg> for Immutability {
+    fn from(arg: ImmutableArg) -> Self {
+        match arg {
+            ImmutableArg::Required => Immutability::Required,
+            ImmutableArg::BestEffort => Immutability::BestEffort,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum OnConflictArg {
+    /// Fail
+    Error,
+    /// Replace it
+    Overwrite,
+    /// Leave it and skip the file
+    Skip,
+    /// Copy to a free name beside it, as in "file (1).txt"
+    Rename,
+}
+
+impl From<OnConflictArg> for OverwritePolicy {
+    fn from(arg: OnConflictArg) -> Self {
+        match arg {
+            OnConflictArg::Error => OverwritePolicy::Error,
+            OnConflictArg::Overwrite => OverwritePolicy::Overwrite,
+            OnConflictArg::Skip => OverwritePolicy::Skip,
+            OnConflictArg::Rename => OverwritePolicy::RenameNew,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ReflinkArg {
+    /// Share blocks where the filesystem can, copy elsewhere
+    Auto,
+    /// Share blocks or fail
+    Always,
+    /// Copy the data
+    Never,
+}
+
+impl From<ReflinkArg> for Reflink {
+    fn from(arg: ReflinkArg) -> Self {
+        match arg {
+            ReflinkArg::Auto => Reflink::Auto,
+            ReflinkArg::Always => Reflink::Always,
+            ReflinkArg::Never => Reflink::Never,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum RacingArg {
+    /// Keep the copy and warn
+    Warn,
+    /// Delete the copy and fail
+    Error,
+    /// Delete the copy and skip the file
+    Skip,
+}
+
+impl From<RacingArg> for RacingPolicy {
+    fn from(arg: RacingArg) -> Self {
+        match arg {
+            RacingArg::Warn => RacingPolicy::Warn,
+            RacingArg::Error => RacingPolicy::Error,
+            RacingArg::Skip => RacingPolicy::Skip,
+        }
+    }
+}
+
+#[cfg(feature = "tracing")]
+#[derive(Clone, Copy, ValueEnum)]
+pub enum LogLevelArg {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+#[cfg(feature = "tracing")]
+impl From<LogLevelArg> for tracing::Level {
+    fn from(arg: LogLevelArg) -> Self {
+        match arg {
+            LogLevelArg::Error => tracing::Level::ERROR,
+            LogLevelArg::Warn => tracing::Level::WARN,
+            LogLevelArg::Info => tracing::Level::INFO,
+            LogLevelArg::Debug => tracing::Level::DEBUG,
+            LogLevelArg::Trace => tracing::Level::TRACE,
+        }
+    }
+}
+
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    units::parse_duration(s).map_err(|e| e.to_string())
+}
+
+fn parse_size(s: &str) -> Result<u64, String> {
+    units::parse_size(s).map_err(|e| e.to_string())
+}
+
+fn parse_buffer_size(s: &str) -> Result<usize, String> {
+    match parse_size(s)? {
+        0 => Err("the buffer needs at least 1 byte".to_string()),
+        bytes => usize::try_from(bytes).map_err(|e| e.to_string()),
+    }
+}
+
+fn parse_rewrite(s: &str) -> Result<RewriteRule, String> {
+    RewriteRule::parse(s).map_err(|e| e.to_string())
+}
+
+fn parse_var(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("expected KEY=VALUE, got {s:?}")),
+    }
+}
+
+/// Exit status for a command line that does not parse.
+const USAGE_EXIT_CODE: i32 = 1;
+
+/// Where flag defaults come from and the exit statuses, as listed by
+/// `fman --help`.
+const AFTER_LONG_HELP: &str = "\
+Defaults:
+  Some flags take their default from the environment or from the
+  configuration file, ~/.config/fman/config.toml unless --config or
+  FMAN_CONFIG names another. A flag on the command line wins over the
+  environment, which wins over the file:
+    FMAN_FORCE     --force for copy and move
+    FMAN_BACKUP    --backup for copy
+    FMAN_PRESERVE  --preserve for copy
+    FMAN_QUIET     --quiet
+    FMAN_VERBOSE   --verbose
+  Variables take 1, true, yes or on, and 0, false, no or off.
+
+Exit status:
+  0    success
+  1    usage error; also `compare` and `hash --compare` when the files differ
+  2    a path does not exist
+  3    a path already exists
+  4    invalid input
+  5    permission denied
+  10   any other failure, or several failures of different kinds
+  75   `lock` found the lock held elsewhere
+  126  `lock` could not run the command
+  127  `lock` did not find the command
+  130  cancelled with Ctrl-C";
+
+pub fn run() {
+    // Handled before parsing, like --help, so that no subcommand is needed.
+    if std::env::args_os().skip(1).any(|arg| arg == "--help-json") {
+        print_json(&help_json::describe(Cli::command()));
+        return;
+    }
+    let cli = match Cli::try_parse() {
+        Ok(cli) => cli,
+        Err(e) => {
+            let _ = e.print();
+            // --help and --version come through here too, successfully.
+            std::process::exit(if e.use_stderr() { USAGE_EXIT_CODE } else { 0 });
+        }
+    };
+    let json = cli.json;
+    #[cfg(feature = "tracing")]
+    if let Some(level) = cli.log_level {
+        tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::from(level))
+            .with_writer(std::io::stderr)
+            .with_ansi(std::io::stderr().is_terminal())
+            .init();
+    }
+    if let Err(e) = Output::init(&cli).and_then(|()| try_run(cli)) {
+        if json {
+            let mut value = serde_json::json!({
+                "status": "error",
+                "kind": e.kind(),
+                "message": e.to_string(),
+            });
+            match &e {
+                FmanError::Multiple(list) => {
+                    value["counts"] = serde_json::json!(list.counts());
+                    value["omitted"] = serde_json::json!(list.omitted());
+                }
+                FmanError::NotFound { suggestions, .. } => {
+                    value["suggestions"] = serde_json::json!(suggestions);
+                }
+                FmanError::MissingCapabilities { missing, .. } => {
+                    value["missing"] = serde_json::json!(missing);
+                }
+                FmanError::QuotaExceeded { limit, written } => {
+                    value["limit"] = serde_json::json!(limit);
+                    value["written"] = serde_json::json!(written);
+                }
+                _ => {}
+            }
+            print_json(&value);
+        } else {
+            eprintln!("error: {e}");
+        }
+        std::process::exit(e.exit_code());
+    }
+}
+
+/// The code to exit with to pass on a child's `status`: its own exit code,
+/// or 128 plus the signal that ended it, as shells report it.
+fn exit_status_code(status: std::process::ExitStatus) -> i32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+    status.code().unwrap_or(1)
+}
+
+/// Take the exclusive `--lock` on `path`, if one was given, for as long as
+/// the returned guard lives.
+fn hold_lock(path: Option<&Path>) -> FmanResult<Option<FileLock>> {
+    path.map(|path| FileLock::acquire(path, LockMode::Exclusive, None))
+        .transpose()
+}
+
+/// Read `--config FILE`, or the default configuration file if there is
+/// one, warning about keys that are not settings.
+fn load_config(path: Option<&Path>) -> FmanResult<Config> {
+    let config = match path {
+        Some(path) => Config::load(path)?,
+        None => Config::load_default()?,
+    };
+    for key in &config.unknown_keys {
+        warn(format_args!("unknown configuration key {key:?}; ignored"));
+    }
+    Ok(config)
+}
+
+/// A boolean setting: `on` or `off`, whichever flag was given; else the
+/// environment variable `var`; else the `configured` value; else off.
+fn setting(on: bool, off: bool, var: &str, configured: Option<bool>) -> FmanResult<bool> {
+    if on || off {
+        return Ok(on);
+    }
+    Ok(env_flag(var)?.or(configured).unwrap_or(false))
+}
+
+/// The boolean environment variable `var`, if set: 1, true, yes or on, or
+/// 0, false, no or off, in any case. Empty counts as off.
+fn env_flag(var: &str) -> FmanResult<Option<bool>> {
+    let Some(value) = std::env::var_os(var) else {
+        return Ok(None);
+    };
+    let value = value.to_string_lossy();
+    match value.trim().to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Ok(Some(true)),
+        "0" | "false" | "no" | "off" | "" => Ok(Some(false)),
+        _ => Err(FmanError::InvalidInput(format!(
+            "{var}={value:?} is not a boolean; use 1, true, yes or on, or 0, false, no or off"
+        ))),
+    }
+}
+
+fn naming_context(seed: Option<u64>) -> NamingContext {
+    seed.map_or_else(NamingContext::new, NamingContext::seeded)
+}
+
+/// Which informational messages are printed. Everything a command says
+/// besides its result and errors goes through [`say`], [`note`], [`warn`]
+/// and [`progress`], so `--verbose` and `--quiet` apply to all of them.
+#[derive(Clone, Copy, Default)]
+struct Output {
+    verbose: bool,
+    quiet: bool,
+}
+
+static OUTPUT: OnceLock<Output> = OnceLock::new();
+
+impl Output {
+    fn init(cli: &Cli) -> FmanResult<()> {
+        // Either flag turns the other's variable off. Clap cannot see
+        // both flags when they are given on different levels (`fman -q
+        // copy -v`), so quiet wins there as it does between the variables.
+        let quiet = setting(cli.quiet, cli.verbose, "FMAN_QUIET", None)?;
+        let verbose = setting(cli.verbose, quiet, "FMAN_VERBOSE", None)?;
+        // Nothing is done in a dry run, and JSON has the paths already.
+        let _ = OUTPUT.set(Output {
+            verbose: verbose && !quiet && !cli.json && !cli.dry_run,
+            quiet,
+        });
+        Ok(())
+    }
+
+    fn get() -> Output {
+        OUTPUT.get().copied().unwrap_or_default()
+    }
+}
+
+/// With --verbose, print a line about something done on stdout.
+fn say(message: impl std::fmt::Display) {
+    if Output::get().verbose {
+        println!("{message}");
+    }
+}
+
+/// Print a notice or summary on stderr, unless --quiet.
+fn note(message: impl std::fmt::Display) {
+    if !Output::get().quiet {
+        eprintln!("{message}");
+    }
+}
+
+/// Print a warning on stderr, unless --quiet.
+fn warn(message: impl std::fmt::Display) {
+    if !Output::get().quiet {
+        eprintln!("warning: {message}");
+    }
+}
+
+/// Overwrite the current stderr line with a progress count, or clear it
+/// with `None`; only when stderr is a terminal and not --quiet.
+fn progress(message: Option<&str>) {
+    if Output::get().quiet || !std::io::stderr().is_terminal() {
+        return;
+    }
+    match message {
+        Some(message) => eprint!("\r{message}"),
+        None => eprint!("\r\x1b[K"),
+    }
+}
+
+/// Asks on the terminal: previews go to stderr, answers come from stdin.
+struct TerminalPrompter;
+
+impl Prompter for TerminalPrompter {
+    fn is_interactive(&self) -> bool {
+        std::io::stdin().is_terminal()
+    }
+
+    fn show(&mut self, line: &str) {
+        eprintln!("{line}");
+    }
+
+    fn confirm(&mut self, question: &str) -> bool {
+        confirm(question)
+    }
+}
+
+/// The paths `arg` stands for: itself, or if it names nothing and is a
+/// wildcard pattern, its matches once `guard` passes them. `None` if the
+/// user declined.
+fn expand_guarded(
+    arg: &Path,
+    action: &str,
+    guard: &MatchGuard,
+) -> FmanResult<Option<Vec<PathBuf>>> {
+    let is_pattern = arg.to_str().is_some_and(glob::is_pattern);
+    if !is_pattern || fs::symlink_metadata(arg).is_ok() {
+        return Ok(Some(vec![arg.to_path_buf()]));
+    }
+    let matches = glob::expand(arg)?;
+    match guard.check(action, arg, &matches, &mut TerminalPrompter) {
+        Ok(()) => Ok(Some(matches)),
+        Err(FmanError::Cancelled) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Copy every match of the wildcard `pattern` into the existing directory
+/// `dst`, directories too if `recursive`, carrying on past failures. Up
+/// to `jobs` matches are copied at once.
+fn copy_matches(
+    pattern: &str,
+    dst: &str,
+    recursive: bool,
+    options: &CopyOptions,
+    json: bool,
+    max_errors: usize,
+    jobs: usize,
+) -> FmanResult<()> {
+    let sources = glob::expand(Path::new(pattern)).map_err(|e| match e {
+        e if e.is_not_found() => {
+            FmanError::InvalidInput(format!("no files match pattern {pattern}"))
+        }
+        e => e,
+    })?;
+    if !Path::new(dst).is_dir() {
+        return Err(FmanError::InvalidInput(format!(
+            "destination {dst} is not an existing directory; copying the matches of {pattern} \
+             needs one"
+        )));
+    }
+    let mut copies = Vec::new();
+    let mut failures = ErrorList::new(max_errors);
+    for (source, result) in sources
+        .iter()
+        .zip(copy_concurrently(&sources, dst, recursive, options, jobs)?)
+    {
+        match result {
+            Ok(report) => copies.push(report),
+            Err(e) => failures.push(source, e),
+        }
+    }
+    failures.into_result(())?;
+    if json {
+        print_json(&serde_json::json!({
+            "operation": "copy",
+            "pattern": pattern,
+            "copies": copies,
+        }));
+    }
+    Ok(())
+}
+
+/// Copy each of `sources` into the existing directory `dst`, in order,
+/// stopping at the first failure; what was copied before it stays. With
+/// more than one job they are copied concurrently instead, and every
+/// failure is reported.
+fn copy_sources(
+    sources: &[String],
+    dst: &str,
+    recursive: bool,
+    options: &CopyOptions,
+    json: bool,
+    max_errors: usize,
+    jobs: usize,
+) -> FmanResult<()> {
+    if !Path::new(dst).is_dir() {
+        return Err(FmanError::InvalidInput(format!(
+            "destination {dst} is not an existing directory; copying {} sources needs one",
+            sources.len()
+        )));
+    }
+    let mut copies = Vec::new();
+    if jobs > 1 {
+        let sources: Vec<PathBuf> = sources.iter().map(PathBuf::from).collect();
+        let mut failures = ErrorList::new(max_errors);
+        for (source, result) in sources
+            .iter()
+            .zip(copy_concurrently(&sources, dst, recursive, options, jobs)?)
+        {
+            match result {
+                Ok(report) => copies.push(report),
+                Err(e) => failures.push(source, e),
+            }
+        }
+        failures.into_result(())?;
+    } else {
+        for source in sources {
+            match copy_into(Path::new(source), dst, recursive, options) {
+                Ok(report) => copies.push(report),
+                Err(e) => {
+                    warn(format_args!(
+                        "stopped at {source}; {} of {} sources copied",
+                        copies.len(),
+                        sources.len()
+                    ));
+                    return Err(e);
+                }
+            }
+        }
+    }
+    if json {
+        print_json(&serde_json::json!({
+            "operation": "copy",
+            "copies": copies,
+        }));
+    }
+    Ok(())
+}
+
+/// [`copy_into`] for each of `sources` on up to `jobs` threads, with the
+/// results in source order. Two sources that would land on the same path
+/// are rejected before anything is copied rather than left to race.
+fn copy_concurrently(
+    sources: &[PathBuf],
+    dst: &str,
+    recursive: bool,
+    options: &CopyOptions,
+    jobs: usize,
+) -> FmanResult<Vec<FmanResult<serde_json::Value>>> {
+    let mut targets = HashMap::new();
+    for source in sources {
+        let target = ops::resolve_destination_path(source, Path::new(dst))?;
+        if let Some(other) = targets.insert(target, source) {
+            return Err(FmanError::InvalidInput(format!(
+                "{} and {} would both be copied to the same path in {dst}",
+                other.display(),
+                source.display()
+            )));
+        }
+    }
+    let next = AtomicUsize::new(0);
+    let results: Vec<Mutex<Option<FmanResult<serde_json::Value>>>> =
+        sources.iter().map(|_| Mutex::new(None)).collect();
+    thread::scope(|scope| {
+        for _ in 0..jobs.min(sources.len()) {
+            scope.spawn(|| {
+                loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(source) = sources.get(i) else { break };
+                    let result = copy_into(source, dst, recursive, options);
+                    *results[i].lock().unwrap_or_else(|e| e.into_inner()) = Some(result);
+                }
+            });
+        }
+    });
+    Ok(results
+        .into_iter()
+        .map(|slot| {
+            slot.into_inner()
+                .unwrap_or_else(|e| e.into_inner())
+                .expect("every source is copied by some worker")
+        })
+        .collect())
+}
+
+/// Copy `source` into the directory `dst`, a whole tree if `recursive`,
+/// warning as a single copy does. Returns the report as JSON.
+fn copy_into(
+    source: &Path,
+    dst: &str,
+    recursive: bool,
+    options: &CopyOptions,
+) -> FmanResult<serde_json::Value> {
+    let request = CopyRequest::new(source, dst).options(options.clone());
+    if recursive && source.is_dir() {
+        let report = ops::copy_dir(&request)?;
+        say_copied(&report.source, &report.destination);
+        warn_vanished(report.vanished);
+        warn_metadata(report.metadata.as_ref());
+        return Ok(to_json(&report));
+    }
+    let report = ops::copy(&request)?;
+    if report.status != CopyStatus::Skipped {
+        say_copied(&report.source, &report.destination);
+    }
+    warn_metadata(report.metadata.as_ref());
+    Ok(to_json(&report))
+}
+
+/// With --verbose, print a completed copy or move as `SOURCE -> DESTINATION`.
+fn say_copied(source: &Path, destination: &Path) {
+    say(format_args!(
+        "{} -> {}",
+        source.display(),
+        destination.display()
+    ));
+}
+
+/// With --verbose, print a completed delete; nothing when `force` found
+/// nothing to remove.
+fn say_deleted(target: &Path, report: &DeleteReport) {
+    if report.files + report.directories > 0 {
+        say(format_args!("removed {}", target.display()));
+    }
+}
+
+/// Move `path` into the trash for `delete --trash`, under the rules of a
+/// plain delete: a directory needs `recursive`, and with `force` a missing
+/// path is no error. Returns where it went, if anywhere.
+fn trash_target(
+    path: &Path,
+    recursive: bool,
+    force: bool,
+    filesystem: &SharedFs,
+    naming: &NamingContext,
+) -> FmanResult<Option<PathBuf>> {
+    match fs::symlink_metadata(path) {
+        Err(_) if force => return Ok(None),
+        Ok(meta) if meta.is_dir() && !recursive => {
+            return Err(FmanError::InvalidInput(format!(
+                "{} is a directory (use --recursive)",
+                path.display()
+            )));
+        }
+        _ => {}
+    }
+    ops::trash(path, filesystem, naming).map(Some)
+}
+
+/// With --verbose, print a file removed outside of a delete.
+fn say_deleted_path(path: &Path) {
+    say(format_args!("removed {}", path.display()));
+}
+
+/// Ask a yes/no question on stderr; anything but `y`/`yes` is a no.
+fn confirm(prompt: &str) -> bool {
+    eprint!("{prompt} [y/N] ");
+    let _ = std::io::stderr().flush();
+    let mut answer = String::new();
+    if std::io::stdin().lock().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes")
+}
+
+/// Cancel `token` on the first Ctrl-C so the operation can stop cleanly
+/// between files; a second Ctrl-C interrupts as usual.
+#[cfg(unix)]
+fn cancel_on_interrupt(token: &CancelToken) {
+    static TOKEN: OnceLock<CancelToken> = OnceLock::new();
+
+    extern "C" fn on_interrupt(_signal: libc::c_int) {
+        if let Some(token) = TOKEN.get() {
+            token.cancel();
+        }
+    }
+
+    if TOKEN.set(token.clone()).is_err() {
+        return;
+    }
+    // SAFETY: the handler only performs atomic loads and a store, and the
+    // action struct is fully initialized before the call.
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESETHAND;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(libc::SIGINT, &action, std::ptr::null_mut());
+    }
+}
+
+#[cfg(not(unix))]
+fn cancel_on_interrupt(_token: &CancelToken) {}
+
+fn note_slow(slow: &SlowFile) {
+    let rate = slow.bytes_per_second.map_or_else(String::new, |rate| {
+        format!(", {}/s", format_size(rate as u64))
+    });
+    note(format_args!(
+        "slow: {} took {:.2}s ({}{rate}, {})",
+        slow.path.display(),
+        slow.seconds,
+        format_size(slow.bytes),
+        slow.strategy.as_str()
+    ));
+}
+
+/// How much of a `--max-total-bytes` limit was used.
+fn note_budget(usage: Option<BudgetUsage>) {
+    if let Some(usage) = usage {
+        note(format_args!(
+            "wrote {} of the {} limit",
+            format_size(usage.written),
+            format_size(usage.limit)
+        ));
+    }
+}
+
+/// Attribute failures a copy let pass.
+fn warn_metadata(summary: Option<&MetadataSummary>) {
+    let Some(summary) = summary else {
+        return;
+    };
+    for warning in &summary.warnings {
+        warn(format_args!(
+            "{}: cannot set {}: {}",
+            warning.path.display(),
+            warning.attribute.as_str(),
+            warning.message
+        ));
+    }
+    if summary.omitted > 0 {
+        warn(format_args!(
+            "{} more attribute failures not shown",
+            summary.omitted
+        ));
+    }
+    for disabled in &summary.disabled {
+        warn(format_args!(
+            "stopped setting {} on the filesystem of {}: every attempt failed ({})",
+            disabled.attribute.as_str(),
+            disabled.path.display(),
+            disabled.message
+        ));
+    }
+}
+
+fn warn_delete(report: &DeleteReport) {
+    warn_vanished(report.vanished);
+    #[cfg(target_os = "linux")]
+    if !report.still_open.is_empty() {
+        for holder in &report.still_open {
+            warn(format_args!(
+                "{} is still open in {} (pid {})",
+                holder.path.display(),
+                holder.name,
+                holder.pid
+            ));
+        }
+        warn(format_args!(
+            "{} stays in use until those processes close the files",
+            format_size(report.pinned_bytes)
+        ));
+    }
+}
+
+fn warn_vanished(count: u64) {
+    if count > 0 {
+        warn(format_args!(
+            "{count} entries vanished during the operation"
+        ));
+    }
+}
+
+/// How often `ls` reports how many entries it has listed, when its output
+/// goes to a file or pipe but stderr is a terminal.
+const LS_PROGRESS_EVERY: usize = 10_000;
+
+fn print_file_info(info: &FileInfo) {
+    let yes_no = |yes: bool| if yes { "yes" } else { "no" };
+    println!("path:        {}", info.path.display());
+    match &info.link_target {
+        Some(target) => println!("type:        symlink -> {}", target.display()),
+        None => println!("type:        {}", info.kind.as_str()),
+    }
+    println!("size:        {} ({})", info.size, format_size(info.size));
+    #[cfg(unix)]
+    println!("permissions: {:04o}", info.mode);
+    if let Some(modified) = info.modified {
+        println!(
+            "modified:    {}",
+            fmt::format_time(modified, fmt::DEFAULT_TIME_FORMAT)
+        );
+    }
+    println!("read-only:   {}", yes_no(info.read_only));
+}
+
+fn file_info_json(info: &FileInfo) -> serde_json::Value {
+    serde_json::json!({
+        "path": info.path,
+        "kind": info.kind.as_str(),
+        "size": info.size,
+        "mode": format!("{:04o}", info.mode),
+        "modified": info.modified.map(|time| fmt::format_time(time, fmt::DEFAULT_TIME_FORMAT)),
+        "read_only": info.read_only,
+        "link_target": info.link_target,
+    })
+}
+
+fn list_entry_json(entry: &ListEntry) -> serde_json::Value {
+    serde_json::json!({
+        "path": entry.path,
+        "depth": entry.depth,
+        "kind": entry.kind.as_str(),
+        "size": entry.size,
+        "mode": format!("{:04o}", entry.mode),
+        "modified": entry.modified.map(|time| fmt::format_time(time, fmt::DEFAULT_TIME_FORMAT)),
+    })
+}
+
+fn print_fs_info(info: &FsInfo) {
+    fn known<T>(value: Option<T>, show: impl Fn(T) -> String) -> String {
+        value.map_or_else(|| "unknown".to_string(), show)
+    }
+    let yes_no = |yes: bool| if yes { "yes" } else { "no" }.to_string();
+    let size = |bytes| format_size(bytes);
+    println!("path:             {}", info.path.display());
+    println!(
+        "mount point:      {}",
+        known(info.mount_point.as_ref(), |p| p.display().to_string())
+    );
+    println!(
+        "filesystem:       {}",
+        known(info.fs_type.as_ref(), |t| match &info.source {
+            Some(source) => format!("{t} ({source})"),
+            None => t.clone(),
+        })
+    );
+    println!("total:            {}", known(info.total_bytes, size));
+    println!("used:             {}", known(info.used_bytes, size));
+    println!("available:        {}", known(info.available_bytes, size));
+    println!("read-only:        {}", known(info.read_only, yes_no));
+    println!("case-sensitive:   {}", known(info.case_sensitive, yes_no));
+    println!(
+        "mtime resolution: {}",
+        known(info.timestamp_granularity_ns, ops::format_granularity)
+    );
+    println!("reflinks:         {}", known(info.reflink, yes_no));
+    println!("sparse files:     {}", known(info.sparse_files, yes_no));
+    println!("xattrs:           {}", known(info.xattrs, yes_no));
+    println!("symlinks:         {}", known(info.symlinks, yes_no));
+    println!("ownership:        {}", known(info.ownership, yes_no));
+}
+
+/// Print `report` as JSON with an `operation` field naming what was done.
+fn print_result(operation: &str, report: &impl JsonReport) {
+    let mut value = to_json(report);
+    if let Some(fields) = value.as_object_mut() {
+        fields.insert("operation".to_string(), operation.into());
+    }
+    print_json(&value);
+}
+
+/// What `--json` can print: anything serializable. Without the `json`
+/// feature the reports are not, and there is no `--json` to print them.
+#[cfg(feature = "json")]
+trait JsonReport: serde::Serialize {}
+#[cfg(feature = "json")]
+impl<T: serde::Serialize + ?Sized> JsonReport for T {}
+#[cfg(not(feature = "json"))]
+trait JsonReport {}
+#[cfg(not(feature = "json"))]
+impl<T: ?Sized> JsonReport for T {}
+
+/// `report` as a JSON value; `null` without the `json` feature.
+fn to_json(report: &impl JsonReport) -> serde_json::Value {
+    #[cfg(feature = "json")]
+    return serde_json::to_value(report).unwrap_or_else(|e| {
+        eprintln!("error: failed to encode JSON: {e}");
+        serde_json::Value::Null
+    });
+    #[cfg(not(feature = "json"))]
+    {
+        let _ = report;
+        serde_json::Value::Null
+    }
+}
+
+fn print_json(value: &impl serde::Serialize) {
+    match serde_json::to_string(value) {
+        Ok(line) => println!("{line}"),
+        Err(e) => eprintln!("error: failed to encode JSON: {e}"),
+    }
+}
+
+pub fn try_run(cli: Cli) -> FmanResult<()> {
+    if !cli.dry_run {
+        return run_command(cli, Arc::new(RealFs));
+    }
+    if let Commands::Lock { .. } | Commands::Watch { .. } = cli.command {
+        return Err(FmanError::InvalidInput(
+            "--dry-run cannot be used with commands that run other programs".to_string(),
+        ));
+    }
+    let own_preview = matches!(
+        cli.command,
+        Commands::Ln { .. } | Commands::MirrorPermissions { .. } | Commands::Trash { .. }
+    );
+    let list_plan = !cli.json && !own_preview;
+    let plan = Arc::new(DryRunFs::new());
+    let result = run_command(cli, plan.clone());
+    if list_plan {
+        for op in plan.ops() {
+            println!("{op}");
+        }
+    }
+    result
+}
+
+/// Run `cli`'s command, making every change through `filesystem`.
+fn run_command(cli: Cli, filesystem: SharedFs) -> FmanResult<()> {
+    let config_path = cli.config.clone().or_else(|| {
+        std::env::var_os("FMAN_CONFIG")
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from)
+    });
+    let config = load_config(config_path.as_deref())?;
+    match cli.command {
+        Commands::Copy {
+            srcs,
+            dst,
+            mut force,
+            no_force,
+            on_conflict,
+            no_clobber,
+            mut interactive,
+            backup,
+            no_backup,
+            detect_racing_writes,
+            racing,
+            small_file_threshold,
+            huge_file_threshold,
+            buffer_size,
+            sparse,
+            reflink,
+            read_only,
+            preserve,
+            no_preserve,
+            parents,
+            verify,
+            atomic,
+            immutable,
+            no_dereference,
+            dereference_command_line: _,
+            dereference,
+            recursive,
+            ignore_vanished,
+            no_ignore_vanished: _,
+            mut update,
+            modify_window,
+            sanitize_windows_names,
+            lowercase_names,
+            checkpoint,
+            keep_checkpoint,
+            hardlink,
+            symlink,
+            relative,
+            min_duration_report,
+            skip_active,
+            retry_active_at_end,
+            max_total_bytes,
+            retries,
+            retry_delay,
+            attributes_only,
+            ignore_missing,
+            stream,
+            ownership_map,
+            ownership_map_file,
+            require_mapped,
+            strict_metadata,
+            exclude_caches,
+            require,
+            jobs,
+        } => {
+            let symlinks = if no_dereference {
+                SymlinkPolicy::Never
+            } else if dereference {
+                SymlinkPolicy::Always
+            } else {
+                SymlinkPolicy::CommandLine
+            };
+            let mut on_conflict = on_conflict.map(OverwritePolicy::from);
+            if no_clobber {
+                on_conflict = Some(OverwritePolicy::Skip);
+            }
+            if !(force || no_force || interactive || update || on_conflict.is_some()) {
+                match env_flag("FMAN_FORCE")?.map_or(config.overwrite, |force| {
+                    Some(if force {
+                        Overwrite::Overwrite
+                    } else {
+                        Overwrite::Error
+                    })
+                }) {
+                    Some(Overwrite::Error) => {}
+                    Some(Overwrite::Overwrite) => force = true,
+                    Some(Overwrite::Update) => update = true,
+                    Some(Overwrite::Ask) => interactive = true,
+                    Some(Overwrite::Skip) => on_conflict = Some(OverwritePolicy::Skip),
+                    Some(Overwrite::Rename) => on_conflict = Some(OverwritePolicy::RenameNew),
+                    None => force = config.force.unwrap_or(false),
+                }
+            }
+            let mut options = CopyOptions::new()
+                .naming(Arc::new(naming_context(cli.seed)))
+                .force(force)
+                .backup(setting(backup, no_backup, "FMAN_BACKUP", config.backup)?)
+                .symlinks(symlinks)
+                .small_file_threshold(small_file_threshold)
+                .huge_file_threshold(huge_file_threshold)
+                .sparse(sparse)
+                .reflink(reflink.into())
+                .read_only(read_only)
+                .preserve(setting(
+                    preserve,
+                    no_preserve,
+                    "FMAN_PRESERVE",
+                    config.preserve,
+                )?)
+                .parents(parents)
+                .verify(verify)
+                .atomic(atomic)
+                .ignore_vanished(ignore_vanished)
+                .link_mode(if hardlink {
+                    LinkMode::Hardlink
+                } else if symlink {
+                    LinkMode::Symlink { relative }
+                } else {
+                    LinkMode::Copy
+                })
+                .update(update)
+                .modify_window(modify_window)
+                .retries(retries)
+                .retry_delay(retry_delay)
+                .stream(stream)
+                .max_errors(cli.max_errors)
+                .fs(filesystem.clone());
+            if let Some(path) = &cli.error_log {
+                options = options.error_log(path);
+            }
+            if let Some(policy) = on_conflict {
+                options = options.on_conflict(policy);
+            }
+            if let Some(bytes) = buffer_size {
+                options = options.buffer_size(bytes);
+            }
+            if let Some(suffix) = &config.backup_suffix {
+                options = options.backup_suffix(suffix);
+            }
+            if let Some(mode) = immutable {
+                options = options.immutable(mode.into());
+            }
+            if let Some(threshold) = min_duration_report {
+                options = options.min_duration_report(threshold);
+            }
+            if let Some(window) = skip_active {
+                options = options
+                    .skip_active(window)
+                    .retry_active_at_end(retry_active_at_end);
+            }
+            if detect_racing_writes || racing.is_some() {
+                options = options.detect_racing_writes(racing.unwrap_or(RacingArg::Warn).into());
+            }
+            if let Some(list) = &attributes_only {
+                options = options
+                    .attributes_only(&Attribute::parse_list(list)?)
+                    .ignore_missing(ignore_missing);
+            }
+            let budget = max_total_bytes.map(ByteBudget::new);
+            if let Some(budget) = &budget {
+                options = options.budget(budget.clone());
+            }
+            if strict_metadata {
+                options = options.metadata_policy(MetadataPolicy::strict());
+            }
+            if let Some(mode) = exclude_caches {
+                options = options.exclude_caches(mode.into());
+            }
+            if interactive {
+                options = options.confirm_overwrite(|dst| {
+                    let replace = confirm(&format!("overwrite {}?", dst.display()));
+                    if !replace {
+                        note(format_args!("skipped {}", dst.display()));
+                    }
+                    replace
+                });
+            }
+            if let Some(list) = &require {
+                options = options.require(&Capability::parse_list(list)?);
+            }
+            if !ownership_map.is_empty() || ownership_map_file.is_some() {
+                let mut owners = OwnershipMap::new().require_mapped(require_mapped);
+                if let Some(path) = &ownership_map_file {
+                    owners = owners.from_file(path)?;
+                }
+                for spec in &ownership_map {
+                    owners = owners.parse(spec)?;
+                }
+                options = options.ownership_map(owners);
+            }
+            match (sanitize_windows_names, lowercase_names) {
+                (true, true) => {
+                    options = options.path_transform(|path| {
+                        ops::lowercase_names(&ops::sanitize_windows_names(path)?)
+                    });
+                }
+                (true, false) => options = options.path_transform(ops::sanitize_windows_names),
+                (false, true) => options = options.path_transform(ops::lowercase_names),
+                (false, false) => {}
+            }
+            let src = match <[String; 1]>::try_from(srcs) {
+                Ok([src]) => src,
+                Err(srcs) => {
+                    if checkpoint.is_some() {
+                        return Err(FmanError::InvalidInput(
+                            "--checkpoint needs a single source".to_string(),
+                        ));
+                    }
+                    if parents {
+                        filesystem.create_dir_all(Path::new(&dst))?;
+                    }
+                    copy_sources(
+                        &srcs,
+                        &dst,
+                        recursive,
+                        &options,
+                        cli.json,
+                        cli.max_errors,
+                        jobs.get(),
+                    )?;
+                    note_budget(budget.as_ref().map(ByteBudget::usage));
+                    return Ok(());
+                }
+            };
+            // A name that merely looks like a pattern is copied as it is.
+            if glob::is_pattern(&src) && fs::symlink_metadata(&src).is_err() {
+                if checkpoint.is_some() {
+                    return Err(FmanError::InvalidInput(
+                        "--checkpoint needs a single source, not a pattern".to_string(),
+                    ));
+                }
+                if parents {
+                    filesystem.create_dir_all(Path::new(&dst))?;
+                }
+                copy_matches(
+                    &src,
+                    &dst,
+                    recursive,
+                    &options,
+                    cli.json,
+                    cli.max_errors,
+                    jobs.get(),
+                )?;
+                note_budget(budget.as_ref().map(ByteBudget::usage));
+                return Ok(());
+            }
+            if recursive && fs::metadata(&src).is_ok_and(|m| m.is_dir()) {
+                let token = CancelToken::new();
+                cancel_on_interrupt(&token);
+                options = options.cancel(token).keep_checkpoint(keep_checkpoint);
+                if let Some(path) = checkpoint {
+                    options = options.checkpoint(path);
+                }
+                let report = ops::copy_dir(&CopyRequest::new(&src, &dst).options(options))?;
+                if cli.json {
+                    print_result("copy", &report);
+                } else {
+                    say_copied(&report.source, &report.destination);
+                    for slow in &report.slow_files {
+                        note_slow(slow);
+                    }
+                    if report.skipped_active > 0 {
+                        warn(format_args!(
+                            "skipped {} files still being written (skipped-active)",
+                            report.skipped_active
+                        ));
+                    }
+                    if ignore_missing && report.skipped > 0 {
+                        warn(format_args!(
+                            "skipped {} sources missing from the destination",
+                            report.skipped
+                        ));
+                    }
+                    warn_vanished(report.vanished);
+                    warn_metadata(report.metadata.as_ref());
+                    note_budget(report.budget);
+                }
+                return Ok(());
+            }
+            let report = ops::copy(&CopyRequest::new(&src, &dst).options(options))?;
+            if cli.json {
+                print_result("copy", &report);
+                return Ok(());
+            }
+            if report.status != CopyStatus::Skipped {
+                say_copied(&report.source, &report.destination);
+            }
+            warn_metadata(report.metadata.as_ref());
+            note_budget(budget.as_ref().map(ByteBudget::usage));
+            if min_duration_report.is_some_and(|threshold| report.timing.wall >= threshold) {
+                note_slow(&SlowFile {
+                    path: report.source.clone(),
+                    bytes: report.bytes,
+                    seconds: report.timing.wall.as_secs_f64(),
+                    bytes_per_second: report.bytes_per_second,
+                    strategy: report.strategy,
+                });
+            }
+            if report.changed_during_copy {
+                let action = match report.status {
+                    CopyStatus::Copied | CopyStatus::Linked | CopyStatus::Attributes => "kept",
+                    CopyStatus::Skipped => "skipped",
+                };
+                warn(format_args!(
+                    "{} changed-during-copy ({action})",
+                    report.source.display()
+                ));
+            }
+        }
+        Commands::Move {
+            src,
+            dst,
+            force,
+            no_force,
+            copy_verify,
+            no_copy_verify,
+            guard,
+        } => {
+            let copy_verify =
+                copy_verify || (!no_copy_verify && config.copy_verify.unwrap_or(false));
+            let mut options = MoveOptions::new()
+                .force(setting(force, no_force, "FMAN_FORCE", config.force)?)
+                .copy_verify(copy_verify)
+                .max_errors(cli.max_errors);
+            if let Some(path) = &cli.error_log {
+                options = options.error_log(path);
+            }
+            let Some(sources) = expand_guarded(Path::new(&src), "move", &guard.guard())? else {
+                note("aborted");
+                return Ok(());
+            };
+            if sources.len() > 1 && !Path::new(&dst).is_dir() {
+                return Err(FmanError::NotADirectory(format!(
+                    "{dst} (moving {} matches needs a directory to move them into)",
+                    sources.len()
+                )));
+            }
+            let mut moves = Vec::new();
+            let mut failures = ErrorList::new(cli.max_errors);
+            for source in &sources {
+                let request = MoveRequest::new(source, &dst)
+                    .options(options.clone())
+                    .fs(filesystem.clone());
+                match ops::move_path(&request) {
+                    Ok(moved) => {
+                        say_copied(&moved.source, &moved.destination);
+                        moves.push(serde_json::json!({
+                            "operation": "move",
+                            "source": moved.source,
+                            "destination": moved.destination,
+                            "status": "moved",
+                        }));
+                    }
+                    Err(e) => failures.push(source, e),
+                }
+            }
+            failures.into_result(())?;
+            if cli.json {
+                match <[_; 1]>::try_from(moves) {
+                    Ok([single]) => print_json(&single),
+                    Err(moves) => print_json(&serde_json::json!({
+                        "operation": "move",
+                        "pattern": src,
+                        "moves": moves,
+                    })),
+                }
+            }
+        }
+        Commands::Link {
+            src,
+            dst,
+            symbolic,
+            relative,
+            force,
+        } => {
+            let kind = if symbolic {
+                LinkKind::Symbolic { relative }
+            } else {
+                LinkKind::Hard
+            };
+            let request = LinkRequest::new(&src, &dst)
+                .kind(kind)
+                .force(force)
+                .fs(filesystem.clone());
+            let report = ops::link(&request)?;
+            if cli.json {
+                print_result("link", &report);
+            } else {
+                say_copied(&report.source, &report.destination);
+            }
+        }
+        Commands::Rename {
+            path,
+            new_name,
+            force,
+        } => {
+            let request = RenameRequest::new(&path, new_name)
+                .force(force)
+                .fs(filesystem.clone());
+            let report = ops::rename(&request)?;
+            if cli.json {
+                print_json(&serde_json::json!({
+                    "operation": "rename",
+                    "source": report.source,
+                    "destination": report.destination,
+                }));
+            } else {
+                say_copied(&report.source, &report.destination);
+            }
+        }
+        Commands::Delete {
+            target,
+            recursive,
+            force,
+            no_ignore_vanished,
+            ignore_vanished: _,
+            trash,
+            #[cfg(target_os = "linux")]
+            report_open,
+            guard,
+        } => {
+            let Some(targets) = expand_guarded(&target, "delete", &guard.guard())? else {
+                note("aborted");
+                return Ok(());
+            };
+            if trash {
+                let naming = naming_context(cli.seed);
+                let mut trashed = Vec::new();
+                let mut failures = ErrorList::new(cli.max_errors);
+                for path in &targets {
+                    match trash_target(path, recursive, force, &filesystem, &naming) {
+                        Ok(Some(location)) => {
+                            say_copied(path, &location);
+                            trashed.push(serde_json::json!({
+                                "source": path,
+                                "destination": location,
+                            }));
+                        }
+                        Ok(None) => {}
+                        Err(e) if targets.len() == 1 => return Err(e),
+                        Err(e) => failures.push(path, e),
+                    }
+                }
+                failures.into_result(())?;
+                if cli.json {
+                    print_json(&serde_json::json!({
+                        "operation": "trash",
+                        "trashed": trashed,
+                    }));
+                }
+                return Ok(());
+            }
+            let mut options = DeleteOptions::new()
+                .recursive(recursive)
+                .force(force)
+                .ignore_vanished(!no_ignore_vanished)
+                .max_errors(cli.max_errors)
+                .fs(filesystem.clone());
+            if let Some(path) = &cli.error_log {
+                options = options.error_log(path);
+            }
+            #[cfg(target_os = "linux")]
+            {
+                options = options.report_open(report_open);
+            }
+            if let [target] = targets.as_slice() {
+                let report = ops::delete(&DeleteRequest::new(target).options(options))?;
+                if cli.json {
+                    print_result("delete", &report);
+                } else {
+                    say_deleted(target, &report);
+                    warn_delete(&report);
+                }
+                return Ok(());
+            }
+            let mut reports = Vec::new();
+            let mut failures = ErrorList::new(cli.max_errors);
+            for path in &targets {
+                match ops::delete(&DeleteRequest::new(path).options(options.clone())) {
+                    Ok(report) => {
+                        say_deleted(path, &report);
+                        reports.push(report);
+                    }
+                    Err(e) => failures.push(path, e),
+                }
+            }
+            if !cli.json {
+                reports.iter().for_each(warn_delete);
+            }
+            failures.into_result(())?;
+            if cli.json {
+                print_json(&serde_json::json!({
+                    "operation": "delete",
+                    "pattern": target,
+                    "reports": to_json(&reports),
+                }));
+            }
+        }
+        Commands::Lock {
+            lockfile,
+            command,
+            timeout,
+            nonblocking,
+            shared,
+        } => {
+            let mode = if shared {
+                LockMode::Shared
+            } else {
+                LockMode::Exclusive
+            };
+            let timeout = if nonblocking {
+                Some(Duration::ZERO)
+            } else {
+                timeout
+            };
+            let lock = FileLock::acquire(&lockfile, mode, timeout)?;
+            let status = std::process::Command::new(&command[0])
+                .args(&command[1..])
+                .status()
+                .map_err(|source| FmanError::CommandNotRun {
+                    command: command[0].to_string_lossy().into_owned(),
+                    source,
+                })?;
+            drop(lock);
+            if cli.json {
+                print_json(&serde_json::json!({
+                    "operation": "lock",
+                    "lockfile": lockfile,
+                    "command": command.iter().map(|arg| arg.to_string_lossy()).collect::<Vec<_>>(),
+                    "exit_code": status.code(),
+                    "success": status.success(),
+                }));
+            }
+            if !status.success() {
+                std::process::exit(exit_status_code(status));
+            }
+        }
+        Commands::Ln {
+            fix_dangling,
+            rewrite,
+        } => {
+            let report = ops::fix_dangling_links(
+                &fix_dangling,
+                &rewrite,
+                &filesystem,
+                &naming_context(cli.seed),
+                cli.max_errors,
+                cli.error_log.as_deref(),
+            )?;
+            if cli.json {
+                print_result("ln", &report);
+                return Ok(());
+            }
+            if cli.dry_run {
+                for fixed in &report.fixed {
+                    println!(
+                        "{}: {} -> {}",
+                        fixed.link.display(),
+                        fixed.old_target.display(),
+                        fixed.new_target.display()
+                    );
+                }
+            }
+            for unfixed in &report.unfixed {
+                warn(format_args!(
+                    "{} -> {}: not fixed: {}",
+                    unfixed.link.display(),
+                    unfixed.target.display(),
+                    unfixed.reason
+                ));
+            }
+        }
+        Commands::MirrorPermissions {
+            reference,
+            target,
+            what,
+        } => {
+            let what = Attribute::parse_list(&what)?;
+            let mut request = MirrorRequest::new(&reference, &target, &what)
+                .fs(filesystem.clone())
+                .max_errors(cli.max_errors);
+            if let Some(path) = &cli.error_log {
+                request = request.error_log(path);
+            }
+            let report = ops::mirror_permissions(&request)?;
+            if cli.json {
+                print_result("mirror-permissions", &report);
+                return Ok(());
+            }
+            if cli.dry_run {
+                for MirrorChange { path, change } in &report.changes {
+                    println!(
+                        "{path}: {} {} -> {}",
+                        change.attribute.as_str(),
+                        change.before,
+                        change.after
+                    );
+                }
+            }
+            for path in &report.missing {
+                warn(format_args!("{path}: missing from target"));
+            }
+            for path in &report.type_mismatches {
+                warn(format_args!(
+                    "{path}: different file type in target; skipped"
+                ));
+            }
+        }
+        Commands::Compare { a, b } => {
+            let equal = ops::files_equal(&a, &b)?;
+            if cli.json {
+                print_json(&serde_json::json!({
+                    "operation": "compare",
+                    "a": a,
+                    "b": b,
+                    "equal": equal,
+                }));
+            } else {
+                let verdict = if equal { "are identical" } else { "differ" };
+                println!("{} and {} {verdict}", a.display(), b.display());
+            }
+            if !equal {
+                std::process::exit(1);
+            }
+        }
+        Commands::Dedupe {
+            root,
+            delete,
+            force,
+            include_empty,
+        } => {
+            let options = DedupeOptions::new().include_empty(include_empty);
+            let report = ops::find_duplicates(&DedupeRequest::new(&root).options(options))?;
+            for error in &report.errors {
+                warn(format_args!("{error}"));
+            }
+            if !cli.json {
+                for (i, group) in report.groups.iter().enumerate() {
+                    if i > 0 {
+                        println!();
+                    }
+                    for path in &group.paths {
+                        println!("{}", path.display());
+                    }
+                }
+                note(format_args!(
+                    "{} groups; {} in extra copies",
+                    report.groups.len(),
+                    format_size(report.redundant_bytes)
+                ));
+            }
+            let extra: usize = report.groups.iter().map(|g| g.paths.len() - 1).sum();
+            let removal = if delete && extra > 0 {
+                let prompt =
+                    format!("delete {extra} duplicate files, keeping the first of each group?");
+                if !force && !cli.dry_run && !confirm(&prompt) {
+                    note("aborted");
+                    None
+                } else {
+                    let removal = ops::remove_duplicates(&report.groups, &filesystem)?;
+                    for path in &removal.removed {
+                        say_deleted_path(path);
+                    }
+                    Some(removal)
+                }
+            } else {
+                None
+            };
+            if cli.json {
+                print_json(&serde_json::json!({
+                    "operation": "dedupe",
+                    "report": to_json(&report),
+                    "removed": to_json(&removal),
+                }));
+            }
+        }
+        Commands::Info { path } => {
+            let info = ops::file_info(&path)?;
+            if cli.json {
+                print_result("info", &file_info_json(&info));
+            } else {
+                print_file_info(&info);
+            }
+        }
+        #[cfg(feature = "archive")]
+        Commands::Archive { src, output, force } => {
+            let mut request = ops::ArchiveRequest::new(&src, &output)
+                .force(force)
+                .fs(filesystem.clone())
+                .max_errors(cli.max_errors);
+            if let Some(path) = &cli.error_log {
+                request = request.error_log(path);
+            }
+            let report = ops::archive(&request)?;
+            if cli.json {
+                print_result("archive", &report);
+            } else {
+                for path in &report.skipped {
+                    warn(format_args!("left out special file {}", path.display()));
+                }
+                say_copied(&src, &report.archive);
+            }
+        }
+        #[cfg(feature = "archive")]
+        Commands::Extract {
+            archive,
+            dest,
+            force,
+        } => {
+            let request = ops::ExtractRequest::new(&archive, &dest)
+                .force(force)
+                .fs(filesystem.clone());
+            let report = ops::extract(&request)?;
+            if cli.json {
+                print_result("extract", &report);
+            } else {
+                for name in &report.skipped {
+                    warn(format_args!(
+                        "left out {name}, which is not a file, directory or link"
+                    ));
+                }
+                say_copied(&archive, &report.destination);
+            }
+        }
+        Commands::Chmod {
+            mode,
+            paths,
+            recursive,
+        } => {
+            let change = ModeChange::parse(&mode)?;
+            let mut changed = Vec::new();
+            let mut failures = ErrorList::new(cli.max_errors);
+            for path in &paths {
+                let mut request = ChmodRequest::new(path, change)
+                    .recursive(recursive)
+                    .fs(filesystem.clone())
+                    .max_errors(cli.max_errors);
+                if let Some(log) = &cli.error_log {
+                    request = request.error_log(log);
+                }
+                match ops::chmod(&request) {
+                    Ok(paths) => changed.extend(paths),
+                    Err(e) => failures.push(path, e),
+                }
+            }
+            for path in &changed {
+                say(format_args!("mode {mode} {}", path.display()));
+            }
+            failures.into_result(())?;
+            if cli.json {
+                print_json(&serde_json::json!({
+                    "operation": "chmod",
+                    "mode": mode,
+                    "changed": changed,
+                }));
+            }
+        }
+        Commands::Touch { paths, no_create } => {
+            let mut touched = Vec::new();
+            let mut failures = ErrorList::new(cli.max_errors);
+            for path in &paths {
+                let request = TouchRequest::new(path)
+                    .no_create(no_create)
+                    .fs(filesystem.clone());
+                match ops::touch(&request) {
+                    Ok(result) => {
+                        if result == Touched::Created {
+                            say(format_args!("created {}", path.display()));
+                        }
+                        touched
+                            .push(serde_json::json!({ "path": path, "result": to_json(&result) }));
+                    }
+                    Err(e) => failures.push(path, e),
+                }
+            }
+            failures.into_result(())?;
+            if cli.json {
+                print_json(&serde_json::json!({
+                    "operation": "touch",
+                    "paths": touched,
+                }));
+            }
+        }
+        Commands::FsInfo { path } => {
+            let info = ops::fs_info(&path)?;
+            if cli.json {
+                print_result("fs-info", &info);
+            } else {
+                print_fs_info(&info);
+            }
+        }
+        Commands::Sync {
+            a,
+            b,
+            bidirectional,
+            state_file,
+            modify_window,
+            max_total_bytes,
+            exclude_caches,
+            require,
+            lock,
+        } => {
+            let _lock = hold_lock(lock.as_deref())?;
+            let mode = if bidirectional {
+                SyncMode::Bidirectional { state_file }
+            } else {
+                SyncMode::OneWay
+            };
+            let mut request = SyncRequest::new(&a, &b)
+                .mode(mode)
+                .modify_window(modify_window)
+                .fs(filesystem.clone())
+                .naming(Arc::new(naming_context(cli.seed)))
+                .max_errors(cli.max_errors);
+            if let Some(limit) = max_total_bytes {
+                request = request.budget(ByteBudget::new(limit));
+            }
+            if let Some(mode) = exclude_caches {
+                request = request.exclude_caches(mode.into());
+            }
+            if let Some(list) = &require {
+                request = request.require(&Capability::parse_list(list)?);
+            }
+            if let Some(path) = &cli.error_log {
+                request = request.error_log(path);
+            }
+            let report = ops::sync(&request)?;
+            if cli.json {
+                print_result("sync", &report);
+            } else {
+                for conflict in &report.conflict_copies {
+                    warn(format_args!("conflict; other version kept as {conflict}"));
+                }
+                let counts = report.counts;
+                let mut summary = format!(
+                    "{} copied, {} updated, {} skipped",
+                    counts.copied, counts.updated, counts.skipped
+                );
+                if bidirectional {
+                    summary += &format!(
+                        ", {} deleted, {} conflicts",
+                        counts.deleted, counts.conflicts
+                    );
+                }
+                note(summary);
+                note_budget(report.budget);
+            }
+        }
+        Commands::Du {
+            path,
+            top,
+            watch,
+            interval,
+            no_cache,
+            json_stream,
+            cache_xattr,
+            clear_cache,
+            exclude_caches,
+        } => {
+            if clear_cache {
+                ops::du_clear_cache(&path, &filesystem)?;
+                return Ok(());
+            }
+            if watch {
+                let mut request = DuWatchRequest::new(&path, interval).use_cache(!no_cache);
+                if let Some(mode) = exclude_caches {
+                    request = request.exclude_caches(mode.into());
+                }
+                let redraw = !json_stream && std::io::stdout().is_terminal();
+                ops::du_watch(&request, &SystemClock, |sample| {
+                    print_du_sample(&path, sample, top, json_stream, redraw);
+                    true
+                })?;
+                return Ok(());
+            }
+            let mut request = DuRequest::new(&path).cache_xattr(cache_xattr);
+            if let Some(mode) = exclude_caches {
+                request = request.exclude_caches(mode.into());
+            }
+            if let Some(top) = top {
+                request = request.top(top);
+            }
+            let report = ops::du(&request)?;
+            for error in &report.errors {
+                warn(format_args!("{error}"));
+            }
+            if cli.json {
+                print_result("du", &report);
+            } else {
+                for entry in &report.entries {
+                    println!("{:>8}  {}", format_size(entry.bytes), entry.path.display());
+                }
+                println!(
+                    "{:>8}  {}",
+                    format_size(report.total),
+                    report.root.display()
+                );
+            }
+        }
+        Commands::Watch {
+            path,
+            run,
+            argv,
+            batch,
+            poll,
+            ignore,
+            initial_run,
+            recursive,
+            pattern,
+        } => {
+            let command: Vec<OsString> = match run {
+                Some(run) if run.trim().is_empty() => {
+                    return Err(FmanError::InvalidInput("empty command".to_string()));
+                }
+                Some(run) => run.split_whitespace().map(OsString::from).collect(),
+                None => argv,
+            };
+            let token = CancelToken::new();
+            cancel_on_interrupt(&token);
+            let mut options = WatchOptions::new()
+                .batch(batch)
+                .poll(poll)
+                .initial_run(initial_run)
+                .recursive(recursive || !command.is_empty())
+                .cancel(token);
+            for pattern in ignore {
+                options = options.ignore(pattern);
+            }
+            if let Some(pattern) = pattern {
+                options = options.pattern(pattern);
+            }
+            let request = WatchRequest::new(&path, &command).options(options);
+            if command.is_empty() {
+                return ops::watch_events(&request, &SystemClock, |event| {
+                    if cli.json {
+                        print_json(&to_json(event));
+                    } else {
+                        println!("{}\t{}", event.kind.as_str(), event.path.display());
+                    }
+                    true
+                });
+            }
+            ops::watch_run(&request, &SystemClock, &mut ProcessRunner, |run| {
+                if cli.json {
+                    print_json(&to_json(run));
+                } else {
+                    print_run(run);
+                }
+                true
+            })?;
+        }
+        Commands::Find {
+            root,
+            relative,
+            absolute,
+            format,
+            print0,
+            include_root,
+            name,
+            kind,
+            max_depth,
+        } => {
+            let format = format
+                .map(|f| FormatTemplate::parse(&f, ops::FIND_FORMAT_FIELDS))
+                .transpose()?;
+            let style = match (relative, absolute) {
+                (true, _) => PathStyle::Relative,
+                (_, true) => PathStyle::Absolute,
+                _ => PathStyle::AsGiven,
+            };
+            let mut options = FindOptions::new()
+                .path_style(style)
+                .include_root(include_root);
+            if let Some(pattern) = name {
+                options = options.name(pattern);
+            }
+            if let Some(kind) = kind {
+                options = options.kind(kind.into());
+            }
+            if let Some(depth) = max_depth {
+                options = options.max_depth(depth);
+            }
+            let separator = if print0 { '\0' } else { '\n' };
+            let mut stdout = std::io::stdout().lock();
+            let mut found = Vec::new();
+            for entry in ops::find(&FindRequest::new(&root).options(options))? {
+                let entry = match entry {
+                    Ok(entry) => entry,
+                    Err(e) => {
+                        warn(format_args!("{e}"));
+                        continue;
+                    }
+                };
+                if cli.json {
+                    let mut value = list_entry_json(&entry.entry);
+                    value["path"] = serde_json::json!(entry.path);
+                    value["relpath"] = serde_json::json!(entry.relpath);
+                    found.push(value);
+                    continue;
+                }
+                let mut record = match &format {
+                    Some(template) => template.render(&entry),
+                    None => entry.path.to_string_lossy().into_owned(),
+                };
+                record.push(separator);
+                stdout.write_all(record.as_bytes())?;
+            }
+            if cli.json {
+                print_json(&serde_json::json!({
+                    "operation": "find",
+                    "root": root,
+                    "entries": found,
+                }));
+            }
+        }
+        Commands::Hash {
+            paths,
+            compare,
+            fast,
+            recursive,
+            algo,
+            verify_sidecars,
+            require_coverage,
+        } => {
+            if let Some(root) = verify_sidecars {
+                let request = SidecarRequest::new(&root).require_coverage(require_coverage);
+                let report = ops::verify_sidecars(&request)?;
+                let counts = report.counts;
+                if cli.json {
+                    print_json(&serde_json::json!({
+                        "operation": "verify-sidecars",
+                        "root": root,
+                        "checks": to_json(&report.checks),
+                        "counts": to_json(&counts),
+                    }));
+                } else {
+                    for check in &report.checks {
+                        match &check.error {
+                            Some(error) => println!(
+                                "{}: {} ({error})",
+                                check.path.display(),
+                                check.status.as_str()
+                            ),
+                            None => {
+                                println!("{}: {}", check.path.display(), check.status.as_str())
+                            }
+                        }
+                    }
+                    let mut summary = format!(
+                        "{} ok, {} failed, {} orphaned",
+                        counts.ok, counts.failed, counts.orphaned
+                    );
+                    if require_coverage {
+                        summary += &format!(", {} uncovered", counts.uncovered);
+                    }
+                    note(summary);
+                }
+                let mut problems = Vec::new();
+                if counts.failed > 0 {
+                    problems.push(format!(
+                        "{} files do not match their sidecars",
+                        counts.failed
+                    ));
+                }
+                if counts.uncovered > 0 {
+                    problems.push(format!("{} files have no sidecar", counts.uncovered));
+                }
+                if !problems.is_empty() {
+                    return Err(FmanError::VerificationFailed(problems.join("; ")));
+                }
+                return Ok(());
+            }
+            if fast {
+                let [a, b] = paths.as_slice() else {
+                    return Err(FmanError::InvalidInput(
+                        "--fast compares exactly two files".to_string(),
+                    ));
+                };
+                if !ops::files_equal(a, b)? {
+                    return Err(FmanError::Mismatch(format!(
+                        "{} and {}",
+                        a.display(),
+                        b.display()
+                    )));
+                }
+                return Ok(());
+            }
+
+            let algorithm = HashAlgorithm::from(algo);
+            let request = HashRequest::new(&paths)
+                .algorithm(algorithm)
+                .recursive(recursive);
+            let report = ops::hash(&request)?;
+            let digests: Vec<&String> = report.files.iter().map(|file| &file.digest).collect();
+            if cli.json {
+                let files: Vec<_> = report
+                    .files
+                    .iter()
+                    .map(|file| {
+                        let mut entry = serde_json::json!({ "path": file.path });
+                        entry[algorithm.as_str()] = serde_json::json!(file.digest);
+                        entry
+                    })
+                    .collect();
+                print_json(&serde_json::json!({ "operation": "hash", "files": files }));
+            } else {
+                for file in &report.files {
+                    println!("{}  {}", file.digest, file.path.display());
+                }
+            }
+            if compare {
+                let differing: Vec<_> = paths
+                    .iter()
+                    .zip(&digests)
+                    .filter(|(_, digest)| **digest != digests[0])
+                    .map(|(path, digest)| {
+                        format!(
+                            "{} ({digest}) differs from {} ({})",
+                            path.display(),
+                            paths[0].display(),
+                            digests[0]
+                        )
+                    })
+                    .collect();
+                if !differing.is_empty() {
+                    return Err(FmanError::Mismatch(differing.join("; ")));
+                }
+            }
+        }
+        Commands::Ls {
+            path,
+            recursive,
+            all,
+            long,
+            format,
+            unsorted,
+            sorted: _,
+            low_memory,
+            max_entries_in_memory,
+        } => {
+            let format = format
+                .map(|f| FormatTemplate::parse(&f, ops::LIST_FORMAT_FIELDS))
+                .transpose()?;
+            let order = if unsorted {
+                WalkOrder::Unsorted
+            } else if low_memory {
+                WalkOrder::SortedLowMemory {
+                    max_in_memory: max_entries_in_memory,
+                }
+            } else {
+                WalkOrder::Sorted
+            };
+            let options = ListOptions::new()
+                .recursive(recursive)
+                .all(all)
+                .order(order);
+            let show_progress = !std::io::stdout().is_terminal();
+            let mut stdout = std::io::BufWriter::new(std::io::stdout().lock());
+            let mut listed_json = Vec::new();
+            for (listed, entry) in ops::list(&ListRequest::new(&path).options(options))?.enumerate()
+            {
+                let entry = entry?;
+                if show_progress && (listed + 1) % LS_PROGRESS_EVERY == 0 {
+                    progress(Some(&format!("{} entries", listed + 1)));
+                }
+                if cli.json {
+                    listed_json.push(list_entry_json(&entry));
+                    continue;
+                }
+                let mut out = match &format {
+                    Some(template) => template.render(&entry),
+                    None => {
+                        let name = if recursive {
+                            entry
+                                .path
+                                .strip_prefix(&path)
+                                .unwrap_or(&entry.path)
+                                .to_string_lossy()
+                                .into_owned()
+                        } else {
+                            entry.name()
+                        };
+                        if long { entry.long_row(&name) } else { name }
+                    }
+                };
+                out.push('\n');
+                stdout.write_all(out.as_bytes())?;
+            }
+            stdout.flush()?;
+            if show_progress {
+                progress(None);
+            }
+            if cli.json {
+                print_json(&serde_json::json!({
+                    "operation": "ls",
+                    "path": path,
+                    "entries": listed_json,
+                }));
+            }
+        }
+        Commands::Trash {
+            command:
+                TrashCommand::Empty {
+                    older_than,
+                    larger_than,
+                    yes,
+                    force,
+                },
+        } => {
+            let filter = EmptyFilter {
+                older_than,
+                larger_than,
+                force,
+            };
+            let plan = ops::plan_trash_empty(&filter, SystemTime::now())?;
+            // Under --json the skipped items and the outcome, removed or
+            // not, are reported in one document instead.
+            let report = |removed: usize, reclaimed: u64| {
+                let skipped: Vec<_> = plan
+                    .invalid
+                    .iter()
+                    .map(|item| {
+                        serde_json::json!({
+                            "path": item.path,
+                            "reason": item.info_error,
+                        })
+                    })
+                    .collect();
+                print_json(&serde_json::json!({
+                    "operation": "trash-empty",
+                    "removed": removed,
+                    "reclaimed_bytes": reclaimed,
+                    "skipped": skipped,
+                }));
+            };
+            if !cli.json {
+                for item in &plan.invalid {
+                    warn(format_args!(
+                        "skipping {}: {} (use --force to remove)",
+                        item.path.display(),
+                        item.info_error.as_deref().unwrap_or_default()
+                    ));
+                }
+            }
+            if cli.dry_run {
+                if cli.json {
+                    report(plan.remove.len(), plan.bytes());
+                    return Ok(());
+                }
+                for item in &plan.remove {
+                    let name = item.original.as_deref().unwrap_or(&item.path);
+                    println!(
+                        "would remove {} ({})",
+                        name.display(),
+                        format_size(item.size)
+                    );
+                }
+                println!(
+                    "would reclaim {} in {} items",
+                    format_size(plan.bytes()),
+                    plan.remove.len()
+                );
+                return Ok(());
+            }
+            if plan.remove.is_empty() {
+                if cli.json {
+                    report(0, 0);
+                }
+                return Ok(());
+            }
+            if !yes
+                && !confirm(&format!(
+                    "permanently remove {} items ({})?",
+                    plan.remove.len(),
+                    format_size(plan.bytes())
+                ))
+            {
+                note("aborted");
+                if cli.json {
+                    report(0, 0);
+                }
+                return Ok(());
+            }
+            let reclaimed = ops::empty_trash(&plan, &filesystem)?;
+            if cli.json {
+                report(plan.remove.len(), reclaimed);
+            } else {
+                println!(
+                    "reclaimed {} in {} items",
+                    format_size(reclaimed),
+                    plan.remove.len()
+                );
+            }
+        }
+        Commands::Template {
+            template,
+            dest,
+            vars,
+            strict_vars,
+            max_size,
+            force,
+        } => {
+            let options = vars
+                .into_iter()
+                .fold(TemplateOptions::new(), |opts, (k, v)| opts.var(k, v))
+                .strict_vars(strict_vars)
+                .max_substitute_size(max_size)
+                .force(force)
+                .fs(filesystem.clone());
+            let report = ops::instantiate_template(
+                &TemplateRequest::new(&template, &dest).options(options),
+            )?;
+            if cli.json {
+                print_result("template", &report);
+            } else {
+                for warning in &report.warnings {
+                    warn(format_args!("{warning}"));
+                }
+            }
+        }
+        Commands::Apply {
+            plan,
+            parallel,
+            jobs,
+            sequential: _,
+            max_total_bytes,
+            lock,
+        } => {
+            let _lock = hold_lock(lock.as_deref())?;
+            let jobs = match jobs {
+                Some(jobs) => jobs.get(),
+                None if parallel => thread::available_parallelism().map_or(1, NonZeroUsize::get),
+                None => 1,
+            };
+            let steps = ops::read_plan(&plan)?;
+            let mut request = ApplyRequest::new(steps).jobs(jobs).fs(filesystem.clone());
+            if let Some(limit) = max_total_bytes {
+                request = request.budget(ByteBudget::new(limit));
+            }
+            let report = ops::apply(&request)?;
+            if cli.json {
+                print_result("apply", &report);
+            } else {
+                say(format_args!(
+                    "applied {} steps in {} waves with up to {} jobs",
+                    report.steps, report.waves, report.jobs
+                ));
+                note_budget(report.budget);
+            }
+        }
+        Commands::Completions { shell } => {
+            let mut command = Cli::command();
+            let name = command.get_name().to_string();
+            let mut script = Vec::new();
+            clap_complete::generate(
+                clap_complete::Shell::from(shell),
+                &mut command,
+                name,
+                &mut script,
+            );
+            std::io::stdout().write_all(&script)?;
+        }
+    }
+    Ok(())
+}
+
+fn print_du_sample(
+    root: &Path,
+    sample: &WatchSample,
+    top: Option<usize>,
+    json_stream: bool,
+    redraw: bool,
+) {
+    let entries = &sample.entries[..top.unwrap_or(usize::MAX).min(sample.entries.len())];
+    let secs = sample
+        .taken_at
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+
+    if json_stream {
+        print_json(&serde_json::json!({
+            "operation": "du",
+            "timestamp": secs,
+            "root": root,
+            "total": sample.total,
+            "total_delta": sample.total_delta,
+            "entries": to_json(&entries),
+        }));
+        return;
+    }
+
+    let stamp = fmt::format_time(sample.taken_at, "%Y-%m-%dT%H:%M:%SZ");
+    if redraw {
+        print!("\x1b[2J\x1b[H");
+        println!(
+            "{stamp}  {}  total {} ({})",
+            root.display(),
+            format_size(sample.total),
+            format_delta(sample.total_delta)
+        );
+        for entry in entries {
+            println!(
+                "{:>8}  {:>9}  {}",
+                format_size(entry.bytes),
+                format_delta(entry.delta),
+                entry.path.display()
+            );
+        }
+    } else {
+        for entry in entries {
+            println!(
+                "{stamp}\t{}\t{:+}\t{}",
+                entry.bytes,
+                entry.delta,
+                entry.path.display()
+            );
+        }
+    }
+    for error in &sample.errors {
+        warn(format_args!("{error}"));
+    }
+}
+
+fn print_run(run: &RunReport) {
+    let command = run.argv.join(" ");
+    let changed = match run.paths.len() {
+        1 => "1 changed path".to_string(),
+        n => format!("{n} changed paths"),
+    };
+    match (&run.error, run.exit_code) {
+        (Some(error), _) => warn(format_args!("cannot run {command}: {error}")),
+        (None, Some(0)) => note(format_args!("ran {command} ({changed}): exit status 0")),
+        (None, Some(code)) => warn(format_args!(
+            "{command} ({changed}) failed: exit status {code}"
+        )),
+        (None, None) => warn(format_args!("{command} ({changed}) was killed by a signal")),
+    }
+}
+
+fn format_delta(delta: i64) -> String {
+    match delta {
+        0 => "0".to_string(),
+        d if d > 0 => format!("+{}", format_size(d as u64)),
+        d => format!("-{}", format_size(d.unsigned_abs())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    /// Held by every test that reads or sets fman's environment variables,
+    /// which are shared by the whole process.
+    static ENV: Mutex<()> = Mutex::new(());
+
+    /// Run `command` on `paths` in `dir` through [`try_run`], with an
+    /// empty configuration file so that the user's own is never read.
+    fn try_command(dir: &Path, command: &[&str], paths: &[&str]) -> FmanResult<()> {
+        try_configured(dir, "", command, paths)
+    }
+
+    /// [`try_command`] with `config` as the configuration file.
+    fn try_configured(
+        dir: &Path,
+        config: &str,
+        command: &[&str],
+        paths: &[&str],
+    ) -> FmanResult<()> {
+        let path = dir.join(".config.toml");
+        fs::write(&path, config).unwrap();
+        let config = path;
+        let mut argv: Vec<OsString> = vec!["fman".into(), "--config".into(), config.into()];
+        argv.extend(command.iter().map(OsString::from));
+        argv.extend(paths.iter().map(|path| dir.join(path).into_os_string()));
+        try_run(Cli::try_parse_from(argv).unwrap())
+    }
+
+    #[test]
+    fn failures_map_to_their_exit_codes() {
+        let _env = ENV.lock().unwrap_or_else(|e| e.into_inner());
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        fs::write(root.join("a"), "a").unwrap();
+        fs::write(root.join("b"), "b").unwrap();
+
+        assert!(try_command(root, &["copy"], &["a", "c"]).is_ok());
+        let code = |result: FmanResult<()>| result.unwrap_err().exit_code();
+        assert_eq!(code(try_command(root, &["copy"], &["missing", "d"])), 2);
+        assert_eq!(code(try_command(root, &["copy"], &["a", "b"])), 3);
+        assert_eq!(code(try_command(root, &["chmod", "999"], &["a"])), 4);
+        assert_eq!(code(try_command(root, &["list"], &["a"])), 4);
+        assert_eq!(
+            code(try_command(root, &["copy"], &["a", "no/such/dir/"])),
+            2
+        );
+        assert_eq!(fs::read_to_string(root.join("b")).unwrap(), "b");
+    }
+
+    /// Run `f` with the environment variables `vars` set, removing them
+    /// afterwards. The caller holds [`ENV`].
+    fn with_env<T>(vars: &[(&str, &str)], f: impl FnOnce() -> T) -> T {
+        for (var, value) in vars {
+            // SAFETY: every test that touches the environment holds `ENV`.
+            unsafe { std::env::set_var(var, value) };
+        }
+        let result = f();
+        for (var, _) in vars {
+            // SAFETY: as above.
+            unsafe { std::env::remove_var(var) };
+        }
+        result
+    }
+
+    #[test]
+    fn environment_booleans_are_forgiving() {
+        let _env = ENV.lock().unwrap_or_else(|e| e.into_inner());
+        let flag = |value| with_env(&[("FMAN_TEST_FLAG", value)], || env_flag("FMAN_TEST_FLAG"));
+        for value in ["1", "true", "YES", " on "] {
+            assert_eq!(flag(value).unwrap(), Some(true), "{value:?}");
+        }
+        for value in ["0", "False", "no", "OFF", ""] {
+            assert_eq!(flag(value).unwrap(), Some(false), "{value:?}");
+        }
+        let err = flag("maybe").unwrap_err();
+        assert!(matches!(err, FmanError::InvalidInput(_)), "{err:?}");
+        assert!(err.to_string().contains("FMAN_TEST_FLAG"), "{err}");
+        assert_eq!(env_flag("FMAN_TEST_FLAG").unwrap(), None);
+    }
+
+    #[test]
+    fn a_flag_beats_the_environment_which_beats_the_file() {
+        let _env = ENV.lock().unwrap_or_else(|e| e.into_inner());
+        assert!(!setting(false, false, "FMAN_TEST_FLAG", None).unwrap());
+        assert!(setting(false, false, "FMAN_TEST_FLAG", Some(true)).unwrap());
+        with_env(&[("FMAN_TEST_FLAG", "0")], || {
+            assert!(!setting(false, false, "FMAN_TEST_FLAG", Some(true)).unwrap());
+            assert!(setting(true, false, "FMAN_TEST_FLAG", Some(false)).unwrap());
+        });
+        with_env(&[("FMAN_TEST_FLAG", "1")], || {
+            assert!(setting(false, false, "FMAN_TEST_FLAG", Some(false)).unwrap());
+            assert!(!setting(false, true, "FMAN_TEST_FLAG", Some(true)).unwrap());
+        });
+    }
+
+    #[test]
+    fn copy_takes_force_and_backup_from_the_environment() {
+        let _env = ENV.lock().unwrap_or_else(|e| e.into_inner());
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        fs::write(root.join("a"), "new").unwrap();
+        fs::write(root.join("b"), "old").unwrap();
+        let read = |name: &str| fs::read_to_string(root.join(name)).unwrap();
+
+        with_env(&[("FMAN_FORCE", "1")], || {
+            let err = try_command(root, &["copy", "--no-force"], &["a", "b"]).unwrap_err();
+            assert_eq!(err.exit_code(), 3);
+            assert_eq!(read("b"), "old");
+        });
+        with_env(&[("FMAN_FORCE", "no")], || {
+            let err = try_configured(root, "force = true\n", &["copy"], &["a", "b"]).unwrap_err();
+            assert_eq!(err.exit_code(), 3);
+            assert_eq!(read("b"), "old");
+        });
+        with_env(&[("FMAN_FORCE", "yes"), ("FMAN_BACKUP", "on")], || {
+            try_configured(root, "backup = false\n", &["copy"], &["a", "b"]).unwrap();
+        });
+        assert_eq!(read("b"), "new");
+        assert_eq!(read("b.bak"), "old");
+
+        with_env(&[("FMAN_FORCE", "sometimes")], || {
+            let err = try_command(root, &["copy"], &["a", "c"]).unwrap_err();
+            assert_eq!(err.exit_code(), 4);
+        });
+        assert!(!root.join("c").exists());
+    }
+
+    #[test]
+    fn usage_errors_keep_their_own_exit_code() {
+        assert!(Cli::try_parse_from(["fman", "copy", "only-one"]).is_err());
+        assert!(Cli::try_parse_from(["fman", "no-such-command"]).is_err());
+        for code in [2, 3, 4, 5, 10, 75, 130] {
+            assert_ne!(USAGE_EXIT_CODE, code);
+        }
+    }
+
+    #[test]
+    fn the_long_help_lists_the_exit_codes() {
+        let help = Cli::command().render_long_help().to_string();
+        for line in [
+            "1    usage error",
+            "2    a path does not exist",
+            "3    a path already exists",
+            "4    invalid input",
+            "5    permission denied",
+            "10   any other failure",
+        ] {
+            assert!(help.contains(line), "{line:?} missing from:\n{help}");
+        }
+    }
+}
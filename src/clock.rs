@@ -0,0 +1,24 @@
+//! Time source for operations that wait or timestamp, replaceable in
+//! embedders and tests.
+
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+pub trait Clock {
+    fn now(&self) -> SystemTime;
+    fn sleep(&self, duration: Duration);
+}
+
+/// The real wall clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
@@ -0,0 +1,213 @@
+//! Comparison of files: contents, and modification times.
+
+use std::cmp::Ordering;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use crate::error::FmanResult;
+use crate::validate::{ensure_exists, ensure_is_file};
+
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// Order two mtimes, treating any pair at most `window` apart as equal.
+///
+/// Filesystems store mtimes at different granularities (FAT keeps two
+/// seconds, some NFS servers drop the sub-second part), so a copy can read
+/// back slightly older than its source. Every mtime comparison goes through
+/// here so that `--modify-window` applies to all of them alike.
+pub fn compare_modified(a: SystemTime, b: SystemTime, window: Duration) -> Ordering {
+    let (difference, order) = match a.duration_since(b) {
+        Ok(ahead) => (ahead, Ordering::Greater),
+        Err(e) => (e.duration(), Ordering::Less),
+    };
+    if difference <= window {
+        Ordering::Equal
+    } else {
+        order
+    }
+}
+
+/// Whether `a` and `b` have identical contents.
+///
+/// Two paths to the same file are equal, and files of different sizes
+/// unequal, without reading either; otherwise both are streamed in
+/// fixed-size chunks and compared as they go.
+pub fn files_equal(a: &Path, b: &Path) -> FmanResult<bool> {
+    for path in [a, b] {
+        ensure_exists(path)?;
+        ensure_is_file(path)?;
+    }
+    if a.canonicalize()? == b.canonicalize()? {
+        return Ok(true);
+    }
+    if a.metadata()?.len() != b.metadata()?.len() {
+        return Ok(false);
+    }
+
+    let (mut fa, mut fb) = (File::open(a)?, File::open(b)?);
+    let mut buf_a = vec![0u8; BUFFER_SIZE];
+    let mut buf_b = vec![0u8; BUFFER_SIZE];
+    loop {
+        let n = read_full(&mut fa, &mut buf_a)?;
+        let m = read_full(&mut fb, &mut buf_b)?;
+        if buf_a[..n] != buf_b[..m] {
+            return Ok(false);
+        }
+        if n == 0 {
+            return Ok(true);
+        }
+    }
+}
+
+/// Fill `buf` as far as the reader allows, so chunk boundaries line up
+/// between two readers regardless of short reads.
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    fn at(secs: u64, nanos: u32) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::new(1_600_000_000 + secs, nanos)
+    }
+
+    #[test]
+    fn without_a_window_only_identical_times_are_equal() {
+        let t = at(10, 0);
+        assert_eq!(compare_modified(t, t, Duration::ZERO), Ordering::Equal);
+        let later = at(10, 1);
+        assert_eq!(
+            compare_modified(later, t, Duration::ZERO),
+            Ordering::Greater
+        );
+        assert_eq!(compare_modified(t, later, Duration::ZERO), Ordering::Less);
+    }
+
+    #[test]
+    fn times_exactly_the_window_apart_are_equal() {
+        let window = Duration::from_secs(2);
+        let (a, b) = (at(10, 0), at(12, 0));
+        assert_eq!(compare_modified(a, b, window), Ordering::Equal);
+        assert_eq!(compare_modified(b, a, window), Ordering::Equal);
+    }
+
+    #[test]
+    fn times_just_inside_and_outside_the_window() {
+        let window = Duration::from_secs(2);
+        let base = at(10, 0);
+        assert_eq!(
+            compare_modified(at(11, 999_999_999), base, window),
+            Ordering::Equal
+        );
+        assert_eq!(compare_modified(at(12, 1), base, window), Ordering::Greater);
+        assert_eq!(compare_modified(base, at(12, 1), window), Ordering::Less);
+    }
+
+    #[test]
+    fn a_window_spans_a_whole_second_boundary() {
+        // A sub-second mtime truncated by the destination filesystem.
+        let window = Duration::from_secs(1);
+        let (source, truncated) = (at(10, 900_000_000), at(10, 0));
+        assert_eq!(compare_modified(source, truncated, window), Ordering::Equal);
+        assert_eq!(
+            compare_modified(source, truncated, Duration::ZERO),
+            Ordering::Greater
+        );
+        // Across the epoch second too.
+        let before_epoch = SystemTime::UNIX_EPOCH - Duration::from_millis(500);
+        let after_epoch = SystemTime::UNIX_EPOCH + Duration::from_millis(500);
+        assert_eq!(
+            compare_modified(before_epoch, after_epoch, window),
+            Ordering::Equal
+        );
+        assert_eq!(
+            compare_modified(before_epoch, after_epoch, Duration::from_millis(999)),
+            Ordering::Less
+        );
+    }
+
+    #[test]
+    fn files_are_equal_by_contents() {
+        let dir = TempDir::new().unwrap();
+        let path = |name: &str| dir.path().join(name);
+        let big: Vec<u8> = (0..(3 * BUFFER_SIZE as u32)).map(|i| i as u8).collect();
+        let mut other = big.clone();
+        *other.last_mut().unwrap() ^= 1;
+        fs::write(path("a"), &big).unwrap();
+        fs::write(path("b"), &big).unwrap();
+        fs::write(path("c"), &other).unwrap();
+        fs::write(path("short"), &big[..10]).unwrap();
+        assert!(files_equal(&path("a"), &path("b")).unwrap());
+        assert!(files_equal(&path("a"), &path("a")).unwrap());
+        assert!(!files_equal(&path("a"), &path("c")).unwrap());
+        assert!(!files_equal(&path("a"), &path("short")).unwrap());
+        assert_eq!(
+            files_equal(&path("a"), dir.path()).unwrap_err().exit_code(),
+            4
+        );
+    }
+
+    #[test]
+    fn one_file_under_two_names_is_equal_to_itself() {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        let a = dir.path().join("a");
+        fs::write(&a, "data").unwrap();
+        assert!(files_equal(&a, &dir.path().join("sub/../a")).unwrap());
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink("a", dir.path().join("link")).unwrap();
+            assert!(files_equal(&a, &dir.path().join("link")).unwrap());
+        }
+    }
+
+    #[test]
+    fn a_missing_file_is_not_found() {
+        let dir = TempDir::new().unwrap();
+        let a = dir.path().join("a");
+        fs::write(&a, "data").unwrap();
+        let missing = dir.path().join("missing");
+        assert!(files_equal(&a, &missing).unwrap_err().is_not_found());
+        assert!(files_equal(&missing, &a).unwrap_err().is_not_found());
+    }
+
+    /// Hands out at most one byte per read.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = self.0.len().min(buf.len()).min(1);
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn short_reads_still_fill_the_buffer() {
+        let mut buf = [0u8; 4];
+        let mut reader = Trickle(b"abcdef");
+        assert_eq!(read_full(&mut reader, &mut buf).unwrap(), 4);
+        assert_eq!(&buf, b"abcd");
+        assert_eq!(read_full(&mut reader, &mut buf).unwrap(), 2);
+        assert_eq!(&buf[..2], b"ef");
+        assert_eq!(read_full(&mut reader, &mut buf).unwrap(), 0);
+    }
+}
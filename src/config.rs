@@ -0,0 +1,238 @@
+//! Defaults for command-line flags, read from a TOML file.
+//!
+//! The file is `$XDG_CONFIG_HOME/fman/config.toml`, falling back to
+//! `~/.config/fman/config.toml` (`%APPDATA%\fman\config.toml` on Windows).
+//! Every key is optional, and a flag given on the command line always wins
+//! over the file:
+//!
+//! ```toml
+//! force = false          # copy and move replace existing destinations
+//! preserve = true        # copy keeps timestamps
+//! backup = true          # copy moves replaced files aside first
+//! backup_suffix = ".orig"
+//! overwrite = "update"   # copy: error, overwrite, update, ask, skip or rename
+//! copy_verify = true     # move hashes copies made across filesystems
+//! ```
+//!
+//! Unknown keys are collected rather than rejected, so a file written for
+//! a newer fman still works with an older one.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::error::{FmanError, FmanResult};
+
+/// Keys [`Config`] understands; anything else ends up in
+/// [`Config::unknown_keys`].
+const KNOWN_KEYS: &[&str] = &[
+    "force",
+    "preserve",
+    "backup",
+    "backup_suffix",
+    "overwrite",
+    "copy_verify",
+];
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct Config {
+    /// Default for `--force` on copy and move.
+    pub force: Option<bool>,
+    /// Default for `copy --preserve`.
+    pub preserve: Option<bool>,
+    /// Default for `copy --backup`.
+    pub backup: Option<bool>,
+    /// What backups are named after the file they keep, `.bak` if unset.
+    pub backup_suffix: Option<String>,
+    /// What copy does with an existing destination when no flag says;
+    /// takes precedence over `force` for copy.
+    pub overwrite: Option<Overwrite>,
+    /// Default for `move --copy-verify`.
+    pub copy_verify: Option<bool>,
+    /// Keys in the file that are not settings, sorted.
+    #[serde(skip)]
+    pub unknown_keys: Vec<String>,
+}
+
+/// The `overwrite` setting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Overwrite {
+    /// Fail with `AlreadyExists`, as without flags.
+    Error,
+    /// Replace it, as with `--force`.
+    Overwrite,
+    /// Replace it if older than the source, as with `--update`.
+    Update,
+    /// Ask first, as with `--interactive`.
+    Ask,
+    /// Leave it alone, as with `--on-conflict skip`.
+    Skip,
+    /// Copy to a free name beside it, as with `--on-conflict rename`.
+    Rename,
+}
+
+impl Config {
+    /// Where the configuration is looked for when `--config` does not say;
+    /// `None` if there is no home directory to look in.
+    pub fn default_path() -> Option<PathBuf> {
+        #[cfg(windows)]
+        let base = std::env::var_os("APPDATA")
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from);
+        #[cfg(not(windows))]
+        let base = std::env::var_os("XDG_CONFIG_HOME")
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| {
+                std::env::var_os("HOME")
+                    .filter(|v| !v.is_empty())
+                    .map(|home| PathBuf::from(home).join(".config"))
+            });
+        Some(base?.join("fman").join("config.toml"))
+    }
+
+    /// Read the file at [`default_path`](Self::default_path). No file, or
+    /// no home directory, gives the empty configuration.
+    pub fn load_default() -> FmanResult<Config> {
+        match Self::default_path() {
+            Some(path) if fs::symlink_metadata(&path).is_ok() => Self::load(&path),
+            _ => Ok(Config::default()),
+        }
+    }
+
+    /// Read the file at `path`, which must exist.
+    pub fn load(path: &Path) -> FmanResult<Config> {
+        let text = fs::read_to_string(path).map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => FmanError::missing_path(path),
+            _ => FmanError::io_at(path, e),
+        })?;
+        Self::parse(&text, path)
+    }
+
+    /// Parse `text`, read from `origin`, which errors name along with the
+    /// line and column the TOML parser stopped at.
+    pub fn parse(text: &str, origin: &Path) -> FmanResult<Config> {
+        let invalid = |e: toml::de::Error| {
+            let position = e.span().map_or_else(String::new, |span| {
+                let (line, column) = line_column(text, span.start);
+                format!(":{line}:{column}")
+            });
+            FmanError::InvalidInput(format!("{}{position}: {}", origin.display(), e.message()))
+        };
+        let mut config: Config = toml::from_str(text).map_err(invalid)?;
+        let table: toml::Table = toml::from_str(text).map_err(invalid)?;
+        config.unknown_keys = table
+            .keys()
+            .filter(|key| !KNOWN_KEYS.contains(&key.as_str()))
+            .cloned()
+            .collect();
+        if let Some(suffix) = &config.backup_suffix
+            && (suffix.is_empty() || suffix.contains(std::path::is_separator))
+        {
+            return Err(FmanError::InvalidInput(format!(
+                "{}: backup_suffix {suffix:?} must be non-empty and contain no path separator",
+                origin.display()
+            )));
+        }
+        Ok(config)
+    }
+}
+
+/// The 1-based line and column of byte `offset` in `text`.
+fn line_column(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset.min(text.len())];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (
+        before.matches('\n').count() + 1,
+        before[line_start..].chars().count() + 1,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copy_verify_is_a_known_key() {
+        let config = Config::parse("copy_verify = true\n", Path::new("config.toml")).unwrap();
+        assert_eq!(config.copy_verify, Some(true));
+        assert!(config.unknown_keys.is_empty());
+    }
+
+    fn parse(text: &str) -> FmanResult<Config> {
+        Config::parse(text, Path::new("config.toml"))
+    }
+
+    #[test]
+    fn every_setting_is_read() {
+        let config = parse(
+            "force = true\npreserve = false\nbackup = true\nbackup_suffix = \".orig\"\n\
+             overwrite = \"update\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config,
+            Config {
+                force: Some(true),
+                preserve: Some(false),
+                backup: Some(true),
+                backup_suffix: Some(".orig".to_string()),
+                overwrite: Some(Overwrite::Update),
+                copy_verify: None,
+                unknown_keys: Vec::new(),
+            }
+        );
+        assert_eq!(parse("").unwrap(), Config::default());
+    }
+
+    #[test]
+    fn unknown_keys_are_collected_not_rejected() {
+        let config = parse("zeta = 1\nforce = true\nalpha = \"x\"\n").unwrap();
+        assert_eq!(config.force, Some(true));
+        assert_eq!(config.unknown_keys, ["alpha", "zeta"]);
+    }
+
+    #[test]
+    fn a_malformed_file_names_the_line_and_column() {
+        let err = parse("force = true\npreserve = yes\n").unwrap_err();
+        let FmanError::InvalidInput(message) = &err else {
+            panic!("{err:?}")
+        };
+        assert!(message.starts_with("config.toml:2:12: "), "{message}");
+
+        let err = parse("force = \"always\"\n").unwrap_err();
+        assert!(err.to_string().contains("config.toml:1:9"), "{err}");
+        assert_eq!(err.exit_code(), 4);
+
+        let err = parse("overwrite = \"sometimes\"\n").unwrap_err();
+        assert!(err.to_string().contains("config.toml:1:"), "{err}");
+    }
+
+    #[test]
+    fn a_backup_suffix_must_be_a_plain_suffix() {
+        for suffix in ["", "/bak", "a/b"] {
+            let err = parse(&format!("backup_suffix = {suffix:?}\n")).unwrap_err();
+            assert!(matches!(err, FmanError::InvalidInput(_)), "{suffix:?}");
+        }
+    }
+
+    #[test]
+    fn a_named_file_must_exist() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let err = Config::load(&dir.path().join("missing.toml")).unwrap_err();
+        assert!(err.is_not_found(), "{err}");
+        let path = dir.path().join("config.toml");
+        fs::write(&path, "backup = true\n").unwrap();
+        assert_eq!(Config::load(&path).unwrap().backup, Some(true));
+    }
+
+    #[test]
+    fn line_column_counts_from_one() {
+        assert_eq!(line_column("abc", 0), (1, 1));
+        assert_eq!(line_column("abc\ndéf", 7), (2, 3));
+        assert_eq!(line_column("a\n", 99), (2, 1));
+    }
+}
@@ -0,0 +1,5092 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+#[cfg(feature = "json")]
+use serde::Serialize;
+
+use crate::backend::{self, CopyStrategy, StrategySelector};
+use crate::budget::{BudgetUsage, ByteBudget};
+use crate::cachedir::ExcludeCaches;
+use crate::cancel::CancelToken;
+use crate::checkpoint::{self, Checkpoint, CheckpointHeader};
+use crate::cleanup::{Cleanup, CleanupId, CleanupRegistry};
+use crate::compare::compare_modified;
+use crate::error::{DEFAULT_MAX_ERRORS, Failures, FmanError, FmanResult};
+use crate::fs::{Fs, RealFs, SharedFs};
+use crate::fsinfo::{self, Capability};
+use crate::hash::{HashAlgorithm, hash_reader};
+use crate::metadata::{MetadataPolicy, MetadataSummary};
+use crate::naming::{DEFAULT_BACKUP_SUFFIX, NamingContext};
+use crate::ownership::OwnershipMap;
+use crate::platform;
+use crate::preserve::{self, Attribute};
+use crate::sync::FileState;
+use crate::timing::Timing;
+use crate::validate::{
+    ensure_exists, ensure_is_dir, ensure_is_file, ensure_not_exists, ensure_not_same_file,
+    ensure_not_same_inode, ensure_parents_are_dirs,
+};
+use crate::walk::{SymlinkPolicy, Walk};
+
+/// Per-file content transform.
+///
+/// Receives the source path and returns replacement contents for the
+/// destination, or `None` to copy the source bytes unchanged.
+pub type Transform = dyn Fn(&Path) -> FmanResult<Option<Vec<u8>>> + Send + Sync;
+
+/// Asked whether to replace an existing destination, given its path;
+/// `false` skips the file.
+pub type ConfirmOverwrite = dyn Fn(&Path) -> bool + Send + Sync;
+
+/// Told of a file's progress: the bytes copied so far and the size the
+/// source had when its copy started. See [`CopyOptions::progress`].
+pub type Progress = dyn Fn(u64, u64) + Send + Sync;
+
+/// Rewrites the path of each entry in [`copy_dir`], relative to the
+/// source root, into its path relative to the destination root.
+pub type PathTransform = dyn Fn(&Path) -> FmanResult<PathBuf> + Send + Sync;
+
+/// Options controlling how a file is copied.
+#[derive(Clone, Default)]
+#[non_exhaustive]
+pub struct CopyOptions {
+    pub(crate) on_conflict: OverwritePolicy,
+    pub(crate) confirm_overwrite: Option<Arc<ConfirmOverwrite>>,
+    pub(crate) transform: Option<Arc<Transform>>,
+    pub(crate) progress: Option<Arc<Progress>>,
+    pub(crate) path_transform: Option<Arc<PathTransform>>,
+    pub(crate) racing: Option<RacingPolicy>,
+    pub(crate) reflink: Reflink,
+    pub(crate) retries: u32,
+    pub(crate) retry_delay: Duration,
+    pub(crate) strategy: StrategySelector,
+    pub(crate) stream: bool,
+    pub(crate) read_only: bool,
+    pub(crate) backup: bool,
+    pub(crate) backup_suffix: Option<String>,
+    pub(crate) parents: bool,
+    pub(crate) preserve: bool,
+    pub(crate) verify: bool,
+    pub(crate) atomic: bool,
+    pub(crate) immutable: Option<Immutability>,
+    pub(crate) fs: Option<SharedFs>,
+    pub(crate) symlinks: SymlinkPolicy,
+    pub(crate) exclude_caches: Option<ExcludeCaches>,
+    pub(crate) ignore_vanished: bool,
+    pub(crate) link_mode: LinkMode,
+    pub(crate) update: bool,
+    pub(crate) attributes_only: Option<Vec<Attribute>>,
+    pub(crate) ignore_missing: bool,
+    pub(crate) owners: Option<OwnershipMap>,
+    pub(crate) modify_window: Duration,
+    pub(crate) min_duration_report: Option<Duration>,
+    pub(crate) skip_active: Option<Duration>,
+    pub(crate) retry_active_at_end: bool,
+    pub(crate) max_errors: Option<usize>,
+    pub(crate) error_log: Option<PathBuf>,
+    pub(crate) checkpoint: Option<PathBuf>,
+    pub(crate) keep_checkpoint: bool,
+    pub(crate) cancel: Option<CancelToken>,
+    pub(crate) budget: Option<ByteBudget>,
+    pub(crate) metadata: MetadataPolicy,
+    pub(crate) cleanup: Option<CleanupRegistry>,
+    pub(crate) require: Vec<Capability>,
+    pub(crate) naming: Arc<NamingContext>,
+}
+
+/// What a copy puts at the destination.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LinkMode {
+    /// A new file with the source's data.
+    #[default]
+    Copy,
+    /// A hard link to the source (`cp -l`); both must be on one filesystem.
+    Hardlink,
+    /// A symlink to the source (`cp -s`), by absolute path or, if
+    /// `relative`, by a path relative to the link's directory.
+    Symlink { relative: bool },
+}
+
+/// What a copy does when its destination file already exists.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverwritePolicy {
+    /// Fail with `AlreadyExists`.
+    #[default]
+    Error,
+    /// Replace the destination.
+    Overwrite,
+    /// Leave the destination alone and report the file as skipped.
+    Skip,
+    /// Copy to the first free name beside the destination instead, as in
+    /// `file (1).txt`.
+    RenameNew,
+}
+
+/// Whether a copy shares the source's data blocks instead of copying
+/// them (a reflink, `FICLONE` on Btrfs, XFS and similar Linux
+/// filesystems), which takes no time and no space until either file is
+/// changed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Reflink {
+    /// Always copy the data.
+    #[default]
+    Never,
+    /// Share blocks where the filesystem can, and copy the data where it
+    /// cannot.
+    Auto,
+    /// Share blocks, failing the copy where the filesystem cannot.
+    Always,
+}
+
+/// How `--immutable` reacts when the attribute cannot be set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Immutability {
+    /// Fail the copy.
+    Required,
+    /// Keep the copy without the attribute.
+    BestEffort,
+}
+
+/// What to do with a file whose source changed while it was being copied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RacingPolicy {
+    /// Keep the copy and flag it in the report.
+    Warn,
+    /// Delete the destination and fail with `ChangedDuringCopy`.
+    Error,
+    /// Delete the destination and report the file as skipped.
+    Skip,
+}
+
+/// Extra attempts made before a racing write is handed to the policy.
+pub const RACING_RETRIES: u32 = 1;
+
+/// Outcome of copying a single file.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "json", derive(Serialize))]
+pub struct CopyReport {
+    /// Empty when copying from a handle ([`copy_from_file`]).
+    pub source: PathBuf,
+    /// Empty when copying into a handle ([`copy_to_file`]).
+    pub destination: PathBuf,
+    pub bytes: u64,
+    pub status: CopyStatus,
+    /// How the data was moved. Transformed contents are always written
+    /// buffered.
+    pub strategy: CopyStrategy,
+    /// The source's size or mtime changed while it was being read, on every
+    /// attempt. Only detected with [`CopyOptions::detect_racing_writes`].
+    pub changed_during_copy: bool,
+    /// The immutable attribute was set on the destination.
+    pub immutable: bool,
+    /// Set when the source was a symlink that was recreated as a link
+    /// rather than followed; no data was copied.
+    pub link_target: Option<PathBuf>,
+    /// The data was read back and matched; see [`CopyOptions::verify`].
+    pub verified: bool,
+    /// Where the file that was at the destination was moved; see
+    /// [`CopyOptions::backup`].
+    pub backup: Option<PathBuf>,
+    /// The source was read to the end without regard to its reported size;
+    /// see [`CopyOptions::stream`]. `bytes` is what was read.
+    pub streamed: bool,
+    /// Attribute failures let pass under [`CopyOptions::metadata_policy`];
+    /// only set by [`copy_file`], and `None` when there were none.
+    pub metadata: Option<MetadataSummary>,
+    pub timing: Timing,
+    /// Throughput of the data phase; `None` if it took no measurable time.
+    pub bytes_per_second: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(Serialize), serde(rename_all = "kebab-case"))]
+pub enum CopyStatus {
+    Copied,
+    Skipped,
+    /// A link was made instead of copying; see [`LinkMode`].
+    Linked,
+    /// Only the attributes were applied; see
+    /// [`CopyOptions::attributes_only`].
+    Attributes,
+}
+
+impl CopyReport {
+    /// The report for a file left alone because `dst` was already there.
+    fn skipped(src: &Path, dst: &Path) -> Self {
+        let mut report = Self::new(src.to_path_buf(), dst.to_path_buf());
+        report.status = CopyStatus::Skipped;
+        report
+    }
+
+    fn new(source: PathBuf, destination: PathBuf) -> Self {
+        Self {
+            source,
+            destination,
+            bytes: 0,
+            status: CopyStatus::Copied,
+            strategy: CopyStrategy::Buffered,
+            changed_during_copy: false,
+            immutable: false,
+            link_target: None,
+            verified: false,
+            backup: None,
+            streamed: false,
+            metadata: None,
+            timing: Timing::default(),
+            bytes_per_second: None,
+        }
+    }
+
+    /// Close the report's timing for an operation begun at `started`.
+    fn timed(mut self, started: Instant) -> Self {
+        self.timing.wall = started.elapsed();
+        self.bytes_per_second = self.timing.throughput(self.bytes);
+        self
+    }
+}
+
+impl CopyOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Overwrite an existing destination instead of failing: shorthand
+    /// for [`on_conflict`](Self::on_conflict) with
+    /// [`OverwritePolicy::Overwrite`], or [`OverwritePolicy::Error`] if
+    /// `force` is false.
+    pub fn force(mut self, force: bool) -> Self {
+        self.on_conflict = if force {
+            OverwritePolicy::Overwrite
+        } else {
+            OverwritePolicy::Error
+        };
+        self
+    }
+
+    /// What to do when a file's destination already exists;
+    /// [`OverwritePolicy::Error`] by default.
+    pub fn on_conflict(mut self, policy: OverwritePolicy) -> Self {
+        self.on_conflict = policy;
+        self
+    }
+
+    /// Ask `confirm` before replacing an existing destination file rather
+    /// than failing with `AlreadyExists`, and skip the file if it says no.
+    /// Not asked with [`force`](Self::force), which replaces without
+    /// asking.
+    pub fn confirm_overwrite<F>(mut self, confirm: F) -> Self
+    where
+        F: Fn(&Path) -> bool + Send + Sync + 'static,
+    {
+        self.confirm_overwrite = Some(Arc::new(confirm));
+        self
+    }
+
+    /// Rewrite file contents on the way through. See [`Transform`].
+    pub fn transform<F>(mut self, transform: F) -> Self
+    where
+        F: Fn(&Path) -> FmanResult<Option<Vec<u8>>> + Send + Sync + 'static,
+    {
+        self.transform = Some(Arc::new(transform));
+        self
+    }
+
+    /// Call `progress` with the bytes copied and the source's size, or 0
+    /// for a [streamed](Self::stream) source whose size is unknown, while
+    /// each file's data is written: every [`PROGRESS_STEP`] bytes and once
+    /// when it is complete, with the final count. The count only grows
+    /// within a copy, even past the size if the source grows meanwhile,
+    /// but starts from 0 again when a racing write makes the copy retry.
+    /// Files with progress are copied with the buffered loop. Never called
+    /// in a dry run, which writes nothing.
+    pub fn progress<F>(mut self, progress: F) -> Self
+    where
+        F: Fn(u64, u64) + Send + Sync + 'static,
+    {
+        self.progress = Some(Arc::new(progress));
+        self
+    }
+
+    /// In [`copy_dir`], rename entries on the way through. See
+    /// [`PathTransform`]; [`sanitize_windows_names`] and [`lowercase_names`]
+    /// are ready-made ones. Two entries mapped to the same path fail.
+    pub fn path_transform<F>(mut self, transform: F) -> Self
+    where
+        F: Fn(&Path) -> FmanResult<PathBuf> + Send + Sync + 'static,
+    {
+        self.path_transform = Some(Arc::new(transform));
+        self
+    }
+
+    /// Stat the source before and after reading it and apply `policy` when
+    /// the two disagree, after [`RACING_RETRIES`] retries.
+    pub fn detect_racing_writes(mut self, policy: RacingPolicy) -> Self {
+        self.racing = Some(policy);
+        self
+    }
+
+    /// Read sources to the end with a plain buffered loop, taking nothing
+    /// from their reported size: no size-based strategy, no up-front
+    /// charge to the budget and no racing-write check. Chosen
+    /// automatically for named pipes and for files on pseudo filesystems
+    /// such as procfs and sysfs, which mostly report a size of 0.
+    pub fn stream(mut self, stream: bool) -> Self {
+        self.stream = stream;
+        self
+    }
+
+    /// Files smaller than this are copied with a small buffer.
+    pub fn small_file_threshold(mut self, bytes: u64) -> Self {
+        self.strategy.small_file_threshold = bytes;
+        self
+    }
+
+    /// Files of at least this size are copied in parallel chunks.
+    pub fn huge_file_threshold(mut self, bytes: u64) -> Self {
+        self.strategy.huge_file_threshold = bytes;
+        self
+    }
+
+    /// Copy every file with a read/write loop over a buffer of `bytes`
+    /// (at least 1) instead of choosing a strategy by size, e.g. small
+    /// writes for a slow USB stick or large ones for a network mount.
+    pub fn buffer_size(mut self, bytes: usize) -> Self {
+        self.strategy.buffer_size = Some(bytes);
+        self
+    }
+
+    /// Whether to make the copy share the source's data blocks;
+    /// [`Reflink::Never`] by default. A streamed source or one that is
+    /// [`transform`](Self::transform)ed is always copied.
+    pub fn reflink(mut self, reflink: Reflink) -> Self {
+        self.reflink = reflink;
+        self
+    }
+
+    /// Leave holes in the destination where the source has blocks of
+    /// zeros, so that a sparse file stays sparse; on Linux the source's
+    /// holes are not even read. The copy has the source's length and
+    /// contents either way. Copies that report
+    /// [`progress`](Self::progress) and streamed copies within a
+    /// [`budget`](Self::budget) write every byte.
+    pub fn sparse(mut self, sparse: bool) -> Self {
+        self.strategy.sparse = sparse;
+        self
+    }
+
+    /// Clear the destination's write permission once the copy is complete.
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Move an existing destination file aside to `NAME.bak` (or the first
+    /// free `NAME.bak.N`) before replacing it, instead of failing or
+    /// overwriting it. If the copy then fails the backup is moved back.
+    pub fn backup(mut self, backup: bool) -> Self {
+        self.backup = backup;
+        self
+    }
+
+    /// Name backups `NAME` plus `suffix` rather than `NAME.bak`.
+    pub fn backup_suffix(mut self, suffix: impl Into<String>) -> Self {
+        self.backup_suffix = Some(suffix.into());
+        self
+    }
+
+    /// Create the directory a file is copied into, with any missing
+    /// parents, instead of failing with `DestinationDirMissing`; see
+    /// [`copy_file`].
+    pub fn parents(mut self, parents: bool) -> Self {
+        self.parents = parents;
+        self
+    }
+
+    /// Give each copied file, and each directory [`copy_dir`] creates, the
+    /// access and modification times of its source. Permission bits are
+    /// copied either way.
+    pub fn preserve(mut self, preserve: bool) -> Self {
+        self.preserve = preserve;
+        self
+    }
+
+    /// Probe the destination's filesystem before copying anything and fail
+    /// with [`FmanError::MissingCapabilities`] if it lacks any of
+    /// `capabilities`, instead of copying without them.
+    pub fn require(mut self, capabilities: &[Capability]) -> Self {
+        self.require = capabilities.to_vec();
+        self
+    }
+
+    /// Once a file is written, read the source and the destination again
+    /// and compare their SHA-256 digests. A destination that differs is
+    /// removed and the copy fails with
+    /// [`FmanError::VerificationFailed`]. Streamed sources, transformed
+    /// contents and copies into a handle cannot be checked this way and
+    /// are not.
+    pub fn verify(mut self, verify: bool) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    /// Write each file to a temporary `.fman-tmp-*` file in the
+    /// destination's directory, flush it to disk, and only then give it
+    /// its name, so that a crash leaves the old destination or none rather
+    /// than a truncated one. With [`force`](Self::force) the rename
+    /// replaces an existing file in one step; without it a destination
+    /// that appears after the check still fails the copy with
+    /// [`FmanError::AlreadyExists`]. Cannot be combined with
+    /// [`immutable`](Self::immutable), which would stop the rename.
+    pub fn atomic(mut self, atomic: bool) -> Self {
+        self.atomic = atomic;
+        self
+    }
+
+    /// Set the filesystem immutable attribute on the destination once the
+    /// copy is complete (Linux, needs `CAP_LINUX_IMMUTABLE`).
+    pub fn immutable(mut self, mode: Immutability) -> Self {
+        self.immutable = Some(mode);
+        self
+    }
+
+    /// Make every change to the filesystem through `fs` instead of
+    /// [`RealFs`]. When `fs` only records, nothing is written and the report
+    /// describes the copy that would have been made.
+    pub fn fs(mut self, fs: SharedFs) -> Self {
+        self.fs = Some(fs);
+        self
+    }
+
+    /// Where temporary, backup and conflict copy names come from.
+    pub fn naming(mut self, naming: Arc<NamingContext>) -> Self {
+        self.naming = naming;
+        self
+    }
+
+    /// Which symlinks to follow. A source given directly is at depth 0.
+    pub fn symlinks(mut self, policy: SymlinkPolicy) -> Self {
+        self.symlinks = policy;
+        self
+    }
+
+    /// In [`copy_dir`], leave out directories tagged as caches with a
+    /// `CACHEDIR.TAG`, keeping what `mode` says of them.
+    pub fn exclude_caches(mut self, mode: ExcludeCaches) -> Self {
+        self.exclude_caches = Some(mode);
+        self
+    }
+
+    /// In [`copy_dir`], count files that disappear between the walk and
+    /// their copy as vanished instead of failing.
+    pub fn ignore_vanished(mut self, ignore: bool) -> Self {
+        self.ignore_vanished = ignore;
+        self
+    }
+
+    /// Link to the source instead of copying its data. Directories are
+    /// still created as usual by [`copy_dir`].
+    pub fn link_mode(mut self, mode: LinkMode) -> Self {
+        self.link_mode = mode;
+        self
+    }
+
+    /// Copy only when the destination is missing or older than the source,
+    /// replacing it in that case; otherwise report the file as skipped.
+    pub fn update(mut self, update: bool) -> Self {
+        self.update = update;
+        self
+    }
+
+    /// Copy no data: give each existing destination the `attributes` of
+    /// its source instead, creating and truncating nothing. A missing
+    /// destination fails with `NotFound` unless
+    /// [`ignore_missing`](Self::ignore_missing) is set. [`copy_dir`] takes
+    /// its `dst` as the counterpart of the source root rather than a
+    /// directory to copy into.
+    pub fn attributes_only(mut self, attributes: &[Attribute]) -> Self {
+        self.attributes_only = Some(attributes.to_vec());
+        self
+    }
+
+    /// With [`attributes_only`](Self::attributes_only), skip sources whose
+    /// destination does not exist instead of failing.
+    pub fn ignore_missing(mut self, ignore: bool) -> Self {
+        self.ignore_missing = ignore;
+        self
+    }
+
+    /// Translate source owners through `owners`. A copied file or created
+    /// directory whose source owner is mapped gets the new ids, and keeps
+    /// the copier's otherwise; [`attributes_only`](Self::attributes_only)
+    /// applies the mapped ids in place of the source's.
+    pub fn ownership_map(mut self, owners: OwnershipMap) -> Self {
+        self.owners = Some(owners);
+        self
+    }
+
+    /// Treat mtimes no more than `window` apart as equal when comparing
+    /// them for [`update`](Self::update).
+    pub fn modify_window(mut self, window: Duration) -> Self {
+        self.modify_window = window;
+        self
+    }
+
+    /// In [`copy_dir`], skip files modified less than `window` ago, which
+    /// are likely still being written.
+    pub fn skip_active(mut self, window: Duration) -> Self {
+        self.skip_active = Some(window);
+        self
+    }
+
+    /// With [`skip_active`](Self::skip_active), look at the skipped files
+    /// again once the rest of the tree is copied and copy those that have
+    /// settled by then.
+    pub fn retry_active_at_end(mut self, retry: bool) -> Self {
+        self.retry_active_at_end = retry;
+        self
+    }
+
+    /// In [`copy_dir`], list files that take at least `threshold` to copy
+    /// in [`CopyDirReport::slow_files`].
+    pub fn min_duration_report(mut self, threshold: Duration) -> Self {
+        self.min_duration_report = Some(threshold);
+        self
+    }
+
+    /// Copy a file's data again, from the start, up to `retries` more times
+    /// when it fails with an error that may pass (`Interrupted`,
+    /// `WouldBlock` or `TimedOut`), as on a flaky network mount. Checks
+    /// before the copy and the post-copy pipeline are not retried, nor is
+    /// a source that cannot be read again, such as a pipe. The last error
+    /// is returned once the retries are used up.
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Wait `delay` before each of the [`retries`](Self::retries); none
+    /// unless set.
+    pub fn retry_delay(mut self, delay: Duration) -> Self {
+        self.retry_delay = delay;
+        self
+    }
+
+    /// In [`copy_dir`], keep at most `max` failures for the final error;
+    /// later ones are only counted. Defaults to [`DEFAULT_MAX_ERRORS`].
+    pub fn max_errors(mut self, max: usize) -> Self {
+        self.max_errors = Some(max);
+        self
+    }
+
+    /// In [`copy_dir`], append every failure to the file at `path`, however
+    /// many there are.
+    pub fn error_log(mut self, path: impl Into<PathBuf>) -> Self {
+        self.error_log = Some(path.into());
+        self
+    }
+
+    /// In [`copy_dir`], record finished files in a [`Checkpoint`] at
+    /// `path` and skip those it already lists with an unchanged source. The
+    /// checkpoint is removed once the copy succeeds.
+    pub fn checkpoint(mut self, path: impl Into<PathBuf>) -> Self {
+        self.checkpoint = Some(path.into());
+        self
+    }
+
+    /// Leave the checkpoint in place after a successful copy.
+    pub fn keep_checkpoint(mut self, keep: bool) -> Self {
+        self.keep_checkpoint = keep;
+        self
+    }
+
+    /// In [`copy_dir`], stop before the next file once `token` is
+    /// cancelled, failing with [`FmanError::Cancelled`].
+    pub fn cancel(mut self, token: CancelToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    /// Charge every byte written to `budget`, failing with
+    /// [`FmanError::QuotaExceeded`] rather than going past its limit. A
+    /// file is charged its size before it is created, so one that does not
+    /// fit is never started; one that grows while being copied is stopped
+    /// when the growth does not fit, and its partial copy removed.
+    /// [`copy_dir`] stops at the first file over the limit.
+    pub fn budget(mut self, budget: ByteBudget) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    /// Decide what a failure to set the destination's mode, owner, times
+    /// or xattrs does. By default one the filesystem does not support is
+    /// only a warning, and a class of attribute that keeps failing on a
+    /// filesystem is no longer tried there; pass
+    /// [`MetadataPolicy::strict`] to fail the file instead.
+    pub fn metadata_policy(mut self, policy: MetadataPolicy) -> Self {
+        self.metadata = policy;
+        self
+    }
+
+    /// Register what the copy creates on `registry` and leave undoing it
+    /// to the caller. Without one, a copy that fails part way removes the
+    /// files it was writing and, in [`copy_dir`], the directories it
+    /// created that are still empty when it stops.
+    pub fn cleanup(mut self, registry: CleanupRegistry) -> Self {
+        self.cleanup = Some(registry);
+        self
+    }
+
+    /// What the [`metadata_policy`](Self::metadata_policy) let pass so
+    /// far, or `None` if nothing.
+    fn metadata_summary(&self) -> Option<MetadataSummary> {
+        Some(self.metadata.summary()).filter(|summary| !summary.is_empty())
+    }
+
+    /// Whether an existing destination is replaced.
+    fn overwrites(&self) -> bool {
+        self.on_conflict == OverwritePolicy::Overwrite
+    }
+
+    pub(crate) fn filesystem(&self) -> &dyn Fs {
+        self.fs.as_deref().unwrap_or(&RealFs)
+    }
+}
+
+impl fmt::Debug for CopyOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CopyOptions")
+            .field("on_conflict", &self.on_conflict)
+            .field("confirm_overwrite", &self.confirm_overwrite.is_some())
+            .field("transform", &self.transform.is_some())
+            .field("progress", &self.progress.is_some())
+            .field("path_transform", &self.path_transform.is_some())
+            .field("racing", &self.racing)
+            .field("reflink", &self.reflink)
+            .field("retries", &self.retries)
+            .field("retry_delay", &self.retry_delay)
+            .field("strategy", &self.strategy)
+            .field("stream", &self.stream)
+            .field("read_only", &self.read_only)
+            .field("backup", &self.backup)
+            .field("backup_suffix", &self.backup_suffix)
+            .field("preserve", &self.preserve)
+            .field("verify", &self.verify)
+            .field("atomic", &self.atomic)
+            .field("immutable", &self.immutable)
+            .field("fs", &self.fs.is_some())
+            .field("symlinks", &self.symlinks)
+            .field("exclude_caches", &self.exclude_caches)
+            .field("ignore_vanished", &self.ignore_vanished)
+            .field("link_mode", &self.link_mode)
+            .field("update", &self.update)
+            .field("attributes_only", &self.attributes_only)
+            .field("ignore_missing", &self.ignore_missing)
+            .field("owners", &self.owners)
+            .field("modify_window", &self.modify_window)
+            .field("min_duration_report", &self.min_duration_report)
+            .field("skip_active", &self.skip_active)
+            .field("retry_active_at_end", &self.retry_active_at_end)
+            .field("max_errors", &self.max_errors)
+            .field("error_log", &self.error_log)
+            .field("checkpoint", &self.checkpoint)
+            .field("keep_checkpoint", &self.keep_checkpoint)
+            .field("cancel", &self.cancel)
+            .field("budget", &self.budget)
+            .field("metadata", &self.metadata)
+            .field("cleanup", &self.cleanup)
+            .field("require", &self.require)
+            .field("naming", &self.naming)
+            .finish()
+    }
+}
+
+/// If `dst` is an existing directory, the file is placed inside it under
+/// the source's file name; otherwise `dst` is the target file path.
+///
+/// A `dst` with `..` in it is resolved against the filesystem first: the
+/// part up to its last `..` must exist and is replaced by its canonical
+/// path, so `link/..` means the directory the system goes to rather than a
+/// lexical join. A `src` ending in `.` or `..` is named after the directory
+/// it resolves to. An empty `dst` is rejected.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(level = "debug", ret, err(level = "debug"))
+)]
+pub fn resolve_destination_path(src: &Path, dst: &Path) -> FmanResult<PathBuf> {
+    resolve_destination_in(src, dst, &RealFs)
+}
+
+/// [`resolve_destination_path`] as `filesystem` sees it, so that under a
+/// dry run a directory the plan has already created counts as one.
+pub(crate) fn resolve_destination_in(
+    src: &Path,
+    dst: &Path,
+    filesystem: &dyn Fs,
+) -> FmanResult<PathBuf> {
+    let dst = normalize_destination(dst)?;
+    if filesystem.is_dir(&dst)
+        && let Some(name) = source_name(src)
+    {
+        return Ok(dst.join(name));
+    }
+    Ok(dst)
+}
+
+/// `err`, raised for the destination `resolved` from `dst`, also naming
+/// `dst` as it was given when resolving its `..` changed it, so that an
+/// error about `/home/me/a` says it came from `d/..`.
+pub(crate) fn as_given(err: FmanError, resolved: &Path, dst: &Path) -> FmanError {
+    if !dst.components().any(|c| c == Component::ParentDir) {
+        return err;
+    }
+    let resolved = resolved.display().to_string();
+    let given = |what: String| {
+        if what.contains(&resolved) {
+            format!("{what} (destination given as {})", dst.display())
+        } else {
+            what
+        }
+    };
+    match err {
+        FmanError::AlreadyExists(what) => FmanError::AlreadyExists(given(what)),
+        FmanError::InvalidInput(what) => FmanError::InvalidInput(given(what)),
+        FmanError::NotADirectory(what) => FmanError::NotADirectory(given(what)),
+        FmanError::SameFile(what) => FmanError::SameFile(given(what)),
+        FmanError::PermissionDenied(what) => FmanError::PermissionDenied(given(what)),
+        err => err,
+    }
+}
+
+/// `dst` with everything up to its last `..` component resolved; see
+/// [`resolve_destination_path`].
+pub(crate) fn normalize_destination(dst: &Path) -> FmanResult<PathBuf> {
+    if dst.as_os_str().is_empty() {
+        return Err(FmanError::InvalidInput(
+            "the destination path is empty".to_string(),
+        ));
+    }
+    let components: Vec<Component> = dst.components().collect();
+    let Some(last_parent) = components
+        .iter()
+        .rposition(|component| *component == Component::ParentDir)
+    else {
+        return Ok(dst.to_path_buf());
+    };
+    let head: PathBuf = components[..=last_parent].iter().collect();
+    let base = head.canonicalize().map_err(|e| {
+        let absolute = std::path::absolute(dst).unwrap_or_else(|_| dst.to_path_buf());
+        // The first component that stops the resolution.
+        let blocking = (1..=last_parent + 1)
+            .map(|len| components[..len].iter().collect::<PathBuf>())
+            .find(|prefix| !prefix.is_dir())
+            .unwrap_or_else(|| head.clone());
+        let problem = if blocking.exists() {
+            "is not a directory"
+        } else {
+            "does not exist"
+        };
+        FmanError::InvalidInput(format!(
+            "cannot resolve destination {} ({}): {} {problem} ({e})",
+            dst.display(),
+            absolute.display(),
+            blocking.display()
+        ))
+    })?;
+    let base = platform::simplify_verbatim(base);
+    let rest: PathBuf = components[last_parent + 1..].iter().collect();
+    if rest.as_os_str().is_empty() {
+        return Ok(base);
+    }
+    Ok(base.join(rest))
+}
+
+/// The name a copy of `src` gets inside a destination directory.
+fn source_name(src: &Path) -> Option<OsString> {
+    match src.file_name() {
+        Some(name) => Some(name.to_os_string()),
+        None => Some(src.canonicalize().ok()?.file_name()?.to_os_string()),
+    }
+}
+
+/// Copy the file at `src` to `dst`.
+///
+/// A `dst` ending in a separator names the directory to copy into. That
+/// directory, or the parent of any other `dst`, must exist unless
+/// [`CopyOptions::parents`] creates it; it is removed again if the copy
+/// then fails.
+///
+/// The source is opened as soon as it has been validated and read only
+/// through that handle from then on; see [`copy_from_file`]. A symlink
+/// source the [`SymlinkPolicy`] does not follow at depth 0 is recreated as
+/// a link to the same target, even if that target does not exist. On
+/// Windows that takes the symlink privilege or Developer Mode, and fails
+/// with the system's error without them.
+pub fn copy_file(
+    src: impl AsRef<Path>,
+    dst: impl AsRef<Path>,
+    options: &CopyOptions,
+) -> FmanResult<CopyReport> {
+    copy_file_at(src.as_ref(), dst.as_ref(), options)
+}
+
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(name = "copy_file", skip(options), err)
+)]
+fn copy_file_at(src: &Path, dst: &Path, options: &CopyOptions) -> FmanResult<CopyReport> {
+    let started = Instant::now();
+    fsinfo::require(src, dst, &options.require)?;
+    // A `..` that cannot be resolved says more than a missing parent does.
+    normalize_destination(dst)?;
+    let registry = options.cleanup.clone().unwrap_or_default();
+    let created = destination_dir(dst, options, &registry)?;
+    let copied = resolve_destination_in(src, dst, options.filesystem()).and_then(|resolved| {
+        copy_entry(src, &resolved, 0, options).map_err(|e| as_given(e, &resolved, dst))
+    });
+    let mut report = match copied {
+        Err(e) if options.cleanup.is_none() => {
+            return registry.unwind(options.filesystem(), options.error_log.as_deref(), e);
+        }
+        result => result?,
+    };
+    for id in created {
+        registry.forget(id);
+    }
+    report.metadata = options.metadata_summary();
+    #[cfg(feature = "tracing")]
+    tracing::info!(
+        src = %report.source.display(),
+        dst = %report.destination.display(),
+        bytes = report.bytes,
+        "copied"
+    );
+    Ok(report.timed(started))
+}
+
+/// Make sure the directory a file copied to `dst` lands in exists: `dst`
+/// itself when it ends in a separator, its parent otherwise. Under
+/// [`CopyOptions::parents`] missing directories are created and
+/// registered on `registry`, to be removed again if the copy fails;
+/// without it they fail with `DestinationDirMissing`.
+fn destination_dir(
+    dst: &Path,
+    options: &CopyOptions,
+    registry: &CleanupRegistry,
+) -> FmanResult<Vec<CleanupId>> {
+    let dir = if ends_with_separator(dst) {
+        dst.components().collect::<PathBuf>()
+    } else {
+        match dst.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => return Ok(Vec::new()),
+        }
+    };
+    let filesystem = options.filesystem();
+    if filesystem.is_dir(&dir) {
+        return Ok(Vec::new());
+    }
+    ensure_parents_are_dirs(&dir)?;
+    if filesystem.exists(&dir) {
+        // A file or a dangling link.
+        return Err(FmanError::NotADirectory(dir.display().to_string()));
+    }
+    if !options.parents {
+        return Err(FmanError::DestinationDirMissing(dir.display().to_string()));
+    }
+    let missing: Vec<PathBuf> = dir
+        .ancestors()
+        .take_while(|ancestor| !ancestor.as_os_str().is_empty() && !filesystem.exists(ancestor))
+        .map(Path::to_path_buf)
+        .collect();
+    filesystem
+        .create_dir_all(&dir)
+        .map_err(|e| FmanError::io_at(&dir, e))?;
+    Ok(missing
+        .into_iter()
+        .rev()
+        .map(|created| registry.push(Cleanup::RemoveEmptyDir(created)))
+        .collect())
+}
+
+/// Whether `path` is written with a trailing separator, as in `june/`.
+pub(crate) fn ends_with_separator(path: &Path) -> bool {
+    path.as_os_str()
+        .as_encoded_bytes()
+        .last()
+        .is_some_and(|&byte| std::path::is_separator(char::from(byte)))
+}
+
+/// Outcome of copying a directory tree.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "json", derive(Serialize))]
+pub struct CopyDirReport {
+    pub source: PathBuf,
+    pub destination: PathBuf,
+    /// Files and symlinks copied.
+    pub files: u64,
+    /// Links made in place of files with [`CopyOptions::link_mode`].
+    pub links: u64,
+    /// Directories created, including the destination root.
+    pub directories: u64,
+    /// Entries, including the root, given their source's attributes under
+    /// [`CopyOptions::attributes_only`].
+    pub attributes: u64,
+    pub bytes: u64,
+    /// Files left alone: up to date under [`CopyOptions::update`], dropped
+    /// by the racing-write policy, or missing from the destination under
+    /// [`CopyOptions::ignore_missing`].
+    pub skipped: u64,
+    /// Entries that disappeared between the walk and their copy.
+    pub vanished: u64,
+    /// Files not copied again because the checkpoint lists them.
+    pub resumed: u64,
+    /// Files skipped as still being written under
+    /// [`CopyOptions::skip_active`], after any retry.
+    pub skipped_active: u64,
+    /// Files skipped as active at first and copied in the second pass.
+    pub retried_active: u64,
+    /// The data phase is the sum over all files.
+    pub timing: Timing,
+    /// Throughput of the data phase; `None` if it took no measurable time.
+    pub bytes_per_second: Option<f64>,
+    /// Files that took at least [`CopyOptions::min_duration_report`].
+    pub slow_files: Vec<SlowFile>,
+    /// Use of the [`CopyOptions::budget`] once the copy finished.
+    pub budget: Option<BudgetUsage>,
+    /// Attribute failures let pass under [`CopyOptions::metadata_policy`];
+    /// `None` when there were none.
+    pub metadata: Option<MetadataSummary>,
+}
+
+/// A file whose copy was slower than the reporting threshold.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "json", derive(Serialize))]
+pub struct SlowFile {
+    pub path: PathBuf,
+    pub bytes: u64,
+    pub seconds: f64,
+    pub bytes_per_second: Option<f64>,
+    pub strategy: CopyStrategy,
+}
+
+/// Copy the directory tree at `src` to `dst`.
+///
+/// `dst` is resolved like a file destination: an existing directory
+/// receives a copy named after `src`, unless a checkpoint being resumed was
+/// written for `dst` itself. Files are copied with [`copy_file`]
+/// semantics, so without `force` a conflicting file fails with
+/// `AlreadyExists`; the copy stops there and keeps what it has copied. Any
+/// other failed entry does not stop the copy: the rest of the tree is
+/// copied and the failures are returned together at the end, as
+/// [`FmanError::Multiple`] when there is more than one.
+/// Copying a directory into itself is rejected. A symlink root the policy
+/// does not follow is copied as a link.
+pub fn copy_dir(src: &Path, dst: &Path, options: &CopyOptions) -> FmanResult<CopyDirReport> {
+    let started = Instant::now();
+    fsinfo::require(src, dst, &options.require)?;
+    let dst = match resumed_destination(dst, options) {
+        _ if options.attributes_only.is_some() => dst.to_path_buf(),
+        Some(resumed) => resumed,
+        None => resolve_destination_in(src, dst, options.filesystem())?,
+    };
+    if !options.symlinks.follows(0)
+        && let Ok(meta) = fs::symlink_metadata(src)
+        && meta.is_symlink()
+    {
+        let link = copy_symlink(src, &meta, &dst, options)?;
+        return Ok(CopyDirReport {
+            source: link.source,
+            destination: link.destination,
+            files: 1,
+            ..CopyDirReport::default()
+        });
+    }
+    ensure_exists(src)?;
+    ensure_is_dir(src)?;
+    ensure_parents_are_dirs(&dst)?;
+    if canonical_destination(&dst)?.starts_with(src.canonicalize()?) {
+        return Err(FmanError::InvalidInput(format!(
+            "cannot copy {} into itself",
+            src.display()
+        )));
+    }
+
+    let registry = options.cleanup.clone().unwrap_or_default();
+    match copy_tree(src, dst, options, started, &registry) {
+        // Stopping at a conflict keeps what was copied before it, the
+        // directories included.
+        Err(e @ FmanError::AlreadyExists(_)) => Err(e),
+        Err(e) if options.cleanup.is_none() => {
+            registry.unwind(options.filesystem(), options.error_log.as_deref(), e)
+        }
+        result => result,
+    }
+}
+
+/// The walk of [`copy_dir`], registering the directories it creates on
+/// `registry` until the walk is through.
+fn copy_tree(
+    src: &Path,
+    dst: PathBuf,
+    options: &CopyOptions,
+    started: Instant,
+    registry: &CleanupRegistry,
+) -> FmanResult<CopyDirReport> {
+    let filesystem = options.filesystem();
+    // Directories created by this copy, undone if it stops part way.
+    let mut created_dirs = Vec::new();
+    let mut create_dir = |dir: &Path| -> FmanResult<()> {
+        let existed = fs::symlink_metadata(dir).is_ok();
+        filesystem
+            .create_dir_all(dir)
+            .map_err(|e| FmanError::io_at(dir, e))?;
+        if !existed {
+            created_dirs.push(registry.push(Cleanup::RemoveEmptyDir(dir.to_path_buf())));
+        }
+        Ok(())
+    };
+    let mut report = CopyDirReport {
+        source: src.to_path_buf(),
+        destination: dst.clone(),
+        ..CopyDirReport::default()
+    };
+    let preserve_dirs = options.preserve && options.attributes_only.is_none();
+    // Directories whose times are set once nothing more is put in them.
+    let mut timed_dirs = Vec::new();
+    match &options.attributes_only {
+        Some(attributes) => {
+            copy_attributes(src, &dst, 0, attributes, options)?;
+            report.attributes += 1;
+        }
+        None => {
+            create_dir(&dst)?;
+            map_dir_owner(src, &dst, options)?;
+            report.directories += 1;
+            if preserve_dirs {
+                timed_dirs.push((src.to_path_buf(), dst.clone()));
+            }
+        }
+    }
+    let mut failures = Failures::new(
+        options.max_errors.unwrap_or(DEFAULT_MAX_ERRORS),
+        options.error_log.as_deref(),
+    )?;
+    let mut checkpoint = match &options.checkpoint {
+        Some(path) => Some(Checkpoint::open(
+            path,
+            checkpoint_header(src, &dst, options)?,
+            filesystem,
+        )?),
+        None => None,
+    };
+    // Transformed destinations and the source each came from.
+    let mut claimed = HashMap::new();
+    // Files put off by `skip_active` until the end of the walk.
+    let mut deferred = Vec::new();
+    let mut walk = Walk::new(src).symlinks(options.symlinks);
+    if let Some(mode) = options.exclude_caches {
+        walk = walk.exclude_caches(mode);
+    }
+    for entry in walk {
+        if options
+            .cancel
+            .as_ref()
+            .is_some_and(CancelToken::is_cancelled)
+        {
+            if let Some(checkpoint) = &mut checkpoint {
+                checkpoint.flush()?;
+            }
+            return Err(FmanError::Cancelled);
+        }
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) if options.ignore_vanished && e.is_not_found() => {
+                report.vanished += 1;
+                continue;
+            }
+            Err(e) => {
+                failures.push(src, e)?;
+                continue;
+            }
+        };
+        let relative = entry.path().strip_prefix(src).unwrap_or(entry.path());
+        let target = match destination_of(relative, entry.path(), &mut claimed, options) {
+            Ok(relative) => dst.join(relative),
+            Err(e) => {
+                failures.push(entry.path(), e)?;
+                continue;
+            }
+        };
+        if entry.file_type().is_dir()
+            && let Some(attributes) = &options.attributes_only
+        {
+            match copy_attributes(entry.path(), &target, entry.depth(), attributes, options) {
+                Ok(applied) if applied.status == CopyStatus::Skipped => report.skipped += 1,
+                Ok(_) => report.attributes += 1,
+                Err(e) => failures.push(entry.path(), e)?,
+            }
+            continue;
+        }
+        if entry.file_type().is_dir() {
+            let created =
+                create_dir(&target).and_then(|()| map_dir_owner(entry.path(), &target, options));
+            match created {
+                Ok(()) => {
+                    report.directories += 1;
+                    if preserve_dirs {
+                        timed_dirs.push((entry.path().to_path_buf(), target));
+                    }
+                }
+                Err(e) => failures.push(entry.path(), e)?,
+            }
+            continue;
+        }
+        // A path transform may put a file below a directory the walk
+        // never created.
+        if options.path_transform.is_some()
+            && options.attributes_only.is_none()
+            && let Some(parent) = target.parent()
+            && !filesystem.is_dir(parent)
+            && let Err(e) = create_dir(parent)
+        {
+            failures.push(entry.path(), e)?;
+            continue;
+        }
+        let meta = if checkpoint.is_some() || options.skip_active.is_some() {
+            match entry.metadata() {
+                Ok(meta) => Some(meta),
+                Err(e) if options.ignore_vanished && e.is_not_found() => {
+                    report.vanished += 1;
+                    continue;
+                }
+                Err(e) => {
+                    failures.push(entry.path(), e)?;
+                    continue;
+                }
+            }
+        } else {
+            None
+        };
+        let modified = match meta.as_ref().map(fs::Metadata::modified).transpose() {
+            Ok(modified) => modified,
+            Err(e) => {
+                failures.push(entry.path(), FmanError::io_at(entry.path(), e))?;
+                continue;
+            }
+        };
+        let file = TreeFile {
+            source: entry.path().to_path_buf(),
+            target,
+            depth: entry.depth(),
+            key: relative_key(relative),
+            state: match (&checkpoint, &meta, modified) {
+                (Some(_), Some(meta), Some(modified)) => Some(FileState {
+                    size: meta.len(),
+                    modified,
+                }),
+                _ => None,
+            },
+        };
+        if let (Some(checkpoint), Some(state)) = (&checkpoint, &file.state)
+            && checkpoint.is_done(&file.key, state)
+        {
+            report.resumed += 1;
+            continue;
+        }
+        if let (Some(window), Some(modified)) = (options.skip_active, modified)
+            && is_active(modified, SystemTime::now(), window)
+        {
+            if options.retry_active_at_end {
+                deferred.push(file);
+            } else {
+                report.skipped_active += 1;
+            }
+            continue;
+        }
+        copy_tree_file(&file, options, &mut report, &mut failures, &mut checkpoint)?;
+    }
+    // Second pass over files that were being written: copy those that
+    // have settled since, and skip the rest for good.
+    for file in deferred {
+        if options
+            .cancel
+            .as_ref()
+            .is_some_and(CancelToken::is_cancelled)
+        {
+            if let Some(checkpoint) = &mut checkpoint {
+                checkpoint.flush()?;
+            }
+            return Err(FmanError::Cancelled);
+        }
+        let meta = if options.symlinks.follows(file.depth) {
+            fs::metadata(&file.source)
+        } else {
+            fs::symlink_metadata(&file.source)
+        };
+        if let (Ok(meta), Some(window)) = (meta, options.skip_active) {
+            match meta.modified() {
+                Ok(modified) if is_active(modified, SystemTime::now(), window) => {
+                    report.skipped_active += 1;
+                    continue;
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    failures.push(&file.source, FmanError::io_at(&file.source, e))?;
+                    continue;
+                }
+            }
+        }
+        report.retried_active += 1;
+        copy_tree_file(&file, options, &mut report, &mut failures, &mut checkpoint)?;
+    }
+    // The walk is through; what it created stays, failures or not.
+    for id in created_dirs {
+        registry.forget(id);
+    }
+    // Only now, since adding entries to a directory changes its mtime.
+    for (source, target) in &timed_dirs {
+        if let Err(e) = preserve_dir_times(source, target, options) {
+            failures.push(source, e)?;
+        }
+    }
+    if let Some(mut checkpoint) = checkpoint {
+        if failures.is_empty() {
+            checkpoint.finish(options.keep_checkpoint, filesystem)?;
+        } else {
+            checkpoint.flush()?;
+        }
+    }
+    report.timing.wall = started.elapsed();
+    report.bytes_per_second = report.timing.throughput(report.bytes);
+    report.budget = options.budget.as_ref().map(ByteBudget::usage);
+    report.metadata = options.metadata_summary();
+    failures.into_result(report)
+}
+
+/// Give the directory `dst`, created for `src`, the owner the ownership
+/// map translates the source's to, if any.
+fn map_dir_owner(src: &Path, dst: &Path, options: &CopyOptions) -> FmanResult<()> {
+    let Some(owners) = &options.owners else {
+        return Ok(());
+    };
+    let meta = fs::metadata(src).map_err(|e| FmanError::io_at(src, e))?;
+    let mapped = preserve::mapped_owner(&meta, owners).map_err(|e| e.at(src))?;
+    if mapped.uid.is_some() || mapped.gid.is_some() {
+        let device = fs::metadata(dst).map_or(0, |meta| platform::device_id(&meta));
+        options
+            .metadata
+            .attempt(device, Attribute::Ownership, dst, || {
+                options.filesystem().set_owner(dst, mapped.uid, mapped.gid)
+            })?;
+    }
+    Ok(())
+}
+
+/// A file of the tree being copied, with where it goes.
+struct TreeFile {
+    source: PathBuf,
+    target: PathBuf,
+    depth: usize,
+    /// Its relative path as the checkpoint records it.
+    key: String,
+    /// The source's size and mtime, when a checkpoint is kept.
+    state: Option<FileState>,
+}
+
+/// Copy one file of a tree, tallying the outcome in `report` and in the
+/// checkpoint. Only a failure to collect a failure and an exhausted budget
+/// are returned.
+fn copy_tree_file(
+    file: &TreeFile,
+    options: &CopyOptions,
+    report: &mut CopyDirReport,
+    failures: &mut Failures,
+    checkpoint: &mut Option<Checkpoint>,
+) -> FmanResult<()> {
+    let started = Instant::now();
+    let copied = copy_entry(&file.source, &file.target, file.depth, options)
+        .map(|copied| copied.timed(started));
+    if let (Ok(copied), Some(threshold)) = (&copied, options.min_duration_report)
+        && copied.timing.wall >= threshold
+    {
+        report.slow_files.push(SlowFile {
+            path: file.source.clone(),
+            bytes: copied.bytes,
+            seconds: copied.timing.wall.as_secs_f64(),
+            bytes_per_second: copied.bytes_per_second,
+            strategy: copied.strategy,
+        });
+    }
+    match copied {
+        Ok(copied) if copied.status == CopyStatus::Skipped => report.skipped += 1,
+        Ok(copied) if copied.status == CopyStatus::Attributes => report.attributes += 1,
+        Ok(copied) if copied.status == CopyStatus::Linked => {
+            report.links += 1;
+            if let (Some(checkpoint), Some(state)) = (checkpoint, file.state) {
+                checkpoint.record(&file.key, state)?;
+            }
+        }
+        Ok(copied) => {
+            report.files += 1;
+            report.bytes += copied.bytes;
+            report.timing.data += copied.timing.data;
+            if let (Some(checkpoint), Some(state)) = (checkpoint, file.state) {
+                checkpoint.record(&file.key, state)?;
+            }
+        }
+        // Only a file that is really gone counts as vanished, not one
+        // that was never readable such as a dangling link being
+        // followed.
+        Err(e)
+            if options.ignore_vanished
+                && e.is_not_found()
+                && fs::symlink_metadata(&file.source).is_err() =>
+        {
+            report.vanished += 1;
+        }
+        // Without a way to settle a conflict the copy stops at the first
+        // one, keeping what it copied before.
+        Err(e @ (FmanError::QuotaExceeded { .. } | FmanError::AlreadyExists(_))) => {
+            if let Some(checkpoint) = checkpoint {
+                checkpoint.flush()?;
+            }
+            return Err(e);
+        }
+        Err(e) => failures.push(&file.source, e)?,
+    }
+    Ok(())
+}
+
+/// Whether a file last modified at `modified` is likely still being
+/// written: changed less than `window` before `now`, or stamped in the
+/// future.
+pub fn is_active(modified: SystemTime, now: SystemTime, window: Duration) -> bool {
+    now.duration_since(modified)
+        .map_or(true, |age| age < window)
+}
+
+/// `dst` itself if the checkpoint being resumed was written for it: the
+/// first run created it, so it would now resolve to a directory inside.
+fn resumed_destination(dst: &Path, options: &CopyOptions) -> Option<PathBuf> {
+    let header = checkpoint::read_header(options.checkpoint.as_deref()?)?;
+    (canonical_destination(dst).ok()? == header.destination).then(|| dst.to_path_buf())
+}
+
+/// The copy a checkpoint belongs to: both roots, and the options that
+/// decide what ends up where.
+fn checkpoint_header(
+    src: &Path,
+    dst: &Path,
+    options: &CopyOptions,
+) -> FmanResult<CheckpointHeader> {
+    let settings = [
+        ("symlinks", format!("{:?}", options.symlinks)),
+        ("transform", options.transform.is_some().to_string()),
+        (
+            "path-transform",
+            options.path_transform.is_some().to_string(),
+        ),
+    ];
+    Ok(CheckpointHeader {
+        source: src.canonicalize()?,
+        destination: canonical_destination(dst)?,
+        options: settings
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect(),
+    })
+}
+
+/// `/`-separated form of a path relative to the copy root.
+fn relative_key(relative: &Path) -> String {
+    relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Where the entry at `relative` goes below the destination root, after
+/// the path transform if there is one. `claimed` remembers transformed
+/// paths so that a second source mapped to the same one is an error.
+fn destination_of(
+    relative: &Path,
+    source: &Path,
+    claimed: &mut HashMap<PathBuf, PathBuf>,
+    options: &CopyOptions,
+) -> FmanResult<PathBuf> {
+    let Some(transform) = &options.path_transform else {
+        return Ok(relative.to_path_buf());
+    };
+    let transformed = transform(relative)?;
+    let stays_below = transformed.components().next().is_some()
+        && transformed
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)));
+    if !stays_below {
+        return Err(FmanError::InvalidInput(format!(
+            "path transform mapped {} to {}, which is not a relative path below the destination",
+            relative.display(),
+            transformed.display()
+        )));
+    }
+    if let Some(first) = claimed.get(&transformed) {
+        return Err(FmanError::AlreadyExists(format!(
+            "{} and {} both map to {}",
+            first.display(),
+            source.display(),
+            transformed.display()
+        )));
+    }
+    claimed.insert(transformed.clone(), source.to_path_buf());
+    Ok(transformed)
+}
+
+/// [`PathTransform`] making names valid on Windows: `<>:"|?*` and control
+/// characters become `_`, and trailing dots and spaces are dropped.
+pub fn sanitize_windows_names(path: &Path) -> FmanResult<PathBuf> {
+    map_names(path, |name| {
+        let replaced: String = name
+            .chars()
+            .map(|c| {
+                if "<>:\"|?*".contains(c) || c.is_control() {
+                    '_'
+                } else {
+                    c
+                }
+            })
+            .collect();
+        let trimmed = replaced.trim_end_matches(['.', ' ']);
+        if trimmed.is_empty() {
+            "_".to_string()
+        } else {
+            trimmed.to_string()
+        }
+    })
+}
+
+/// [`PathTransform`] lowercasing every name, for case-insensitive targets.
+pub fn lowercase_names(path: &Path) -> FmanResult<PathBuf> {
+    map_names(path, str::to_lowercase)
+}
+
+/// Apply `rename` to each component of `path`.
+fn map_names(path: &Path, rename: impl Fn(&str) -> String) -> FmanResult<PathBuf> {
+    path.components()
+        .map(|component| {
+            let name = component.as_os_str();
+            name.to_str().map(&rename).ok_or_else(|| {
+                FmanError::InvalidInput(format!(
+                    "cannot rename {}: not valid UTF-8",
+                    name.to_string_lossy()
+                ))
+            })
+        })
+        .collect()
+}
+
+/// `dst` with its nearest existing ancestor canonicalized, for comparison
+/// with a canonical source path.
+fn canonical_destination(dst: &Path) -> FmanResult<PathBuf> {
+    let absolute = std::path::absolute(dst)?;
+    for ancestor in absolute.ancestors() {
+        if let Ok(canonical) = ancestor.canonicalize() {
+            return Ok(match absolute.strip_prefix(ancestor) {
+                Ok(rest) if !rest.as_os_str().is_empty() => canonical.join(rest),
+                _ => canonical,
+            });
+        }
+    }
+    Ok(absolute)
+}
+
+/// Give the existing `dst` the `attributes` of the entry met at `depth`
+/// at `src`, read through a symlink the policy follows.
+fn copy_attributes(
+    src: &Path,
+    dst: &Path,
+    depth: usize,
+    attributes: &[Attribute],
+    options: &CopyOptions,
+) -> FmanResult<CopyReport> {
+    ensure_exists(src)?;
+    let mut report = CopyReport::new(src.to_path_buf(), dst.to_path_buf());
+    let Ok(dst_meta) = fs::symlink_metadata(dst) else {
+        if options.ignore_missing {
+            report.status = CopyStatus::Skipped;
+            return Ok(report);
+        }
+        return Err(FmanError::missing_path(dst));
+    };
+    let reference = if options.symlinks.follows(depth) && fs::symlink_metadata(src)?.is_symlink() {
+        src.canonicalize()?
+    } else {
+        src.to_path_buf()
+    };
+    if fs::symlink_metadata(&reference)?.is_dir() != dst_meta.is_dir() {
+        return Err(FmanError::InvalidInput(format!(
+            "cannot copy the attributes of {} onto {}: one is a directory and the other is not",
+            src.display(),
+            dst.display()
+        )));
+    }
+    let owners = options.owners.clone().unwrap_or_default();
+    preserve::apply_mapped(
+        &reference,
+        dst,
+        attributes,
+        &owners,
+        &options.metadata,
+        options.filesystem(),
+    )?;
+    report.status = CopyStatus::Attributes;
+    Ok(report)
+}
+
+/// Copy one file met at `depth` to the resolved destination `dst`.
+fn copy_entry(
+    src: &Path,
+    dst: &Path,
+    depth: usize,
+    options: &CopyOptions,
+) -> FmanResult<CopyReport> {
+    if let Some(attributes) = &options.attributes_only {
+        return copy_attributes(src, dst, depth, attributes, options);
+    }
+    if !options.symlinks.follows(depth)
+        && let Ok(meta) = fs::symlink_metadata(src)
+        && meta.is_symlink()
+    {
+        let Some(dst) = settle_conflict(dst, options)? else {
+            return Ok(CopyReport::skipped(src, dst));
+        };
+        let dst = dst.as_ref();
+        return replace_existing(src, dst, options, |options| {
+            copy_symlink(src, &meta, dst, options)
+        });
+    }
+    // The link is there, only what it leads to is not.
+    if let Ok(target) = fs::read_link(src)
+        && !src.exists()
+    {
+        return Err(FmanError::not_found(format!(
+            "{} (a dangling symlink to {}; --no-dereference copies the link itself)",
+            src.display(),
+            target.display()
+        )));
+    }
+    ensure_exists(src)?;
+    // A pipe is read when named on the command line; in a tree it could
+    // block forever.
+    let pipe = depth == 0 && platform::is_fifo(fs::metadata(src)?.file_type());
+    if !pipe {
+        ensure_is_file(src)?;
+    }
+    let file = File::open(src).map_err(|e| FmanError::io_at(src, e))?;
+
+    ensure_parents_are_dirs(dst)?;
+    ensure_not_same_file(src, dst).map_err(onto_itself)?;
+    let Some(dst) = settle_conflict(dst, options)? else {
+        return Ok(CopyReport::skipped(src, dst));
+    };
+    let dst = dst.as_ref();
+    let mut options = streaming(&file, options);
+    if options.update
+        && let Ok(dst_meta) = fs::metadata(dst)
+    {
+        let (ours, theirs) = (file.metadata()?.modified()?, dst_meta.modified()?);
+        if compare_modified(ours, theirs, options.modify_window) != Ordering::Greater {
+            let mut report = CopyReport::new(src.to_path_buf(), dst.to_path_buf());
+            report.status = CopyStatus::Skipped;
+            return Ok(report);
+        }
+        options = Cow::Owned(options.into_owned().force(true));
+    }
+    replace_existing(src, dst, &options, |options| match options.link_mode {
+        LinkMode::Copy => copy_to_path(&file, Some(src), dst.to_path_buf(), options),
+        mode => link_file(src, dst, mode, options),
+    })
+}
+
+/// Where a file goes under [`CopyOptions::on_conflict`] when something is
+/// already at `dst`: nowhere (`None`) to skip it, a free name beside `dst`
+/// to keep both, or `dst` itself, to fail or replace it there.
+fn settle_conflict<'a>(dst: &'a Path, options: &CopyOptions) -> FmanResult<Option<Cow<'a, Path>>> {
+    if !options.filesystem().exists(dst) {
+        return Ok(Some(Cow::Borrowed(dst)));
+    }
+    match options.on_conflict {
+        OverwritePolicy::Skip => Ok(None),
+        OverwritePolicy::RenameNew => Ok(Some(Cow::Owned(options.naming.alternative_path(dst)?))),
+        OverwritePolicy::Error | OverwritePolicy::Overwrite => Ok(Some(Cow::Borrowed(dst))),
+    }
+}
+
+/// Run `copy` of `src` into `dst`, settling first what happens to an
+/// existing `dst` file: [`CopyOptions::confirm_overwrite`] is asked, and
+/// then a [`CopyOptions::backup`] is made, which is moved back if `copy`
+/// fails. Once replacing is settled `copy` gets options that let it write
+/// where `dst` is.
+fn replace_existing(
+    src: &Path,
+    dst: &Path,
+    options: &CopyOptions,
+    copy: impl FnOnce(&CopyOptions) -> FmanResult<CopyReport>,
+) -> FmanResult<CopyReport> {
+    if !fs::symlink_metadata(dst).is_ok_and(|meta| !meta.is_dir()) {
+        return copy(options);
+    }
+    if !options.overwrites()
+        && let Some(confirm) = &options.confirm_overwrite
+    {
+        if !confirm(dst) {
+            let mut report = CopyReport::new(src.to_path_buf(), dst.to_path_buf());
+            report.status = CopyStatus::Skipped;
+            return Ok(report);
+        }
+        if !options.backup {
+            return copy(&options.clone().force(true));
+        }
+    }
+    if !options.backup {
+        return copy(options);
+    }
+    let filesystem = options.filesystem();
+    let suffix = options
+        .backup_suffix
+        .as_deref()
+        .unwrap_or(DEFAULT_BACKUP_SUFFIX);
+    let backup = options.naming.backup_path(dst, suffix)?;
+    filesystem.rename(dst, &backup)?;
+    let registry = options.cleanup.clone().unwrap_or_default();
+    let id = registry.push(Cleanup::Restore {
+        from: backup.clone(),
+        to: dst.to_path_buf(),
+    });
+    // Nothing is left to overwrite, except in a dry run that only
+    // recorded the move.
+    match copy(&options.clone().force(true)) {
+        Ok(mut report) => {
+            registry.forget(id);
+            report.backup = Some(backup);
+            Ok(report)
+        }
+        Err(e) if options.cleanup.is_none() => {
+            registry.unwind(filesystem, options.error_log.as_deref(), e)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// `options`, with [`CopyOptions::stream`] set if `src` cannot be trusted
+/// to report its size.
+fn streaming<'a>(src: &File, options: &'a CopyOptions) -> Cow<'a, CopyOptions> {
+    if options.stream || !stat_size_unreliable(src) {
+        return Cow::Borrowed(options);
+    }
+    Cow::Owned(options.clone().stream(true))
+}
+
+/// Whether the size `src` reports says nothing about the data it holds:
+/// it is not a regular file, or it is on a pseudo filesystem.
+fn stat_size_unreliable(src: &File) -> bool {
+    src.metadata().is_ok_and(|meta| !meta.is_file()) || platform::stat_size_unreliable(src)
+}
+
+/// Make `dst` a link to the regular file `src` instead of a copy.
+fn link_file(
+    src: &Path,
+    dst: &Path,
+    mode: LinkMode,
+    options: &CopyOptions,
+) -> FmanResult<CopyReport> {
+    let filesystem = options.filesystem();
+    let mut report = CopyReport::new(src.to_path_buf(), dst.to_path_buf());
+    report.status = CopyStatus::Linked;
+    clear_destination(&fs::symlink_metadata(src)?, dst, options)?;
+    if mode == LinkMode::Hardlink {
+        // A hard link to a symlink would link the symlink itself.
+        let original = if fs::symlink_metadata(src)?.is_symlink() {
+            src.canonicalize()?
+        } else {
+            src.to_path_buf()
+        };
+        return match filesystem.hard_link(&original, dst) {
+            Ok(()) => Ok(report),
+            Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+                Err(FmanError::CrossDevice(format!(
+                    "cannot hard-link {} to {}: they are on different filesystems",
+                    src.display(),
+                    dst.display()
+                )))
+            }
+            Err(e) => Err(e.into()),
+        };
+    }
+
+    // Resolve the source's directory but not the source itself, so that
+    // a followed symlink stays the link's target.
+    let src_dir = src.parent().filter(|p| !p.as_os_str().is_empty());
+    let src_name = src.file_name().unwrap_or(src.as_os_str());
+    let absolute = platform::simplify_verbatim(src_dir.unwrap_or(Path::new(".")).canonicalize()?)
+        .join(src_name);
+    let target = match mode {
+        LinkMode::Symlink { relative: true } => {
+            let dst_dir = dst.parent().filter(|p| !p.as_os_str().is_empty());
+            relative_path(
+                &platform::simplify_verbatim(dst_dir.unwrap_or(Path::new(".")).canonicalize()?),
+                &absolute,
+            )
+        }
+        _ => absolute,
+    };
+    filesystem.symlink(&target, dst)?;
+    report.link_target = Some(target);
+    Ok(report)
+}
+
+/// Path from the directory `from` to `to`, both absolute and canonical.
+pub(crate) fn relative_path(from: &Path, to: &Path) -> PathBuf {
+    let (from, to): (Vec<_>, Vec<_>) = (from.components().collect(), to.components().collect());
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+    let mut path: PathBuf = from[common..]
+        .iter()
+        .map(|_| Component::ParentDir)
+        .collect();
+    path.extend(&to[common..]);
+    path
+}
+
+/// A copy onto its own source is invalid input; moves and links report
+/// the same check as [`FmanError::SameFile`].
+fn onto_itself(err: FmanError) -> FmanError {
+    match err {
+        FmanError::SameFile(dst) => FmanError::same_file(Path::new(&dst)),
+        err => err,
+    }
+}
+
+/// Make way for a link at `dst`: fail if something is there unless
+/// forcing, and then remove it unless it is a directory.
+fn clear_destination(src_meta: &fs::Metadata, dst: &Path, options: &CopyOptions) -> FmanResult<()> {
+    let Ok(dst_meta) = fs::symlink_metadata(dst) else {
+        return Ok(());
+    };
+    ensure_not_same_inode(src_meta, &dst_meta, &dst.display().to_string()).map_err(onto_itself)?;
+    if !options.overwrites() {
+        return Err(FmanError::AlreadyExists(dst.display().to_string()));
+    }
+    if dst_meta.is_dir() {
+        return Err(FmanError::InvalidInput(format!(
+            "cannot replace directory {} with a link",
+            dst.display()
+        )));
+    }
+    options.filesystem().remove_file(dst)?;
+    Ok(())
+}
+
+fn copy_symlink(
+    src: &Path,
+    src_meta: &fs::Metadata,
+    dst: &Path,
+    options: &CopyOptions,
+) -> FmanResult<CopyReport> {
+    let target = fs::read_link(src)?;
+    ensure_parents_are_dirs(dst)?;
+    clear_destination(src_meta, dst, options)?;
+    options.filesystem().symlink(&target, dst)?;
+
+    let mut report = CopyReport::new(src.to_path_buf(), dst.to_path_buf());
+    report.link_target = Some(target);
+    Ok(report)
+}
+
+/// Copy the contents of an already open file to `dst`.
+///
+/// The whole file is copied from offset 0 whatever the handle's current
+/// position, which is left at the end of the data, and the destination
+/// gets the handle's permissions. A handle has no file name, so `dst` must
+/// name the file rather than a directory to put it in, the report's
+/// `source` is empty, and options with a [`Transform`] are rejected.
+pub fn copy_from_file(
+    src: &File,
+    dst: impl AsRef<Path>,
+    options: &CopyOptions,
+) -> FmanResult<CopyReport> {
+    let dst = dst.as_ref();
+    if options.transform.is_some() {
+        return Err(FmanError::InvalidInput(
+            "content transforms need a source path".to_string(),
+        ));
+    }
+    if dst.is_dir() {
+        return Err(FmanError::InvalidInput(format!(
+            "{} is a directory; copying from a handle needs a file destination",
+            dst.display()
+        )));
+    }
+    let started = Instant::now();
+    ensure_parents_are_dirs(dst)?;
+    if let Ok(dst_meta) = fs::metadata(dst) {
+        ensure_not_same_inode(&src.metadata()?, &dst_meta, &dst.display().to_string())
+            .map_err(onto_itself)?;
+    }
+    let options = streaming(src, options);
+    Ok(copy_to_path(src, None, dst.to_path_buf(), &options)?.timed(started))
+}
+
+/// Copy the file at `src` into an already open, writable file.
+///
+/// `dst` is truncated and written from offset 0 whatever its current
+/// position, and gets the source's permissions. The report's `destination`
+/// is empty. A racing-write policy that discards the copy truncates the
+/// handle instead of removing anything.
+pub fn copy_to_file(
+    src: impl AsRef<Path>,
+    dst: &mut File,
+    options: &CopyOptions,
+) -> FmanResult<CopyReport> {
+    let started = Instant::now();
+    let src = src.as_ref();
+    ensure_exists(src)?;
+    ensure_is_file(src)?;
+    let file = File::open(src)?;
+    ensure_not_same_inode(&file.metadata()?, &dst.metadata()?, "destination handle")
+        .map_err(onto_itself)?;
+
+    let mut report = CopyReport::new(src.to_path_buf(), PathBuf::new());
+    let options = streaming(&file, options);
+    let charged = charge_upfront(&file, &options)?;
+    copy_handles(
+        &file,
+        Some(src),
+        dst,
+        &mut report,
+        &options,
+        charged,
+        &|| dst.set_len(0),
+    )?;
+    Ok(report.timed(started))
+}
+
+/// Create (or, with `force`, replace) the file at `dst` and copy `src`
+/// into it, by way of a temporary file if the copy is
+/// [`atomic`](CopyOptions::atomic).
+fn copy_to_path(
+    src: &File,
+    src_path: Option<&Path>,
+    dst: PathBuf,
+    options: &CopyOptions,
+) -> FmanResult<CopyReport> {
+    if options.atomic {
+        copy_atomically(src, src_path, dst, options)
+    } else {
+        write_to_path(src, src_path, dst, options, false)
+    }
+}
+
+/// Copy `src` to a new temporary file beside `dst`, flush it, and
+/// [`publish`] it as `dst`. The report names `dst`; a temporary file left
+/// by a failure is removed.
+fn copy_atomically(
+    src: &File,
+    src_path: Option<&Path>,
+    dst: PathBuf,
+    options: &CopyOptions,
+) -> FmanResult<CopyReport> {
+    if options.immutable.is_some() {
+        return Err(FmanError::InvalidInput(
+            "an atomic copy cannot be made immutable: the attribute would stop the rename"
+                .to_string(),
+        ));
+    }
+    if !options.overwrites() {
+        ensure_not_exists(&dst)?;
+    }
+    let dir = match dst.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let temp = options.naming.scratch_path(dir)?;
+    let mut report = write_to_path(
+        src,
+        src_path,
+        temp.clone(),
+        &options.clone().force(false),
+        true,
+    )?;
+    report.destination = dst;
+    if report.status == CopyStatus::Skipped {
+        return Ok(report);
+    }
+    let filesystem = options.filesystem();
+    let registry = options.cleanup.clone().unwrap_or_default();
+    let id = registry.push(Cleanup::RemoveFile(temp.clone()));
+    match publish(filesystem, &temp, &report.destination, options.overwrites()) {
+        Ok(()) => {
+            registry.forget(id);
+            Ok(report)
+        }
+        Err(e) if options.cleanup.is_none() => {
+            registry.unwind(filesystem, options.error_log.as_deref(), e)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Give the finished temporary file `temp` the name `dst`. With `replace`
+/// one rename swaps out whatever is at `dst`. Otherwise `temp` is hard
+/// linked as `dst`, which fails rather than clobbers a file that got there
+/// since it was checked, and then removed; where the filesystem has no
+/// hard links the check is repeated just before a plain rename.
+fn publish(filesystem: &dyn Fs, temp: &Path, dst: &Path, replace: bool) -> FmanResult<()> {
+    if replace {
+        return filesystem
+            .rename(temp, dst)
+            .map_err(|e| FmanError::io_at(dst, e));
+    }
+    match filesystem.hard_link(temp, dst) {
+        Ok(()) => filesystem
+            .remove_file(temp)
+            .map_err(|e| FmanError::io_at(temp, e)),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+            Err(FmanError::AlreadyExists(dst.display().to_string()))
+        }
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::Unsupported | io::ErrorKind::PermissionDenied
+            ) =>
+        {
+            ensure_not_exists(dst)?;
+            filesystem
+                .rename(temp, dst)
+                .map_err(|e| FmanError::io_at(dst, e))
+        }
+        Err(e) => Err(FmanError::io_at(dst, e)),
+    }
+}
+
+/// Create (or, with `force`, replace) the file at `dst` and copy `src`
+/// into it, flushing it to disk afterwards if `sync`.
+fn write_to_path(
+    src: &File,
+    src_path: Option<&Path>,
+    dst: PathBuf,
+    options: &CopyOptions,
+    sync: bool,
+) -> FmanResult<CopyReport> {
+    let filesystem = options.filesystem();
+    if !options.overwrites() {
+        ensure_not_exists(&dst)?;
+    } else if fs::symlink_metadata(&dst).is_ok_and(|m| m.file_type().is_symlink()) {
+        // Replace the link itself rather than writing through it.
+        filesystem
+            .remove_file(&dst)
+            .map_err(|e| FmanError::io_at(&dst, e))?;
+    }
+    let charged = charge_upfront(src, options)?;
+    let replaces = fs::symlink_metadata(&dst).is_ok();
+    let created = match src_path {
+        Some(src_path) => filesystem.create_copy(src_path, &dst, !options.overwrites()),
+        None => filesystem.create_file(&dst, !options.overwrites()),
+    };
+    let created = match created {
+        Ok(created) => created,
+        Err(e) => {
+            refund(options, charged);
+            if e.kind() == io::ErrorKind::AlreadyExists {
+                return Err(FmanError::AlreadyExists(dst.display().to_string()));
+            }
+            return Err(FmanError::io_at(&dst, e));
+        }
+    };
+
+    let source = src_path.map(Path::to_path_buf).unwrap_or_default();
+    let mut report = CopyReport::new(source, dst);
+    let Some(file) = created else {
+        // Only recorded: report what the copy would have done.
+        report.bytes = src.metadata()?.len();
+        report.strategy = options.strategy.select(report.bytes);
+        return Ok(report);
+    };
+    // A file that was there before is left alone, even half rewritten.
+    let registry = options.cleanup.clone().unwrap_or_default();
+    let destination = report.destination.clone();
+    let registered = (!replaces).then(|| registry.push(Cleanup::RemoveFile(destination.clone())));
+    let copied = copy_handles(src, src_path, &file, &mut report, options, charged, &|| {
+        filesystem.remove_file(&destination)
+    })
+    .and_then(|()| {
+        if sync && report.status != CopyStatus::Skipped {
+            file.sync_all()
+                .map_err(|e| FmanError::io_at(&destination, e))?;
+        }
+        Ok(())
+    });
+    match copied {
+        Ok(()) => {
+            if let Some(id) = registered {
+                registry.forget(id);
+            }
+            Ok(report)
+        }
+        Err(e) if options.cleanup.is_none() => {
+            drop(file);
+            registry.unwind(filesystem, options.error_log.as_deref(), e)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// The copy engine proper: move the data between the two handles, apply
+/// the racing-write policy and run the post-copy pipeline. `discard` gets
+/// rid of the destination when the policy throws the copy away, or when
+/// the budget stops it part way; `charged` is what the budget was already
+/// charged for this file.
+fn copy_handles(
+    src: &File,
+    src_path: Option<&Path>,
+    dst: &File,
+    report: &mut CopyReport,
+    options: &CopyOptions,
+    mut charged: u64,
+    discard: &dyn Fn() -> io::Result<()>,
+) -> FmanResult<()> {
+    // Reading the source can move its atime, so it is taken first.
+    let accessed = if options.preserve {
+        Some(src.metadata()?.accessed()?)
+    } else {
+        None
+    };
+    // A stream's size and mtime say nothing about a racing writer.
+    let transferred = match options.racing.filter(|_| !options.stream) {
+        None => {
+            let started = Instant::now();
+            let result = transfer_retrying(src, src_path, dst, options, &mut charged);
+            report.timing.data += started.elapsed();
+            result.map(|(bytes, strategy)| {
+                (report.bytes, report.strategy) = (bytes, strategy);
+                true
+            })
+        }
+        Some(_) => transfer_detecting_races(src, src_path, dst, report, options, &mut charged),
+    };
+    let clean = match transferred {
+        Err(e @ FmanError::QuotaExceeded { .. }) => {
+            discard()?;
+            refund(options, charged);
+            return Err(e);
+        }
+        result => result?,
+    };
+    report.streamed = options.stream;
+    if let Some(policy) = options.racing
+        && !clean
+    {
+        report.changed_during_copy = true;
+        match policy {
+            RacingPolicy::Warn => {}
+            RacingPolicy::Error => {
+                discard()?;
+                refund(options, charged);
+                return Err(FmanError::ChangedDuringCopy(
+                    report.source.display().to_string(),
+                ));
+            }
+            RacingPolicy::Skip => {
+                discard()?;
+                refund(options, charged);
+                report.bytes = 0;
+                report.status = CopyStatus::Skipped;
+                return Ok(());
+            }
+        }
+    }
+    refund(options, charged.saturating_sub(report.bytes));
+
+    match finish(src, dst, report, options, accessed) {
+        Err(e @ FmanError::VerificationFailed(_)) => {
+            discard()?;
+            refund(options, report.bytes);
+            Err(e)
+        }
+        result => result,
+    }
+}
+
+/// Transfer with before/after source stamps, retrying up to
+/// [`RACING_RETRIES`] times. Returns whether a clean copy was made.
+fn transfer_detecting_races(
+    src: &File,
+    src_path: Option<&Path>,
+    dst: &File,
+    report: &mut CopyReport,
+    options: &CopyOptions,
+    charged: &mut u64,
+) -> FmanResult<bool> {
+    for _ in 0..=RACING_RETRIES {
+        let before = SourceStamp::read(src)?;
+        let started = Instant::now();
+        (report.bytes, report.strategy) = transfer_retrying(src, src_path, dst, options, charged)?;
+        report.timing.data += started.elapsed();
+        if SourceStamp::read(src)? == before {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Post-copy pipeline, run once the data is in place.
+///
+/// Stages run in a fixed order: attribute changes first, then checks of the
+/// written data, and write protection (read-only, then immutable) last,
+/// because nothing (including setting timestamps) can modify an immutable
+/// file. Times are set only
+/// when `accessed`, the source's atime from before it was read, is given.
+fn finish(
+    src: &File,
+    dst: &File,
+    report: &mut CopyReport,
+    options: &CopyOptions,
+    accessed: Option<SystemTime>,
+) -> FmanResult<()> {
+    let src_meta = src.metadata()?;
+    let device = platform::device_id(&dst.metadata()?);
+    let attempt = |attribute, apply: &dyn Fn() -> io::Result<()>| {
+        options
+            .metadata
+            .attempt(device, attribute, &report.destination, apply)
+    };
+
+    if let Some(owners) = &options.owners {
+        let mapped = preserve::mapped_owner(&src_meta, owners).map_err(|e| e.at(&report.source))?;
+        if mapped.uid.is_some() || mapped.gid.is_some() {
+            attempt(Attribute::Ownership, &|| {
+                options.filesystem().set_file_owner(
+                    &report.destination,
+                    dst,
+                    mapped.uid,
+                    mapped.gid,
+                )
+            })?;
+        }
+    }
+
+    let permissions = src_meta.permissions();
+    if dst.metadata()?.permissions() != permissions {
+        attempt(Attribute::Mode, &|| {
+            options
+                .filesystem()
+                .set_file_permissions(&report.destination, dst, permissions.clone())
+        })?;
+    }
+
+    if let Some(accessed) = accessed {
+        let modified = Some(src_meta.modified()?);
+        attempt(Attribute::Times, &|| {
+            options
+                .filesystem()
+                .set_file_times(&report.destination, dst, Some(accessed), modified)
+                .map_err(|e| times_error(&report.destination, e))
+        })?;
+    }
+
+    if options.verify
+        && !options.stream
+        && options.transform.is_none()
+        && !report.destination.as_os_str().is_empty()
+    {
+        verify_written(src, report)?;
+        report.verified = true;
+    }
+
+    if options.read_only {
+        let mut permissions = dst.metadata()?.permissions();
+        if !permissions.readonly() {
+            permissions.set_readonly(true);
+            options
+                .filesystem()
+                .set_file_permissions(&report.destination, dst, permissions)
+                .map_err(|e| FmanError::io_at(&report.destination, e))?;
+        }
+    }
+
+    if let Some(mode) = options.immutable {
+        match platform::set_immutable(dst) {
+            Ok(()) => report.immutable = true,
+            Err(_) if mode == Immutability::BestEffort => {}
+            Err(e) => return Err(immutable_error(&report.destination, e)),
+        }
+    }
+    Ok(())
+}
+
+/// The error for failing to make `path` immutable: a permission denial
+/// names the capability it lacks.
+fn immutable_error(path: &Path, e: io::Error) -> FmanError {
+    if e.kind() != io::ErrorKind::PermissionDenied {
+        return FmanError::Io(e);
+    }
+    FmanError::PermissionDenied(format!(
+        "{} (making it immutable needs CAP_LINUX_IMMUTABLE; \
+         use --immutable=best-effort to continue without it)",
+        path.display()
+    ))
+}
+
+/// Hash `src` again from the start, and the file written at the report's
+/// destination, failing with `VerificationFailed` if they differ.
+fn verify_written(src: &File, report: &CopyReport) -> FmanResult<()> {
+    (&*src).rewind()?;
+    let want = hash_reader(src, HashAlgorithm::Sha256)?;
+    let have = hash_reader(File::open(&report.destination)?, HashAlgorithm::Sha256)?;
+    if want == have {
+        return Ok(());
+    }
+    Err(FmanError::VerificationFailed(format!(
+        "copy of {} to {} has sha256 {have}, expected {want}; removed the copy",
+        report.source.display(),
+        report.destination.display()
+    )))
+}
+
+/// Give the directory `dst`, created for `src`, the source's times.
+fn preserve_dir_times(src: &Path, dst: &Path, options: &CopyOptions) -> FmanResult<()> {
+    let src_meta = fs::metadata(src)?;
+    let device = fs::metadata(dst).map_or(0, |meta| platform::device_id(&meta));
+    let (accessed, modified) = (src_meta.accessed()?, src_meta.modified()?);
+    options
+        .metadata
+        .attempt(device, Attribute::Times, dst, || {
+            options
+                .filesystem()
+                .set_times(dst, Some(accessed), Some(modified))
+                .map_err(|e| times_error(dst, e))
+        })?;
+    Ok(())
+}
+
+fn times_error(path: &Path, e: io::Error) -> io::Error {
+    io::Error::new(
+        e.kind(),
+        format!("cannot set the times of {}: {e}", path.display()),
+    )
+}
+
+/// [`transfer`], started over while it fails with a transient error and
+/// [`CopyOptions::retries`] are left.
+fn transfer_retrying(
+    src: &File,
+    src_path: Option<&Path>,
+    dst: &File,
+    options: &CopyOptions,
+    charged: &mut u64,
+) -> FmanResult<(u64, CopyStrategy)> {
+    let mut retried = 0;
+    loop {
+        match transfer(src, src_path, dst, options, charged) {
+            Err(FmanError::Io(e))
+                if retried < options.retries
+                    && is_transient(&e)
+                    && (&*src).stream_position().is_ok() =>
+            {
+                retried += 1;
+                #[cfg(feature = "tracing")]
+                tracing::warn!(error = %e, retry = retried, of = options.retries, "retrying copy");
+                #[cfg(not(feature = "tracing"))]
+                let _ = e;
+                std::thread::sleep(options.retry_delay);
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Errors [`CopyOptions::retries`] tries again after.
+fn is_transient(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+/// Write the whole of `src` over `dst`, starting both from offset 0, and
+/// give `dst` the source's permissions. Returns the number of bytes and
+/// the strategy used.
+fn transfer(
+    src: &File,
+    src_path: Option<&Path>,
+    dst: &File,
+    options: &CopyOptions,
+    charged: &mut u64,
+) -> FmanResult<(u64, CopyStrategy)> {
+    match (&*src).rewind() {
+        // A pipe is read once, from where it is.
+        Err(e) if e.kind() == io::ErrorKind::NotSeekable => {}
+        rewound => rewound?,
+    }
+    dst.set_len(0)?;
+    (&*dst).rewind()?;
+
+    if let (Some(transform), Some(path)) = (&options.transform, src_path)
+        && let Some(contents) = transform(path)?
+    {
+        if let Some(budget) = &options.budget {
+            charge_growth(budget, charged, contents.len() as u64)?;
+        }
+        (&*dst).write_all(&contents)?;
+        if let Some(progress) = &options.progress {
+            progress(contents.len() as u64, contents.len() as u64);
+        }
+        return Ok((contents.len() as u64, CopyStrategy::Buffered));
+    }
+
+    if options.reflink != Reflink::Never
+        && !options.stream
+        && let Some(bytes) = clone_blocks(src, src_path, dst, options)?
+    {
+        if let Some(budget) = &options.budget {
+            charge_growth(budget, charged, bytes)?;
+        }
+        if let Some(progress) = &options.progress {
+            progress(bytes, bytes);
+        }
+        return Ok((bytes, CopyStrategy::Reflink));
+    }
+
+    if let Some(progress) = &options.progress {
+        let total = if options.stream {
+            0
+        } else {
+            src.metadata()?.len()
+        };
+        let mut writer = ProgressWriter::new(dst, total, progress.as_ref());
+        let bytes = match &options.budget {
+            Some(budget) => {
+                let buf_size = options.strategy.buffer_size.unwrap_or(STREAM_BUFFER_SIZE);
+                stream_within_budget(src, &mut writer, buf_size, budget, charged)?
+            }
+            None => backend::copy_contents(src, &mut writer, options.strategy.buffer_size())?,
+        };
+        writer.finish();
+        return Ok((bytes, CopyStrategy::Buffered));
+    }
+
+    let strategy = if options.stream && !options.strategy.sparse {
+        CopyStrategy::Buffered
+    } else {
+        options.strategy.select(src.metadata()?.len())
+    };
+    let bytes = match &options.budget {
+        Some(budget) if options.stream => {
+            let buf_size = options.strategy.buffer_size.unwrap_or(STREAM_BUFFER_SIZE);
+            stream_within_budget(src, dst, buf_size, budget, charged)?
+        }
+        Some(budget) => transfer_within_budget(
+            strategy,
+            options.strategy.buffer_size(),
+            src,
+            dst,
+            budget,
+            charged,
+        )?,
+        None => backend::copy_with(strategy, options.strategy.buffer_size(), src, dst)?,
+    };
+    Ok((bytes, strategy))
+}
+
+/// Make `dst` share the data blocks of `src` and return its new length,
+/// or `None` if the filesystem cannot and [`Reflink::Auto`] lets the data
+/// be copied instead.
+fn clone_blocks(
+    src: &File,
+    src_path: Option<&Path>,
+    dst: &File,
+    options: &CopyOptions,
+) -> FmanResult<Option<u64>> {
+    match platform::reflink(src, dst) {
+        Ok(()) => Ok(Some(dst.metadata()?.len())),
+        Err(e) if options.reflink == Reflink::Auto && platform::reflink_unsupported(&e) => Ok(None),
+        Err(e) => Err(io::Error::new(
+            e.kind(),
+            match src_path {
+                Some(path) => format!(
+                    "cannot reflink {}: {e} (--reflink=auto copies the data instead)",
+                    path.display()
+                ),
+                None => format!("cannot reflink: {e} (--reflink=auto copies the data instead)"),
+            },
+        )
+        .into()),
+    }
+}
+
+/// Copy no more than the `charged` bytes already paid for, then keep going
+/// in steps charged as they are taken while the source has grown past
+/// that.
+fn transfer_within_budget(
+    strategy: CopyStrategy,
+    buffer_size: usize,
+    src: &File,
+    dst: &File,
+    budget: &ByteBudget,
+    charged: &mut u64,
+) -> FmanResult<u64> {
+    let mut bytes = backend::copy_at_most(strategy, buffer_size, src, dst, *charged)?;
+    loop {
+        let len = src.metadata()?.len();
+        if bytes < *charged || len <= bytes {
+            return Ok(bytes);
+        }
+        charge_growth(budget, charged, bytes + (len - bytes).min(GROWTH_STEP))?;
+        let step = match strategy {
+            CopyStrategy::Sparse => CopyStrategy::Sparse,
+            _ => CopyStrategy::Buffered,
+        };
+        bytes += backend::copy_at_most(step, buffer_size, src, dst, *charged - bytes)?;
+    }
+}
+
+/// Copy a source of unknown size, charging each buffer before it is
+/// written.
+fn stream_within_budget(
+    mut src: &File,
+    mut dst: impl Write,
+    buf_size: usize,
+    budget: &ByteBudget,
+    charged: &mut u64,
+) -> FmanResult<u64> {
+    let mut buf = vec![0u8; buf_size.max(1)];
+    let mut bytes = 0;
+    loop {
+        let n = match src.read(&mut buf) {
+            Ok(0) => return Ok(bytes),
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        charge_growth(budget, charged, bytes + n as u64)?;
+        dst.write_all(&buf[..n])?;
+        bytes += n as u64;
+    }
+}
+
+const STREAM_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Bytes between two calls of a [`CopyOptions::progress`] callback.
+pub const PROGRESS_STEP: u64 = 1024 * 1024;
+
+/// Writes to a file, telling a [`Progress`] callback how far it got.
+struct ProgressWriter<'a> {
+    file: &'a File,
+    progress: &'a Progress,
+    total: u64,
+    written: u64,
+    reported: Option<u64>,
+}
+
+impl<'a> ProgressWriter<'a> {
+    fn new(file: &'a File, total: u64, progress: &'a Progress) -> Self {
+        Self {
+            file,
+            progress,
+            total,
+            written: 0,
+            reported: None,
+        }
+    }
+
+    /// Report the final count, unless that was the last one reported.
+    fn finish(self) {
+        if self.reported != Some(self.written) {
+            (self.progress)(self.written, self.total);
+        }
+    }
+}
+
+impl Write for ProgressWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.file.write(buf)?;
+        self.written += n as u64;
+        if self.written - self.reported.unwrap_or(0) >= PROGRESS_STEP {
+            (self.progress)(self.written, self.total);
+            self.reported = Some(self.written);
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Most a growing source is charged for at a time.
+const GROWTH_STEP: u64 = 1024 * 1024;
+
+/// Charge a file's size to the budget, if there is one, before its copy
+/// starts. Returns the amount charged.
+fn charge_upfront(src: &File, options: &CopyOptions) -> FmanResult<u64> {
+    let Some(budget) = options.budget.as_ref().filter(|_| !options.stream) else {
+        return Ok(0);
+    };
+    let len = src.metadata()?.len();
+    budget.charge(len)?;
+    Ok(len)
+}
+
+/// Raise a file's charge to `total` bytes.
+fn charge_growth(budget: &ByteBudget, charged: &mut u64, total: u64) -> FmanResult<()> {
+    if total > *charged {
+        budget.charge(total - *charged)?;
+        *charged = total;
+    }
+    Ok(())
+}
+
+fn refund(options: &CopyOptions, bytes: u64) {
+    if let Some(budget) = &options.budget {
+        budget.refund(bytes);
+    }
+}
+
+/// Size and mtime of a source file, compared around a copy.
+#[derive(PartialEq, Eq)]
+struct SourceStamp {
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+impl SourceStamp {
+    fn read(file: &File) -> FmanResult<Self> {
+        let meta = file.metadata()?;
+        Ok(Self {
+            len: meta.len(),
+            modified: meta.modified().ok(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{self, AtomicUsize};
+    use std::sync::{Arc, Mutex};
+
+    use tempfile::TempDir;
+
+    use crate::fs::{DryRunFs, FsOp, RecordingFs};
+
+    /// A scratch directory with `src` holding "new" and `dst` holding "old".
+    fn conflict() -> (TempDir, PathBuf, PathBuf) {
+        let dir = TempDir::new().unwrap();
+        let (src, dst) = (dir.path().join("src.txt"), dir.path().join("dst.txt"));
+        fs::write(&src, "new").unwrap();
+        fs::write(&dst, "old").unwrap();
+        (dir, src, dst)
+    }
+
+    /// A scratch directory with `s/a`, `s/sub/b` and an empty `s/empty`.
+    fn tree() -> (TempDir, PathBuf) {
+        let dir = TempDir::new().unwrap();
+        let src = dir.path().join("s");
+        fs::create_dir_all(src.join("sub")).unwrap();
+        fs::create_dir(src.join("empty")).unwrap();
+        fs::write(src.join("a"), "1").unwrap();
+        fs::write(src.join("sub/b"), "2").unwrap();
+        (dir, src)
+    }
+
+    #[test]
+    fn copy_dir_recreates_empty_directories() {
+        let (dir, src) = tree();
+        let dst = dir.path().join("d");
+        let report = copy_dir(&src, &dst, &CopyOptions::new()).unwrap();
+        assert_eq!(report.files, 2);
+        assert_eq!(fs::read_to_string(dst.join("sub/b")).unwrap(), "2");
+        assert!(dst.join("empty").is_dir());
+    }
+
+    #[test]
+    fn copy_dir_stops_at_the_first_conflict_and_keeps_copied_files() {
+        let (dir, src) = tree();
+        fs::write(src.join("sub/c"), "3").unwrap();
+        fs::write(src.join("z"), "4").unwrap();
+        let dst = dir.path().join("d");
+        fs::create_dir_all(dst.join("s/sub")).unwrap();
+        fs::write(dst.join("s/sub/b"), "mine").unwrap();
+        fs::write(dst.join("s/sub/c"), "mine too").unwrap();
+        let err = copy_dir(&src, &dst, &CopyOptions::new()).unwrap_err();
+        assert!(
+            matches!(&err, FmanError::AlreadyExists(what) if what.ends_with("sub/b")),
+            "{err:?}"
+        );
+        assert_eq!(err.exit_code(), 3);
+        assert_eq!(fs::read_to_string(dst.join("s/a")).unwrap(), "1");
+        assert_eq!(fs::read_to_string(dst.join("s/sub/b")).unwrap(), "mine");
+        assert_eq!(fs::read_to_string(dst.join("s/sub/c")).unwrap(), "mine too");
+        assert!(!dst.join("s/z").exists());
+    }
+
+    #[test]
+    fn copy_dir_force_overwrites() {
+        let (dir, src) = tree();
+        let dst = dir.path().join("d");
+        fs::create_dir_all(dst.join("s")).unwrap();
+        fs::write(dst.join("s/a"), "mine").unwrap();
+        copy_dir(&src, &dst, &CopyOptions::new().force(true)).unwrap();
+        assert_eq!(fs::read_to_string(dst.join("s/a")).unwrap(), "1");
+    }
+
+    #[test]
+    fn copy_dir_into_itself_is_invalid_input() {
+        let (_dir, src) = tree();
+        let err = copy_dir(&src, &src.join("sub"), &CopyOptions::new()).unwrap_err();
+        assert!(matches!(err, FmanError::InvalidInput(_)), "{err}");
+        assert!(!src.join("sub/s").exists());
+    }
+
+    #[test]
+    fn preserved_attributes_go_through_the_filesystem() {
+        let (_dir, src, dst) = conflict();
+        fs::remove_file(&dst).unwrap();
+        let recording = Arc::new(RecordingFs::new());
+        let options = CopyOptions::new().preserve(true).fs(recording.clone());
+        copy_file(&src, &dst, &options).unwrap();
+        let ops = recording.ops();
+        assert!(matches!(ops[0], FsOp::Copy { .. }), "{ops:?}");
+        assert!(
+            ops.iter()
+                .any(|op| matches!(op, FsOp::SetTimes { path, .. } if *path == dst)),
+            "{ops:?}"
+        );
+    }
+
+    #[test]
+    fn conflicts_are_judged_by_the_filesystem() {
+        let (_dir, src, dst) = conflict();
+        let dry_run = Arc::new(DryRunFs::new());
+        let options = CopyOptions::new()
+            .on_conflict(OverwritePolicy::Skip)
+            .fs(dry_run.clone());
+        let report = copy_file(&src, &dst, &options).unwrap();
+        assert_eq!(report.status, CopyStatus::Skipped);
+        assert!(dry_run.ops().is_empty());
+    }
+
+    #[test]
+    fn a_planned_directory_receives_the_source_name() {
+        let (dir, src, _dst) = conflict();
+        let planned = dir.path().join("q");
+        let dry_run = DryRunFs::new();
+        assert_eq!(
+            resolve_destination_in(&src, &planned, &dry_run).unwrap(),
+            planned
+        );
+        dry_run.create_dir_all(&planned).unwrap();
+        assert_eq!(
+            resolve_destination_in(&src, &planned, &dry_run).unwrap(),
+            planned.join("src.txt")
+        );
+    }
+
+    #[test]
+    fn error_policy_keeps_the_destination() {
+        let (_dir, src, dst) = conflict();
+        let options = CopyOptions::new().on_conflict(OverwritePolicy::Error);
+        let err = copy_file(&src, &dst, &options).unwrap_err();
+        assert!(matches!(err, FmanError::AlreadyExists(_)), "{err}");
+        assert_eq!(fs::read_to_string(&dst).unwrap(), "old");
+    }
+
+    #[test]
+    fn overwrite_policy_replaces_the_destination() {
+        let (_dir, src, dst) = conflict();
+        let options = CopyOptions::new().on_conflict(OverwritePolicy::Overwrite);
+        let report = copy_file(&src, &dst, &options).unwrap();
+        assert_eq!(report.status, CopyStatus::Copied);
+        assert_eq!(fs::read_to_string(&dst).unwrap(), "new");
+    }
+
+    #[test]
+    fn skip_policy_reports_the_file_as_skipped() {
+        let (_dir, src, dst) = conflict();
+        let options = CopyOptions::new().on_conflict(OverwritePolicy::Skip);
+        let report = copy_file(&src, &dst, &options).unwrap();
+        assert_eq!(report.status, CopyStatus::Skipped);
+        assert_eq!(report.destination, dst);
+        assert_eq!(fs::read_to_string(&dst).unwrap(), "old");
+    }
+
+    #[test]
+    fn rename_policy_copies_to_a_free_name() {
+        let (dir, src, dst) = conflict();
+        let options = CopyOptions::new().on_conflict(OverwritePolicy::RenameNew);
+        let first = copy_file(&src, &dst, &options).unwrap();
+        let second = copy_file(&src, &dst, &options).unwrap();
+        assert_eq!(first.destination, dir.path().join("dst (1).txt"));
+        assert_eq!(second.destination, dir.path().join("dst (2).txt"));
+        assert_eq!(fs::read_to_string(&dst).unwrap(), "old");
+        assert_eq!(fs::read_to_string(&first.destination).unwrap(), "new");
+    }
+
+    #[test]
+    fn every_policy_applies_to_each_file_of_a_tree() {
+        let (dir, src) = tree();
+        let dst = dir.path().join("d");
+        fs::create_dir(&dst).unwrap();
+        copy_dir(&src, &dst, &CopyOptions::new()).unwrap();
+        let copied = dst.join("s");
+        fs::write(copied.join("a"), "old").unwrap();
+        let read = |name: &str| fs::read_to_string(copied.join(name)).unwrap();
+
+        let policy = |policy| CopyOptions::new().on_conflict(policy);
+        let err = copy_dir(&src, &dst, &policy(OverwritePolicy::Error)).unwrap_err();
+        assert_eq!(err.exit_code(), 3, "{err}");
+        let report = copy_dir(&src, &dst, &policy(OverwritePolicy::Skip)).unwrap();
+        assert_eq!((report.files, report.skipped), (0, 2));
+        assert_eq!(read("a"), "old");
+
+        let report = copy_dir(&src, &dst, &policy(OverwritePolicy::RenameNew)).unwrap();
+        assert_eq!(report.files, 2);
+        assert_eq!(read("a (1)"), "1");
+        assert_eq!(read("sub/b (1)"), "2");
+        assert_eq!(read("a"), "old");
+
+        copy_dir(&src, &dst, &policy(OverwritePolicy::Overwrite)).unwrap();
+        assert_eq!(read("a"), "1");
+    }
+
+    #[test]
+    fn force_is_shorthand_for_overwrite_and_error() {
+        assert_eq!(
+            CopyOptions::new().force(true).on_conflict,
+            OverwritePolicy::Overwrite
+        );
+        assert_eq!(
+            CopyOptions::new().force(false).on_conflict,
+            OverwritePolicy::Error
+        );
+        assert_eq!(CopyOptions::new().on_conflict, OverwritePolicy::Error);
+    }
+
+    #[test]
+    fn a_missing_source_fails_whatever_the_policy() {
+        let (dir, _src, dst) = conflict();
+        let missing = dir.path().join("missing.txt");
+        for policy in [OverwritePolicy::Skip, OverwritePolicy::RenameNew] {
+            let options = CopyOptions::new().on_conflict(policy);
+            let err = copy_file(&missing, &dst, &options).unwrap_err();
+            assert!(err.is_not_found(), "{policy:?}: {err}");
+        }
+        assert!(!dir.path().join("dst (1).txt").exists());
+    }
+
+    /// Options whose progress callback appends to `src` on the first
+    /// `races` copy attempts, so that the source changes mid-copy.
+    fn racing(src: &Path, races: usize, policy: RacingPolicy) -> CopyOptions {
+        let src = src.to_path_buf();
+        let left = Arc::new(std::sync::atomic::AtomicUsize::new(races));
+        CopyOptions::new()
+            .detect_racing_writes(policy)
+            .progress(move |copied, total| {
+                use std::sync::atomic::Ordering;
+                if copied == total
+                    && left
+                        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+                        .is_ok()
+                {
+                    let mut file = fs::OpenOptions::new().append(true).open(&src).unwrap();
+                    io::Write::write_all(&mut file, b"+").unwrap();
+                }
+            })
+    }
+
+    #[test]
+    fn a_write_on_one_attempt_is_retried() {
+        let (dir, src, _) = conflict();
+        let dst = dir.path().join("copy.txt");
+        let report = copy_file(&src, &dst, &racing(&src, 1, RacingPolicy::Error)).unwrap();
+        assert!(!report.changed_during_copy);
+        assert_eq!(fs::read_to_string(&dst).unwrap(), "new+");
+    }
+
+    #[test]
+    fn a_source_that_keeps_changing_gets_the_policy() {
+        let (dir, src, _) = conflict();
+        let dst = dir.path().join("warn.txt");
+        let report = copy_file(&src, &dst, &racing(&src, 2, RacingPolicy::Warn)).unwrap();
+        assert!(report.changed_during_copy);
+        assert!(dst.exists());
+
+        let dst = dir.path().join("skip.txt");
+        let report = copy_file(&src, &dst, &racing(&src, 2, RacingPolicy::Skip)).unwrap();
+        assert_eq!((report.status, report.bytes), (CopyStatus::Skipped, 0));
+        assert!(!dst.exists());
+
+        let dst = dir.path().join("error.txt");
+        let err = copy_file(&src, &dst, &racing(&src, 2, RacingPolicy::Error)).unwrap_err();
+        assert!(matches!(err, FmanError::ChangedDuringCopy(_)), "{err}");
+        assert!(!dst.exists());
+    }
+
+    #[test]
+    fn a_steady_source_is_not_flagged() {
+        let (dir, src, _) = conflict();
+        let dst = dir.path().join("copy.txt");
+        let options = CopyOptions::new().detect_racing_writes(RacingPolicy::Error);
+        let report = copy_file(&src, &dst, &options).unwrap();
+        assert!(!report.changed_during_copy);
+        assert_eq!(report.status, CopyStatus::Copied);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_dangling_symlink_destination_is_never_written_through() {
+        let (dir, src, _) = conflict();
+        let target = dir.path().join("elsewhere");
+        let link = dir.path().join("link");
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+
+        let err = copy_file(&src, &link, &CopyOptions::new()).unwrap_err();
+        assert!(matches!(err, FmanError::AlreadyExists(_)), "{err}");
+        assert!(fs::symlink_metadata(&link).unwrap().is_symlink());
+
+        copy_file(&src, &link, &CopyOptions::new().force(true)).unwrap();
+        let meta = fs::symlink_metadata(&link).unwrap();
+        assert!(meta.is_file(), "the link itself was replaced");
+        assert_eq!(fs::read_to_string(&link).unwrap(), "new");
+        assert!(!target.exists(), "nothing was written at the link's target");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn force_replaces_a_live_symlink_instead_of_its_target() {
+        let (dir, src, dst) = conflict();
+        let link = dir.path().join("link");
+        std::os::unix::fs::symlink(&dst, &link).unwrap();
+        copy_file(&src, &link, &CopyOptions::new().force(true)).unwrap();
+        assert!(fs::symlink_metadata(&link).unwrap().is_file());
+        assert_eq!(fs::read_to_string(&dst).unwrap(), "old");
+    }
+
+    #[test]
+    fn a_buffer_size_copies_files_larger_than_the_buffer() {
+        let dir = TempDir::new().unwrap();
+        let src = dir.path().join("big");
+        let data: Vec<u8> = (0..100_003u32).map(|i| (i % 251) as u8).collect();
+        fs::write(&src, &data).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&src, fs::Permissions::from_mode(0o640)).unwrap();
+        }
+        for size in [1, 4096, 1 << 20] {
+            let dst = dir.path().join(format!("copy-{size}"));
+            let report = copy_file(&src, &dst, &CopyOptions::new().buffer_size(size)).unwrap();
+            assert_eq!(report.strategy, CopyStrategy::Buffered);
+            assert_eq!(report.bytes, data.len() as u64);
+            assert!(fs::read(&dst).unwrap() == data, "{size}");
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                let mode = fs::metadata(&dst).unwrap().permissions().mode();
+                assert_eq!(mode & 0o777, 0o640, "{size}");
+            }
+        }
+    }
+
+    #[test]
+    fn a_read_only_copy_is_protected_after_its_times() {
+        let (dir, src, _) = conflict();
+        let dst = dir.path().join("ro.txt");
+        let recording = Arc::new(RecordingFs::new());
+        let options = CopyOptions::new()
+            .read_only(true)
+            .preserve(true)
+            .verify(true)
+            .fs(recording.clone());
+        let report = copy_file(&src, &dst, &options).unwrap();
+        assert!(report.verified);
+        assert!(fs::metadata(&dst).unwrap().permissions().readonly());
+        assert_eq!(
+            fs::metadata(&dst).unwrap().modified().unwrap(),
+            fs::metadata(&src).unwrap().modified().unwrap()
+        );
+        let ops = recording.ops();
+        let times = ops
+            .iter()
+            .position(|op| matches!(op, FsOp::SetTimes { .. }))
+            .unwrap();
+        let protect = ops
+            .iter()
+            .position(|op| matches!(op, FsOp::SetPermissions { readonly: true, .. }))
+            .unwrap();
+        assert!(times < protect, "{ops:?}");
+    }
+
+    #[test]
+    fn a_read_only_copy_is_not_clobbered_by_a_safe_copy() {
+        let (dir, src, _) = conflict();
+        let dst = dir.path().join("ro.txt");
+        copy_file(&src, &dst, &CopyOptions::new().read_only(true)).unwrap();
+        fs::write(&src, "newer").unwrap();
+        let err = copy_file(&src, &dst, &CopyOptions::new()).unwrap_err();
+        assert!(matches!(err, FmanError::AlreadyExists(_)), "{err}");
+        assert_eq!(fs::read_to_string(&dst).unwrap(), "new");
+    }
+
+    #[test]
+    fn a_read_only_tree_copy_protects_every_file() {
+        let (dir, src) = tree();
+        let dst = dir.path().join("d");
+        copy_dir(&src, &dst, &CopyOptions::new().read_only(true)).unwrap();
+        for file in ["a", "sub/b"] {
+            assert!(
+                fs::metadata(dst.join(file))
+                    .unwrap()
+                    .permissions()
+                    .readonly()
+            );
+        }
+    }
+
+    #[test]
+    fn an_atomic_copy_cannot_be_made_immutable() {
+        let (dir, src, _) = conflict();
+        let dst = dir.path().join("imm.txt");
+        let options = CopyOptions::new()
+            .atomic(true)
+            .immutable(Immutability::BestEffort);
+        let err = copy_file(&src, &dst, &options).unwrap_err();
+        assert!(matches!(err, FmanError::InvalidInput(_)), "{err}");
+        assert!(!dst.exists());
+    }
+
+    #[test]
+    fn an_unprivileged_immutable_copy_is_permission_denied() {
+        let dst = Path::new("d/imm.txt");
+        let err = immutable_error(dst, io::ErrorKind::PermissionDenied.into());
+        let FmanError::PermissionDenied(message) = &err else {
+            panic!("{err:?}")
+        };
+        assert!(message.starts_with("d/imm.txt"), "{message}");
+        assert!(message.contains("CAP_LINUX_IMMUTABLE"), "{message}");
+        assert_eq!(err.exit_code(), 5);
+
+        let err = immutable_error(dst, io::ErrorKind::Unsupported.into());
+        assert!(matches!(err, FmanError::Io(_)), "{err:?}");
+    }
+
+    /// Every `(done, total)` a progress callback was called with.
+    type ProgressCalls = Arc<Mutex<Vec<(u64, u64)>>>;
+
+    /// Options whose progress calls are collected in the returned list.
+    fn recording_progress() -> (CopyOptions, ProgressCalls) {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let seen = calls.clone();
+        let options = CopyOptions::new()
+            .progress(move |done, total| seen.lock().unwrap().push((done, total)));
+        (options, calls)
+    }
+
+    #[test]
+    fn progress_steps_up_to_the_file_size() {
+        let (dir, src, _) = conflict();
+        let size = 2 * PROGRESS_STEP + PROGRESS_STEP / 2 + 7;
+        fs::write(&src, vec![1u8; size as usize]).unwrap();
+        let (options, calls) = recording_progress();
+        copy_file(&src, dir.path().join("copy"), &options).unwrap();
+        let calls = calls.lock().unwrap();
+        assert_eq!(calls.last(), Some(&(size, size)));
+        assert_eq!(calls.len(), 3, "{calls:?}");
+        assert!(calls.iter().all(|&(_, total)| total == size));
+        for pair in calls.windows(2) {
+            assert!(pair[1].0 - pair[0].0 >= PROGRESS_STEP || pair[1].0 == size);
+        }
+    }
+
+    #[test]
+    fn progress_only_grows_while_the_source_does() {
+        let (dir, src, _) = conflict();
+        fs::write(&src, vec![1u8; 3 * PROGRESS_STEP as usize]).unwrap();
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let seen = calls.clone();
+        let grow = src.clone();
+        let options = CopyOptions::new().progress(move |done, total| {
+            let mut seen = seen.lock().unwrap();
+            if seen.is_empty() {
+                let mut file = fs::OpenOptions::new().append(true).open(&grow).unwrap();
+                io::Write::write_all(&mut file, &vec![2u8; PROGRESS_STEP as usize]).unwrap();
+            }
+            seen.push((done, total));
+        });
+        let report = copy_file(&src, dir.path().join("copy"), &options).unwrap();
+        let calls = calls.lock().unwrap();
+        assert!(
+            calls.windows(2).all(|pair| pair[0].0 < pair[1].0),
+            "{calls:?}"
+        );
+        assert!(calls.iter().all(|&(_, total)| total == 3 * PROGRESS_STEP));
+        assert_eq!(calls.last().unwrap().0, report.bytes);
+    }
+
+    #[test]
+    fn progress_is_silent_in_a_dry_run() {
+        let (dir, src, _) = conflict();
+        let (options, calls) = recording_progress();
+        let options = options.fs(Arc::new(DryRunFs::new()));
+        copy_file(&src, dir.path().join("copy"), &options).unwrap();
+        assert!(!dir.path().join("copy").exists());
+        assert!(calls.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn an_empty_file_reports_once() {
+        let (dir, src, _) = conflict();
+        fs::write(&src, "").unwrap();
+        let (options, calls) = recording_progress();
+        copy_file(&src, dir.path().join("copy"), &options).unwrap();
+        assert_eq!(*calls.lock().unwrap(), [(0, 0)]);
+    }
+
+    #[test]
+    fn a_handle_is_copied_from_the_start() {
+        let (dir, src, _) = conflict();
+        fs::write(&src, "0123456789").unwrap();
+        let mut handle = File::open(&src).unwrap();
+        io::Seek::seek(&mut handle, io::SeekFrom::Start(4)).unwrap();
+        let dst = dir.path().join("copy.txt");
+        let progressed = Arc::new(Mutex::new(Vec::new()));
+        let seen = progressed.clone();
+        let options = CopyOptions::new()
+            .verify(true)
+            .progress(move |done, total| seen.lock().unwrap().push((done, total)));
+        let report = copy_from_file(&handle, &dst, &options).unwrap();
+        assert_eq!(fs::read_to_string(&dst).unwrap(), "0123456789");
+        assert_eq!(report.bytes, 10);
+        assert_eq!(report.source, PathBuf::new());
+        assert_eq!(report.destination, dst);
+        assert!(report.verified);
+        assert_eq!(progressed.lock().unwrap().last(), Some(&(10, 10)));
+        assert_eq!(io::Seek::stream_position(&mut handle).unwrap(), 10);
+    }
+
+    #[test]
+    fn a_handle_needs_a_free_file_destination() {
+        let (dir, src, dst) = conflict();
+        let handle = File::open(&src).unwrap();
+        let err = copy_from_file(&handle, dir.path(), &CopyOptions::new()).unwrap_err();
+        assert!(matches!(err, FmanError::InvalidInput(_)), "{err}");
+        let err = copy_from_file(&handle, &dst, &CopyOptions::new()).unwrap_err();
+        assert!(matches!(err, FmanError::AlreadyExists(_)), "{err}");
+        assert_eq!(fs::read_to_string(&dst).unwrap(), "old");
+        copy_from_file(&handle, &dst, &CopyOptions::new().force(true)).unwrap();
+        assert_eq!(fs::read_to_string(&dst).unwrap(), "new");
+        let err = copy_from_file(&handle, &src, &CopyOptions::new().force(true)).unwrap_err();
+        assert!(err.to_string().contains("same file"), "{err}");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_handle_lends_its_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let (dir, src, _) = conflict();
+        fs::set_permissions(&src, fs::Permissions::from_mode(0o640)).unwrap();
+        let handle = File::open(&src).unwrap();
+        let dst = dir.path().join("copy.txt");
+        copy_from_file(&handle, &dst, &CopyOptions::new()).unwrap();
+        assert_eq!(
+            fs::metadata(&dst).unwrap().permissions().mode() & 0o777,
+            0o640
+        );
+    }
+
+    #[test]
+    fn a_path_is_copied_over_an_open_handle() {
+        let (dir, src, dst) = conflict();
+        fs::write(&dst, "a much longer old text").unwrap();
+        let mut handle = File::options().read(true).write(true).open(&dst).unwrap();
+        io::Seek::seek(&mut handle, io::SeekFrom::Start(5)).unwrap();
+        let report = copy_to_file(&src, &mut handle, &CopyOptions::new()).unwrap();
+        drop(handle);
+        assert_eq!(fs::read_to_string(&dst).unwrap(), "new");
+        assert_eq!(report.bytes, 3);
+        assert_eq!(report.source, src);
+        assert_eq!(report.destination, PathBuf::new());
+
+        let mut same = File::options().write(true).open(&src).unwrap();
+        let err = copy_to_file(&src, &mut same, &CopyOptions::new()).unwrap_err();
+        assert!(err.to_string().contains("same file"), "{err}");
+        let err = copy_to_file(dir.path(), &mut same, &CopyOptions::new()).unwrap_err();
+        assert_eq!(err.exit_code(), 4);
+        assert_eq!(fs::read_to_string(&src).unwrap(), "new");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn the_symlink_policy_decides_by_depth() {
+        let (dir, src) = tree();
+        std::os::unix::fs::symlink("../a", src.join("sub/inner")).unwrap();
+        let link = dir.path().join("link");
+        std::os::unix::fs::symlink(&src, &link).unwrap();
+        let copy = |policy, name: &str| {
+            let dst = dir.path().join(name);
+            copy_dir(&link, &dst, &CopyOptions::new().symlinks(policy)).unwrap();
+            dst
+        };
+        let never = copy(SymlinkPolicy::Never, "never");
+        assert_eq!(fs::read_link(&never).unwrap(), src);
+        let command_line = copy(SymlinkPolicy::CommandLine, "command-line");
+        assert!(fs::symlink_metadata(&command_line).unwrap().is_dir());
+        assert_eq!(
+            fs::read_link(command_line.join("sub/inner")).unwrap(),
+            PathBuf::from("../a")
+        );
+        let always = copy(SymlinkPolicy::Always, "always");
+        assert!(
+            fs::symlink_metadata(always.join("sub/inner"))
+                .unwrap()
+                .is_file()
+        );
+        assert_eq!(fs::read_to_string(always.join("sub/inner")).unwrap(), "1");
+    }
+
+    /// Options whose first progress report removes `gone`, as another
+    /// process would between the walk and the copy.
+    fn vanishing(gone: &Path) -> CopyOptions {
+        let gone = gone.to_path_buf();
+        CopyOptions::new().progress(move |_, _| {
+            if gone.exists() {
+                fs::remove_dir_all(&gone).unwrap();
+            }
+        })
+    }
+
+    #[test]
+    fn files_vanishing_mid_copy_are_counted_when_ignored() {
+        let (dir, src) = tree();
+        fs::write(src.join("z"), "3").unwrap();
+        let dst = dir.path().join("d");
+        let options = vanishing(&src.join("sub")).ignore_vanished(true);
+        let report = copy_dir(&src, &dst, &options).unwrap();
+        assert_eq!((report.files, report.vanished), (2, 1));
+        assert_eq!(fs::read_to_string(dst.join("z")).unwrap(), "3");
+        assert!(!dst.join("sub/b").exists());
+    }
+
+    #[test]
+    fn files_vanishing_mid_copy_fail_it_by_default() {
+        let (dir, src) = tree();
+        let dst = dir.path().join("d");
+        let err = copy_dir(&src, &dst, &vanishing(&src.join("sub"))).unwrap_err();
+        assert!(err.is_not_found(), "{err}");
+    }
+
+    #[test]
+    fn a_file_whose_metadata_cannot_be_read_fails_only_itself() {
+        let (dir, src) = tree();
+        fs::write(src.join("z"), "3").unwrap();
+        let dst = dir.path().join("d");
+        let gone = src.join("z");
+        let options = CopyOptions::new()
+            .skip_active(Duration::ZERO)
+            .progress(move |_, _| {
+                let _ = fs::remove_file(&gone);
+            });
+        let err = copy_dir(&src, &dst, &options).unwrap_err();
+        assert!(err.is_not_found(), "{err}");
+        assert_eq!(fs::read_to_string(dst.join("a")).unwrap(), "1");
+        assert_eq!(fs::read_to_string(dst.join("sub/b")).unwrap(), "2");
+        assert!(!dst.join("z").exists());
+    }
+
+    #[test]
+    fn a_missing_copy_source_is_not_a_vanished_file() {
+        let dir = TempDir::new().unwrap();
+        let options = CopyOptions::new().ignore_vanished(true);
+        let err = copy_dir(&dir.path().join("missing"), &dir.path().join("d"), &options);
+        assert!(err.unwrap_err().is_not_found());
+    }
+
+    #[test]
+    fn sanitize_windows_names_replaces_reserved_characters() {
+        let renamed = sanitize_windows_names(Path::new("a:b/what?<>|*\"/x. . ")).unwrap();
+        assert_eq!(renamed, Path::new("a_b/what______/x"));
+        assert_eq!(
+            sanitize_windows_names(Path::new("...")).unwrap(),
+            Path::new("_")
+        );
+        assert_eq!(
+            sanitize_windows_names(Path::new("ok.txt")).unwrap(),
+            Path::new("ok.txt")
+        );
+    }
+
+    #[test]
+    fn lowercase_names_lowercases_every_component() {
+        let renamed = lowercase_names(Path::new("Docs/README.MD")).unwrap();
+        assert_eq!(renamed, Path::new("docs/readme.md"));
+    }
+
+    #[test]
+    fn copy_dir_applies_a_custom_path_transform() {
+        let (dir, src) = tree();
+        let dst = dir.path().join("d");
+        let options =
+            CopyOptions::new().path_transform(|path: &Path| Ok(Path::new("2024").join(path)));
+        let report = copy_dir(&src, &dst, &options).unwrap();
+        assert_eq!(report.files, 2);
+        assert_eq!(fs::read_to_string(dst.join("2024/a")).unwrap(), "1");
+        assert_eq!(fs::read_to_string(dst.join("2024/sub/b")).unwrap(), "2");
+        assert!(!dst.join("a").exists());
+    }
+
+    #[test]
+    fn copy_dir_lowercases_names() {
+        let dir = TempDir::new().unwrap();
+        let src = dir.path().join("s");
+        fs::create_dir_all(src.join("Sub")).unwrap();
+        fs::write(src.join("Sub/File.TXT"), "x").unwrap();
+        let dst = dir.path().join("d");
+        copy_dir(
+            &src,
+            &dst,
+            &CopyOptions::new().path_transform(lowercase_names),
+        )
+        .unwrap();
+        assert_eq!(fs::read_to_string(dst.join("sub/file.txt")).unwrap(), "x");
+    }
+
+    #[test]
+    fn two_sources_mapped_to_one_destination_are_an_error() {
+        let dir = TempDir::new().unwrap();
+        let src = dir.path().join("s");
+        fs::create_dir(&src).unwrap();
+        fs::write(src.join("a?"), "1").unwrap();
+        fs::write(src.join("a*"), "2").unwrap();
+        let dst = dir.path().join("d");
+        let options = CopyOptions::new().path_transform(sanitize_windows_names);
+        let err = copy_dir(&src, &dst, &options).unwrap_err().to_string();
+        assert!(err.contains("a?") && err.contains("a*"), "{err}");
+        assert!(err.contains("both map to a_"), "{err}");
+    }
+
+    #[test]
+    fn a_path_transform_cannot_escape_the_destination() {
+        let (dir, src) = tree();
+        let options =
+            CopyOptions::new().path_transform(|path: &Path| Ok(Path::new("..").join(path)));
+        let err = copy_dir(&src, &dir.path().join("d"), &options).unwrap_err();
+        assert_eq!(err.exit_code(), 4, "{err}");
+        assert!(
+            err.to_string().contains("not a relative path below"),
+            "{err}"
+        );
+        assert!(!dir.path().join("a").exists());
+    }
+
+    /// A scratch directory with six one-byte files in `s`.
+    fn flat_tree() -> (TempDir, PathBuf) {
+        let dir = TempDir::new().unwrap();
+        let src = dir.path().join("s");
+        fs::create_dir(&src).unwrap();
+        for i in 0..6 {
+            fs::write(src.join(format!("f{i}")), i.to_string()).unwrap();
+        }
+        (dir, src)
+    }
+
+    /// Sources `recording` has opened for a copy.
+    fn copied_from(recording: &RecordingFs) -> Vec<PathBuf> {
+        recording
+            .ops()
+            .into_iter()
+            .filter_map(|op| match op {
+                FsOp::Copy { from, .. } => Some(from),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn a_cancelled_copy_resumes_from_its_checkpoint() {
+        let (dir, src) = flat_tree();
+        let (dst, checkpoint) = (dir.path().join("d"), dir.path().join("cp"));
+        let token = CancelToken::new();
+        let cancel = token.clone();
+        let options = CopyOptions::new()
+            .checkpoint(&checkpoint)
+            .cancel(token)
+            .progress(move |_, _| cancel.cancel());
+        let err = copy_dir(&src, &dst, &options).unwrap_err();
+        assert!(matches!(err, FmanError::Cancelled), "{err}");
+        let first: Vec<String> = fs::read_dir(&dst)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        assert!(!first.is_empty() && first.len() < 6, "{first:?}");
+        let recorded = fs::read_to_string(&checkpoint).unwrap();
+        assert_eq!(recorded.lines().count(), 1 + first.len(), "{recorded}");
+
+        let recording = Arc::new(RecordingFs::new());
+        let options = CopyOptions::new()
+            .checkpoint(&checkpoint)
+            .fs(recording.clone());
+        let report = copy_dir(&src, &dst, &options).unwrap();
+        assert_eq!(report.resumed, first.len() as u64);
+        assert_eq!(report.files, 6 - first.len() as u64);
+        let read_again = copied_from(&recording);
+        assert_eq!(read_again.len(), 6 - first.len());
+        for name in &first {
+            assert!(
+                !read_again.contains(&src.join(name)),
+                "{name} was read again"
+            );
+        }
+        for i in 0..6 {
+            assert_eq!(
+                fs::read_to_string(dst.join(format!("f{i}"))).unwrap(),
+                i.to_string()
+            );
+        }
+        assert!(!checkpoint.exists());
+    }
+
+    #[test]
+    fn a_source_changed_since_the_checkpoint_is_copied_again() {
+        let (dir, src) = flat_tree();
+        let (dst, checkpoint) = (dir.path().join("d"), dir.path().join("cp"));
+        let options = CopyOptions::new()
+            .force(true)
+            .checkpoint(&checkpoint)
+            .keep_checkpoint(true);
+        copy_dir(&src, &dst, &options).unwrap();
+        assert!(checkpoint.exists());
+        fs::write(src.join("f2"), "changed").unwrap();
+
+        let recording = Arc::new(RecordingFs::new());
+        let report = copy_dir(&src, &dst, &options.clone().fs(recording.clone())).unwrap();
+        assert_eq!(report.resumed, 5);
+        assert_eq!(copied_from(&recording), [src.join("f2")]);
+        assert_eq!(fs::read_to_string(dst.join("f2")).unwrap(), "changed");
+    }
+
+    #[test]
+    fn a_checkpoint_is_not_reused_for_another_destination() {
+        let (dir, src) = flat_tree();
+        let checkpoint = dir.path().join("cp");
+        let options = CopyOptions::new()
+            .checkpoint(&checkpoint)
+            .keep_checkpoint(true);
+        copy_dir(&src, &dir.path().join("d"), &options).unwrap();
+        let err = copy_dir(&src, &dir.path().join("e"), &options).unwrap_err();
+        assert!(matches!(err, FmanError::InvalidInput(_)), "{err}");
+        assert!(!dir.path().join("e/f0").exists());
+        let err = copy_dir(
+            &src,
+            &dir.path().join("d"),
+            &options.clone().path_transform(lowercase_names),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("different copy"), "{err}");
+    }
+
+    /// The real filesystem, except that hard links fail as if the two
+    /// paths were on different devices.
+    #[derive(Debug)]
+    struct NoHardLinkFs;
+
+    impl Fs for NoHardLinkFs {
+        fn create_file(&self, path: &Path, create_new: bool) -> io::Result<Option<File>> {
+            RealFs.create_file(path, create_new)
+        }
+        fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+            RealFs.rename(from, to)
+        }
+        fn remove_file(&self, path: &Path) -> io::Result<()> {
+            RealFs.remove_file(path)
+        }
+        fn remove_dir(&self, path: &Path) -> io::Result<()> {
+            RealFs.remove_dir(path)
+        }
+        fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+            RealFs.remove_dir_all(path)
+        }
+        fn create_dir(&self, path: &Path) -> io::Result<()> {
+            RealFs.create_dir(path)
+        }
+        fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+            RealFs.create_dir_all(path)
+        }
+        fn set_permissions(&self, path: &Path, permissions: fs::Permissions) -> io::Result<()> {
+            RealFs.set_permissions(path, permissions)
+        }
+        fn set_times(
+            &self,
+            path: &Path,
+            accessed: Option<SystemTime>,
+            modified: Option<SystemTime>,
+        ) -> io::Result<()> {
+            RealFs.set_times(path, accessed, modified)
+        }
+        fn set_owner(&self, path: &Path, uid: Option<u32>, gid: Option<u32>) -> io::Result<()> {
+            RealFs.set_owner(path, uid, gid)
+        }
+        fn set_xattr(&self, path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+            RealFs.set_xattr(path, name, value)
+        }
+        fn remove_xattr(&self, path: &Path, name: &str) -> io::Result<()> {
+            RealFs.remove_xattr(path, name)
+        }
+        fn symlink(&self, target: &Path, link: &Path) -> io::Result<()> {
+            RealFs.symlink(target, link)
+        }
+        fn hard_link(&self, _: &Path, _: &Path) -> io::Result<()> {
+            Err(io::ErrorKind::CrossesDevices.into())
+        }
+        fn set_file_permissions(
+            &self,
+            path: &Path,
+            file: &File,
+            permissions: fs::Permissions,
+        ) -> io::Result<()> {
+            RealFs.set_file_permissions(path, file, permissions)
+        }
+        fn set_file_times(
+            &self,
+            path: &Path,
+            file: &File,
+            accessed: Option<SystemTime>,
+            modified: Option<SystemTime>,
+        ) -> io::Result<()> {
+            RealFs.set_file_times(path, file, accessed, modified)
+        }
+        fn set_file_owner(
+            &self,
+            path: &Path,
+            file: &File,
+            uid: Option<u32>,
+            gid: Option<u32>,
+        ) -> io::Result<()> {
+            RealFs.set_file_owner(path, file, uid, gid)
+        }
+    }
+
+    #[cfg(unix)]
+    fn inode(path: &Path) -> u64 {
+        use std::os::unix::fs::MetadataExt;
+        fs::metadata(path).unwrap().ino()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hardlink_mode_links_every_file_of_a_tree() {
+        let (dir, src) = tree();
+        let dst = dir.path().join("d");
+        let options = CopyOptions::new().link_mode(LinkMode::Hardlink);
+        let report = copy_dir(&src, &dst, &options).unwrap();
+        assert_eq!((report.links, report.files, report.bytes), (2, 0, 0));
+        assert_eq!(report.directories, 3);
+        assert_eq!(inode(&dst.join("a")), inode(&src.join("a")));
+        assert_eq!(inode(&dst.join("sub/b")), inode(&src.join("sub/b")));
+        assert_ne!(inode(&dst.join("sub")), inode(&src.join("sub")));
+        assert!(dst.join("empty").is_dir());
+    }
+
+    #[test]
+    fn a_hard_link_across_devices_is_a_cross_device_error() {
+        let (_dir, src, dst) = conflict();
+        let options = CopyOptions::new()
+            .force(true)
+            .link_mode(LinkMode::Hardlink)
+            .fs(Arc::new(NoHardLinkFs));
+        let err = copy_file(&src, &dst, &options).unwrap_err();
+        assert!(matches!(err, FmanError::CrossDevice(_)), "{err}");
+        assert!(err.to_string().contains("different filesystems"), "{err}");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_mode_points_at_the_absolute_source() {
+        let (dir, src) = tree();
+        let dst = dir.path().join("d");
+        let options = CopyOptions::new().link_mode(LinkMode::Symlink { relative: false });
+        let report = copy_dir(&src, &dst, &options).unwrap();
+        assert_eq!(report.links, 2);
+        let src = src.canonicalize().unwrap();
+        assert_eq!(fs::read_link(dst.join("a")).unwrap(), src.join("a"));
+        assert_eq!(fs::read_link(dst.join("sub/b")).unwrap(), src.join("sub/b"));
+        assert!(fs::symlink_metadata(dst.join("sub")).unwrap().is_dir());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_mode_can_point_by_relative_path() {
+        let (dir, src) = tree();
+        let dst = dir.path().join("out/d");
+        let options = CopyOptions::new().link_mode(LinkMode::Symlink { relative: true });
+        copy_dir(&src, &dst, &options).unwrap();
+        assert_eq!(
+            fs::read_link(dst.join("a")).unwrap(),
+            Path::new("../../s/a")
+        );
+        assert_eq!(
+            fs::read_link(dst.join("sub/b")).unwrap(),
+            Path::new("../../../s/sub/b")
+        );
+        assert_eq!(fs::read_to_string(dst.join("sub/b")).unwrap(), "2");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn link_modes_follow_the_conflict_policy() {
+        let (_dir, src, dst) = conflict();
+        let options = CopyOptions::new().link_mode(LinkMode::Hardlink);
+        let err = copy_file(&src, &dst, &options).unwrap_err();
+        assert!(matches!(err, FmanError::AlreadyExists(_)), "{err}");
+        let skipped = copy_file(
+            &src,
+            &dst,
+            &options.clone().on_conflict(OverwritePolicy::Skip),
+        )
+        .unwrap();
+        assert_eq!(skipped.status, CopyStatus::Skipped);
+        assert_eq!(fs::read_to_string(&dst).unwrap(), "old");
+        let linked = copy_file(&src, &dst, &options.force(true)).unwrap();
+        assert_eq!(linked.status, CopyStatus::Linked);
+        assert_eq!(inode(&dst), inode(&src));
+    }
+
+    /// Options that hold up each file over 1 KiB by `delay` per progress
+    /// call, reporting files slower than `threshold`.
+    fn throttled(delay: Duration, threshold: Duration) -> CopyOptions {
+        CopyOptions::new()
+            .min_duration_report(threshold)
+            .progress(move |_, size| {
+                if size > 1024 {
+                    std::thread::sleep(delay);
+                }
+            })
+    }
+
+    #[test]
+    fn a_throttled_copy_reports_its_timing_and_throughput() {
+        let (_dir, src, dst) = conflict();
+        fs::write(&src, vec![7u8; 4096]).unwrap();
+        let delay = Duration::from_millis(20);
+        let report = copy_file(&src, &dst, &throttled(delay, delay).force(true)).unwrap();
+        assert!(report.timing.data >= delay, "{:?}", report.timing);
+        assert!(report.timing.wall >= report.timing.data);
+        assert_eq!(
+            report.timing.metadata(),
+            report.timing.wall - report.timing.data
+        );
+        let rate = report.bytes_per_second.unwrap();
+        let expected = 4096.0 / report.timing.data.as_secs_f64();
+        assert!((rate - expected).abs() < 1e-6, "{rate} vs {expected}");
+    }
+
+    #[test]
+    fn only_files_over_the_threshold_are_reported_slow() {
+        let (dir, src) = tree();
+        fs::write(src.join("big"), vec![1u8; 8192]).unwrap();
+        let dst = dir.path().join("d");
+        let delay = Duration::from_millis(30);
+        let report = copy_dir(&src, &dst, &throttled(delay, delay)).unwrap();
+        assert_eq!(report.files, 3);
+        assert_eq!(report.slow_files.len(), 1, "{:?}", report.slow_files);
+        let slow = &report.slow_files[0];
+        assert_eq!(slow.path, src.join("big"));
+        assert_eq!(slow.bytes, 8192);
+        assert!(slow.seconds >= delay.as_secs_f64());
+        assert!(slow.bytes_per_second.is_some_and(|rate| rate > 0.0));
+
+        assert_eq!(report.bytes, 8194);
+        assert!(report.timing.data >= delay);
+        let rate = report.bytes_per_second.unwrap();
+        let expected = 8194.0 / report.timing.data.as_secs_f64();
+        assert!((rate - expected).abs() < 1e-6, "{rate} vs {expected}");
+    }
+
+    #[test]
+    fn without_a_threshold_no_file_is_reported_slow() {
+        let (dir, src) = tree();
+        let report = copy_dir(&src, &dir.path().join("d"), &CopyOptions::new()).unwrap();
+        assert!(report.slow_files.is_empty());
+    }
+
+    #[test]
+    fn is_active_looks_at_the_window_before_now() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let window = Duration::from_secs(60);
+        let ago = |secs| now - Duration::from_secs(secs);
+        assert!(is_active(ago(0), now, window));
+        assert!(is_active(ago(59), now, window));
+        assert!(!is_active(ago(60), now, window));
+        assert!(!is_active(ago(3600), now, window));
+        // A stamp in the future is from a clock or a writer not done yet.
+        assert!(is_active(now + Duration::from_secs(5), now, window));
+        assert!(!is_active(ago(1), now, Duration::ZERO));
+    }
+
+    /// A scratch tree with `s/a-fresh` modified a second ago and `s/b-old`
+    /// an hour ago.
+    fn active_tree() -> (TempDir, PathBuf) {
+        let dir = TempDir::new().unwrap();
+        let src = dir.path().join("s");
+        fs::create_dir(&src).unwrap();
+        let now = SystemTime::now();
+        for (name, age) in [("a-fresh", 1), ("b-old", 3600)] {
+            let file = File::create(src.join(name)).unwrap();
+            file.set_modified(now - Duration::from_secs(age)).unwrap();
+        }
+        (dir, src)
+    }
+
+    #[test]
+    fn active_files_are_skipped() {
+        let (dir, src) = active_tree();
+        let dst = dir.path().join("d");
+        let options = CopyOptions::new().skip_active(Duration::from_secs(60));
+        let report = copy_dir(&src, &dst, &options).unwrap();
+        assert_eq!((report.files, report.skipped_active), (1, 1));
+        assert!(dst.join("b-old").exists());
+        assert!(!dst.join("a-fresh").exists());
+    }
+
+    #[test]
+    fn a_file_that_settles_is_copied_in_the_second_pass() {
+        let (dir, src) = active_tree();
+        let dst = dir.path().join("d");
+        let fresh = src.join("a-fresh");
+        let recording = Arc::new(RecordingFs::new());
+        // The writer finishes while the rest of the tree is copied.
+        let settle = fresh.clone();
+        let options = CopyOptions::new()
+            .skip_active(Duration::from_secs(60))
+            .retry_active_at_end(true)
+            .fs(recording.clone())
+            .progress(move |_, _| {
+                let old = SystemTime::now() - Duration::from_secs(7200);
+                File::options()
+                    .write(true)
+                    .open(&settle)
+                    .and_then(|file| file.set_modified(old))
+                    .unwrap();
+            });
+        let report = copy_dir(&src, &dst, &options).unwrap();
+        assert_eq!(report.files, 2);
+        assert_eq!((report.retried_active, report.skipped_active), (1, 0));
+        assert_eq!(copied_from(&recording), [src.join("b-old"), fresh]);
+    }
+
+    #[test]
+    fn a_file_still_active_at_the_end_stays_skipped() {
+        let (dir, src) = active_tree();
+        let dst = dir.path().join("d");
+        let options = CopyOptions::new()
+            .skip_active(Duration::from_secs(60))
+            .retry_active_at_end(true);
+        let report = copy_dir(&src, &dst, &options).unwrap();
+        assert_eq!(report.files, 1);
+        assert_eq!((report.retried_active, report.skipped_active), (0, 1));
+        assert!(!dst.join("a-fresh").exists());
+    }
+
+    #[test]
+    fn atomic_copies_take_their_temporary_name_from_the_naming_context() {
+        let dir = TempDir::new().unwrap();
+        let src = dir.path().join("src");
+        fs::write(&src, "data").unwrap();
+        let temp_name = |dst: &str| {
+            let recording = Arc::new(RecordingFs::new());
+            let options = CopyOptions::new()
+                .atomic(true)
+                .naming(Arc::new(NamingContext::seeded(5)))
+                .fs(recording.clone());
+            copy_file(&src, dir.path().join(dst), &options).unwrap();
+            recording
+                .ops()
+                .into_iter()
+                .find_map(|op| match op {
+                    FsOp::Copy { to, .. } => Some(to),
+                    _ => None,
+                })
+                .unwrap()
+        };
+        let (a, b) = (temp_name("a"), temp_name("b"));
+        assert_eq!(a, b);
+        assert!(
+            a.file_name()
+                .unwrap()
+                .to_string_lossy()
+                .starts_with(crate::naming::SCRATCH_PREFIX)
+        );
+    }
+
+    /// Holes of various sizes around and between data, in a file of
+    /// 8 MiB plus a few bytes.
+    fn sparse_source(path: &Path) -> u64 {
+        let len = 8 * 1024 * 1024 + 123;
+        let file = File::create(path).unwrap();
+        file.set_len(len).unwrap();
+        for (at, data) in [
+            (0, &b"head"[..]),
+            (3 * 1024 * 1024, &[7u8; 70_000][..]),
+            (5 * 1024 * 1024 + 1, &b"odd offset"[..]),
+        ] {
+            (&file).seek(io::SeekFrom::Start(at)).unwrap();
+            (&file).write_all(data).unwrap();
+        }
+        len
+    }
+
+    #[test]
+    fn a_sparse_copy_keeps_the_size_and_contents() {
+        let dir = TempDir::new().unwrap();
+        let src = dir.path().join("disk.img");
+        let len = sparse_source(&src);
+        let dst = dir.path().join("copy.img");
+        let report = copy_file(&src, &dst, &CopyOptions::new().sparse(true)).unwrap();
+        assert_eq!(report.strategy, CopyStrategy::Sparse);
+        assert_eq!(report.bytes, len);
+        assert_eq!(fs::metadata(&dst).unwrap().len(), len);
+        let digest =
+            |path: &Path| hash_reader(File::open(path).unwrap(), HashAlgorithm::Sha256).unwrap();
+        assert_eq!(digest(&dst), digest(&src));
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::unix::fs::MetadataExt;
+            // Holes are only kept where the filesystem supports them.
+            let allocated = |path: &Path| fs::metadata(path).unwrap().blocks() * 512;
+            if allocated(&src) < len / 2 {
+                assert!(
+                    allocated(&dst) < len / 2,
+                    "{} bytes allocated",
+                    allocated(&dst)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn a_sparse_copy_of_a_file_that_is_all_hole() {
+        let dir = TempDir::new().unwrap();
+        let src = dir.path().join("empty.img");
+        File::create(&src)
+            .unwrap()
+            .set_len(3 * 1024 * 1024)
+            .unwrap();
+        for (name, options) in [
+            ("whole", CopyOptions::new().sparse(true)),
+            ("buffered", CopyOptions::new().sparse(true).buffer_size(7)),
+        ] {
+            let dst = dir.path().join(name);
+            copy_file(&src, &dst, &options).unwrap();
+            assert_eq!(fs::metadata(&dst).unwrap().len(), 3 * 1024 * 1024, "{name}");
+            assert!(fs::read(&dst).unwrap().iter().all(|&b| b == 0), "{name}");
+        }
+    }
+
+    #[test]
+    fn reflink_auto_falls_back_where_always_fails() {
+        let (dir, src, _) = conflict();
+        let always = copy_file(
+            &src,
+            dir.path().join("always"),
+            &CopyOptions::new().reflink(Reflink::Always),
+        );
+        let auto = copy_file(
+            &src,
+            dir.path().join("auto"),
+            &CopyOptions::new().reflink(Reflink::Auto),
+        )
+        .unwrap();
+        assert_eq!(fs::read_to_string(dir.path().join("auto")).unwrap(), "new");
+        assert_eq!(auto.bytes, 3);
+        match always {
+            Ok(report) => {
+                assert_eq!(report.strategy, CopyStrategy::Reflink);
+                assert_eq!(auto.strategy, CopyStrategy::Reflink);
+                assert_eq!(
+                    fs::read_to_string(dir.path().join("always")).unwrap(),
+                    "new"
+                );
+            }
+            Err(err) => {
+                assert!(err.to_string().contains("cannot reflink"), "{err}");
+                assert!(err.to_string().contains("--reflink=auto"), "{err}");
+                assert_ne!(auto.strategy, CopyStrategy::Reflink);
+                assert!(!dir.path().join("always").exists());
+            }
+        }
+        let never = copy_file(&src, dir.path().join("never"), &CopyOptions::new()).unwrap();
+        assert_ne!(never.strategy, CopyStrategy::Reflink);
+    }
+
+    #[test]
+    fn reflinks_are_not_tried_for_streams_or_transforms() {
+        let (dir, src, _) = conflict();
+        let options = CopyOptions::new()
+            .reflink(Reflink::Always)
+            .transform(|_| Ok(Some(b"changed".to_vec())));
+        let report = copy_file(&src, dir.path().join("transformed"), &options).unwrap();
+        assert_eq!(report.strategy, CopyStrategy::Buffered);
+        let options = CopyOptions::new().reflink(Reflink::Always).stream(true);
+        let report = copy_file(&src, dir.path().join("streamed"), &options).unwrap();
+        assert_ne!(report.strategy, CopyStrategy::Reflink);
+        assert_eq!(
+            fs::read_to_string(dir.path().join("streamed")).unwrap(),
+            "new"
+        );
+    }
+
+    /// Options whose transform fails with a `kind` error the first
+    /// `failures` times it is called, counting the calls in the counter
+    /// returned.
+    fn failing(failures: usize, kind: io::ErrorKind) -> (CopyOptions, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let seen = calls.clone();
+        let options = CopyOptions::new().transform(move |_| {
+            if seen.fetch_add(1, atomic::Ordering::SeqCst) < failures {
+                return Err(io::Error::from(kind).into());
+            }
+            Ok(None)
+        });
+        (options, calls)
+    }
+
+    #[test]
+    fn transient_errors_are_retried_until_the_retries_run_out() {
+        let (dir, src, _) = conflict();
+        for kind in [
+            io::ErrorKind::Interrupted,
+            io::ErrorKind::WouldBlock,
+            io::ErrorKind::TimedOut,
+        ] {
+            let dst = dir.path().join(format!("{kind:?}"));
+            let (options, calls) = failing(2, kind);
+            copy_file(&src, &dst, &options.retries(2)).unwrap();
+            assert_eq!(calls.load(atomic::Ordering::SeqCst), 3);
+            assert_eq!(fs::read_to_string(&dst).unwrap(), "new");
+        }
+
+        let dst = dir.path().join("exhausted");
+        let (options, calls) = failing(3, io::ErrorKind::TimedOut);
+        let err = copy_file(&src, &dst, &options.retries(2)).unwrap_err();
+        assert!(
+            matches!(&err, FmanError::Io(e) if e.kind() == io::ErrorKind::TimedOut),
+            "{err:?}"
+        );
+        assert_eq!(calls.load(atomic::Ordering::SeqCst), 3);
+        assert!(!dst.exists());
+    }
+
+    #[test]
+    fn other_errors_are_not_retried() {
+        let (dir, src, _) = conflict();
+        let (options, calls) = failing(1, io::ErrorKind::PermissionDenied);
+        copy_file(&src, dir.path().join("copy"), &options.retries(5)).unwrap_err();
+        assert_eq!(calls.load(atomic::Ordering::SeqCst), 1);
+        let (options, calls) = failing(1, io::ErrorKind::TimedOut);
+        copy_file(&src, dir.path().join("copy"), &options).unwrap_err();
+        assert_eq!(calls.load(atomic::Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn a_retry_starts_the_destination_over() {
+        let (_dir, src, dst) = conflict();
+        fs::write(&dst, "a much longer old file").unwrap();
+        let (options, _) = failing(1, io::ErrorKind::Interrupted);
+        let options = options
+            .force(true)
+            .retries(1)
+            .retry_delay(Duration::from_millis(1));
+        let report = copy_file(&src, &dst, &options).unwrap();
+        assert_eq!(report.bytes, 3);
+        assert_eq!(fs::read_to_string(&dst).unwrap(), "new");
+    }
+
+    /// The temporary files an atomic copy left in `dir`.
+    fn scratch_files(dir: &Path) -> Vec<OsString> {
+        fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .filter(|name| {
+                name.to_string_lossy()
+                    .starts_with(crate::naming::SCRATCH_PREFIX)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn an_atomic_copy_replaces_only_when_forced() {
+        let (dir, src, dst) = conflict();
+        let err = copy_file(&src, &dst, &CopyOptions::new().atomic(true)).unwrap_err();
+        assert!(matches!(err, FmanError::AlreadyExists(_)), "{err}");
+        assert_eq!(fs::read_to_string(&dst).unwrap(), "old");
+        let options = CopyOptions::new().atomic(true).force(true);
+        let report = copy_file(&src, &dst, &options).unwrap();
+        assert_eq!(report.destination, dst);
+        assert_eq!(fs::read_to_string(&dst).unwrap(), "new");
+        assert!(scratch_files(dir.path()).is_empty());
+    }
+
+    #[test]
+    fn a_destination_that_appears_during_an_atomic_copy_is_kept() {
+        let (dir, src, _) = conflict();
+        let dst = dir.path().join("late.txt");
+        let racer = dst.clone();
+        let options = CopyOptions::new()
+            .atomic(true)
+            .progress(move |_, _| fs::write(&racer, "raced").unwrap());
+        let err = copy_file(&src, &dst, &options).unwrap_err();
+        assert!(matches!(err, FmanError::AlreadyExists(_)), "{err}");
+        assert_eq!(fs::read_to_string(&dst).unwrap(), "raced");
+        assert!(scratch_files(dir.path()).is_empty());
+    }
+
+    #[test]
+    fn a_failed_atomic_copy_leaves_no_temporary_file() {
+        let (dir, src, dst) = conflict();
+        let options = CopyOptions::new()
+            .atomic(true)
+            .force(true)
+            .transform(|_| Err(FmanError::InvalidInput("no".to_string())));
+        copy_file(&src, &dst, &options).unwrap_err();
+        assert_eq!(fs::read_to_string(&dst).unwrap(), "old");
+        assert!(scratch_files(dir.path()).is_empty());
+    }
+
+    #[test]
+    fn a_trailing_separator_marks_a_directory() {
+        assert!(ends_with_separator(Path::new("backup/")));
+        assert!(ends_with_separator(Path::new("a/b/")));
+        assert!(!ends_with_separator(Path::new("backup")));
+        assert!(!ends_with_separator(Path::new("")));
+        assert_eq!(ends_with_separator(Path::new("backup\\")), cfg!(windows));
+    }
+
+    #[test]
+    fn a_missing_trailing_slash_directory_is_never_a_file() {
+        let (dir, src, _) = conflict();
+        let dst = dir.path().join("backup/");
+        let err = copy_file(&src, &dst, &CopyOptions::new().force(true)).unwrap_err();
+        assert!(matches!(err, FmanError::DestinationDirMissing(_)), "{err}");
+        assert!(!dir.path().join("backup").exists());
+        let report = copy_file(&src, &dst, &CopyOptions::new().parents(true)).unwrap();
+        assert_eq!(report.destination, dir.path().join("backup/src.txt"));
+    }
+
+    /// Three 10-byte files: `s/a`, `s/b` and `s/c`.
+    fn tens() -> (TempDir, PathBuf) {
+        let dir = TempDir::new().unwrap();
+        let src = dir.path().join("s");
+        fs::create_dir(&src).unwrap();
+        for name in ["a", "b", "c"] {
+            fs::write(src.join(name), [b'x'; 10]).unwrap();
+        }
+        (dir, src)
+    }
+
+    #[test]
+    fn a_budget_stops_a_tree_copy_before_the_file_that_would_cross_it() {
+        let (dir, src) = tens();
+        let dst = dir.path().join("d");
+        let budget = ByteBudget::new(25);
+        let options = CopyOptions::new().budget(budget.clone());
+        let err = copy_dir(&src, &dst, &options).unwrap_err();
+        assert!(
+            matches!(
+                err,
+                FmanError::QuotaExceeded {
+                    limit: 25,
+                    written: 20
+                }
+            ),
+            "{err:?}"
+        );
+        assert!(dst.join("a").exists() && dst.join("b").exists());
+        assert!(!dst.join("c").exists());
+        assert_eq!(budget.written(), 20);
+    }
+
+    #[test]
+    fn a_budget_that_fits_is_reported() {
+        let (dir, src) = tens();
+        let options = CopyOptions::new().budget(ByteBudget::new(30));
+        let report = copy_dir(&src, &dir.path().join("d"), &options).unwrap();
+        assert_eq!(
+            report.budget,
+            Some(BudgetUsage {
+                limit: 30,
+                written: 30
+            })
+        );
+    }
+
+    #[test]
+    fn a_file_over_the_budget_is_never_created() {
+        let (dir, src) = tens();
+        let dst = dir.path().join("a");
+        let options = CopyOptions::new().budget(ByteBudget::new(9));
+        let err = copy_file(src.join("a"), &dst, &options).unwrap_err();
+        assert!(matches!(
+            err,
+            FmanError::QuotaExceeded {
+                limit: 9,
+                written: 0
+            }
+        ));
+        assert!(!dst.exists());
+    }
+
+    #[test]
+    fn a_streamed_copy_is_stopped_mid_file_and_its_partial_copy_removed() {
+        let dir = TempDir::new().unwrap();
+        let src = dir.path().join("big");
+        fs::write(&src, vec![b'x'; 3 * STREAM_BUFFER_SIZE]).unwrap();
+        let dst = dir.path().join("copy");
+        let budget = ByteBudget::new(STREAM_BUFFER_SIZE as u64 + 1);
+        let options = CopyOptions::new().stream(true).budget(budget.clone());
+        let err = copy_file(&src, &dst, &options).unwrap_err();
+        assert!(matches!(err, FmanError::QuotaExceeded { .. }), "{err:?}");
+        assert!(!dst.exists());
+        assert_eq!(budget.written(), 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn attributes_only_restores_scrambled_metadata_and_leaves_the_data() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let (dir, src) = tree();
+        let old = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        for file in ["a", "sub/b"] {
+            fs::set_permissions(src.join(file), fs::Permissions::from_mode(0o640)).unwrap();
+            File::options()
+                .write(true)
+                .open(src.join(file))
+                .unwrap()
+                .set_modified(old)
+                .unwrap();
+        }
+        let dst = dir.path().join("d");
+        copy_dir(&src, &dst, &CopyOptions::new()).unwrap();
+        // The data arrived some other way: same bytes, new metadata.
+        fs::write(dst.join("a"), "1").unwrap();
+        fs::set_permissions(dst.join("a"), fs::Permissions::from_mode(0o777)).unwrap();
+        fs::set_permissions(dst.join("sub/b"), fs::Permissions::from_mode(0o600)).unwrap();
+
+        let options = CopyOptions::new().attributes_only(&[Attribute::Mode, Attribute::Times]);
+        let report = copy_dir(&src, &dst, &options).unwrap();
+        assert_eq!(report.files, 0);
+        assert!(report.attributes >= 2, "{report:?}");
+        for file in ["a", "sub/b"] {
+            let meta = fs::metadata(dst.join(file)).unwrap();
+            assert_eq!(meta.permissions().mode() & 0o777, 0o640, "{file}");
+            assert_eq!(meta.modified().unwrap(), old, "{file}");
+        }
+        assert_eq!(fs::read_to_string(dst.join("a")).unwrap(), "1");
+        assert_eq!(fs::read_to_string(dst.join("sub/b")).unwrap(), "2");
+    }
+
+    #[test]
+    fn attributes_only_copies_no_data() {
+        let (_dir, src, dst) = conflict();
+        let before = fs::read(&dst).unwrap();
+        let recording = Arc::new(RecordingFs::new());
+        let options = CopyOptions::new()
+            .attributes_only(&Attribute::ALL)
+            .fs(recording.clone());
+        copy_file(&src, &dst, &options).unwrap();
+        assert!(copied_from(&recording).is_empty());
+        assert!(!recording.ops().iter().any(|op| matches!(
+            op,
+            FsOp::CreateFile { .. } | FsOp::Copy { .. } | FsOp::Rename { .. }
+        )));
+        assert_eq!(fs::read(&dst).unwrap(), before);
+    }
+
+    #[test]
+    fn attributes_only_needs_the_destination_unless_missing_ones_are_ignored() {
+        let (dir, src) = tree();
+        let dst = dir.path().join("d");
+        fs::create_dir_all(dst.join("sub")).unwrap();
+        fs::create_dir(dst.join("empty")).unwrap();
+        fs::write(dst.join("a"), "1").unwrap();
+        let options = CopyOptions::new().attributes_only(&[Attribute::Mode]);
+        let err = copy_dir(&src, &dst, &options).unwrap_err();
+        assert_eq!(err.exit_code(), 2, "{err}");
+        assert!(!dst.join("sub/b").exists());
+
+        let report = copy_dir(&src, &dst, &options.clone().ignore_missing(true)).unwrap();
+        assert_eq!(report.skipped, 1);
+        assert!(!dst.join("sub/b").exists());
+        let err = copy_file(
+            src.join("a"),
+            dir.path().join("nowhere"),
+            &CopyOptions::new().attributes_only(&[Attribute::Mode]),
+        )
+        .unwrap_err();
+        assert!(err.is_not_found(), "{err:?}");
+        assert!(!dir.path().join("nowhere").exists());
+    }
+
+    #[test]
+    fn attributes_only_refuses_a_directory_onto_a_file() {
+        let (dir, src) = tree();
+        let dst = dir.path().join("d");
+        fs::create_dir(&dst).unwrap();
+        fs::write(dst.join("sub"), "").unwrap();
+        let options = CopyOptions::new()
+            .attributes_only(&[Attribute::Mode])
+            .ignore_missing(true);
+        let err = copy_dir(&src, &dst, &options).unwrap_err();
+        assert_eq!(err.exit_code(), 4, "{err}");
+        assert!(err.to_string().contains("one is a directory"), "{err}");
+    }
+
+    #[cfg(unix)]
+    fn fifo(path: &Path) {
+        use std::os::unix::ffi::OsStrExt;
+
+        let c_path = std::ffi::CString::new(path.as_os_str().as_bytes()).unwrap();
+        // SAFETY: `c_path` is a valid NUL-terminated string.
+        assert_eq!(unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) }, 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_named_pipe_is_read_to_the_end() {
+        let dir = TempDir::new().unwrap();
+        let pipe = dir.path().join("pipe");
+        fifo(&pipe);
+        let data: Vec<u8> = (0..200_000u32).map(|i| i as u8).collect();
+        let writer = {
+            let (pipe, data) = (pipe.clone(), data.clone());
+            std::thread::spawn(move || fs::write(pipe, data).unwrap())
+        };
+        let dst = dir.path().join("copy");
+        let report = copy_file(&pipe, &dst, &CopyOptions::new()).unwrap();
+        writer.join().unwrap();
+        assert!(report.streamed);
+        assert_eq!(report.bytes, data.len() as u64);
+        assert_eq!(fs::read(&dst).unwrap(), data);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_named_pipe_inside_a_tree_is_not_read() {
+        let (dir, src) = tree();
+        fifo(&src.join("pipe"));
+        let err = copy_dir(&src, &dir.path().join("d"), &CopyOptions::new()).unwrap_err();
+        assert!(err.to_string().contains("pipe"), "{err}");
+        assert!(dir.path().join("d/a").exists());
+        assert!(!dir.path().join("d/pipe").exists());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn a_proc_file_is_streamed_despite_its_size_of_zero() {
+        let dir = TempDir::new().unwrap();
+        let src = Path::new("/proc/self/cmdline");
+        assert_eq!(fs::metadata(src).unwrap().len(), 0);
+        let dst = dir.path().join("cmdline");
+        let report = copy_file(src, &dst, &CopyOptions::new()).unwrap();
+        let copied = fs::read(&dst).unwrap();
+        assert!(report.streamed);
+        assert!(!copied.is_empty());
+        assert_eq!(report.bytes, copied.len() as u64);
+        assert_eq!(copied, fs::read(src).unwrap());
+    }
+
+    #[test]
+    fn streamed_progress_has_no_total() {
+        let (_dir, src, dst) = conflict();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let options = {
+            let seen = seen.clone();
+            CopyOptions::new()
+                .force(true)
+                .stream(true)
+                .progress(move |done, total| seen.lock().unwrap().push((done, total)))
+        };
+        let report = copy_file(&src, &dst, &options).unwrap();
+        assert!(report.streamed);
+        assert_eq!(*seen.lock().unwrap(), [(report.bytes, 0)]);
+    }
+
+    /// What a destination should resolve to, relative to the scratch root.
+    enum Resolves {
+        To(&'static str),
+        /// An error mentioning the raw input and this text.
+        Error(&'static str),
+    }
+
+    #[test]
+    fn destinations_with_dot_components_resolve_exactly() {
+        use Resolves::*;
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        fs::create_dir_all(root.join("r/sub")).unwrap();
+        fs::create_dir(root.join("src")).unwrap();
+        fs::write(root.join("src/file.txt"), "x").unwrap();
+        fs::write(root.join("r/afile"), "x").unwrap();
+        let src = root.join("src/file.txt");
+        let cases = [
+            ("r/sub", To("r/sub/file.txt")),
+            ("r/sub/", To("r/sub/file.txt")),
+            ("r/sub/.", To("r/sub/file.txt")),
+            ("r/./sub", To("r/sub/file.txt")),
+            ("r//sub///", To("r/sub/file.txt")),
+            ("r/sub/..", To("r/file.txt")),
+            ("r/sub/../", To("r/file.txt")),
+            ("r/sub/../.", To("r/file.txt")),
+            ("r/sub/./..", To("r/file.txt")),
+            ("r/sub/../sub", To("r/sub/file.txt")),
+            ("r/sub/..//sub/", To("r/sub/file.txt")),
+            ("r/sub/../..", To("file.txt")),
+            ("r/sub/../new", To("r/new")),
+            ("r/sub/../new/", To("r/new")),
+            ("r/sub//new", To("r/sub/new")),
+            ("r/missing", To("r/missing")),
+            ("r/missing/new", To("r/missing/new")),
+            ("r/afile", To("r/afile")),
+            ("r/missing/..", Error("r/missing does not exist")),
+            ("r/missing/../new", Error("r/missing does not exist")),
+            ("r/sub/missing/../..", Error("r/sub/missing does not exist")),
+            ("r/afile/..", Error("r/afile is not a directory")),
+            ("r/afile/../new", Error("r/afile is not a directory")),
+        ];
+        for (input, expected) in cases {
+            let dst = PathBuf::from(format!("{}/{input}", root.display()));
+            let resolved = resolve_destination_path(&src, &dst);
+            match expected {
+                To(path) => assert_eq!(resolved.unwrap(), root.join(path), "{input}"),
+                Error(text) => {
+                    let message = resolved.unwrap_err().to_string();
+                    assert!(
+                        message.contains(&dst.display().to_string()),
+                        "{input}: {message}"
+                    );
+                    assert!(
+                        message.contains(&root.join(text).display().to_string()),
+                        "{input}: {message}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn an_empty_destination_is_rejected() {
+        let err = resolve_destination_path(Path::new("a"), Path::new("")).unwrap_err();
+        assert!(matches!(err, FmanError::InvalidInput(_)));
+        assert!(err.to_string().contains("empty"), "{err}");
+    }
+
+    #[test]
+    fn a_relative_destination_error_names_the_absolute_path_too() {
+        let dst = Path::new("no-such-dir-for-fman-tests/..");
+        let message = resolve_destination_path(Path::new("a"), dst)
+            .unwrap_err()
+            .to_string();
+        let absolute = std::path::absolute(dst).unwrap();
+        assert!(
+            message.contains("no-such-dir-for-fman-tests/.."),
+            "{message}"
+        );
+        assert!(
+            message.contains(&absolute.display().to_string()),
+            "{message}"
+        );
+    }
+
+    #[test]
+    fn a_dry_run_resolves_dot_dot_through_real_directories_only() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        fs::create_dir(root.join("sub")).unwrap();
+        let dry_run = DryRunFs::new();
+        let resolved =
+            resolve_destination_in(Path::new("file.txt"), &root.join("sub/../sub"), &dry_run)
+                .unwrap();
+        assert_eq!(resolved, root.join("sub/file.txt"));
+    }
+
+    #[test]
+    fn errors_after_resolving_dot_dot_name_the_destination_as_given() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        fs::create_dir(root.join("d")).unwrap();
+        fs::write(root.join("a"), "a").unwrap();
+        fs::write(root.join("d/a"), "new").unwrap();
+        let given = root.join("d/..");
+        let err = copy_file(root.join("a"), &given, &CopyOptions::new()).unwrap_err();
+        assert!(
+            matches!(&err, FmanError::InvalidInput(what) if what.ends_with(&format!("(destination given as {})", given.display()))),
+            "{err:?}"
+        );
+        assert_eq!(err.exit_code(), 4);
+        let err = copy_file(root.join("d/a"), &given, &CopyOptions::new()).unwrap_err();
+        assert!(
+            matches!(&err, FmanError::AlreadyExists(what) if what.contains("given as")),
+            "{err:?}"
+        );
+        assert_eq!(err.exit_code(), 3);
+        assert_eq!(fs::read_to_string(root.join("a")).unwrap(), "a");
+    }
+
+    /// Ten-byte files `s/a`, `s/x/b` and `s/x/y/c` beside the empty
+    /// `s/w` and `s/x/y/z`, to be copied into `d/s`, which already holds
+    /// `keep` and an empty `x`.
+    fn partly_there() -> (TempDir, PathBuf, PathBuf) {
+        let dir = TempDir::new().unwrap();
+        let src = dir.path().join("s");
+        fs::create_dir_all(src.join("x/y/z")).unwrap();
+        fs::create_dir(src.join("w")).unwrap();
+        for name in ["a", "x/b", "x/y/c"] {
+            fs::write(src.join(name), [b'x'; 10]).unwrap();
+        }
+        let dst = dir.path().join("d");
+        fs::create_dir_all(dst.join("s/x")).unwrap();
+        fs::write(dst.join("s/keep"), "mine").unwrap();
+        (dir, src, dst)
+    }
+
+    /// Every path below `root`, relative to it.
+    fn paths_below(root: &Path) -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = Walk::new(root)
+            .flatten()
+            .map(|entry| entry.path().strip_prefix(root).unwrap().to_path_buf())
+            .collect();
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn a_stopped_tree_copy_removes_only_what_it_created_and_left_empty() {
+        for limit in [0, 5, 10, 15, 20, 25] {
+            let (_dir, src, dst) = partly_there();
+            let options = CopyOptions::new().budget(ByteBudget::new(limit));
+            let err = copy_dir(&src, &dst, &options).unwrap_err();
+            assert!(matches!(err, FmanError::QuotaExceeded { .. }), "{err}");
+
+            let copied = dst.join("s");
+            assert_eq!(fs::read_to_string(copied.join("keep")).unwrap(), "mine");
+            assert!(copied.join("x").is_dir(), "{limit}: pre-existing x removed");
+            let mut files = 0;
+            for path in paths_below(&copied) {
+                let full = copied.join(&path);
+                if full.is_file() {
+                    if path != Path::new("keep") {
+                        assert_eq!(fs::read(&full).unwrap(), [b'x'; 10], "{limit}: {path:?}");
+                        files += 1;
+                    }
+                } else if path != Path::new("x") {
+                    assert!(
+                        fs::read_dir(&full).unwrap().next().is_some(),
+                        "{limit}: created {path:?} left empty"
+                    );
+                }
+            }
+            assert_eq!(files, limit / 10, "{limit}");
+        }
+    }
+
+    #[test]
+    fn a_finished_tree_copy_keeps_its_empty_directories() {
+        let (_dir, src, dst) = partly_there();
+        copy_dir(&src, &dst, &CopyOptions::new()).unwrap();
+        assert!(dst.join("s/w").is_dir());
+        assert!(dst.join("s/x/y/z").is_dir());
+    }
+
+    #[test]
+    fn a_cancelled_tree_copy_removes_the_directories_it_created() {
+        let (_dir, src, dst) = partly_there();
+        fs::remove_dir_all(dst.join("s")).unwrap();
+        let token = CancelToken::new();
+        token.cancel();
+        let err = copy_dir(&src, &dst, &CopyOptions::new().cancel(token)).unwrap_err();
+        assert!(matches!(err, FmanError::Cancelled), "{err}");
+        assert_eq!(paths_below(&dst), Vec::<PathBuf>::new());
+    }
+
+    #[test]
+    fn a_caller_registry_takes_the_undoing_over() {
+        let (_dir, src, dst) = partly_there();
+        let registry = CleanupRegistry::new();
+        // The third file crosses the budget, after x/y is made for c.
+        let options = CopyOptions::new()
+            .budget(ByteBudget::new(25))
+            .cleanup(registry.clone());
+        copy_dir(&src, &dst, &options).unwrap_err();
+
+        // Nothing is undone yet, and only new directories are registered.
+        let registered: Vec<PathBuf> = registry
+            .take()
+            .into_iter()
+            .map(|action| match action {
+                Cleanup::RemoveEmptyDir(path) => path,
+                other => panic!("unexpected {other}"),
+            })
+            .collect();
+        assert!(registered.contains(&dst.join("s/x/y")), "{registered:?}");
+        for path in &registered {
+            assert!(path.is_dir(), "{path:?}");
+            assert!(
+                *path != dst.join("s") && *path != dst.join("s/x"),
+                "{path:?}"
+            );
+        }
+    }
+
+    /// The real filesystem, except that the copy to `broken` gets a
+    /// handle it cannot write through.
+    #[derive(Debug)]
+    struct BrokenWriteFs {
+        broken: PathBuf,
+    }
+
+    impl Fs for BrokenWriteFs {
+        fn create_file(&self, path: &Path, create_new: bool) -> io::Result<Option<File>> {
+            RealFs.create_file(path, create_new)
+        }
+        fn create_copy(
+            &self,
+            from: &Path,
+            to: &Path,
+            create_new: bool,
+        ) -> io::Result<Option<File>> {
+            let file = RealFs.create_copy(from, to, create_new)?;
+            if to != self.broken {
+                return Ok(file);
+            }
+            File::open(to).map(Some)
+        }
+        fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+            RealFs.rename(from, to)
+        }
+        fn remove_file(&self, path: &Path) -> io::Result<()> {
+            RealFs.remove_file(path)
+        }
+        fn remove_dir(&self, path: &Path) -> io::Result<()> {
+            RealFs.remove_dir(path)
+        }
+        fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+            RealFs.remove_dir_all(path)
+        }
+        fn create_dir(&self, path: &Path) -> io::Result<()> {
+            RealFs.create_dir(path)
+        }
+        fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+            RealFs.create_dir_all(path)
+        }
+        fn set_permissions(&self, path: &Path, permissions: fs::Permissions) -> io::Result<()> {
+            RealFs.set_permissions(path, permissions)
+        }
+        fn set_times(
+            &self,
+            path: &Path,
+            accessed: Option<SystemTime>,
+            modified: Option<SystemTime>,
+        ) -> io::Result<()> {
+            RealFs.set_times(path, accessed, modified)
+        }
+        fn set_owner(&self, path: &Path, uid: Option<u32>, gid: Option<u32>) -> io::Result<()> {
+            RealFs.set_owner(path, uid, gid)
+        }
+        fn set_xattr(&self, path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+            RealFs.set_xattr(path, name, value)
+        }
+        fn remove_xattr(&self, path: &Path, name: &str) -> io::Result<()> {
+            RealFs.remove_xattr(path, name)
+        }
+        fn symlink(&self, target: &Path, link: &Path) -> io::Result<()> {
+            RealFs.symlink(target, link)
+        }
+        fn hard_link(&self, original: &Path, link: &Path) -> io::Result<()> {
+            RealFs.hard_link(original, link)
+        }
+        fn set_file_permissions(
+            &self,
+            path: &Path,
+            file: &File,
+            permissions: fs::Permissions,
+        ) -> io::Result<()> {
+            RealFs.set_file_permissions(path, file, permissions)
+        }
+        fn set_file_times(
+            &self,
+            path: &Path,
+            file: &File,
+            accessed: Option<SystemTime>,
+            modified: Option<SystemTime>,
+        ) -> io::Result<()> {
+            RealFs.set_file_times(path, file, accessed, modified)
+        }
+        fn set_file_owner(
+            &self,
+            path: &Path,
+            file: &File,
+            uid: Option<u32>,
+            gid: Option<u32>,
+        ) -> io::Result<()> {
+            RealFs.set_file_owner(path, file, uid, gid)
+        }
+    }
+
+    /// The real filesystem, except that setting times fails as it may on
+    /// exotic filesystems.
+    #[derive(Debug)]
+    struct NoTimesFs;
+
+    impl Fs for NoTimesFs {
+        fn create_file(&self, path: &Path, create_new: bool) -> io::Result<Option<File>> {
+            RealFs.create_file(path, create_new)
+        }
+        fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+            RealFs.rename(from, to)
+        }
+        fn remove_file(&self, path: &Path) -> io::Result<()> {
+            RealFs.remove_file(path)
+        }
+        fn remove_dir(&self, path: &Path) -> io::Result<()> {
+            RealFs.remove_dir(path)
+        }
+        fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+            RealFs.remove_dir_all(path)
+        }
+        fn create_dir(&self, path: &Path) -> io::Result<()> {
+            RealFs.create_dir(path)
+        }
+        fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+            RealFs.create_dir_all(path)
+        }
+        fn set_permissions(&self, path: &Path, permissions: fs::Permissions) -> io::Result<()> {
+            RealFs.set_permissions(path, permissions)
+        }
+        fn set_times(
+            &self,
+            _: &Path,
+            _: Option<SystemTime>,
+            _: Option<SystemTime>,
+        ) -> io::Result<()> {
+            Err(io::Error::other("times not stored"))
+        }
+        fn set_owner(&self, path: &Path, uid: Option<u32>, gid: Option<u32>) -> io::Result<()> {
+            RealFs.set_owner(path, uid, gid)
+        }
+        fn set_xattr(&self, path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+            RealFs.set_xattr(path, name, value)
+        }
+        fn remove_xattr(&self, path: &Path, name: &str) -> io::Result<()> {
+            RealFs.remove_xattr(path, name)
+        }
+        fn symlink(&self, target: &Path, link: &Path) -> io::Result<()> {
+            RealFs.symlink(target, link)
+        }
+        fn hard_link(&self, original: &Path, link: &Path) -> io::Result<()> {
+            RealFs.hard_link(original, link)
+        }
+        fn set_file_permissions(
+            &self,
+            path: &Path,
+            file: &File,
+            permissions: fs::Permissions,
+        ) -> io::Result<()> {
+            RealFs.set_file_permissions(path, file, permissions)
+        }
+        fn set_file_times(
+            &self,
+            _: &Path,
+            _: &File,
+            _: Option<SystemTime>,
+            _: Option<SystemTime>,
+        ) -> io::Result<()> {
+            Err(io::Error::other("times not stored"))
+        }
+        fn set_file_owner(
+            &self,
+            path: &Path,
+            file: &File,
+            uid: Option<u32>,
+            gid: Option<u32>,
+        ) -> io::Result<()> {
+            RealFs.set_file_owner(path, file, uid, gid)
+        }
+    }
+
+    #[test]
+    fn preserve_gives_the_copy_the_source_times() {
+        let (_dir, src, dst) = conflict();
+        fs::remove_file(&dst).unwrap();
+        let accessed = SystemTime::UNIX_EPOCH + Duration::new(1_000_000_000, 123_456_789);
+        let modified = SystemTime::UNIX_EPOCH + Duration::new(1_100_000_000, 987_654_321);
+        File::options()
+            .write(true)
+            .open(&src)
+            .unwrap()
+            .set_times(
+                fs::FileTimes::new()
+                    .set_accessed(accessed)
+                    .set_modified(modified),
+            )
+            .unwrap();
+        copy_file(&src, &dst, &CopyOptions::new().preserve(true)).unwrap();
+        let meta = fs::metadata(&dst).unwrap();
+        assert_eq!(meta.modified().unwrap(), modified);
+        assert_eq!(meta.accessed().unwrap(), accessed);
+    }
+
+    #[test]
+    fn without_preserve_the_copy_gets_new_times() {
+        let (_dir, src, dst) = conflict();
+        fs::remove_file(&dst).unwrap();
+        let old = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        File::options()
+            .write(true)
+            .open(&src)
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+        copy_file(&src, &dst, &CopyOptions::new()).unwrap();
+        assert_ne!(fs::metadata(&dst).unwrap().modified().unwrap(), old);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn the_full_mode_is_copied() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let (_dir, src, dst) = conflict();
+        fs::remove_file(&dst).unwrap();
+        fs::set_permissions(&src, fs::Permissions::from_mode(0o751)).unwrap();
+        copy_file(&src, &dst, &CopyOptions::new().preserve(true)).unwrap();
+        let mode = fs::metadata(&dst).unwrap().permissions().mode();
+        assert_eq!(mode & 0o7777, 0o751);
+    }
+
+    #[test]
+    fn a_tree_copy_preserves_directory_times() {
+        let (dir, src) = tree();
+        let old = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        File::open(src.join("sub"))
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+        let dst = dir.path().join("d");
+        copy_dir(&src, &dst, &CopyOptions::new().preserve(true)).unwrap();
+        assert_eq!(
+            fs::metadata(dst.join("sub")).unwrap().modified().unwrap(),
+            old
+        );
+    }
+
+    #[test]
+    fn times_that_cannot_be_set_fail_naming_the_destination() {
+        let (_dir, src, dst) = conflict();
+        fs::remove_file(&dst).unwrap();
+        let options = CopyOptions::new().preserve(true).fs(Arc::new(NoTimesFs));
+        let err = copy_file(&src, &dst, &options).unwrap_err();
+        assert!(matches!(err, FmanError::Io(_)), "{err:?}");
+        assert!(
+            err.to_string().contains(&dst.display().to_string()),
+            "{err}"
+        );
+        assert!(err.to_string().contains("times not stored"), "{err}");
+    }
+
+    #[test]
+    fn times_are_not_touched_without_preserve() {
+        let (_dir, src, dst) = conflict();
+        fs::remove_file(&dst).unwrap();
+        copy_file(&src, &dst, &CopyOptions::new().fs(Arc::new(NoTimesFs))).unwrap();
+    }
+
+    #[test]
+    fn a_file_that_fails_part_way_is_removed_while_the_rest_stay() {
+        let (_dir, src, dst) = partly_there();
+        let broken = dst.join("s/x/b");
+        let options = CopyOptions::new().fs(Arc::new(BrokenWriteFs {
+            broken: broken.clone(),
+        }));
+        copy_dir(&src, &dst, &options).unwrap_err();
+        assert!(!broken.exists());
+        assert!(dst.join("s/a").exists() && dst.join("s/x/y/c").exists());
+        assert!(
+            dst.join("s/w").is_dir(),
+            "collected failures roll nothing back"
+        );
+        assert_eq!(fs::read_to_string(dst.join("s/keep")).unwrap(), "mine");
+    }
+
+    #[test]
+    fn a_single_file_that_fails_is_removed_with_the_directory_made_for_it() {
+        let dir = TempDir::new().unwrap();
+        let src = dir.path().join("src.txt");
+        fs::write(&src, "data").unwrap();
+        let dst = dir.path().join("new/dir/dst.txt");
+        let options = CopyOptions::new().parents(true).fs(Arc::new(BrokenWriteFs {
+            broken: dst.clone(),
+        }));
+        copy_file(&src, &dst, &options).unwrap_err();
+        assert!(!dir.path().join("new").exists());
+        assert!(src.exists());
+    }
+
+    #[test]
+    fn a_file_that_was_there_before_is_never_removed() {
+        let (_dir, src, dst) = conflict();
+        let options = CopyOptions::new().force(true).fs(Arc::new(BrokenWriteFs {
+            broken: dst.clone(),
+        }));
+        copy_file(&src, &dst, &options).unwrap_err();
+        assert!(dst.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn no_dereference_recreates_the_link_with_the_same_target() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("f"), "file").unwrap();
+        let link = dir.path().join("link");
+        std::os::unix::fs::symlink("f", &link).unwrap();
+        let dst = dir.path().join("copy");
+        let options = CopyOptions::new().symlinks(SymlinkPolicy::Never);
+
+        let report = copy_file(&link, &dst, &options).unwrap();
+        assert_eq!(fs::read_link(&dst).unwrap(), Path::new("f"));
+        assert_eq!(report.link_target.as_deref(), Some(Path::new("f")));
+        assert_eq!(report.bytes, 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn no_dereference_copies_a_dangling_link() {
+        let dir = TempDir::new().unwrap();
+        let link = dir.path().join("link");
+        std::os::unix::fs::symlink("nowhere", &link).unwrap();
+        let dst = dir.path().join("copy");
+
+        let err = copy_file(&link, &dst, &CopyOptions::new()).unwrap_err();
+        assert!(err.is_not_found(), "{err}");
+        let message = err.to_string();
+        assert!(
+            message.contains("a dangling symlink to nowhere"),
+            "{message}"
+        );
+        assert!(message.contains("--no-dereference"), "{message}");
+        assert!(fs::symlink_metadata(&dst).is_err());
+
+        let options = CopyOptions::new().symlinks(SymlinkPolicy::Never);
+        copy_file(&link, &dst, &options).unwrap();
+        assert_eq!(fs::read_link(&dst).unwrap(), Path::new("nowhere"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn no_dereference_keeps_an_existing_destination_unless_forced() {
+        let (dir, _, dst) = conflict();
+        let link = dir.path().join("link");
+        std::os::unix::fs::symlink("elsewhere", &link).unwrap();
+        let options = CopyOptions::new().symlinks(SymlinkPolicy::Never);
+
+        let err = copy_file(&link, &dst, &options).unwrap_err();
+        assert!(matches!(err, FmanError::AlreadyExists(_)), "{err}");
+        assert_eq!(fs::read_to_string(&dst).unwrap(), "old");
+
+        copy_file(&link, &dst, &options.force(true)).unwrap();
+        assert_eq!(fs::read_link(&dst).unwrap(), Path::new("elsewhere"));
+    }
+
+    #[test]
+    fn backup_moves_the_old_destination_aside() {
+        let (dir, src, dst) = conflict();
+        let report = copy_file(&src, &dst, &CopyOptions::new().backup(true)).unwrap();
+        let backup = dir.path().join("dst.txt.bak");
+        assert_eq!(report.backup.as_deref(), Some(backup.as_path()));
+        assert_eq!(fs::read_to_string(&backup).unwrap(), "old");
+        assert_eq!(fs::read_to_string(&dst).unwrap(), "new");
+    }
+
+    #[test]
+    fn repeated_backups_never_replace_an_earlier_one() {
+        let (dir, src, dst) = conflict();
+        let options = CopyOptions::new().backup(true);
+        copy_file(&src, &dst, &options).unwrap();
+        for round in ["second", "third"] {
+            fs::write(&src, round).unwrap();
+            copy_file(&src, &dst, &options).unwrap();
+        }
+        let read = |name: &str| fs::read_to_string(dir.path().join(name)).unwrap();
+        assert_eq!(read("dst.txt.bak"), "old");
+        assert_eq!(read("dst.txt.bak.1"), "new");
+        assert_eq!(read("dst.txt.bak.2"), "second");
+        assert_eq!(read("dst.txt"), "third");
+    }
+
+    #[test]
+    fn backup_takes_a_custom_suffix() {
+        let (dir, src, dst) = conflict();
+        let options = CopyOptions::new().backup(true).backup_suffix("~");
+        copy_file(&src, &dst, &options).unwrap();
+        copy_file(&src, &dst, &options).unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.path().join("dst.txt~")).unwrap(),
+            "old"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.path().join("dst.txt~.1")).unwrap(),
+            "new"
+        );
+    }
+
+    #[test]
+    fn no_backup_is_made_of_a_missing_destination() {
+        let (dir, src, _) = conflict();
+        let dst = dir.path().join("fresh.txt");
+        let report = copy_file(&src, &dst, &CopyOptions::new().backup(true)).unwrap();
+        assert_eq!(report.backup, None);
+        assert!(!dir.path().join("fresh.txt.bak").exists());
+    }
+
+    #[test]
+    fn a_failed_copy_puts_the_backup_back() {
+        let (dir, src, dst) = conflict();
+        let options = CopyOptions::new().backup(true).fs(Arc::new(BrokenWriteFs {
+            broken: dst.clone(),
+        }));
+        copy_file(&src, &dst, &options).unwrap_err();
+        assert_eq!(fs::read_to_string(&dst).unwrap(), "old");
+        assert!(!dir.path().join("dst.txt.bak").exists());
+    }
+
+    #[test]
+    fn a_dry_run_backup_only_records_the_move() {
+        let (dir, src, dst) = conflict();
+        let dry_run = Arc::new(DryRunFs::new());
+        let options = CopyOptions::new().backup(true).fs(dry_run.clone());
+        copy_file(&src, &dst, &options).unwrap();
+        let backup = dir.path().join("dst.txt.bak");
+        assert!(dry_run.ops().contains(&FsOp::Rename {
+            from: dst.clone(),
+            to: backup.clone(),
+        }));
+        assert!(!backup.exists());
+        assert_eq!(fs::read_to_string(&dst).unwrap(), "old");
+    }
+
+    #[test]
+    fn a_declined_overwrite_skips_the_file() {
+        let (_dir, src, dst) = conflict();
+        let asked = Arc::new(Mutex::new(Vec::new()));
+        let seen = asked.clone();
+        let options = CopyOptions::new().confirm_overwrite(move |path| {
+            seen.lock().unwrap().push(path.to_path_buf());
+            false
+        });
+        let report = copy_file(&src, &dst, &options).unwrap();
+        assert_eq!(report.status, CopyStatus::Skipped);
+        assert_eq!(*asked.lock().unwrap(), [dst.as_path()]);
+        assert_eq!(fs::read_to_string(&dst).unwrap(), "old");
+    }
+
+    #[test]
+    fn an_accepted_overwrite_replaces_the_file() {
+        let (_dir, src, dst) = conflict();
+        let options = CopyOptions::new().confirm_overwrite(|_| true);
+        let report = copy_file(&src, &dst, &options).unwrap();
+        assert_eq!(report.status, CopyStatus::Copied);
+        assert_eq!(fs::read_to_string(&dst).unwrap(), "new");
+    }
+
+    #[test]
+    fn overwrite_is_only_asked_about_an_existing_file_when_not_forced() {
+        let (dir, src, dst) = conflict();
+        let never = |_: &Path| -> bool { panic!("asked") };
+        copy_file(
+            &src,
+            dir.path().join("fresh"),
+            &CopyOptions::new().confirm_overwrite(never),
+        )
+        .unwrap();
+        copy_file(
+            &src,
+            &dst,
+            &CopyOptions::new().force(true).confirm_overwrite(never),
+        )
+        .unwrap();
+        assert_eq!(fs::read_to_string(&dst).unwrap(), "new");
+    }
+
+    /// Overwrites one byte of each file it is asked to give times to,
+    /// which [`finish`] does before the copy is verified.
+    #[derive(Debug)]
+    struct CorruptingFs {
+        at: u64,
+    }
+
+    impl Fs for CorruptingFs {
+        fn create_file(&self, path: &Path, create_new: bool) -> io::Result<Option<File>> {
+            RealFs.create_file(path, create_new)
+        }
+        fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+            RealFs.rename(from, to)
+        }
+        fn remove_file(&self, path: &Path) -> io::Result<()> {
+            RealFs.remove_file(path)
+        }
+        fn remove_dir(&self, path: &Path) -> io::Result<()> {
+            RealFs.remove_dir(path)
+        }
+        fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+            RealFs.remove_dir_all(path)
+        }
+        fn create_dir(&self, path: &Path) -> io::Result<()> {
+            RealFs.create_dir(path)
+        }
+        fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+            RealFs.create_dir_all(path)
+        }
+        fn set_permissions(&self, path: &Path, permissions: fs::Permissions) -> io::Result<()> {
+            RealFs.set_permissions(path, permissions)
+        }
+        fn set_times(
+            &self,
+            path: &Path,
+            accessed: Option<SystemTime>,
+            modified: Option<SystemTime>,
+        ) -> io::Result<()> {
+            RealFs.set_times(path, accessed, modified)
+        }
+        fn set_owner(&self, path: &Path, uid: Option<u32>, gid: Option<u32>) -> io::Result<()> {
+            RealFs.set_owner(path, uid, gid)
+        }
+        fn set_xattr(&self, path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+            RealFs.set_xattr(path, name, value)
+        }
+        fn remove_xattr(&self, path: &Path, name: &str) -> io::Result<()> {
+            RealFs.remove_xattr(path, name)
+        }
+        fn symlink(&self, target: &Path, link: &Path) -> io::Result<()> {
+            RealFs.symlink(target, link)
+        }
+        fn hard_link(&self, original: &Path, link: &Path) -> io::Result<()> {
+            RealFs.hard_link(original, link)
+        }
+        fn set_file_permissions(
+            &self,
+            path: &Path,
+            file: &File,
+            permissions: fs::Permissions,
+        ) -> io::Result<()> {
+            RealFs.set_file_permissions(path, file, permissions)
+        }
+        fn set_file_times(
+            &self,
+            path: &Path,
+            file: &File,
+            accessed: Option<SystemTime>,
+            modified: Option<SystemTime>,
+        ) -> io::Result<()> {
+            let mut corrupt = File::options().read(true).write(true).open(path)?;
+            let mut byte = [0];
+            io::Seek::seek(&mut corrupt, io::SeekFrom::Start(self.at))?;
+            io::Read::read_exact(&mut corrupt, &mut byte)?;
+            io::Seek::seek(&mut corrupt, io::SeekFrom::Start(self.at))?;
+            io::Write::write_all(&mut corrupt, &[!byte[0]])?;
+            RealFs.set_file_times(path, file, accessed, modified)
+        }
+        fn set_file_owner(
+            &self,
+            path: &Path,
+            file: &File,
+            uid: Option<u32>,
+            gid: Option<u32>,
+        ) -> io::Result<()> {
+            RealFs.set_file_owner(path, file, uid, gid)
+        }
+    }
+
+    /// `len` bytes that repeat nowhere a read buffer could line up with.
+    fn noise(len: usize) -> Vec<u8> {
+        let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn verify_checks_a_file_larger_than_any_buffer() {
+        let dir = TempDir::new().unwrap();
+        let (src, dst) = (dir.path().join("big"), dir.path().join("copy"));
+        let data = noise(5 * 1024 * 1024 + 17);
+        fs::write(&src, &data).unwrap();
+        let report = copy_file(&src, &dst, &CopyOptions::new().verify(true)).unwrap();
+        assert!(report.verified);
+        assert_eq!(fs::read(&dst).unwrap(), data);
+    }
+
+    #[test]
+    fn a_copy_that_does_not_match_is_removed() {
+        let dir = TempDir::new().unwrap();
+        let (src, dst) = (dir.path().join("big"), dir.path().join("copy"));
+        let len = 5 * 1024 * 1024 + 17;
+        fs::write(&src, noise(len)).unwrap();
+        let options = CopyOptions::new()
+            .verify(true)
+            .preserve(true)
+            .fs(Arc::new(CorruptingFs { at: len as u64 - 3 }));
+        let err = copy_file(&src, &dst, &options).unwrap_err();
+        assert!(matches!(err, FmanError::VerificationFailed(_)), "{err}");
+        let message = err.to_string();
+        assert!(message.contains(&src.display().to_string()), "{message}");
+        assert!(message.contains(&dst.display().to_string()), "{message}");
+        assert!(!dst.exists());
+        assert_eq!(fs::read(&src).unwrap().len(), len);
+    }
+
+    #[test]
+    fn without_verify_a_mismatch_goes_unnoticed() {
+        let (_dir, src, dst) = conflict();
+        fs::remove_file(&dst).unwrap();
+        let options = CopyOptions::new()
+            .preserve(true)
+            .fs(Arc::new(CorruptingFs { at: 0 }));
+        let report = copy_file(&src, &dst, &options).unwrap();
+        assert!(!report.verified);
+        assert_ne!(fs::read(&dst).unwrap(), b"new");
+    }
+
+    #[test]
+    fn a_dry_run_is_not_verified() {
+        let (dir, src, _) = conflict();
+        let dst = dir.path().join("planned");
+        let options = CopyOptions::new()
+            .verify(true)
+            .fs(Arc::new(DryRunFs::new()));
+        let report = copy_file(&src, &dst, &options).unwrap();
+        assert!(!report.verified);
+        assert!(!dst.exists());
+    }
+}
@@ -0,0 +1,271 @@
+//! Finding files with identical contents below a directory, for
+//! `fman dedupe`.
+//!
+//! Files are bucketed by size first; only those that share their size with
+//! another file are hashed, so a tree of mostly unique sizes costs little
+//! more than a walk.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "json")]
+use serde::Serialize;
+
+use crate::error::{DEFAULT_MAX_ERRORS, ErrorList, FmanResult};
+use crate::fs::Fs;
+use crate::hash::{HashAlgorithm, hash_paths};
+use crate::validate::{ensure_exists, ensure_is_dir};
+use crate::walk::{SymlinkPolicy, Walk};
+
+#[derive(Debug, Clone, Default)]
+pub struct DedupeOptions {
+    include_empty: bool,
+}
+
+impl DedupeOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Group empty files too; by default they are left out, as every
+    /// empty file is a duplicate of every other.
+    pub fn include_empty(mut self, include: bool) -> Self {
+        self.include_empty = include;
+        self
+    }
+}
+
+/// Files with the same contents.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "json", derive(Serialize))]
+pub struct DuplicateGroup {
+    /// Size of each file.
+    pub size: u64,
+    /// SHA-256 of the contents, lowercase hex.
+    pub digest: String,
+    /// In path order; at least two.
+    pub paths: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "json", derive(Serialize))]
+pub struct DedupeReport {
+    /// Ordered by their first path.
+    pub groups: Vec<DuplicateGroup>,
+    /// Regular files seen.
+    pub files: u64,
+    /// Files whose size matched another's and were hashed.
+    pub hashed: u64,
+    /// Bytes taken by every copy but the first of each group.
+    pub redundant_bytes: u64,
+    /// Entries that could not be read or hashed, left out of every group.
+    pub errors: Vec<String>,
+}
+
+/// Find the groups of regular files below `root` with identical contents.
+/// Symlinks are neither followed nor grouped.
+pub fn find_duplicates(root: &Path, options: &DedupeOptions) -> FmanResult<DedupeReport> {
+    ensure_exists(root)?;
+    ensure_is_dir(root)?;
+
+    let mut report = DedupeReport::default();
+    let mut by_size: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+    for entry in Walk::new(root).symlinks(SymlinkPolicy::Never) {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                report.errors.push(e.to_string());
+                continue;
+            }
+        };
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        report.files += 1;
+        match entry.metadata() {
+            Ok(meta) if meta.len() > 0 || options.include_empty => by_size
+                .entry(meta.len())
+                .or_default()
+                .push(entry.path().to_path_buf()),
+            Ok(_) => {}
+            Err(e) => report.errors.push(e.to_string()),
+        }
+    }
+
+    let candidates: Vec<(u64, PathBuf)> = by_size
+        .into_iter()
+        .filter(|(_, paths)| paths.len() > 1)
+        .flat_map(|(size, paths)| paths.into_iter().map(move |path| (size, path)))
+        .collect();
+    report.hashed = candidates.len() as u64;
+    let paths: Vec<PathBuf> = candidates.iter().map(|(_, path)| path.clone()).collect();
+    let digests = hash_paths(&paths, HashAlgorithm::Sha256, false);
+
+    let mut by_content: BTreeMap<(u64, String), Vec<PathBuf>> = BTreeMap::new();
+    for ((size, path), digest) in candidates.into_iter().zip(digests) {
+        match digest {
+            Ok(digest) => by_content.entry((size, digest)).or_default().push(path),
+            Err(e) => report.errors.push(e.to_string()),
+        }
+    }
+    for ((size, digest), mut paths) in by_content {
+        if paths.len() < 2 {
+            continue;
+        }
+        paths.sort();
+        report.redundant_bytes += size * (paths.len() as u64 - 1);
+        report.groups.push(DuplicateGroup {
+            size,
+            digest,
+            paths,
+        });
+    }
+    report.groups.sort_by(|a, b| a.paths[0].cmp(&b.paths[0]));
+    Ok(report)
+}
+
+/// What [`remove_duplicates`] removed.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "json", derive(Serialize))]
+pub struct DedupeRemoval {
+    pub removed: Vec<PathBuf>,
+    pub bytes: u64,
+}
+
+/// Remove every file of each group but the first, through `filesystem`,
+/// carrying on past failures and returning them together.
+pub fn remove_duplicates(
+    groups: &[DuplicateGroup],
+    filesystem: &dyn Fs,
+) -> FmanResult<DedupeRemoval> {
+    let mut removal = DedupeRemoval::default();
+    let mut failures = ErrorList::new(DEFAULT_MAX_ERRORS);
+    for group in groups {
+        for path in group.paths.iter().skip(1) {
+            match filesystem.remove_file(path) {
+                Ok(()) => {
+                    removal.removed.push(path.clone());
+                    removal.bytes += group.size;
+                }
+                Err(e) => failures.push(path, e.into()),
+            }
+        }
+    }
+    failures.into_result(removal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    use crate::fs::{DryRunFs, RealFs};
+
+    /// `a`, `sub/b` and `sub/c` holding the same bytes, `d` the same size
+    /// but different, `e` a unique size, and two empty files.
+    fn tree() -> TempDir {
+        let dir = TempDir::new().unwrap();
+        let files = [
+            ("a", "same"),
+            ("sub/b", "same"),
+            ("sub/c", "same"),
+            ("d", "diff"),
+            ("e", "unique size"),
+            ("empty1", ""),
+            ("sub/empty2", ""),
+        ];
+        for (rel, contents) in files {
+            let path = dir.path().join(rel);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn identical_files_are_grouped_in_path_order() {
+        let dir = tree();
+        let report = find_duplicates(dir.path(), &DedupeOptions::new()).unwrap();
+        assert_eq!(report.groups.len(), 1);
+        let group = &report.groups[0];
+        assert_eq!(group.size, 4);
+        assert_eq!(
+            group.paths,
+            ["a", "sub/b", "sub/c"].map(|rel| dir.path().join(rel))
+        );
+        assert_eq!(report.redundant_bytes, 8);
+        assert_eq!(report.files, 7);
+        assert!(report.errors.is_empty());
+    }
+
+    #[test]
+    fn only_files_sharing_a_size_are_hashed() {
+        let dir = tree();
+        let report = find_duplicates(dir.path(), &DedupeOptions::new()).unwrap();
+        // a, sub/b, sub/c and d are 4 bytes; e is alone in its size and the
+        // empty files are left out.
+        assert_eq!(report.hashed, 4);
+    }
+
+    #[test]
+    fn empty_files_are_grouped_only_when_asked() {
+        let dir = tree();
+        let options = DedupeOptions::new().include_empty(true);
+        let report = find_duplicates(dir.path(), &options).unwrap();
+        assert_eq!(report.groups.len(), 2);
+        let empty = report.groups.iter().find(|g| g.size == 0).unwrap();
+        assert_eq!(
+            empty.paths,
+            [dir.path().join("empty1"), dir.path().join("sub/empty2")]
+        );
+        assert_eq!(report.hashed, 6);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_are_neither_followed_nor_grouped() {
+        let dir = tree();
+        std::os::unix::fs::symlink("a", dir.path().join("link")).unwrap();
+        std::os::unix::fs::symlink("sub", dir.path().join("sublink")).unwrap();
+        let report = find_duplicates(dir.path(), &DedupeOptions::new()).unwrap();
+        assert_eq!(report.groups[0].paths.len(), 3);
+        assert_eq!(report.files, 7);
+    }
+
+    #[test]
+    fn the_root_must_be_a_directory() {
+        let dir = tree();
+        let err = find_duplicates(&dir.path().join("a"), &DedupeOptions::new()).unwrap_err();
+        assert_eq!(err.exit_code(), 4);
+        let err = find_duplicates(&dir.path().join("missing"), &DedupeOptions::new()).unwrap_err();
+        assert!(err.is_not_found(), "{err}");
+    }
+
+    #[test]
+    fn removing_keeps_the_first_of_each_group() {
+        let dir = tree();
+        let report = find_duplicates(dir.path(), &DedupeOptions::new()).unwrap();
+        let removal = remove_duplicates(&report.groups, &RealFs).unwrap();
+        assert_eq!(
+            removal.removed,
+            [dir.path().join("sub/b"), dir.path().join("sub/c")]
+        );
+        assert_eq!(removal.bytes, 8);
+        assert!(dir.path().join("a").exists());
+        assert!(dir.path().join("d").exists());
+    }
+
+    #[test]
+    fn removing_goes_through_the_filesystem() {
+        let dir = tree();
+        let report = find_duplicates(dir.path(), &DedupeOptions::new()).unwrap();
+        let dry_run = DryRunFs::new();
+        let removal = remove_duplicates(&report.groups, &dry_run).unwrap();
+        assert_eq!(removal.removed.len(), 2);
+        assert_eq!(dry_run.ops().len(), 2);
+        assert!(dir.path().join("sub/b").exists());
+    }
+}
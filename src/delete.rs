@@ -0,0 +1,613 @@
+//! Removing files and directory trees.
+
+use std::fs::{self, FileType};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+#[cfg(feature = "json")]
+use serde::Serialize;
+
+use crate::error::{DEFAULT_MAX_ERRORS, Failures, FmanError, FmanResult};
+use crate::fs::{Fs, RealFs, SharedFs};
+#[cfg(target_os = "linux")]
+use crate::open_files::{self, DeletedFiles, OpenHolder};
+use crate::platform;
+
+#[derive(Debug, Clone)]
+pub struct DeleteOptions {
+    recursive: bool,
+    force: bool,
+    ignore_vanished: bool,
+    fs: Option<SharedFs>,
+    max_errors: usize,
+    error_log: Option<PathBuf>,
+    #[cfg(target_os = "linux")]
+    report_open: bool,
+}
+
+impl Default for DeleteOptions {
+    fn default() -> Self {
+        Self {
+            recursive: false,
+            force: false,
+            ignore_vanished: true,
+            fs: None,
+            max_errors: DEFAULT_MAX_ERRORS,
+            error_log: None,
+            #[cfg(target_os = "linux")]
+            report_open: false,
+        }
+    }
+}
+
+impl DeleteOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Remove directories and everything below them.
+    pub fn recursive(mut self, recursive: bool) -> Self {
+        self.recursive = recursive;
+        self
+    }
+
+    /// Make directories this process cannot modify writable before
+    /// emptying them, like `rm -rf`, instead of failing with
+    /// `PermissionDenied` for each. A target that does not exist is then
+    /// not an error either, so deleting is idempotent.
+    pub fn force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    /// Count entries found by the walk that are gone by the time they are
+    /// removed as vanished instead of failing (on by default). The target
+    /// itself missing is always an error.
+    pub fn ignore_vanished(mut self, ignore: bool) -> Self {
+        self.ignore_vanished = ignore;
+        self
+    }
+
+    /// Make every change to the filesystem through `fs`.
+    pub fn fs(mut self, fs: SharedFs) -> Self {
+        self.fs = Some(fs);
+        self
+    }
+
+    /// Keep at most `max` failures for the final error; later ones are only
+    /// counted.
+    pub fn max_errors(mut self, max: usize) -> Self {
+        self.max_errors = max;
+        self
+    }
+
+    /// Append every failure to the file at `path`, however many there are.
+    pub fn error_log(mut self, path: impl Into<PathBuf>) -> Self {
+        self.error_log = Some(path.into());
+        self
+    }
+
+    /// After deleting, look for processes that still hold removed files
+    /// open and so keep their space in use (Linux).
+    #[cfg(target_os = "linux")]
+    pub fn report_open(mut self, report: bool) -> Self {
+        self.report_open = report;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "json", derive(Serialize))]
+pub struct DeleteReport {
+    /// Files and symlinks removed.
+    pub files: u64,
+    pub directories: u64,
+    /// Entries that disappeared between the walk and their removal.
+    pub vanished: u64,
+    /// Processes still holding removed files open, with
+    /// [`DeleteOptions::report_open`].
+    #[cfg(target_os = "linux")]
+    pub still_open: Vec<OpenHolder>,
+    /// Bytes of removed files that stay allocated until those processes
+    /// close them.
+    #[cfg(target_os = "linux")]
+    pub pinned_bytes: u64,
+}
+
+/// Delete `target`. A symlink is removed itself, never its target; a
+/// directory needs [`DeleteOptions::recursive`] and is removed bottom-up,
+/// every entry before the directory holding it, carrying on past entries
+/// that cannot be removed and returning their failures together at the
+/// end.
+pub fn delete_path(target: &Path, options: &DeleteOptions) -> FmanResult<DeleteReport> {
+    let filesystem = options.fs.clone().unwrap_or_else(|| Arc::new(RealFs));
+    let mut report = DeleteReport::default();
+    let meta = match fs::symlink_metadata(target) {
+        Ok(meta) => meta,
+        Err(e) if options.force && e.kind() == io::ErrorKind::NotFound => return Ok(report),
+        Err(_) => return Err(FmanError::missing_path(target)),
+    };
+    #[cfg(target_os = "linux")]
+    let mut deleted = options.report_open.then(DeletedFiles::new);
+
+    if !meta.is_dir() {
+        match filesystem.remove_file(target) {
+            Err(e) if options.force && e.kind() == io::ErrorKind::NotFound => return Ok(report),
+            result => result.map_err(|e| FmanError::io_at(target, e))?,
+        }
+        report.files += 1;
+        #[cfg(target_os = "linux")]
+        if let Some(deleted) = &mut deleted {
+            open_files::track(deleted, target, &meta);
+            report_holders(&mut report, deleted);
+        }
+        return Ok(report);
+    }
+    if !options.recursive {
+        return Err(FmanError::InvalidInput(format!(
+            "{} is a directory (use --recursive)",
+            target.display()
+        )));
+    }
+
+    let mut failures = Failures::new(options.max_errors, options.error_log.as_deref())?;
+    let mut removal = Removal {
+        options,
+        filesystem: filesystem.as_ref(),
+        report: &mut report,
+        failures: &mut failures,
+        #[cfg(target_os = "linux")]
+        deleted: deleted.as_mut(),
+    };
+    removal.remove_tree(target)?;
+    #[cfg(target_os = "linux")]
+    if let Some(deleted) = &deleted {
+        report_holders(&mut report, deleted);
+    }
+    failures.into_result(report)
+}
+
+/// A directory being emptied, with the entries still to remove.
+struct OpenDir {
+    path: PathBuf,
+    entries: Vec<(PathBuf, FileType)>,
+    /// Something below it could not be removed, so neither can it.
+    failed: bool,
+}
+
+/// State of one recursive delete.
+struct Removal<'a> {
+    options: &'a DeleteOptions,
+    filesystem: &'a dyn Fs,
+    report: &'a mut DeleteReport,
+    failures: &'a mut Failures,
+    #[cfg(target_os = "linux")]
+    deleted: Option<&'a mut DeletedFiles>,
+}
+
+impl Removal<'_> {
+    /// Remove the tree at `root` bottom-up: a directory is removed only
+    /// once every entry in it is, so a failure keeps all its ancestors.
+    /// Directories are read one at a time, each when it is reached, so
+    /// with [`DeleteOptions::force`] one can be made writable before it
+    /// is read.
+    fn remove_tree(&mut self, root: &Path) -> FmanResult<()> {
+        let mut stack = Vec::new();
+        if let Some(dir) = self.open(root)? {
+            stack.push(dir);
+        }
+        while let Some(dir) = stack.last_mut() {
+            let Some((path, file_type)) = dir.entries.pop() else {
+                let dir = stack.pop().expect("a directory is open");
+                let removed = !dir.failed && self.remove(&dir.path, true)?;
+                if !removed && let Some(parent) = stack.last_mut() {
+                    parent.failed = true;
+                }
+                continue;
+            };
+            if file_type.is_dir() {
+                match self.open(&path)? {
+                    Some(child) => stack.push(child),
+                    None => dir.failed = true,
+                }
+            } else if !self.remove(&path, false)? {
+                dir.failed = true;
+            }
+        }
+        Ok(())
+    }
+
+    /// Read the directory at `path`, first making it writable under
+    /// `force`. `None` if it cannot be emptied; the failure is recorded.
+    fn open(&mut self, path: &Path) -> FmanResult<Option<OpenDir>> {
+        if !platform::can_modify_dir(path) && fs::symlink_metadata(path).is_ok() {
+            if !self.options.force {
+                let denied = FmanError::PermissionDenied(format!(
+                    "{} is read-only, so its entries cannot be removed (use --force)",
+                    path.display()
+                ));
+                self.failures.push(path, denied)?;
+                return Ok(None);
+            }
+            // Nothing to restore: the directory is about to go.
+            let meta = fs::symlink_metadata(path)?;
+            if let Err(e) = self
+                .filesystem
+                .set_permissions(path, platform::owner_rwx(&meta))
+            {
+                self.failures.push(path, FmanError::io_at(path, e))?;
+                return Ok(None);
+            }
+        }
+        let read = fs::read_dir(path).and_then(|entries| {
+            entries
+                .map(|entry| entry.and_then(|entry| Ok((entry.path(), entry.file_type()?))))
+                .collect::<io::Result<Vec<_>>>()
+        });
+        match read {
+            Ok(entries) => Ok(Some(OpenDir {
+                path: path.to_path_buf(),
+                entries,
+                failed: false,
+            })),
+            Err(e) if self.options.ignore_vanished && e.kind() == io::ErrorKind::NotFound => {
+                self.report.vanished += 1;
+                Ok(None)
+            }
+            Err(e) => {
+                self.failures.push(path, FmanError::io_at(path, e))?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Remove one entry, tallying the outcome. Returns whether it is gone.
+    fn remove(&mut self, path: &Path, is_dir: bool) -> FmanResult<bool> {
+        #[cfg(target_os = "linux")]
+        let meta = self
+            .deleted
+            .is_some()
+            .then(|| fs::symlink_metadata(path).ok())
+            .flatten();
+        let removed = if is_dir {
+            self.filesystem.remove_dir(path)
+        } else {
+            self.filesystem.remove_file(path)
+        };
+        match removed {
+            Ok(()) if is_dir => self.report.directories += 1,
+            Ok(()) => {
+                self.report.files += 1;
+                #[cfg(target_os = "linux")]
+                if let (Some(deleted), Some(meta)) = (&mut self.deleted, &meta) {
+                    open_files::track(deleted, path, meta);
+                }
+            }
+            Err(e) if self.options.ignore_vanished && e.kind() == io::ErrorKind::NotFound => {
+                self.report.vanished += 1;
+            }
+            Err(e) => {
+                self.failures.push(path, FmanError::io_at(path, e))?;
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn report_holders(report: &mut DeleteReport, deleted: &DeletedFiles) {
+    report.still_open = open_files::find_holders(Path::new(open_files::PROC_ROOT), deleted);
+    report.pinned_bytes = open_files::pinned_bytes(&report.still_open);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    use crate::fs::{DryRunFs, FsOp};
+
+    fn tree(root: &Path) -> PathBuf {
+        let top = root.join("t");
+        fs::create_dir_all(top.join("sub/deeper")).unwrap();
+        fs::write(top.join("a"), "1").unwrap();
+        fs::write(top.join("sub/b"), "2").unwrap();
+        fs::write(top.join("sub/deeper/c"), "3").unwrap();
+        top
+    }
+
+    #[test]
+    fn a_file_is_removed() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("f");
+        fs::write(&file, "x").unwrap();
+        let report = delete_path(&file, &DeleteOptions::new()).unwrap();
+        assert_eq!((report.files, report.directories), (1, 0));
+        assert!(!file.exists());
+    }
+
+    #[test]
+    fn a_directory_needs_recursive() {
+        let dir = TempDir::new().unwrap();
+        let top = tree(dir.path());
+        let err = delete_path(&top, &DeleteOptions::new()).unwrap_err();
+        assert!(matches!(&err, FmanError::InvalidInput(m) if m.contains("--recursive")));
+        assert!(top.join("sub/deeper/c").exists());
+    }
+
+    #[test]
+    fn recursive_removes_the_whole_tree() {
+        let dir = TempDir::new().unwrap();
+        let top = tree(dir.path());
+        let report = delete_path(&top, &DeleteOptions::new().recursive(true)).unwrap();
+        assert_eq!(
+            (report.files, report.directories, report.vanished),
+            (3, 3, 0)
+        );
+        assert!(!top.exists());
+    }
+
+    #[test]
+    fn every_entry_goes_before_its_directory() {
+        let dir = TempDir::new().unwrap();
+        let top = tree(dir.path());
+        let dry_run = Arc::new(DryRunFs::new());
+        let options = DeleteOptions::new().recursive(true).fs(dry_run.clone());
+        delete_path(&top, &options).unwrap();
+        assert!(top.join("sub/deeper/c").exists());
+        let removed: Vec<PathBuf> = dry_run
+            .ops()
+            .into_iter()
+            .map(|op| match op {
+                FsOp::RemoveFile { path } | FsOp::RemoveDir { path } => path,
+                other => panic!("unexpected {other:?}"),
+            })
+            .collect();
+        assert_eq!(removed.len(), 6);
+        for (i, path) in removed.iter().enumerate() {
+            assert!(
+                removed[i + 1..]
+                    .iter()
+                    .all(|later| !later.starts_with(path)),
+                "{} removed before what is inside it",
+                path.display()
+            );
+        }
+        assert_eq!(removed.last(), Some(&top));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_symlink_is_removed_not_its_target() {
+        let dir = TempDir::new().unwrap();
+        let top = tree(dir.path());
+        let link = dir.path().join("link");
+        std::os::unix::fs::symlink(&top, &link).unwrap();
+        let report = delete_path(&link, &DeleteOptions::new().recursive(true)).unwrap();
+        assert_eq!(report.files, 1);
+        assert!(fs::symlink_metadata(&link).is_err());
+        assert!(top.join("sub/deeper/c").exists());
+    }
+
+    #[test]
+    fn a_missing_target_is_not_found_unless_forced() {
+        let dir = TempDir::new().unwrap();
+        let missing = dir.path().join("missing");
+        let err = delete_path(&missing, &DeleteOptions::new()).unwrap_err();
+        assert_eq!(err.exit_code(), 2);
+        let report = delete_path(&missing, &DeleteOptions::new().force(true)).unwrap();
+        assert_eq!(report.files, 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_read_only_directory_keeps_its_entries_unless_forced() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempDir::new().unwrap();
+        let top = tree(dir.path());
+        let locked = top.join("sub");
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o555)).unwrap();
+        if platform::can_modify_dir(&locked) {
+            // Running with privileges that ignore permission bits.
+            return;
+        }
+        let err = delete_path(&top, &DeleteOptions::new().recursive(true)).unwrap_err();
+        assert_eq!(err.exit_code(), 5);
+        assert!(locked.join("b").exists());
+        assert!(!top.join("a").exists());
+
+        let options = DeleteOptions::new().recursive(true).force(true);
+        delete_path(&top, &options).unwrap();
+        assert!(!top.exists());
+    }
+
+    /// Real removals, except that the first file removed sets off another
+    /// process deleting every other file next to the tree's root, to race
+    /// the walk deterministically.
+    #[derive(Debug)]
+    struct RacingFs {
+        top: PathBuf,
+        raced: std::sync::atomic::AtomicBool,
+    }
+
+    impl RacingFs {
+        fn race(&self, removing: &Path) {
+            if self.raced.swap(true, std::sync::atomic::Ordering::SeqCst) {
+                return;
+            }
+            for entry in fs::read_dir(&self.top).unwrap() {
+                let path = entry.unwrap().path();
+                if path != removing && path.is_file() {
+                    fs::remove_file(path).unwrap();
+                }
+            }
+        }
+    }
+
+    impl Fs for RacingFs {
+        fn create_file(&self, path: &Path, create_new: bool) -> io::Result<Option<fs::File>> {
+            RealFs.create_file(path, create_new)
+        }
+        fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+            RealFs.rename(from, to)
+        }
+        fn remove_file(&self, path: &Path) -> io::Result<()> {
+            self.race(path);
+            RealFs.remove_file(path)
+        }
+        fn remove_dir(&self, path: &Path) -> io::Result<()> {
+            RealFs.remove_dir(path)
+        }
+        fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+            RealFs.remove_dir_all(path)
+        }
+        fn create_dir(&self, path: &Path) -> io::Result<()> {
+            RealFs.create_dir(path)
+        }
+        fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+            RealFs.create_dir_all(path)
+        }
+        fn set_permissions(&self, path: &Path, permissions: fs::Permissions) -> io::Result<()> {
+            RealFs.set_permissions(path, permissions)
+        }
+        fn set_times(
+            &self,
+            path: &Path,
+            accessed: Option<std::time::SystemTime>,
+            modified: Option<std::time::SystemTime>,
+        ) -> io::Result<()> {
+            RealFs.set_times(path, accessed, modified)
+        }
+        fn set_owner(&self, path: &Path, uid: Option<u32>, gid: Option<u32>) -> io::Result<()> {
+            RealFs.set_owner(path, uid, gid)
+        }
+        fn set_xattr(&self, path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+            RealFs.set_xattr(path, name, value)
+        }
+        fn remove_xattr(&self, path: &Path, name: &str) -> io::Result<()> {
+            RealFs.remove_xattr(path, name)
+        }
+        fn symlink(&self, target: &Path, link: &Path) -> io::Result<()> {
+            RealFs.symlink(target, link)
+        }
+        fn hard_link(&self, original: &Path, link: &Path) -> io::Result<()> {
+            RealFs.hard_link(original, link)
+        }
+        fn set_file_permissions(
+            &self,
+            path: &Path,
+            file: &fs::File,
+            permissions: fs::Permissions,
+        ) -> io::Result<()> {
+            RealFs.set_file_permissions(path, file, permissions)
+        }
+        fn set_file_times(
+            &self,
+            path: &Path,
+            file: &fs::File,
+            accessed: Option<std::time::SystemTime>,
+            modified: Option<std::time::SystemTime>,
+        ) -> io::Result<()> {
+            RealFs.set_file_times(path, file, accessed, modified)
+        }
+        fn set_file_owner(
+            &self,
+            path: &Path,
+            file: &fs::File,
+            uid: Option<u32>,
+            gid: Option<u32>,
+        ) -> io::Result<()> {
+            RealFs.set_file_owner(path, file, uid, gid)
+        }
+    }
+
+    /// A tree with `x`, `y` and `z` at the top to race on.
+    fn racing_tree(root: &Path) -> (PathBuf, SharedFs) {
+        let top = tree(root);
+        for name in ["x", "y", "z"] {
+            fs::write(top.join(name), name).unwrap();
+        }
+        let racing = RacingFs {
+            top: top.clone(),
+            raced: Default::default(),
+        };
+        (top, Arc::new(racing))
+    }
+
+    #[test]
+    fn files_vanishing_mid_delete_are_counted() {
+        let dir = TempDir::new().unwrap();
+        let (top, racing) = racing_tree(dir.path());
+        let options = DeleteOptions::new().recursive(true).fs(racing);
+        let report = delete_path(&top, &options).unwrap();
+        assert!(!top.exists());
+        assert!(report.vanished >= 1, "{report:?}");
+        assert_eq!(report.files + report.vanished, 6, "{report:?}");
+        assert_eq!(report.directories, 3);
+    }
+
+    #[test]
+    fn vanishing_files_fail_the_delete_when_not_ignored() {
+        let dir = TempDir::new().unwrap();
+        let (top, racing) = racing_tree(dir.path());
+        let options = DeleteOptions::new()
+            .recursive(true)
+            .ignore_vanished(false)
+            .fs(racing);
+        let err = delete_path(&top, &options).unwrap_err();
+        assert!(matches!(err, FmanError::Multiple(_)), "{err}");
+        assert_eq!(err.exit_code(), 2);
+    }
+
+    #[test]
+    fn a_missing_target_is_not_a_vanished_file() {
+        let dir = TempDir::new().unwrap();
+        let options = DeleteOptions::new().recursive(true).ignore_vanished(true);
+        let err = delete_path(&dir.path().join("missing"), &options).unwrap_err();
+        assert_eq!(err.exit_code(), 2);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn a_file_held_open_by_this_process_is_reported() {
+        let dir = TempDir::new().unwrap();
+        let top = tree(dir.path());
+        let held = top.join("sub/b");
+        let _open = fs::File::open(&held).unwrap();
+        let options = DeleteOptions::new().recursive(true).report_open(true);
+        let report = delete_path(&top, &options).unwrap();
+        let mine: Vec<&OpenHolder> = report
+            .still_open
+            .iter()
+            .filter(|holder| holder.pid == std::process::id())
+            .collect();
+        assert_eq!(mine.len(), 1, "{:?}", report.still_open);
+        assert_eq!((&mine[0].path, mine[0].size), (&held, 1));
+        assert!(report.pinned_bytes >= 1);
+
+        let quiet = tree(dir.path());
+        let _open = fs::File::open(quiet.join("a")).unwrap();
+        let report = delete_path(&quiet, &DeleteOptions::new().recursive(true)).unwrap();
+        assert!(report.still_open.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn force_removes_nested_read_only_directories() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempDir::new().unwrap();
+        let top = tree(dir.path());
+        for locked in [top.join("sub"), top.clone()] {
+            fs::set_permissions(&locked, fs::Permissions::from_mode(0o555)).unwrap();
+        }
+        let options = DeleteOptions::new().recursive(true).force(true);
+        let report = delete_path(&top, &options).unwrap();
+        assert_eq!((report.files, report.directories), (3, 3));
+        assert!(!top.exists());
+    }
+}
@@ -0,0 +1,718 @@
+//! Disk usage: apparent size of the files below a directory.
+//!
+//! Sizes are the sum of regular file lengths; directories and symlinks
+//! themselves count as zero and symlinks are never followed.
+//!
+//! Repeated queries over mostly static trees can keep the total of each
+//! directory's own files in an extended attribute (see
+//! [`DuOptions::cache_xattr`]), so only directories that changed have
+//! their files stat'ed again.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "json")]
+use serde::Serialize;
+
+use crate::cachedir::{self, ExcludeCaches};
+use crate::clock::Clock;
+use crate::error::FmanResult;
+use crate::fs::Fs;
+use crate::platform;
+use crate::validate::ensure_exists;
+use crate::walk::{SymlinkPolicy, Walk};
+
+/// Extended attribute holding a directory's cached file total.
+pub const DIRSIZE_XATTR: &str = "user.fman.dirsize";
+
+/// Size of one direct child of the measured root.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "json", derive(Serialize))]
+pub struct DuEntry {
+    pub path: PathBuf,
+    pub bytes: u64,
+    pub is_dir: bool,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "json", derive(Serialize))]
+pub struct DuReport {
+    pub root: PathBuf,
+    pub total: u64,
+    /// Direct children of the root, largest first.
+    pub entries: Vec<DuEntry>,
+    /// Directories that could not be read; their contents count as zero.
+    pub errors: Vec<String>,
+    /// How the xattr cache fared, when it was used.
+    pub xattr_cache: Option<XattrCacheStats>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(Serialize))]
+pub struct XattrCacheStats {
+    /// Directories whose cached total was still valid.
+    pub hits: u64,
+    /// Directories whose files were measured again.
+    pub misses: u64,
+    /// Files stat'ed for their size.
+    pub files_stated: u64,
+}
+
+/// How [`disk_usage_with`] measures.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DuOptions {
+    cache_xattr: bool,
+    exclude_caches: Option<ExcludeCaches>,
+}
+
+impl DuOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Trust the total of a directory's own files stored in its
+    /// [`DIRSIZE_XATTR`] while the directory's mtime and number of entries
+    /// still match the ones stored with it, and store it again where they
+    /// do not.
+    ///
+    /// Creating, removing or renaming an entry changes both, so the cache
+    /// only misses a file rewritten in place to a new size, which is not
+    /// seen until its directory changes. Where attributes cannot be read
+    /// or written the files are measured as usual.
+    pub fn cache_xattr(mut self, cache_xattr: bool) -> Self {
+        self.cache_xattr = cache_xattr;
+        self
+    }
+
+    /// Count directories below the root tagged as caches as what `mode`
+    /// keeps of them: nothing, their tag file, or an empty directory.
+    pub fn exclude_caches(mut self, mode: ExcludeCaches) -> Self {
+        self.exclude_caches = Some(mode);
+        self
+    }
+}
+
+/// Measure `root` and each of its direct children.
+pub fn disk_usage(root: &Path) -> FmanResult<DuReport> {
+    Scanner::new(None).scan(root)
+}
+
+/// Like [`disk_usage`], measuring as `options` say.
+pub fn disk_usage_with(root: &Path, options: &DuOptions) -> FmanResult<DuReport> {
+    let mut scanner = Scanner::new(None);
+    scanner.xattr = options.cache_xattr.then(XattrCacheStats::default);
+    scanner.exclude_caches = options.exclude_caches;
+    scanner.scan(root)
+}
+
+/// Remove [`DIRSIZE_XATTR`] from `root` and every directory below it
+/// through `filesystem`, returning how many had one.
+pub fn clear_xattr_cache(root: &Path, filesystem: &dyn Fs) -> FmanResult<u64> {
+    ensure_exists(root)?;
+    let below = Walk::new(root)
+        .symlinks(SymlinkPolicy::Never)
+        .flatten()
+        .filter(|entry| entry.file_type().is_dir())
+        .map(|entry| entry.path().to_path_buf());
+    let mut cleared = 0;
+    for dir in std::iter::once(root.to_path_buf()).chain(below) {
+        if let Ok(Some(_)) = platform::get_xattr(&dir, DIRSIZE_XATTR)
+            && filesystem.remove_xattr(&dir, DIRSIZE_XATTR).is_ok()
+        {
+            cleared += 1;
+        }
+    }
+    Ok(cleared)
+}
+
+/// What a cached total is valid for: the directory as it was when the
+/// total was taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct DirToken {
+    mtime: Duration,
+    children: usize,
+}
+
+impl DirToken {
+    fn of(meta: &fs::Metadata, children: usize) -> Option<Self> {
+        let mtime = meta.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+        Some(DirToken { mtime, children })
+    }
+
+    /// `BYTES MTIME_SECS.NANOS CHILDREN`.
+    fn encode(self, bytes: u64) -> String {
+        format!(
+            "{bytes} {}.{:09} {}",
+            self.mtime.as_secs(),
+            self.mtime.subsec_nanos(),
+            self.children
+        )
+    }
+
+    /// The total in `value` if it was stored for exactly this token.
+    fn decode(self, value: &[u8]) -> Option<u64> {
+        let value = std::str::from_utf8(value).ok()?;
+        let mut fields = value.split(' ');
+        let bytes = fields.next()?.parse().ok()?;
+        let (secs, nanos) = fields.next()?.split_once('.')?;
+        let stored = DirToken {
+            mtime: Duration::new(secs.parse().ok()?, nanos.parse().ok()?),
+            children: fields.next()?.parse().ok()?,
+        };
+        (fields.next().is_none() && stored == self).then_some(bytes)
+    }
+}
+
+/// Directory listings remembered between scans.
+///
+/// A listing is reused while the directory's mtime is unchanged, which
+/// saves the `read_dir` of every static directory on repeated scans. Files
+/// are still stat'ed on every scan, so in-place growth is always seen.
+#[derive(Debug, Default)]
+pub struct DuCache {
+    dirs: HashMap<PathBuf, CachedDir>,
+}
+
+#[derive(Debug, Clone)]
+struct CachedDir {
+    modified: SystemTime,
+    children: Vec<(PathBuf, bool)>,
+}
+
+struct Scanner<'a> {
+    cache: Option<&'a mut DuCache>,
+    /// Set when directory totals are cached in xattrs.
+    xattr: Option<XattrCacheStats>,
+    exclude_caches: Option<ExcludeCaches>,
+    errors: Vec<String>,
+}
+
+impl<'a> Scanner<'a> {
+    fn new(cache: Option<&'a mut DuCache>) -> Self {
+        Self {
+            cache,
+            xattr: None,
+            exclude_caches: None,
+            errors: Vec::new(),
+        }
+    }
+
+    fn scan(mut self, root: &Path) -> FmanResult<DuReport> {
+        ensure_exists(root)?;
+        let meta = fs::symlink_metadata(root)?;
+        if !meta.is_dir() {
+            return Ok(DuReport {
+                root: root.to_path_buf(),
+                total: if meta.is_file() { meta.len() } else { 0 },
+                entries: Vec::new(),
+                errors: Vec::new(),
+                xattr_cache: self.xattr,
+            });
+        }
+
+        let mut entries = Vec::new();
+        for (path, is_dir) in self.children(root)? {
+            if is_dir
+                && self.exclude_caches == Some(ExcludeCaches::All)
+                && cachedir::is_cache_dir(&path)
+            {
+                continue;
+            }
+            let bytes = if is_dir {
+                self.dir_total(&path)
+            } else {
+                file_size(&path)
+            };
+            entries.push(DuEntry {
+                path,
+                bytes,
+                is_dir,
+            });
+        }
+        entries.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.path.cmp(&b.path)));
+
+        Ok(DuReport {
+            root: root.to_path_buf(),
+            total: entries.iter().map(|e| e.bytes).sum(),
+            entries,
+            errors: self.errors,
+            xattr_cache: self.xattr,
+        })
+    }
+
+    fn dir_total(&mut self, dir: &Path) -> u64 {
+        if let Some(mode) = self.exclude_caches
+            && cachedir::is_cache_dir(dir)
+        {
+            return match mode {
+                ExcludeCaches::Tag => file_size(&dir.join(cachedir::TAG_NAME)),
+                ExcludeCaches::All | ExcludeCaches::Contents => 0,
+            };
+        }
+        // Taken before listing, so a change made meanwhile leaves a token
+        // that no longer matches on the next run.
+        let meta = self
+            .xattr
+            .is_some()
+            .then(|| fs::symlink_metadata(dir).ok())
+            .flatten();
+        let children = match self.children(dir) {
+            Ok(children) => children,
+            Err(e) => {
+                self.errors.push(format!("{}: {e}", dir.display()));
+                return 0;
+            }
+        };
+        let token = meta.and_then(|meta| DirToken::of(&meta, children.len()));
+        let files = self.files_total(dir, &children, token);
+        let dirs: u64 = children
+            .iter()
+            .filter(|(_, is_dir)| *is_dir)
+            .map(|(path, _)| self.dir_total(path))
+            .sum();
+        files + dirs
+    }
+
+    /// Total size of the files among `children`, from the xattr cache of
+    /// `dir` when it holds one for `token`.
+    fn files_total(
+        &mut self,
+        dir: &Path,
+        children: &[(PathBuf, bool)],
+        token: Option<DirToken>,
+    ) -> u64 {
+        let files = children.iter().filter(|(_, is_dir)| !is_dir);
+        let Some(stats) = self.xattr.as_mut() else {
+            return files.map(|(path, _)| file_size(path)).sum();
+        };
+        if let Some(token) = token
+            && let Ok(Some(value)) = platform::get_xattr(dir, DIRSIZE_XATTR)
+            && let Some(bytes) = token.decode(&value)
+        {
+            stats.hits += 1;
+            return bytes;
+        }
+        stats.misses += 1;
+        let bytes = files
+            .map(|(path, _)| {
+                stats.files_stated += 1;
+                file_size(path)
+            })
+            .sum();
+        // Unsupported filesystems and directories we may not modify just
+        // go uncached.
+        if let Some(token) = token {
+            let _ = platform::set_xattr(dir, DIRSIZE_XATTR, token.encode(bytes).as_bytes());
+        }
+        bytes
+    }
+
+    /// `(path, is_dir)` for each entry of `dir`, from the cache when valid.
+    fn children(&mut self, dir: &Path) -> std::io::Result<Vec<(PathBuf, bool)>> {
+        let Some(cache) = self.cache.as_deref_mut() else {
+            return read_children(dir);
+        };
+        let modified = fs::symlink_metadata(dir)?.modified()?;
+        if let Some(cached) = cache.dirs.get(dir)
+            && cached.modified == modified
+        {
+            return Ok(cached.children.clone());
+        }
+        let children = read_children(dir)?;
+        cache.dirs.insert(
+            dir.to_path_buf(),
+            CachedDir {
+                modified,
+                children: children.clone(),
+            },
+        );
+        Ok(children)
+    }
+}
+
+fn read_children(dir: &Path) -> std::io::Result<Vec<(PathBuf, bool)>> {
+    fs::read_dir(dir)?
+        .map(|entry| {
+            let entry = entry?;
+            Ok((entry.path(), entry.file_type()?.is_dir()))
+        })
+        .collect()
+}
+
+fn file_size(path: &Path) -> u64 {
+    // Files can vanish between listing and stat; they simply count as zero.
+    fs::symlink_metadata(path)
+        .map(|m| if m.is_file() { m.len() } else { 0 })
+        .unwrap_or(0)
+}
+
+/// One child's size in a watch sample, with its change since the last one.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "json", derive(Serialize))]
+pub struct WatchEntry {
+    pub path: PathBuf,
+    pub bytes: u64,
+    pub delta: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct WatchSample {
+    pub taken_at: SystemTime,
+    pub total: u64,
+    pub total_delta: i64,
+    /// Direct children of the root, largest growth first.
+    pub entries: Vec<WatchEntry>,
+    pub errors: Vec<String>,
+}
+
+/// Repeated disk-usage sampling of one root, reporting growth per child.
+pub struct DuWatcher {
+    root: PathBuf,
+    cache: Option<DuCache>,
+    exclude_caches: Option<ExcludeCaches>,
+    previous: HashMap<PathBuf, u64>,
+    previous_total: Option<u64>,
+}
+
+impl DuWatcher {
+    /// With `use_cache`, unchanged directories are not re-listed between
+    /// samples (see [`DuCache`]).
+    pub fn new(root: impl Into<PathBuf>, use_cache: bool) -> Self {
+        Self {
+            root: root.into(),
+            cache: use_cache.then(DuCache::default),
+            exclude_caches: None,
+            previous: HashMap::new(),
+            previous_total: None,
+        }
+    }
+
+    /// Measure cache directories as [`DuOptions::exclude_caches`] does.
+    pub fn exclude_caches(mut self, mode: ExcludeCaches) -> Self {
+        self.exclude_caches = Some(mode);
+        self
+    }
+
+    /// Take a sample. Deltas are relative to the previous call; the first
+    /// sample reports zero growth everywhere.
+    pub fn sample(&mut self, clock: &dyn Clock) -> FmanResult<WatchSample> {
+        let taken_at = clock.now();
+        let mut scanner = Scanner::new(self.cache.as_mut());
+        scanner.exclude_caches = self.exclude_caches;
+        let report = scanner.scan(&self.root)?;
+        let first = self.previous_total.is_none();
+
+        let mut entries: Vec<WatchEntry> = report
+            .entries
+            .into_iter()
+            .map(|e| {
+                let before = self.previous.get(&e.path).copied();
+                let delta = match before {
+                    Some(before) => e.bytes as i64 - before as i64,
+                    None if first => 0,
+                    None => e.bytes as i64,
+                };
+                WatchEntry {
+                    path: e.path,
+                    bytes: e.bytes,
+                    delta,
+                }
+            })
+            .collect();
+        entries.sort_by(|a, b| {
+            b.delta
+                .cmp(&a.delta)
+                .then_with(|| b.bytes.cmp(&a.bytes))
+                .then_with(|| a.path.cmp(&b.path))
+        });
+
+        self.previous = entries.iter().map(|e| (e.path.clone(), e.bytes)).collect();
+        let total_delta = self
+            .previous_total
+            .map_or(0, |before| report.total as i64 - before as i64);
+        self.previous_total = Some(report.total);
+
+        Ok(WatchSample {
+            taken_at,
+            total: report.total,
+            total_delta,
+            entries,
+            errors: report.errors,
+        })
+    }
+}
+
+/// Sample `watcher` every `interval` until `on_sample` returns `false`.
+pub fn watch(
+    watcher: &mut DuWatcher,
+    interval: Duration,
+    clock: &dyn Clock,
+    mut on_sample: impl FnMut(&WatchSample) -> bool,
+) -> FmanResult<()> {
+    loop {
+        let sample = watcher.sample(clock)?;
+        if !on_sample(&sample) {
+            return Ok(());
+        }
+        clock.sleep(interval);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+
+    use tempfile::TempDir;
+
+    /// A clock that runs the next scripted step instead of sleeping.
+    struct ScriptedClock {
+        steps: RefCell<VecDeque<Box<dyn Fn()>>>,
+    }
+
+    impl Clock for ScriptedClock {
+        fn now(&self) -> SystemTime {
+            UNIX_EPOCH
+        }
+
+        fn sleep(&self, _: Duration) {
+            if let Some(step) = self.steps.borrow_mut().pop_front() {
+                step();
+            }
+        }
+    }
+
+    fn tree() -> TempDir {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir_all(dir.path().join("big/deeper")).unwrap();
+        fs::create_dir(dir.path().join("small")).unwrap();
+        fs::write(dir.path().join("big/a"), [0; 100]).unwrap();
+        fs::write(dir.path().join("big/deeper/b"), [0; 50]).unwrap();
+        fs::write(dir.path().join("small/c"), [0; 10]).unwrap();
+        fs::write(dir.path().join("top"), [0; 1]).unwrap();
+        dir
+    }
+
+    #[test]
+    fn children_are_measured_largest_first() {
+        let dir = tree();
+        let report = disk_usage(dir.path()).unwrap();
+        assert_eq!(report.total, 161);
+        let sizes: Vec<(String, u64, bool)> = report
+            .entries
+            .iter()
+            .map(|e| {
+                (
+                    e.path.file_name().unwrap().to_string_lossy().into(),
+                    e.bytes,
+                    e.is_dir,
+                )
+            })
+            .collect();
+        assert_eq!(
+            sizes,
+            [
+                ("big".into(), 150, true),
+                ("small".into(), 10, true),
+                ("top".into(), 1, false)
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_count_as_nothing() {
+        let dir = tree();
+        std::os::unix::fs::symlink(dir.path().join("big"), dir.path().join("small/link")).unwrap();
+        assert_eq!(disk_usage(dir.path()).unwrap().total, 161);
+    }
+
+    /// The first two samples of watching `root` while `step` runs
+    /// between them.
+    fn two_samples(root: &Path, use_cache: bool, step: Box<dyn Fn()>) -> Vec<WatchSample> {
+        let clock = ScriptedClock {
+            steps: RefCell::new(VecDeque::from([step])),
+        };
+        let mut samples = Vec::new();
+        let mut watcher = DuWatcher::new(root, use_cache);
+        watch(&mut watcher, Duration::from_secs(1), &clock, |sample| {
+            samples.push(sample.clone());
+            samples.len() < 2
+        })
+        .unwrap();
+        samples
+    }
+
+    #[test]
+    fn watch_reports_growth_since_the_last_sample() {
+        for use_cache in [false, true] {
+            let dir = tree();
+            let small = dir.path().join("small");
+            let step = Box::new(move || fs::write(small.join("d"), [0; 500]).unwrap());
+            let samples = two_samples(dir.path(), use_cache, step);
+
+            assert_eq!((samples[0].total, samples[0].total_delta), (161, 0));
+            assert!(samples[0].entries.iter().all(|e| e.delta == 0));
+            assert_eq!((samples[1].total, samples[1].total_delta), (661, 500));
+            let grown = &samples[1].entries[0];
+            assert_eq!(grown.path, dir.path().join("small"));
+            assert_eq!((grown.bytes, grown.delta), (510, 500));
+        }
+    }
+
+    #[test]
+    fn the_listing_cache_still_sees_files_growing_in_place() {
+        let dir = tree();
+        let file = dir.path().join("big/deeper/b");
+        let step = Box::new(move || fs::write(&file, [0; 80]).unwrap());
+        let samples = two_samples(dir.path(), true, step);
+        assert_eq!(samples[1].total_delta, 30);
+    }
+
+    #[test]
+    fn a_new_child_counts_as_all_growth() {
+        let dir = tree();
+        let root = dir.path().to_path_buf();
+        let step = Box::new(move || fs::write(root.join("new"), [0; 7]).unwrap());
+        let samples = two_samples(dir.path(), false, step);
+        let new = samples[1]
+            .entries
+            .iter()
+            .find(|e| e.path.ends_with("new"))
+            .unwrap();
+        assert_eq!((new.bytes, new.delta), (7, 7));
+    }
+
+    #[test]
+    fn a_token_decodes_only_what_was_stored_for_it() {
+        let token = DirToken {
+            mtime: Duration::new(1_700_000_000, 5),
+            children: 3,
+        };
+        let value = token.encode(161);
+        assert_eq!(value, "161 1700000000.000000005 3");
+        assert_eq!(token.decode(value.as_bytes()), Some(161));
+
+        let later = DirToken {
+            mtime: Duration::new(1_700_000_000, 6),
+            ..token
+        };
+        let fewer = DirToken {
+            children: 2,
+            ..token
+        };
+        assert_eq!(later.decode(value.as_bytes()), None);
+        assert_eq!(fewer.decode(value.as_bytes()), None);
+        for garbage in ["", "161", "161 1700000000 3", "x 1.0 3", "161 1.0 3 extra"] {
+            assert_eq!(token.decode(garbage.as_bytes()), None, "{garbage:?}");
+        }
+        assert_eq!(token.decode(&[0xff, 0xfe]), None);
+    }
+
+    /// `tree()`, or `None` where its filesystem keeps no user xattrs.
+    fn xattr_tree() -> Option<TempDir> {
+        let dir = tree();
+        platform::set_xattr(dir.path(), "user.fman.probe", b"1").ok()?;
+        Some(dir)
+    }
+
+    fn cached(root: &Path) -> DuReport {
+        disk_usage_with(root, &DuOptions::new().cache_xattr(true)).unwrap()
+    }
+
+    #[test]
+    fn a_second_scan_of_an_unchanged_tree_stats_no_files() {
+        let Some(dir) = xattr_tree() else { return };
+        let first = cached(dir.path());
+        let stats = first.xattr_cache.unwrap();
+        assert_eq!((stats.hits, stats.misses, stats.files_stated), (0, 3, 3));
+
+        let second = cached(dir.path());
+        let stats = second.xattr_cache.unwrap();
+        assert_eq!((stats.hits, stats.misses, stats.files_stated), (3, 0, 0));
+        assert_eq!(second.total, first.total);
+        assert_eq!(second.total, disk_usage(dir.path()).unwrap().total);
+    }
+
+    #[test]
+    fn only_a_changed_directory_is_measured_again() {
+        let Some(dir) = xattr_tree() else { return };
+        cached(dir.path());
+        fs::write(dir.path().join("big/deeper/new"), [0; 9]).unwrap();
+        let report = cached(dir.path());
+        let stats = report.xattr_cache.unwrap();
+        assert_eq!((stats.hits, stats.misses, stats.files_stated), (2, 1, 2));
+        assert_eq!(report.total, 170);
+    }
+
+    #[test]
+    fn a_changed_child_count_invalidates_even_with_the_old_mtime() {
+        let Some(dir) = xattr_tree() else { return };
+        let small = dir.path().join("small");
+        cached(dir.path());
+        let modified = fs::metadata(&small).unwrap().modified().unwrap();
+        fs::write(small.join("d"), [0; 5]).unwrap();
+        fs::File::open(&small)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        assert_eq!(cached(dir.path()).total, 166);
+    }
+
+    #[test]
+    fn a_changed_mtime_invalidates_even_with_the_old_child_count() {
+        let Some(dir) = xattr_tree() else { return };
+        let small = dir.path().join("small");
+        cached(dir.path());
+        // Same count and size class, new contents: only the mtime tells.
+        fs::remove_file(small.join("c")).unwrap();
+        fs::write(small.join("e"), [0; 40]).unwrap();
+        let report = cached(dir.path());
+        assert_eq!(report.xattr_cache.unwrap().misses, 1);
+        assert_eq!(report.total, 191);
+    }
+
+    #[test]
+    fn a_matching_token_is_trusted_as_stored() {
+        let Some(dir) = xattr_tree() else { return };
+        let small = dir.path().join("small");
+        cached(dir.path());
+        let value = platform::get_xattr(&small, DIRSIZE_XATTR).unwrap().unwrap();
+        let value = String::from_utf8(value).unwrap();
+        let (_, token) = value.split_once(' ').unwrap();
+        platform::set_xattr(&small, DIRSIZE_XATTR, format!("1000 {token}").as_bytes()).unwrap();
+        assert_eq!(cached(dir.path()).total, 1151);
+    }
+
+    #[test]
+    fn clearing_removes_every_cached_total() {
+        let Some(dir) = xattr_tree() else { return };
+        cached(dir.path());
+        let cleared = clear_xattr_cache(dir.path(), &crate::fs::RealFs).unwrap();
+        assert_eq!(cleared, 3);
+        assert_eq!(
+            platform::get_xattr(&dir.path().join("big"), DIRSIZE_XATTR).unwrap(),
+            None
+        );
+        assert_eq!(
+            clear_xattr_cache(dir.path(), &crate::fs::RealFs).unwrap(),
+            0
+        );
+        assert_eq!(cached(dir.path()).xattr_cache.unwrap().hits, 0);
+    }
+
+    #[test]
+    fn without_the_option_no_attribute_is_written() {
+        let Some(dir) = xattr_tree() else { return };
+        let report = disk_usage(dir.path()).unwrap();
+        assert!(report.xattr_cache.is_none());
+        assert_eq!(
+            platform::get_xattr(&dir.path().join("big"), DIRSIZE_XATTR).unwrap(),
+            None
+        );
+    }
+}
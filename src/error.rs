@@ -0,0 +1,498 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+use crate::{suggest, units};
+
+/// Errors returned by fman operations.
+#[derive(Debug, Error)]
+pub enum FmanError {
+    #[error("not found: {path}{}", did_you_mean(.suggestions))]
+    NotFound {
+        path: String,
+        /// Existing paths with similar names, best first.
+        suggestions: Vec<PathBuf>,
+    },
+
+    #[error("already exists: {0}")]
+    AlreadyExists(String),
+
+    #[error("invalid input: {0}")]
+    InvalidInput(String),
+
+    #[error("not a directory: {0}")]
+    NotADirectory(String),
+
+    #[error("destination directory does not exist: {0}")]
+    DestinationDirMissing(String),
+
+    #[error("source and destination are the same file: {0}")]
+    SameFile(String),
+
+    #[error("contents differ: {0}")]
+    Mismatch(String),
+
+    #[error("changed during copy: {0}")]
+    ChangedDuringCopy(String),
+
+    #[error("verification failed: {0}")]
+    VerificationFailed(String),
+
+    #[error("{0}")]
+    CrossDevice(String),
+
+    #[error("permission denied: {0}")]
+    PermissionDenied(String),
+
+    #[error("write limit of {} reached with {} written", units::format_size(*.limit), units::format_size(*.written))]
+    QuotaExceeded { limit: u64, written: u64 },
+
+    #[error("{0} is locked by another process")]
+    Locked(String),
+
+    #[error("cannot run {command}: {source}")]
+    CommandNotRun { command: String, source: io::Error },
+
+    #[error("{path} lacks required capabilities: {}", .missing.join(", "))]
+    MissingCapabilities { path: String, missing: Vec<String> },
+
+    #[error("cancelled")]
+    Cancelled,
+
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    #[error("{0}")]
+    Multiple(ErrorList),
+}
+
+impl FmanError {
+    /// `NotFound` for `what`, with no suggestions.
+    pub fn not_found(what: impl Into<String>) -> Self {
+        FmanError::NotFound {
+            path: what.into(),
+            suggestions: Vec::new(),
+        }
+    }
+
+    /// `InvalidInput` for a copy whose destination `dst` is the source
+    /// itself. Moves and links report [`FmanError::SameFile`] instead.
+    pub fn same_file(dst: &Path) -> Self {
+        FmanError::InvalidInput(format!(
+            "source and destination are the same file: {}",
+            dst.display()
+        ))
+    }
+
+    /// `NotFound` for the missing `path`, suggesting similarly named paths
+    /// next to it.
+    pub fn missing_path(path: &Path) -> Self {
+        FmanError::NotFound {
+            path: path.display().to_string(),
+            suggestions: suggest::similar_paths(path),
+        }
+    }
+
+    /// `e`, from an operation on `path`: a permission denial becomes
+    /// `PermissionDenied` naming `path`, anything else stays `Io`.
+    pub fn io_at(path: &Path, e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::PermissionDenied => {
+                FmanError::PermissionDenied(path.display().to_string())
+            }
+            _ => FmanError::Io(e),
+        }
+    }
+
+    /// Stable kebab-case name of the variant, for machine-readable output.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            FmanError::NotFound { .. } => "not-found",
+            FmanError::AlreadyExists(_) => "already-exists",
+            FmanError::InvalidInput(_) => "invalid-input",
+            FmanError::NotADirectory(_) => "not-a-directory",
+            FmanError::DestinationDirMissing(_) => "destination-dir-missing",
+            FmanError::SameFile(_) => "same-file",
+            FmanError::Mismatch(_) => "mismatch",
+            FmanError::ChangedDuringCopy(_) => "changed-during-copy",
+            FmanError::VerificationFailed(_) => "verification-failed",
+            FmanError::CrossDevice(_) => "cross-device",
+            FmanError::PermissionDenied(_) => "permission-denied",
+            FmanError::QuotaExceeded { .. } => "quota-exceeded",
+            FmanError::Locked(_) => "locked",
+            FmanError::CommandNotRun { .. } => "command-not-run",
+            FmanError::MissingCapabilities { .. } => "missing-capabilities",
+            FmanError::Cancelled => "cancelled",
+            FmanError::Io(_) => "io",
+            FmanError::Multiple(_) => "multiple",
+        }
+    }
+
+    /// Process exit status for this error, by category: 2 a missing path,
+    /// 3 an existing one in the way, 4 bad input, 5 a permission denial,
+    /// 75 a held lock (`EX_TEMPFAIL`), 126 a command that cannot be run and
+    /// 127 one that is not found (as with `env`), 130 a cancellation and 10
+    /// any other failure. Several failures share a status only if they
+    /// agree on it. 1 is left for usage errors and for contents that
+    /// differ, as `compare` reports them.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            FmanError::NotFound { .. } | FmanError::DestinationDirMissing(_) => 2,
+            FmanError::AlreadyExists(_) => 3,
+            FmanError::InvalidInput(_)
+            | FmanError::NotADirectory(_)
+            | FmanError::SameFile(_)
+            | FmanError::MissingCapabilities { .. } => 4,
+            FmanError::PermissionDenied(_) => 5,
+            FmanError::Locked(_) => 75,
+            FmanError::CommandNotRun { source, .. } => match source.kind() {
+                io::ErrorKind::NotFound => 127,
+                _ => 126,
+            },
+            FmanError::Cancelled => 130,
+            FmanError::Io(e) => match e.kind() {
+                io::ErrorKind::NotFound => 2,
+                io::ErrorKind::AlreadyExists => 3,
+                io::ErrorKind::InvalidInput => 4,
+                io::ErrorKind::PermissionDenied => 5,
+                _ => 10,
+            },
+            FmanError::Multiple(list) => {
+                let mut codes = list.failures().iter().map(|(_, e)| e.exit_code());
+                let first = codes.next().unwrap_or(10);
+                if list.omitted() == 0 && codes.all(|code| code == first) {
+                    first
+                } else {
+                    10
+                }
+            }
+            FmanError::Mismatch(_) => 1,
+            FmanError::ChangedDuringCopy(_)
+            | FmanError::VerificationFailed(_)
+            | FmanError::CrossDevice(_)
+            | FmanError::QuotaExceeded { .. } => 10,
+        }
+    }
+
+    /// Whether this is a missing path, whichever way it was detected.
+    pub fn is_not_found(&self) -> bool {
+        match self {
+            FmanError::NotFound { .. } => true,
+            FmanError::Io(e) => e.kind() == io::ErrorKind::NotFound,
+            _ => false,
+        }
+    }
+}
+
+pub type FmanResult<T> = Result<T, FmanError>;
+
+/// `; did you mean 'a' or 'b'?`, or nothing without suggestions.
+fn did_you_mean(suggestions: &[PathBuf]) -> String {
+    let quoted: Vec<String> = suggestions
+        .iter()
+        .map(|path| format!("'{}'", path.display()))
+        .collect();
+    match quoted.split_last() {
+        None => String::new(),
+        Some((last, [])) => format!("; did you mean {last}?"),
+        Some((last, rest)) => format!("; did you mean {} or {last}?", rest.join(", ")),
+    }
+}
+
+/// How many failures an [`ErrorList`] keeps by default.
+pub const DEFAULT_MAX_ERRORS: usize = 100;
+
+/// Example paths shown per error kind when an [`ErrorList`] is displayed.
+const EXAMPLES_PER_KIND: usize = 3;
+
+/// Failures collected by an operation that carries on past errors.
+///
+/// Only the first `max` failures are kept; later ones are counted by kind
+/// but dropped, so a run with many failures holds bounded memory.
+#[derive(Debug, Default)]
+pub struct ErrorList {
+    failures: Vec<(PathBuf, FmanError)>,
+    counts: BTreeMap<&'static str, u64>,
+    omitted: u64,
+    max: usize,
+}
+
+impl ErrorList {
+    pub fn new(max: usize) -> Self {
+        ErrorList {
+            max,
+            ..ErrorList::default()
+        }
+    }
+
+    pub fn push(&mut self, path: impl Into<PathBuf>, error: FmanError) {
+        *self.counts.entry(error.kind()).or_default() += 1;
+        if self.failures.len() < self.max {
+            self.failures.push((path.into(), error));
+        } else {
+            self.omitted += 1;
+        }
+    }
+
+    /// The failures kept, in the order they happened.
+    pub fn failures(&self) -> &[(PathBuf, FmanError)] {
+        &self.failures
+    }
+
+    /// Failures counted but not kept.
+    pub fn omitted(&self) -> u64 {
+        self.omitted
+    }
+
+    /// Every failure pushed, kept or not.
+    pub fn total(&self) -> u64 {
+        self.failures.len() as u64 + self.omitted
+    }
+
+    /// Failures per [`FmanError::kind`], kept or not.
+    pub fn counts(&self) -> &BTreeMap<&'static str, u64> {
+        &self.counts
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.total() == 0
+    }
+
+    /// `Ok(value)` if nothing failed, the only failure if there was one, and
+    /// [`FmanError::Multiple`] otherwise.
+    pub fn into_result<T>(mut self, value: T) -> FmanResult<T> {
+        match self.total() {
+            0 => Ok(value),
+            1 if self.omitted == 0 => Err(self.failures.pop().expect("one failure").1),
+            _ => Err(FmanError::Multiple(self)),
+        }
+    }
+}
+
+/// A digest grouped by kind: a count per kind, a few example paths each,
+/// and how many more there were.
+impl fmt::Display for ErrorList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} failures", self.total())?;
+        for (kind, count) in &self.counts {
+            write!(f, "\n  {kind} ({count}):")?;
+            let examples = self
+                .failures
+                .iter()
+                .filter(|(_, e)| e.kind() == *kind)
+                .take(EXAMPLES_PER_KIND);
+            let mut shown = 0;
+            for (path, error) in examples {
+                write!(f, "\n    {}: {error}", path.display())?;
+                shown += 1;
+            }
+            if *count > shown {
+                write!(f, "\n    and {} more", count - shown)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Collects failures into an [`ErrorList`] and, when configured, writes
+/// every one of them to an error log regardless of the cap.
+pub(crate) struct Failures {
+    list: ErrorList,
+    log: Option<BufWriter<File>>,
+}
+
+impl Failures {
+    pub(crate) fn new(max: usize, log: Option<&Path>) -> FmanResult<Self> {
+        let log = match log {
+            Some(path) => Some(BufWriter::new(
+                OpenOptions::new().create(true).append(true).open(path)?,
+            )),
+            None => None,
+        };
+        Ok(Failures {
+            list: ErrorList::new(max),
+            log,
+        })
+    }
+
+    pub(crate) fn push(&mut self, path: &Path, error: FmanError) -> FmanResult<()> {
+        if let Some(log) = &mut self.log {
+            writeln!(log, "{}: {}: {error}", error.kind(), path.display())?;
+        }
+        self.list.push(path, error);
+        Ok(())
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    pub(crate) fn into_result<T>(self, value: T) -> FmanResult<T> {
+        if let Some(mut log) = self.log {
+            log.flush()?;
+        }
+        self.list.into_result(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    /// 300 failures, 100 each of three kinds, interleaved.
+    fn synthetic(max: usize) -> ErrorList {
+        let mut list = ErrorList::new(max);
+        for i in 0..100 {
+            list.push(
+                format!("missing/{i}"),
+                FmanError::missing_path(Path::new("x")),
+            );
+            list.push(
+                format!("taken/{i}"),
+                FmanError::AlreadyExists(format!("taken/{i}")),
+            );
+            list.push(format!("bad/{i}"), FmanError::InvalidInput("bad".into()));
+        }
+        list
+    }
+
+    #[test]
+    fn only_the_first_failures_are_kept() {
+        let list = synthetic(10);
+        assert_eq!(list.failures().len(), 10);
+        assert_eq!((list.total(), list.omitted()), (300, 290));
+        assert_eq!(list.failures()[0].0, PathBuf::from("missing/0"));
+        assert_eq!(
+            list.counts().values().copied().collect::<Vec<_>>(),
+            [100, 100, 100]
+        );
+    }
+
+    #[test]
+    fn failures_are_shown_grouped_by_kind() {
+        let shown = synthetic(DEFAULT_MAX_ERRORS).to_string();
+        let lines: Vec<&str> = shown.lines().collect();
+        assert_eq!(lines[0], "300 failures");
+        assert_eq!(lines[1], "  already-exists (100):");
+        assert_eq!(lines[2], "    taken/0: already exists: taken/0");
+        assert_eq!(lines[5], "    and 97 more");
+        assert_eq!(lines[6], "  invalid-input (100):");
+        assert_eq!(lines[11], "  not-found (100):");
+        assert_eq!(lines.len(), 16);
+    }
+
+    #[test]
+    fn a_kind_past_the_cap_is_counted_without_examples() {
+        let mut list = ErrorList::new(1);
+        list.push("a", FmanError::InvalidInput("bad".into()));
+        list.push("b", FmanError::AlreadyExists("b".into()));
+        assert_eq!(
+            list.to_string(),
+            "2 failures\n  already-exists (1):\n    and 1 more\n  invalid-input (1):\n    a: \
+             invalid input: bad"
+        );
+    }
+
+    #[test]
+    fn one_failure_is_returned_as_it_is() {
+        assert!(ErrorList::new(5).into_result(7).is_ok_and(|v| v == 7));
+        let mut one = ErrorList::new(5);
+        one.push("a", FmanError::AlreadyExists("a".into()));
+        assert!(matches!(
+            one.into_result(()),
+            Err(FmanError::AlreadyExists(_))
+        ));
+        // Capped at zero, the one failure is only counted.
+        let mut counted = ErrorList::new(0);
+        counted.push("a", FmanError::AlreadyExists("a".into()));
+        assert!(matches!(
+            counted.into_result(()),
+            Err(FmanError::Multiple(_))
+        ));
+    }
+
+    #[test]
+    fn many_failures_of_one_kind_keep_its_exit_code() {
+        let mut same = ErrorList::new(5);
+        for name in ["a", "b"] {
+            same.push(name, FmanError::AlreadyExists(name.into()));
+        }
+        assert_eq!(same.into_result(()).unwrap_err().exit_code(), 3);
+        let mut omitted = ErrorList::new(1);
+        for name in ["a", "b"] {
+            omitted.push(name, FmanError::AlreadyExists(name.into()));
+        }
+        assert_eq!(omitted.into_result(()).unwrap_err().exit_code(), 10);
+        assert_eq!(synthetic(500).into_result(()).unwrap_err().exit_code(), 10);
+    }
+
+    #[test]
+    fn the_error_log_has_every_failure() {
+        let dir = TempDir::new().unwrap();
+        let log = dir.path().join("errors.log");
+        let mut failures = Failures::new(2, Some(&log)).unwrap();
+        for i in 0..5 {
+            failures
+                .push(
+                    Path::new(&format!("f{i}")),
+                    FmanError::InvalidInput("bad".into()),
+                )
+                .unwrap();
+        }
+        let err = failures.into_result(()).unwrap_err();
+        let FmanError::Multiple(list) = &err else {
+            panic!("{err}")
+        };
+        assert_eq!(list.failures().len(), 2);
+        let logged = fs::read_to_string(&log).unwrap();
+        assert_eq!(logged.lines().count(), 5);
+        assert!(
+            logged.ends_with("invalid-input: f4: invalid input: bad\n"),
+            "{logged}"
+        );
+    }
+
+    #[test]
+    fn not_found_names_its_suggestions() {
+        let error = |suggestions: &[&str]| FmanError::NotFound {
+            path: "reprot".to_string(),
+            suggestions: suggestions.iter().map(PathBuf::from).collect(),
+        };
+        assert_eq!(error(&[]).to_string(), "not found: reprot");
+        assert_eq!(
+            error(&["report"]).to_string(),
+            "not found: reprot; did you mean 'report'?"
+        );
+        assert_eq!(
+            error(&["a", "b", "c"]).to_string(),
+            "not found: reprot; did you mean 'a', 'b' or 'c'?"
+        );
+        assert_eq!(error(&["a"]).exit_code(), 2);
+    }
+
+    #[test]
+    fn a_permission_denial_names_its_path() {
+        let denied = FmanError::io_at(
+            Path::new("locked/a.txt"),
+            io::Error::from(io::ErrorKind::PermissionDenied),
+        );
+        assert!(matches!(&denied, FmanError::PermissionDenied(path) if path == "locked/a.txt"));
+        assert_eq!(denied.to_string(), "permission denied: locked/a.txt");
+        assert_eq!(
+            (denied.kind(), denied.exit_code()),
+            ("permission-denied", 5)
+        );
+
+        let other = FmanError::io_at(Path::new("a"), io::Error::other("disk on fire"));
+        assert!(matches!(other, FmanError::Io(_)));
+        assert_eq!(other.exit_code(), 10);
+    }
+}
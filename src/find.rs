@@ -0,0 +1,271 @@
+//! Recursive search below a directory for `fman find`.
+
+use std::path::{Path, PathBuf};
+
+use crate::error::FmanResult;
+use crate::format::Fields;
+use crate::glob;
+use crate::list::{EntryKind, ListEntry};
+use crate::validate::{ensure_exists, ensure_is_dir};
+use crate::walk::Walk;
+
+/// Placeholders accepted by `find --format`.
+pub const FORMAT_FIELDS: &[&str] = &[
+    "path", "relpath", "name", "depth", "size", "mtime", "kind", "mode",
+];
+
+/// How result paths are printed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PathStyle {
+    /// The search root as given, joined with the relative path.
+    #[default]
+    AsGiven,
+    /// Relative to the search root (`.` for the root itself).
+    Relative,
+    /// The canonicalized root joined with the relative path.
+    Absolute,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FindOptions {
+    path_style: PathStyle,
+    include_root: bool,
+    name: Option<String>,
+    kind: Option<EntryKind>,
+    max_depth: Option<usize>,
+}
+
+impl FindOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn path_style(mut self, style: PathStyle) -> Self {
+        self.path_style = style;
+        self
+    }
+
+    /// Report the search root itself, at depth 0.
+    pub fn include_root(mut self, include: bool) -> Self {
+        self.include_root = include;
+        self
+    }
+
+    /// Only report entries whose file name matches the wildcard `pattern`;
+    /// see [`glob::matches`].
+    pub fn name(mut self, pattern: impl Into<String>) -> Self {
+        self.name = Some(pattern.into());
+        self
+    }
+
+    /// Only report entries of `kind`. Symlinks are not followed, so a link
+    /// to a directory is a [`EntryKind::Symlink`].
+    pub fn kind(mut self, kind: EntryKind) -> Self {
+        self.kind = Some(kind);
+        self
+    }
+
+    /// Do not descend more than `depth` levels below the root (1 = its
+    /// direct children only).
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    /// Whether `entry` passes the name and kind filters.
+    fn selects(&self, entry: &ListEntry) -> bool {
+        self.kind.is_none_or(|kind| entry.kind == kind)
+            && self
+                .name
+                .as_deref()
+                .is_none_or(|pattern| glob::matches(pattern, &entry.name()))
+    }
+}
+
+/// One search result.
+#[derive(Debug, Clone)]
+pub struct FindEntry {
+    /// The path in the requested [`PathStyle`].
+    pub path: PathBuf,
+    pub relpath: PathBuf,
+    pub entry: ListEntry,
+}
+
+impl Fields for FindEntry {
+    fn write_field(&self, name: &str, spec: Option<&str>, out: &mut String) {
+        match name {
+            "path" => out.push_str(&self.path.to_string_lossy()),
+            "relpath" => out.push_str(&self.relpath.to_string_lossy()),
+            other => self.entry.write_field(other, spec, out),
+        }
+    }
+}
+
+/// Walk `root`, yielding the entries the options select in sorted
+/// pre-order.
+///
+/// The root is validated up front. Unreadable subdirectories show up as
+/// `Err` items and the walk continues past them. Filters only decide what
+/// is reported; the walk still descends into directories they reject.
+pub fn find(
+    root: &Path,
+    options: &FindOptions,
+) -> FmanResult<impl Iterator<Item = FmanResult<FindEntry>> + use<>> {
+    ensure_exists(root)?;
+    ensure_is_dir(root)?;
+
+    let base = match options.path_style {
+        PathStyle::AsGiven => Some(root.to_path_buf()),
+        PathStyle::Relative => None,
+        PathStyle::Absolute => Some(root.canonicalize()?),
+    };
+    let make = {
+        let root = root.to_path_buf();
+        move |path: PathBuf, depth: usize| -> FmanResult<FindEntry> {
+            let relpath = match path.strip_prefix(&root) {
+                Ok(rel) if !rel.as_os_str().is_empty() => rel.to_path_buf(),
+                _ => PathBuf::from("."),
+            };
+            let shown = match (&base, depth) {
+                (Some(base), 0) => base.clone(),
+                (Some(base), _) => base.join(&relpath),
+                (None, _) => relpath.clone(),
+            };
+            Ok(FindEntry {
+                path: shown,
+                relpath,
+                entry: ListEntry::from_path(path, depth)?,
+            })
+        }
+    };
+
+    let root_entry = options.include_root.then(|| make(root.to_path_buf(), 0));
+    let mut walk = Walk::new(root);
+    if let Some(depth) = options.max_depth {
+        walk = walk.max_depth(depth);
+    }
+    let walk = walk.map(move |entry| {
+        let entry = entry?;
+        make(entry.path().to_path_buf(), entry.depth())
+    });
+    let options = options.clone();
+    Ok(root_entry
+        .into_iter()
+        .chain(walk)
+        .filter(move |found| match found {
+            Ok(found) => options.selects(&found.entry),
+            Err(_) => true,
+        }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    use crate::format::FormatTemplate;
+
+    /// `r/a.txt`, `r/sub/b.txt` and `r/sub/deeper/c.log`.
+    fn nested() -> (TempDir, PathBuf) {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().join("r");
+        fs::create_dir_all(root.join("sub/deeper")).unwrap();
+        fs::write(root.join("a.txt"), "1").unwrap();
+        fs::write(root.join("sub/b.txt"), "22").unwrap();
+        fs::write(root.join("sub/deeper/c.log"), "333").unwrap();
+        (dir, root)
+    }
+
+    fn paths(root: &Path, options: &FindOptions) -> Vec<PathBuf> {
+        find(root, options)
+            .unwrap()
+            .map(|found| found.unwrap().path)
+            .collect()
+    }
+
+    #[test]
+    fn paths_are_shown_in_the_requested_style() {
+        let (_dir, root) = nested();
+        let as_given = paths(&root, &FindOptions::new());
+        assert_eq!(
+            as_given,
+            [
+                root.join("a.txt"),
+                root.join("sub"),
+                root.join("sub/b.txt"),
+                root.join("sub/deeper"),
+                root.join("sub/deeper/c.log"),
+            ]
+        );
+        let relative = paths(&root, &FindOptions::new().path_style(PathStyle::Relative));
+        assert_eq!(relative[2], PathBuf::from("sub/b.txt"));
+        let absolute = paths(&root, &FindOptions::new().path_style(PathStyle::Absolute));
+        assert!(absolute.iter().all(|path| path.is_absolute()));
+        assert_eq!(absolute[2], root.canonicalize().unwrap().join("sub/b.txt"));
+    }
+
+    #[test]
+    fn the_root_is_reported_only_when_asked() {
+        let (_dir, root) = nested();
+        let with_root = |style| {
+            let options = FindOptions::new().path_style(style).include_root(true);
+            find(&root, &options).unwrap().next().unwrap().unwrap()
+        };
+        let first = with_root(PathStyle::AsGiven);
+        assert_eq!((first.path, first.entry.depth), (root.clone(), 0));
+        assert_eq!(first.relpath, PathBuf::from("."));
+        assert_eq!(with_root(PathStyle::Relative).path, PathBuf::from("."));
+        assert!(!paths(&root, &FindOptions::new()).contains(&root));
+    }
+
+    #[test]
+    fn filters_select_without_stopping_the_descent() {
+        let (_dir, root) = nested();
+        let options = FindOptions::new()
+            .path_style(PathStyle::Relative)
+            .name("*.log")
+            .kind(EntryKind::File);
+        assert_eq!(paths(&root, &options), [PathBuf::from("sub/deeper/c.log")]);
+        let dirs = FindOptions::new()
+            .path_style(PathStyle::Relative)
+            .kind(EntryKind::Dir)
+            .max_depth(1);
+        assert_eq!(paths(&root, &dirs), [PathBuf::from("sub")]);
+    }
+
+    #[test]
+    fn a_format_combines_find_and_entry_fields() {
+        let (_dir, root) = nested();
+        let template =
+            FormatTemplate::parse("{depth} {size} {relpath} {kind}", FORMAT_FIELDS).unwrap();
+        let options = FindOptions::new().kind(EntryKind::File);
+        let records: Vec<String> = find(&root, &options)
+            .unwrap()
+            .map(|found| template.render(&found.unwrap()))
+            .collect();
+        let sep = std::path::MAIN_SEPARATOR;
+        assert_eq!(
+            records,
+            [
+                "1 1 a.txt file".to_string(),
+                format!("2 2 sub{sep}b.txt file"),
+                format!("3 3 sub{sep}deeper{sep}c.log file"),
+            ]
+        );
+        assert!(FormatTemplate::parse("{owner}", FORMAT_FIELDS).is_err());
+    }
+
+    #[test]
+    fn the_root_must_be_a_directory() {
+        let (_dir, root) = nested();
+        let err = find(&root.join("a.txt"), &FindOptions::new())
+            .err()
+            .unwrap();
+        assert_eq!(err.exit_code(), 4);
+        let err = find(&root.join("nope"), &FindOptions::new()).err().unwrap();
+        assert_eq!(err.exit_code(), 2);
+    }
+}
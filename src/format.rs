@@ -0,0 +1,276 @@
+//! `{field}` output templates, as used by `ls --format`.
+//!
+//! A template is literal text with `{name}` or `{name:spec}` placeholders.
+//! `{{` and `}}` produce literal braces, and `\t`, `\n`, `\0` and `\\` are
+//! recognised escapes. Field names are checked against the caller's list
+//! when the template is parsed, so a typo fails before any output.
+
+use std::fmt::Write as _;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::{FmanError, FmanResult};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Field { name: String, spec: Option<String> },
+}
+
+/// A parsed output template.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormatTemplate {
+    segments: Vec<Segment>,
+}
+
+/// Something that can supply values for template fields.
+pub trait Fields {
+    /// Append the value of `name` to `out`. Only names accepted at parse time
+    /// are ever requested.
+    fn write_field(&self, name: &str, spec: Option<&str>, out: &mut String);
+}
+
+impl FormatTemplate {
+    /// Parse `template`, accepting only the placeholders listed in `fields`.
+    pub fn parse(template: &str, fields: &[&str]) -> FmanResult<Self> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.peek() {
+                    Some('t') => literal.push('\t'),
+                    Some('n') => literal.push('\n'),
+                    Some('0') => literal.push('\0'),
+                    Some('\\') => literal.push('\\'),
+                    _ => {
+                        literal.push('\\');
+                        continue;
+                    }
+                },
+                '{' if chars.peek() == Some(&'{') => literal.push('{'),
+                '}' if chars.peek() == Some(&'}') => literal.push('}'),
+                '{' => {
+                    let mut body = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => body.push(c),
+                            None => {
+                                return Err(FmanError::InvalidInput(format!(
+                                    "unterminated placeholder in format {template:?}"
+                                )));
+                            }
+                        }
+                    }
+                    let (name, spec) = match body.split_once(':') {
+                        Some((name, spec)) => (name, Some(spec.to_string())),
+                        None => (body.as_str(), None),
+                    };
+                    if !fields.contains(&name) {
+                        return Err(FmanError::InvalidInput(format!(
+                            "unknown placeholder {{{name}}} (expected one of: {})",
+                            fields.join(", ")
+                        )));
+                    }
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(Segment::Field {
+                        name: name.to_string(),
+                        spec,
+                    });
+                    continue;
+                }
+                '}' => {
+                    return Err(FmanError::InvalidInput(format!(
+                        "unmatched '}}' in format {template:?}"
+                    )));
+                }
+                c => {
+                    literal.push(c);
+                    continue;
+                }
+            }
+            // Two-character sequence: consume the second character.
+            chars.next();
+        }
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+        Ok(Self { segments })
+    }
+
+    /// Render the template against `fields`.
+    pub fn render(&self, fields: &impl Fields) -> String {
+        let mut out = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(text) => out.push_str(text),
+                Segment::Field { name, spec } => {
+                    fields.write_field(name, spec.as_deref(), &mut out)
+                }
+            }
+        }
+        out
+    }
+}
+
+/// Default `strftime` pattern for time fields without a spec.
+pub const DEFAULT_TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// Format `time` in UTC with a small `strftime` subset: `%Y %m %d %H %M %S`,
+/// `%F` (`%Y-%m-%d`), `%T` (`%H:%M:%S`), `%s` (Unix seconds) and `%%`.
+/// Other sequences are copied through unchanged.
+pub fn format_time(time: SystemTime, pattern: &str) -> String {
+    let secs = match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs_f64().ceil() as i64),
+    };
+    let (year, month, day) = civil_from_days(secs.div_euclid(86_400));
+    let of_day = secs.rem_euclid(86_400);
+    let (hour, minute, second) = (of_day / 3600, of_day % 3600 / 60, of_day % 60);
+
+    let mut out = String::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        let _ = match chars.next() {
+            Some('Y') => write!(out, "{year:04}"),
+            Some('m') => write!(out, "{month:02}"),
+            Some('d') => write!(out, "{day:02}"),
+            Some('H') => write!(out, "{hour:02}"),
+            Some('M') => write!(out, "{minute:02}"),
+            Some('S') => write!(out, "{second:02}"),
+            Some('F') => write!(out, "{year:04}-{month:02}-{day:02}"),
+            Some('T') => write!(out, "{hour:02}:{minute:02}:{second:02}"),
+            Some('s') => write!(out, "{secs}"),
+            Some('%') => write!(out, "%"),
+            Some(other) => write!(out, "%{other}"),
+            None => write!(out, "%"),
+        };
+    }
+    out
+}
+
+/// Parse `YYYY-MM-DDTHH:MM:SS` as a UTC time, the inverse of
+/// `format_time(t, "%Y-%m-%dT%H:%M:%S")`.
+pub fn parse_time(text: &str) -> Option<SystemTime> {
+    let (date, time) = text.trim().split_once('T')?;
+    let mut date = date.splitn(3, '-');
+    let year: i64 = date.next()?.parse().ok()?;
+    let month: u32 = date.next()?.parse().ok()?;
+    let day: u32 = date.next()?.parse().ok()?;
+    let mut time = time.splitn(3, ':');
+    let hour: i64 = time.next()?.parse().ok()?;
+    let minute: i64 = time.next()?.parse().ok()?;
+    // Fractional seconds are accepted and ignored.
+    let second: i64 = time.next()?.split('.').next()?.parse().ok()?;
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return None;
+    }
+    let secs = days_from_civil(year, month, day) * 86_400 + hour * 3600 + minute * 60 + second;
+    Some(if secs >= 0 {
+        UNIX_EPOCH + std::time::Duration::from_secs(secs as u64)
+    } else {
+        UNIX_EPOCH - std::time::Duration::from_secs(secs.unsigned_abs())
+    })
+}
+
+/// Proleptic Gregorian (year, month, day) to days since 1970-01-01.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let month = i64::from(month);
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Days since 1970-01-01 to a proleptic Gregorian (year, month, day).
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    struct Entry;
+
+    impl Fields for Entry {
+        fn write_field(&self, name: &str, spec: Option<&str>, out: &mut String) {
+            out.push_str(name);
+            if let Some(spec) = spec {
+                out.push('=');
+                out.push_str(spec);
+            }
+        }
+    }
+
+    fn render(template: &str) -> FmanResult<String> {
+        FormatTemplate::parse(template, &["path", "mtime"]).map(|t| t.render(&Entry))
+    }
+
+    #[test]
+    fn fields_specs_braces_and_escapes_render() {
+        assert_eq!(
+            render(r"{path}\t{mtime:%s} {{x}}\n\\").unwrap(),
+            "path\tmtime=%s {x}\n\\"
+        );
+        assert_eq!(render(r"a\qb").unwrap(), r"a\qb");
+    }
+
+    #[test]
+    fn bad_templates_fail_at_parse_time() {
+        let err = render("{size}").unwrap_err();
+        assert!(
+            err.to_string().contains("expected one of: path, mtime"),
+            "{err}"
+        );
+        assert!(render("{path").is_err());
+        assert!(render("path}").is_err());
+    }
+
+    #[test]
+    fn times_format_in_utc() {
+        let time = UNIX_EPOCH + Duration::from_secs(951_827_696);
+        assert_eq!(format_time(time, "%F %T"), "2000-02-29 12:34:56");
+        assert_eq!(format_time(time, "%s %% %q"), "951827696 % %q");
+        assert_eq!(
+            format_time(UNIX_EPOCH - Duration::from_secs(1), "%F %T"),
+            "1969-12-31 23:59:59"
+        );
+    }
+
+    #[test]
+    fn parse_time_inverts_format_time() {
+        for secs in [0, 951_827_696, 4_102_444_799] {
+            let time = UNIX_EPOCH + Duration::from_secs(secs);
+            let text = format_time(time, "%Y-%m-%dT%H:%M:%S");
+            assert_eq!(parse_time(&text), Some(time), "{text}");
+        }
+        assert_eq!(parse_time("2000-13-01T00:00:00"), None);
+        assert_eq!(parse_time("yesterday"), None);
+    }
+}
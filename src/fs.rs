@@ -0,0 +1,784 @@
+//! The single route by which fman changes the filesystem.
+//!
+//! Every operation that creates, removes, renames or modifies something on
+//! disk goes through an [`Fs`]; reads stay direct. [`RealFs`] performs the
+//! operations, [`DryRunFs`] only records them, and [`RecordingFs`] does
+//! both. Writes to a file handle returned by [`Fs::create_file`] are part
+//! of creating that file and are not recorded separately; attributes set
+//! on such a handle go through the `set_file_*` methods. Reads stay direct
+//! except for [`Fs::exists`] and [`Fs::is_dir`], which decisions about a
+//! destination ask so that a dry run sees the changes it has planned.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, File, FileTimes, OpenOptions, Permissions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use serde::Deserialize;
+#[cfg(feature = "json")]
+use serde::Serialize;
+
+use crate::platform;
+
+/// A filesystem implementation shared between operations.
+pub type SharedFs = Arc<dyn Fs>;
+
+/// Mutating filesystem operations.
+pub trait Fs: fmt::Debug + Send + Sync {
+    /// Open `path` for writing, truncating it. With `create_new` the call
+    /// fails with `AlreadyExists` if anything is at `path`.
+    ///
+    /// Returns `None` when the operation was only recorded; the caller then
+    /// skips writing the contents.
+    fn create_file(&self, path: &Path, create_new: bool) -> io::Result<Option<File>>;
+    /// Like [`Fs::create_file`] for `to`, which is about to receive a copy
+    /// of `from`; recording implementations note the source as well.
+    fn create_copy(&self, from: &Path, to: &Path, create_new: bool) -> io::Result<Option<File>> {
+        let _ = from;
+        self.create_file(to, create_new)
+    }
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+    fn remove_file(&self, path: &Path) -> io::Result<()>;
+    fn remove_dir(&self, path: &Path) -> io::Result<()>;
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()>;
+    fn create_dir(&self, path: &Path) -> io::Result<()>;
+    fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+    fn set_permissions(&self, path: &Path, permissions: Permissions) -> io::Result<()>;
+    /// Set access and/or modification time; `None` leaves one unchanged.
+    fn set_times(
+        &self,
+        path: &Path,
+        accessed: Option<SystemTime>,
+        modified: Option<SystemTime>,
+    ) -> io::Result<()>;
+    /// Change owner and/or group of `path` itself (not a link's target).
+    fn set_owner(&self, path: &Path, uid: Option<u32>, gid: Option<u32>) -> io::Result<()>;
+    fn set_xattr(&self, path: &Path, name: &str, value: &[u8]) -> io::Result<()>;
+    fn remove_xattr(&self, path: &Path, name: &str) -> io::Result<()>;
+    fn symlink(&self, target: &Path, link: &Path) -> io::Result<()>;
+    fn hard_link(&self, original: &Path, link: &Path) -> io::Result<()>;
+    /// Like [`Fs::set_permissions`] for `file`, an open handle to `path`.
+    fn set_file_permissions(
+        &self,
+        path: &Path,
+        file: &File,
+        permissions: Permissions,
+    ) -> io::Result<()>;
+    /// Like [`Fs::set_times`] for `file`, an open handle to `path`.
+    fn set_file_times(
+        &self,
+        path: &Path,
+        file: &File,
+        accessed: Option<SystemTime>,
+        modified: Option<SystemTime>,
+    ) -> io::Result<()>;
+    /// Like [`Fs::set_owner`] for `file`, an open handle to `path`.
+    fn set_file_owner(
+        &self,
+        path: &Path,
+        file: &File,
+        uid: Option<u32>,
+        gid: Option<u32>,
+    ) -> io::Result<()>;
+    /// Whether anything, even a dangling symlink, is at `path`.
+    fn exists(&self, path: &Path) -> bool {
+        fs::symlink_metadata(path).is_ok()
+    }
+    /// Whether `path` is a directory or a symlink to one.
+    fn is_dir(&self, path: &Path) -> bool {
+        path.is_dir()
+    }
+    /// Whether operations take effect on disk; `false` when they are only
+    /// recorded, so a renamed file is still under its old name.
+    fn performs(&self) -> bool {
+        true
+    }
+}
+
+/// One recorded operation, and one step of a plan for `fman apply`, which
+/// reads them in their JSON form.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[cfg_attr(feature = "json", derive(Serialize))]
+#[serde(tag = "op", rename_all = "kebab-case")]
+pub enum FsOp {
+    CreateFile {
+        path: PathBuf,
+        create_new: bool,
+    },
+    /// A file created as the copy of another.
+    Copy {
+        from: PathBuf,
+        to: PathBuf,
+        create_new: bool,
+    },
+    Rename {
+        from: PathBuf,
+        to: PathBuf,
+    },
+    RemoveFile {
+        path: PathBuf,
+    },
+    RemoveDir {
+        path: PathBuf,
+    },
+    RemoveDirAll {
+        path: PathBuf,
+    },
+    CreateDir {
+        path: PathBuf,
+    },
+    CreateDirAll {
+        path: PathBuf,
+    },
+    SetPermissions {
+        path: PathBuf,
+        readonly: bool,
+    },
+    SetTimes {
+        path: PathBuf,
+        accessed: Option<SystemTime>,
+        modified: Option<SystemTime>,
+    },
+    SetOwner {
+        path: PathBuf,
+        uid: Option<u32>,
+        gid: Option<u32>,
+    },
+    SetXattr {
+        path: PathBuf,
+        name: String,
+    },
+    RemoveXattr {
+        path: PathBuf,
+        name: String,
+    },
+    Symlink {
+        target: PathBuf,
+        link: PathBuf,
+    },
+    HardLink {
+        original: PathBuf,
+        link: PathBuf,
+    },
+}
+
+/// One line per operation, as `fman --dry-run` lists its plan.
+impl fmt::Display for FsOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FsOp::CreateFile { path, .. } => write!(f, "create {}", path.display()),
+            FsOp::Copy { from, to, .. } => {
+                write!(f, "copy {} -> {}", from.display(), to.display())
+            }
+            FsOp::Rename { from, to } => write!(f, "move {} -> {}", from.display(), to.display()),
+            FsOp::RemoveFile { path } | FsOp::RemoveDir { path } => {
+                write!(f, "delete {}", path.display())
+            }
+            FsOp::RemoveDirAll { path } => write!(f, "delete -r {}", path.display()),
+            FsOp::CreateDir { path } | FsOp::CreateDirAll { path } => {
+                write!(f, "mkdir {}", path.display())
+            }
+            FsOp::SetPermissions { path, readonly } => {
+                let mode = if *readonly { "read-only" } else { "writable" };
+                write!(f, "chmod {} ({mode})", path.display())
+            }
+            FsOp::SetTimes { path, .. } => write!(f, "touch {}", path.display()),
+            FsOp::SetOwner { path, uid, gid } => {
+                let id = |id: &Option<u32>| id.map_or("-".to_string(), |id| id.to_string());
+                write!(f, "chown {}:{} {}", id(uid), id(gid), path.display())
+            }
+            FsOp::SetXattr { path, name } => write!(f, "setxattr {name} {}", path.display()),
+            FsOp::RemoveXattr { path, name } => write!(f, "rmxattr {name} {}", path.display()),
+            FsOp::Symlink { target, link } => {
+                write!(f, "symlink {} -> {}", link.display(), target.display())
+            }
+            FsOp::HardLink { original, link } => {
+                write!(f, "link {} -> {}", link.display(), original.display())
+            }
+        }
+    }
+}
+
+/// The real filesystem.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RealFs;
+
+impl Fs for RealFs {
+    fn create_file(&self, path: &Path, create_new: bool) -> io::Result<Option<File>> {
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .create_new(create_new)
+            .open(path)
+            .map(Some)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        fs::remove_dir(path)
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::remove_dir_all(path)
+    }
+
+    fn create_dir(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir(path)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir_all(path)
+    }
+
+    fn set_permissions(&self, path: &Path, permissions: Permissions) -> io::Result<()> {
+        fs::set_permissions(path, permissions)
+    }
+
+    fn set_times(
+        &self,
+        path: &Path,
+        accessed: Option<SystemTime>,
+        modified: Option<SystemTime>,
+    ) -> io::Result<()> {
+        self.set_file_times(path, &open_for_attributes(path)?, accessed, modified)
+    }
+
+    #[cfg(unix)]
+    fn set_owner(&self, path: &Path, uid: Option<u32>, gid: Option<u32>) -> io::Result<()> {
+        std::os::unix::fs::lchown(path, uid, gid)
+    }
+
+    #[cfg(not(unix))]
+    fn set_owner(&self, _path: &Path, _uid: Option<u32>, _gid: Option<u32>) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "ownership can only be changed on Unix",
+        ))
+    }
+
+    fn set_xattr(&self, path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+        platform::set_xattr(path, name, value)
+    }
+
+    fn remove_xattr(&self, path: &Path, name: &str) -> io::Result<()> {
+        platform::remove_xattr(path, name)
+    }
+
+    #[cfg(unix)]
+    fn symlink(&self, target: &Path, link: &Path) -> io::Result<()> {
+        std::os::unix::fs::symlink(target, link)
+    }
+
+    #[cfg(windows)]
+    fn symlink(&self, target: &Path, link: &Path) -> io::Result<()> {
+        let resolved = link.parent().unwrap_or(Path::new("")).join(target);
+        if resolved.is_dir() {
+            std::os::windows::fs::symlink_dir(target, link)
+        } else {
+            std::os::windows::fs::symlink_file(target, link)
+        }
+    }
+
+    fn hard_link(&self, original: &Path, link: &Path) -> io::Result<()> {
+        fs::hard_link(original, link)
+    }
+
+    fn set_file_permissions(
+        &self,
+        _path: &Path,
+        file: &File,
+        permissions: Permissions,
+    ) -> io::Result<()> {
+        file.set_permissions(permissions)
+    }
+
+    fn set_file_times(
+        &self,
+        _path: &Path,
+        file: &File,
+        accessed: Option<SystemTime>,
+        modified: Option<SystemTime>,
+    ) -> io::Result<()> {
+        let mut times = FileTimes::new();
+        if let Some(accessed) = accessed {
+            times = times.set_accessed(accessed);
+        }
+        if let Some(modified) = modified {
+            times = times.set_modified(modified);
+        }
+        file.set_times(times)
+    }
+
+    fn set_file_owner(
+        &self,
+        _path: &Path,
+        file: &File,
+        uid: Option<u32>,
+        gid: Option<u32>,
+    ) -> io::Result<()> {
+        platform::set_file_owner(file, uid, gid)
+    }
+}
+
+/// Records operations without performing any of them.
+///
+/// [`Fs::exists`] and [`Fs::is_dir`] answer as if the recorded operations
+/// had been performed, so a directory planned for creation can receive
+/// files later in the same plan.
+#[derive(Debug, Default)]
+pub struct DryRunFs {
+    ops: Mutex<Vec<FsOp>>,
+    planned: Mutex<BTreeMap<PathBuf, Planned>>,
+}
+
+/// What a dry run has planned for a path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Planned {
+    Dir,
+    File,
+    Removed,
+}
+
+impl DryRunFs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The operations recorded so far, in order.
+    pub fn ops(&self) -> Vec<FsOp> {
+        lock(&self.ops).clone()
+    }
+
+    fn record(&self, op: FsOp) -> io::Result<()> {
+        match &op {
+            FsOp::CreateFile { path, .. } | FsOp::Copy { to: path, .. } => {
+                self.plan(path, Planned::File)
+            }
+            FsOp::Symlink { link, .. } | FsOp::HardLink { link, .. } => {
+                self.plan(link, Planned::File)
+            }
+            FsOp::CreateDir { path } => self.plan(path, Planned::Dir),
+            FsOp::CreateDirAll { path } => {
+                for dir in path.ancestors().filter(|dir| !dir.as_os_str().is_empty()) {
+                    self.plan(dir, Planned::Dir);
+                }
+            }
+            FsOp::RemoveFile { path } | FsOp::RemoveDir { path } | FsOp::RemoveDirAll { path } => {
+                self.plan(path, Planned::Removed)
+            }
+            FsOp::Rename { from, to } => {
+                let moved = if self.is_dir(from) {
+                    Planned::Dir
+                } else {
+                    Planned::File
+                };
+                self.plan(from, Planned::Removed);
+                self.plan(to, moved);
+            }
+            _ => {}
+        }
+        lock(&self.ops).push(op);
+        Ok(())
+    }
+
+    fn plan(&self, path: &Path, planned: Planned) {
+        lock(&self.planned).insert(path.components().collect(), planned);
+    }
+
+    /// The planned state of `path`, if the plan decides it: `path` itself
+    /// was planned, or one of its ancestors is to be removed.
+    fn planned(&self, path: &Path) -> Option<Planned> {
+        let path: PathBuf = path.components().collect();
+        let planned = lock(&self.planned);
+        if let Some(&state) = planned.get(&path) {
+            return Some(state);
+        }
+        path.ancestors()
+            .skip(1)
+            .any(|ancestor| planned.get(ancestor) == Some(&Planned::Removed))
+            .then_some(Planned::Removed)
+    }
+}
+
+impl Fs for DryRunFs {
+    fn create_file(&self, path: &Path, create_new: bool) -> io::Result<Option<File>> {
+        self.record(FsOp::CreateFile {
+            path: path.to_path_buf(),
+            create_new,
+        })?;
+        Ok(None)
+    }
+
+    fn create_copy(&self, from: &Path, to: &Path, create_new: bool) -> io::Result<Option<File>> {
+        self.record(FsOp::Copy {
+            from: from.to_path_buf(),
+            to: to.to_path_buf(),
+            create_new,
+        })?;
+        Ok(None)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.record(FsOp::Rename {
+            from: from.to_path_buf(),
+            to: to.to_path_buf(),
+        })
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        self.record(FsOp::RemoveFile {
+            path: path.to_path_buf(),
+        })
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        self.record(FsOp::RemoveDir {
+            path: path.to_path_buf(),
+        })
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.record(FsOp::RemoveDirAll {
+            path: path.to_path_buf(),
+        })
+    }
+
+    fn create_dir(&self, path: &Path) -> io::Result<()> {
+        self.record(FsOp::CreateDir {
+            path: path.to_path_buf(),
+        })
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.record(FsOp::CreateDirAll {
+            path: path.to_path_buf(),
+        })
+    }
+
+    fn set_permissions(&self, path: &Path, permissions: Permissions) -> io::Result<()> {
+        self.record(FsOp::SetPermissions {
+            path: path.to_path_buf(),
+            readonly: permissions.readonly(),
+        })
+    }
+
+    fn set_times(
+        &self,
+        path: &Path,
+        accessed: Option<SystemTime>,
+        modified: Option<SystemTime>,
+    ) -> io::Result<()> {
+        self.record(FsOp::SetTimes {
+            path: path.to_path_buf(),
+            accessed,
+            modified,
+        })
+    }
+
+    fn set_owner(&self, path: &Path, uid: Option<u32>, gid: Option<u32>) -> io::Result<()> {
+        self.record(FsOp::SetOwner {
+            path: path.to_path_buf(),
+            uid,
+            gid,
+        })
+    }
+
+    fn set_xattr(&self, path: &Path, name: &str, _value: &[u8]) -> io::Result<()> {
+        self.record(FsOp::SetXattr {
+            path: path.to_path_buf(),
+            name: name.to_string(),
+        })
+    }
+
+    fn remove_xattr(&self, path: &Path, name: &str) -> io::Result<()> {
+        self.record(FsOp::RemoveXattr {
+            path: path.to_path_buf(),
+            name: name.to_string(),
+        })
+    }
+
+    fn symlink(&self, target: &Path, link: &Path) -> io::Result<()> {
+        self.record(FsOp::Symlink {
+            target: target.to_path_buf(),
+            link: link.to_path_buf(),
+        })
+    }
+
+    fn hard_link(&self, original: &Path, link: &Path) -> io::Result<()> {
+        self.record(FsOp::HardLink {
+            original: original.to_path_buf(),
+            link: link.to_path_buf(),
+        })
+    }
+
+    fn set_file_permissions(
+        &self,
+        path: &Path,
+        _file: &File,
+        permissions: Permissions,
+    ) -> io::Result<()> {
+        self.set_permissions(path, permissions)
+    }
+
+    fn set_file_times(
+        &self,
+        path: &Path,
+        _file: &File,
+        accessed: Option<SystemTime>,
+        modified: Option<SystemTime>,
+    ) -> io::Result<()> {
+        self.set_times(path, accessed, modified)
+    }
+
+    fn set_file_owner(
+        &self,
+        path: &Path,
+        _file: &File,
+        uid: Option<u32>,
+        gid: Option<u32>,
+    ) -> io::Result<()> {
+        self.set_owner(path, uid, gid)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        match self.planned(path) {
+            Some(planned) => planned != Planned::Removed,
+            None => fs::symlink_metadata(path).is_ok(),
+        }
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        match self.planned(path) {
+            Some(planned) => planned == Planned::Dir,
+            None => path.is_dir(),
+        }
+    }
+
+    fn performs(&self) -> bool {
+        false
+    }
+}
+
+/// Performs operations on the real filesystem and records the ones that
+/// succeeded.
+#[derive(Debug, Default)]
+pub struct RecordingFs {
+    log: DryRunFs,
+}
+
+impl RecordingFs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The operations performed so far, in order.
+    pub fn ops(&self) -> Vec<FsOp> {
+        self.log.ops()
+    }
+}
+
+impl Fs for RecordingFs {
+    fn create_file(&self, path: &Path, create_new: bool) -> io::Result<Option<File>> {
+        let file = RealFs.create_file(path, create_new)?;
+        self.log.create_file(path, create_new)?;
+        Ok(file)
+    }
+
+    fn create_copy(&self, from: &Path, to: &Path, create_new: bool) -> io::Result<Option<File>> {
+        let file = RealFs.create_file(to, create_new)?;
+        self.log.create_copy(from, to, create_new)?;
+        Ok(file)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        RealFs.rename(from, to)?;
+        self.log.rename(from, to)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        RealFs.remove_file(path)?;
+        self.log.remove_file(path)
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        RealFs.remove_dir(path)?;
+        self.log.remove_dir(path)
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        RealFs.remove_dir_all(path)?;
+        self.log.remove_dir_all(path)
+    }
+
+    fn create_dir(&self, path: &Path) -> io::Result<()> {
+        RealFs.create_dir(path)?;
+        self.log.create_dir(path)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        RealFs.create_dir_all(path)?;
+        self.log.create_dir_all(path)
+    }
+
+    fn set_permissions(&self, path: &Path, permissions: Permissions) -> io::Result<()> {
+        RealFs.set_permissions(path, permissions.clone())?;
+        self.log.set_permissions(path, permissions)
+    }
+
+    fn set_times(
+        &self,
+        path: &Path,
+        accessed: Option<SystemTime>,
+        modified: Option<SystemTime>,
+    ) -> io::Result<()> {
+        RealFs.set_times(path, accessed, modified)?;
+        self.log.set_times(path, accessed, modified)
+    }
+
+    fn set_owner(&self, path: &Path, uid: Option<u32>, gid: Option<u32>) -> io::Result<()> {
+        RealFs.set_owner(path, uid, gid)?;
+        self.log.set_owner(path, uid, gid)
+    }
+
+    fn set_xattr(&self, path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+        RealFs.set_xattr(path, name, value)?;
+        self.log.set_xattr(path, name, value)
+    }
+
+    fn remove_xattr(&self, path: &Path, name: &str) -> io::Result<()> {
+        RealFs.remove_xattr(path, name)?;
+        self.log.remove_xattr(path, name)
+    }
+
+    fn symlink(&self, target: &Path, link: &Path) -> io::Result<()> {
+        RealFs.symlink(target, link)?;
+        self.log.symlink(target, link)
+    }
+
+    fn hard_link(&self, original: &Path, link: &Path) -> io::Result<()> {
+        RealFs.hard_link(original, link)?;
+        self.log.hard_link(original, link)
+    }
+
+    fn set_file_permissions(
+        &self,
+        path: &Path,
+        file: &File,
+        permissions: Permissions,
+    ) -> io::Result<()> {
+        RealFs.set_file_permissions(path, file, permissions.clone())?;
+        self.log.set_permissions(path, permissions)
+    }
+
+    fn set_file_times(
+        &self,
+        path: &Path,
+        file: &File,
+        accessed: Option<SystemTime>,
+        modified: Option<SystemTime>,
+    ) -> io::Result<()> {
+        RealFs.set_file_times(path, file, accessed, modified)?;
+        self.log.set_times(path, accessed, modified)
+    }
+
+    fn set_file_owner(
+        &self,
+        path: &Path,
+        file: &File,
+        uid: Option<u32>,
+        gid: Option<u32>,
+    ) -> io::Result<()> {
+        RealFs.set_file_owner(path, file, uid, gid)?;
+        self.log.set_owner(path, uid, gid)
+    }
+}
+
+/// Open `path` so that its times can be set: read-only suffices on Unix
+/// (and works for directories), Windows needs write access.
+#[cfg(unix)]
+fn open_for_attributes(path: &Path) -> io::Result<File> {
+    File::open(path)
+}
+
+#[cfg(not(unix))]
+fn open_for_attributes(path: &Path) -> io::Result<File> {
+    OpenOptions::new().write(true).open(path)
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    #[test]
+    fn dry_run_changes_nothing_and_records_everything() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("f");
+        fs::write(&file, "x").unwrap();
+        let dry_run = DryRunFs::new();
+        dry_run.create_dir_all(&dir.path().join("a/b")).unwrap();
+        assert!(
+            dry_run
+                .create_file(&dir.path().join("g"), true)
+                .unwrap()
+                .is_none()
+        );
+        dry_run.rename(&file, &dir.path().join("h")).unwrap();
+        assert!(file.exists());
+        assert!(!dir.path().join("a").exists());
+        assert!(!dir.path().join("g").exists());
+        assert_eq!(dry_run.ops().len(), 3);
+    }
+
+    #[test]
+    fn dry_run_sees_its_own_plan() {
+        let dir = TempDir::new().unwrap();
+        let existing = dir.path().join("d");
+        fs::create_dir_all(existing.join("inner")).unwrap();
+        let dry_run = DryRunFs::new();
+
+        let planned = dir.path().join("new/deeper");
+        dry_run.create_dir_all(&planned).unwrap();
+        assert!(dry_run.is_dir(&planned));
+        assert!(dry_run.is_dir(&dir.path().join("new/")));
+
+        dry_run.create_file(&planned.join("f"), false).unwrap();
+        assert!(dry_run.exists(&planned.join("f")));
+        assert!(!dry_run.is_dir(&planned.join("f")));
+
+        dry_run.remove_dir_all(&existing).unwrap();
+        assert!(!dry_run.exists(&existing));
+        assert!(!dry_run.exists(&existing.join("inner")));
+
+        dry_run.rename(&planned, &existing).unwrap();
+        assert!(dry_run.is_dir(&existing));
+        assert!(!dry_run.exists(&planned));
+    }
+
+    #[test]
+    fn recording_performs_and_records() {
+        let dir = TempDir::new().unwrap();
+        let recording = RecordingFs::new();
+        let path = dir.path().join("d");
+        recording.create_dir(&path).unwrap();
+        assert!(path.is_dir());
+        assert!(recording.remove_dir(&dir.path().join("missing")).is_err());
+        assert_eq!(recording.ops(), [FsOp::CreateDir { path }]);
+    }
+}
@@ -0,0 +1,626 @@
+//! What the filesystem holding a path is and what it can do.
+//!
+//! Mount details come from the platform (`/proc/self/mountinfo` on Linux,
+//! `statfs` on macOS). Case sensitivity, timestamp granularity and support
+//! for reflinks, sparse files, extended attributes, symlinks and changing
+//! owners are probed by creating short-lived `.fman-probe-*` files next to
+//! the path, so they are unknown where that directory is not writable.
+//!
+//! [`require`] turns the probes into a check made before an operation
+//! starts, for runs that must fail rather than lose an attribute.
+
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+#[cfg(feature = "json")]
+use serde::Serialize;
+
+use crate::error::{FmanError, FmanResult};
+use crate::platform;
+use crate::validate::ensure_exists;
+
+/// One line of `/proc/self/mountinfo`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mount {
+    pub mount_point: PathBuf,
+    pub fs_type: String,
+    pub source: String,
+    pub read_only: bool,
+}
+
+/// Parse the contents of a Linux `mountinfo` file, skipping malformed
+/// lines.
+///
+/// Each line reads `id parent major:minor root mount-point options
+/// [optional fields...] - fs-type source super-options`, with spaces and
+/// other special characters in paths escaped as octal (`\040`).
+pub fn parse_mountinfo(text: &str) -> Vec<Mount> {
+    text.lines()
+        .filter_map(|line| {
+            let (mount, filesystem) = line.split_once(" - ")?;
+            let mount: Vec<&str> = mount.split(' ').collect();
+            let mut filesystem = filesystem.split(' ');
+            let (mount_point, options) = (mount.get(4)?, mount.get(5)?);
+            Some(Mount {
+                mount_point: unescape(mount_point).into(),
+                fs_type: filesystem.next()?.to_string(),
+                source: unescape(filesystem.next()?),
+                read_only: options.split(',').any(|option| option == "ro"),
+            })
+        })
+        .collect()
+}
+
+/// The mount holding `path`, which should be canonical: the one with the
+/// longest mount point above it, the last listed if several share it (a
+/// later mount hides an earlier one at the same place).
+pub fn best_mount<'a>(mounts: &'a [Mount], path: &Path) -> Option<&'a Mount> {
+    mounts
+        .iter()
+        .filter(|mount| path.starts_with(&mount.mount_point))
+        .max_by_key(|mount| mount.mount_point.components().count())
+}
+
+fn unescape(field: &str) -> String {
+    let bytes = field.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let octal = bytes
+            .get(i + 1..i + 4)
+            .filter(|digits| bytes[i] == b'\\' && digits.iter().all(|d| (b'0'..=b'7').contains(d)));
+        match octal {
+            Some(digits) => {
+                let value = digits.iter().fold(0u32, |n, d| n * 8 + u32::from(d - b'0'));
+                out.push(value as u8);
+                i += 4;
+            }
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Everything `fman fs-info` reports. `None` means unknown on this platform
+/// or not probed.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "json", derive(Serialize))]
+pub struct FsInfo {
+    pub path: PathBuf,
+    pub mount_point: Option<PathBuf>,
+    pub fs_type: Option<String>,
+    /// The device or remote the filesystem was mounted from.
+    pub source: Option<String>,
+    pub total_bytes: Option<u64>,
+    pub used_bytes: Option<u64>,
+    pub available_bytes: Option<u64>,
+    pub read_only: Option<bool>,
+    pub case_sensitive: Option<bool>,
+    /// The finest mtime step the filesystem keeps, in nanoseconds.
+    pub timestamp_granularity_ns: Option<u64>,
+    pub reflink: Option<bool>,
+    pub sparse_files: Option<bool>,
+    pub xattrs: Option<bool>,
+    pub symlinks: Option<bool>,
+    /// Whether files can be given to another owner, which takes both the
+    /// privilege and a filesystem that keeps owners.
+    pub ownership: Option<bool>,
+}
+
+/// Describe the filesystem holding `path`.
+pub fn fs_info(path: &Path) -> FmanResult<FsInfo> {
+    ensure_exists(path)?;
+    let path = path.canonicalize()?;
+    let mut info = FsInfo {
+        path: path.clone(),
+        mount_point: None,
+        fs_type: None,
+        source: None,
+        total_bytes: None,
+        used_bytes: None,
+        available_bytes: None,
+        read_only: None,
+        case_sensitive: None,
+        timestamp_granularity_ns: None,
+        reflink: None,
+        sparse_files: None,
+        xattrs: None,
+        symlinks: None,
+        ownership: None,
+    };
+
+    if let Some(mount) = mount_of(&path) {
+        info.read_only = Some(mount.read_only);
+        info.mount_point = Some(mount.mount_point);
+        info.fs_type = Some(mount.fs_type);
+        info.source = Some(mount.source);
+    }
+    if let Ok(space) = platform::space_usage(&path) {
+        info.total_bytes = Some(space.total);
+        info.used_bytes = Some(space.total.saturating_sub(space.free));
+        info.available_bytes = Some(space.available);
+        info.read_only = Some(space.read_only);
+    }
+    if info.read_only != Some(true) {
+        let dir = if path.is_dir() {
+            path.as_path()
+        } else {
+            path.parent().unwrap_or(&path)
+        };
+        probe(dir, &mut info);
+    }
+    Ok(info)
+}
+
+#[cfg(target_os = "linux")]
+fn mount_of(path: &Path) -> Option<Mount> {
+    let mounts = parse_mountinfo(&fs::read_to_string("/proc/self/mountinfo").ok()?);
+    best_mount(&mounts, path).cloned()
+}
+
+#[cfg(target_os = "macos")]
+fn mount_of(path: &Path) -> Option<Mount> {
+    let (mount_point, fs_type, source) = platform::mount_of(path).ok()?;
+    Some(Mount {
+        mount_point,
+        fs_type,
+        source,
+        read_only: false,
+    })
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn mount_of(_path: &Path) -> Option<Mount> {
+    None
+}
+
+/// Run the probes in `dir`, leaving the fields unknown if no probe file
+/// can be created there.
+fn probe(dir: &Path, info: &mut FsInfo) {
+    let stem = format!(".fman-probe-{}", std::process::id());
+    let (first, second) = (dir.join(&stem), dir.join(format!("{stem}-clone")));
+    let link = dir.join(format!("{stem}-link"));
+    let Ok(file) = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&first)
+    else {
+        return;
+    };
+
+    info.case_sensitive = Some(fs::symlink_metadata(dir.join(stem.to_uppercase())).is_err());
+    info.timestamp_granularity_ns = probe_granularity(&file, &first).map(|d| d.as_nanos() as u64);
+    info.sparse_files = probe_sparse(&file, &first);
+    info.symlinks = probe_symlink(&first, &link);
+    info.ownership = probe_ownership(&file);
+    if cfg!(target_os = "linux") {
+        info.xattrs = Some(platform::set_xattr(&first, "user.fman.probe", b"1").is_ok());
+        if let Ok(clone) = File::create_new(&second) {
+            info.reflink = Some(platform::reflink(&file, &clone).is_ok());
+        }
+    }
+    let _ = fs::remove_file(&link);
+    let _ = fs::remove_file(&second);
+    let _ = fs::remove_file(&first);
+}
+
+#[cfg(unix)]
+fn probe_symlink(target: &Path, link: &Path) -> Option<bool> {
+    Some(std::os::unix::fs::symlink(target, link).is_ok())
+}
+
+#[cfg(not(unix))]
+fn probe_symlink(_target: &Path, _link: &Path) -> Option<bool> {
+    None
+}
+
+/// Hand the probe file to another user; it is removed right after.
+#[cfg(unix)]
+fn probe_ownership(file: &File) -> Option<bool> {
+    use std::os::unix::fs::MetadataExt;
+
+    let uid = file.metadata().ok()?.uid();
+    let other = if uid == 65534 { 65533 } else { 65534 };
+    Some(platform::set_file_owner(file, Some(other), None).is_ok())
+}
+
+#[cfg(not(unix))]
+fn probe_ownership(_file: &File) -> Option<bool> {
+    None
+}
+
+/// Set an mtime with every sub-second digit in use and see how much of it
+/// survives.
+fn probe_granularity(file: &File, path: &Path) -> Option<Duration> {
+    const SECS: u64 = 1_000_000_001;
+    const NANOS: u32 = 123_456_789;
+    file.set_modified(UNIX_EPOCH + Duration::new(SECS, NANOS))
+        .ok()?;
+    let kept = fs::metadata(path)
+        .ok()?
+        .modified()
+        .ok()?
+        .duration_since(UNIX_EPOCH)
+        .ok()?;
+    if kept.as_secs() != SECS {
+        // FAT rounds to even seconds.
+        return (kept.as_secs().abs_diff(SECS) == 1).then(|| Duration::from_secs(2));
+    }
+    std::iter::successors(Some(1u32), |step| step.checked_mul(10))
+        .take_while(|step| *step <= 1_000_000_000)
+        .find(|step| NANOS - NANOS % step == kept.subsec_nanos())
+        .map(|step| Duration::from_nanos(u64::from(step)))
+}
+
+/// Write one byte a megabyte in and check that the hole takes no space.
+#[cfg(unix)]
+fn probe_sparse(file: &File, path: &Path) -> Option<bool> {
+    use std::os::unix::fs::{FileExt, MetadataExt};
+
+    const OFFSET: u64 = 1 << 20;
+    file.write_all_at(b"x", OFFSET).ok()?;
+    file.sync_all().ok()?;
+    let meta = fs::metadata(path).ok()?;
+    Some(meta.blocks() * 512 < OFFSET)
+}
+
+#[cfg(not(unix))]
+fn probe_sparse(_file: &File, _path: &Path) -> Option<bool> {
+    None
+}
+
+/// What an operation can insist on instead of degrading without it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "json", derive(Serialize), serde(rename_all = "kebab-case"))]
+pub enum Capability {
+    Reflink,
+    Xattr,
+    Symlink,
+    Ownership,
+    SubSecondTimes,
+    Sparse,
+}
+
+impl Capability {
+    pub const ALL: [Capability; 6] = [
+        Capability::Reflink,
+        Capability::Xattr,
+        Capability::Symlink,
+        Capability::Ownership,
+        Capability::SubSecondTimes,
+        Capability::Sparse,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Capability::Reflink => "reflink",
+            Capability::Xattr => "xattr",
+            Capability::Symlink => "symlink",
+            Capability::Ownership => "ownership",
+            Capability::SubSecondTimes => "sub-second-times",
+            Capability::Sparse => "sparse",
+        }
+    }
+
+    /// Parse a comma-separated list such as `reflink,xattr`.
+    pub fn parse_list(list: &str) -> FmanResult<Vec<Capability>> {
+        let mut capabilities = Vec::new();
+        for name in list.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            let capability = Capability::ALL
+                .into_iter()
+                .find(|c| c.as_str() == name)
+                .ok_or_else(|| {
+                    let known: Vec<_> = Capability::ALL.map(Capability::as_str).into();
+                    FmanError::InvalidInput(format!(
+                        "unknown capability {name:?} (expected {})",
+                        known.join(", ")
+                    ))
+                })?;
+            capabilities.push(capability);
+        }
+        capabilities.sort();
+        capabilities.dedup();
+        Ok(capabilities)
+    }
+
+    /// Whether `info` shows this capability; `None` if it was not probed.
+    pub fn supported_by(self, info: &FsInfo) -> Option<bool> {
+        match self {
+            Capability::Reflink => info.reflink,
+            Capability::Xattr => info.xattrs,
+            Capability::Symlink => info.symlinks,
+            Capability::Ownership => info.ownership,
+            Capability::SubSecondTimes => {
+                info.timestamp_granularity_ns.map(|ns| ns < 1_000_000_000)
+            }
+            Capability::Sparse => info.sparse_files,
+        }
+    }
+}
+
+/// Fail with [`FmanError::MissingCapabilities`] unless the filesystem `dst`
+/// is written to has every one of `required`. A `dst` that does not exist
+/// yet is probed through the nearest directory above it that does. Reflinks
+/// also need `src` on that same filesystem.
+///
+/// A capability that cannot be probed counts as missing.
+pub fn require(src: &Path, dst: &Path, required: &[Capability]) -> FmanResult<()> {
+    if required.is_empty() {
+        return Ok(());
+    }
+    let target = dst
+        .ancestors()
+        .find(|path| fs::symlink_metadata(path).is_ok())
+        .filter(|path| !path.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let info = fs_info(target)?;
+    let same_device = match (fs::metadata(src), fs::metadata(&info.path)) {
+        (Ok(a), Ok(b)) => platform::device_id(&a) == platform::device_id(&b),
+        _ => false,
+    };
+    let missing: Vec<String> = required
+        .iter()
+        .filter_map(|&capability| match capability.supported_by(&info) {
+            Some(true) if capability == Capability::Reflink && !same_device => Some(format!(
+                "{} (the source is on another filesystem)",
+                capability.as_str()
+            )),
+            Some(true) => None,
+            Some(false) => Some(capability.as_str().to_string()),
+            None => Some(format!("{} (could not be probed)", capability.as_str())),
+        })
+        .collect();
+    if missing.is_empty() {
+        return Ok(());
+    }
+    Err(FmanError::MissingCapabilities {
+        path: info.path.display().to_string(),
+        missing,
+    })
+}
+
+/// `1ns`, `100ns`, `1µs`, `2s` and so on.
+pub fn format_granularity(nanos: u64) -> String {
+    match nanos {
+        n if n >= 1_000_000_000 && n % 1_000_000_000 == 0 => format!("{}s", n / 1_000_000_000),
+        n if n >= 1_000_000 && n % 1_000_000 == 0 => format!("{}ms", n / 1_000_000),
+        n if n >= 1_000 && n % 1_000 == 0 => format!("{}µs", n / 1_000),
+        n => format!("{n}ns"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    const MOUNTINFO: &str = "\
+22 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw,errors=remount-ro
+35 22 0:31 / /home rw,nosuid shared:20 - btrfs /dev/sda2 rw,space_cache
+36 35 0:32 / /home/me/My\\040Drive ro,nodev - fuse.rclone remote:\\040docs ro
+37 22 0:33 / /mnt rw - tmpfs tmpfs rw
+38 22 0:34 / /mnt rw - nfs server:/export rw
+not a mountinfo line
+";
+
+    #[test]
+    fn parse_mountinfo_reads_every_well_formed_line() {
+        let mounts = parse_mountinfo(MOUNTINFO);
+        assert_eq!(mounts.len(), 5);
+        assert_eq!(
+            mounts[0],
+            Mount {
+                mount_point: "/".into(),
+                fs_type: "ext4".into(),
+                source: "/dev/sda1".into(),
+                read_only: false,
+            }
+        );
+        assert_eq!(mounts[2].mount_point, Path::new("/home/me/My Drive"));
+        assert_eq!(mounts[2].source, "remote: docs");
+        assert_eq!(mounts[2].fs_type, "fuse.rclone");
+        assert!(mounts[2].read_only);
+    }
+
+    #[test]
+    fn best_mount_picks_the_longest_mount_point_above_the_path() {
+        let mounts = parse_mountinfo(MOUNTINFO);
+        let fs_type = |path: &str| {
+            best_mount(&mounts, Path::new(path))
+                .unwrap()
+                .fs_type
+                .clone()
+        };
+        assert_eq!(fs_type("/etc/passwd"), "ext4");
+        assert_eq!(fs_type("/home/me/notes"), "btrfs");
+        assert_eq!(fs_type("/home/me/My Drive/a"), "fuse.rclone");
+        // `/home2` is not below `/home`.
+        assert_eq!(fs_type("/home2"), "ext4");
+        // The later of two mounts at one place hides the earlier.
+        assert_eq!(fs_type("/mnt/x"), "nfs");
+        assert!(best_mount(&[], Path::new("/")).is_none());
+    }
+
+    #[test]
+    fn unescape_decodes_octal_escapes_only() {
+        assert_eq!(unescape("a\\040b\\011c"), "a b\tc");
+        assert_eq!(unescape("a\\09b\\"), "a\\09b\\");
+    }
+
+    #[test]
+    fn format_granularity_uses_the_largest_exact_unit() {
+        assert_eq!(format_granularity(1), "1ns");
+        assert_eq!(format_granularity(100), "100ns");
+        assert_eq!(format_granularity(1_000), "1µs");
+        assert_eq!(format_granularity(10_000_000), "10ms");
+        assert_eq!(format_granularity(2_000_000_000), "2s");
+        assert_eq!(format_granularity(1_500_000_000), "1500ms");
+    }
+
+    #[test]
+    fn fs_info_reports_on_a_temp_dir() {
+        let dir = TempDir::new().unwrap();
+        let info = fs_info(dir.path()).unwrap();
+        assert_eq!(info.path, dir.path().canonicalize().unwrap());
+        let (total, used, available) = (
+            info.total_bytes.unwrap(),
+            info.used_bytes.unwrap(),
+            info.available_bytes.unwrap(),
+        );
+        assert!(used <= total && available <= total);
+        assert_eq!(info.read_only, Some(false));
+        assert!(info.case_sensitive.is_some());
+        assert!(info.timestamp_granularity_ns.is_some_and(|ns| ns > 0));
+        assert!(info.sparse_files.is_some());
+        if cfg!(target_os = "linux") {
+            let mount_point = info.mount_point.unwrap();
+            assert!(info.path.starts_with(mount_point));
+            assert!(info.fs_type.is_some_and(|t| !t.is_empty()));
+            assert_eq!(info.case_sensitive, Some(true));
+        }
+        if cfg!(unix) {
+            assert_eq!(info.symlinks, Some(true));
+        }
+        let leftovers = fs::read_dir(dir.path()).unwrap().count();
+        assert_eq!(leftovers, 0, "probe files were left behind");
+    }
+
+    #[test]
+    fn fs_info_of_a_file_probes_its_directory() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("f");
+        fs::write(&file, "x").unwrap();
+        let info = fs_info(&file).unwrap();
+        assert!(info.case_sensitive.is_some());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn fs_info_of_a_missing_path_is_not_found() {
+        let dir = TempDir::new().unwrap();
+        let err = fs_info(&dir.path().join("missing")).unwrap_err();
+        assert!(err.is_not_found(), "{err}");
+    }
+
+    fn blank_info() -> FsInfo {
+        FsInfo {
+            path: PathBuf::from("/"),
+            mount_point: None,
+            fs_type: None,
+            source: None,
+            total_bytes: None,
+            used_bytes: None,
+            available_bytes: None,
+            read_only: None,
+            case_sensitive: None,
+            timestamp_granularity_ns: None,
+            reflink: None,
+            sparse_files: None,
+            xattrs: None,
+            symlinks: None,
+            ownership: None,
+        }
+    }
+
+    /// A capability the filesystem under `dir` is known to lack, if any.
+    fn lacking(dir: &Path) -> Option<Capability> {
+        let info = fs_info(dir).unwrap();
+        Capability::ALL
+            .into_iter()
+            .find(|c| c.supported_by(&info) == Some(false))
+    }
+
+    #[test]
+    fn parse_list_sorts_dedups_and_trims() {
+        assert_eq!(
+            Capability::parse_list(" xattr,reflink ,xattr,").unwrap(),
+            [Capability::Reflink, Capability::Xattr]
+        );
+        assert_eq!(Capability::parse_list("").unwrap(), []);
+        let every: Vec<_> = Capability::ALL.map(Capability::as_str).into();
+        assert_eq!(
+            Capability::parse_list(&every.join(",")).unwrap(),
+            Capability::ALL
+        );
+    }
+
+    #[test]
+    fn parse_list_rejects_an_unknown_name() {
+        let err = Capability::parse_list("reflink,teleport").unwrap_err();
+        assert_eq!(err.exit_code(), 4);
+        let message = err.to_string();
+        assert!(message.contains("\"teleport\""), "{message}");
+        assert!(message.contains("sub-second-times"), "{message}");
+    }
+
+    #[test]
+    fn supported_by_reads_the_matching_field() {
+        let mut info = blank_info();
+        for capability in Capability::ALL {
+            assert_eq!(capability.supported_by(&info), None);
+        }
+        info.reflink = Some(false);
+        info.xattrs = Some(true);
+        info.timestamp_granularity_ns = Some(2_000_000_000);
+        assert_eq!(Capability::Reflink.supported_by(&info), Some(false));
+        assert_eq!(Capability::Xattr.supported_by(&info), Some(true));
+        assert_eq!(Capability::SubSecondTimes.supported_by(&info), Some(false));
+        info.timestamp_granularity_ns = Some(100);
+        assert_eq!(Capability::SubSecondTimes.supported_by(&info), Some(true));
+    }
+
+    #[test]
+    fn require_nothing_always_passes() {
+        let dir = TempDir::new().unwrap();
+        let missing = dir.path().join("no/such/place");
+        require(&missing, &missing, &[]).unwrap();
+    }
+
+    #[test]
+    fn require_names_a_capability_the_filesystem_lacks() {
+        let dir = TempDir::new().unwrap();
+        let Some(capability) = lacking(dir.path()) else {
+            return;
+        };
+        let src = dir.path().join("src");
+        fs::write(&src, "x").unwrap();
+        let err = require(&src, &dir.path().join("dst"), &[capability]).unwrap_err();
+        assert_eq!(err.exit_code(), 4);
+        match err {
+            FmanError::MissingCapabilities { path, missing } => {
+                assert_eq!(
+                    path,
+                    dir.path().canonicalize().unwrap().display().to_string()
+                );
+                assert_eq!(missing, [capability.as_str()]);
+            }
+            other => panic!("unexpected error {other}"),
+        }
+    }
+
+    #[test]
+    fn require_probes_a_missing_destination_through_its_ancestors() {
+        let dir = TempDir::new().unwrap();
+        let src = dir.path().join("src");
+        fs::write(&src, "x").unwrap();
+        let dst = dir.path().join("a/b/c");
+        if cfg!(unix) {
+            require(&src, &dst, &[Capability::Symlink]).unwrap();
+        }
+        if let Some(capability) = lacking(dir.path()) {
+            let err = require(&src, &dst, &[capability]).unwrap_err();
+            let message = err.to_string();
+            assert!(
+                message.starts_with(&dir.path().canonicalize().unwrap().display().to_string()),
+                "{message}"
+            );
+        }
+        assert!(!dir.path().join("a").exists());
+    }
+}
@@ -0,0 +1,230 @@
+//! Shell-style wildcard patterns, expanded by fman itself for quoted
+//! arguments such as `fman delete 'logs/*.tmp'`.
+//!
+//! `*` matches any run of characters within a name, `?` any one character
+//! and `[...]` one character of a set such as `[abc]` or `[a-z]`, negated
+//! with `[!...]` or `[^...]`. Wildcards never match `/`, and a leading `.`
+//! only matches literally, as in the shell.
+
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use crate::error::{FmanError, FmanResult};
+
+/// Whether `text` contains wildcards.
+pub fn is_pattern(text: &str) -> bool {
+    text.contains(['*', '?', '['])
+}
+
+/// Whether the single name `name` matches `pattern`.
+pub fn matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    if name.first() == Some(&'.') && pattern.first() != Some(&'.') {
+        return false;
+    }
+    let (mut p, mut n) = (0, 0);
+    // Where to resume after the last `*`: the pattern past it, and the
+    // next name position it could swallow up to.
+    let mut star = None;
+    while n < name.len() {
+        let next = match pattern.get(p) {
+            Some('*') => {
+                star = Some((p + 1, n));
+                p += 1;
+                continue;
+            }
+            Some('?') => Some(p + 1),
+            Some('[') => match class(&pattern[p..], name[n]) {
+                Some((true, len)) => Some(p + len),
+                Some((false, _)) => None,
+                // An unclosed `[` is literal.
+                None => (name[n] == '[').then_some(p + 1),
+            },
+            Some(&c) => (c == name[n]).then_some(p + 1),
+            None => None,
+        };
+        match (next, star) {
+            (Some(next), _) => {
+                p = next;
+                n += 1;
+            }
+            (None, Some((after, swallowed))) => {
+                p = after;
+                n = swallowed + 1;
+                star = Some((after, swallowed + 1));
+            }
+            (None, None) => return false,
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Match `c` against the set opening `pattern`, returning whether it
+/// matched and the length of the set, or `None` if the `[` is unclosed.
+fn class(pattern: &[char], c: char) -> Option<(bool, usize)> {
+    let negated = matches!(pattern.get(1), Some('!' | '^'));
+    let mut i = if negated { 2 } else { 1 };
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let &start = pattern.get(i)?;
+        if start == ']' && !first {
+            return Some((matched != negated, i + 1));
+        }
+        first = false;
+        match (pattern.get(i + 1), pattern.get(i + 2)) {
+            (Some('-'), Some(&end)) if end != ']' => {
+                matched |= (start..=end).contains(&c);
+                i += 3;
+            }
+            _ => {
+                matched |= start == c;
+                i += 1;
+            }
+        }
+    }
+}
+
+/// The existing paths matching `pattern`, sorted. Wildcards may appear in
+/// any component; names that are not valid UTF-8 never match one.
+pub fn expand(pattern: &Path) -> FmanResult<Vec<PathBuf>> {
+    let mut found = vec![PathBuf::new()];
+    for component in pattern.components() {
+        let name = match component {
+            Component::Normal(name) => name.to_str().filter(|name| is_pattern(name)),
+            _ => None,
+        };
+        let Some(name) = name else {
+            found.iter_mut().for_each(|path| path.push(component));
+            continue;
+        };
+        let mut next = Vec::new();
+        for base in &found {
+            let dir = if base.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                base.as_path()
+            };
+            let Ok(entries) = fs::read_dir(dir) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                if entry
+                    .file_name()
+                    .to_str()
+                    .is_some_and(|entry_name| matches(name, entry_name))
+                {
+                    next.push(base.join(entry.file_name()));
+                }
+            }
+        }
+        found = next;
+    }
+    found.retain(|path| fs::symlink_metadata(path).is_ok());
+    if found.is_empty() {
+        return Err(FmanError::not_found(format!(
+            "no matches for {}",
+            pattern.display()
+        )));
+    }
+    found.sort();
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    #[test]
+    fn wildcards_are_recognised() {
+        for text in ["*.txt", "a?", "[ab]", "logs/*/x"] {
+            assert!(is_pattern(text), "{text}");
+        }
+        for text in ["plain.txt", "a-b", "dir/file", "x]"] {
+            assert!(!is_pattern(text), "{text}");
+        }
+    }
+
+    #[test]
+    fn names_match_like_the_shell() {
+        let cases = [
+            ("*.txt", "a.txt", true),
+            ("*.txt", "a.txt.bak", false),
+            ("*", "anything", true),
+            ("a*b*c", "aXXbYYc", true),
+            ("a*b*c", "aXXbYY", false),
+            ("?.log", "1.log", true),
+            ("?.log", "12.log", false),
+            ("[abc].rs", "b.rs", true),
+            ("[abc].rs", "d.rs", false),
+            ("[a-c]x", "bx", true),
+            ("[!a-c]x", "bx", false),
+            ("[^a-c]x", "dx", true),
+            ("[]]", "]", true),
+            ("[a-]", "-", true),
+            ("[unclosed", "[unclosed", true),
+            ("[unclosed", "u", false),
+            ("", "", true),
+            ("", "a", false),
+        ];
+        for (pattern, name, expected) in cases {
+            assert_eq!(matches(pattern, name), expected, "{pattern} ~ {name}");
+        }
+    }
+
+    #[test]
+    fn a_leading_dot_only_matches_literally() {
+        assert!(!matches("*", ".hidden"));
+        assert!(!matches("?hidden", ".hidden"));
+        assert!(matches(".*", ".hidden"));
+        assert!(matches("*.*", "a.b"));
+    }
+
+    fn files(names: &[&str]) -> TempDir {
+        let dir = TempDir::new().unwrap();
+        for name in names {
+            let path = dir.path().join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn expansion_finds_the_matches_in_order() {
+        let dir = files(&["logs/b.txt", "logs/a.txt", "logs/c.log", "logs/.d.txt"]);
+        let found = expand(&dir.path().join("logs/*.txt")).unwrap();
+        assert_eq!(
+            found,
+            [dir.path().join("logs/a.txt"), dir.path().join("logs/b.txt")]
+        );
+    }
+
+    #[test]
+    fn wildcards_may_appear_in_any_component() {
+        let dir = files(&["x1/in/f", "x2/in/f", "y/in/f", "x3/out/f"]);
+        let found = expand(&dir.path().join("x?/in/f")).unwrap();
+        assert_eq!(
+            found,
+            [dir.path().join("x1/in/f"), dir.path().join("x2/in/f")]
+        );
+    }
+
+    #[test]
+    fn no_match_is_not_found() {
+        let dir = files(&["a.txt"]);
+        let err = expand(&dir.path().join("*.log")).unwrap_err();
+        assert!(err.is_not_found());
+        assert!(err.to_string().contains("*.log"), "{err}");
+    }
+
+    #[test]
+    fn wildcards_do_not_cross_separators() {
+        let dir = files(&["a/b.txt"]);
+        assert!(expand(&dir.path().join("*.txt")).is_err());
+        assert!(expand(&dir.path().join("a*b.txt")).is_err());
+    }
+}
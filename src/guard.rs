@@ -0,0 +1,272 @@
+//! A safety check between expanding a wildcard pattern and acting on what
+//! it matched: show the matches, and ask before touching more than a few.
+
+use std::path::{Path, PathBuf};
+
+use crate::error::{FmanError, FmanResult};
+
+/// How many matches may be acted on without asking first.
+pub const DEFAULT_MAX_MATCHES_WITHOUT_CONFIRM: usize = 50;
+
+/// Matches shown from each end of a long preview.
+const PREVIEW_EDGE: usize = 5;
+
+/// Where a [`MatchGuard`] shows its preview and asks its question. The CLI
+/// uses the terminal; a scripted prompter drives the guard without one.
+pub trait Prompter {
+    /// Whether anyone is there to answer.
+    fn is_interactive(&self) -> bool;
+
+    fn show(&mut self, line: &str);
+
+    /// Ask a yes/no question.
+    fn confirm(&mut self, question: &str) -> bool;
+}
+
+#[derive(Debug, Clone)]
+pub struct MatchGuard {
+    preview: bool,
+    max_without_confirm: usize,
+    assume_yes: bool,
+}
+
+impl Default for MatchGuard {
+    fn default() -> Self {
+        MatchGuard {
+            preview: true,
+            max_without_confirm: DEFAULT_MAX_MATCHES_WITHOUT_CONFIRM,
+            assume_yes: false,
+        }
+    }
+}
+
+impl MatchGuard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Show the match count and the first and last few matches (default
+    /// on).
+    pub fn preview(mut self, preview: bool) -> Self {
+        self.preview = preview;
+        self
+    }
+
+    /// Ask before acting on more than `max` matches.
+    pub fn max_without_confirm(mut self, max: usize) -> Self {
+        self.max_without_confirm = max;
+        self
+    }
+
+    /// Act on any number of matches without asking.
+    pub fn assume_yes(mut self, yes: bool) -> Self {
+        self.assume_yes = yes;
+        self
+    }
+
+    /// Vet `matches`, the expansion of `pattern`, before `action` (a verb
+    /// such as `delete`) is applied to them.
+    ///
+    /// Fails with `InvalidInput` if confirmation is needed but `prompter`
+    /// is not interactive, and with `Cancelled` if the answer is no.
+    pub fn check(
+        &self,
+        action: &str,
+        pattern: &Path,
+        matches: &[PathBuf],
+        prompter: &mut dyn Prompter,
+    ) -> FmanResult<()> {
+        if self.preview {
+            for line in preview(pattern, matches, PREVIEW_EDGE) {
+                prompter.show(&line);
+            }
+        }
+        if matches.len() <= self.max_without_confirm || self.assume_yes {
+            return Ok(());
+        }
+        if !prompter.is_interactive() {
+            return Err(FmanError::InvalidInput(format!(
+                "{} matches {} entries, more than {} allowed without confirmation; \
+                 pass --yes to {action} them",
+                pattern.display(),
+                matches.len(),
+                self.max_without_confirm
+            )));
+        }
+        let question = format!(
+            "{action} {} entries matching {}?",
+            matches.len(),
+            pattern.display()
+        );
+        if prompter.confirm(&question) {
+            Ok(())
+        } else {
+            Err(FmanError::Cancelled)
+        }
+    }
+}
+
+/// The preview of `matches`, sorted: a count, then every match, or only
+/// `edge` from each end with the number left out between them.
+pub fn preview(pattern: &Path, matches: &[PathBuf], edge: usize) -> Vec<String> {
+    let mut sorted: Vec<&PathBuf> = matches.iter().collect();
+    sorted.sort();
+    let count = match sorted.len() {
+        1 => "1 match".to_string(),
+        n => format!("{n} matches"),
+    };
+    let mut lines = vec![format!("{}: {count}", pattern.display())];
+    let line = |path: &&PathBuf| format!("  {}", path.display());
+    if sorted.len() <= edge * 2 {
+        lines.extend(sorted.iter().map(line));
+    } else {
+        lines.extend(sorted[..edge].iter().map(line));
+        lines.push(format!("  ... {} more", sorted.len() - edge * 2));
+        lines.extend(sorted[sorted.len() - edge..].iter().map(line));
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Answers from a script, remembering what it was shown and asked.
+    #[derive(Default)]
+    struct Scripted {
+        interactive: bool,
+        answers: Vec<bool>,
+        shown: Vec<String>,
+        asked: Vec<String>,
+    }
+
+    impl Scripted {
+        fn answering(answers: &[bool]) -> Self {
+            Scripted {
+                interactive: true,
+                answers: answers.to_vec(),
+                ..Scripted::default()
+            }
+        }
+    }
+
+    impl Prompter for Scripted {
+        fn is_interactive(&self) -> bool {
+            self.interactive
+        }
+
+        fn show(&mut self, line: &str) {
+            self.shown.push(line.to_string());
+        }
+
+        fn confirm(&mut self, question: &str) -> bool {
+            self.asked.push(question.to_string());
+            self.answers.remove(0)
+        }
+    }
+
+    fn matches(count: usize) -> Vec<PathBuf> {
+        (0..count)
+            .rev()
+            .map(|i| PathBuf::from(format!("f{i:02}")))
+            .collect()
+    }
+
+    #[test]
+    fn a_short_list_is_previewed_in_full_and_sorted() {
+        let lines = preview(
+            Path::new("*.log"),
+            &[PathBuf::from("b"), PathBuf::from("a")],
+            5,
+        );
+        assert_eq!(lines, ["*.log: 2 matches", "  a", "  b"]);
+        let lines = preview(Path::new("*.log"), &[PathBuf::from("a")], 5);
+        assert_eq!(lines[0], "*.log: 1 match");
+    }
+
+    #[test]
+    fn a_long_list_shows_only_its_ends() {
+        let lines = preview(Path::new("f*"), &matches(20), 3);
+        assert_eq!(
+            lines,
+            [
+                "f*: 20 matches",
+                "  f00",
+                "  f01",
+                "  f02",
+                "  ... 14 more",
+                "  f17",
+                "  f18",
+                "  f19",
+            ]
+        );
+        assert_eq!(preview(Path::new("f*"), &matches(6), 3).len(), 7);
+    }
+
+    #[test]
+    fn matches_within_the_threshold_need_no_answer() {
+        let mut prompter = Scripted::answering(&[]);
+        let guard = MatchGuard::new().max_without_confirm(10);
+        guard
+            .check("delete", Path::new("f*"), &matches(10), &mut prompter)
+            .unwrap();
+        assert!(prompter.asked.is_empty());
+        assert_eq!(prompter.shown[0], "f*: 10 matches");
+        assert_eq!(prompter.shown.len(), 11);
+    }
+
+    #[test]
+    fn more_matches_than_the_threshold_ask_first() {
+        let guard = MatchGuard::new().max_without_confirm(10);
+        let mut prompter = Scripted::answering(&[true]);
+        guard
+            .check("delete", Path::new("f*"), &matches(11), &mut prompter)
+            .unwrap();
+        assert_eq!(prompter.asked, ["delete 11 entries matching f*?"]);
+
+        let mut prompter = Scripted::answering(&[false]);
+        let err = guard
+            .check("move", Path::new("f*"), &matches(11), &mut prompter)
+            .unwrap_err();
+        assert!(matches!(err, FmanError::Cancelled), "{err}");
+        assert_eq!(prompter.asked, ["move 11 entries matching f*?"]);
+    }
+
+    #[test]
+    fn without_anyone_to_ask_too_many_matches_are_an_error() {
+        let guard = MatchGuard::new().max_without_confirm(10);
+        let mut prompter = Scripted::default();
+        let err = guard
+            .check("delete", Path::new("f*"), &matches(11), &mut prompter)
+            .unwrap_err();
+        assert!(matches!(err, FmanError::InvalidInput(_)), "{err}");
+        assert!(
+            err.to_string().contains("pass --yes to delete them"),
+            "{err}"
+        );
+        guard
+            .check("delete", Path::new("f*"), &matches(10), &mut prompter)
+            .unwrap();
+    }
+
+    #[test]
+    fn assume_yes_skips_the_question() {
+        let guard = MatchGuard::new().max_without_confirm(1).assume_yes(true);
+        let mut prompter = Scripted::default();
+        guard
+            .check("delete", Path::new("f*"), &matches(100), &mut prompter)
+            .unwrap();
+        assert!(prompter.asked.is_empty());
+    }
+
+    #[test]
+    fn the_preview_can_be_turned_off() {
+        let guard = MatchGuard::new().preview(false).max_without_confirm(1);
+        let mut prompter = Scripted::answering(&[true]);
+        guard
+            .check("delete", Path::new("f*"), &matches(5), &mut prompter)
+            .unwrap();
+        assert!(prompter.shown.is_empty());
+        assert_eq!(prompter.asked.len(), 1);
+    }
+}
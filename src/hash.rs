@@ -0,0 +1,613 @@
+//! Streaming content digests of files and directory trees, and checks of
+//! files against `.sha256` sidecars.
+
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use md5::Md5;
+#[cfg(feature = "json")]
+use serde::Serialize;
+use sha1::Sha1;
+use sha2::Sha256;
+use sha2::digest::DynDigest;
+
+use crate::error::{FmanError, FmanResult};
+use crate::validate::{ensure_exists, ensure_is_dir, ensure_is_file};
+use crate::walk::Walk;
+
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// Extension of a digest sidecar: `data.bin` is covered by
+/// `data.bin.sha256`.
+pub const SIDECAR_EXTENSION: &str = "sha256";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    /// Only for checking against existing `md5sum` output; not collision
+    /// resistant.
+    Md5,
+    /// Only for checking against existing `sha1sum` output; not collision
+    /// resistant.
+    Sha1,
+    #[default]
+    Sha256,
+}
+
+impl HashAlgorithm {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            HashAlgorithm::Md5 => "md5",
+            HashAlgorithm::Sha1 => "sha1",
+            HashAlgorithm::Sha256 => "sha256",
+        }
+    }
+
+    fn hasher(self) -> Box<dyn DynDigest> {
+        match self {
+            HashAlgorithm::Md5 => Box::new(Md5::default()),
+            HashAlgorithm::Sha1 => Box::new(Sha1::default()),
+            HashAlgorithm::Sha256 => Box::new(Sha256::default()),
+        }
+    }
+}
+
+/// Lowercase hex digest of the file at `path`, read in fixed-size chunks.
+pub fn hash_file(path: &Path, algo: HashAlgorithm) -> FmanResult<String> {
+    ensure_exists(path)?;
+    ensure_is_file(path)?;
+    Ok(hash_reader(File::open(path)?, algo)?)
+}
+
+/// Lowercase hex digest of everything `reader` yields.
+pub fn hash_reader(mut reader: impl Read, algo: HashAlgorithm) -> io::Result<String> {
+    let mut hasher = algo.hasher();
+    let mut buf = vec![0u8; BUFFER_SIZE];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => hasher.update(&buf[..n]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(to_hex(&hasher.finalize()))
+}
+
+/// Deterministic digest of a directory tree.
+///
+/// Every regular file contributes its `/`-separated relative path and
+/// content digest, and every symlink its relative path and target, in
+/// sorted path order. Two trees hash equal exactly when they hold the same
+/// files with the same contents at the same places; directory metadata and
+/// empty directories are ignored.
+pub fn hash_tree(dir: &Path, algo: HashAlgorithm) -> FmanResult<String> {
+    let mut records = Vec::new();
+    for entry in Walk::new(dir) {
+        let entry = entry?;
+        let rel = entry
+            .path()
+            .strip_prefix(dir)
+            .unwrap_or(entry.path())
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let file_type = entry.file_type();
+        if file_type.is_file() {
+            records.push(format!("F {rel}\0{}\n", hash_file(entry.path(), algo)?));
+        } else if file_type.is_symlink() {
+            let target = fs::read_link(entry.path())?;
+            records.push(format!("L {rel}\0{}\n", target.to_string_lossy()));
+        }
+    }
+    records.sort();
+
+    let mut hasher = algo.hasher();
+    for record in &records {
+        hasher.update(record.as_bytes());
+    }
+    Ok(to_hex(&hasher.finalize()))
+}
+
+/// Hash each of `paths` in parallel, preserving order in the result.
+///
+/// Directories are reduced with [`hash_tree`] when `recursive` is set and
+/// rejected with `InvalidInput` otherwise.
+pub fn hash_paths(
+    paths: &[PathBuf],
+    algo: HashAlgorithm,
+    recursive: bool,
+) -> Vec<FmanResult<String>> {
+    let hash_one = |path: &Path| {
+        if path.is_dir() {
+            if !recursive {
+                return Err(FmanError::InvalidInput(format!(
+                    "{} is a directory (use --recursive)",
+                    path.display()
+                )));
+            }
+            return hash_tree(path, algo);
+        }
+        hash_file(path, algo)
+    };
+
+    let workers = thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .min(paths.len());
+    let next = AtomicUsize::new(0);
+    let results: Vec<Mutex<Option<FmanResult<String>>>> =
+        paths.iter().map(|_| Mutex::new(None)).collect();
+
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| {
+                loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(path) = paths.get(i) else { break };
+                    let result = hash_one(path);
+                    *results[i].lock().unwrap_or_else(|e| e.into_inner()) = Some(result);
+                }
+            });
+        }
+    });
+
+    results
+        .into_iter()
+        .map(|slot| {
+            slot.into_inner()
+                .unwrap_or_else(|e| e.into_inner())
+                .expect("every path is hashed by some worker")
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(Serialize), serde(rename_all = "kebab-case"))]
+pub enum SidecarStatus {
+    /// The file's digest matches its sidecar.
+    Ok,
+    /// It does not, or the sidecar or the file could not be read.
+    Failed,
+    /// A sidecar next to which there is no file.
+    Orphaned,
+    /// A file without a sidecar.
+    Uncovered,
+}
+
+impl SidecarStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SidecarStatus::Ok => "OK",
+            SidecarStatus::Failed => "FAILED",
+            SidecarStatus::Orphaned => "ORPHANED",
+            SidecarStatus::Uncovered => "UNCOVERED",
+        }
+    }
+}
+
+/// The outcome for one file or sidecar.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "json", derive(Serialize))]
+pub struct SidecarCheck {
+    /// The data file; for an orphaned sidecar, the sidecar.
+    pub path: PathBuf,
+    pub status: SidecarStatus,
+    /// The digest the sidecar records.
+    #[cfg_attr(feature = "json", serde(skip_serializing_if = "Option::is_none"))]
+    pub expected: Option<String>,
+    /// The file's digest, when it could be read.
+    #[cfg_attr(feature = "json", serde(skip_serializing_if = "Option::is_none"))]
+    pub actual: Option<String>,
+    /// Why a failed check has no digest to compare.
+    #[cfg_attr(feature = "json", serde(skip_serializing_if = "Option::is_none"))]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "json", derive(Serialize))]
+pub struct SidecarCounts {
+    pub ok: u64,
+    pub failed: u64,
+    pub orphaned: u64,
+    pub uncovered: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "json", derive(Serialize))]
+pub struct SidecarReport {
+    /// Sorted by path.
+    pub checks: Vec<SidecarCheck>,
+    pub counts: SidecarCounts,
+}
+
+/// Check every `.sha256` sidecar under `root` against the file next to it,
+/// hashing the files in parallel. With `require_coverage`, files that have
+/// no sidecar are reported as well.
+///
+/// A sidecar holds either `DIGEST  NAME`, as `sha256sum` writes it, or the
+/// bare digest; only the digest is used, the file being the sidecar's own
+/// path without the extension. A directory the walk cannot read is a
+/// failure of its own and the rest is still checked.
+pub fn verify_sidecars(
+    root: &Path,
+    algo: HashAlgorithm,
+    require_coverage: bool,
+) -> FmanResult<SidecarReport> {
+    ensure_exists(root)?;
+    ensure_is_dir(root)?;
+    let mut checks = Vec::new();
+    let mut files = BTreeSet::new();
+    for entry in Walk::new(root) {
+        match entry {
+            Ok(entry) if entry.file_type().is_file() => {
+                files.insert(entry.path().to_path_buf());
+            }
+            Ok(_) => {}
+            Err(e) => checks.push(SidecarCheck {
+                path: root.to_path_buf(),
+                status: SidecarStatus::Failed,
+                expected: None,
+                actual: None,
+                error: Some(e.to_string()),
+            }),
+        }
+    }
+    let is_sidecar = |path: &Path| path.extension().is_some_and(|ext| ext == SIDECAR_EXTENSION);
+
+    let mut pending = Vec::new();
+    let mut covered = BTreeSet::new();
+    for sidecar in files.iter().filter(|path| is_sidecar(path)) {
+        let data = sidecar.with_extension("");
+        let failed = |error: String| SidecarCheck {
+            path: data.clone(),
+            status: SidecarStatus::Failed,
+            expected: None,
+            actual: None,
+            error: Some(error),
+        };
+        if fs::symlink_metadata(&data).is_err() {
+            checks.push(SidecarCheck {
+                path: sidecar.clone(),
+                status: SidecarStatus::Orphaned,
+                expected: None,
+                actual: None,
+                error: None,
+            });
+            continue;
+        }
+        covered.insert(data.clone());
+        match fs::read_to_string(sidecar) {
+            Ok(text) => match parse_sidecar(&text) {
+                Some(expected) => pending.push((data, expected)),
+                None => checks.push(failed(format!(
+                    "{} holds no {SIDECAR_EXTENSION} digest",
+                    sidecar.display()
+                ))),
+            },
+            Err(e) => checks.push(failed(format!("{}: {e}", sidecar.display()))),
+        }
+    }
+
+    let paths: Vec<PathBuf> = pending.iter().map(|(path, _)| path.clone()).collect();
+    for ((path, expected), digest) in pending.into_iter().zip(hash_paths(&paths, algo, false)) {
+        let (status, actual, error) = match digest {
+            Ok(actual) if actual == expected => (SidecarStatus::Ok, Some(actual), None),
+            Ok(actual) => (SidecarStatus::Failed, Some(actual), None),
+            Err(e) => (SidecarStatus::Failed, None, Some(e.to_string())),
+        };
+        checks.push(SidecarCheck {
+            path,
+            status,
+            expected: Some(expected),
+            actual,
+            error,
+        });
+    }
+
+    if require_coverage {
+        for path in files
+            .into_iter()
+            .filter(|path| !is_sidecar(path) && !covered.contains(path))
+        {
+            checks.push(SidecarCheck {
+                path,
+                status: SidecarStatus::Uncovered,
+                expected: None,
+                actual: None,
+                error: None,
+            });
+        }
+    }
+
+    checks.sort_by(|a, b| a.path.cmp(&b.path));
+    let mut counts = SidecarCounts::default();
+    for check in &checks {
+        *match check.status {
+            SidecarStatus::Ok => &mut counts.ok,
+            SidecarStatus::Failed => &mut counts.failed,
+            SidecarStatus::Orphaned => &mut counts.orphaned,
+            SidecarStatus::Uncovered => &mut counts.uncovered,
+        } += 1;
+    }
+    Ok(SidecarReport { checks, counts })
+}
+
+/// The lowercase digest at the start of a sidecar's first line, followed
+/// by nothing or by whitespace and a name.
+fn parse_sidecar(text: &str) -> Option<String> {
+    let digest = text.lines().next()?.split_whitespace().next()?;
+    (digest.len() == 64 && digest.bytes().all(|b| b.is_ascii_hexdigit()))
+        .then(|| digest.to_ascii_lowercase())
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        let _ = write!(out, "{byte:02x}");
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    fn tree(root: &Path, files: &[(&str, &str)]) {
+        for (rel, contents) in files {
+            let path = root.join(rel);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+    }
+
+    #[test]
+    fn digests_match_the_usual_tools() {
+        let empty = |algo| hash_reader(io::empty(), algo).unwrap();
+        assert_eq!(
+            empty(HashAlgorithm::Md5),
+            "d41d8cd98f00b204e9800998ecf8427e"
+        );
+        assert_eq!(
+            empty(HashAlgorithm::Sha1),
+            "da39a3ee5e6b4b0d3255bfef95601890afd80709"
+        );
+        assert_eq!(
+            empty(HashAlgorithm::Sha256),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+
+    #[test]
+    fn identical_trees_hash_equal() {
+        let dir = TempDir::new().unwrap();
+        let files = [("a", "1"), ("sub/b", "2"), ("sub/deeper/c", "3")];
+        tree(&dir.path().join("x"), &files);
+        tree(&dir.path().join("y"), &files);
+        // Empty directories do not count.
+        fs::create_dir(dir.path().join("y/empty")).unwrap();
+        let digest = |name| hash_tree(&dir.path().join(name), HashAlgorithm::Sha256).unwrap();
+        assert_eq!(digest("x"), digest("y"));
+    }
+
+    #[test]
+    fn trees_differing_in_content_or_place_hash_differently() {
+        let dir = TempDir::new().unwrap();
+        tree(&dir.path().join("x"), &[("a", "1"), ("sub/b", "2")]);
+        tree(&dir.path().join("content"), &[("a", "1"), ("sub/b", "3")]);
+        tree(&dir.path().join("place"), &[("a", "1"), ("b", "2")]);
+        let digest = |name| hash_tree(&dir.path().join(name), HashAlgorithm::Sha256).unwrap();
+        assert_ne!(digest("x"), digest("content"));
+        assert_ne!(digest("x"), digest("place"));
+    }
+
+    #[test]
+    fn paths_are_hashed_in_order() {
+        let dir = TempDir::new().unwrap();
+        let paths: Vec<PathBuf> = (0..20).map(|i| dir.path().join(i.to_string())).collect();
+        for (i, path) in paths.iter().enumerate() {
+            fs::write(path, i.to_string()).unwrap();
+        }
+        let digests = hash_paths(&paths, HashAlgorithm::Sha256, false);
+        for (i, digest) in digests.into_iter().enumerate() {
+            let expected = hash_reader(i.to_string().as_bytes(), HashAlgorithm::Sha256).unwrap();
+            assert_eq!(digest.unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn a_directory_needs_recursive() {
+        let dir = TempDir::new().unwrap();
+        tree(dir.path(), &[("a", "1")]);
+        let paths = [dir.path().to_path_buf()];
+        let err = hash_paths(&paths, HashAlgorithm::Sha256, false)
+            .remove(0)
+            .unwrap_err();
+        assert!(matches!(&err, FmanError::InvalidInput(m) if m.contains("--recursive")));
+        let digest = hash_paths(&paths, HashAlgorithm::Sha256, true).remove(0);
+        assert_eq!(
+            digest.unwrap(),
+            hash_tree(dir.path(), HashAlgorithm::Sha256).unwrap()
+        );
+    }
+
+    const HELLO: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+    const WORLD: &str = "486ea46224d1bb4fb680f34f7c9ad96a8f24ec88be73ea8e5a6c65260e9cb8a7";
+
+    /// One valid pair in each sidecar format, a corrupted file, an orphaned
+    /// sidecar and a file with no sidecar.
+    fn sidecar_tree() -> TempDir {
+        let dir = TempDir::new().unwrap();
+        let named = format!("{HELLO}  good.txt\n");
+        let bare = format!("{WORLD}\n");
+        let corrupt = format!("{HELLO}  sub/bad.txt\n");
+        tree(
+            dir.path(),
+            &[
+                ("good.txt", "hello"),
+                ("good.txt.sha256", &named),
+                ("sub/bare.txt", "world"),
+                ("sub/bare.txt.sha256", &bare),
+                ("sub/bad.txt", "hellO"),
+                ("sub/bad.txt.sha256", &corrupt),
+                ("gone.txt.sha256", &bare),
+                ("loose.txt", "loose"),
+            ],
+        );
+        dir
+    }
+
+    fn statuses(report: &SidecarReport, root: &Path) -> Vec<(String, &'static str)> {
+        report
+            .checks
+            .iter()
+            .map(|check| {
+                let rel = check.path.strip_prefix(root).unwrap();
+                (rel.to_string_lossy().into_owned(), check.status.as_str())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sidecars_are_classified() {
+        let dir = sidecar_tree();
+        let report = verify_sidecars(dir.path(), HashAlgorithm::Sha256, false).unwrap();
+        assert_eq!(
+            statuses(&report, dir.path()),
+            [
+                ("gone.txt.sha256".to_string(), "ORPHANED"),
+                ("good.txt".to_string(), "OK"),
+                ("sub/bad.txt".to_string(), "FAILED"),
+                ("sub/bare.txt".to_string(), "OK"),
+            ]
+        );
+        let counts = report.counts;
+        assert_eq!(
+            (counts.ok, counts.failed, counts.orphaned, counts.uncovered),
+            (2, 1, 1, 0)
+        );
+        let bad = &report.checks[2];
+        assert_eq!(bad.expected.as_deref(), Some(HELLO));
+        assert_ne!(bad.actual.as_deref(), Some(HELLO));
+    }
+
+    #[test]
+    fn coverage_reports_files_without_sidecars() {
+        let dir = sidecar_tree();
+        let report = verify_sidecars(dir.path(), HashAlgorithm::Sha256, true).unwrap();
+        let uncovered: Vec<_> = statuses(&report, dir.path())
+            .into_iter()
+            .filter(|(_, status)| *status == "UNCOVERED")
+            .collect();
+        assert_eq!(uncovered, [("loose.txt".to_string(), "UNCOVERED")]);
+        assert_eq!(report.counts.uncovered, 1);
+    }
+
+    #[test]
+    fn an_unreadable_sidecar_fails_its_file() {
+        let dir = TempDir::new().unwrap();
+        tree(
+            dir.path(),
+            &[("f", "hello"), ("f.sha256", "not a digest\n")],
+        );
+        let report = verify_sidecars(dir.path(), HashAlgorithm::Sha256, false).unwrap();
+        assert_eq!(report.checks[0].status, SidecarStatus::Failed);
+        assert!(
+            report.checks[0]
+                .error
+                .as_deref()
+                .unwrap()
+                .contains("no sha256 digest")
+        );
+    }
+
+    #[test]
+    fn sidecar_digests_parse_in_either_format() {
+        let upper = HELLO.to_ascii_uppercase();
+        for text in [
+            HELLO.to_string(),
+            format!("{HELLO}\n"),
+            format!("{HELLO}  name with spaces.txt\n"),
+            format!("{HELLO} *binary.bin\n"),
+            format!("{upper}\n"),
+        ] {
+            assert_eq!(parse_sidecar(&text).as_deref(), Some(HELLO), "{text:?}");
+        }
+        for text in [
+            "",
+            "\n",
+            &HELLO[..63],
+            &format!("{HELLO}0"),
+            &"g".repeat(64),
+        ] {
+            assert_eq!(parse_sidecar(text), None, "{text:?}");
+        }
+    }
+
+    #[test]
+    fn verifying_needs_a_directory() {
+        let dir = TempDir::new().unwrap();
+        tree(dir.path(), &[("f", "")]);
+        let err = verify_sidecars(&dir.path().join("f"), HashAlgorithm::Sha256, false).unwrap_err();
+        assert_eq!(err.exit_code(), 4);
+        assert!(
+            verify_sidecars(&dir.path().join("missing"), HashAlgorithm::Sha256, false)
+                .unwrap_err()
+                .is_not_found()
+        );
+    }
+
+    #[test]
+    fn hash_file_uses_the_chosen_algorithm() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("hello");
+        fs::write(&path, "hello").unwrap();
+        let digest = |algo| hash_file(&path, algo).unwrap();
+        assert_eq!(
+            digest(HashAlgorithm::Md5),
+            "5d41402abc4b2a76b9719d911017c592"
+        );
+        assert_eq!(
+            digest(HashAlgorithm::Sha1),
+            "aaf4c61ddcc5e8a2dabede0f3b482cd9aea9434d"
+        );
+        assert_eq!(
+            digest(HashAlgorithm::Sha256),
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+    }
+
+    #[test]
+    fn hash_file_streams_past_one_buffer() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("big");
+        let data: Vec<u8> = (0..BUFFER_SIZE * 3 + 5).map(|i| (i % 251) as u8).collect();
+        fs::write(&path, &data).unwrap();
+        assert_eq!(
+            hash_file(&path, HashAlgorithm::Sha256).unwrap(),
+            hash_reader(&data[..], HashAlgorithm::Sha256).unwrap()
+        );
+        let mut truncated = data.clone();
+        truncated.pop();
+        assert_ne!(
+            hash_file(&path, HashAlgorithm::Sha256).unwrap(),
+            hash_reader(&truncated[..], HashAlgorithm::Sha256).unwrap()
+        );
+    }
+
+    #[test]
+    fn hash_file_rejects_directories_and_missing_paths() {
+        let dir = TempDir::new().unwrap();
+        let err = hash_file(dir.path(), HashAlgorithm::Sha256).unwrap_err();
+        assert!(matches!(err, FmanError::InvalidInput(_)), "{err}");
+        let err = hash_file(&dir.path().join("missing"), HashAlgorithm::Sha256).unwrap_err();
+        assert!(err.is_not_found(), "{err}");
+    }
+}
@@ -0,0 +1,294 @@
+//! `fman --help-json`: the command-line definition as JSON, for tools that
+//! generate wrappers around fman.
+//!
+//! The output is built from the live clap definition, so every flag shows
+//! up without being listed here. Field names are a compatibility surface:
+//! add fields freely, but rename or remove one only together with a bump
+//! of [`FORMAT_VERSION`].
+
+use clap::{Arg, ArgAction, Command};
+use serde::Serialize;
+
+/// Version of the JSON layout below.
+pub const FORMAT_VERSION: u32 = 1;
+
+#[derive(Serialize)]
+pub struct Description {
+    pub format_version: u32,
+    #[serde(flatten)]
+    pub command: CommandInfo,
+}
+
+#[derive(Serialize)]
+pub struct CommandInfo {
+    pub name: String,
+    pub version: Option<String>,
+    pub about: Option<String>,
+    pub aliases: Vec<String>,
+    pub hidden: bool,
+    /// Every argument the command accepts, including global ones and those
+    /// flattened in from shared groups.
+    pub args: Vec<ArgInfo>,
+    pub subcommands: Vec<CommandInfo>,
+}
+
+#[derive(Serialize)]
+pub struct ArgInfo {
+    /// Internal name; what `conflicts_with` refers to.
+    pub id: String,
+    pub long: Option<String>,
+    pub short: Option<String>,
+    pub aliases: Vec<String>,
+    /// 1-based position for positional arguments.
+    pub index: Option<usize>,
+    pub help: Option<String>,
+    /// Whether the argument takes a value rather than being a switch.
+    pub takes_value: bool,
+    pub value_names: Vec<String>,
+    /// Fewest and most values per occurrence; `max_values` is `null` when
+    /// unbounded.
+    pub min_values: usize,
+    pub max_values: Option<usize>,
+    /// The value must be attached with `=`.
+    pub require_equals: bool,
+    pub required: bool,
+    /// Whether the argument may be given more than once.
+    pub repeatable: bool,
+    /// Whether it is accepted after any subcommand as well.
+    pub global: bool,
+    pub hidden: bool,
+    /// Values used when the argument is absent; empty for switches.
+    pub defaults: Vec<String>,
+    /// The accepted values, when the set is fixed.
+    pub possible_values: Vec<String>,
+    /// Ids of the arguments that cannot be combined with this one, declared
+    /// on either side.
+    pub conflicts_with: Vec<String>,
+}
+
+/// Describe `command` and everything below it.
+pub fn describe(mut command: Command) -> Description {
+    command.build();
+    Description {
+        format_version: FORMAT_VERSION,
+        command: describe_command(&command),
+    }
+}
+
+fn describe_command(command: &Command) -> CommandInfo {
+    CommandInfo {
+        name: command.get_name().to_string(),
+        version: command.get_version().map(str::to_string),
+        about: command.get_about().map(ToString::to_string),
+        aliases: command.get_visible_aliases().map(str::to_string).collect(),
+        hidden: command.is_hide_set(),
+        args: command
+            .get_arguments()
+            .map(|arg| describe_arg(command, arg))
+            .collect(),
+        subcommands: command.get_subcommands().map(describe_command).collect(),
+    }
+}
+
+fn describe_arg(command: &Command, arg: &Arg) -> ArgInfo {
+    let range = arg.get_num_args().unwrap_or_default();
+    let takes_value = arg.get_action().takes_values() && range.takes_values();
+    let mut conflicts: Vec<String> = command
+        .get_arguments()
+        .filter(|other| {
+            other.get_id() != arg.get_id()
+                && (conflicts_with(command, arg, other) || conflicts_with(command, other, arg))
+        })
+        .map(|other| other.get_id().to_string())
+        .collect();
+    conflicts.sort();
+    ArgInfo {
+        id: arg.get_id().to_string(),
+        long: arg.get_long().map(str::to_string),
+        short: arg.get_short().map(String::from),
+        aliases: arg
+            .get_visible_aliases()
+            .unwrap_or_default()
+            .into_iter()
+            .map(str::to_string)
+            .collect(),
+        index: arg.get_index(),
+        help: arg.get_help().map(ToString::to_string),
+        takes_value,
+        value_names: arg
+            .get_value_names()
+            .filter(|_| takes_value)
+            .unwrap_or_default()
+            .iter()
+            .map(ToString::to_string)
+            .collect(),
+        min_values: if takes_value { range.min_values() } else { 0 },
+        max_values: match range.max_values() {
+            _ if !takes_value => Some(0),
+            usize::MAX => None,
+            max => Some(max),
+        },
+        require_equals: arg.is_require_equals_set(),
+        required: arg.is_required_set(),
+        repeatable: matches!(arg.get_action(), ArgAction::Append | ArgAction::Count),
+        global: arg.is_global_set(),
+        hidden: arg.is_hide_set(),
+        defaults: arg
+            .get_default_values()
+            .iter()
+            .filter(|_| takes_value)
+            .map(|value| value.to_string_lossy().into_owned())
+            .collect(),
+        possible_values: arg
+            .get_possible_values()
+            .iter()
+            .filter(|value| !value.is_hide_set())
+            .map(|value| value.get_name().to_string())
+            .collect(),
+        conflicts_with: conflicts,
+    }
+}
+
+/// Whether `arg` declares a conflict with `other`.
+fn conflicts_with(command: &Command, arg: &Arg, other: &Arg) -> bool {
+    command
+        .get_arg_conflicts_with(arg)
+        .iter()
+        .any(|conflict| conflict.get_id() == other.get_id())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use clap::{CommandFactory, Subcommand};
+
+    use crate::cli::{Cli, Commands};
+
+    fn sample() -> Command {
+        Command::new("tool")
+            .version("1.0")
+            .arg(
+                Arg::new("verbose")
+                    .long("verbose")
+                    .global(true)
+                    .action(ArgAction::SetTrue),
+            )
+            .subcommand(
+                Command::new("put")
+                    .about("Put things")
+                    .visible_alias("p")
+                    .arg(Arg::new("src").required(true))
+                    .arg(
+                        Arg::new("force")
+                            .short('f')
+                            .long("force")
+                            .action(ArgAction::SetTrue)
+                            .conflicts_with("keep"),
+                    )
+                    .arg(Arg::new("keep").long("keep").action(ArgAction::SetTrue))
+                    .arg(
+                        Arg::new("tag")
+                            .long("tag")
+                            .value_name("NAME")
+                            .action(ArgAction::Append),
+                    )
+                    .arg(
+                        Arg::new("mode")
+                            .long("mode")
+                            .value_parser(["fast", "safe"])
+                            .default_value("safe"),
+                    ),
+            )
+    }
+
+    fn subcommand<'a>(command: &'a CommandInfo, name: &str) -> &'a CommandInfo {
+        command
+            .subcommands
+            .iter()
+            .find(|sub| sub.name == name)
+            .unwrap_or_else(|| panic!("no subcommand {name}"))
+    }
+
+    fn arg<'a>(command: &'a CommandInfo, id: &str) -> &'a ArgInfo {
+        command
+            .args
+            .iter()
+            .find(|arg| arg.id == id)
+            .unwrap_or_else(|| panic!("{} has no argument {id}", command.name))
+    }
+
+    #[test]
+    fn commands_are_described_with_their_arguments() {
+        let described = describe(sample());
+        assert_eq!(described.format_version, FORMAT_VERSION);
+        assert_eq!(described.command.name, "tool");
+        assert_eq!(described.command.version.as_deref(), Some("1.0"));
+        let put = subcommand(&described.command, "put");
+        assert_eq!(put.about.as_deref(), Some("Put things"));
+        assert_eq!(put.aliases, ["p"]);
+        let src = arg(put, "src");
+        assert_eq!(
+            (src.index, src.required, src.takes_value),
+            (Some(1), true, true)
+        );
+    }
+
+    #[test]
+    fn switches_take_no_values() {
+        let described = describe(sample());
+        let force = arg(subcommand(&described.command, "put"), "force");
+        assert_eq!(force.short.as_deref(), Some("f"));
+        assert!(!force.takes_value && !force.repeatable);
+        assert_eq!((force.min_values, force.max_values), (0, Some(0)));
+        assert!(force.defaults.is_empty() && force.value_names.is_empty());
+    }
+
+    #[test]
+    fn conflicts_are_listed_on_both_sides() {
+        let described = describe(sample());
+        let put = subcommand(&described.command, "put");
+        assert_eq!(arg(put, "force").conflicts_with, ["keep"]);
+        assert_eq!(arg(put, "keep").conflicts_with, ["force"]);
+        assert!(arg(put, "tag").conflicts_with.is_empty());
+    }
+
+    #[test]
+    fn values_defaults_and_repetition_are_described() {
+        let described = describe(sample());
+        let put = subcommand(&described.command, "put");
+        let tag = arg(put, "tag");
+        assert!(tag.repeatable && tag.takes_value);
+        assert_eq!(tag.value_names, ["NAME"]);
+        let mode = arg(put, "mode");
+        assert_eq!(mode.possible_values, ["fast", "safe"]);
+        assert_eq!(mode.defaults, ["safe"]);
+    }
+
+    #[test]
+    fn global_arguments_are_listed_on_every_subcommand() {
+        let described = describe(sample());
+        let verbose = arg(subcommand(&described.command, "put"), "verbose");
+        assert!(verbose.global);
+    }
+
+    #[test]
+    fn every_fman_subcommand_is_described() {
+        let described = describe(Cli::command());
+        let expected = Commands::augment_subcommands(Command::new("fman"));
+        for command in expected.get_subcommands() {
+            subcommand(&described.command, command.get_name());
+        }
+    }
+
+    #[test]
+    fn flattened_groups_belong_to_the_subcommands_that_use_them() {
+        let described = describe(Cli::command());
+        let delete = subcommand(&described.command, "delete");
+        for id in ["preview", "max_matches_without_confirm", "yes"] {
+            arg(delete, id);
+        }
+        let copy = subcommand(&described.command, "copy");
+        assert!(copy.args.iter().all(|arg| arg.id != "yes"));
+    }
+}
@@ -0,0 +1,125 @@
+//! What `fman info` reports about a single path.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::error::{FmanError, FmanResult};
+use crate::list::{EntryKind, mode_bits};
+
+/// A path's type and attributes. A symlink is described as the link
+/// itself, never as its target.
+#[derive(Debug, Clone)]
+pub struct FileInfo {
+    pub path: PathBuf,
+    pub kind: EntryKind,
+    /// Size in bytes; for a symlink, the length of the path it holds.
+    pub size: u64,
+    /// Permission bits; on non-Unix platforms only the read-only bit is
+    /// reflected (`0o444` vs `0o666`).
+    pub mode: u32,
+    pub modified: Option<SystemTime>,
+    pub read_only: bool,
+    /// What a symlink points to, as stored in the link.
+    pub link_target: Option<PathBuf>,
+}
+
+/// Describe `path` without following it if it is a symlink.
+pub fn file_info(path: &Path) -> FmanResult<FileInfo> {
+    let meta = fs::symlink_metadata(path).map_err(|e| match e.kind() {
+        std::io::ErrorKind::NotFound => FmanError::missing_path(path),
+        _ => e.into(),
+    })?;
+    let kind = EntryKind::of(meta.file_type());
+    let link_target = match kind {
+        EntryKind::Symlink => Some(fs::read_link(path)?),
+        _ => None,
+    };
+    Ok(FileInfo {
+        path: path.to_path_buf(),
+        kind,
+        size: meta.len(),
+        mode: mode_bits(&meta),
+        modified: meta.modified().ok(),
+        read_only: meta.permissions().readonly(),
+        link_target,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    #[test]
+    fn a_file_is_described() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("f");
+        fs::write(&path, "hello").unwrap();
+        let info = file_info(&path).unwrap();
+        assert_eq!(info.path, path);
+        assert_eq!(info.kind, EntryKind::File);
+        assert_eq!(info.size, 5);
+        assert!(!info.read_only);
+        assert_eq!(info.link_target, None);
+        assert_eq!(info.modified, fs::metadata(&path).unwrap().modified().ok());
+    }
+
+    #[test]
+    fn a_directory_is_described() {
+        let dir = TempDir::new().unwrap();
+        assert_eq!(file_info(dir.path()).unwrap().kind, EntryKind::Dir);
+    }
+
+    #[test]
+    fn read_only_follows_the_permissions() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("f");
+        fs::write(&path, "x").unwrap();
+        let mut permissions = fs::metadata(&path).unwrap().permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(&path, permissions).unwrap();
+        let info = file_info(&path).unwrap();
+        assert!(info.read_only);
+        assert_eq!(info.mode & 0o222, 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn the_mode_is_the_permission_bits() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("f");
+        fs::write(&path, "x").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o751)).unwrap();
+        assert_eq!(file_info(&path).unwrap().mode, 0o751);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_symlink_is_described_as_itself() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("target"), "a much longer body").unwrap();
+        let link = dir.path().join("link");
+        std::os::unix::fs::symlink("target", &link).unwrap();
+        let info = file_info(&link).unwrap();
+        assert_eq!(info.kind, EntryKind::Symlink);
+        assert_eq!(info.link_target.as_deref(), Some(Path::new("target")));
+        assert_eq!(info.size, "target".len() as u64);
+
+        let dangling = dir.path().join("dangling");
+        std::os::unix::fs::symlink("nowhere", &dangling).unwrap();
+        let info = file_info(&dangling).unwrap();
+        assert_eq!(info.link_target.as_deref(), Some(Path::new("nowhere")));
+    }
+
+    #[test]
+    fn a_missing_path_is_not_found() {
+        let dir = TempDir::new().unwrap();
+        let err = file_info(&dir.path().join("missing")).unwrap_err();
+        assert!(err.is_not_found(), "{err}");
+        assert!(err.to_string().contains("missing"), "{err}");
+    }
+}
@@ -0,0 +1,143 @@
+//! Core file operations behind the `fman` command-line tool.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+pub(crate) mod apply;
+#[cfg(feature = "archive")]
+pub(crate) mod archive;
+#[cfg(feature = "async")]
+pub mod asynch;
+pub mod backend;
+pub mod budget;
+pub mod cachedir;
+pub mod cancel;
+pub mod checkpoint;
+pub(crate) mod chmod;
+pub mod cleanup;
+pub mod clock;
+pub(crate) mod compare;
+pub mod config;
+pub(crate) mod copy;
+pub(crate) mod dedupe;
+pub(crate) mod delete;
+pub(crate) mod du;
+pub mod error;
+pub(crate) mod find;
+pub mod format;
+pub mod fs;
+pub(crate) mod fsinfo;
+pub mod glob;
+pub mod guard;
+pub(crate) mod hash;
+pub(crate) mod info;
+pub(crate) mod link;
+pub(crate) mod list;
+pub mod lock;
+pub mod metadata;
+pub(crate) mod mirror;
+pub(crate) mod mv;
+pub mod naming;
+#[cfg(target_os = "linux")]
+pub mod open_files;
+pub mod ops;
+pub mod ownership;
+mod platform;
+pub mod preserve;
+pub(crate) mod relink;
+pub(crate) mod rename;
+pub mod schedule;
+pub mod spill;
+pub mod suggest;
+pub(crate) mod sync;
+pub(crate) mod template;
+pub mod timing;
+pub(crate) mod touch;
+pub(crate) mod trash;
+pub mod units;
+mod validate;
+pub mod walk;
+pub(crate) mod watch;
+
+pub use compare::files_equal;
+pub use copy::{
+    CopyOptions, CopyReport, CopyStatus, OverwritePolicy, Reflink, copy_from_file, copy_to_file,
+};
+pub use error::{FmanError, FmanResult};
+pub use info::FileInfo;
+pub use touch::Touched;
+
+use copy::copy_file;
+use delete::{DeleteOptions, delete_path};
+use fs::{RealFs, SharedFs};
+use hash::HashAlgorithm;
+use naming::NamingContext;
+use rename::rename_path;
+
+/// Copy `src` to `dst` as `options` say, e.g.
+/// `CopyOptions::new().force(true).preserve(true)`.
+///
+/// If `dst` is an existing directory the file is copied into it. The
+/// report has the path actually written and how the copy went.
+pub fn copy_file_with_options(
+    src: impl AsRef<Path>,
+    dst: impl AsRef<Path>,
+    options: &CopyOptions,
+) -> FmanResult<CopyReport> {
+    copy_file(src, dst, options)
+}
+
+/// Copy `src` to `dst`, failing with `AlreadyExists` rather than overwriting.
+///
+/// If `dst` is an existing directory the file is copied into it. Returns
+/// the path actually written.
+pub fn copy_file_safe(src: impl AsRef<Path>, dst: impl AsRef<Path>) -> FmanResult<PathBuf> {
+    Ok(copy_file_with_options(src, dst, &CopyOptions::new())?.destination)
+}
+
+/// Copy `src` to `dst`, overwriting any existing destination file. Returns
+/// the path actually written.
+pub fn copy_file_force(src: impl AsRef<Path>, dst: impl AsRef<Path>) -> FmanResult<PathBuf> {
+    Ok(copy_file_with_options(src, dst, &CopyOptions::new().force(true))?.destination)
+}
+
+/// Delete the file at `path`, failing on directories. With `force`, a
+/// missing file is not an error, like `rm -f`.
+pub fn delete_file(path: impl AsRef<Path>, force: bool) -> FmanResult<()> {
+    delete_path(path.as_ref(), &DeleteOptions::new().force(force))?;
+    Ok(())
+}
+
+/// Rename `path` to `new_name` within its directory, failing with
+/// `AlreadyExists` if that name is taken unless `force` is set. Returns
+/// the new path.
+pub fn rename_file(path: impl AsRef<Path>, new_name: &str, force: bool) -> FmanResult<PathBuf> {
+    rename_path(path.as_ref(), new_name, force, &RealFs)
+}
+
+/// Move `path` into the trash can instead of deleting it, returning its
+/// new location there. A name already in the trash gets a numeric suffix.
+pub fn trash_file(path: impl AsRef<Path>) -> FmanResult<PathBuf> {
+    let filesystem: SharedFs = Arc::new(RealFs);
+    trash::trash_file(path.as_ref(), &filesystem, &NamingContext::new())
+}
+
+/// Describe `path`: its type, size, permissions and modification time. A
+/// symlink is described as a link, with its target, not followed.
+pub fn file_info(path: impl AsRef<Path>) -> FmanResult<FileInfo> {
+    info::file_info(path.as_ref())
+}
+
+/// Lowercase hex digest of the file at `path` with `algo`, read in
+/// fixed-size chunks. A directory fails with `InvalidInput`, a missing
+/// path with `NotFound`.
+pub fn hash_file(path: impl AsRef<Path>, algo: HashAlgorithm) -> FmanResult<String> {
+    hash::hash_file(path.as_ref(), algo)
+}
+
+/// Create `path` as an empty file, or set its modification time to now if
+/// it exists. With `no_create` a missing file is left missing. A missing
+/// parent directory fails with `NotFound` naming it.
+pub fn touch(path: impl AsRef<Path>, no_create: bool) -> FmanResult<Touched> {
+    touch::touch_path(path.as_ref(), no_create, &RealFs)
+}
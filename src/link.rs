@@ -0,0 +1,265 @@
+//! Making hard links and symlinks, like `ln`.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "json")]
+use serde::Serialize;
+
+use crate::copy::{relative_path, resolve_destination_path};
+use crate::error::{FmanError, FmanResult};
+use crate::fs::Fs;
+use crate::validate::{
+    ensure_exists, ensure_is_file, ensure_not_exists, ensure_not_same_file, ensure_parents_are_dirs,
+};
+
+/// Which kind of link [`link_path`] makes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LinkKind {
+    /// Another name for the same file; both must be on one filesystem.
+    #[default]
+    Hard,
+    /// A symlink to the source by absolute path or, if `relative`, by a
+    /// path relative to the link's directory.
+    Symbolic { relative: bool },
+}
+
+/// Outcome of [`link_path`].
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "json", derive(Serialize))]
+pub struct LinkReport {
+    pub source: PathBuf,
+    /// The link, as resolved.
+    pub destination: PathBuf,
+    /// What a symlink points to; `None` for a hard link.
+    pub link_target: Option<PathBuf>,
+}
+
+/// Make `dst` a link of `kind` to `src`.
+///
+/// `dst` is resolved like a copy destination, so an existing directory
+/// gets a link named after the source. A hard link needs a regular file
+/// as its source. An existing destination fails with `AlreadyExists`
+/// unless `force` is set, in which case it is removed first; a directory
+/// is never replaced. Every change is made through `filesystem`.
+pub fn link_path(
+    src: &Path,
+    dst: &Path,
+    kind: LinkKind,
+    force: bool,
+    filesystem: &dyn Fs,
+) -> FmanResult<LinkReport> {
+    ensure_exists(src)?;
+    if kind == LinkKind::Hard {
+        ensure_is_file(src)?;
+    }
+    let dst = resolve_destination_path(src, dst)?;
+    ensure_parents_are_dirs(&dst)?;
+    if let Ok(existing) = fs::symlink_metadata(&dst) {
+        if !force {
+            ensure_not_exists(&dst)?;
+        }
+        if !existing.is_symlink() {
+            ensure_not_same_file(src, &dst)?;
+        } else if std::path::absolute(src)? == std::path::absolute(&dst)? {
+            // Replacing a symlink to the source is fine; replacing the
+            // source is not.
+            return Err(FmanError::SameFile(dst.display().to_string()));
+        }
+        if existing.is_dir() {
+            return Err(FmanError::InvalidInput(format!(
+                "cannot replace directory {} with a link",
+                dst.display()
+            )));
+        }
+        filesystem.remove_file(&dst)?;
+    }
+
+    let mut report = LinkReport {
+        source: src.to_path_buf(),
+        destination: dst.clone(),
+        link_target: None,
+    };
+    let relative = match kind {
+        LinkKind::Hard => {
+            // A hard link to a symlink would link the symlink itself.
+            let original = if fs::symlink_metadata(src)?.is_symlink() {
+                src.canonicalize()?
+            } else {
+                src.to_path_buf()
+            };
+            return match filesystem.hard_link(&original, &dst) {
+                Ok(()) => Ok(report),
+                Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+                    Err(FmanError::CrossDevice(format!(
+                        "cannot hard-link {} to {}: they are on different filesystems",
+                        src.display(),
+                        dst.display()
+                    )))
+                }
+                Err(e) => Err(e.into()),
+            };
+        }
+        LinkKind::Symbolic { relative } => relative,
+    };
+
+    // Resolve the source's directory but not the source itself, so that a
+    // link to a symlink points at that symlink.
+    let src_dir = src.parent().filter(|p| !p.as_os_str().is_empty());
+    let src_name = src.file_name().unwrap_or(src.as_os_str());
+    let absolute = src_dir
+        .unwrap_or(Path::new("."))
+        .canonicalize()?
+        .join(src_name);
+    let target = if relative {
+        let dst_dir = dst.parent().filter(|p| !p.as_os_str().is_empty());
+        relative_path(
+            &dst_dir.unwrap_or(Path::new(".")).canonicalize()?,
+            &absolute,
+        )
+    } else {
+        absolute
+    };
+    filesystem
+        .symlink(&target, &dst)
+        .map_err(symlink_privilege_error)?;
+    report.link_target = Some(target);
+    Ok(report)
+}
+
+/// On Windows a symlink takes the symlink privilege or Developer Mode;
+/// say so instead of passing on the bare "privilege not held".
+fn symlink_privilege_error(e: io::Error) -> io::Error {
+    #[cfg(windows)]
+    if e.raw_os_error() == Some(1314) {
+        // ERROR_PRIVILEGE_NOT_HELD
+        return io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "creating symlinks needs the symlink privilege or Developer Mode enabled",
+        );
+    }
+    e
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    use crate::fs::RealFs;
+
+    /// A scratch directory holding `src` with some contents.
+    fn source() -> (TempDir, PathBuf) {
+        let dir = TempDir::new().unwrap();
+        let src = dir.path().join("src");
+        fs::write(&src, "data").unwrap();
+        (dir, src)
+    }
+
+    #[test]
+    fn a_hard_link_shares_the_file() {
+        let (dir, src) = source();
+        let dst = dir.path().join("hard");
+        let report = link_path(&src, &dst, LinkKind::Hard, false, &RealFs).unwrap();
+        assert_eq!(report.destination, dst);
+        assert_eq!(report.link_target, None);
+        fs::write(&src, "changed").unwrap();
+        assert_eq!(fs::read_to_string(&dst).unwrap(), "changed");
+    }
+
+    #[test]
+    fn a_hard_link_needs_a_file() {
+        let (dir, _) = source();
+        let sub = dir.path().join("sub");
+        fs::create_dir(&sub).unwrap();
+        let err =
+            link_path(&sub, &dir.path().join("x"), LinkKind::Hard, false, &RealFs).unwrap_err();
+        assert!(matches!(err, FmanError::InvalidInput(_)), "{err}");
+        let err = link_path(
+            &dir.path().join("missing"),
+            &dir.path().join("x"),
+            LinkKind::Hard,
+            false,
+            &RealFs,
+        )
+        .unwrap_err();
+        assert!(err.is_not_found(), "{err}");
+        assert!(!dir.path().join("x").exists());
+    }
+
+    #[test]
+    fn linking_into_a_directory_uses_the_source_name() {
+        let (dir, src) = source();
+        let into = dir.path().join("into");
+        fs::create_dir(&into).unwrap();
+        let report = link_path(&src, &into, LinkKind::Hard, false, &RealFs).unwrap();
+        assert_eq!(report.destination, into.join("src"));
+        assert_eq!(fs::read_to_string(into.join("src")).unwrap(), "data");
+    }
+
+    #[test]
+    fn an_existing_destination_is_replaced_only_when_forced() {
+        let (dir, src) = source();
+        let dst = dir.path().join("taken");
+        fs::write(&dst, "old").unwrap();
+        let err = link_path(&src, &dst, LinkKind::Hard, false, &RealFs).unwrap_err();
+        assert!(matches!(err, FmanError::AlreadyExists(_)), "{err}");
+        assert_eq!(fs::read_to_string(&dst).unwrap(), "old");
+        link_path(&src, &dst, LinkKind::Hard, true, &RealFs).unwrap();
+        assert_eq!(fs::read_to_string(&dst).unwrap(), "data");
+    }
+
+    #[test]
+    fn a_file_is_never_linked_onto_itself() {
+        let (_dir, src) = source();
+        let err = link_path(&src, &src, LinkKind::Hard, true, &RealFs).unwrap_err();
+        assert!(matches!(err, FmanError::SameFile(_)), "{err}");
+        assert_eq!(fs::read_to_string(&src).unwrap(), "data");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_symlink_points_at_the_absolute_source() {
+        let (dir, src) = source();
+        let dst = dir.path().join("soft");
+        let kind = LinkKind::Symbolic { relative: false };
+        let report = link_path(&src, &dst, kind, false, &RealFs).unwrap();
+        let target = fs::read_link(&dst).unwrap();
+        assert!(target.is_absolute());
+        assert_eq!(target, src.canonicalize().unwrap());
+        assert_eq!(report.link_target, Some(target));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_relative_symlink_is_relative_to_the_link_directory() {
+        let (dir, src) = source();
+        let deep = dir.path().join("a/b");
+        fs::create_dir_all(&deep).unwrap();
+        let dst = deep.join("soft");
+        let kind = LinkKind::Symbolic { relative: true };
+        link_path(&src, &dst, kind, false, &RealFs).unwrap();
+        assert_eq!(fs::read_link(&dst).unwrap(), Path::new("../../src"));
+        assert_eq!(fs::read_to_string(&dst).unwrap(), "data");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_symlink_can_point_at_a_directory_but_never_replace_one() {
+        let (dir, _) = source();
+        let sub = dir.path().join("sub");
+        fs::create_dir(&sub).unwrap();
+        let kind = LinkKind::Symbolic { relative: true };
+        link_path(&sub, &dir.path().join("to-sub"), kind, false, &RealFs).unwrap();
+        assert!(dir.path().join("to-sub").is_dir());
+
+        let other = dir.path().join("other");
+        fs::create_dir(&other).unwrap();
+        fs::create_dir(other.join("sub")).unwrap();
+        let err = link_path(&sub, &other, kind, true, &RealFs).unwrap_err();
+        assert!(matches!(err, FmanError::InvalidInput(_)), "{err}");
+        assert!(other.join("sub").is_dir());
+    }
+}
@@ -0,0 +1,319 @@
+//! Directory listings for `fman ls`.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::error::FmanResult;
+use crate::format::{self, Fields};
+use crate::validate::{ensure_exists, ensure_is_dir};
+use crate::walk::{Walk, WalkOrder};
+
+/// Placeholders accepted by `ls --format`.
+pub const FORMAT_FIELDS: &[&str] = &["path", "name", "size", "mtime", "kind", "mode", "depth"];
+
+#[derive(Debug, Clone, Default)]
+pub struct ListOptions {
+    recursive: bool,
+    all: bool,
+    order: WalkOrder,
+}
+
+impl ListOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Descend into subdirectories.
+    pub fn recursive(mut self, recursive: bool) -> Self {
+        self.recursive = recursive;
+        self
+    }
+
+    /// Include entries whose names start with a dot, which are left out
+    /// (along with their contents) by default.
+    pub fn all(mut self, all: bool) -> Self {
+        self.all = all;
+        self
+    }
+
+    /// Order of the entries within each directory; see [`WalkOrder`].
+    pub fn order(mut self, order: WalkOrder) -> Self {
+        self.order = order;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Dir,
+    Symlink,
+    Other,
+}
+
+impl EntryKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EntryKind::File => "file",
+            EntryKind::Dir => "dir",
+            EntryKind::Symlink => "symlink",
+            EntryKind::Other => "other",
+        }
+    }
+
+    pub(crate) fn of(file_type: fs::FileType) -> Self {
+        if file_type.is_symlink() {
+            EntryKind::Symlink
+        } else if file_type.is_dir() {
+            EntryKind::Dir
+        } else if file_type.is_file() {
+            EntryKind::File
+        } else {
+            EntryKind::Other
+        }
+    }
+}
+
+/// A listed entry. Metadata is that of the entry itself, not a link target.
+#[derive(Debug, Clone)]
+pub struct ListEntry {
+    pub path: PathBuf,
+    pub depth: usize,
+    pub kind: EntryKind,
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+    /// Permission bits; on non-Unix platforms only the read-only bit is
+    /// reflected (`0o444` vs `0o666`).
+    pub mode: u32,
+}
+
+impl ListEntry {
+    pub(crate) fn from_path(path: PathBuf, depth: usize) -> FmanResult<Self> {
+        let meta = fs::symlink_metadata(&path)?;
+        Ok(Self {
+            kind: EntryKind::of(meta.file_type()),
+            size: meta.len(),
+            modified: meta.modified().ok(),
+            mode: mode_bits(&meta),
+            path,
+            depth,
+        })
+    }
+
+    /// `name` after the entry's size and modification time, in columns,
+    /// for `ls --long`.
+    pub fn long_row(&self, name: &str) -> String {
+        let modified = self.modified.map_or_else(
+            || "-".to_string(),
+            |time| format::format_time(time, format::DEFAULT_TIME_FORMAT),
+        );
+        format!("{:>12}  {modified:<19}  {name}", self.size)
+    }
+
+    pub fn name(&self) -> String {
+        self.path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+}
+
+impl Fields for ListEntry {
+    fn write_field(&self, name: &str, spec: Option<&str>, out: &mut String) {
+        match name {
+            "path" => out.push_str(&self.path.to_string_lossy()),
+            "name" => out.push_str(&self.name()),
+            "size" => out.push_str(&self.size.to_string()),
+            "mtime" => {
+                if let Some(time) = self.modified {
+                    out.push_str(&format::format_time(
+                        time,
+                        spec.unwrap_or(format::DEFAULT_TIME_FORMAT),
+                    ));
+                }
+            }
+            "kind" => out.push_str(self.kind.as_str()),
+            "mode" => out.push_str(&format!("{:o}", self.mode)),
+            "depth" => out.push_str(&self.depth.to_string()),
+            _ => {}
+        }
+    }
+}
+
+/// List the entries of `dir`, sorted by name within each directory unless
+/// the options ask for another order.
+pub fn list_dir(dir: &Path, options: &ListOptions) -> FmanResult<Vec<ListEntry>> {
+    list_dir_iter(dir, options)?.collect()
+}
+
+/// Like [`list_dir`], but yields entries as the walk produces them instead
+/// of collecting them.
+pub fn list_dir_iter(
+    dir: &Path,
+    options: &ListOptions,
+) -> FmanResult<impl Iterator<Item = FmanResult<ListEntry>> + use<>> {
+    ensure_exists(dir)?;
+    ensure_is_dir(dir)?;
+
+    let mut walk = Walk::new(dir)
+        .order(options.order)
+        .skip_hidden(!options.all);
+    if !options.recursive {
+        walk = walk.max_depth(1);
+    }
+    Ok(walk.map(|entry| {
+        let entry = entry?;
+        ListEntry::from_path(entry.path().to_path_buf(), entry.depth())
+    }))
+}
+
+#[cfg(unix)]
+pub(crate) fn mode_bits(meta: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    meta.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+pub(crate) fn mode_bits(meta: &fs::Metadata) -> u32 {
+    if meta.permissions().readonly() {
+        0o444
+    } else {
+        0o666
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    use crate::format::FormatTemplate;
+
+    fn tree() -> TempDir {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir_all(dir.path().join("sub/.hidden")).unwrap();
+        fs::write(dir.path().join("b"), "22").unwrap();
+        fs::write(dir.path().join("sub/a"), "1").unwrap();
+        fs::write(dir.path().join("sub/.hidden/c"), "3").unwrap();
+        dir
+    }
+
+    fn rows(dir: &Path, options: &ListOptions) -> Vec<String> {
+        let template = FormatTemplate::parse("{depth} {kind} {name}", FORMAT_FIELDS).unwrap();
+        list_dir(dir, options)
+            .unwrap()
+            .iter()
+            .map(|entry| template.render(entry))
+            .collect()
+    }
+
+    #[test]
+    fn only_the_top_level_by_default() {
+        let dir = tree();
+        assert_eq!(
+            rows(dir.path(), &ListOptions::new()),
+            ["1 file b", "1 dir sub"]
+        );
+    }
+
+    #[test]
+    fn recursive_descends_and_all_shows_hidden_entries() {
+        let dir = tree();
+        let options = ListOptions::new().recursive(true);
+        assert_eq!(
+            rows(dir.path(), &options),
+            ["1 file b", "1 dir sub", "2 file a"]
+        );
+        assert_eq!(
+            rows(dir.path(), &options.all(true)),
+            [
+                "1 file b",
+                "1 dir sub",
+                "2 dir .hidden",
+                "3 file c",
+                "2 file a"
+            ]
+        );
+    }
+
+    #[test]
+    fn fields_describe_the_entry() {
+        let dir = tree();
+        let entries = list_dir(dir.path(), &ListOptions::new()).unwrap();
+        let template = FormatTemplate::parse("{path}|{size}|{mtime:%Y}", FORMAT_FIELDS).unwrap();
+        let row = template.render(&entries[0]);
+        let year = format::format_time(SystemTime::now(), "%Y");
+        assert_eq!(row, format!("{}|2|{year}", dir.path().join("b").display()));
+        assert_eq!(entries[0].name(), "b");
+    }
+
+    #[test]
+    fn a_file_is_not_listable() {
+        let dir = tree();
+        let err = list_dir(&dir.path().join("b"), &ListOptions::new()).unwrap_err();
+        assert_eq!(err.exit_code(), 4);
+    }
+
+    #[test]
+    fn entries_are_sorted_by_name() {
+        let dir = TempDir::new().unwrap();
+        for name in ["delta", "Alpha", "charlie", "bravo"] {
+            fs::write(dir.path().join(name), "").unwrap();
+        }
+        let names: Vec<String> = list_dir(dir.path(), &ListOptions::new())
+            .unwrap()
+            .iter()
+            .map(ListEntry::name)
+            .collect();
+        assert_eq!(names, ["Alpha", "bravo", "charlie", "delta"]);
+    }
+
+    #[test]
+    fn a_missing_directory_is_not_found() {
+        let dir = tree();
+        let err = list_dir(&dir.path().join("missing"), &ListOptions::new()).unwrap_err();
+        assert!(err.is_not_found(), "{err}");
+    }
+
+    #[test]
+    fn a_long_row_has_size_and_time_columns() {
+        let entry = ListEntry {
+            path: PathBuf::from("d/name"),
+            depth: 1,
+            kind: EntryKind::File,
+            size: 1234,
+            modified: None,
+            mode: 0o644,
+        };
+        assert_eq!(
+            entry.long_row("name"),
+            format!("{:>12}  {:<19}  name", 1234, "-")
+        );
+        let dated = ListEntry {
+            modified: Some(SystemTime::now()),
+            ..entry
+        };
+        let row = dated.long_row("name");
+        let year = format::format_time(SystemTime::now(), "%Y");
+        assert_eq!(&row[14..18], year, "{row}");
+        assert!(row.ends_with("  name"), "{row}");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_symlink_is_listed_as_itself() {
+        let dir = tree();
+        std::os::unix::fs::symlink("sub", dir.path().join("link")).unwrap();
+        let entries = list_dir(dir.path(), &ListOptions::new().recursive(true)).unwrap();
+        let link = entries.iter().find(|e| e.name() == "link").unwrap();
+        assert_eq!(link.kind, EntryKind::Symlink);
+        let below = dir.path().join("link");
+        assert!(
+            !entries
+                .iter()
+                .any(|e| e.path.starts_with(&below) && e.depth > 1)
+        );
+    }
+}
@@ -0,0 +1,275 @@
+//! Advisory locks on lock files, held for as long as a guard lives.
+//!
+//! The lock is the platform's whole-file advisory lock (`flock` on Unix,
+//! `LockFileEx` on Windows), so it excludes other fman processes and
+//! anything else using the same mechanism, such as `flock(1)`.
+
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error::{FmanError, FmanResult};
+
+/// Longest pause between attempts while waiting with a timeout.
+const MAX_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LockMode {
+    /// Held by one holder at a time, excluding shared holders too.
+    #[default]
+    Exclusive,
+    /// Held by any number of shared holders, but no exclusive one.
+    Shared,
+}
+
+/// A held lock, released when dropped (also while unwinding from a panic).
+#[derive(Debug)]
+pub struct FileLock {
+    file: File,
+    path: PathBuf,
+    mode: LockMode,
+}
+
+impl FileLock {
+    /// Lock the file at `path`, creating it if needed, in `mode`. A shared
+    /// lock is also taken on a lock file this process may only read.
+    ///
+    /// Without a `timeout` this waits as long as it takes; with one it
+    /// gives up after that long, and a zero timeout does not wait at all.
+    /// Giving up fails with [`FmanError::Locked`].
+    pub fn acquire(path: &Path, mode: LockMode, timeout: Option<Duration>) -> FmanResult<Self> {
+        let opened = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path);
+        let file = match opened {
+            Err(e) if mode == LockMode::Shared && e.kind() == io::ErrorKind::PermissionDenied => {
+                File::open(path)
+            }
+            opened => opened,
+        }
+        .map_err(|e| FmanError::io_at(path, e))?;
+        let Some(timeout) = timeout else {
+            match mode {
+                LockMode::Exclusive => file.lock(),
+                LockMode::Shared => file.lock_shared(),
+            }
+            .map_err(|e| FmanError::io_at(path, e))?;
+            return Ok(FileLock::new(file, path, mode));
+        };
+        let deadline = Instant::now() + timeout;
+        let mut interval = Duration::from_millis(1);
+        loop {
+            let attempt = match mode {
+                LockMode::Exclusive => file.try_lock(),
+                LockMode::Shared => file.try_lock_shared(),
+            };
+            match attempt {
+                Ok(()) => return Ok(FileLock::new(file, path, mode)),
+                Err(TryLockError::Error(e)) => return Err(FmanError::io_at(path, e)),
+                Err(TryLockError::WouldBlock) => {}
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(FmanError::Locked(path.display().to_string()));
+            }
+            thread::sleep(interval.min(deadline - now));
+            interval = (interval * 2).min(MAX_RETRY_INTERVAL);
+        }
+    }
+
+    fn new(file: File, path: &Path, mode: LockMode) -> Self {
+        FileLock {
+            file,
+            path: path.to_path_buf(),
+            mode,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn mode(&self) -> LockMode {
+        self.mode
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        // Closing the file would release it as well.
+        let _ = self.file.unlock();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::{Arc, Barrier};
+
+    fn lockfile() -> (tempfile::TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("lock");
+        (dir, path)
+    }
+
+    fn try_now(path: &Path, mode: LockMode) -> FmanResult<FileLock> {
+        FileLock::acquire(path, mode, Some(Duration::ZERO))
+    }
+
+    #[test]
+    fn creates_the_lock_file_and_leaves_it_in_place() {
+        let (_dir, path) = lockfile();
+        let lock = FileLock::acquire(&path, LockMode::Exclusive, None).unwrap();
+        assert_eq!(lock.path(), path);
+        assert_eq!(lock.mode(), LockMode::Exclusive);
+        assert!(path.is_file());
+        drop(lock);
+        assert!(path.is_file());
+    }
+
+    #[test]
+    fn keeps_what_the_lock_file_holds() {
+        let (_dir, path) = lockfile();
+        std::fs::write(&path, "pid 42").unwrap();
+        drop(FileLock::acquire(&path, LockMode::Exclusive, None).unwrap());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "pid 42");
+    }
+
+    #[test]
+    fn exclusive_holders_in_two_threads_never_overlap() {
+        let (_dir, path) = lockfile();
+        let inside = Arc::new(AtomicBool::new(false));
+        let entries = Arc::new(AtomicUsize::new(0));
+        let start = Arc::new(Barrier::new(2));
+        let workers: Vec<_> = (0..2)
+            .map(|_| {
+                let (path, inside, entries, start) =
+                    (path.clone(), inside.clone(), entries.clone(), start.clone());
+                thread::spawn(move || {
+                    start.wait();
+                    for _ in 0..20 {
+                        let _lock = FileLock::acquire(&path, LockMode::Exclusive, None).unwrap();
+                        assert!(!inside.swap(true, Ordering::SeqCst), "two holders at once");
+                        entries.fetch_add(1, Ordering::SeqCst);
+                        thread::sleep(Duration::from_millis(1));
+                        inside.store(false, Ordering::SeqCst);
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        assert_eq!(entries.load(Ordering::SeqCst), 40);
+    }
+
+    #[test]
+    fn a_waiting_thread_gets_the_lock_once_it_is_released() {
+        let (_dir, path) = lockfile();
+        let held = FileLock::acquire(&path, LockMode::Exclusive, None).unwrap();
+        let released = Arc::new(AtomicBool::new(false));
+        let waiter = {
+            let (path, released) = (path.clone(), released.clone());
+            thread::spawn(move || {
+                let _lock = FileLock::acquire(&path, LockMode::Exclusive, None).unwrap();
+                released.load(Ordering::SeqCst)
+            })
+        };
+        thread::sleep(Duration::from_millis(50));
+        released.store(true, Ordering::SeqCst);
+        drop(held);
+        assert!(
+            waiter.join().unwrap(),
+            "got the lock while it was still held"
+        );
+    }
+
+    #[test]
+    fn a_zero_timeout_gives_up_at_once() {
+        let (_dir, path) = lockfile();
+        let _held = FileLock::acquire(&path, LockMode::Exclusive, None).unwrap();
+        let started = Instant::now();
+        let err = try_now(&path, LockMode::Exclusive).unwrap_err();
+        assert!(matches!(err, FmanError::Locked(ref p) if p == &path.display().to_string()));
+        assert_eq!(err.exit_code(), 75);
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn a_timeout_waits_about_that_long_before_giving_up() {
+        let (_dir, path) = lockfile();
+        let _held = FileLock::acquire(&path, LockMode::Exclusive, None).unwrap();
+        let started = Instant::now();
+        let err = FileLock::acquire(&path, LockMode::Exclusive, Some(Duration::from_millis(300)))
+            .unwrap_err();
+        let waited = started.elapsed();
+        assert!(matches!(err, FmanError::Locked(_)));
+        assert!(waited >= Duration::from_millis(300), "{waited:?}");
+        assert!(waited < Duration::from_secs(3), "{waited:?}");
+    }
+
+    #[test]
+    fn a_timeout_succeeds_if_the_lock_is_freed_in_time() {
+        let (_dir, path) = lockfile();
+        let held = FileLock::acquire(&path, LockMode::Exclusive, None).unwrap();
+        let releaser = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            drop(held);
+        });
+        FileLock::acquire(&path, LockMode::Exclusive, Some(Duration::from_secs(10))).unwrap();
+        releaser.join().unwrap();
+    }
+
+    #[test]
+    fn shared_holders_coexist_but_exclude_an_exclusive_one() {
+        let (_dir, path) = lockfile();
+        let first = try_now(&path, LockMode::Shared).unwrap();
+        let second = try_now(&path, LockMode::Shared).unwrap();
+        assert_eq!(second.mode(), LockMode::Shared);
+        assert!(matches!(
+            try_now(&path, LockMode::Exclusive),
+            Err(FmanError::Locked(_))
+        ));
+        drop(first);
+        drop(second);
+        try_now(&path, LockMode::Exclusive).unwrap();
+    }
+
+    #[test]
+    fn an_exclusive_holder_excludes_shared_ones() {
+        let (_dir, path) = lockfile();
+        let _held = try_now(&path, LockMode::Exclusive).unwrap();
+        assert!(matches!(
+            try_now(&path, LockMode::Shared),
+            Err(FmanError::Locked(_))
+        ));
+    }
+
+    #[test]
+    fn a_panicking_holder_releases_the_lock() {
+        let (_dir, path) = lockfile();
+        let holder = {
+            let path = path.clone();
+            thread::spawn(move || {
+                let _lock = FileLock::acquire(&path, LockMode::Exclusive, None).unwrap();
+                panic!("while holding the lock");
+            })
+        };
+        assert!(holder.join().is_err());
+        try_now(&path, LockMode::Exclusive).unwrap();
+    }
+
+    #[test]
+    fn a_missing_directory_is_an_error_not_a_wait() {
+        let (dir, _) = lockfile();
+        let err =
+            FileLock::acquire(&dir.path().join("no/lock"), LockMode::Exclusive, None).unwrap_err();
+        assert_eq!(err.exit_code(), 2);
+    }
+}
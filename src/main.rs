@@ -1,5 +1,6 @@
 mod cli;
+mod help_json;
 
 fn main() {
-    println!("Hello, world!");
+    cli::run();
 }
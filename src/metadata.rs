@@ -0,0 +1,397 @@
+//! How a copy reacts when setting attributes fails while the data copies
+//! fine, as on SMB mounts and FUSE object stores that reject every chmod
+//! or utimens with `ENOTSUP`.
+//!
+//! Such failures are downgraded to warnings, counted per attribute class
+//! and destination filesystem. A class whose first attempts on a
+//! filesystem all fail is not tried there again for the rest of the run.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+#[cfg(feature = "json")]
+use serde::Serialize;
+
+use crate::error::{FmanError, FmanResult};
+use crate::preserve::Attribute;
+
+/// Warnings kept per attribute class and filesystem.
+pub const DEFAULT_MAX_WARNINGS: u64 = 5;
+/// Attempts after which a class that never succeeded is given up on.
+pub const DEFAULT_PROBE_ATTEMPTS: u64 = 10;
+
+/// Shared record of attribute failures. Clones share it, so every file of
+/// a run counts against the same state.
+#[derive(Debug, Clone)]
+pub struct MetadataPolicy {
+    strict: bool,
+    max_warnings: u64,
+    probe_attempts: u64,
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    classes: BTreeMap<(u64, Attribute), ClassState>,
+    summary: MetadataSummary,
+}
+
+/// Progress of one attribute class on one filesystem.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ClassState {
+    pub attempts: u64,
+    pub failures: u64,
+    pub disabled: bool,
+}
+
+/// What [`ClassState::record`] made of an attempt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transition {
+    Succeeded,
+    /// A failure, still within the first `max_warnings`.
+    Warned,
+    /// A failure past the warning limit, only counted.
+    Counted,
+    /// A failure that completed a run of `probe_attempts` failures out of
+    /// as many attempts; the class is disabled from now on.
+    Disabled,
+}
+
+impl ClassState {
+    /// Record one attempt, successful or not.
+    pub fn record(&mut self, failed: bool, max_warnings: u64, probe_attempts: u64) -> Transition {
+        self.attempts += 1;
+        if !failed {
+            return Transition::Succeeded;
+        }
+        self.failures += 1;
+        if self.attempts >= probe_attempts && self.failures == self.attempts {
+            self.disabled = true;
+            return Transition::Disabled;
+        }
+        if self.failures <= max_warnings {
+            Transition::Warned
+        } else {
+            Transition::Counted
+        }
+    }
+}
+
+/// Attribute failures of a run, for reports.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "json", derive(Serialize))]
+pub struct MetadataSummary {
+    pub warnings: Vec<MetadataWarning>,
+    /// Failures beyond the warnings kept.
+    pub omitted: u64,
+    /// Classes given up on, with the path whose failure decided it.
+    pub disabled: Vec<MetadataWarning>,
+    /// Attempts not made because their class was disabled.
+    pub skipped: u64,
+}
+
+impl MetadataSummary {
+    pub fn is_empty(&self) -> bool {
+        self.warnings.is_empty() && self.omitted == 0 && self.disabled.is_empty()
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "json", derive(Serialize))]
+pub struct MetadataWarning {
+    pub path: PathBuf,
+    pub attribute: Attribute,
+    pub message: String,
+}
+
+impl Default for MetadataPolicy {
+    fn default() -> Self {
+        MetadataPolicy {
+            strict: false,
+            max_warnings: DEFAULT_MAX_WARNINGS,
+            probe_attempts: DEFAULT_PROBE_ATTEMPTS,
+            state: Arc::default(),
+        }
+    }
+}
+
+impl MetadataPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A policy under which every failure is an error.
+    pub fn strict() -> Self {
+        MetadataPolicy {
+            strict: true,
+            ..Self::default()
+        }
+    }
+
+    pub fn max_warnings(mut self, max: u64) -> Self {
+        self.max_warnings = max;
+        self
+    }
+
+    pub fn probe_attempts(mut self, attempts: u64) -> Self {
+        self.probe_attempts = attempts.max(1);
+        self
+    }
+
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    /// Set `attribute` on `path`, which is on filesystem `device`, with
+    /// `apply`. Returns whether it was set; `false` when the class is
+    /// disabled there or the failure was downgraded to a warning.
+    ///
+    /// Only "not supported" and "not permitted" failures are downgraded;
+    /// any other failure, and every failure of a strict policy, is
+    /// returned as an error.
+    pub fn attempt(
+        &self,
+        device: u64,
+        attribute: Attribute,
+        path: &Path,
+        apply: impl FnOnce() -> io::Result<()>,
+    ) -> FmanResult<bool> {
+        if self.strict {
+            apply().map_err(|e| FmanError::io_at(path, e))?;
+            return Ok(true);
+        }
+        if self.with_state(|state| {
+            let disabled = state
+                .classes
+                .get(&(device, attribute))
+                .is_some_and(|class| class.disabled);
+            state.summary.skipped += u64::from(disabled);
+            disabled
+        }) {
+            return Ok(false);
+        }
+        let error = match apply() {
+            Ok(()) => None,
+            Err(e) if is_unsupported(&e) => Some(e),
+            Err(e) => return Err(FmanError::io_at(path, e)),
+        };
+        self.with_state(|state| {
+            let class = state.classes.entry((device, attribute)).or_default();
+            let transition = class.record(error.is_some(), self.max_warnings, self.probe_attempts);
+            let warning = || MetadataWarning {
+                path: path.to_path_buf(),
+                attribute,
+                message: error.as_ref().map(ToString::to_string).unwrap_or_default(),
+            };
+            match transition {
+                Transition::Succeeded => {}
+                Transition::Warned => state.summary.warnings.push(warning()),
+                Transition::Counted => state.summary.omitted += 1,
+                Transition::Disabled => state.summary.disabled.push(warning()),
+            }
+        });
+        Ok(error.is_none())
+    }
+
+    pub fn summary(&self) -> MetadataSummary {
+        self.with_state(|state| state.summary.clone())
+    }
+
+    fn with_state<T>(&self, f: impl FnOnce(&mut State) -> T) -> T {
+        f(&mut self.state.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+/// `ENOTSUP`/`EOPNOTSUPP` and `EPERM`/`EACCES`.
+fn is_unsupported(e: &io::Error) -> bool {
+    if matches!(
+        e.kind(),
+        io::ErrorKind::Unsupported | io::ErrorKind::PermissionDenied
+    ) {
+        return true;
+    }
+    #[cfg(unix)]
+    if let Some(code) = e.raw_os_error() {
+        return code == libc::ENOTSUP || code == libc::EOPNOTSUPP;
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unsupported() -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    #[test]
+    fn a_class_that_succeeds_is_never_disabled() {
+        let mut class = ClassState::default();
+        for _ in 0..20 {
+            assert_eq!(class.record(false, 5, 10), Transition::Succeeded);
+        }
+        assert_eq!(
+            class,
+            ClassState {
+                attempts: 20,
+                failures: 0,
+                disabled: false
+            }
+        );
+    }
+
+    #[test]
+    fn failures_warn_then_count_then_disable() {
+        let mut class = ClassState::default();
+        let transitions: Vec<_> = (0..4).map(|_| class.record(true, 2, 4)).collect();
+        assert_eq!(
+            transitions,
+            [
+                Transition::Warned,
+                Transition::Warned,
+                Transition::Counted,
+                Transition::Disabled
+            ]
+        );
+        assert!(class.disabled);
+    }
+
+    #[test]
+    fn one_success_among_the_probes_keeps_the_class_enabled() {
+        let mut class = ClassState::default();
+        assert_eq!(class.record(false, 5, 3), Transition::Succeeded);
+        for _ in 0..10 {
+            assert_ne!(class.record(true, 5, 3), Transition::Disabled);
+        }
+        assert!(!class.disabled);
+        assert_eq!((class.attempts, class.failures), (11, 10));
+    }
+
+    #[test]
+    fn the_policy_stops_trying_a_disabled_class() {
+        let policy = MetadataPolicy::new().max_warnings(1).probe_attempts(3);
+        let mut calls = 0;
+        for i in 0..10 {
+            let path = PathBuf::from(format!("f{i}"));
+            let set = policy
+                .attempt(1, Attribute::Mode, &path, || {
+                    calls += 1;
+                    unsupported()
+                })
+                .unwrap();
+            assert!(!set);
+        }
+        assert_eq!(calls, 3);
+        let summary = policy.summary();
+        assert_eq!(summary.warnings.len(), 1);
+        assert_eq!(summary.warnings[0].path, Path::new("f0"));
+        assert_eq!(summary.omitted, 1);
+        assert_eq!(summary.disabled.len(), 1);
+        assert_eq!(summary.disabled[0].path, Path::new("f2"));
+        assert_eq!(summary.skipped, 7);
+        assert!(!summary.is_empty());
+    }
+
+    #[test]
+    fn classes_and_filesystems_are_tracked_apart() {
+        let policy = MetadataPolicy::new().probe_attempts(1);
+        let path = Path::new("f");
+        assert!(
+            !policy
+                .attempt(1, Attribute::Mode, path, unsupported)
+                .unwrap()
+        );
+        assert!(
+            policy
+                .attempt(1, Attribute::Times, path, || Ok(()))
+                .unwrap()
+        );
+        assert!(policy.attempt(2, Attribute::Mode, path, || Ok(())).unwrap());
+        assert!(!policy.attempt(1, Attribute::Mode, path, || Ok(())).unwrap());
+        assert_eq!(policy.summary().skipped, 1);
+    }
+
+    #[test]
+    fn clones_share_their_state() {
+        let policy = MetadataPolicy::new().probe_attempts(1);
+        let clone = policy.clone();
+        clone
+            .attempt(1, Attribute::Xattr, Path::new("f"), unsupported)
+            .unwrap();
+        assert_eq!(policy.summary().disabled.len(), 1);
+    }
+
+    #[test]
+    fn permission_failures_are_downgraded_too() {
+        let policy = MetadataPolicy::new();
+        let set = policy
+            .attempt(1, Attribute::Ownership, Path::new("f"), || {
+                Err(io::ErrorKind::PermissionDenied.into())
+            })
+            .unwrap();
+        assert!(!set);
+        assert_eq!(policy.summary().warnings.len(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn raw_enotsup_is_downgraded() {
+        let policy = MetadataPolicy::new();
+        let set = policy
+            .attempt(1, Attribute::Mode, Path::new("f"), || {
+                Err(io::Error::from_raw_os_error(libc::EOPNOTSUPP))
+            })
+            .unwrap();
+        assert!(!set);
+    }
+
+    #[test]
+    fn other_failures_stay_errors() {
+        let policy = MetadataPolicy::new();
+        let result = policy.attempt(1, Attribute::Mode, Path::new("f"), || {
+            Err(io::ErrorKind::NotFound.into())
+        });
+        assert_eq!(result.unwrap_err().exit_code(), 2);
+        assert!(policy.summary().is_empty());
+    }
+
+    #[test]
+    fn a_strict_policy_keeps_every_failure_an_error() {
+        let policy = MetadataPolicy::strict();
+        assert!(policy.is_strict());
+        for _ in 0..20 {
+            assert!(
+                policy
+                    .attempt(1, Attribute::Mode, Path::new("f"), unsupported)
+                    .is_err()
+            );
+        }
+        assert!(policy.summary().is_empty());
+    }
+
+    #[test]
+    fn a_strict_permission_denial_names_the_path() {
+        let policy = MetadataPolicy::strict();
+        let err = policy
+            .attempt(1, Attribute::Ownership, Path::new("f"), || {
+                Err(io::ErrorKind::PermissionDenied.into())
+            })
+            .unwrap_err();
+        assert!(
+            matches!(&err, FmanError::PermissionDenied(path) if path == "f"),
+            "{err:?}"
+        );
+    }
+
+    #[test]
+    fn probe_attempts_are_at_least_one() {
+        let policy = MetadataPolicy::new().probe_attempts(0);
+        policy
+            .attempt(1, Attribute::Mode, Path::new("f"), unsupported)
+            .unwrap();
+        assert_eq!(policy.summary().disabled.len(), 1);
+    }
+}
@@ -0,0 +1,248 @@
+//! Copying attributes, but not contents, from a reference tree onto a
+//! target tree.
+
+use std::fs;
+use std::path::Path;
+
+#[cfg(feature = "json")]
+use serde::Serialize;
+
+use crate::error::{Failures, FmanResult};
+use crate::fs::Fs;
+use crate::preserve::{self, Attribute, AttributeChange};
+use crate::validate::{ensure_exists, ensure_is_dir};
+use crate::walk::{SymlinkPolicy, Walk};
+
+/// The order [`preserve::apply`] sets attributes in.
+const APPLY_ORDER: [Attribute; 4] = [
+    Attribute::Ownership,
+    Attribute::Mode,
+    Attribute::Xattr,
+    Attribute::Times,
+];
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "json", derive(Serialize))]
+pub struct MirrorChange {
+    /// Path relative to both roots; `.` for the roots themselves.
+    pub path: String,
+    #[cfg_attr(feature = "json", serde(flatten))]
+    pub change: AttributeChange,
+}
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "json", derive(Serialize))]
+pub struct MirrorReport {
+    pub changes: Vec<MirrorChange>,
+    /// Reference paths with nothing at the same place in the target.
+    pub missing: Vec<String>,
+    /// Paths that are a different kind of file in the target.
+    pub type_mismatches: Vec<String>,
+}
+
+/// Apply `attributes` of every path below `reference` (and of the root) to
+/// the same relative path below `target`, through `filesystem`.
+///
+/// Symlinks are compared as links. Paths missing from the target or of a
+/// different file type are reported and skipped; file contents are never
+/// touched. A path whose attributes cannot all be read or set goes into
+/// `failures`, after the rest of its attributes have been applied, and the
+/// walk carries on.
+pub fn mirror_attributes(
+    reference: &Path,
+    target: &Path,
+    attributes: &[Attribute],
+    filesystem: &dyn Fs,
+    mut failures: Failures,
+) -> FmanResult<MirrorReport> {
+    ensure_exists(reference)?;
+    ensure_is_dir(reference)?;
+    ensure_exists(target)?;
+    ensure_is_dir(target)?;
+
+    let mut report = MirrorReport::default();
+    let mut mirror_one = |rel: String, from: &Path, to: &Path, failures: &mut Failures| {
+        let (Ok(want), Ok(have)) = (fs::symlink_metadata(from), fs::symlink_metadata(to)) else {
+            report.missing.push(rel);
+            return Ok(());
+        };
+        if want.file_type() != have.file_type() {
+            report.type_mismatches.push(rel);
+            return Ok(());
+        }
+        // One attribute at a time, in the order `preserve::apply` uses, so
+        // an owner that cannot be set still leaves the mode mirrored.
+        for attribute in APPLY_ORDER.iter().filter(|a| attributes.contains(a)) {
+            match preserve::apply(from, to, &[*attribute], filesystem) {
+                Ok(changes) => {
+                    report
+                        .changes
+                        .extend(changes.into_iter().map(|change| MirrorChange {
+                            path: rel.clone(),
+                            change,
+                        }))
+                }
+                Err(e) => failures.push(to, e)?,
+            }
+        }
+        FmanResult::Ok(())
+    };
+
+    mirror_one(".".to_string(), reference, target, &mut failures)?;
+    for entry in Walk::new(reference).symlinks(SymlinkPolicy::Never) {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                failures.push(reference, e)?;
+                continue;
+            }
+        };
+        let rel_path = entry.path().strip_prefix(reference).unwrap_or(entry.path());
+        let rel = rel_path
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        mirror_one(rel, entry.path(), &target.join(rel_path), &mut failures)?;
+    }
+    failures.into_result(report)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::PathBuf;
+    use std::time::{Duration, SystemTime};
+
+    use tempfile::TempDir;
+
+    use crate::error::DEFAULT_MAX_ERRORS;
+    use crate::fs::{DryRunFs, RealFs};
+
+    fn mirror(
+        reference: &Path,
+        target: &Path,
+        attributes: &[Attribute],
+        filesystem: &dyn Fs,
+    ) -> FmanResult<MirrorReport> {
+        let failures = Failures::new(DEFAULT_MAX_ERRORS, None)?;
+        mirror_attributes(reference, target, attributes, filesystem, failures)
+    }
+
+    fn at(secs: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000 + secs)
+    }
+
+    fn set(path: &Path, mode: u32, modified: SystemTime) {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode)).unwrap();
+        let file = fs::File::options()
+            .write(path.is_file())
+            .read(true)
+            .open(path);
+        if let Ok(file) = file {
+            file.set_modified(modified).unwrap();
+        }
+    }
+
+    fn mode(path: &Path) -> u32 {
+        fs::metadata(path).unwrap().permissions().mode() & 0o7777
+    }
+
+    fn modified(path: &Path) -> SystemTime {
+        fs::metadata(path).unwrap().modified().unwrap()
+    }
+
+    /// A reference tree with distinct modes and mtimes, and a target with
+    /// the same files holding other contents and scrambled attributes, one
+    /// file missing and one of another type.
+    fn trees() -> (TempDir, PathBuf, PathBuf) {
+        let dir = TempDir::new().unwrap();
+        let (reference, target) = (dir.path().join("ref"), dir.path().join("target"));
+        for root in [&reference, &target] {
+            fs::create_dir_all(root.join("sub")).unwrap();
+            fs::write(root.join("a"), format!("a in {}", root.display())).unwrap();
+            fs::write(root.join("sub/b"), "b").unwrap();
+        }
+        fs::write(reference.join("only-ref"), "").unwrap();
+        fs::write(reference.join("kind"), "").unwrap();
+        fs::create_dir(target.join("kind")).unwrap();
+        set(&reference.join("a"), 0o640, at(10));
+        set(&reference.join("sub/b"), 0o600, at(20));
+        set(&reference.join("sub"), 0o750, at(30));
+        set(&target.join("a"), 0o666, at(100));
+        set(&target.join("sub/b"), 0o644, at(200));
+        set(&target.join("sub"), 0o777, at(300));
+        (dir, reference, target)
+    }
+
+    #[test]
+    fn attributes_are_mirrored_without_touching_contents() {
+        let (_dir, reference, target) = trees();
+        let what = [Attribute::Mode, Attribute::Times];
+        let report = mirror(&reference, &target, &what, &RealFs).unwrap();
+        for rel in ["a", "sub/b", "sub"] {
+            assert_eq!(mode(&target.join(rel)), mode(&reference.join(rel)), "{rel}");
+            assert_eq!(
+                modified(&target.join(rel)),
+                modified(&reference.join(rel)),
+                "{rel}"
+            );
+        }
+        let contents = fs::read_to_string(target.join("a")).unwrap();
+        assert_eq!(contents, format!("a in {}", target.display()));
+        assert_eq!(report.missing, ["only-ref"]);
+        assert_eq!(report.type_mismatches, ["kind"]);
+        let a_mode = report
+            .changes
+            .iter()
+            .find(|c| c.path == "a" && c.change.attribute == Attribute::Mode)
+            .unwrap();
+        assert_eq!(
+            (&*a_mode.change.before, &*a_mode.change.after),
+            ("0666", "0640")
+        );
+    }
+
+    #[test]
+    fn only_the_selected_attributes_change() {
+        let (_dir, reference, target) = trees();
+        let report = mirror(&reference, &target, &[Attribute::Mode], &RealFs).unwrap();
+        assert_eq!(mode(&target.join("sub/b")), 0o600);
+        assert_eq!(modified(&target.join("sub/b")), at(200));
+        assert!(
+            report
+                .changes
+                .iter()
+                .all(|c| c.change.attribute == Attribute::Mode)
+        );
+    }
+
+    #[test]
+    fn a_dry_run_lists_changes_and_makes_none() {
+        let (_dir, reference, target) = trees();
+        let what = Attribute::parse_list("mode,times").unwrap();
+        let report = mirror(&reference, &target, &what, &DryRunFs::new()).unwrap();
+        assert!(!report.changes.is_empty());
+        assert_eq!(mode(&target.join("a")), 0o666);
+        assert_eq!(modified(&target.join("a")), at(100));
+        assert!(
+            report
+                .changes
+                .iter()
+                .any(|c| c.path == "sub/b" && c.change.attribute == Attribute::Times)
+        );
+    }
+
+    #[test]
+    fn both_roots_must_be_directories() {
+        let (_dir, reference, target) = trees();
+        let err = mirror(&reference.join("a"), &target, &[], &RealFs).unwrap_err();
+        assert_eq!(err.exit_code(), 4);
+        let err = mirror(&reference, &target.join("nope"), &[], &RealFs).unwrap_err();
+        assert_eq!(err.exit_code(), 2);
+        assert!(Attribute::parse_list("mode,colour").is_err());
+        assert_eq!(Attribute::parse_list("all").unwrap(), Attribute::ALL);
+    }
+}
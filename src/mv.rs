@@ -0,0 +1,623 @@
+//! Moving and renaming files and directories.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::copy::{
+    CopyOptions, as_given, copy_file, ends_with_separator, normalize_destination,
+    resolve_destination_in,
+};
+use crate::error::{DEFAULT_MAX_ERRORS, Failures, FmanError, FmanResult};
+use crate::fs::{Fs, SharedFs};
+use crate::hash::{HashAlgorithm, hash_file};
+use crate::platform;
+use crate::validate::{
+    ensure_exists, ensure_not_exists, ensure_not_same_file, ensure_parents_are_dirs,
+};
+use crate::walk::{SymlinkPolicy, Walk};
+
+#[derive(Debug, Clone)]
+pub struct MoveOptions {
+    force: bool,
+    copy_verify: bool,
+    max_errors: usize,
+    error_log: Option<PathBuf>,
+}
+
+impl Default for MoveOptions {
+    fn default() -> Self {
+        Self {
+            force: false,
+            copy_verify: false,
+            max_errors: DEFAULT_MAX_ERRORS,
+            error_log: None,
+        }
+    }
+}
+
+impl MoveOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Overwrite an existing destination.
+    pub fn force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    /// When a move falls back to copying, hash each copied file against
+    /// its source and only remove the source if they match.
+    pub fn copy_verify(mut self, verify: bool) -> Self {
+        self.copy_verify = verify;
+        self
+    }
+
+    /// Keep at most `max` failures of a tree moved by copying for the
+    /// final error; later ones are only counted.
+    pub fn max_errors(mut self, max: usize) -> Self {
+        self.max_errors = max;
+        self
+    }
+
+    /// Append every failure of a tree moved by copying to the file at
+    /// `path`, however many there are.
+    pub fn error_log(mut self, path: impl Into<PathBuf>) -> Self {
+        self.error_log = Some(path.into());
+        self
+    }
+}
+
+/// Move `src` to `dst`, returning the resolved destination.
+///
+/// `dst` is resolved like a copy destination; one written with a trailing
+/// separator must be an existing directory unless `src` is a directory
+/// itself, which is then renamed to it. A rename is attempted first;
+/// when it fails because the paths are on different filesystems, a regular
+/// file or directory tree is copied and each source file removed only once
+/// its copy checks out. Every change is made through `filesystem`.
+pub fn move_path(
+    src: &Path,
+    dst: &Path,
+    options: &MoveOptions,
+    filesystem: &SharedFs,
+) -> FmanResult<PathBuf> {
+    ensure_exists(src)?;
+    normalize_destination(dst)?;
+    if ends_with_separator(dst) && !filesystem.is_dir(dst) && !src.is_dir() {
+        let dir = dst.components().collect::<PathBuf>();
+        if fs::symlink_metadata(&dir).is_ok() {
+            return Err(FmanError::NotADirectory(dir.display().to_string()));
+        }
+        return Err(FmanError::DestinationDirMissing(dir.display().to_string()));
+    }
+    let given = dst;
+    let dst = resolve_destination_in(src, dst, filesystem.as_ref())?;
+    ensure_parents_are_dirs(&dst)
+        .and_then(|()| ensure_not_same_file(src, &dst))
+        .and_then(|()| {
+            if options.force {
+                Ok(())
+            } else {
+                ensure_not_exists(&dst)
+            }
+        })
+        .map_err(|e| as_given(e, &dst, given))?;
+
+    match filesystem.rename(src, &dst) {
+        Ok(()) => Ok(dst),
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            let copy_options = CopyOptions::new()
+                .force(true)
+                .symlinks(SymlinkPolicy::Never)
+                .fs(filesystem.clone());
+            let copy = |src: &Path, dst: &Path| Ok(copy_file(src, dst, &copy_options)?.bytes);
+            if fs::symlink_metadata(src)?.is_dir() {
+                move_tree_via_copy(src, &dst, options, filesystem.as_ref(), copy)?;
+            } else if src.is_file() {
+                move_via_copy(src, &dst, options, filesystem.as_ref(), copy)?;
+            } else {
+                return Err(e.into());
+            }
+            Ok(dst)
+        }
+        Err(e) => Err(FmanError::io_at(denied_side(src, &dst), e)),
+    }
+}
+
+/// Which end of a move a permission denial is put down to: `dst` when its
+/// directory cannot be modified, `src` otherwise.
+fn denied_side<'a>(src: &'a Path, dst: &'a Path) -> &'a Path {
+    let dst_dir = dst.parent().filter(|dir| !dir.as_os_str().is_empty());
+    if platform::can_modify_dir(dst_dir.unwrap_or(Path::new("."))) {
+        src
+    } else {
+        dst
+    }
+}
+
+/// Copy-then-delete fallback for cross-device moves of a directory tree.
+///
+/// Each file goes through [`move_via_copy`] on its own, so its source is
+/// removed as soon as its copy checks out and an interruption never leaves
+/// a file in neither place. Symlinks are recreated, not followed. A file
+/// that fails stays in the source tree along with the directories above
+/// it, and the failures are returned together once the rest has moved.
+fn move_tree_via_copy(
+    src: &Path,
+    dst: &Path,
+    options: &MoveOptions,
+    filesystem: &dyn Fs,
+    copy: impl Fn(&Path, &Path) -> FmanResult<u64>,
+) -> FmanResult<()> {
+    filesystem
+        .create_dir_all(dst)
+        .map_err(|e| FmanError::io_at(dst, e))?;
+    let mut failures = Failures::new(options.max_errors, options.error_log.as_deref())?;
+    let mut dirs = vec![src.to_path_buf()];
+    for entry in Walk::new(src).symlinks(SymlinkPolicy::Never) {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                failures.push(src, e)?;
+                continue;
+            }
+        };
+        let relative = entry
+            .path()
+            .strip_prefix(src)
+            .expect("walk yields paths below its root");
+        let target = dst.join(relative);
+        let file_type = entry.file_type();
+        let moved = if file_type.is_dir() {
+            dirs.push(entry.path().to_path_buf());
+            filesystem
+                .create_dir_all(&target)
+                .map_err(|e| FmanError::io_at(&target, e))
+        } else if file_type.is_file() {
+            move_via_copy(entry.path(), &target, options, filesystem, &copy)
+        } else if file_type.is_symlink() {
+            copy(entry.path(), &target).and_then(|_| {
+                filesystem
+                    .remove_file(entry.path())
+                    .map_err(|e| FmanError::io_at(entry.path(), e))
+            })
+        } else {
+            Err(FmanError::InvalidInput(format!(
+                "cannot move special file {} across filesystems",
+                entry.path().display()
+            )))
+        };
+        if let Err(e) = moved {
+            failures.push(entry.path(), e)?;
+        }
+    }
+    // Parents were walked before their children, so in reverse every
+    // directory comes after everything inside it.
+    for dir in dirs.iter().rev() {
+        let still_holds_files = fs::read_dir(dir).is_ok_and(|mut entries| entries.next().is_some());
+        if still_holds_files && !failures.is_empty() {
+            continue;
+        }
+        if let Err(e) = filesystem.remove_dir(dir) {
+            failures.push(dir, FmanError::io_at(dir, e))?;
+        }
+    }
+    failures.into_result(())
+}
+
+/// Copy-then-delete fallback for cross-device moves.
+///
+/// `copy` performs the data transfer. The source is only removed once the
+/// destination exists as a regular file with the source's size, and with
+/// [`MoveOptions::copy_verify`] the same hash; otherwise the source is left
+/// in place and the mismatch is reported.
+fn move_via_copy(
+    src: &Path,
+    dst: &Path,
+    options: &MoveOptions,
+    filesystem: &dyn Fs,
+    copy: impl FnOnce(&Path, &Path) -> FmanResult<u64>,
+) -> FmanResult<()> {
+    let expected = fs::metadata(src)
+        .map_err(|e| FmanError::io_at(src, e))?
+        .len();
+    copy(src, dst)?;
+
+    match fs::symlink_metadata(dst) {
+        Ok(meta) if meta.is_file() && meta.len() == expected => {}
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "copy of {} to {} is incomplete; source was not removed",
+                    src.display(),
+                    dst.display()
+                ),
+            )
+            .into());
+        }
+    }
+    if options.copy_verify {
+        verify_copy(src, dst, filesystem)?;
+    }
+    filesystem
+        .remove_file(src)
+        .map_err(|e| FmanError::io_at(src, e))?;
+    Ok(())
+}
+
+/// Compare the hashes of `src` and its copy `dst`, removing the copy
+/// through `filesystem` if they differ.
+fn verify_copy(src: &Path, dst: &Path, filesystem: &dyn Fs) -> FmanResult<()> {
+    let want = hash_file(src, HashAlgorithm::Sha256)?;
+    let have = hash_file(dst, HashAlgorithm::Sha256)?;
+    if want == have {
+        return Ok(());
+    }
+    filesystem
+        .remove_file(dst)
+        .map_err(|e| FmanError::io_at(dst, e))?;
+    Err(FmanError::VerificationFailed(format!(
+        "copy of {} to {} has sha256 {have}, expected {want}; removed the copy and kept the source",
+        src.display(),
+        dst.display()
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs::{File, Permissions};
+    use std::sync::Arc;
+    use std::time::SystemTime;
+
+    use tempfile::TempDir;
+
+    use crate::fs::RealFs;
+
+    /// The real filesystem, except that every rename fails as if the two
+    /// paths were on different devices, and with `read_only` so does every
+    /// removal and directory creation, as if permission were denied.
+    #[derive(Debug)]
+    struct CrossDeviceFs {
+        read_only: bool,
+    }
+
+    impl CrossDeviceFs {
+        fn check(&self) -> io::Result<()> {
+            if self.read_only {
+                return Err(io::ErrorKind::PermissionDenied.into());
+            }
+            Ok(())
+        }
+    }
+
+    impl Fs for CrossDeviceFs {
+        fn create_file(&self, path: &Path, create_new: bool) -> io::Result<Option<File>> {
+            RealFs.create_file(path, create_new)
+        }
+        fn rename(&self, _: &Path, _: &Path) -> io::Result<()> {
+            Err(io::ErrorKind::CrossesDevices.into())
+        }
+        fn remove_file(&self, path: &Path) -> io::Result<()> {
+            self.check()?;
+            RealFs.remove_file(path)
+        }
+        fn remove_dir(&self, path: &Path) -> io::Result<()> {
+            RealFs.remove_dir(path)
+        }
+        fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+            RealFs.remove_dir_all(path)
+        }
+        fn create_dir(&self, path: &Path) -> io::Result<()> {
+            RealFs.create_dir(path)
+        }
+        fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+            self.check()?;
+            RealFs.create_dir_all(path)
+        }
+        fn set_permissions(&self, path: &Path, permissions: Permissions) -> io::Result<()> {
+            RealFs.set_permissions(path, permissions)
+        }
+        fn set_times(
+            &self,
+            path: &Path,
+            accessed: Option<SystemTime>,
+            modified: Option<SystemTime>,
+        ) -> io::Result<()> {
+            RealFs.set_times(path, accessed, modified)
+        }
+        fn set_owner(&self, path: &Path, uid: Option<u32>, gid: Option<u32>) -> io::Result<()> {
+            RealFs.set_owner(path, uid, gid)
+        }
+        fn set_xattr(&self, path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+            RealFs.set_xattr(path, name, value)
+        }
+        fn remove_xattr(&self, path: &Path, name: &str) -> io::Result<()> {
+            RealFs.remove_xattr(path, name)
+        }
+        fn symlink(&self, target: &Path, link: &Path) -> io::Result<()> {
+            RealFs.symlink(target, link)
+        }
+        fn hard_link(&self, original: &Path, link: &Path) -> io::Result<()> {
+            RealFs.hard_link(original, link)
+        }
+        fn set_file_permissions(
+            &self,
+            path: &Path,
+            file: &File,
+            permissions: Permissions,
+        ) -> io::Result<()> {
+            RealFs.set_file_permissions(path, file, permissions)
+        }
+        fn set_file_times(
+            &self,
+            path: &Path,
+            file: &File,
+            accessed: Option<SystemTime>,
+            modified: Option<SystemTime>,
+        ) -> io::Result<()> {
+            RealFs.set_file_times(path, file, accessed, modified)
+        }
+        fn set_file_owner(
+            &self,
+            path: &Path,
+            file: &File,
+            uid: Option<u32>,
+            gid: Option<u32>,
+        ) -> io::Result<()> {
+            RealFs.set_file_owner(path, file, uid, gid)
+        }
+    }
+
+    fn cross_device() -> SharedFs {
+        Arc::new(CrossDeviceFs { read_only: false })
+    }
+
+    #[test]
+    fn fallback_moves_a_file() {
+        let dir = TempDir::new().unwrap();
+        let (src, dst) = (dir.path().join("a"), dir.path().join("b"));
+        fs::write(&src, "data").unwrap();
+        let moved = move_path(&src, &dst, &MoveOptions::new(), &cross_device()).unwrap();
+        assert_eq!(moved, dst);
+        assert!(!src.exists());
+        assert_eq!(fs::read_to_string(&dst).unwrap(), "data");
+    }
+
+    #[test]
+    fn fallback_moves_a_tree() {
+        let dir = TempDir::new().unwrap();
+        let src = dir.path().join("s");
+        fs::create_dir_all(src.join("sub")).unwrap();
+        fs::create_dir(src.join("empty")).unwrap();
+        fs::write(src.join("sub/f"), "data").unwrap();
+        let dst = dir.path().join("d");
+        move_path(&src, &dst, &MoveOptions::new(), &cross_device()).unwrap();
+        assert!(!src.exists());
+        assert_eq!(fs::read_to_string(dst.join("sub/f")).unwrap(), "data");
+        assert!(dst.join("empty").is_dir());
+    }
+
+    #[test]
+    fn fallback_does_not_overwrite_without_force() {
+        let dir = TempDir::new().unwrap();
+        let (src, dst) = (dir.path().join("a"), dir.path().join("b"));
+        fs::write(&src, "new").unwrap();
+        fs::write(&dst, "old").unwrap();
+        let err = move_path(&src, &dst, &MoveOptions::new(), &cross_device()).unwrap_err();
+        assert!(matches!(err, FmanError::AlreadyExists(_)), "{err}");
+        assert_eq!(fs::read_to_string(&src).unwrap(), "new");
+        assert_eq!(fs::read_to_string(&dst).unwrap(), "old");
+
+        move_path(&src, &dst, &MoveOptions::new().force(true), &cross_device()).unwrap();
+        assert!(!src.exists());
+        assert_eq!(fs::read_to_string(&dst).unwrap(), "new");
+    }
+
+    #[test]
+    fn failed_copy_keeps_the_source() {
+        let dir = TempDir::new().unwrap();
+        let (src, dst) = (dir.path().join("a"), dir.path().join("b"));
+        fs::write(&src, "data").unwrap();
+        let failing = |_: &Path, _: &Path| Err(io::Error::other("disk full").into());
+        move_via_copy(&src, &dst, &MoveOptions::new(), &RealFs, failing).unwrap_err();
+        assert_eq!(fs::read_to_string(&src).unwrap(), "data");
+    }
+
+    #[test]
+    fn incomplete_copy_keeps_the_source() {
+        let dir = TempDir::new().unwrap();
+        let (src, dst) = (dir.path().join("a"), dir.path().join("b"));
+        fs::write(&src, "data").unwrap();
+        let truncating = |_: &Path, dst: &Path| {
+            fs::write(dst, "da")?;
+            Ok(2)
+        };
+        let err = move_via_copy(&src, &dst, &MoveOptions::new(), &RealFs, truncating).unwrap_err();
+        assert!(err.to_string().contains("incomplete"), "{err}");
+        assert_eq!(fs::read_to_string(&src).unwrap(), "data");
+    }
+
+    #[test]
+    fn failed_file_stays_in_the_source_tree() {
+        let dir = TempDir::new().unwrap();
+        let src = dir.path().join("s");
+        fs::create_dir_all(src.join("sub")).unwrap();
+        fs::write(src.join("keep"), "1").unwrap();
+        fs::write(src.join("sub/fail"), "2").unwrap();
+        let dst = dir.path().join("d");
+        let copy = |from: &Path, to: &Path| {
+            if from.ends_with("fail") {
+                return Err(io::Error::other("disk full").into());
+            }
+            Ok(fs::copy(from, to)?)
+        };
+        move_tree_via_copy(&src, &dst, &MoveOptions::new(), &RealFs, copy).unwrap_err();
+        assert!(!src.join("keep").exists());
+        assert_eq!(fs::read_to_string(dst.join("keep")).unwrap(), "1");
+        assert_eq!(fs::read_to_string(src.join("sub/fail")).unwrap(), "2");
+    }
+
+    #[test]
+    fn tree_failures_honour_max_errors_and_the_error_log() {
+        let dir = TempDir::new().unwrap();
+        let src = dir.path().join("s");
+        for i in 0..3 {
+            fs::create_dir_all(src.join(format!("{i}"))).unwrap();
+            fs::write(src.join(format!("{i}/fail")), "x").unwrap();
+        }
+        let log = dir.path().join("errors.log");
+        let options = MoveOptions::new().max_errors(1).error_log(&log);
+        let failing = |_: &Path, _: &Path| Err(io::Error::other("disk full").into());
+        let err = move_tree_via_copy(&src, &dir.path().join("d"), &options, &RealFs, failing)
+            .unwrap_err();
+        let FmanError::Multiple(list) = &err else {
+            panic!("{err}")
+        };
+        assert_eq!(list.failures().len(), 1);
+        assert_eq!(list.total(), 3);
+        assert_eq!(fs::read_to_string(&log).unwrap().lines().count(), 3);
+    }
+
+    #[test]
+    fn verify_mismatch_removes_the_copy() {
+        let dir = TempDir::new().unwrap();
+        let (src, dst) = (dir.path().join("a"), dir.path().join("b"));
+        fs::write(&src, "data").unwrap();
+        let corrupting = |_: &Path, dst: &Path| {
+            fs::write(dst, "dada")?;
+            Ok(4)
+        };
+        let options = MoveOptions::new().copy_verify(true);
+        let err = move_via_copy(&src, &dst, &options, &RealFs, corrupting).unwrap_err();
+        assert!(matches!(err, FmanError::VerificationFailed(_)), "{err}");
+        assert!(!dst.exists());
+        assert_eq!(fs::read_to_string(&src).unwrap(), "data");
+    }
+
+    #[test]
+    fn a_copy_that_cannot_be_removed_is_permission_denied() {
+        let dir = TempDir::new().unwrap();
+        let (src, dst) = (dir.path().join("a"), dir.path().join("b"));
+        fs::write(&src, "data").unwrap();
+        fs::write(&dst, "dada").unwrap();
+        let err = verify_copy(&src, &dst, &CrossDeviceFs { read_only: true }).unwrap_err();
+        assert!(
+            matches!(&err, FmanError::PermissionDenied(path) if *path == dst.display().to_string()),
+            "{err:?}"
+        );
+        assert_eq!(err.exit_code(), 5);
+        assert!(dst.exists());
+    }
+
+    #[test]
+    fn a_tree_destination_that_cannot_be_created_is_permission_denied() {
+        let dir = TempDir::new().unwrap();
+        let (src, dst) = (dir.path().join("s"), dir.path().join("d"));
+        fs::create_dir(&src).unwrap();
+        fs::write(src.join("f"), "data").unwrap();
+        let filesystem: SharedFs = Arc::new(CrossDeviceFs { read_only: true });
+        let err = move_path(&src, &dst, &MoveOptions::new(), &filesystem).unwrap_err();
+        assert!(
+            matches!(&err, FmanError::PermissionDenied(path) if *path == dst.display().to_string()),
+            "{err:?}"
+        );
+        assert_eq!(err.exit_code(), 5);
+        assert!(src.join("f").exists());
+    }
+
+    #[track_caller]
+    fn assert_refused_as_same_file(src: &Path, dst: &Path) {
+        for options in [MoveOptions::new(), MoveOptions::new().force(true)] {
+            for filesystem in [Arc::new(RealFs) as SharedFs, cross_device()] {
+                let err = move_path(src, dst, &options, &filesystem).unwrap_err();
+                assert!(matches!(err, FmanError::SameFile(_)), "{err:?}");
+                assert_eq!(err.exit_code(), 4);
+                assert_eq!(fs::read_to_string(src).unwrap(), "data");
+            }
+        }
+    }
+
+    #[test]
+    fn moving_a_file_onto_itself_is_refused() {
+        let dir = TempDir::new().unwrap();
+        let src = dir.path().join("a.txt");
+        fs::write(&src, "data").unwrap();
+        assert_refused_as_same_file(&src, &src);
+        assert_refused_as_same_file(&src, &dir.path().join(".").join("a.txt"));
+        // A directory destination resolves to the source itself.
+        assert_refused_as_same_file(&src, dir.path());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn moving_a_file_onto_a_link_to_it_is_refused() {
+        let dir = TempDir::new().unwrap();
+        let src = dir.path().join("a.txt");
+        fs::write(&src, "data").unwrap();
+        let linked_dir = dir.path().join("here");
+        std::os::unix::fs::symlink(dir.path(), &linked_dir).unwrap();
+        assert_refused_as_same_file(&src, &linked_dir.join("a.txt"));
+        let hard = dir.path().join("hard.txt");
+        fs::hard_link(&src, &hard).unwrap();
+        assert_refused_as_same_file(&src, &hard);
+    }
+
+    #[test]
+    fn a_corrupted_file_stays_in_the_source_tree_while_the_rest_moves() {
+        let dir = TempDir::new().unwrap();
+        let src = dir.path().join("s");
+        fs::create_dir_all(src.join("sub")).unwrap();
+        fs::write(src.join("a"), "1").unwrap();
+        fs::write(src.join("sub/bad"), "good").unwrap();
+        fs::write(src.join("sub/c"), "3").unwrap();
+        let dst = dir.path().join("d");
+        let corrupting = |from: &Path, to: &Path| {
+            let mut data = fs::read(from)?;
+            if from.ends_with("bad") {
+                data.reverse();
+            }
+            fs::write(to, &data)?;
+            Ok(data.len() as u64)
+        };
+        let options = MoveOptions::new().copy_verify(true);
+        let err = move_tree_via_copy(&src, &dst, &options, &RealFs, corrupting).unwrap_err();
+        assert!(err.to_string().contains("sha256"), "{err}");
+        assert_eq!(err.exit_code(), 10);
+        assert_eq!(fs::read_to_string(src.join("sub/bad")).unwrap(), "good");
+        assert!(!dst.join("sub/bad").exists());
+        assert!(!src.join("a").exists() && !src.join("sub/c").exists());
+        assert_eq!(fs::read_to_string(dst.join("a")).unwrap(), "1");
+        assert_eq!(fs::read_to_string(dst.join("sub/c")).unwrap(), "3");
+    }
+
+    #[test]
+    fn without_verification_a_same_size_corruption_goes_unnoticed() {
+        let dir = TempDir::new().unwrap();
+        let (src, dst) = (dir.path().join("a"), dir.path().join("b"));
+        fs::write(&src, "data").unwrap();
+        let corrupting = |_: &Path, dst: &Path| {
+            fs::write(dst, "dada")?;
+            Ok(4)
+        };
+        move_via_copy(&src, &dst, &MoveOptions::new(), &RealFs, corrupting).unwrap();
+        assert!(!src.exists());
+    }
+
+    #[test]
+    fn a_verified_cross_device_move_removes_the_source() {
+        let dir = TempDir::new().unwrap();
+        let src = dir.path().join("s");
+        fs::create_dir_all(src.join("sub")).unwrap();
+        fs::write(src.join("sub/f"), "data").unwrap();
+        let dst = dir.path().join("d");
+        let options = MoveOptions::new().copy_verify(true);
+        move_path(&src, &dst, &options, &cross_device()).unwrap();
+        assert!(!src.exists());
+        assert_eq!(fs::read_to_string(dst.join("sub/f")).unwrap(), "data");
+    }
+}
@@ -0,0 +1,450 @@
+//! Generated names: temporary files beside their destination, and numbered
+//! alternatives when a name is taken.
+//!
+//! Names come from a [`NamingContext`], whose entropy source can be seeded
+//! so that runs are reproducible. Every name is probed against the place
+//! it is meant for, kept within [`NAME_MAX`] bytes by shortening the stem
+//! (never the suffix that makes it unique) and cleared of characters the
+//! filesystem would reject. A bounded number of names is tried before
+//! giving up with `AlreadyExists`.
+
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::{FmanError, FmanResult};
+
+/// Longest file name in bytes on the filesystems fman targets.
+pub const NAME_MAX: usize = 255;
+
+/// What [`NamingContext::backup_path`] appends unless told otherwise.
+pub const DEFAULT_BACKUP_SUFFIX: &str = ".bak";
+
+/// What the names of [`NamingContext::scratch_path`] start with.
+pub const SCRATCH_PREFIX: &str = ".fman-tmp-";
+
+/// Names tried before giving up.
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 1000;
+
+/// Length of the random part of a temporary name.
+const RANDOM_LEN: usize = 8;
+
+const ALPHABET: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+
+/// Where random suffixes come from.
+pub trait Entropy: Send {
+    fn next_u64(&mut self) -> u64;
+}
+
+/// SplitMix64: tiny and plenty for names, which need to differ rather than
+/// be unpredictable.
+#[derive(Debug, Clone)]
+pub struct SplitMix64(u64);
+
+impl SplitMix64 {
+    pub fn new(seed: u64) -> Self {
+        SplitMix64(seed)
+    }
+}
+
+impl Entropy for SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+pub struct NamingContext {
+    entropy: Mutex<Box<dyn Entropy>>,
+    max_attempts: u32,
+    name_max: usize,
+}
+
+impl Default for NamingContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for NamingContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NamingContext")
+            .field("max_attempts", &self.max_attempts)
+            .field("name_max", &self.name_max)
+            .finish_non_exhaustive()
+    }
+}
+
+impl NamingContext {
+    /// Seeded from the clock and the process id.
+    pub fn new() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        Self::seeded(nanos ^ (u64::from(std::process::id()) << 32))
+    }
+
+    /// The same seed gives the same sequence of names.
+    pub fn seeded(seed: u64) -> Self {
+        Self::with_entropy(SplitMix64::new(seed))
+    }
+
+    pub fn with_entropy(entropy: impl Entropy + 'static) -> Self {
+        NamingContext {
+            entropy: Mutex::new(Box::new(entropy)),
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            name_max: NAME_MAX,
+        }
+    }
+
+    /// Give up after trying `attempts` names (at least 1).
+    pub fn max_attempts(mut self, attempts: u32) -> Self {
+        self.max_attempts = attempts.max(1);
+        self
+    }
+
+    /// Keep names within `bytes` instead of [`NAME_MAX`].
+    pub fn name_max(mut self, bytes: usize) -> Self {
+        self.name_max = bytes;
+        self
+    }
+
+    /// `len` random lowercase letters and digits.
+    pub fn random_suffix(&self, len: usize) -> String {
+        let mut entropy = self.entropy.lock().unwrap_or_else(|e| e.into_inner());
+        (0..len)
+            .map(|_| char::from(ALPHABET[(entropy.next_u64() % ALPHABET.len() as u64) as usize]))
+            .collect()
+    }
+
+    /// A path in `dir` that does not exist yet, for a temporary file that
+    /// will become `name`: `.name.XXXXXXXX.tmp`.
+    ///
+    /// The name is only probed; create it with `create_new` to claim it.
+    pub fn temp_path(&self, dir: &Path, name: &str) -> FmanResult<PathBuf> {
+        let stem = format!(".{name}");
+        for _ in 0..self.max_attempts {
+            let suffix = format!(".{}.tmp", self.random_suffix(RANDOM_LEN));
+            let path = dir.join(fit_name(&stem, &suffix, self.name_max));
+            if fs::symlink_metadata(&path).is_err() {
+                return Ok(path);
+            }
+        }
+        Err(self.exhausted(&dir.join(name).display().to_string()))
+    }
+
+    /// A path in `dir` that does not exist yet, for a temporary file whose
+    /// final name is not known from it: `.fman-tmp-XXXXXXXX`.
+    pub fn scratch_path(&self, dir: &Path) -> FmanResult<PathBuf> {
+        for _ in 0..self.max_attempts {
+            let path = dir.join(format!(
+                "{SCRATCH_PREFIX}{}",
+                self.random_suffix(RANDOM_LEN)
+            ));
+            if fs::symlink_metadata(&path).is_err() {
+                return Ok(path);
+            }
+        }
+        Err(self.exhausted(&dir.join(SCRATCH_PREFIX).display().to_string()))
+    }
+
+    /// A path beside `path` that does not exist yet, to move `path` to
+    /// before it is replaced: `name` plus `suffix`, as in `name.bak`, or
+    /// `name.bak.1`, `name.bak.2` and so on when earlier backups are there.
+    pub fn backup_path(&self, path: &Path, suffix: &str) -> FmanResult<PathBuf> {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        for n in 0..self.max_attempts {
+            let suffix = match n {
+                0 => suffix.to_string(),
+                n => format!("{suffix}.{n}"),
+            };
+            let backup = path.with_file_name(fit_name(&name, &suffix, self.name_max));
+            if fs::symlink_metadata(&backup).is_err() {
+                return Ok(backup);
+            }
+        }
+        Err(self.exhausted(&format!("a backup of {}", path.display())))
+    }
+
+    /// A path beside `path` that does not exist yet, for a copy that must
+    /// not replace it: `name (1).ext`, or `name (2).ext` and so on when
+    /// that is taken too.
+    pub fn alternative_path(&self, path: &Path) -> FmanResult<PathBuf> {
+        let stem = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let extension = path
+            .extension()
+            .map(|extension| format!(".{}", extension.to_string_lossy()))
+            .unwrap_or_default();
+        for n in 1..=self.max_attempts {
+            let suffix = format!(" ({n}){extension}");
+            let candidate = path.with_file_name(fit_name(&stem, &suffix, self.name_max));
+            if fs::symlink_metadata(&candidate).is_err() {
+                return Ok(candidate);
+            }
+        }
+        Err(self.exhausted(&path.display().to_string()))
+    }
+
+    /// The names to try for `name` in turn: `name`, `name.2`, `name.3` and
+    /// so on, as many as the attempt limit allows. Each is fitted to the
+    /// length limit less `reserve` bytes, left for an extension the caller
+    /// adds to a related name.
+    pub fn numbered_candidates(
+        &self,
+        name: &str,
+        reserve: usize,
+    ) -> impl Iterator<Item = String> + use<> {
+        let name = name.to_string();
+        let name_max = self.name_max.saturating_sub(reserve);
+        (1..=self.max_attempts).map(move |n| match n {
+            1 => fit_name(&name, "", name_max),
+            n => fit_name(&name, &format!(".{n}"), name_max),
+        })
+    }
+
+    /// The first of [`numbered_candidates`](Self::numbered_candidates)
+    /// that `taken` does not reject.
+    pub fn numbered(&self, name: &str, taken: impl Fn(&str) -> bool) -> FmanResult<String> {
+        self.numbered_candidates(name, 0)
+            .find(|candidate| !taken(candidate))
+            .ok_or_else(|| self.exhausted(name))
+    }
+
+    /// The error for running out of attempts at a name for `what`.
+    pub fn exhausted(&self, what: &str) -> FmanError {
+        FmanError::AlreadyExists(format!(
+            "no free name for {what} after {} attempts",
+            self.max_attempts
+        ))
+    }
+}
+
+/// `stem` followed by `suffix`, in at most `max` bytes: characters the
+/// filesystem rejects are replaced in the stem with `_`, and the stem is
+/// cut at a character boundary if the whole is too long. The suffix is
+/// kept intact even if that leaves no stem.
+pub fn fit_name(stem: &str, suffix: &str, max: usize) -> String {
+    let mut name: String = stem
+        .chars()
+        .map(|c| if is_invalid(c) { '_' } else { c })
+        .collect();
+    let budget = max.saturating_sub(suffix.len());
+    if name.len() > budget {
+        let cut = (0..=budget)
+            .rev()
+            .find(|&i| name.is_char_boundary(i))
+            .unwrap_or(0);
+        name.truncate(cut);
+    }
+    name.push_str(suffix);
+    name
+}
+
+fn is_invalid(c: char) -> bool {
+    c == '/' || c == '\0' || (cfg!(windows) && (c.is_control() || "<>:\"\\|?*".contains(c)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    /// Entropy that always gives the same number, so every name collides.
+    struct Constant;
+
+    impl Entropy for Constant {
+        fn next_u64(&mut self) -> u64 {
+            0
+        }
+    }
+
+    #[test]
+    fn fit_name_keeps_short_names_whole() {
+        assert_eq!(fit_name("report", ".bak", NAME_MAX), "report.bak");
+    }
+
+    #[test]
+    fn fit_name_shortens_the_stem_to_name_max() {
+        let stem = "a".repeat(300);
+        let name = fit_name(&stem, ".tmp", NAME_MAX);
+        assert_eq!(name.len(), NAME_MAX);
+        assert!(name.ends_with(".tmp"));
+        assert_eq!(fit_name(&"a".repeat(251), ".tmp", NAME_MAX).len(), NAME_MAX);
+        assert_eq!(fit_name(&"a".repeat(252), ".tmp", NAME_MAX).len(), NAME_MAX);
+    }
+
+    #[test]
+    fn fit_name_cuts_at_a_character_boundary() {
+        // 'é' is two bytes: 127 of them make 254, one over the budget.
+        let stem = "é".repeat(127);
+        let name = fit_name(&stem, ".x", NAME_MAX);
+        assert!(name.len() <= NAME_MAX);
+        assert_eq!(name, format!("{}.x", "é".repeat(126)));
+    }
+
+    #[test]
+    fn fit_name_keeps_a_suffix_longer_than_the_limit() {
+        assert_eq!(fit_name("stem", ".suffix", 4), ".suffix");
+    }
+
+    #[test]
+    fn fit_name_replaces_rejected_characters() {
+        assert_eq!(fit_name("a/b\0c", "", NAME_MAX), "a_b_c");
+    }
+
+    #[test]
+    fn a_fixed_seed_gives_the_same_names() {
+        let dir = TempDir::new().unwrap();
+        let (a, b) = (NamingContext::seeded(42), NamingContext::seeded(42));
+        for _ in 0..3 {
+            assert_eq!(
+                a.temp_path(dir.path(), "f").unwrap(),
+                b.temp_path(dir.path(), "f").unwrap()
+            );
+            assert_eq!(
+                a.scratch_path(dir.path()).unwrap(),
+                b.scratch_path(dir.path()).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn different_seeds_give_different_names() {
+        let (a, b) = (NamingContext::seeded(1), NamingContext::seeded(2));
+        assert_ne!(a.random_suffix(RANDOM_LEN), b.random_suffix(RANDOM_LEN));
+    }
+
+    #[test]
+    fn random_suffixes_use_lowercase_letters_and_digits() {
+        let suffix = NamingContext::seeded(7).random_suffix(64);
+        assert_eq!(suffix.len(), 64);
+        assert!(suffix.bytes().all(|b| ALPHABET.contains(&b)));
+    }
+
+    #[test]
+    fn temp_and_scratch_names_have_their_shapes() {
+        let dir = TempDir::new().unwrap();
+        let naming = NamingContext::seeded(3);
+        let temp = naming.temp_path(dir.path(), "data.csv").unwrap();
+        let name = temp.file_name().unwrap().to_str().unwrap();
+        assert!(name.starts_with(".data.csv.") && name.ends_with(".tmp"));
+        assert_eq!(name.len(), ".data.csv.".len() + RANDOM_LEN + ".tmp".len());
+        let scratch = naming.scratch_path(dir.path()).unwrap();
+        let name = scratch.file_name().unwrap().to_str().unwrap();
+        assert!(name.starts_with(SCRATCH_PREFIX));
+        assert_eq!(name.len(), SCRATCH_PREFIX.len() + RANDOM_LEN);
+        assert_eq!(temp.parent(), Some(dir.path()));
+    }
+
+    #[test]
+    fn temp_names_for_long_names_stay_within_name_max() {
+        let dir = TempDir::new().unwrap();
+        let long = "n".repeat(NAME_MAX);
+        let temp = NamingContext::seeded(3)
+            .temp_path(dir.path(), &long)
+            .unwrap();
+        let name = temp.file_name().unwrap().to_str().unwrap();
+        assert_eq!(name.len(), NAME_MAX);
+        assert!(name.ends_with(".tmp"));
+    }
+
+    #[test]
+    fn a_taken_name_is_retried() {
+        let dir = TempDir::new().unwrap();
+        let first = NamingContext::seeded(9).scratch_path(dir.path()).unwrap();
+        fs::write(&first, "").unwrap();
+        let second = NamingContext::seeded(9).scratch_path(dir.path()).unwrap();
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn retries_give_up_after_max_attempts() {
+        let dir = TempDir::new().unwrap();
+        let naming = NamingContext::with_entropy(Constant).max_attempts(3);
+        let taken = naming.scratch_path(dir.path()).unwrap();
+        fs::write(&taken, "").unwrap();
+        let err = naming.scratch_path(dir.path()).unwrap_err();
+        assert!(matches!(err, FmanError::AlreadyExists(_)), "{err:?}");
+        assert!(err.to_string().contains("after 3 attempts"), "{err}");
+        let taken = naming.temp_path(dir.path(), "f").unwrap();
+        fs::write(&taken, "").unwrap();
+        assert!(naming.temp_path(dir.path(), "f").is_err());
+    }
+
+    #[test]
+    fn max_attempts_is_at_least_one() {
+        let dir = TempDir::new().unwrap();
+        let naming = NamingContext::with_entropy(Constant).max_attempts(0);
+        assert!(naming.scratch_path(dir.path()).is_ok());
+    }
+
+    #[test]
+    fn backups_are_numbered_once_the_plain_name_is_taken() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("notes.txt");
+        let naming = NamingContext::seeded(0);
+        assert_eq!(
+            naming.backup_path(&file, ".bak").unwrap(),
+            dir.path().join("notes.txt.bak")
+        );
+        fs::write(dir.path().join("notes.txt.bak"), "").unwrap();
+        fs::write(dir.path().join("notes.txt.bak.1"), "").unwrap();
+        assert_eq!(
+            naming.backup_path(&file, ".bak").unwrap(),
+            dir.path().join("notes.txt.bak.2")
+        );
+    }
+
+    #[test]
+    fn alternatives_keep_the_extension() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("photo.jpg");
+        let naming = NamingContext::seeded(0);
+        assert_eq!(
+            naming.alternative_path(&file).unwrap(),
+            dir.path().join("photo (1).jpg")
+        );
+        fs::write(dir.path().join("photo (1).jpg"), "").unwrap();
+        assert_eq!(
+            naming.alternative_path(&file).unwrap(),
+            dir.path().join("photo (2).jpg")
+        );
+    }
+
+    #[test]
+    fn alternatives_give_up_after_max_attempts() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("a");
+        fs::write(dir.path().join("a (1)"), "").unwrap();
+        fs::write(dir.path().join("a (2)"), "").unwrap();
+        let naming = NamingContext::seeded(0).max_attempts(2);
+        let err = naming.alternative_path(&file).unwrap_err();
+        assert!(err.to_string().contains("after 2 attempts"), "{err}");
+    }
+
+    #[test]
+    fn numbered_names_skip_taken_ones_and_respect_the_reserve() {
+        let naming = NamingContext::seeded(0);
+        let name = naming.numbered("x", |n| n == "x" || n == "x.2").unwrap();
+        assert_eq!(name, "x.3");
+        let long = "y".repeat(NAME_MAX);
+        let first = naming.numbered_candidates(&long, 10).next().unwrap();
+        assert_eq!(first.len(), NAME_MAX - 10);
+        let err = NamingContext::seeded(0)
+            .max_attempts(2)
+            .numbered("z", |_| true)
+            .unwrap_err();
+        assert!(matches!(err, FmanError::AlreadyExists(_)));
+    }
+}
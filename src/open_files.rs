@@ -0,0 +1,204 @@
+//! Finding processes that still hold deleted files open (Linux).
+//!
+//! Removing a file only drops its name; the space comes back once the last
+//! open descriptor is closed. After a delete, the inodes that were removed
+//! are looked up among the descriptors listed under `/proc/<pid>/fd`.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fs::{self, Metadata};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "json")]
+use serde::Serialize;
+
+/// Where the kernel lists processes.
+pub const PROC_ROOT: &str = "/proc";
+
+/// A removed file, keyed by inode in [`DeletedFiles`].
+#[derive(Debug, Clone)]
+pub struct DeletedFile {
+    pub path: PathBuf,
+    pub size: u64,
+}
+
+/// Removed regular files by `(device, inode)`.
+pub type DeletedFiles = HashMap<(u64, u64), DeletedFile>;
+
+/// Remember `path` as about to be removed, if removing it could free data:
+/// a regular file with no other name.
+pub fn track(deleted: &mut DeletedFiles, path: &Path, meta: &Metadata) {
+    if meta.is_file() && meta.nlink() == 1 {
+        deleted.insert(
+            (meta.dev(), meta.ino()),
+            DeletedFile {
+                path: path.to_path_buf(),
+                size: meta.len(),
+            },
+        );
+    }
+}
+
+/// A process holding one of the removed files open.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(Serialize))]
+pub struct OpenHolder {
+    pub pid: u32,
+    /// The process name from `comm`, empty if unreadable.
+    pub name: String,
+    /// The path the file had before it was removed.
+    pub path: PathBuf,
+    pub size: u64,
+}
+
+/// Scan the process table under `proc_root` (normally [`PROC_ROOT`]) for
+/// descriptors open on any of `deleted`.
+///
+/// Processes whose descriptors cannot be read, which without privileges
+/// is every process of another user, are skipped. Results are ordered by
+/// pid, then path, with one entry per process and file however many
+/// descriptors it has on it.
+pub fn find_holders(proc_root: &Path, deleted: &DeletedFiles) -> Vec<OpenHolder> {
+    let mut holders = BTreeSet::new();
+    if deleted.is_empty() {
+        return Vec::new();
+    }
+    let Ok(processes) = fs::read_dir(proc_root) else {
+        return Vec::new();
+    };
+    for process in processes.flatten() {
+        let Some(pid) = process
+            .file_name()
+            .to_str()
+            .and_then(|s| s.parse::<u32>().ok())
+        else {
+            continue;
+        };
+        let Ok(descriptors) = fs::read_dir(process.path().join("fd")) else {
+            continue;
+        };
+        let mut name = None;
+        for descriptor in descriptors.flatten() {
+            // The fd entries are magic links to the open file itself, so
+            // stat reaches the inode even after its name is gone.
+            let Ok(meta) = fs::metadata(descriptor.path()) else {
+                continue;
+            };
+            if let Some(file) = deleted.get(&(meta.dev(), meta.ino())) {
+                let name = name.get_or_insert_with(|| process_name(&process.path()));
+                holders.insert((pid, file.path.clone(), name.clone(), file.size));
+            }
+        }
+    }
+    holders
+        .into_iter()
+        .map(|(pid, path, name, size)| OpenHolder {
+            pid,
+            name,
+            path,
+            size,
+        })
+        .collect()
+}
+
+/// Bytes still in use because of `holders`, counting each file once.
+pub fn pinned_bytes(holders: &[OpenHolder]) -> u64 {
+    let mut files: HashMap<&Path, u64> = HashMap::new();
+    for holder in holders {
+        files.insert(&holder.path, holder.size);
+    }
+    files.values().sum()
+}
+
+fn process_name(process_dir: &Path) -> String {
+    fs::read_to_string(process_dir.join("comm"))
+        .map(|comm| comm.trim_end().to_string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::os::unix::fs::symlink;
+
+    use tempfile::TempDir;
+
+    fn deleted(files: &[&Path]) -> DeletedFiles {
+        let mut deleted = DeletedFiles::new();
+        for path in files {
+            track(&mut deleted, path, &fs::metadata(path).unwrap());
+        }
+        deleted
+    }
+
+    /// A process table under `root`: pid 12 ("editor") with two
+    /// descriptors on `held` and one on `other`, pid 7 ("tail") with one on
+    /// `held`, a pid whose descriptors cannot be read, a dangling
+    /// descriptor and an entry that is not a process.
+    fn proc_table(root: &Path, held: &Path, other: &Path) {
+        let process = |pid: &str, name: &str, fds: &[(&str, &Path)]| {
+            let dir = root.join(pid);
+            fs::create_dir_all(dir.join("fd")).unwrap();
+            fs::write(dir.join("comm"), format!("{name}\n")).unwrap();
+            for (fd, target) in fds {
+                symlink(target, dir.join("fd").join(fd)).unwrap();
+            }
+        };
+        process("12", "editor", &[("3", held), ("4", held), ("5", other)]);
+        process("7", "tail", &[("0", held), ("1", &root.join("gone"))]);
+        fs::create_dir_all(root.join("99")).unwrap();
+        process("self", "not-a-pid", &[("3", held)]);
+    }
+
+    #[test]
+    fn holders_are_found_once_per_process_and_file() {
+        let dir = TempDir::new().unwrap();
+        let (held, other) = (dir.path().join("held"), dir.path().join("other"));
+        fs::write(&held, "12345").unwrap();
+        fs::write(&other, "1").unwrap();
+        let proc_root = dir.path().join("proc");
+        proc_table(&proc_root, &held, &other);
+
+        let holders = find_holders(&proc_root, &deleted(&[&held, &other]));
+        let summary: Vec<(u32, &str, &Path, u64)> = holders
+            .iter()
+            .map(|h| (h.pid, h.name.as_str(), h.path.as_path(), h.size))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (7, "tail", held.as_path(), 5),
+                (12, "editor", held.as_path(), 5),
+                (12, "editor", other.as_path(), 1),
+            ]
+        );
+        assert_eq!(pinned_bytes(&holders), 6);
+    }
+
+    #[test]
+    fn files_not_deleted_are_not_reported() {
+        let dir = TempDir::new().unwrap();
+        let (held, other) = (dir.path().join("held"), dir.path().join("other"));
+        fs::write(&held, "12345").unwrap();
+        fs::write(&other, "1").unwrap();
+        let proc_root = dir.path().join("proc");
+        proc_table(&proc_root, &held, &other);
+        let holders = find_holders(&proc_root, &deleted(&[&other]));
+        assert_eq!(holders.len(), 1);
+        assert!(find_holders(&proc_root, &DeletedFiles::new()).is_empty());
+        assert!(find_holders(&dir.path().join("no-proc"), &deleted(&[&held])).is_empty());
+    }
+
+    #[test]
+    fn only_files_whose_data_can_be_freed_are_tracked() {
+        let dir = TempDir::new().unwrap();
+        let (lone, linked) = (dir.path().join("lone"), dir.path().join("linked"));
+        fs::write(&lone, "1").unwrap();
+        fs::write(&linked, "2").unwrap();
+        fs::hard_link(&linked, dir.path().join("second-name")).unwrap();
+        let tracked = deleted(&[&lone, &linked, dir.path()]);
+        assert_eq!(tracked.len(), 1);
+        assert_eq!(tracked.values().next().unwrap().path, lone);
+    }
+}
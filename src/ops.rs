@@ -0,0 +1,1235 @@
+//! The library's entry point: one function per `fman` operation.
+//!
+//! Each function takes a request describing the operation and returns a
+//! report of what it did. Nothing here prints, exits or reads global
+//! state; changes go through the [`Fs`] a request carries (the real
+//! filesystem unless one is set), so a caller can record or dry-run them.
+//! The `fman` binary is a thin layer over this module, and
+//! `examples/sync_summary.rs` shows a program built on it.
+//!
+//! The types the operations take and return are re-exported here as well.
+//!
+//! ```
+//! use fman::ops::{self, SyncAction, SyncRequest};
+//!
+//! # let dir = tempfile::TempDir::new()?;
+//! # let (photos, backup) = (dir.path().join("photos"), dir.path().join("backup"));
+//! # std::fs::create_dir_all(photos.join("2024"))?;
+//! # std::fs::create_dir(&backup)?;
+//! # std::fs::write(photos.join("2024/beach.jpg"), "jpeg")?;
+//! let report = ops::sync(&SyncRequest::new(&photos, &backup))?;
+//! for action in &report.actions {
+//!     if let SyncAction::Copy { path, .. } = action {
+//!         println!("backed up {path}");
+//!     }
+//! }
+//! println!("{} changes", report.actions.len());
+//! # assert_eq!(report.actions.len(), 1);
+//! # assert_eq!(std::fs::read_to_string(backup.join("2024/beach.jpg"))?, "jpeg");
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! The modules behind these functions are private, so this is the only
+//! way in:
+//!
+//! ```compile_fail
+//! use fman::copy::copy_file;
+//! ```
+//!
+//! ```compile_fail
+//! use fman::sync::sync_one_way;
+//! ```
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+#[cfg(feature = "json")]
+use serde::Serialize;
+
+use crate::clock::Clock;
+use crate::error::{DEFAULT_MAX_ERRORS, Failures, FmanResult};
+use crate::fs::{FsOp, RealFs, SharedFs};
+use crate::naming::NamingContext;
+use crate::{
+    apply, copy, dedupe, delete, du, find, fsinfo, hash, info, link, list, mirror, mv, relink,
+    rename, sync, template, trash, watch,
+};
+
+pub use crate::apply::{ApplyReport, parse_plan, read_plan, step_paths};
+#[cfg(feature = "archive")]
+pub use crate::archive::{ArchiveFormat, ArchiveReport, ExtractReport};
+pub use crate::backend::CopyStrategy;
+pub use crate::budget::{BudgetUsage, ByteBudget};
+pub use crate::cachedir::ExcludeCaches;
+pub use crate::chmod::ModeChange;
+pub use crate::compare::{compare_modified, files_equal};
+pub use crate::copy::{
+    ConfirmOverwrite, CopyDirReport, CopyOptions, CopyReport, CopyStatus, Immutability, LinkMode,
+    OverwritePolicy, PROGRESS_STEP, PathTransform, Progress, RacingPolicy, Reflink, SlowFile,
+    copy_from_file, copy_to_file, is_active, lowercase_names, resolve_destination_path,
+    sanitize_windows_names,
+};
+pub use crate::dedupe::{DedupeOptions, DedupeRemoval, DedupeReport, DuplicateGroup};
+pub use crate::delete::{DeleteOptions, DeleteReport};
+pub use crate::du::{
+    DIRSIZE_XATTR, DuEntry, DuOptions, DuReport, WatchEntry, WatchSample, XattrCacheStats,
+};
+pub use crate::find::{FindEntry, FindOptions, PathStyle};
+pub use crate::fsinfo::{
+    Capability, FsInfo, Mount, best_mount, format_granularity, parse_mountinfo,
+};
+pub use crate::hash::{
+    HashAlgorithm, SIDECAR_EXTENSION, SidecarCheck, SidecarCounts, SidecarReport, SidecarStatus,
+    hash_reader,
+};
+pub use crate::info::FileInfo;
+pub use crate::link::{LinkKind, LinkReport};
+pub use crate::list::{EntryKind, ListEntry, ListOptions, list_dir};
+pub use crate::mirror::{MirrorChange, MirrorReport};
+pub use crate::mv::MoveOptions;
+pub use crate::ownership::OwnershipMap;
+pub use crate::relink::{RelinkReport, Relinked, RewriteRule, Unfixed};
+pub use crate::sync::{
+    Baseline, FileState, Side, Snapshot, SyncAction, SyncCounts, SyncReport, SyncScope,
+    plan_bidirectional, plan_one_way,
+};
+pub use crate::template::{DEFAULT_MAX_SUBSTITUTE_SIZE, TemplateOptions, TemplateReport, render};
+pub use crate::touch::Touched;
+pub use crate::trash::{EmptyFilter, EmptyPlan, TrashItem};
+pub use crate::watch::{
+    ALL_PATHS, CommandRunner, EACH_PATH, ProcessRunner, RunReport, WatchEvent, WatchEventKind,
+    WatchOptions, expand_command,
+};
+
+/// Placeholders accepted by a `find` format template.
+pub const FIND_FORMAT_FIELDS: &[&str] = find::FORMAT_FIELDS;
+
+/// Placeholders accepted by an `ls` format template.
+pub const LIST_FORMAT_FIELDS: &[&str] = list::FORMAT_FIELDS;
+
+fn real_fs() -> SharedFs {
+    Arc::new(RealFs)
+}
+
+/// A copy of one file, or of a directory tree with [`copy_dir`].
+#[derive(Debug, Clone)]
+pub struct CopyRequest {
+    source: PathBuf,
+    destination: PathBuf,
+    options: CopyOptions,
+}
+
+impl CopyRequest {
+    pub fn new(source: impl Into<PathBuf>, destination: impl Into<PathBuf>) -> Self {
+        CopyRequest {
+            source: source.into(),
+            destination: destination.into(),
+            options: CopyOptions::new(),
+        }
+    }
+
+    pub fn options(mut self, options: CopyOptions) -> Self {
+        self.options = options;
+        self
+    }
+}
+
+/// Copy a file. An existing directory as the destination receives the file.
+pub fn copy(request: &CopyRequest) -> FmanResult<CopyReport> {
+    copy::copy_file(&request.source, &request.destination, &request.options)
+}
+
+/// Copy a directory tree.
+pub fn copy_dir(request: &CopyRequest) -> FmanResult<CopyDirReport> {
+    copy::copy_dir(&request.source, &request.destination, &request.options)
+}
+
+#[derive(Clone)]
+pub struct ApplyRequest {
+    plan: Vec<FsOp>,
+    jobs: usize,
+    filesystem: SharedFs,
+    budget: Option<ByteBudget>,
+}
+
+impl ApplyRequest {
+    pub fn new(plan: Vec<FsOp>) -> Self {
+        ApplyRequest {
+            plan,
+            jobs: 1,
+            filesystem: real_fs(),
+            budget: None,
+        }
+    }
+
+    /// Run up to `jobs` independent steps at once; 1, the default, runs
+    /// the plan strictly in order.
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs;
+        self
+    }
+
+    pub fn fs(mut self, filesystem: SharedFs) -> Self {
+        self.filesystem = filesystem;
+        self
+    }
+
+    /// Charge the data the plan's copies write to `budget`, stopping with
+    /// [`FmanError::QuotaExceeded`](crate::FmanError::QuotaExceeded) at the
+    /// first file that does not fit.
+    pub fn budget(mut self, budget: ByteBudget) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+}
+
+/// Carry out a plan of recorded operations, each step waiting for the
+/// earlier ones whose paths overlap its own.
+pub fn apply(request: &ApplyRequest) -> FmanResult<ApplyReport> {
+    apply::apply_plan(
+        &request.plan,
+        request.jobs,
+        &request.filesystem,
+        request.budget.as_ref(),
+    )
+}
+
+#[derive(Clone)]
+pub struct MoveRequest {
+    source: PathBuf,
+    destination: PathBuf,
+    options: MoveOptions,
+    filesystem: SharedFs,
+}
+
+impl MoveRequest {
+    pub fn new(source: impl Into<PathBuf>, destination: impl Into<PathBuf>) -> Self {
+        MoveRequest {
+            source: source.into(),
+            destination: destination.into(),
+            options: MoveOptions::new(),
+            filesystem: real_fs(),
+        }
+    }
+
+    pub fn options(mut self, options: MoveOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn fs(mut self, filesystem: SharedFs) -> Self {
+        self.filesystem = filesystem;
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "json", derive(Serialize))]
+pub struct MoveReport {
+    pub source: PathBuf,
+    /// Where the source ended up, after resolving a directory destination.
+    pub destination: PathBuf,
+}
+
+/// Move or rename a file or directory tree.
+pub fn move_path(request: &MoveRequest) -> FmanResult<MoveReport> {
+    let destination = mv::move_path(
+        &request.source,
+        &request.destination,
+        &request.options,
+        &request.filesystem,
+    )?;
+    Ok(MoveReport {
+        source: request.source.clone(),
+        destination,
+    })
+}
+
+#[derive(Clone)]
+pub struct RenameRequest {
+    path: PathBuf,
+    new_name: String,
+    force: bool,
+    filesystem: SharedFs,
+}
+
+impl RenameRequest {
+    pub fn new(path: impl Into<PathBuf>, new_name: impl Into<String>) -> Self {
+        RenameRequest {
+            path: path.into(),
+            new_name: new_name.into(),
+            force: false,
+            filesystem: real_fs(),
+        }
+    }
+
+    /// Replace an existing entry with the new name.
+    pub fn force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    pub fn fs(mut self, filesystem: SharedFs) -> Self {
+        self.filesystem = filesystem;
+        self
+    }
+}
+
+/// Give a file or directory a new name in the same directory.
+pub fn rename(request: &RenameRequest) -> FmanResult<MoveReport> {
+    let destination = rename::rename_path(
+        &request.path,
+        &request.new_name,
+        request.force,
+        request.filesystem.as_ref(),
+    )?;
+    Ok(MoveReport {
+        source: request.path.clone(),
+        destination,
+    })
+}
+
+#[derive(Clone)]
+pub struct LinkRequest {
+    src: PathBuf,
+    dst: PathBuf,
+    kind: LinkKind,
+    force: bool,
+    filesystem: SharedFs,
+}
+
+impl LinkRequest {
+    /// A hard link at `dst` to `src`.
+    pub fn new(src: impl Into<PathBuf>, dst: impl Into<PathBuf>) -> Self {
+        LinkRequest {
+            src: src.into(),
+            dst: dst.into(),
+            kind: LinkKind::Hard,
+            force: false,
+            filesystem: real_fs(),
+        }
+    }
+
+    pub fn kind(mut self, kind: LinkKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    /// Replace an existing destination that is not a directory.
+    pub fn force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    pub fn fs(mut self, filesystem: SharedFs) -> Self {
+        self.filesystem = filesystem;
+        self
+    }
+}
+
+/// Make a hard link or symlink to a file, like `ln`.
+pub fn link(request: &LinkRequest) -> FmanResult<LinkReport> {
+    link::link_path(
+        &request.src,
+        &request.dst,
+        request.kind,
+        request.force,
+        request.filesystem.as_ref(),
+    )
+}
+
+#[cfg(feature = "archive")]
+#[derive(Clone)]
+pub struct ArchiveRequest {
+    src: PathBuf,
+    output: PathBuf,
+    force: bool,
+    filesystem: SharedFs,
+    max_errors: usize,
+    error_log: Option<PathBuf>,
+}
+
+#[cfg(feature = "archive")]
+impl ArchiveRequest {
+    /// Pack `src` into `output`, in the format its extension names.
+    pub fn new(src: impl Into<PathBuf>, output: impl Into<PathBuf>) -> Self {
+        ArchiveRequest {
+            src: src.into(),
+            output: output.into(),
+            force: false,
+            filesystem: real_fs(),
+            max_errors: DEFAULT_MAX_ERRORS,
+            error_log: None,
+        }
+    }
+
+    /// Overwrite an existing archive.
+    pub fn force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    pub fn fs(mut self, filesystem: SharedFs) -> Self {
+        self.filesystem = filesystem;
+        self
+    }
+
+    /// Keep at most `max` failures for the final error; later ones are only
+    /// counted.
+    pub fn max_errors(mut self, max: usize) -> Self {
+        self.max_errors = max;
+        self
+    }
+
+    /// Append every failure to the file at `path`, however many there are.
+    pub fn error_log(mut self, path: impl Into<PathBuf>) -> Self {
+        self.error_log = Some(path.into());
+        self
+    }
+}
+
+/// Pack a file or directory tree into a tar, tar.gz or zip archive.
+#[cfg(feature = "archive")]
+pub fn archive(request: &ArchiveRequest) -> FmanResult<ArchiveReport> {
+    crate::archive::create_archive(
+        &request.src,
+        &request.output,
+        request.force,
+        request.filesystem.as_ref(),
+        Failures::new(request.max_errors, request.error_log.as_deref())?,
+    )
+}
+
+#[cfg(feature = "archive")]
+#[derive(Clone)]
+pub struct ExtractRequest {
+    archive: PathBuf,
+    dest: PathBuf,
+    force: bool,
+    filesystem: SharedFs,
+}
+
+#[cfg(feature = "archive")]
+impl ExtractRequest {
+    /// Unpack `archive` into `dest`, creating it if missing.
+    pub fn new(archive: impl Into<PathBuf>, dest: impl Into<PathBuf>) -> Self {
+        ExtractRequest {
+            archive: archive.into(),
+            dest: dest.into(),
+            force: false,
+            filesystem: real_fs(),
+        }
+    }
+
+    /// Replace existing files.
+    pub fn force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    pub fn fs(mut self, filesystem: SharedFs) -> Self {
+        self.filesystem = filesystem;
+        self
+    }
+}
+
+/// Unpack a tar, tar.gz or zip archive, refusing entries that would land
+/// outside the destination.
+#[cfg(feature = "archive")]
+pub fn extract(request: &ExtractRequest) -> FmanResult<ExtractReport> {
+    crate::archive::extract_archive(
+        &request.archive,
+        &request.dest,
+        request.force,
+        request.filesystem.as_ref(),
+    )
+}
+
+#[derive(Clone)]
+pub struct ChmodRequest {
+    path: PathBuf,
+    change: ModeChange,
+    recursive: bool,
+    filesystem: SharedFs,
+    max_errors: usize,
+    error_log: Option<PathBuf>,
+}
+
+impl ChmodRequest {
+    pub fn new(path: impl Into<PathBuf>, change: ModeChange) -> Self {
+        ChmodRequest {
+            path: path.into(),
+            change,
+            recursive: false,
+            filesystem: real_fs(),
+            max_errors: DEFAULT_MAX_ERRORS,
+            error_log: None,
+        }
+    }
+
+    /// Change everything below a directory too.
+    pub fn recursive(mut self, recursive: bool) -> Self {
+        self.recursive = recursive;
+        self
+    }
+
+    pub fn fs(mut self, filesystem: SharedFs) -> Self {
+        self.filesystem = filesystem;
+        self
+    }
+
+    /// Keep at most `max` failures below a directory for the final error;
+    /// later ones are only counted.
+    pub fn max_errors(mut self, max: usize) -> Self {
+        self.max_errors = max;
+        self
+    }
+
+    /// Append every failure below a directory to the file at `path`,
+    /// however many there are.
+    pub fn error_log(mut self, path: impl Into<PathBuf>) -> Self {
+        self.error_log = Some(path.into());
+        self
+    }
+}
+
+/// Change permission bits, returning the paths changed.
+pub fn chmod(request: &ChmodRequest) -> FmanResult<Vec<PathBuf>> {
+    crate::chmod::chmod_path(
+        &request.path,
+        request.change,
+        request.recursive,
+        request.filesystem.as_ref(),
+        Failures::new(request.max_errors, request.error_log.as_deref())?,
+    )
+}
+
+#[derive(Clone)]
+pub struct TouchRequest {
+    path: PathBuf,
+    no_create: bool,
+    filesystem: SharedFs,
+}
+
+impl TouchRequest {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        TouchRequest {
+            path: path.into(),
+            no_create: false,
+            filesystem: real_fs(),
+        }
+    }
+
+    /// Leave a missing path missing instead of creating it.
+    pub fn no_create(mut self, no_create: bool) -> Self {
+        self.no_create = no_create;
+        self
+    }
+
+    pub fn fs(mut self, filesystem: SharedFs) -> Self {
+        self.filesystem = filesystem;
+        self
+    }
+}
+
+/// Create a file empty, or set its timestamps to now if it exists.
+pub fn touch(request: &TouchRequest) -> FmanResult<Touched> {
+    crate::touch::touch_path(
+        &request.path,
+        request.no_create,
+        request.filesystem.as_ref(),
+    )
+}
+
+#[derive(Debug, Clone)]
+pub struct DeleteRequest {
+    target: PathBuf,
+    options: DeleteOptions,
+}
+
+impl DeleteRequest {
+    pub fn new(target: impl Into<PathBuf>) -> Self {
+        DeleteRequest {
+            target: target.into(),
+            options: DeleteOptions::new(),
+        }
+    }
+
+    pub fn options(mut self, options: DeleteOptions) -> Self {
+        self.options = options;
+        self
+    }
+}
+
+/// Delete a file, symlink or (with [`DeleteOptions::recursive`]) tree.
+pub fn delete(request: &DeleteRequest) -> FmanResult<DeleteReport> {
+    delete::delete_path(&request.target, &request.options)
+}
+
+/// Which way a [`SyncRequest`] copies.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum SyncMode {
+    /// Make `b` hold what `a` holds.
+    #[default]
+    OneWay,
+    /// Carry changes both ways, with the baseline from the last run in
+    /// `state_file` if given.
+    Bidirectional { state_file: Option<PathBuf> },
+}
+
+#[derive(Clone)]
+pub struct SyncRequest {
+    a: PathBuf,
+    b: PathBuf,
+    mode: SyncMode,
+    scope: SyncScope,
+    filesystem: SharedFs,
+    naming: Arc<NamingContext>,
+    budget: Option<ByteBudget>,
+    require: Vec<Capability>,
+    max_errors: usize,
+    error_log: Option<PathBuf>,
+}
+
+impl SyncRequest {
+    pub fn new(a: impl Into<PathBuf>, b: impl Into<PathBuf>) -> Self {
+        SyncRequest {
+            a: a.into(),
+            b: b.into(),
+            mode: SyncMode::default(),
+            scope: SyncScope::default(),
+            filesystem: real_fs(),
+            naming: Arc::new(NamingContext::new()),
+            budget: None,
+            require: Vec::new(),
+            max_errors: DEFAULT_MAX_ERRORS,
+            error_log: None,
+        }
+    }
+
+    pub fn mode(mut self, mode: SyncMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Treat mtimes at most `window` apart as equal.
+    pub fn modify_window(mut self, window: Duration) -> Self {
+        self.scope.window = window;
+        self
+    }
+
+    /// Leave out directories tagged as caches with a `CACHEDIR.TAG` in
+    /// either tree, keeping what `mode` says of them.
+    pub fn exclude_caches(mut self, mode: ExcludeCaches) -> Self {
+        self.scope.exclude_caches = Some(mode);
+        self
+    }
+
+    pub fn fs(mut self, filesystem: SharedFs) -> Self {
+        self.filesystem = filesystem;
+        self
+    }
+
+    /// Where conflict copy and temporary file names come from.
+    pub fn naming(mut self, naming: Arc<NamingContext>) -> Self {
+        self.naming = naming;
+        self
+    }
+
+    /// Charge the data copied to `budget`, stopping with
+    /// [`FmanError::QuotaExceeded`](crate::FmanError::QuotaExceeded) at the
+    /// first file that does not fit.
+    pub fn budget(mut self, budget: ByteBudget) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    /// Fail before syncing anything unless every filesystem written to has
+    /// all of `capabilities`: `b`'s, and with
+    /// [`SyncMode::Bidirectional`] `a`'s as well.
+    pub fn require(mut self, capabilities: &[Capability]) -> Self {
+        self.require = capabilities.to_vec();
+        self
+    }
+
+    /// Keep at most `max` failures for the final error; later ones are only
+    /// counted.
+    pub fn max_errors(mut self, max: usize) -> Self {
+        self.max_errors = max;
+        self
+    }
+
+    /// Append every failure to the file at `path`, however many there are.
+    pub fn error_log(mut self, path: impl Into<PathBuf>) -> Self {
+        self.error_log = Some(path.into());
+        self
+    }
+}
+
+pub fn sync(request: &SyncRequest) -> FmanResult<SyncReport> {
+    let (a, b, scope) = (&request.a, &request.b, &request.scope);
+    fsinfo::require(a, b, &request.require)?;
+    if let SyncMode::Bidirectional { .. } = request.mode {
+        fsinfo::require(b, a, &request.require)?;
+    }
+    let (filesystem, naming) = (&request.filesystem, request.naming.as_ref());
+    let budget = request.budget.as_ref();
+    let failures = Failures::new(request.max_errors, request.error_log.as_deref())?;
+    match &request.mode {
+        SyncMode::OneWay => sync::sync_one_way(a, b, scope, filesystem, naming, budget, failures),
+        SyncMode::Bidirectional { state_file } => sync::sync_bidirectional(
+            a,
+            b,
+            state_file.as_deref(),
+            scope,
+            filesystem,
+            naming,
+            budget,
+            failures,
+        ),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DedupeRequest {
+    root: PathBuf,
+    options: DedupeOptions,
+}
+
+impl DedupeRequest {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        DedupeRequest {
+            root: root.into(),
+            options: DedupeOptions::new(),
+        }
+    }
+
+    pub fn options(mut self, options: DedupeOptions) -> Self {
+        self.options = options;
+        self
+    }
+}
+
+/// Groups of files with identical contents below a directory.
+pub fn find_duplicates(request: &DedupeRequest) -> FmanResult<DedupeReport> {
+    dedupe::find_duplicates(&request.root, &request.options)
+}
+
+/// Remove all but the first file of each group through `filesystem`.
+pub fn remove_duplicates(
+    groups: &[DuplicateGroup],
+    filesystem: &SharedFs,
+) -> FmanResult<DedupeRemoval> {
+    dedupe::remove_duplicates(groups, filesystem.as_ref())
+}
+
+#[derive(Debug, Clone)]
+pub struct DuRequest {
+    path: PathBuf,
+    top: Option<usize>,
+    options: DuOptions,
+}
+
+impl DuRequest {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        DuRequest {
+            path: path.into(),
+            top: None,
+            options: DuOptions::new(),
+        }
+    }
+
+    /// Keep only the `n` largest entries.
+    pub fn top(mut self, n: usize) -> Self {
+        self.top = Some(n);
+        self
+    }
+
+    /// Keep the total of each directory's own files in its
+    /// [`DIRSIZE_XATTR`] and reuse it on later runs while the directory's
+    /// mtime and entry count are unchanged. A file rewritten in place to a
+    /// new size goes unseen until its directory changes.
+    pub fn cache_xattr(mut self, cache_xattr: bool) -> Self {
+        self.options = self.options.cache_xattr(cache_xattr);
+        self
+    }
+
+    /// Count directories tagged as caches with a `CACHEDIR.TAG` as what
+    /// `mode` keeps of them.
+    pub fn exclude_caches(mut self, mode: ExcludeCaches) -> Self {
+        self.options = self.options.exclude_caches(mode);
+        self
+    }
+}
+
+/// Disk usage below a directory, largest entries first.
+pub fn du(request: &DuRequest) -> FmanResult<DuReport> {
+    let mut report = du::disk_usage_with(&request.path, &request.options)?;
+    if let Some(top) = request.top {
+        report.entries.truncate(top);
+    }
+    Ok(report)
+}
+
+/// Remove the directory totals cached by [`DuRequest::cache_xattr`] below
+/// `path` through `filesystem`, returning how many directories had one.
+pub fn du_clear_cache(path: &Path, filesystem: &SharedFs) -> FmanResult<u64> {
+    du::clear_xattr_cache(path, filesystem.as_ref())
+}
+
+#[derive(Debug, Clone)]
+pub struct DuWatchRequest {
+    path: PathBuf,
+    interval: Duration,
+    use_cache: bool,
+    exclude_caches: Option<ExcludeCaches>,
+}
+
+impl DuWatchRequest {
+    pub fn new(path: impl Into<PathBuf>, interval: Duration) -> Self {
+        DuWatchRequest {
+            path: path.into(),
+            interval,
+            use_cache: true,
+            exclude_caches: None,
+        }
+    }
+
+    /// Reuse sizes of directories whose mtime has not changed (default on).
+    pub fn use_cache(mut self, use_cache: bool) -> Self {
+        self.use_cache = use_cache;
+        self
+    }
+
+    /// See [`DuRequest::exclude_caches`].
+    pub fn exclude_caches(mut self, mode: ExcludeCaches) -> Self {
+        self.exclude_caches = Some(mode);
+        self
+    }
+}
+
+/// Sample disk usage every interval, timed by `clock`, until `on_sample`
+/// returns `false`.
+pub fn du_watch(
+    request: &DuWatchRequest,
+    clock: &dyn Clock,
+    on_sample: impl FnMut(&WatchSample) -> bool,
+) -> FmanResult<()> {
+    let mut watcher = du::DuWatcher::new(&request.path, request.use_cache);
+    if let Some(mode) = request.exclude_caches {
+        watcher = watcher.exclude_caches(mode);
+    }
+    du::watch(&mut watcher, request.interval, clock, on_sample)
+}
+
+#[derive(Debug, Clone)]
+pub struct WatchRequest {
+    root: PathBuf,
+    command: Vec<OsString>,
+    options: WatchOptions,
+}
+
+impl WatchRequest {
+    pub fn new(root: impl Into<PathBuf>, command: &[OsString]) -> Self {
+        WatchRequest {
+            root: root.into(),
+            command: command.to_vec(),
+            options: WatchOptions::new(),
+        }
+    }
+
+    pub fn options(mut self, options: WatchOptions) -> Self {
+        self.options = options;
+        self
+    }
+}
+
+/// Run the request's command through `runner` after each batch of changes
+/// below its root, timed by `clock`, until `on_run` returns `false`.
+pub fn watch_run(
+    request: &WatchRequest,
+    clock: &dyn Clock,
+    runner: &mut dyn CommandRunner,
+    on_run: impl FnMut(&RunReport) -> bool,
+) -> FmanResult<()> {
+    watch::watch_and_run(
+        &request.root,
+        &request.command,
+        &request.options,
+        clock,
+        runner,
+        on_run,
+    )
+}
+
+/// Report each path created, modified or removed below the request's
+/// root, batch by batch, timed by `clock`, until `on_event` returns
+/// `false` or the options' cancel token is cancelled. The request's
+/// command is not used.
+pub fn watch_events(
+    request: &WatchRequest,
+    clock: &dyn Clock,
+    on_event: impl FnMut(&WatchEvent) -> bool,
+) -> FmanResult<()> {
+    watch::watch_events(&request.root, &request.options, clock, on_event)
+}
+
+#[derive(Debug, Clone)]
+pub struct FindRequest {
+    root: PathBuf,
+    options: FindOptions,
+}
+
+impl FindRequest {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        FindRequest {
+            root: root.into(),
+            options: FindOptions::new(),
+        }
+    }
+
+    pub fn options(mut self, options: FindOptions) -> Self {
+        self.options = options;
+        self
+    }
+}
+
+/// Everything below a directory, in sorted pre-order. Unreadable
+/// subdirectories show up as `Err` items and the search continues.
+pub fn find(
+    request: &FindRequest,
+) -> FmanResult<impl Iterator<Item = FmanResult<FindEntry>> + use<>> {
+    find::find(&request.root, &request.options)
+}
+
+#[derive(Debug, Clone)]
+pub struct ListRequest {
+    dir: PathBuf,
+    options: ListOptions,
+}
+
+impl ListRequest {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        ListRequest {
+            dir: dir.into(),
+            options: ListOptions::new(),
+        }
+    }
+
+    pub fn options(mut self, options: ListOptions) -> Self {
+        self.options = options;
+        self
+    }
+}
+
+/// The entries of a directory, as they are read.
+pub fn list(
+    request: &ListRequest,
+) -> FmanResult<impl Iterator<Item = FmanResult<ListEntry>> + use<>> {
+    list::list_dir_iter(&request.dir, &request.options)
+}
+
+#[derive(Debug, Clone)]
+pub struct HashRequest {
+    paths: Vec<PathBuf>,
+    algorithm: HashAlgorithm,
+    recursive: bool,
+}
+
+impl HashRequest {
+    pub fn new(paths: impl IntoIterator<Item = impl Into<PathBuf>>) -> Self {
+        HashRequest {
+            paths: paths.into_iter().map(Into::into).collect(),
+            algorithm: HashAlgorithm::default(),
+            recursive: false,
+        }
+    }
+
+    pub fn algorithm(mut self, algorithm: HashAlgorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// Reduce directories to one digest of their whole tree instead of
+    /// rejecting them.
+    pub fn recursive(mut self, recursive: bool) -> Self {
+        self.recursive = recursive;
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "json", derive(Serialize))]
+pub struct HashedFile {
+    pub path: PathBuf,
+    /// Lowercase hex.
+    pub digest: String,
+}
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "json", derive(Serialize))]
+pub struct HashReport {
+    /// In the order the paths were given.
+    pub files: Vec<HashedFile>,
+}
+
+/// Digest each path; the first failure fails the whole request.
+pub fn hash(request: &HashRequest) -> FmanResult<HashReport> {
+    let digests = hash::hash_paths(&request.paths, request.algorithm, request.recursive);
+    let files = request
+        .paths
+        .iter()
+        .zip(digests)
+        .map(|(path, digest)| {
+            Ok(HashedFile {
+                path: path.clone(),
+                digest: digest?,
+            })
+        })
+        .collect::<FmanResult<_>>()?;
+    Ok(HashReport { files })
+}
+
+#[derive(Debug, Clone)]
+pub struct SidecarRequest {
+    root: PathBuf,
+    algorithm: HashAlgorithm,
+    require_coverage: bool,
+}
+
+impl SidecarRequest {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        SidecarRequest {
+            root: root.into(),
+            algorithm: HashAlgorithm::default(),
+            require_coverage: false,
+        }
+    }
+
+    pub fn algorithm(mut self, algorithm: HashAlgorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// Also report files that have no sidecar.
+    pub fn require_coverage(mut self, require: bool) -> Self {
+        self.require_coverage = require;
+        self
+    }
+}
+
+/// Check every sidecar under the root against its file. Mismatches are
+/// reported, not returned as errors.
+pub fn verify_sidecars(request: &SidecarRequest) -> FmanResult<SidecarReport> {
+    hash::verify_sidecars(&request.root, request.algorithm, request.require_coverage)
+}
+
+#[derive(Clone)]
+pub struct MirrorRequest {
+    reference: PathBuf,
+    target: PathBuf,
+    attributes: Vec<crate::preserve::Attribute>,
+    filesystem: SharedFs,
+    max_errors: usize,
+    error_log: Option<PathBuf>,
+}
+
+impl MirrorRequest {
+    pub fn new(
+        reference: impl Into<PathBuf>,
+        target: impl Into<PathBuf>,
+        attributes: &[crate::preserve::Attribute],
+    ) -> Self {
+        MirrorRequest {
+            reference: reference.into(),
+            target: target.into(),
+            attributes: attributes.to_vec(),
+            filesystem: real_fs(),
+            max_errors: DEFAULT_MAX_ERRORS,
+            error_log: None,
+        }
+    }
+
+    /// Pass a [`DryRunFs`](crate::fs::DryRunFs) to only list the changes.
+    pub fn fs(mut self, filesystem: SharedFs) -> Self {
+        self.filesystem = filesystem;
+        self
+    }
+
+    /// Keep at most `max` failures for the final error; later ones are only
+    /// counted.
+    pub fn max_errors(mut self, max: usize) -> Self {
+        self.max_errors = max;
+        self
+    }
+
+    /// Append every failure to the file at `path`, however many there are.
+    pub fn error_log(mut self, path: impl Into<PathBuf>) -> Self {
+        self.error_log = Some(path.into());
+        self
+    }
+}
+
+/// Give every entry of the target tree the attributes of its counterpart
+/// in the reference tree.
+pub fn mirror_permissions(request: &MirrorRequest) -> FmanResult<MirrorReport> {
+    mirror::mirror_attributes(
+        &request.reference,
+        &request.target,
+        &request.attributes,
+        request.filesystem.as_ref(),
+        Failures::new(request.max_errors, request.error_log.as_deref())?,
+    )
+}
+
+/// Describe `path` itself, a symlink as a link.
+pub fn file_info(path: &Path) -> FmanResult<FileInfo> {
+    info::file_info(path)
+}
+
+/// Describe the filesystem holding `path`.
+pub fn fs_info(path: &Path) -> FmanResult<FsInfo> {
+    fsinfo::fs_info(path)
+}
+
+/// Repair the dangling symlinks below `root` whose targets one of `rules`
+/// rewrites to an existing path, through `filesystem`. Replacement links
+/// get temporary names from `naming`. What cannot be read is a failure
+/// that does not stop the rest; at most `max_errors` are kept, and all
+/// are appended to `error_log` if given.
+pub fn fix_dangling_links(
+    root: &Path,
+    rules: &[RewriteRule],
+    filesystem: &SharedFs,
+    naming: &NamingContext,
+    max_errors: usize,
+    error_log: Option<&Path>,
+) -> FmanResult<RelinkReport> {
+    let failures = Failures::new(max_errors, error_log)?;
+    relink::fix_dangling(root, rules, filesystem.as_ref(), naming, failures)
+}
+
+/// Move `path` into the trash through `filesystem`, returning its new
+/// location. A name already in the trash gets a suffix from `naming`.
+pub fn trash(path: &Path, filesystem: &SharedFs, naming: &NamingContext) -> FmanResult<PathBuf> {
+    trash::trash_file(path, filesystem, naming)
+}
+
+/// What emptying the trash with `filter` would remove, judged at `now`.
+pub fn plan_trash_empty(filter: &EmptyFilter, now: SystemTime) -> FmanResult<EmptyPlan> {
+    let items = trash::list_items(&trash::trash_root()?)?;
+    Ok(trash::plan_empty(items, filter, now))
+}
+
+/// Carry out `plan` through `filesystem`, returning the bytes reclaimed.
+pub fn empty_trash(plan: &EmptyPlan, filesystem: &SharedFs) -> FmanResult<u64> {
+    trash::execute_empty(plan, filesystem.as_ref())
+}
+
+#[derive(Debug, Clone)]
+pub struct TemplateRequest {
+    template: PathBuf,
+    destination: PathBuf,
+    options: TemplateOptions,
+}
+
+impl TemplateRequest {
+    pub fn new(template: impl Into<PathBuf>, destination: impl Into<PathBuf>) -> Self {
+        TemplateRequest {
+            template: template.into(),
+            destination: destination.into(),
+            options: TemplateOptions::new(),
+        }
+    }
+
+    pub fn options(mut self, options: TemplateOptions) -> Self {
+        self.options = options;
+        self
+    }
+}
+
+/// Instantiate a template directory, substituting its variables.
+pub fn instantiate_template(request: &TemplateRequest) -> FmanResult<TemplateReport> {
+    template::instantiate(&request.template, &request.destination, &request.options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    use crate::fs::{DryRunFs, RecordingFs};
+
+    /// A scratch directory with `a/x` holding "1" and `a/sub/y` holding "22".
+    fn tree() -> TempDir {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir_all(dir.path().join("a/sub")).unwrap();
+        fs::write(dir.path().join("a/x"), "1").unwrap();
+        fs::write(dir.path().join("a/sub/y"), "22").unwrap();
+        dir
+    }
+
+    #[test]
+    fn a_dry_run_sync_reports_without_changing_anything() {
+        let dir = tree();
+        let (a, b) = (dir.path().join("a"), dir.path().join("b"));
+        fs::create_dir(&b).unwrap();
+        let dry_run = Arc::new(DryRunFs::new());
+        let report = sync(&SyncRequest::new(&a, &b).fs(dry_run.clone())).unwrap();
+        assert_eq!(report.actions.len(), 2);
+        assert!(!dry_run.ops().is_empty());
+        assert_eq!(fs::read_dir(&b).unwrap().count(), 0);
+
+        let report = sync(&SyncRequest::new(&a, &b)).unwrap();
+        assert_eq!(report.actions.len(), 2);
+        assert_eq!(fs::read_to_string(b.join("sub/y")).unwrap(), "22");
+    }
+
+    #[test]
+    fn copy_and_move_go_through_the_request_fs() {
+        let dir = tree();
+        let recording = Arc::new(RecordingFs::new());
+        let options = CopyOptions::new().fs(recording.clone());
+        let copied = copy_dir(
+            &CopyRequest::new(dir.path().join("a"), dir.path().join("c")).options(options),
+        )
+        .unwrap();
+        assert_eq!(copied.files, 2);
+        assert!(
+            recording
+                .ops()
+                .iter()
+                .any(|op| matches!(op, FsOp::Copy { .. }))
+        );
+
+        let moved = move_path(
+            &MoveRequest::new(dir.path().join("c"), dir.path().join("m"))
+                .fs(Arc::new(DryRunFs::new())),
+        )
+        .unwrap();
+        assert_eq!(moved.destination, dir.path().join("m"));
+        assert!(dir.path().join("c/x").exists());
+    }
+
+    #[test]
+    fn delete_du_and_hash_return_typed_reports() {
+        let dir = tree();
+        let usage = du(&DuRequest::new(dir.path().join("a"))).unwrap();
+        assert_eq!(usage.total, 3);
+        assert_eq!(usage.entries[0].path, dir.path().join("a/sub"));
+
+        let hashed = hash(&HashRequest::new([dir.path().join("a/x")])).unwrap();
+        assert_eq!(hashed.files.len(), 1);
+        assert_eq!(hashed.files[0].digest.len(), 64);
+
+        let options = DeleteOptions::new().recursive(true);
+        let report = delete(&DeleteRequest::new(dir.path().join("a")).options(options)).unwrap();
+        assert_eq!(report.files, 2);
+        assert!(!dir.path().join("a").exists());
+    }
+}
@@ -0,0 +1,309 @@
+//! Translating file owners between systems whose numeric uids and gids
+//! differ, for restoring a copy made elsewhere.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::error::{FmanError, FmanResult};
+use crate::platform;
+
+/// Old id to new id, separately for users and groups.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OwnershipMap {
+    users: BTreeMap<u32, u32>,
+    groups: BTreeMap<u32, u32>,
+    require_mapped: bool,
+}
+
+/// What [`map_owner`] makes of an owner: the new ids, or `None` where the
+/// map has no entry and the caller's usual policy applies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MappedOwner {
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+/// An id missing from a map that requires every id to be mapped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unmapped {
+    User(u32),
+    Group(u32),
+}
+
+impl Unmapped {
+    /// The error for the owner of `path` being unmapped.
+    pub fn at(self, path: &Path) -> FmanError {
+        FmanError::InvalidInput(format!("{}: {self}", path.display()))
+    }
+}
+
+impl fmt::Display for Unmapped {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Unmapped::User(uid) => write!(f, "uid {uid} is not in the ownership map"),
+            Unmapped::Group(gid) => write!(f, "gid {gid} is not in the ownership map"),
+        }
+    }
+}
+
+/// The file form of a map, as read by [`OwnershipMap::from_file`].
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MapFile {
+    #[serde(default)]
+    users: BTreeMap<String, IdSpec>,
+    #[serde(default)]
+    groups: BTreeMap<String, IdSpec>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum IdSpec {
+    Id(u32),
+    Name(String),
+}
+
+impl OwnershipMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse `OLD_UID:NEW_UID[,OLD_GID:NEW_GID]` and add it to the map.
+    ///
+    /// The old side is numeric, as recorded on the system the files came
+    /// from. The new side may also be a user or group name, resolved on
+    /// this system. Leave the user pair empty (`,100:200`) to map only a
+    /// group.
+    pub fn parse(mut self, spec: &str) -> FmanResult<Self> {
+        let (user, group) = match spec.split_once(',') {
+            Some((user, group)) => (user, Some(group)),
+            None => (spec, None),
+        };
+        if user.is_empty() && group.is_none() {
+            return Err(bad_spec(spec));
+        }
+        if !user.is_empty() {
+            let (old, new) = parse_pair(spec, user)?;
+            self.users.insert(old, resolve(new, Kind::User)?);
+        }
+        if let Some(group) = group {
+            let (old, new) = parse_pair(spec, group)?;
+            self.groups.insert(old, resolve(new, Kind::Group)?);
+        }
+        Ok(self)
+    }
+
+    /// Add the entries of the TOML file at `path`, which has a `[users]`
+    /// and a `[groups]` table of old id to new id or name:
+    ///
+    /// ```toml
+    /// [users]
+    /// 1001 = "deploy"
+    /// 1002 = 2002
+    ///
+    /// [groups]
+    /// 100 = 200
+    /// ```
+    pub fn from_file(mut self, path: &Path) -> FmanResult<Self> {
+        let invalid =
+            |message: String| FmanError::InvalidInput(format!("{}: {message}", path.display()));
+        let text = fs::read_to_string(path).map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => FmanError::missing_path(path),
+            _ => e.into(),
+        })?;
+        let file: MapFile = toml::from_str(&text).map_err(|e| invalid(e.message().to_string()))?;
+        for (table, entries, kind) in [
+            (&mut self.users, file.users, Kind::User),
+            (&mut self.groups, file.groups, Kind::Group),
+        ] {
+            for (old, new) in entries {
+                let old = old
+                    .parse()
+                    .map_err(|_| invalid(format!("{old:?} is not a numeric id")))?;
+                let new = match new {
+                    IdSpec::Id(id) => id,
+                    IdSpec::Name(name) => resolve(&name, kind)?,
+                };
+                table.insert(old, new);
+            }
+        }
+        Ok(self)
+    }
+
+    /// Fail on ids the map has no entry for instead of leaving them to the
+    /// usual policy.
+    pub fn require_mapped(mut self, require: bool) -> Self {
+        self.require_mapped = require;
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.users.is_empty() && self.groups.is_empty()
+    }
+}
+
+/// Translate the owner `uid`:`gid` through `map`.
+pub fn map_owner(uid: u32, gid: u32, map: &OwnershipMap) -> Result<MappedOwner, Unmapped> {
+    let mapped = MappedOwner {
+        uid: map.users.get(&uid).copied(),
+        gid: map.groups.get(&gid).copied(),
+    };
+    if map.require_mapped {
+        if mapped.uid.is_none() {
+            return Err(Unmapped::User(uid));
+        }
+        if mapped.gid.is_none() {
+            return Err(Unmapped::Group(gid));
+        }
+    }
+    Ok(mapped)
+}
+
+fn bad_spec(spec: &str) -> FmanError {
+    FmanError::InvalidInput(format!(
+        "bad ownership map '{spec}'; expected OLD_UID:NEW_UID[,OLD_GID:NEW_GID]"
+    ))
+}
+
+fn parse_pair<'a>(spec: &str, pair: &'a str) -> FmanResult<(u32, &'a str)> {
+    let bad = || bad_spec(spec);
+    let (old, new) = pair.split_once(':').ok_or_else(bad)?;
+    let old = old.trim().parse().map_err(|_| bad())?;
+    let new = new.trim();
+    if new.is_empty() {
+        return Err(bad());
+    }
+    Ok((old, new))
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    User,
+    Group,
+}
+
+/// `id` as a number, or else looked up as a user or group name.
+fn resolve(id: &str, kind: Kind) -> FmanResult<u32> {
+    if let Ok(id) = id.parse() {
+        return Ok(id);
+    }
+    let (found, what) = match kind {
+        Kind::User => (platform::user_id(id)?, "user"),
+        Kind::Group => (platform::group_id(id)?, "group"),
+    };
+    found.ok_or_else(|| FmanError::not_found(format!("{what} '{id}'")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    fn map(specs: &[&str]) -> OwnershipMap {
+        specs
+            .iter()
+            .try_fold(OwnershipMap::new(), |map, spec| map.parse(spec))
+            .unwrap()
+    }
+
+    fn owner(uid: Option<u32>, gid: Option<u32>) -> MappedOwner {
+        MappedOwner { uid, gid }
+    }
+
+    #[test]
+    fn mapped_ids_are_translated() {
+        let map = map(&["1001:2001,100:200"]);
+        assert_eq!(map_owner(1001, 100, &map), Ok(owner(Some(2001), Some(200))));
+    }
+
+    #[test]
+    fn unmapped_ids_are_left_to_the_usual_policy() {
+        let map = map(&["1001:2001"]);
+        assert_eq!(map_owner(1001, 100, &map), Ok(owner(Some(2001), None)));
+        assert_eq!(map_owner(7, 100, &map), Ok(owner(None, None)));
+        assert_eq!(map_owner(7, 8, &OwnershipMap::new()), Ok(owner(None, None)));
+    }
+
+    #[test]
+    fn require_mapped_rejects_either_unmapped_id() {
+        let map = map(&["1001:2001,100:200"]).require_mapped(true);
+        assert!(map_owner(1001, 100, &map).is_ok());
+        assert_eq!(map_owner(1002, 100, &map), Err(Unmapped::User(1002)));
+        assert_eq!(map_owner(1001, 101, &map), Err(Unmapped::Group(101)));
+        let err = Unmapped::Group(101).at(Path::new("f"));
+        assert_eq!(
+            err.to_string(),
+            "invalid input: f: gid 101 is not in the ownership map"
+        );
+    }
+
+    #[test]
+    fn specs_accumulate_and_may_map_only_a_group() {
+        let map = map(&["1:2", ",10:20", "3:4,30:40"]);
+        assert_eq!(map_owner(1, 10, &map), Ok(owner(Some(2), Some(20))));
+        assert_eq!(map_owner(3, 30, &map), Ok(owner(Some(4), Some(40))));
+        assert!(!map.is_empty());
+        assert!(OwnershipMap::new().is_empty());
+    }
+
+    #[test]
+    fn new_ids_may_be_names_on_this_system() {
+        let map = map(&["1001:root,100:root"]);
+        assert_eq!(map_owner(1001, 100, &map), Ok(owner(Some(0), Some(0))));
+        let err = OwnershipMap::new()
+            .parse("1001:no-such-user-here")
+            .unwrap_err();
+        assert!(err.is_not_found(), "{err:?}");
+    }
+
+    #[test]
+    fn bad_specs_are_rejected() {
+        for spec in ["", "1001", "x:1", "1:", "1:2,", "1:2,3", "-1:2"] {
+            assert!(
+                matches!(
+                    OwnershipMap::new().parse(spec),
+                    Err(FmanError::InvalidInput(_))
+                ),
+                "{spec:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn a_map_file_has_users_and_groups() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("map.toml");
+        fs::write(
+            &path,
+            "[users]\n1001 = \"root\"\n1002 = 2002\n\n[groups]\n100 = 200\n",
+        )
+        .unwrap();
+        let map = OwnershipMap::new().from_file(&path).unwrap();
+        assert_eq!(map_owner(1001, 100, &map), Ok(owner(Some(0), Some(200))));
+        assert_eq!(map_owner(1002, 1, &map), Ok(owner(Some(2002), None)));
+    }
+
+    #[test]
+    fn a_bad_map_file_is_named() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("map.toml");
+        for text in ["[users]\nalice = 1\n", "[people]\n1 = 2\n", "[users\n"] {
+            fs::write(&path, text).unwrap();
+            let err = OwnershipMap::new().from_file(&path).unwrap_err();
+            assert!(
+                matches!(&err, FmanError::InvalidInput(m) if m.contains("map.toml")),
+                "{text:?}: {err:?}"
+            );
+        }
+        let err = OwnershipMap::new()
+            .from_file(&dir.path().join("missing.toml"))
+            .unwrap_err();
+        assert!(err.is_not_found());
+    }
+}
@@ -0,0 +1,636 @@
+//! Platform-specific filesystem operations.
+
+use std::fs::{File, FileType, Metadata, Permissions};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Set the filesystem immutable attribute (`chattr +i`) on an open file.
+///
+/// Needs `CAP_LINUX_IMMUTABLE`; without it the error kind is
+/// `PermissionDenied`. Other platforms return `Unsupported`.
+#[cfg(target_os = "linux")]
+pub fn set_immutable(file: &File) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    const FS_IMMUTABLE_FL: libc::c_int = 0x0000_0010;
+
+    let fd = file.as_raw_fd();
+    let mut flags: libc::c_int = 0;
+    // SAFETY: `fd` is open for the duration of both calls and `flags` is a
+    // valid int for the kernel to read and write.
+    unsafe {
+        if libc::ioctl(fd, libc::FS_IOC_GETFLAGS, &mut flags) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        flags |= FS_IMMUTABLE_FL;
+        if libc::ioctl(fd, libc::FS_IOC_SETFLAGS, &flags) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn set_immutable(_file: &File) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "the immutable attribute is only supported on Linux",
+    ))
+}
+
+/// Size and state of the filesystem holding a path, in bytes.
+#[derive(Debug, Clone, Copy)]
+pub struct SpaceUsage {
+    pub total: u64,
+    /// Free blocks, including those reserved for the superuser.
+    pub free: u64,
+    /// Free to unprivileged users.
+    pub available: u64,
+    pub read_only: bool,
+}
+
+#[cfg(unix)]
+pub fn space_usage(path: &Path) -> io::Result<SpaceUsage> {
+    let c_path = c_path(path)?;
+    // SAFETY: statvfs is plain data, fully written by the call on success.
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let block = stat.f_frsize as u64;
+    Ok(SpaceUsage {
+        total: stat.f_blocks as u64 * block,
+        free: stat.f_bfree as u64 * block,
+        available: stat.f_bavail as u64 * block,
+        read_only: stat.f_flag & libc::ST_RDONLY != 0,
+    })
+}
+
+#[cfg(not(unix))]
+pub fn space_usage(_path: &Path) -> io::Result<SpaceUsage> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "filesystem usage is only available on Unix",
+    ))
+}
+
+/// Mount point, filesystem type and source device of the filesystem
+/// holding `path`, as `statfs` reports them.
+#[cfg(target_os = "macos")]
+pub fn mount_of(path: &Path) -> io::Result<(std::path::PathBuf, String, String)> {
+    use std::ffi::CStr;
+
+    let c_path = c_path(path)?;
+    // SAFETY: statfs is plain data, fully written by the call on success,
+    // and its name fields are NUL-terminated.
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let text = |field: &[libc::c_char]| {
+        unsafe { CStr::from_ptr(field.as_ptr()) }
+            .to_string_lossy()
+            .into_owned()
+    };
+    Ok((
+        text(&stat.f_mntonname).into(),
+        text(&stat.f_fstypename),
+        text(&stat.f_mntfromname),
+    ))
+}
+
+/// Make `dst` share `src`'s data blocks instead of copying them
+/// (`FICLONE`, on Btrfs, XFS and similar). Fails with `Unsupported` or an
+/// OS error where the filesystem cannot.
+#[cfg(target_os = "linux")]
+pub fn reflink(src: &File, dst: &File) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    // SAFETY: both descriptors are open for the duration of the call.
+    if unsafe { libc::ioctl(dst.as_raw_fd(), libc::FICLONE, src.as_raw_fd()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Whether a failed [`reflink`] means the filesystem, or the pair of
+/// files, cannot share blocks at all, rather than that something went
+/// wrong trying.
+#[cfg(target_os = "linux")]
+pub fn reflink_unsupported(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::Unsupported
+        || matches!(
+            e.raw_os_error(),
+            Some(libc::EOPNOTSUPP | libc::EXDEV | libc::EINVAL | libc::ENOTTY | libc::ENOSYS)
+        )
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn reflink_unsupported(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::Unsupported
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn reflink(_src: &File, _dst: &File) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "reflinks are only supported on Linux",
+    ))
+}
+
+/// Where the next data at or after `offset` in `file` starts
+/// (`SEEK_DATA`), or `None` if only a hole follows up to the end. Moves the
+/// file's offset. Fails where the filesystem or file type cannot tell.
+#[cfg(target_os = "linux")]
+pub fn next_data(file: &File, offset: u64) -> io::Result<Option<u64>> {
+    use std::os::fd::AsRawFd;
+
+    let offset = libc::off_t::try_from(offset)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    // SAFETY: the descriptor is open for the duration of the call.
+    let data = unsafe { libc::lseek(file.as_raw_fd(), offset, libc::SEEK_DATA) };
+    if data < 0 {
+        let e = io::Error::last_os_error();
+        return match e.raw_os_error() {
+            Some(libc::ENXIO) => Ok(None),
+            _ => Err(e),
+        };
+    }
+    Ok(Some(data as u64))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn next_data(_file: &File, _offset: u64) -> io::Result<Option<u64>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "finding holes is only supported on Linux",
+    ))
+}
+
+/// `path`, as returned by `canonicalize`, without the verbatim prefix
+/// Windows adds, where [`verbatim_plain_form`] finds a plain form that
+/// means the same. Longer paths keep the prefix, without which most
+/// programs could not open them.
+/// Elsewhere `path` is returned as it is.
+#[cfg(windows)]
+pub fn simplify_verbatim(path: PathBuf) -> PathBuf {
+    match path.to_str().and_then(verbatim_plain_form) {
+        Some(plain) => PathBuf::from(plain),
+        None => path,
+    }
+}
+
+/// The plain form of the verbatim Windows path `text` (`\\?\C:\dir`
+/// becomes `C:\dir`, `\\?\UNC\server\share` becomes `\\server\share`),
+/// or `None` if it has no verbatim prefix or the plain form would mean
+/// something else: it is at least `MAX_PATH` long, or has a component
+/// Windows would reinterpret, such as `NUL` or one ending in a dot.
+#[cfg_attr(not(any(windows, test)), allow(dead_code))]
+fn verbatim_plain_form(text: &str) -> Option<String> {
+    const MAX_PATH: usize = 260;
+    const RESERVED: &[&str] = &[
+        "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+        "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+    ];
+
+    let plain = if let Some(rest) = text.strip_prefix(r"\\?\UNC\") {
+        format!(r"\\{rest}")
+    } else if let Some(rest) = text.strip_prefix(r"\\?\")
+        && rest.as_bytes().get(1) == Some(&b':')
+    {
+        rest.to_string()
+    } else {
+        return None;
+    };
+    let ambiguous = plain.split('\\').any(|component| {
+        let stem = component.split('.').next().unwrap_or(component);
+        component.ends_with(['.', ' '])
+            || component.contains('/')
+            || RESERVED.iter().any(|name| stem.eq_ignore_ascii_case(name))
+    });
+    (plain.len() < MAX_PATH && !ambiguous).then_some(plain)
+}
+
+#[cfg(not(windows))]
+pub fn simplify_verbatim(path: PathBuf) -> PathBuf {
+    path
+}
+
+/// This machine's host name, or `localhost` if it cannot be determined.
+#[cfg(unix)]
+pub fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    // SAFETY: `buf` is valid for `buf.len()` bytes; the name is
+    // NUL-terminated on success (the last byte stays 0 if truncated).
+    let ok = unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len() - 1) } == 0;
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(0);
+    if !ok || len == 0 {
+        return "localhost".to_string();
+    }
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+#[cfg(not(unix))]
+pub fn hostname() -> String {
+    std::env::var("COMPUTERNAME")
+        .ok()
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "localhost".to_string())
+}
+
+/// Whether `file` is on a pseudo filesystem (procfs, sysfs and the like)
+/// whose files report a size that has nothing to do with the data read
+/// from them, usually 0.
+#[cfg(target_os = "linux")]
+pub fn stat_size_unreliable(file: &File) -> bool {
+    use std::os::fd::AsRawFd;
+
+    const PSEUDO_FILESYSTEMS: [i64; 9] = [
+        0x9fa0,      // proc
+        0x6265_6572, // sysfs
+        0x6462_6720, // debugfs
+        0x7472_6163, // tracefs
+        0x7363_6673, // securityfs
+        0x6265_6570, // configfs
+        0x0027_e0eb, // cgroup
+        0x6367_7270, // cgroup2
+        0x6165_676c, // pstore
+    ];
+    let mut stat = std::mem::MaybeUninit::<libc::statfs>::uninit();
+    // SAFETY: the descriptor is open for the life of `file` and `stat` is
+    // valid for writes of a `statfs`.
+    if unsafe { libc::fstatfs(file.as_raw_fd(), stat.as_mut_ptr()) } != 0 {
+        return false;
+    }
+    // SAFETY: fstatfs succeeded, so it filled in `stat`.
+    let f_type = unsafe { stat.assume_init() }.f_type;
+    // f_type is narrower than i64 on some targets.
+    #[allow(clippy::useless_conversion)]
+    PSEUDO_FILESYSTEMS.contains(&i64::from(f_type))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn stat_size_unreliable(_file: &File) -> bool {
+    false
+}
+
+/// Whether `file_type` is a named pipe.
+#[cfg(unix)]
+pub fn is_fifo(file_type: FileType) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+    file_type.is_fifo()
+}
+
+#[cfg(not(unix))]
+pub fn is_fifo(_file_type: FileType) -> bool {
+    false
+}
+
+/// Whether this process may list the directory `dir` and add or remove
+/// entries in it.
+#[cfg(unix)]
+pub fn can_modify_dir(dir: &Path) -> bool {
+    let Ok(c_path) = c_path(dir) else {
+        return false;
+    };
+    // SAFETY: `c_path` is a valid NUL-terminated string.
+    unsafe { libc::access(c_path.as_ptr(), libc::R_OK | libc::W_OK | libc::X_OK) == 0 }
+}
+
+#[cfg(not(unix))]
+pub fn can_modify_dir(_dir: &Path) -> bool {
+    true
+}
+
+/// `meta`'s permissions with owner read, write and search added (the
+/// read-only flag cleared off Unix).
+#[cfg(unix)]
+pub fn owner_rwx(meta: &Metadata) -> Permissions {
+    use std::os::unix::fs::PermissionsExt;
+    let mut permissions = meta.permissions();
+    permissions.set_mode(permissions.mode() | 0o700);
+    permissions
+}
+
+#[cfg(not(unix))]
+pub fn owner_rwx(meta: &Metadata) -> Permissions {
+    let mut permissions = meta.permissions();
+    permissions.set_readonly(false);
+    permissions
+}
+
+/// Extended attributes of `path` itself (not a link's target), sorted by
+/// name. Empty where the platform has none.
+#[cfg(target_os = "linux")]
+pub fn list_xattrs(path: &Path) -> io::Result<Vec<(String, Vec<u8>)>> {
+    let c_path = c_path(path)?;
+    // SAFETY: a null buffer of size 0 asks for the required length.
+    let len = unsafe { libc::llistxattr(c_path.as_ptr(), std::ptr::null_mut(), 0) };
+    if len < 0 {
+        return match io::Error::last_os_error() {
+            e if e.raw_os_error() == Some(libc::ENOTSUP) => Ok(Vec::new()),
+            e => Err(e),
+        };
+    }
+    let mut names = vec![0u8; len as usize];
+    // SAFETY: `names` is valid for `names.len()` bytes.
+    let len = unsafe { libc::llistxattr(c_path.as_ptr(), names.as_mut_ptr().cast(), names.len()) };
+    if len < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    names.truncate(len as usize);
+
+    let mut attrs = Vec::new();
+    for name in names.split(|&b| b == 0).filter(|n| !n.is_empty()) {
+        let c_name = std::ffi::CString::new(name).map_err(io::Error::other)?;
+        // SAFETY: as above, first the length, then the value into a buffer
+        // of that length.
+        let size =
+            unsafe { libc::lgetxattr(c_path.as_ptr(), c_name.as_ptr(), std::ptr::null_mut(), 0) };
+        if size < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut value = vec![0u8; size as usize];
+        let size = unsafe {
+            libc::lgetxattr(
+                c_path.as_ptr(),
+                c_name.as_ptr(),
+                value.as_mut_ptr().cast(),
+                value.len(),
+            )
+        };
+        if size < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        value.truncate(size as usize);
+        attrs.push((String::from_utf8_lossy(name).into_owned(), value));
+    }
+    attrs.sort();
+    Ok(attrs)
+}
+
+/// The value of the extended attribute `name` of `path` itself, or `None`
+/// if it has none by that name.
+#[cfg(target_os = "linux")]
+pub fn get_xattr(path: &Path, name: &str) -> io::Result<Option<Vec<u8>>> {
+    let (c_path, c_name) = (c_path(path)?, c_name(name)?);
+    // SAFETY: a null buffer of size 0 asks for the required length, then
+    // the value is read into a buffer of that length.
+    let size =
+        unsafe { libc::lgetxattr(c_path.as_ptr(), c_name.as_ptr(), std::ptr::null_mut(), 0) };
+    if size < 0 {
+        return match io::Error::last_os_error() {
+            e if e.raw_os_error() == Some(libc::ENODATA) => Ok(None),
+            e => Err(e),
+        };
+    }
+    let mut value = vec![0u8; size as usize];
+    let size = unsafe {
+        libc::lgetxattr(
+            c_path.as_ptr(),
+            c_name.as_ptr(),
+            value.as_mut_ptr().cast(),
+            value.len(),
+        )
+    };
+    if size < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    value.truncate(size as usize);
+    Ok(Some(value))
+}
+
+#[cfg(target_os = "linux")]
+pub fn set_xattr(path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+    let (c_path, c_name) = (c_path(path)?, c_name(name)?);
+    // SAFETY: all pointers are valid for the lengths passed.
+    let rc = unsafe {
+        libc::lsetxattr(
+            c_path.as_ptr(),
+            c_name.as_ptr(),
+            value.as_ptr().cast(),
+            value.len(),
+            0,
+        )
+    };
+    if rc != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+pub fn remove_xattr(path: &Path, name: &str) -> io::Result<()> {
+    let (c_path, c_name) = (c_path(path)?, c_name(name)?);
+    // SAFETY: both strings are NUL-terminated.
+    if unsafe { libc::lremovexattr(c_path.as_ptr(), c_name.as_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn c_path(path: &Path) -> io::Result<std::ffi::CString> {
+    use std::os::unix::ffi::OsStrExt;
+    std::ffi::CString::new(path.as_os_str().as_bytes()).map_err(io::Error::other)
+}
+
+#[cfg(target_os = "linux")]
+fn c_name(name: &str) -> io::Result<std::ffi::CString> {
+    std::ffi::CString::new(name).map_err(io::Error::other)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn list_xattrs(_path: &Path) -> io::Result<Vec<(String, Vec<u8>)>> {
+    Ok(Vec::new())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn get_xattr(_path: &Path, _name: &str) -> io::Result<Option<Vec<u8>>> {
+    Err(xattr_unsupported())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn set_xattr(_path: &Path, _name: &str, _value: &[u8]) -> io::Result<()> {
+    Err(xattr_unsupported())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn remove_xattr(_path: &Path, _name: &str) -> io::Result<()> {
+    Err(xattr_unsupported())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn xattr_unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "extended attributes are only supported on Linux",
+    )
+}
+
+/// The uid of the user called `name` on this system, if there is one.
+#[cfg(unix)]
+pub fn user_id(name: &str) -> io::Result<Option<u32>> {
+    let c_name = std::ffi::CString::new(name).map_err(io::Error::other)?;
+    let mut buf = vec![0 as libc::c_char; 16 * 1024];
+    // SAFETY: passwd is plain data, filled in by the call on success.
+    let mut entry: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut found = std::ptr::null_mut();
+    // SAFETY: every pointer is valid for the duration of the call and
+    // `buf.len()` is the size of `buf`.
+    let rc = unsafe {
+        libc::getpwnam_r(
+            c_name.as_ptr(),
+            &mut entry,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut found,
+        )
+    };
+    if rc != 0 {
+        return Err(io::Error::from_raw_os_error(rc));
+    }
+    Ok((!found.is_null()).then_some(entry.pw_uid))
+}
+
+/// The gid of the group called `name` on this system, if there is one.
+#[cfg(unix)]
+pub fn group_id(name: &str) -> io::Result<Option<u32>> {
+    let c_name = std::ffi::CString::new(name).map_err(io::Error::other)?;
+    let mut buf = vec![0 as libc::c_char; 16 * 1024];
+    // SAFETY: group is plain data, filled in by the call on success.
+    let mut entry: libc::group = unsafe { std::mem::zeroed() };
+    let mut found = std::ptr::null_mut();
+    // SAFETY: as for getpwnam_r above.
+    let rc = unsafe {
+        libc::getgrnam_r(
+            c_name.as_ptr(),
+            &mut entry,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut found,
+        )
+    };
+    if rc != 0 {
+        return Err(io::Error::from_raw_os_error(rc));
+    }
+    Ok((!found.is_null()).then_some(entry.gr_gid))
+}
+
+#[cfg(not(unix))]
+pub fn user_id(_name: &str) -> io::Result<Option<u32>> {
+    Err(names_unsupported())
+}
+
+#[cfg(not(unix))]
+pub fn group_id(_name: &str) -> io::Result<Option<u32>> {
+    Err(names_unsupported())
+}
+
+#[cfg(not(unix))]
+fn names_unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "user and group names can only be resolved on Unix",
+    )
+}
+
+/// Change the owner and/or group of an open file.
+#[cfg(unix)]
+pub fn set_file_owner(file: &File, uid: Option<u32>, gid: Option<u32>) -> io::Result<()> {
+    std::os::unix::fs::fchown(file, uid, gid)
+}
+
+#[cfg(not(unix))]
+pub fn set_file_owner(_file: &File, _uid: Option<u32>, _gid: Option<u32>) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "ownership can only be changed on Unix",
+    ))
+}
+
+/// Id of the filesystem `meta` was read from; 0 where there is none.
+#[cfg(unix)]
+pub fn device_id(meta: &Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    meta.dev()
+}
+
+#[cfg(not(unix))]
+pub fn device_id(_meta: &Metadata) -> u64 {
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verbatim_drive_and_unc_paths_lose_their_prefix() {
+        assert_eq!(
+            verbatim_plain_form(r"\\?\C:\Users\me\file.txt").as_deref(),
+            Some(r"C:\Users\me\file.txt")
+        );
+        assert_eq!(verbatim_plain_form(r"\\?\C:\").as_deref(), Some(r"C:\"));
+        assert_eq!(
+            verbatim_plain_form(r"\\?\UNC\server\share\dir").as_deref(),
+            Some(r"\\server\share\dir")
+        );
+    }
+
+    #[test]
+    fn paths_without_a_verbatim_prefix_are_left_alone() {
+        for text in [
+            r"C:\dir",
+            r"\\server\share",
+            r"\\?\Volume{1234}\dir",
+            r"\\.\C:\dir",
+            "/home/me",
+        ] {
+            assert_eq!(verbatim_plain_form(text), None, "{text}");
+        }
+    }
+
+    #[test]
+    fn a_prefix_that_changes_the_meaning_is_kept() {
+        let long = format!(r"\\?\C:\{}", "d\\".repeat(130));
+        assert_eq!(verbatim_plain_form(&long), None);
+        let just_short = format!(r"\\?\C:\{}", "x".repeat(256));
+        assert_eq!(verbatim_plain_form(&just_short).map(|p| p.len()), Some(259));
+        for text in [
+            r"\\?\C:\dir\NUL",
+            r"\\?\C:\dir\com1.txt",
+            r"\\?\C:\trailing.",
+            r"\\?\C:\trailing ",
+            r"\\?\C:\a/b",
+            r"\\?\UNC\server\share\aux",
+        ] {
+            assert_eq!(verbatim_plain_form(text), None, "{text}");
+        }
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn canonical_paths_are_simplified_on_windows() {
+        assert_eq!(
+            simplify_verbatim(PathBuf::from(r"\\?\C:\dir")),
+            PathBuf::from(r"C:\dir")
+        );
+        assert_eq!(
+            simplify_verbatim(PathBuf::from(r"\\?\C:\dir\NUL")),
+            PathBuf::from(r"\\?\C:\dir\NUL")
+        );
+        let dir = tempfile::TempDir::new().unwrap();
+        let canonical = dir.path().canonicalize().unwrap();
+        let simplified = simplify_verbatim(canonical.clone());
+        assert!(!simplified.to_string_lossy().starts_with(r"\\?\"));
+        assert_eq!(simplified.canonicalize().unwrap(), canonical);
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn paths_are_untouched_elsewhere() {
+        let path = PathBuf::from(r"\\?\C:\dir");
+        assert_eq!(simplify_verbatim(path.clone()), path);
+    }
+}
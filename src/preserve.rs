@@ -0,0 +1,268 @@
+//! File attributes other than contents: reading them from one path and
+//! applying them to another.
+
+use std::fs::{self, Metadata};
+use std::io;
+use std::path::Path;
+use std::time::SystemTime;
+
+#[cfg(feature = "json")]
+use serde::Serialize;
+
+use crate::error::{FmanError, FmanResult};
+use crate::format;
+use crate::fs::Fs;
+use crate::metadata::MetadataPolicy;
+use crate::ownership::{self, MappedOwner, OwnershipMap, Unmapped};
+use crate::platform;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "json", derive(Serialize), serde(rename_all = "kebab-case"))]
+pub enum Attribute {
+    /// Permission bits (the read-only flag off Unix).
+    Mode,
+    /// Owner and group (Unix).
+    Ownership,
+    /// Access and modification times.
+    Times,
+    /// Extended attributes (Linux).
+    Xattr,
+}
+
+impl Attribute {
+    pub const ALL: [Attribute; 4] = [
+        Attribute::Mode,
+        Attribute::Ownership,
+        Attribute::Times,
+        Attribute::Xattr,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Attribute::Mode => "mode",
+            Attribute::Ownership => "ownership",
+            Attribute::Times => "times",
+            Attribute::Xattr => "xattr",
+        }
+    }
+
+    /// Parse a comma-separated list such as `mode,times`, or `all`.
+    pub fn parse_list(list: &str) -> FmanResult<Vec<Attribute>> {
+        let mut attributes = Vec::new();
+        for name in list.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            if name == "all" {
+                attributes.extend(Attribute::ALL);
+                continue;
+            }
+            let attribute = Attribute::ALL
+                .into_iter()
+                .find(|a| a.as_str() == name)
+                .ok_or_else(|| {
+                    FmanError::InvalidInput(format!(
+                        "unknown attribute {name:?} (expected mode, ownership, times, xattr or all)"
+                    ))
+                })?;
+            attributes.push(attribute);
+        }
+        attributes.sort();
+        attributes.dedup();
+        Ok(attributes)
+    }
+}
+
+/// One attribute that differs, rendered for display.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(Serialize))]
+pub struct AttributeChange {
+    pub attribute: Attribute,
+    pub before: String,
+    pub after: String,
+}
+
+/// Give `target` the `attributes` of `reference` through `filesystem`,
+/// returning what differed. Both paths are used as they are, without
+/// following symlinks; only ownership is applied to a symlink.
+///
+/// Ownership goes first since changing it can clear set-id bits, and times
+/// last since the other changes could disturb them.
+pub fn apply(
+    reference: &Path,
+    target: &Path,
+    attributes: &[Attribute],
+    filesystem: &dyn Fs,
+) -> FmanResult<Vec<AttributeChange>> {
+    apply_mapped(
+        reference,
+        target,
+        attributes,
+        &OwnershipMap::new(),
+        &MetadataPolicy::strict(),
+        filesystem,
+    )
+}
+
+/// [`apply`], translating the reference's owner through `owners`; ids the
+/// map has no entry for are copied as they are. Failures to set an
+/// attribute go through `policy`, and what it lets pass is left out of
+/// the changes.
+pub fn apply_mapped(
+    reference: &Path,
+    target: &Path,
+    attributes: &[Attribute],
+    owners: &OwnershipMap,
+    policy: &MetadataPolicy,
+    filesystem: &dyn Fs,
+) -> FmanResult<Vec<AttributeChange>> {
+    let (want, have) = (
+        fs::symlink_metadata(reference)?,
+        fs::symlink_metadata(target)?,
+    );
+    let is_link = have.is_symlink();
+    let device = platform::device_id(&have);
+    let attempt = |attribute, apply: &dyn Fn() -> io::Result<()>| {
+        policy.attempt(device, attribute, target, apply)
+    };
+    let mut changes = Vec::new();
+
+    if attributes.contains(&Attribute::Ownership)
+        && let (Some((uid, gid)), Some(have_owner)) = (owner(&want), owner(&have))
+    {
+        let mapped = ownership::map_owner(uid, gid, owners).map_err(|e| e.at(reference))?;
+        let want_owner = (mapped.uid.unwrap_or(uid), mapped.gid.unwrap_or(gid));
+        if want_owner != have_owner
+            && attempt(Attribute::Ownership, &|| {
+                filesystem.set_owner(target, Some(want_owner.0), Some(want_owner.1))
+            })?
+        {
+            changes.push(AttributeChange {
+                attribute: Attribute::Ownership,
+                before: format!("{}:{}", have_owner.0, have_owner.1),
+                after: format!("{}:{}", want_owner.0, want_owner.1),
+            });
+        }
+    }
+
+    if attributes.contains(&Attribute::Mode)
+        && !is_link
+        && want.permissions() != have.permissions()
+        && attempt(Attribute::Mode, &|| {
+            filesystem.set_permissions(target, want.permissions())
+        })?
+    {
+        changes.push(AttributeChange {
+            attribute: Attribute::Mode,
+            before: describe_mode(&have),
+            after: describe_mode(&want),
+        });
+    }
+
+    if attributes.contains(&Attribute::Xattr) && !is_link {
+        let want_xattrs = platform::list_xattrs(reference)?;
+        let have_xattrs = platform::list_xattrs(target)?;
+        for (name, value) in &want_xattrs {
+            let current = have_xattrs.iter().find(|(n, _)| n == name).map(|(_, v)| v);
+            if current != Some(value)
+                && attempt(Attribute::Xattr, &|| {
+                    filesystem.set_xattr(target, name, value)
+                })?
+            {
+                changes.push(AttributeChange {
+                    attribute: Attribute::Xattr,
+                    before: describe_xattr(name, current.map(Vec::as_slice)),
+                    after: describe_xattr(name, Some(value)),
+                });
+            }
+        }
+        for (name, value) in &have_xattrs {
+            if !want_xattrs.iter().any(|(n, _)| n == name)
+                && attempt(Attribute::Xattr, &|| filesystem.remove_xattr(target, name))?
+            {
+                changes.push(AttributeChange {
+                    attribute: Attribute::Xattr,
+                    before: describe_xattr(name, Some(value)),
+                    after: describe_xattr(name, None),
+                });
+            }
+        }
+    }
+
+    if attributes.contains(&Attribute::Times) && !is_link {
+        let (want_times, have_times) = (times(&want)?, times(&have)?);
+        if want_times != have_times
+            && attempt(Attribute::Times, &|| {
+                filesystem.set_times(target, Some(want_times.0), Some(want_times.1))
+            })?
+        {
+            changes.push(AttributeChange {
+                attribute: Attribute::Times,
+                before: describe_times(have_times),
+                after: describe_times(want_times),
+            });
+        }
+    }
+    Ok(changes)
+}
+
+/// The owner of `reference` translated through `owners`; nothing is
+/// mapped off Unix.
+pub fn mapped_owner(reference: &Metadata, owners: &OwnershipMap) -> Result<MappedOwner, Unmapped> {
+    match owner(reference) {
+        Some((uid, gid)) => ownership::map_owner(uid, gid, owners),
+        None => Ok(MappedOwner {
+            uid: None,
+            gid: None,
+        }),
+    }
+}
+
+#[cfg(unix)]
+fn owner(meta: &Metadata) -> Option<(u32, u32)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((meta.uid(), meta.gid()))
+}
+
+#[cfg(not(unix))]
+fn owner(_meta: &Metadata) -> Option<(u32, u32)> {
+    None
+}
+
+#[cfg(unix)]
+fn describe_mode(meta: &Metadata) -> String {
+    use std::os::unix::fs::PermissionsExt;
+    format!("{:04o}", meta.permissions().mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+fn describe_mode(meta: &Metadata) -> String {
+    if meta.permissions().readonly() {
+        "read-only".to_string()
+    } else {
+        "writable".to_string()
+    }
+}
+
+fn times(meta: &Metadata) -> FmanResult<(SystemTime, SystemTime)> {
+    Ok((meta.accessed()?, meta.modified()?))
+}
+
+fn describe_times((accessed, modified): (SystemTime, SystemTime)) -> String {
+    format!(
+        "atime {} mtime {}",
+        describe_time(accessed),
+        describe_time(modified)
+    )
+}
+
+fn describe_time(time: SystemTime) -> String {
+    let nanos = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.subsec_nanos());
+    format!("{}.{nanos:09}", format::format_time(time, "%F %T"))
+}
+
+fn describe_xattr(name: &str, value: Option<&[u8]>) -> String {
+    match value {
+        Some(value) => format!("{name}={:?}", String::from_utf8_lossy(value)),
+        None => format!("{name} unset"),
+    }
+}
@@ -0,0 +1,386 @@
+//! Repairing symlinks left dangling after what they pointed into moved.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "json")]
+use serde::Serialize;
+
+use crate::error::{Failures, FmanError, FmanResult};
+use crate::fs::Fs;
+use crate::naming::NamingContext;
+use crate::validate::{ensure_exists, ensure_is_dir};
+use crate::walk::{SymlinkPolicy, Walk};
+
+/// `OLD=NEW`: a link target under `OLD` is pointed at the same place under
+/// `NEW` instead.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RewriteRule {
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+
+impl RewriteRule {
+    /// Parse `OLD=NEW`, split at the first `=`.
+    pub fn parse(spec: &str) -> FmanResult<Self> {
+        match spec.split_once('=') {
+            Some((from, to)) if !from.is_empty() && !to.is_empty() => Ok(RewriteRule {
+                from: PathBuf::from(from),
+                to: PathBuf::from(to),
+            }),
+            _ => Err(FmanError::InvalidInput(format!(
+                "bad rewrite rule '{spec}'; expected OLD=NEW"
+            ))),
+        }
+    }
+
+    /// `target` with the `from` prefix replaced by `to`, if it starts with
+    /// `from`. Whole components are compared, so `/old` does not match
+    /// `/older`. The target is taken as written, without resolving it.
+    pub fn apply(&self, target: &Path) -> Option<PathBuf> {
+        let rest = target.strip_prefix(&self.from).ok()?;
+        if rest.as_os_str().is_empty() {
+            return Some(self.to.clone());
+        }
+        Some(self.to.join(rest))
+    }
+}
+
+/// A link pointed somewhere that exists again.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "json", derive(Serialize))]
+pub struct Relinked {
+    pub link: PathBuf,
+    pub old_target: PathBuf,
+    pub new_target: PathBuf,
+}
+
+/// A dangling link left as it was.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "json", derive(Serialize))]
+pub struct Unfixed {
+    pub link: PathBuf,
+    pub target: PathBuf,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "json", derive(Serialize))]
+pub struct RelinkReport {
+    /// Symlinks looked at, dangling or not.
+    pub links: u64,
+    pub fixed: Vec<Relinked>,
+    pub unfixed: Vec<Unfixed>,
+}
+
+/// Find the dangling symlinks below `root` and point each at the target
+/// the first matching rule gives it, through `filesystem`.
+///
+/// A relative target is resolved against the link's directory to tell
+/// whether it dangles, but rewritten as written. A link is only replaced
+/// when its new target exists; it is replaced atomically, by creating the
+/// new link under a temporary name from `naming` and renaming it over the
+/// old one. Links no rule matches, or whose new target is missing too, are
+/// reported as unfixed. A directory or link that cannot be read goes into
+/// `failures` and the walk carries on.
+pub fn fix_dangling(
+    root: &Path,
+    rules: &[RewriteRule],
+    filesystem: &dyn Fs,
+    naming: &NamingContext,
+    mut failures: Failures,
+) -> FmanResult<RelinkReport> {
+    ensure_exists(root)?;
+    ensure_is_dir(root)?;
+    let mut report = RelinkReport::default();
+    for entry in Walk::new(root).symlinks(SymlinkPolicy::Never) {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                failures.push(root, e)?;
+                continue;
+            }
+        };
+        if !entry.file_type().is_symlink() {
+            continue;
+        }
+        report.links += 1;
+        let link = entry.path();
+        let target = match fs::read_link(link) {
+            Ok(target) => target,
+            Err(e) => {
+                failures.push(link, FmanError::io_at(link, e))?;
+                continue;
+            }
+        };
+        let dir = link.parent().unwrap_or(Path::new("."));
+        if !dangles(&dir.join(&target)) {
+            continue;
+        }
+        let unfixed = |reason: String| Unfixed {
+            link: link.to_path_buf(),
+            target: target.clone(),
+            reason,
+        };
+        let Some(new_target) = rules.iter().find_map(|rule| rule.apply(&target)) else {
+            report
+                .unfixed
+                .push(unfixed("no rewrite rule matches".to_string()));
+            continue;
+        };
+        if dangles(&dir.join(&new_target)) {
+            report.unfixed.push(unfixed(format!(
+                "{} does not exist either",
+                new_target.display()
+            )));
+            continue;
+        }
+        if let Err(e) = replace_link(link, &new_target, filesystem, naming) {
+            report.unfixed.push(unfixed(e.to_string()));
+            continue;
+        }
+        report.fixed.push(Relinked {
+            link: link.to_path_buf(),
+            old_target: target,
+            new_target,
+        });
+    }
+    failures.into_result(report)
+}
+
+/// Whether nothing is at `path`, following links.
+fn dangles(path: &Path) -> bool {
+    matches!(fs::metadata(path), Err(e) if e.kind() == io::ErrorKind::NotFound)
+}
+
+/// Point `link` at `target` by renaming a new link over it.
+fn replace_link(
+    link: &Path,
+    target: &Path,
+    filesystem: &dyn Fs,
+    naming: &NamingContext,
+) -> FmanResult<()> {
+    let dir = link.parent().unwrap_or(Path::new("."));
+    let name = link
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    let temp = naming.temp_path(dir, &name)?;
+    filesystem.symlink(target, &temp)?;
+    if let Err(e) = filesystem.rename(&temp, link) {
+        let _ = filesystem.remove_file(&temp);
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    use std::os::unix::fs::symlink;
+
+    use tempfile::TempDir;
+
+    use crate::error::DEFAULT_MAX_ERRORS;
+    use crate::fs::{DryRunFs, RealFs};
+
+    fn fix(
+        root: &Path,
+        rules: &[RewriteRule],
+        filesystem: &dyn Fs,
+        naming: &NamingContext,
+    ) -> FmanResult<RelinkReport> {
+        let failures = Failures::new(DEFAULT_MAX_ERRORS, None)?;
+        fix_dangling(root, rules, filesystem, naming, failures)
+    }
+
+    fn rule(spec: &str) -> RewriteRule {
+        RewriteRule::parse(spec).unwrap()
+    }
+
+    #[test]
+    fn rules_split_at_the_first_equals_sign() {
+        assert_eq!(
+            rule("/old=/new=x"),
+            RewriteRule {
+                from: "/old".into(),
+                to: "/new=x".into()
+            }
+        );
+        for bad in ["/old", "=/new", "/old=", ""] {
+            assert!(
+                matches!(RewriteRule::parse(bad), Err(FmanError::InvalidInput(_))),
+                "{bad}"
+            );
+        }
+    }
+
+    #[test]
+    fn rules_match_whole_components() {
+        let rule = rule("/old=/new");
+        assert_eq!(rule.apply(Path::new("/old/a/b")), Some("/new/a/b".into()));
+        assert_eq!(rule.apply(Path::new("/old")), Some("/new".into()));
+        assert_eq!(rule.apply(Path::new("/older/a")), None);
+        assert_eq!(rule.apply(Path::new("old/a")), None);
+        assert_eq!(
+            self::rule("../old=../new").apply(Path::new("../old/x")),
+            Some("../new/x".into())
+        );
+    }
+
+    /// A farm under `links` pointing into `old`, which has moved to `new`.
+    struct Farm {
+        _dir: TempDir,
+        root: PathBuf,
+        links: PathBuf,
+    }
+
+    fn farm() -> Farm {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().to_path_buf();
+        let links = root.join("links");
+        fs::create_dir_all(links.join("sub")).unwrap();
+        fs::create_dir_all(root.join("new")).unwrap();
+        fs::write(root.join("new/a"), "a").unwrap();
+        fs::write(root.join("new/b"), "b").unwrap();
+        fs::write(root.join("live"), "live").unwrap();
+        // Fixable: absolute and relative targets under the old place.
+        symlink(root.join("old/a"), links.join("abs")).unwrap();
+        symlink("../../old/b", links.join("sub/rel")).unwrap();
+        // Not fixable: no rule matches, or the new target is missing too.
+        symlink(root.join("elsewhere/a"), links.join("unmatched")).unwrap();
+        symlink(root.join("old/gone"), links.join("missing")).unwrap();
+        // Not dangling at all, though a rule would match its text.
+        symlink(root.join("live"), links.join("ok")).unwrap();
+        Farm {
+            _dir: dir,
+            root,
+            links,
+        }
+    }
+
+    fn rules(farm: &Farm) -> Vec<RewriteRule> {
+        vec![
+            RewriteRule {
+                from: farm.root.join("old"),
+                to: farm.root.join("new"),
+            },
+            rule("../../old=../../new"),
+        ]
+    }
+
+    #[test]
+    fn exactly_the_fixable_links_are_repaired() {
+        let farm = farm();
+        let report = fix(
+            &farm.links,
+            &rules(&farm),
+            &RealFs,
+            &NamingContext::seeded(1),
+        )
+        .unwrap();
+        assert_eq!(report.links, 5);
+        let mut fixed: Vec<_> = report.fixed.iter().map(|f| f.link.clone()).collect();
+        fixed.sort();
+        assert_eq!(fixed, [farm.links.join("abs"), farm.links.join("sub/rel")]);
+        assert_eq!(
+            fs::read_link(farm.links.join("abs")).unwrap(),
+            farm.root.join("new/a")
+        );
+        assert_eq!(
+            fs::read_link(farm.links.join("sub/rel")).unwrap(),
+            Path::new("../../new/b")
+        );
+        assert_eq!(fs::read_to_string(farm.links.join("sub/rel")).unwrap(), "b");
+
+        let mut unfixed: Vec<_> = report
+            .unfixed
+            .iter()
+            .map(|u| (u.link.clone(), u.reason.clone()))
+            .collect();
+        unfixed.sort();
+        assert_eq!(unfixed[0].0, farm.links.join("missing"));
+        assert!(unfixed[0].1.contains("does not exist either"));
+        assert_eq!(unfixed[1].0, farm.links.join("unmatched"));
+        assert_eq!(unfixed[1].1, "no rewrite rule matches");
+        assert_eq!(
+            fs::read_link(farm.links.join("ok")).unwrap(),
+            farm.root.join("live")
+        );
+        assert_eq!(
+            fs::read_link(farm.links.join("missing")).unwrap(),
+            farm.root.join("old/gone")
+        );
+    }
+
+    #[test]
+    fn no_temporary_links_are_left_behind() {
+        let farm = farm();
+        fix(
+            &farm.links,
+            &rules(&farm),
+            &RealFs,
+            &NamingContext::seeded(1),
+        )
+        .unwrap();
+        let mut names: Vec<_> = fs::read_dir(&farm.links)
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["abs", "missing", "ok", "sub", "unmatched"]);
+    }
+
+    #[test]
+    fn the_first_matching_rule_wins() {
+        let farm = farm();
+        fs::create_dir(farm.root.join("other")).unwrap();
+        fs::write(farm.root.join("other/a"), "other").unwrap();
+        let rules = [
+            RewriteRule {
+                from: farm.root.join("old"),
+                to: farm.root.join("other"),
+            },
+            RewriteRule {
+                from: farm.root.join("old"),
+                to: farm.root.join("new"),
+            },
+        ];
+        fix(&farm.links, &rules, &RealFs, &NamingContext::seeded(1)).unwrap();
+        assert_eq!(
+            fs::read_link(farm.links.join("abs")).unwrap(),
+            farm.root.join("other/a")
+        );
+    }
+
+    #[test]
+    fn a_dry_run_reports_the_repairs_without_making_them() {
+        let farm = farm();
+        let dry_run = DryRunFs::new();
+        let report = fix(
+            &farm.links,
+            &rules(&farm),
+            &dry_run,
+            &NamingContext::seeded(1),
+        )
+        .unwrap();
+        assert_eq!(report.fixed.len(), 2);
+        assert_eq!(
+            fs::read_link(farm.links.join("abs")).unwrap(),
+            farm.root.join("old/a")
+        );
+        assert!(!dry_run.ops().is_empty());
+    }
+
+    #[test]
+    fn the_root_must_be_a_directory() {
+        let farm = farm();
+        let naming = NamingContext::seeded(1);
+        let missing = fix(&farm.root.join("nope"), &[], &RealFs, &naming);
+        assert!(matches!(missing, Err(FmanError::NotFound { .. })));
+        let file = fix(&farm.root.join("live"), &[], &RealFs, &naming);
+        assert!(file.is_err());
+    }
+}
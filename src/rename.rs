@@ -0,0 +1,162 @@
+//! Renaming an entry within its own directory.
+
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use crate::error::{FmanError, FmanResult};
+use crate::fs::Fs;
+use crate::validate::{ensure_not_exists, same_inode};
+
+/// Give `path` the file name `new_name`, in the same directory, and return
+/// the new path.
+///
+/// `new_name` must be a bare name: one with a path separator in it, or `.`
+/// or `..`, fails with `InvalidInput`. An existing entry of that name
+/// fails with `AlreadyExists` unless `force` is set, in which case the
+/// rename replaces it as `rename(2)` does. Renaming an entry to a name
+/// that already refers to it, such as a change of case on a
+/// case-insensitive filesystem, is not a conflict.
+pub fn rename_path(
+    path: &Path,
+    new_name: &str,
+    force: bool,
+    filesystem: &dyn Fs,
+) -> FmanResult<PathBuf> {
+    let Ok(meta) = fs::symlink_metadata(path) else {
+        return Err(FmanError::missing_path(path));
+    };
+    let mut components = Path::new(new_name).components();
+    let bare = matches!(
+        (components.next(), components.next()),
+        (Some(Component::Normal(name)), None) if name == OsStr::new(new_name)
+    );
+    if !bare {
+        return Err(FmanError::InvalidInput(format!(
+            "{new_name:?} is not a bare file name (rename only changes the name; use move to change the directory)"
+        )));
+    }
+    if path.file_name().is_none() {
+        return Err(FmanError::InvalidInput(format!(
+            "cannot rename {}",
+            path.display()
+        )));
+    }
+    let target = path.with_file_name(new_name);
+    if !force && !fs::symlink_metadata(&target).is_ok_and(|existing| same_inode(&meta, &existing)) {
+        ensure_not_exists(&target)?;
+    }
+    filesystem
+        .rename(path, &target)
+        .map_err(|e| FmanError::io_at(path, e))?;
+    Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    use crate::fs::{DryRunFs, FsOp, RealFs};
+
+    fn file(dir: &TempDir, name: &str, contents: &str) -> PathBuf {
+        let path = dir.path().join(name);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn renames_within_the_same_directory() {
+        let dir = TempDir::new().unwrap();
+        let old = file(&dir, "old.txt", "data");
+        let new = rename_path(&old, "new.txt", false, &RealFs).unwrap();
+        assert_eq!(new, dir.path().join("new.txt"));
+        assert_eq!(fs::read_to_string(&new).unwrap(), "data");
+        assert!(!old.exists());
+    }
+
+    #[test]
+    fn renames_to_and_from_unicode_names() {
+        let dir = TempDir::new().unwrap();
+        let old = file(&dir, "résumé.txt", "cv");
+        let new = rename_path(&old, "履歴書 📄.txt", false, &RealFs).unwrap();
+        assert_eq!(new.file_name().unwrap(), "履歴書 📄.txt");
+        assert_eq!(fs::read_to_string(&new).unwrap(), "cv");
+        let back = rename_path(&new, "résumé.txt", false, &RealFs).unwrap();
+        assert_eq!(back, old);
+    }
+
+    #[test]
+    fn an_existing_sibling_is_kept_unless_forced() {
+        let dir = TempDir::new().unwrap();
+        let old = file(&dir, "a", "a");
+        let taken = file(&dir, "b", "b");
+        let err = rename_path(&old, "b", false, &RealFs).unwrap_err();
+        assert!(matches!(err, FmanError::AlreadyExists(_)), "{err}");
+        assert_eq!(fs::read_to_string(&taken).unwrap(), "b");
+        assert!(old.exists());
+
+        rename_path(&old, "b", true, &RealFs).unwrap();
+        assert_eq!(fs::read_to_string(&taken).unwrap(), "a");
+        assert!(!old.exists());
+    }
+
+    #[test]
+    fn only_a_bare_name_is_accepted() {
+        let dir = TempDir::new().unwrap();
+        let old = file(&dir, "a", "a");
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        for name in ["sub/b", "../b", "/tmp/b", ".", "..", "", "b/"] {
+            let err = rename_path(&old, name, false, &RealFs).unwrap_err();
+            assert!(matches!(err, FmanError::InvalidInput(_)), "{name:?}: {err}");
+        }
+        if cfg!(windows) {
+            let err = rename_path(&old, "sub\\b", false, &RealFs).unwrap_err();
+            assert!(matches!(err, FmanError::InvalidInput(_)), "{err}");
+        }
+        assert!(old.exists());
+        assert_eq!(fs::read_dir(dir.path().join("sub")).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn a_missing_path_is_not_found() {
+        let dir = TempDir::new().unwrap();
+        let err = rename_path(&dir.path().join("gone"), "b", false, &RealFs).unwrap_err();
+        assert!(err.is_not_found(), "{err}");
+    }
+
+    #[test]
+    fn renaming_to_the_same_name_is_not_a_conflict() {
+        let dir = TempDir::new().unwrap();
+        let old = file(&dir, "a", "a");
+        assert_eq!(rename_path(&old, "a", false, &RealFs).unwrap(), old);
+        assert_eq!(fs::read_to_string(&old).unwrap(), "a");
+    }
+
+    #[test]
+    fn directories_are_renamed_too() {
+        let dir = TempDir::new().unwrap();
+        let old = dir.path().join("d");
+        fs::create_dir(&old).unwrap();
+        fs::write(old.join("inner"), "x").unwrap();
+        let new = rename_path(&old, "e", false, &RealFs).unwrap();
+        assert_eq!(fs::read_to_string(new.join("inner")).unwrap(), "x");
+    }
+
+    #[test]
+    fn the_rename_goes_through_the_filesystem() {
+        let dir = TempDir::new().unwrap();
+        let old = file(&dir, "a", "a");
+        let dry_run = DryRunFs::new();
+        let new = rename_path(&old, "b", false, &dry_run).unwrap();
+        assert_eq!(
+            dry_run.ops(),
+            [FsOp::Rename {
+                from: old.clone(),
+                to: new
+            }]
+        );
+        assert!(old.exists());
+    }
+}
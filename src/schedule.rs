@@ -0,0 +1,353 @@
+//! Ordering the steps of a plan so that independent ones can run at once.
+//!
+//! Each step declares the paths it reads and the paths it writes. Two steps
+//! conflict when one writes a path the other reads or writes, counting a
+//! directory as overlapping everything below it; the later step in plan
+//! order then waits for the earlier one. Explicit edges can be added on
+//! top, and since those can form cycles, the graph is checked before
+//! anything runs.
+
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::{Condvar, Mutex};
+use std::thread;
+
+use crate::error::{FmanError, FmanResult};
+
+/// The paths one step touches.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StepPaths {
+    pub reads: Vec<PathBuf>,
+    pub writes: Vec<PathBuf>,
+}
+
+/// Whether a step touching `later` must wait for one touching `earlier`.
+pub fn conflicts(earlier: &StepPaths, later: &StepPaths) -> bool {
+    overlaps(&earlier.writes, &later.reads)
+        || overlaps(&earlier.writes, &later.writes)
+        || overlaps(&earlier.reads, &later.writes)
+}
+
+fn overlaps(a: &[PathBuf], b: &[PathBuf]) -> bool {
+    a.iter()
+        .any(|a| b.iter().any(|b| a.starts_with(b) || b.starts_with(a)))
+}
+
+/// Which steps wait for which.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Dag {
+    /// For each step, the steps it waits for.
+    waits_for: Vec<Vec<usize>>,
+}
+
+impl Dag {
+    /// A graph of `steps` steps with no edges.
+    pub fn new(steps: usize) -> Self {
+        Dag {
+            waits_for: vec![Vec::new(); steps],
+        }
+    }
+
+    /// The graph of `steps` in plan order, with an edge wherever two of
+    /// them [conflict](conflicts).
+    pub fn infer(steps: &[StepPaths]) -> Self {
+        let mut dag = Dag::new(steps.len());
+        for (later, later_paths) in steps.iter().enumerate() {
+            for (earlier, earlier_paths) in steps[..later].iter().enumerate() {
+                if conflicts(earlier_paths, later_paths) {
+                    dag.waits_for[later].push(earlier);
+                }
+            }
+        }
+        dag
+    }
+
+    pub fn len(&self) -> usize {
+        self.waits_for.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.waits_for.is_empty()
+    }
+
+    /// Make `after` wait for `before`.
+    pub fn add_edge(&mut self, before: usize, after: usize) {
+        if !self.waits_for[after].contains(&before) {
+            self.waits_for[after].push(before);
+        }
+    }
+
+    pub fn waits_for(&self, step: usize) -> &[usize] {
+        &self.waits_for[step]
+    }
+
+    /// For each step, the steps waiting for it.
+    fn dependents(&self) -> Vec<Vec<usize>> {
+        let mut dependents = vec![Vec::new(); self.len()];
+        for (step, waits_for) in self.waits_for.iter().enumerate() {
+            for &before in waits_for {
+                dependents[before].push(step);
+            }
+        }
+        dependents
+    }
+
+    /// The steps grouped into waves: every step of a wave only waits for
+    /// steps of earlier waves, so a wave can run all at once. Fails with
+    /// `InvalidInput` naming the steps caught in a cycle.
+    pub fn waves(&self) -> FmanResult<Vec<Vec<usize>>> {
+        let dependents = self.dependents();
+        let mut pending: Vec<usize> = self.waits_for.iter().map(Vec::len).collect();
+        let mut wave: Vec<usize> = (0..self.len()).filter(|&s| pending[s] == 0).collect();
+        let mut waves = Vec::new();
+        let mut placed = 0;
+        while !wave.is_empty() {
+            placed += wave.len();
+            let mut next = Vec::new();
+            for &step in &wave {
+                for &dependent in &dependents[step] {
+                    pending[dependent] -= 1;
+                    if pending[dependent] == 0 {
+                        next.push(dependent);
+                    }
+                }
+            }
+            next.sort_unstable();
+            waves.push(wave);
+            wave = next;
+        }
+        if placed < self.len() {
+            let stuck: Vec<String> = (0..self.len())
+                .filter(|&s| pending[s] > 0)
+                .map(|s| (s + 1).to_string())
+                .collect();
+            return Err(FmanError::InvalidInput(format!(
+                "plan steps {} depend on each other in a cycle",
+                stuck.join(", ")
+            )));
+        }
+        Ok(waves)
+    }
+}
+
+/// Run every step of `dag` through `step`, up to `jobs` at a time, each
+/// only once the steps it waits for have finished. With one job the steps
+/// run in plan order.
+///
+/// Cycles are reported before anything runs. After a failure no new step
+/// starts; the ones already running finish and the first error is
+/// returned.
+pub fn run(
+    dag: &Dag,
+    jobs: usize,
+    step: impl Fn(usize) -> FmanResult<()> + Sync,
+) -> FmanResult<()> {
+    dag.waves()?;
+    if jobs <= 1 {
+        return (0..dag.len()).try_for_each(step);
+    }
+
+    struct State {
+        pending: Vec<usize>,
+        ready: VecDeque<usize>,
+        running: usize,
+        finished: usize,
+        failed: Option<FmanError>,
+    }
+    let dependents = dag.dependents();
+    let pending: Vec<usize> = dag.waits_for.iter().map(Vec::len).collect();
+    let state = Mutex::new(State {
+        ready: (0..dag.len()).filter(|&s| pending[s] == 0).collect(),
+        pending,
+        running: 0,
+        finished: 0,
+        failed: None,
+    });
+    let changed = Condvar::new();
+    let worker = || {
+        let mut guard = state.lock().unwrap_or_else(|e| e.into_inner());
+        loop {
+            let done =
+                guard.finished == dag.len() || (guard.failed.is_some() && guard.running == 0);
+            if done {
+                changed.notify_all();
+                return;
+            }
+            let next = if guard.failed.is_none() {
+                guard.ready.pop_front()
+            } else {
+                None
+            };
+            let Some(next) = next else {
+                guard = changed.wait(guard).unwrap_or_else(|e| e.into_inner());
+                continue;
+            };
+            guard.running += 1;
+            drop(guard);
+            let result = step(next);
+            guard = state.lock().unwrap_or_else(|e| e.into_inner());
+            guard.running -= 1;
+            match result {
+                Ok(()) => {
+                    guard.finished += 1;
+                    for &dependent in &dependents[next] {
+                        guard.pending[dependent] -= 1;
+                        if guard.pending[dependent] == 0 {
+                            guard.ready.push_back(dependent);
+                        }
+                    }
+                }
+                Err(e) => {
+                    guard.failed.get_or_insert(e);
+                }
+            }
+            changed.notify_all();
+        }
+    };
+    thread::scope(|scope| {
+        for _ in 0..jobs.min(dag.len()) {
+            scope.spawn(worker);
+        }
+    });
+    let state = state.into_inner().unwrap_or_else(|e| e.into_inner());
+    state.failed.map_or(Ok(()), Err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    fn paths(reads: &[&str], writes: &[&str]) -> StepPaths {
+        StepPaths {
+            reads: reads.iter().map(PathBuf::from).collect(),
+            writes: writes.iter().map(PathBuf::from).collect(),
+        }
+    }
+
+    #[test]
+    fn only_a_write_conflicts() {
+        let read_a = paths(&["a"], &[]);
+        let write_a = paths(&[], &["a"]);
+        assert!(!conflicts(&read_a, &read_a));
+        assert!(conflicts(&read_a, &write_a));
+        assert!(conflicts(&write_a, &read_a));
+        assert!(conflicts(&write_a, &write_a));
+        assert!(!conflicts(&write_a, &paths(&[], &["b"])));
+    }
+
+    #[test]
+    fn a_directory_overlaps_everything_below_it() {
+        let dir = paths(&[], &["d"]);
+        assert!(conflicts(&dir, &paths(&["d/a/b"], &[])));
+        assert!(conflicts(&paths(&[], &["d/a"]), &dir));
+        assert!(!conflicts(&dir, &paths(&[], &["dd"])));
+    }
+
+    #[test]
+    fn infer_makes_later_steps_wait_for_conflicting_earlier_ones() {
+        let dag = Dag::infer(&[
+            paths(&[], &["d"]),
+            paths(&["s"], &["d/a"]),
+            paths(&[], &["e"]),
+            paths(&[], &["s"]),
+        ]);
+        assert_eq!(dag.waits_for(0), &[] as &[usize]);
+        assert_eq!(dag.waits_for(1), &[0]);
+        assert_eq!(dag.waits_for(2), &[] as &[usize]);
+        assert_eq!(dag.waits_for(3), &[1]);
+        assert_eq!(dag.waves().unwrap(), vec![vec![0, 2], vec![1], vec![3]]);
+    }
+
+    #[test]
+    fn a_cycle_is_reported_with_its_steps() {
+        let mut dag = Dag::new(3);
+        dag.add_edge(0, 1);
+        dag.add_edge(1, 2);
+        dag.add_edge(2, 1);
+        let err = dag.waves().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            FmanError::InvalidInput("plan steps 2, 3 depend on each other in a cycle".into())
+                .to_string()
+        );
+        let ran = AtomicUsize::new(0);
+        assert!(
+            run(&dag, 4, |_| {
+                ran.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            })
+            .is_err()
+        );
+        assert_eq!(ran.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn one_job_runs_in_plan_order() {
+        let dag = Dag::infer(&[paths(&[], &["a"]), paths(&[], &["b"]), paths(&[], &["c"])]);
+        let order = Mutex::new(Vec::new());
+        run(&dag, 1, |step| {
+            order.lock().unwrap().push(step);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(order.into_inner().unwrap(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn several_jobs_respect_every_edge() {
+        let steps: Vec<StepPaths> = (0..40)
+            .map(|i| paths(&[&format!("f{}", i % 5)], &[&format!("f{}", (i + 1) % 5)]))
+            .collect();
+        let dag = Dag::infer(&steps);
+        let finished = Mutex::new(Vec::new());
+        run(&dag, 4, |step| {
+            let done = finished.lock().unwrap().clone();
+            for before in dag.waits_for(step) {
+                assert!(done.contains(before), "step {step} ran before {before}");
+            }
+            thread::sleep(Duration::from_millis(1));
+            finished.lock().unwrap().push(step);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(finished.into_inner().unwrap().len(), 40);
+    }
+
+    #[test]
+    fn independent_steps_run_at_once() {
+        let dag = Dag::infer(&[paths(&[], &["a"]), paths(&[], &["b"]), paths(&[], &["c"])]);
+        let running = AtomicUsize::new(0);
+        let most = AtomicUsize::new(0);
+        run(&dag, 3, |_| {
+            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+            most.fetch_max(now, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(50));
+            running.fetch_sub(1, Ordering::SeqCst);
+            Ok(())
+        })
+        .unwrap();
+        assert!(most.load(Ordering::SeqCst) > 1);
+    }
+
+    #[test]
+    fn no_step_starts_after_a_failure() {
+        let mut dag = Dag::new(3);
+        dag.add_edge(0, 1);
+        dag.add_edge(1, 2);
+        let ran = Mutex::new(Vec::new());
+        let err = run(&dag, 2, |step| {
+            ran.lock().unwrap().push(step);
+            if step == 1 {
+                Err(FmanError::InvalidInput("step 2".into()))
+            } else {
+                Ok(())
+            }
+        })
+        .unwrap_err();
+        assert!(err.to_string().contains("step 2"));
+        assert_eq!(ran.into_inner().unwrap(), vec![0, 1]);
+    }
+}
@@ -0,0 +1,264 @@
+//! External merge sort for sequences too large to hold in memory.
+//!
+//! Items are byte strings. Up to a fixed number are buffered; each time the
+//! buffer fills it is sorted and written to a temporary run file, and the
+//! runs are merged when the items are read back. Memory use is the buffer
+//! plus one item per run.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Sorts byte strings, spilling to disk beyond `max_in_memory` of them.
+pub struct ExternalSorter {
+    max_in_memory: usize,
+    buffer: Vec<Vec<u8>>,
+    runs: Vec<Run>,
+}
+
+impl ExternalSorter {
+    /// `max_in_memory` is at least 1.
+    pub fn new(max_in_memory: usize) -> Self {
+        ExternalSorter {
+            max_in_memory: max_in_memory.max(1),
+            buffer: Vec::new(),
+            runs: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, item: Vec<u8>) -> io::Result<()> {
+        self.buffer.push(item);
+        if self.buffer.len() >= self.max_in_memory {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    /// Number of runs written to disk so far.
+    pub fn runs(&self) -> usize {
+        self.runs.len()
+    }
+
+    /// Everything pushed, in ascending byte order.
+    pub fn finish(mut self) -> io::Result<Sorted> {
+        if self.runs.is_empty() {
+            self.buffer.sort_unstable();
+            self.buffer.reverse();
+            return Ok(Sorted::Memory(self.buffer));
+        }
+        if !self.buffer.is_empty() {
+            self.spill()?;
+        }
+        let mut heap = BinaryHeap::new();
+        for (index, run) in self.runs.iter_mut().enumerate() {
+            run.rewind()?;
+            if let Some(item) = run.next_item()? {
+                heap.push(Reverse((item, index)));
+            }
+        }
+        Ok(Sorted::Merge {
+            runs: self.runs,
+            heap,
+        })
+    }
+
+    fn spill(&mut self) -> io::Result<()> {
+        self.buffer.sort_unstable();
+        let mut run = Run::create()?;
+        for item in self.buffer.drain(..) {
+            run.write_item(&item)?;
+        }
+        self.runs.push(run);
+        Ok(())
+    }
+}
+
+/// The sorted items of an [`ExternalSorter`].
+pub enum Sorted {
+    /// Never spilled; reversed so the next item can be popped.
+    Memory(Vec<Vec<u8>>),
+    Merge {
+        runs: Vec<Run>,
+        /// The smallest unread item of each run, with the run's index.
+        heap: BinaryHeap<Reverse<(Vec<u8>, usize)>>,
+    },
+}
+
+impl Iterator for Sorted {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Sorted::Memory(items) => items.pop().map(Ok),
+            Sorted::Merge { runs, heap } => {
+                let Reverse((item, index)) = heap.pop()?;
+                match runs[index].next_item() {
+                    Ok(Some(next)) => heap.push(Reverse((next, index))),
+                    Ok(None) => {}
+                    Err(e) => return Some(Err(e)),
+                }
+                Some(Ok(item))
+            }
+        }
+    }
+}
+
+/// One sorted run in a temporary file, removed when dropped. Items are
+/// stored as a little-endian `u32` length and the bytes.
+pub struct Run {
+    path: PathBuf,
+    writer: Option<BufWriter<File>>,
+    reader: Option<BufReader<File>>,
+}
+
+impl Run {
+    fn create() -> io::Result<Run> {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        let name = format!(
+            "fman-spill-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        );
+        let path = std::env::temp_dir().join(name);
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        Ok(Run {
+            path,
+            writer: Some(BufWriter::new(file)),
+            reader: None,
+        })
+    }
+
+    fn write_item(&mut self, item: &[u8]) -> io::Result<()> {
+        let writer = self.writer.as_mut().expect("run is still being written");
+        let len = u32::try_from(item.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "item too large to spill"))?;
+        writer.write_all(&len.to_le_bytes())?;
+        writer.write_all(item)
+    }
+
+    /// Switch from writing to reading from the start.
+    fn rewind(&mut self) -> io::Result<()> {
+        if let Some(writer) = self.writer.take() {
+            let mut file = writer.into_inner().map_err(|e| e.into_error())?;
+            file.rewind()?;
+            self.reader = Some(BufReader::new(file));
+        }
+        Ok(())
+    }
+
+    fn next_item(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let reader = self.reader.as_mut().expect("run has been rewound");
+        let mut len = [0u8; 4];
+        match reader.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let mut item = vec![0u8; u32::from_le_bytes(len) as usize];
+        reader.read_exact(&mut item)?;
+        Ok(Some(item))
+    }
+}
+
+impl Drop for Run {
+    fn drop(&mut self) {
+        drop(self.writer.take());
+        drop(self.reader.take());
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `count` pseudo-random items of up to 12 bytes, with repeats and
+    /// empty items among them.
+    fn items(count: usize) -> Vec<Vec<u8>> {
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        (0..count)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                let len = (state % 13) as usize;
+                state.to_le_bytes().repeat(2)[..len].to_vec()
+            })
+            .collect()
+    }
+
+    fn sort(items: &[Vec<u8>], max_in_memory: usize) -> (Vec<Vec<u8>>, usize) {
+        let mut sorter = ExternalSorter::new(max_in_memory);
+        for item in items {
+            sorter.push(item.clone()).unwrap();
+        }
+        let runs = sorter.runs();
+        let sorted = sorter.finish().unwrap().collect::<io::Result<_>>().unwrap();
+        (sorted, runs)
+    }
+
+    #[test]
+    fn a_stream_larger_than_the_bound_is_merged_from_runs() {
+        let input = items(10_000);
+        let mut expected = input.clone();
+        expected.sort();
+        for bound in [1, 7, 100, 9_999] {
+            let (sorted, runs) = sort(&input, bound);
+            assert_eq!(runs, 10_000 / bound, "bound {bound}");
+            assert_eq!(sorted, expected, "bound {bound}");
+        }
+    }
+
+    #[test]
+    fn a_stream_within_the_bound_stays_in_memory() {
+        let input = items(500);
+        let mut expected = input.clone();
+        expected.sort();
+        let (sorted, runs) = sort(&input, 501);
+        assert_eq!(runs, 0);
+        assert_eq!(sorted, expected);
+    }
+
+    #[test]
+    fn duplicates_and_empty_items_survive_the_merge() {
+        let input: Vec<Vec<u8>> = [&b"b"[..], b"", b"a", b"b", b"", b"\xff", b"a"]
+            .iter()
+            .map(|item| item.to_vec())
+            .collect();
+        let (sorted, runs) = sort(&input, 2);
+        assert_eq!(runs, 3);
+        assert_eq!(sorted, [&b""[..], b"", b"a", b"a", b"b", b"b", b"\xff"]);
+    }
+
+    #[test]
+    fn an_empty_sorter_yields_nothing() {
+        assert_eq!(sort(&[], 3), (Vec::new(), 0));
+    }
+
+    #[test]
+    fn a_zero_bound_is_treated_as_one() {
+        let (sorted, runs) = sort(&[b"b".to_vec(), b"a".to_vec()], 0);
+        assert_eq!(runs, 2);
+        assert_eq!(sorted, [b"a".to_vec(), b"b".to_vec()]);
+    }
+
+    #[test]
+    fn run_files_are_removed_when_dropped() {
+        let mut run = Run::create().unwrap();
+        run.write_item(b"x").unwrap();
+        let path = run.path.clone();
+        run.rewind().unwrap();
+        assert_eq!(run.next_item().unwrap(), Some(b"x".to_vec()));
+        assert_eq!(run.next_item().unwrap(), None);
+        assert!(path.exists());
+        drop(run);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,235 @@
+//! "Did you mean" suggestions for paths that do not exist.
+//!
+//! The missing path's parent directory is scanned for names close to the
+//! one asked for. Very large directories are not scanned at all, so a typo
+//! under a huge spool directory costs no more than the failed lookup.
+
+use std::cmp::Ordering;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Directories with more entries than this are not scanned.
+pub const MAX_SCANNED: usize = 4096;
+
+/// Suggestions returned at most.
+const MAX_SUGGESTIONS: usize = 3;
+
+/// Why a name was considered close; earlier variants rank higher.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Similarity {
+    /// One name is a prefix of the other, as when a name is cut short or
+    /// has an extension added or left off.
+    Prefix,
+    /// The names differ only in case.
+    Case,
+    /// The names differ only in their extension.
+    Extension,
+    /// Within a small number of edits.
+    Distance(usize),
+}
+
+/// The optimal string alignment distance between `a` and `b`: insertions,
+/// deletions, substitutions and swaps of adjacent characters each count
+/// one, so `reprot` is one edit from `report`.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    // Three rows: two back for swaps, the previous one and the current one.
+    let mut before: Vec<usize> = vec![0; b.len() + 1];
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+    for i in 1..=a.len() {
+        current[0] = i;
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            current[j] = (previous[j] + 1)
+                .min(current[j - 1] + 1)
+                .min(previous[j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                current[j] = current[j].min(before[j - 2] + 1);
+            }
+        }
+        std::mem::swap(&mut before, &mut previous);
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous[b.len()]
+}
+
+/// How close `candidate` is to `wanted`, or `None` if it is not worth
+/// suggesting. Distances up to a third of the wanted name's length, and at
+/// most 2, are accepted.
+pub fn similarity(wanted: &str, candidate: &str) -> Option<Similarity> {
+    if wanted == candidate {
+        return None;
+    }
+    let shorter = wanted.chars().count().min(candidate.chars().count());
+    if shorter >= 3 && (candidate.starts_with(wanted) || wanted.starts_with(candidate)) {
+        return Some(Similarity::Prefix);
+    }
+    if wanted.to_lowercase() == candidate.to_lowercase() {
+        return Some(Similarity::Case);
+    }
+    if let (Some(wanted_stem), Some(candidate_stem)) = (stem(wanted), stem(candidate))
+        && wanted_stem == candidate_stem
+    {
+        return Some(Similarity::Extension);
+    }
+    let distance = edit_distance(wanted, candidate);
+    let allowed = (wanted.chars().count() / 3).clamp(1, 2);
+    (distance <= allowed).then_some(Similarity::Distance(distance))
+}
+
+fn stem(name: &str) -> Option<&str> {
+    name.rsplit_once('.')
+        .map(|(stem, _)| stem)
+        .filter(|stem| !stem.is_empty())
+}
+
+/// Order `candidates` from most to least similar to `wanted`, dropping
+/// those too far off; ties go by name.
+pub fn rank<'a>(wanted: &str, candidates: impl IntoIterator<Item = &'a str>) -> Vec<&'a str> {
+    let mut ranked: Vec<(Similarity, &str)> = candidates
+        .into_iter()
+        .filter_map(|candidate| Some((similarity(wanted, candidate)?, candidate)))
+        .collect();
+    ranked.sort_by(|a, b| match a.0.cmp(&b.0) {
+        Ordering::Equal => a.1.cmp(b.1),
+        order => order,
+    });
+    ranked.into_iter().map(|(_, candidate)| candidate).collect()
+}
+
+/// Existing paths next to the missing `path` with similar names, best
+/// first. Empty if the parent cannot be read or holds more than
+/// [`MAX_SCANNED`] entries.
+pub fn similar_paths(path: &Path) -> Vec<PathBuf> {
+    let Some(wanted) = path.file_name().and_then(|name| name.to_str()) else {
+        return Vec::new();
+    };
+    let parent = path.parent().unwrap_or(Path::new(""));
+    let dir = if parent.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        parent
+    };
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut names = Vec::new();
+    for entry in entries.flatten() {
+        if names.len() == MAX_SCANNED {
+            return Vec::new();
+        }
+        if let Ok(name) = entry.file_name().into_string() {
+            names.push(name);
+        }
+    }
+    rank(wanted, names.iter().map(String::as_str))
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|name| parent.join(name))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    #[test]
+    fn edit_distance_counts_each_kind_of_edit_once() {
+        assert_eq!(edit_distance("report", "report"), 0);
+        assert_eq!(edit_distance("reprot", "report"), 1);
+        assert_eq!(edit_distance("report", "reportt"), 1);
+        assert_eq!(edit_distance("report", "repor"), 1);
+        assert_eq!(edit_distance("report", "rexort"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("ça", "ca"), 1);
+    }
+
+    #[test]
+    fn similarity_prefers_prefix_then_case_then_extension_then_distance() {
+        assert_eq!(similarity("report.txt", "report.txt"), None);
+        assert_eq!(similarity("report", "report.txt"), Some(Similarity::Prefix));
+        assert_eq!(similarity("README", "readme"), Some(Similarity::Case));
+        assert_eq!(
+            similarity("notes.md", "notes.txt"),
+            Some(Similarity::Extension)
+        );
+        assert_eq!(
+            similarity("reprot.txt", "report.txt"),
+            Some(Similarity::Distance(1))
+        );
+        assert!(Similarity::Prefix < Similarity::Case);
+        assert!(Similarity::Extension < Similarity::Distance(1));
+        assert!(Similarity::Distance(1) < Similarity::Distance(2));
+    }
+
+    #[test]
+    fn short_or_distant_names_are_not_suggested() {
+        // A one-letter prefix says nothing.
+        assert_eq!(similarity("ab", "abcdef"), None);
+        assert_eq!(similarity("abc", "xyz"), None);
+        // Two edits are too many for a short name.
+        assert_eq!(similarity("cat", "dog"), None);
+        assert_eq!(
+            similarity("abcdef", "abxyef"),
+            Some(Similarity::Distance(2))
+        );
+        assert_eq!(similarity("abcdefghi", "abxyzfghi"), None);
+    }
+
+    #[test]
+    fn rank_orders_by_similarity_then_name() {
+        let candidates = [
+            "report.txt",
+            "Reprot",
+            "reprot.md",
+            "reprt",
+            "zzz",
+            "reprot.txt",
+        ];
+        assert_eq!(
+            rank("reprot", candidates),
+            ["reprot.md", "reprot.txt", "Reprot", "reprt"]
+        );
+        assert!(rank("reprot", ["zzz"]).is_empty());
+    }
+
+    #[test]
+    fn similar_paths_looks_next_to_the_missing_path() {
+        let dir = TempDir::new().unwrap();
+        for name in [
+            "report.txt",
+            "Report.txt",
+            "unrelated",
+            "report.txt.bak",
+            "reportx.txt",
+        ] {
+            fs::write(dir.path().join(name), "").unwrap();
+        }
+        let found = similar_paths(&dir.path().join("reprot.txt"));
+        assert_eq!(
+            found,
+            [
+                dir.path().join("report.txt"),
+                dir.path().join("Report.txt"),
+                dir.path().join("reportx.txt"),
+            ]
+        );
+        let found = similar_paths(&dir.path().join("report"));
+        assert_eq!(found.len(), MAX_SUGGESTIONS);
+        assert!(similar_paths(&dir.path().join("missing/report.txt")).is_empty());
+    }
+
+    #[test]
+    fn a_huge_directory_is_not_scanned() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("report.txt"), "").unwrap();
+        for i in 0..MAX_SCANNED {
+            fs::File::create(dir.path().join(i.to_string())).unwrap();
+        }
+        assert!(similar_paths(&dir.path().join("reprot.txt")).is_empty());
+    }
+}
@@ -0,0 +1,975 @@
+//! Keeping two directory trees in step.
+//!
+//! Syncing is split into three stages: [`snapshot`] each tree, plan the
+//! actions with a pure function over the snapshots ([`plan_one_way`] or
+//! [`plan_bidirectional`]), then [`execute`] the plan. Only regular files
+//! are synced; directories are created as files need them and symlinks are
+//! ignored. Copies carry the source's mtime over so that an unchanged file
+//! compares equal on the next run.
+
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use crate::budget::{BudgetUsage, ByteBudget};
+use crate::cachedir::ExcludeCaches;
+use crate::cleanup::{Cleanup, CleanupRegistry};
+use crate::compare::compare_modified;
+use crate::copy::{CopyOptions, copy_file};
+use crate::error::{Failures, FmanError, FmanResult};
+use crate::format;
+use crate::fs::{Fs, SharedFs};
+use crate::naming::NamingContext;
+use crate::platform;
+use crate::validate::{ensure_exists, ensure_is_dir};
+use crate::walk::Walk;
+
+/// Size and mtime of one file, the basis of every comparison.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileState {
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+impl FileState {
+    /// Same size, and mtimes no more than `window` apart.
+    pub fn matches(&self, other: &FileState, window: Duration) -> bool {
+        self.size == other.size
+            && compare_modified(self.modified, other.modified, window) == Ordering::Equal
+    }
+}
+
+/// Which files a sync looks at, and when two of them are the same.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SyncScope {
+    /// Mtimes at most this far apart count as equal.
+    pub window: Duration,
+    /// Leave out directories tagged as caches, on both sides.
+    pub exclude_caches: Option<ExcludeCaches>,
+}
+
+/// Regular files of a tree keyed by `/`-separated relative path.
+pub type Snapshot = BTreeMap<String, FileState>;
+
+/// Which of the two trees.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(Serialize), serde(rename_all = "kebab-case"))]
+pub enum Side {
+    A,
+    B,
+}
+
+impl Side {
+    pub fn other(self) -> Side {
+        match self {
+            Side::A => Side::B,
+            Side::B => Side::A,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "json",
+    derive(Serialize),
+    serde(tag = "action", rename_all = "kebab-case")
+)]
+pub enum SyncAction {
+    /// Copy `path` from side `from` over the other side.
+    Copy { path: String, from: Side },
+    /// Remove `path` from `side`; it was deleted on the other side.
+    Delete { path: String, side: Side },
+    /// Both sides changed `path`. The loser is kept next to it as a
+    /// conflict copy on both sides and the winner copied over it.
+    Conflict { path: String, winner: Side },
+}
+
+/// What both trees looked like at the end of the previous sync, per file.
+///
+/// A file the baseline knows about that is now missing on one side was
+/// deleted there; one it does not know about is new. Without a baseline
+/// (or with an empty one) nothing is ever deleted.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Baseline {
+    pub files: BTreeMap<String, BaselineEntry>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BaselineEntry {
+    pub a: FileState,
+    pub b: FileState,
+}
+
+impl Baseline {
+    /// Read a state file, treating a missing file as an empty baseline.
+    pub fn load(path: &Path) -> FmanResult<Self> {
+        match fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str(&text).map_err(|e| {
+                FmanError::InvalidInput(format!("invalid sync state {}: {e}", path.display()))
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Write the state file through a temporary file and a rename, so an
+    /// interrupted save leaves the previous baseline intact.
+    pub fn save(&self, path: &Path, filesystem: &dyn Fs, naming: &NamingContext) -> FmanResult<()> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| FmanError::InvalidInput(format!("cannot encode sync state: {e}")))?;
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let name = path
+            .file_name()
+            .map_or_else(|| "sync-state".into(), |name| name.to_string_lossy());
+        let tmp = naming.temp_path(dir, &name)?;
+        let file = filesystem.create_file(&tmp, false)?;
+        let registry = CleanupRegistry::new();
+        registry.push(Cleanup::RemoveFile(tmp.clone()));
+        let saved = file
+            .map_or(Ok(()), |mut file| {
+                file.write_all(json.as_bytes())?;
+                file.sync_all()
+            })
+            .and_then(|()| filesystem.rename(&tmp, path));
+        match saved {
+            Ok(()) => Ok(()),
+            Err(e) => registry.unwind(filesystem, None, e.into()),
+        }
+    }
+
+    /// The baseline for two trees that were just synced: every file present
+    /// on both sides.
+    pub fn from_snapshots(a: &Snapshot, b: &Snapshot) -> Self {
+        let files = a
+            .iter()
+            .filter_map(|(path, &a)| {
+                let &b = b.get(path)?;
+                Some((path.clone(), BaselineEntry { a, b }))
+            })
+            .collect();
+        Self { files }
+    }
+}
+
+/// Outcome of a sync run.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "json", derive(Serialize))]
+pub struct SyncReport {
+    pub actions: Vec<SyncAction>,
+    /// Conflict copies written, relative to either root.
+    pub conflict_copies: Vec<String>,
+    pub counts: SyncCounts,
+    /// Use of the write budget, if the sync had one.
+    pub budget: Option<BudgetUsage>,
+}
+
+/// How many files a sync copied, updated and left alone.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(Serialize))]
+pub struct SyncCounts {
+    /// Copied to a side that did not have them.
+    pub copied: u64,
+    /// Copied over a differing file.
+    pub updated: u64,
+    /// On both sides and already in step.
+    pub skipped: u64,
+    pub deleted: u64,
+    pub conflicts: u64,
+}
+
+impl SyncCounts {
+    /// Tally `actions`, planned from snapshots `a` and `b`.
+    pub fn tally(actions: &[SyncAction], a: &Snapshot, b: &Snapshot) -> Self {
+        let mut counts = SyncCounts::default();
+        let mut touched = BTreeSet::new();
+        for action in actions {
+            let path = match action {
+                SyncAction::Copy { path, from } => {
+                    let target = match from {
+                        Side::A => b,
+                        Side::B => a,
+                    };
+                    if target.contains_key(path) {
+                        counts.updated += 1;
+                    } else {
+                        counts.copied += 1;
+                    }
+                    path
+                }
+                SyncAction::Delete { path, .. } => {
+                    counts.deleted += 1;
+                    path
+                }
+                SyncAction::Conflict { path, .. } => {
+                    counts.conflicts += 1;
+                    path
+                }
+            };
+            touched.insert(path.as_str());
+        }
+        counts.skipped = a
+            .keys()
+            .filter(|path| b.contains_key(*path) && !touched.contains(path.as_str()))
+            .count() as u64;
+        counts
+    }
+}
+
+/// Snapshot the regular files below `root`, minus cache directories if
+/// `exclude_caches` is set. A missing root is an empty tree. A directory
+/// or file that cannot be read goes into `failures` and is left out.
+pub fn snapshot(
+    root: &Path,
+    exclude_caches: Option<ExcludeCaches>,
+    failures: &mut Failures,
+) -> FmanResult<Snapshot> {
+    let mut files = Snapshot::new();
+    if fs::symlink_metadata(root).is_err() {
+        return Ok(files);
+    }
+    let mut walk = Walk::new(root);
+    if let Some(mode) = exclude_caches {
+        walk = walk.exclude_caches(mode);
+    }
+    for entry in walk {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                failures.push(root, e)?;
+                continue;
+            }
+        };
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let state = entry.metadata().and_then(|meta| {
+            let modified = meta
+                .modified()
+                .map_err(|e| FmanError::io_at(entry.path(), e))?;
+            Ok(FileState {
+                size: meta.len(),
+                modified,
+            })
+        });
+        match state {
+            Ok(state) => {
+                files.insert(relative_key(root, entry.path()), state);
+            }
+            Err(e) => failures.push(entry.path(), e)?,
+        }
+    }
+    Ok(files)
+}
+
+fn relative_key(root: &Path, path: &Path) -> String {
+    path.strip_prefix(root)
+        .unwrap_or(path)
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Make `b` hold everything `a` has: copy files that are missing from `b`
+/// or differ in size or in mtime by more than `window`. Files only in `b`
+/// are left alone.
+pub fn plan_one_way(a: &Snapshot, b: &Snapshot, window: Duration) -> Vec<SyncAction> {
+    a.iter()
+        .filter(|(path, state)| {
+            !b.get(*path)
+                .is_some_and(|other| state.matches(other, window))
+        })
+        .map(|(path, _)| SyncAction::Copy {
+            path: path.clone(),
+            from: Side::A,
+        })
+        .collect()
+}
+
+/// Plan a two-way merge of `a` and `b` against the previous `baseline`.
+///
+/// A side has changed a path when its state differs from the baseline's
+/// record of that side. Changes on one side propagate to the other; changes
+/// on both are a conflict, won by the newer mtime (`a` on a tie). A
+/// deletion propagates only if the surviving copy is unchanged; a file
+/// modified on one side and deleted on the other is restored instead.
+///
+/// Paths the baseline does not know are new: a file on one side is copied
+/// over, and differing files on both sides go to the newer one, or are a
+/// conflict if only their sizes differ.
+///
+/// Mtimes no more than `window` apart compare equal throughout.
+pub fn plan_bidirectional(
+    a: &Snapshot,
+    b: &Snapshot,
+    baseline: &Baseline,
+    window: Duration,
+) -> Vec<SyncAction> {
+    let paths: BTreeSet<&String> = a.keys().chain(b.keys()).collect();
+    let mut actions = Vec::new();
+    for path in paths {
+        let base = baseline.files.get(path);
+        let action = match (a.get(path), b.get(path)) {
+            (Some(sa), Some(sb)) => match base {
+                _ if sa.matches(sb, window) => None,
+                Some(base) => match (!base.a.matches(sa, window), !base.b.matches(sb, window)) {
+                    (false, false) => None,
+                    (true, false) => Some(copy(path, Side::A)),
+                    (false, true) => Some(copy(path, Side::B)),
+                    (true, true) => Some(conflict(path, sa, sb, window)),
+                },
+                None => match newer(sa, sb, window) {
+                    Some(side) => Some(copy(path, side)),
+                    None => Some(conflict(path, sa, sb, window)),
+                },
+            },
+            (Some(sa), None) => one_sided(path, Side::A, sa, base.map(|base| base.a), window),
+            (None, Some(sb)) => one_sided(path, Side::B, sb, base.map(|base| base.b), window),
+            (None, None) => None,
+        };
+        actions.extend(action);
+    }
+    actions
+}
+
+/// A path that exists only on `side`: new there, or deleted on the other.
+fn one_sided(
+    path: &str,
+    side: Side,
+    state: &FileState,
+    recorded: Option<FileState>,
+    window: Duration,
+) -> Option<SyncAction> {
+    match recorded {
+        Some(recorded) if recorded.matches(state, window) => Some(SyncAction::Delete {
+            path: path.to_string(),
+            side,
+        }),
+        _ => Some(copy(path, side)),
+    }
+}
+
+fn copy(path: &str, from: Side) -> SyncAction {
+    SyncAction::Copy {
+        path: path.to_string(),
+        from,
+    }
+}
+
+fn conflict(path: &str, a: &FileState, b: &FileState, window: Duration) -> SyncAction {
+    SyncAction::Conflict {
+        path: path.to_string(),
+        winner: newer(a, b, window).unwrap_or(Side::A),
+    }
+}
+
+/// The side with the later mtime, or `None` if they are within `window`.
+fn newer(a: &FileState, b: &FileState, window: Duration) -> Option<Side> {
+    match compare_modified(a.modified, b.modified, window) {
+        Ordering::Less => Some(Side::B),
+        Ordering::Greater => Some(Side::A),
+        Ordering::Equal => None,
+    }
+}
+
+/// Carry out `actions` between the trees at `a` and `b`.
+///
+/// Conflict copies are named `<name>.conflict-<host>-<date>`, with a
+/// numeric suffix from `naming` if that name is taken. Returns the conflict
+/// copies written. Every change is made through `filesystem`, and the data
+/// copied is charged to `budget` if there is one; the run stops at the
+/// first copy that does not fit. Any other failed action goes into
+/// `failures` and the rest are still carried out.
+pub fn execute(
+    a: &Path,
+    b: &Path,
+    actions: &[SyncAction],
+    filesystem: &SharedFs,
+    naming: &NamingContext,
+    budget: Option<&ByteBudget>,
+    failures: &mut Failures,
+) -> FmanResult<Vec<String>> {
+    let root = |side: Side| match side {
+        Side::A => a,
+        Side::B => b,
+    };
+    let mut conflict_copies = Vec::new();
+    for action in actions {
+        let (path, done) = match action {
+            SyncAction::Copy { path, from } => {
+                let src = root(*from).join(path);
+                let copied =
+                    copy_preserving_mtime(&src, &root(from.other()).join(path), filesystem, budget);
+                (src, copied)
+            }
+            SyncAction::Delete { path, side } => {
+                let target = root(*side).join(path);
+                let deleted = filesystem
+                    .remove_file(&target)
+                    .map_err(|e| FmanError::io_at(&target, e));
+                (target, deleted)
+            }
+            SyncAction::Conflict { path, winner } => {
+                let (winner_root, loser_root) = (root(*winner), root(winner.other()));
+                let settled =
+                    settle_conflict(path, winner_root, loser_root, filesystem, naming, budget)
+                        .map(|conflict| conflict_copies.push(conflict));
+                (loser_root.join(path), settled)
+            }
+        };
+        match done {
+            Ok(()) => {}
+            Err(e @ FmanError::QuotaExceeded { .. }) => return Err(e),
+            Err(e) => failures.push(&path, e)?,
+        }
+    }
+    Ok(conflict_copies)
+}
+
+/// Keep the loser of a conflict over `path` as a conflict copy in both
+/// trees and put the winner in its place, returning the copy's name.
+fn settle_conflict(
+    path: &str,
+    winner_root: &Path,
+    loser_root: &Path,
+    filesystem: &SharedFs,
+    naming: &NamingContext,
+    budget: Option<&ByteBudget>,
+) -> FmanResult<String> {
+    let conflict = conflict_name(path, winner_root, loser_root, naming)?;
+    let original = loser_root.join(path);
+    filesystem
+        .rename(&original, &loser_root.join(&conflict))
+        .map_err(|e| FmanError::io_at(&original, e))?;
+    let loser = if filesystem.performs() {
+        loser_root.join(&conflict)
+    } else {
+        original
+    };
+    copy_preserving_mtime(&loser, &winner_root.join(&conflict), filesystem, budget)?;
+    copy_preserving_mtime(
+        &winner_root.join(path),
+        &loser_root.join(path),
+        filesystem,
+        budget,
+    )?;
+    Ok(conflict)
+}
+
+/// A conflict copy name for `path` free in both trees.
+fn conflict_name(path: &str, a: &Path, b: &Path, naming: &NamingContext) -> FmanResult<String> {
+    let date = format::format_time(SystemTime::now(), "%Y-%m-%d");
+    let (dir, name) = match path.rsplit_once('/') {
+        Some((dir, name)) => (format!("{dir}/"), name),
+        None => (String::new(), path),
+    };
+    let base = format!("{name}.conflict-{}-{date}", platform::hostname());
+    let taken = |name: &str| {
+        let path = format!("{dir}{name}");
+        fs::symlink_metadata(a.join(&path)).is_ok() || fs::symlink_metadata(b.join(&path)).is_ok()
+    };
+    Ok(format!("{dir}{}", naming.numbered(&base, taken)?))
+}
+
+fn copy_preserving_mtime(
+    src: &Path,
+    dst: &Path,
+    filesystem: &SharedFs,
+    budget: Option<&ByteBudget>,
+) -> FmanResult<()> {
+    let mut options = CopyOptions::new()
+        .force(true)
+        .parents(true)
+        .fs(filesystem.clone());
+    if let Some(budget) = budget {
+        options = options.budget(budget.clone());
+    }
+    copy_file(src, dst, &options)?;
+    filesystem.set_times(dst, None, Some(fs::metadata(src)?.modified()?))?;
+    Ok(())
+}
+
+/// Copy what `a` has and `b` lacks or holds differently into `b`, within
+/// `scope`. Files that cannot be read or copied go into `failures`, which
+/// are returned together once the rest is in step.
+pub fn sync_one_way(
+    a: &Path,
+    b: &Path,
+    scope: &SyncScope,
+    filesystem: &SharedFs,
+    naming: &NamingContext,
+    budget: Option<&ByteBudget>,
+    mut failures: Failures,
+) -> FmanResult<SyncReport> {
+    ensure_exists(a)?;
+    ensure_is_dir(a)?;
+    if !filesystem.is_dir(b) {
+        filesystem.create_dir_all(b)?;
+    }
+    let (a_files, b_files) = (
+        snapshot(a, scope.exclude_caches, &mut failures)?,
+        snapshot(b, scope.exclude_caches, &mut failures)?,
+    );
+    let actions = plan_one_way(&a_files, &b_files, scope.window);
+    execute(a, b, &actions, filesystem, naming, budget, &mut failures)?;
+    failures.into_result(SyncReport {
+        counts: SyncCounts::tally(&actions, &a_files, &b_files),
+        actions,
+        conflict_copies: Vec::new(),
+        budget: budget.map(ByteBudget::usage),
+    })
+}
+
+/// Two-way sync of `a` and `b`.
+///
+/// With a `state_file` the previous baseline is read from it (a missing
+/// file counts as empty) and the new one written back after a successful
+/// run; without one, deletions are never propagated. Only files within
+/// `scope` are considered. Files that cannot be read or copied go into
+/// `failures`, which are returned together once the rest is in step; a
+/// tree that could not be read in full propagates no deletions, since a
+/// file it misses may only be unreadable.
+#[allow(clippy::too_many_arguments)]
+pub fn sync_bidirectional(
+    a: &Path,
+    b: &Path,
+    state_file: Option<&Path>,
+    scope: &SyncScope,
+    filesystem: &SharedFs,
+    naming: &NamingContext,
+    budget: Option<&ByteBudget>,
+    mut failures: Failures,
+) -> FmanResult<SyncReport> {
+    ensure_exists(a)?;
+    ensure_is_dir(a)?;
+    if !filesystem.is_dir(b) {
+        filesystem.create_dir_all(b)?;
+    }
+    let baseline = match state_file {
+        Some(path) => Baseline::load(path)?,
+        None => Baseline::default(),
+    };
+
+    let exclude = scope.exclude_caches;
+    let (a_files, b_files) = (
+        snapshot(a, exclude, &mut failures)?,
+        snapshot(b, exclude, &mut failures)?,
+    );
+    let mut actions = plan_bidirectional(&a_files, &b_files, &baseline, scope.window);
+    if !failures.is_empty() {
+        actions.retain(|action| !matches!(action, SyncAction::Delete { .. }));
+    }
+    let conflict_copies = execute(a, b, &actions, filesystem, naming, budget, &mut failures)?;
+
+    if let Some(path) = state_file
+        && failures.is_empty()
+    {
+        let (a_now, b_now) = (
+            snapshot(a, exclude, &mut failures)?,
+            snapshot(b, exclude, &mut failures)?,
+        );
+        Baseline::from_snapshots(&a_now, &b_now).save(path, filesystem.as_ref(), naming)?;
+    }
+    failures.into_result(SyncReport {
+        counts: SyncCounts::tally(&actions, &a_files, &b_files),
+        actions,
+        conflict_copies,
+        budget: budget.map(ByteBudget::usage),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+
+    use tempfile::TempDir;
+
+    use crate::error::DEFAULT_MAX_ERRORS;
+    use crate::fs::{DryRunFs, FsOp, RealFs};
+
+    fn state(size: u64, secs: u64) -> FileState {
+        FileState {
+            size,
+            modified: SystemTime::UNIX_EPOCH + Duration::from_secs(secs),
+        }
+    }
+
+    fn snap(files: &[(&str, FileState)]) -> Snapshot {
+        files
+            .iter()
+            .map(|(path, state)| (path.to_string(), *state))
+            .collect()
+    }
+
+    fn base(files: &[(&str, FileState, FileState)]) -> Baseline {
+        Baseline {
+            files: files
+                .iter()
+                .map(|(path, a, b)| (path.to_string(), BaselineEntry { a: *a, b: *b }))
+                .collect(),
+        }
+    }
+
+    fn failures() -> Failures {
+        Failures::new(DEFAULT_MAX_ERRORS, None).unwrap()
+    }
+
+    fn plan(a: &Snapshot, b: &Snapshot, baseline: &Baseline) -> Vec<SyncAction> {
+        plan_bidirectional(a, b, baseline, Duration::ZERO)
+    }
+
+    #[test]
+    fn one_way_copies_what_is_missing_or_different() {
+        let a = snap(&[
+            ("same", state(1, 10)),
+            ("newer", state(1, 20)),
+            ("new", state(1, 10)),
+        ]);
+        let b = snap(&[
+            ("same", state(1, 10)),
+            ("newer", state(1, 10)),
+            ("extra", state(1, 10)),
+        ]);
+        assert_eq!(
+            plan_one_way(&a, &b, Duration::ZERO),
+            [copy("new", Side::A), copy("newer", Side::A)]
+        );
+        assert_eq!(
+            plan_one_way(&a, &b, Duration::from_secs(10)),
+            [copy("new", Side::A)]
+        );
+    }
+
+    #[test]
+    fn new_files_go_to_the_side_without_them() {
+        let a = snap(&[("only-a", state(1, 10))]);
+        let b = snap(&[("only-b", state(1, 10))]);
+        assert_eq!(
+            plan(&a, &b, &Baseline::default()),
+            [copy("only-a", Side::A), copy("only-b", Side::B)]
+        );
+    }
+
+    #[test]
+    fn a_change_on_one_side_propagates() {
+        let was = state(1, 10);
+        let baseline = base(&[("x", was, was), ("y", was, was)]);
+        let a = snap(&[("x", state(2, 20)), ("y", was)]);
+        // An older mtime still counts as a change against the baseline.
+        let b = snap(&[("x", was), ("y", state(1, 5))]);
+        assert_eq!(
+            plan(&a, &b, &baseline),
+            [copy("x", Side::A), copy("y", Side::B)]
+        );
+    }
+
+    #[test]
+    fn changes_on_both_sides_are_a_conflict_won_by_the_newer() {
+        let was = state(1, 10);
+        let baseline = base(&[("x", was, was), ("tie", was, was)]);
+        let a = snap(&[("x", state(2, 20)), ("tie", state(2, 30))]);
+        let b = snap(&[("x", state(3, 30)), ("tie", state(3, 30))]);
+        assert_eq!(
+            plan(&a, &b, &baseline),
+            [
+                conflict_won_by("tie", Side::A),
+                conflict_won_by("x", Side::B)
+            ]
+        );
+    }
+
+    #[test]
+    fn identical_changes_on_both_sides_are_no_conflict() {
+        let was = state(1, 10);
+        let baseline = base(&[("x", was, was)]);
+        let now = state(2, 20);
+        assert!(plan(&snap(&[("x", now)]), &snap(&[("x", now)]), &baseline).is_empty());
+    }
+
+    #[test]
+    fn a_deletion_propagates_only_from_a_baseline() {
+        let was = state(1, 10);
+        let a = snap(&[("gone-from-b", was)]);
+        let b = snap(&[("gone-from-a", was)]);
+        let baseline = base(&[("gone-from-b", was, was), ("gone-from-a", was, was)]);
+        assert_eq!(
+            plan(&a, &b, &baseline),
+            [
+                SyncAction::Delete {
+                    path: "gone-from-a".into(),
+                    side: Side::B
+                },
+                SyncAction::Delete {
+                    path: "gone-from-b".into(),
+                    side: Side::A
+                },
+            ]
+        );
+        assert_eq!(
+            plan(&a, &b, &Baseline::default()),
+            [copy("gone-from-a", Side::B), copy("gone-from-b", Side::A)]
+        );
+    }
+
+    #[test]
+    fn a_file_changed_on_one_side_and_deleted_on_the_other_is_restored() {
+        let was = state(1, 10);
+        let baseline = base(&[("x", was, was)]);
+        let a = snap(&[("x", state(2, 20))]);
+        assert_eq!(plan(&a, &Snapshot::new(), &baseline), [copy("x", Side::A)]);
+    }
+
+    #[test]
+    fn unrecorded_differing_files_go_to_the_newer() {
+        let a = snap(&[("x", state(1, 10)), ("y", state(1, 10))]);
+        let b = snap(&[("x", state(1, 20)), ("y", state(2, 10))]);
+        assert_eq!(
+            plan(&a, &b, &Baseline::default()),
+            [copy("x", Side::B), conflict_won_by("y", Side::A)]
+        );
+    }
+
+    #[test]
+    fn the_window_hides_small_mtime_differences() {
+        let was = state(1, 10);
+        let baseline = base(&[("x", was, was)]);
+        let a = snap(&[("x", state(1, 11))]);
+        let b = snap(&[("x", was)]);
+        assert!(plan_bidirectional(&a, &b, &baseline, Duration::from_secs(2)).is_empty());
+        assert_eq!(plan(&a, &b, &baseline), [copy("x", Side::A)]);
+    }
+
+    #[test]
+    fn counts_follow_the_actions() {
+        let a = snap(&[
+            ("new", state(1, 10)),
+            ("upd", state(1, 20)),
+            ("same", state(1, 10)),
+        ]);
+        let b = snap(&[
+            ("upd", state(1, 10)),
+            ("same", state(1, 10)),
+            ("c", state(1, 10)),
+        ]);
+        let actions = [
+            copy("new", Side::A),
+            copy("upd", Side::A),
+            conflict_won_by("c", Side::B),
+            SyncAction::Delete {
+                path: "d".into(),
+                side: Side::A,
+            },
+        ];
+        assert_eq!(
+            SyncCounts::tally(&actions, &a, &b),
+            SyncCounts {
+                copied: 1,
+                updated: 1,
+                skipped: 1,
+                deleted: 1,
+                conflicts: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn a_baseline_holds_files_on_both_sides_and_survives_a_save() {
+        let dir = TempDir::new().unwrap();
+        let a = snap(&[("both", state(1, 10)), ("a-only", state(1, 10))]);
+        let b = snap(&[("both", state(1, 20))]);
+        let baseline = Baseline::from_snapshots(&a, &b);
+        assert_eq!(baseline.files.keys().collect::<Vec<_>>(), ["both"]);
+
+        let path = dir.path().join("state.json");
+        assert!(Baseline::load(&path).unwrap().files.is_empty());
+        baseline
+            .save(&path, &RealFs, &NamingContext::default())
+            .unwrap();
+        assert_eq!(Baseline::load(&path).unwrap().files, baseline.files);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        fs::write(&path, "{").unwrap();
+        assert!(matches!(
+            Baseline::load(&path),
+            Err(FmanError::InvalidInput(_))
+        ));
+    }
+
+    #[test]
+    fn a_conflict_keeps_the_loser_on_both_sides() {
+        let dir = TempDir::new().unwrap();
+        let (a, b) = (dir.path().join("a"), dir.path().join("b"));
+        fs::create_dir_all(a.join("sub")).unwrap();
+        fs::create_dir_all(b.join("sub")).unwrap();
+        fs::write(a.join("sub/x"), "from a").unwrap();
+        fs::write(b.join("sub/x"), "from b").unwrap();
+        let filesystem: SharedFs = Arc::new(RealFs);
+        let copies = execute(
+            &a,
+            &b,
+            &[conflict_won_by("sub/x", Side::A)],
+            &filesystem,
+            &NamingContext::default(),
+            None,
+            &mut failures(),
+        )
+        .unwrap();
+        let [copy] = copies.as_slice() else {
+            panic!("{copies:?}")
+        };
+        assert!(copy.starts_with("sub/x.conflict-"), "{copy}");
+        for root in [&a, &b] {
+            assert_eq!(fs::read_to_string(root.join("sub/x")).unwrap(), "from a");
+            assert_eq!(fs::read_to_string(root.join(copy)).unwrap(), "from b");
+        }
+    }
+
+    #[test]
+    fn a_dry_run_conflict_copies_the_loser_from_its_old_name() {
+        let dir = TempDir::new().unwrap();
+        let (a, b) = (dir.path().join("a"), dir.path().join("b"));
+        fs::create_dir(&a).unwrap();
+        fs::create_dir(&b).unwrap();
+        fs::write(a.join("x"), "from a").unwrap();
+        fs::write(b.join("x"), "from b").unwrap();
+        let dry_run = Arc::new(DryRunFs::new());
+        let filesystem: SharedFs = dry_run.clone();
+        let copies = execute(
+            &a,
+            &b,
+            &[conflict_won_by("x", Side::A)],
+            &filesystem,
+            &NamingContext::default(),
+            None,
+            &mut failures(),
+        )
+        .unwrap();
+        let [copy] = copies.as_slice() else {
+            panic!("{copies:?}")
+        };
+        let ops = dry_run.ops();
+        assert!(
+            ops.iter().any(|op| matches!(
+                op,
+                FsOp::Copy { from, to, .. } if *from == b.join("x") && *to == a.join(copy)
+            )),
+            "{ops:?}"
+        );
+        assert_eq!(fs::read_to_string(b.join("x")).unwrap(), "from b");
+        assert!(!b.join(copy).exists());
+    }
+
+    fn conflict_won_by(path: &str, winner: Side) -> SyncAction {
+        SyncAction::Conflict {
+            path: path.to_string(),
+            winner,
+        }
+    }
+
+    #[test]
+    fn a_budget_stops_a_sync_before_the_file_that_would_cross_it() {
+        let dir = TempDir::new().unwrap();
+        let (a, b) = (dir.path().join("a"), dir.path().join("b"));
+        fs::create_dir(&a).unwrap();
+        for name in ["1", "2", "3"] {
+            fs::write(a.join(name), [b'x'; 10]).unwrap();
+        }
+        let filesystem: SharedFs = Arc::new(RealFs);
+        let budget = ByteBudget::new(25);
+        let err = sync_one_way(
+            &a,
+            &b,
+            &SyncScope::default(),
+            &filesystem,
+            &NamingContext::default(),
+            Some(&budget),
+            failures(),
+        )
+        .unwrap_err();
+        assert!(
+            matches!(
+                err,
+                FmanError::QuotaExceeded {
+                    limit: 25,
+                    written: 20
+                }
+            ),
+            "{err:?}"
+        );
+        assert!(b.join("1").exists() && b.join("2").exists());
+        assert!(!b.join("3").exists());
+    }
+
+    #[test]
+    fn a_size_difference_is_a_change_even_at_the_same_time() {
+        let a = snap(&[("x", state(2, 10))]);
+        let b = snap(&[("x", state(1, 10))]);
+        assert_eq!(
+            plan_one_way(&a, &b, Duration::from_secs(60)),
+            [copy("x", Side::A)]
+        );
+    }
+
+    #[test]
+    fn one_way_sync_creates_subdirectories_and_leaves_extras() {
+        let dir = TempDir::new().unwrap();
+        let (a, b) = (dir.path().join("a"), dir.path().join("b"));
+        fs::create_dir_all(a.join("s/t")).unwrap();
+        fs::write(a.join("s/t/deep"), "1").unwrap();
+        fs::create_dir(&b).unwrap();
+        fs::write(b.join("extra"), "2").unwrap();
+        let filesystem: SharedFs = Arc::new(RealFs);
+        let report = sync_one_way(
+            &a,
+            &b,
+            &SyncScope::default(),
+            &filesystem,
+            &NamingContext::default(),
+            None,
+            failures(),
+        )
+        .unwrap();
+        assert_eq!(fs::read_to_string(b.join("s/t/deep")).unwrap(), "1");
+        assert_eq!(fs::read_to_string(b.join("extra")).unwrap(), "2");
+        assert_eq!(report.counts.copied, 1);
+    }
+
+    #[test]
+    fn a_dry_run_plans_only_what_is_missing() {
+        let dir = TempDir::new().unwrap();
+        let (a, b) = (dir.path().join("a"), dir.path().join("b"));
+        fs::create_dir(&a).unwrap();
+        fs::create_dir(&b).unwrap();
+        fs::write(a.join("new"), "1").unwrap();
+        let dry_run = Arc::new(DryRunFs::new());
+        let filesystem: SharedFs = dry_run.clone();
+        let report = sync_one_way(
+            &a,
+            &b,
+            &SyncScope::default(),
+            &filesystem,
+            &NamingContext::default(),
+            None,
+            failures(),
+        )
+        .unwrap();
+        assert_eq!(report.actions, [copy("new", Side::A)]);
+        let ops = dry_run.ops();
+        assert!(
+            !ops.iter()
+                .any(|op| matches!(op, FsOp::CreateDirAll { path } if *path == b)),
+            "{ops:?}"
+        );
+        assert!(!b.join("new").exists());
+    }
+}
@@ -0,0 +1,539 @@
+//! Instantiation of directory skeletons with `{{name}}` placeholders.
+//!
+//! A template is an ordinary directory. Placeholders are substituted in
+//! file and directory names, and inside text files up to a size limit;
+//! binary files (anything containing a NUL byte) are copied verbatim. An
+//! optional `template.toml` at the template root declares variables:
+//!
+//! ```toml
+//! [variables]
+//! name = { required = true }
+//! year = { default = "2025" }
+//! ```
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::Deserialize;
+#[cfg(feature = "json")]
+use serde::Serialize;
+
+use crate::copy::{CopyOptions, copy_file};
+use crate::error::{FmanError, FmanResult};
+use crate::fs::{RealFs, SharedFs};
+use crate::validate::{ensure_exists, ensure_is_dir, ensure_not_exists, ensure_parents_are_dirs};
+
+/// Name of the manifest file at the template root. It is never copied.
+pub const MANIFEST_NAME: &str = "template.toml";
+
+/// Files larger than this are copied without substitution by default.
+pub const DEFAULT_MAX_SUBSTITUTE_SIZE: u64 = 1024 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct TemplateOptions {
+    vars: BTreeMap<String, String>,
+    strict_vars: bool,
+    max_substitute_size: u64,
+    force: bool,
+    fs: Option<SharedFs>,
+}
+
+impl Default for TemplateOptions {
+    fn default() -> Self {
+        Self {
+            vars: BTreeMap::new(),
+            strict_vars: false,
+            max_substitute_size: DEFAULT_MAX_SUBSTITUTE_SIZE,
+            force: false,
+            fs: None,
+        }
+    }
+}
+
+impl TemplateOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Define a variable, overriding any manifest default.
+    pub fn var(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.vars.insert(name.into(), value.into());
+        self
+    }
+
+    /// Treat unknown placeholders as errors instead of warnings.
+    pub fn strict_vars(mut self, strict: bool) -> Self {
+        self.strict_vars = strict;
+        self
+    }
+
+    /// Only substitute inside files up to this many bytes.
+    pub fn max_substitute_size(mut self, bytes: u64) -> Self {
+        self.max_substitute_size = bytes;
+        self
+    }
+
+    /// Overwrite files that already exist in the destination.
+    pub fn force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    /// Make every change to the filesystem through `fs`.
+    pub fn fs(mut self, fs: SharedFs) -> Self {
+        self.fs = Some(fs);
+        self
+    }
+}
+
+/// What an instantiation produced.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "json", derive(Serialize))]
+pub struct TemplateReport {
+    pub files: usize,
+    pub directories: usize,
+    /// Unknown placeholders, one message per offending path.
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Manifest {
+    #[serde(default)]
+    variables: BTreeMap<String, VariableSpec>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct VariableSpec {
+    #[serde(default)]
+    required: bool,
+    default: Option<String>,
+}
+
+enum PlannedEntry {
+    Dir(PathBuf),
+    File { src: PathBuf, dst: PathBuf },
+}
+
+struct Plan {
+    entries: Vec<PlannedEntry>,
+    /// Sources eligible for content substitution.
+    text_files: HashSet<PathBuf>,
+    warnings: Vec<String>,
+}
+
+/// Copy the `template` tree to `dest`, substituting placeholders.
+///
+/// Variables are checked against the manifest and every destination path is
+/// computed before anything is written, so a missing variable or a conflict
+/// leaves `dest` untouched.
+pub fn instantiate(
+    template: &Path,
+    dest: &Path,
+    options: &TemplateOptions,
+) -> FmanResult<TemplateReport> {
+    ensure_exists(template)?;
+    ensure_is_dir(template)?;
+    ensure_parents_are_dirs(dest)?;
+    if fs::metadata(dest).is_ok_and(|m| !m.is_dir()) {
+        return Err(FmanError::NotADirectory(dest.display().to_string()));
+    }
+
+    let vars = resolve_vars(&load_manifest(template)?, &options.vars)?;
+    let plan = plan(template, dest, &vars, options.max_substitute_size)?;
+
+    if options.strict_vars && !plan.warnings.is_empty() {
+        return Err(FmanError::InvalidInput(plan.warnings.join("; ")));
+    }
+    if !options.force {
+        for entry in &plan.entries {
+            if let PlannedEntry::File { dst, .. } = entry {
+                ensure_not_exists(dst)?;
+            }
+        }
+    }
+
+    let filesystem = options.fs.clone().unwrap_or_else(|| Arc::new(RealFs));
+    let text_files = plan.text_files;
+    let copy_options = CopyOptions::new()
+        .force(options.force)
+        .fs(filesystem.clone())
+        .transform(move |src| {
+            if !text_files.contains(src) {
+                return Ok(None);
+            }
+            let contents = fs::read_to_string(src)?;
+            Ok(Some(render(&contents, &vars).0.into_bytes()))
+        });
+
+    let mut report = TemplateReport {
+        warnings: plan.warnings,
+        ..TemplateReport::default()
+    };
+    filesystem.create_dir_all(dest)?;
+    for entry in &plan.entries {
+        match entry {
+            PlannedEntry::Dir(dst) => {
+                filesystem.create_dir_all(dst)?;
+                report.directories += 1;
+            }
+            PlannedEntry::File { src, dst } => {
+                copy_file(src, dst, &copy_options)?;
+                report.files += 1;
+            }
+        }
+    }
+    Ok(report)
+}
+
+fn load_manifest(template: &Path) -> FmanResult<Manifest> {
+    let path = template.join(MANIFEST_NAME);
+    if !path.is_file() {
+        return Ok(Manifest::default());
+    }
+    let text = fs::read_to_string(&path)?;
+    toml::from_str(&text)
+        .map_err(|e| FmanError::InvalidInput(format!("{}: {}", path.display(), e.message())))
+}
+
+fn resolve_vars(
+    manifest: &Manifest,
+    given: &BTreeMap<String, String>,
+) -> FmanResult<BTreeMap<String, String>> {
+    let mut vars = BTreeMap::new();
+    let mut missing = Vec::new();
+    for (name, spec) in &manifest.variables {
+        match (&spec.default, given.contains_key(name)) {
+            (Some(default), false) => {
+                vars.insert(name.clone(), default.clone());
+            }
+            (None, false) if spec.required => missing.push(name.as_str()),
+            _ => {}
+        }
+    }
+    if !missing.is_empty() {
+        return Err(FmanError::InvalidInput(format!(
+            "missing required template variables: {}",
+            missing.join(", ")
+        )));
+    }
+    vars.extend(given.iter().map(|(k, v)| (k.clone(), v.clone())));
+    Ok(vars)
+}
+
+fn plan(
+    template: &Path,
+    dest: &Path,
+    vars: &BTreeMap<String, String>,
+    max_substitute_size: u64,
+) -> FmanResult<Plan> {
+    let mut planner = Planner {
+        root: template,
+        vars,
+        max_substitute_size,
+        claimed: HashMap::new(),
+        plan: Plan {
+            entries: Vec::new(),
+            text_files: HashSet::new(),
+            warnings: Vec::new(),
+        },
+    };
+    planner.visit(template, dest)?;
+    Ok(planner.plan)
+}
+
+struct Planner<'a> {
+    root: &'a Path,
+    vars: &'a BTreeMap<String, String>,
+    max_substitute_size: u64,
+    /// Rendered destination -> the source that produced it.
+    claimed: HashMap<PathBuf, PathBuf>,
+    plan: Plan,
+}
+
+impl Planner<'_> {
+    fn visit(&mut self, dir: &Path, dest: &Path) -> FmanResult<()> {
+        let mut children: Vec<_> = fs::read_dir(dir)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<Result<_, _>>()?;
+        children.sort();
+
+        for src in children {
+            if dir == self.root && src.file_name() == Some(MANIFEST_NAME.as_ref()) {
+                continue;
+            }
+            let rel = src.strip_prefix(self.root).unwrap_or(&src).to_path_buf();
+            let (name, unknown) = render_name(&src, self.vars)?;
+            warn_unknown(&mut self.plan.warnings, &rel, &unknown);
+            let dst = dest.join(name);
+
+            if let Some(other) = self.claimed.insert(dst.clone(), src.clone()) {
+                return Err(FmanError::InvalidInput(format!(
+                    "{} and {} both render to {}",
+                    other.display(),
+                    src.display(),
+                    dst.display()
+                )));
+            }
+
+            let meta = fs::metadata(&src)?;
+            if meta.is_dir() {
+                self.plan.entries.push(PlannedEntry::Dir(dst.clone()));
+                self.visit(&src, &dst)?;
+                continue;
+            }
+
+            if meta.len() <= self.max_substitute_size {
+                let bytes = fs::read(&src)?;
+                if !bytes.contains(&0)
+                    && let Ok(text) = String::from_utf8(bytes)
+                {
+                    let (_, unknown) = render(&text, self.vars);
+                    warn_unknown(&mut self.plan.warnings, &rel, &unknown);
+                    self.plan.text_files.insert(src.clone());
+                }
+            }
+            self.plan.entries.push(PlannedEntry::File { src, dst });
+        }
+        Ok(())
+    }
+}
+
+/// Render placeholders in the final component of `src`.
+fn render_name(
+    src: &Path,
+    vars: &BTreeMap<String, String>,
+) -> FmanResult<(OsString, BTreeSet<String>)> {
+    let name = src.file_name().unwrap_or_default();
+    let Some(text) = name.to_str() else {
+        return Ok((name.to_os_string(), BTreeSet::new()));
+    };
+    let (rendered, unknown) = render(text, vars);
+    if rendered.is_empty() || rendered == "." || rendered == ".." || rendered.contains(['/', '\\'])
+    {
+        return Err(FmanError::InvalidInput(format!(
+            "{} renders to invalid name {rendered:?}",
+            src.display()
+        )));
+    }
+    Ok((OsString::from(rendered), unknown))
+}
+
+fn warn_unknown(warnings: &mut Vec<String>, rel: &Path, unknown: &BTreeSet<String>) {
+    if unknown.is_empty() {
+        return;
+    }
+    let names: Vec<_> = unknown.iter().map(|n| format!("{{{{{n}}}}}")).collect();
+    warnings.push(format!(
+        "unknown placeholder {} in {}",
+        names.join(", "),
+        rel.display()
+    ));
+}
+
+/// Substitute `{{name}}` placeholders in `input`.
+///
+/// Returns the rendered text and the names of placeholders that had no
+/// value; those are left in the output untouched.
+pub fn render(input: &str, vars: &BTreeMap<String, String>) -> (String, BTreeSet<String>) {
+    let mut out = String::with_capacity(input.len());
+    let mut unknown = BTreeSet::new();
+    let mut rest = input;
+
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let Some(end) = after.find("}}") else {
+            rest = &rest[start..];
+            break;
+        };
+        let name = after[..end].trim();
+        if is_var_name(name) {
+            match vars.get(name) {
+                Some(value) => out.push_str(value),
+                None => {
+                    unknown.insert(name.to_string());
+                    out.push_str(&rest[start..start + 2 + end + 2]);
+                }
+            }
+            rest = &after[end + 2..];
+        } else {
+            out.push_str("{{");
+            rest = after;
+        }
+    }
+    out.push_str(rest);
+    (out, unknown)
+}
+
+fn is_var_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    use crate::fs::{DryRunFs, FsOp};
+
+    #[test]
+    fn dry_run_plans_the_whole_tree_without_writing() {
+        let dir = TempDir::new().unwrap();
+        let template = dir.path().join("tpl");
+        fs::create_dir_all(template.join("{{name}}")).unwrap();
+        fs::write(template.join("{{name}}/main.txt"), "hi {{name}}").unwrap();
+        let dest = dir.path().join("out");
+        let dry_run = Arc::new(DryRunFs::new());
+        let options = TemplateOptions::new()
+            .var("name", "app")
+            .fs(dry_run.clone());
+
+        let report = instantiate(&template, &dest, &options).unwrap();
+        assert_eq!(report.files, 1);
+        assert!(!dest.exists());
+        let copied = dest.join("app/main.txt");
+        assert!(
+            dry_run
+                .ops()
+                .iter()
+                .any(|op| matches!(op, FsOp::Copy { to, .. } if *to == copied)),
+            "{:?}",
+            dry_run.ops()
+        );
+    }
+
+    #[test]
+    fn render_substitutes_known_variables() {
+        let vars = BTreeMap::from([("name".to_string(), "app".to_string())]);
+        let (out, unknown) = render("{{name}} {{ name }} {{other}} {{", &vars);
+        assert_eq!(out, "app app {{other}} {{");
+        assert_eq!(unknown, BTreeSet::from(["other".to_string()]));
+    }
+
+    fn template(root: &Path, files: &[(&str, &str)]) -> PathBuf {
+        let template = root.join("tpl");
+        for (name, contents) in files {
+            let path = template.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        template
+    }
+
+    #[test]
+    fn names_and_contents_are_substituted() {
+        let dir = TempDir::new().unwrap();
+        let template = template(
+            dir.path(),
+            &[
+                ("{{name}}/README", "# {{name}}"),
+                ("{{name}}/bin", "\0{{name}}"),
+            ],
+        );
+        let dest = dir.path().join("out");
+        let options = TemplateOptions::new().var("name", "app");
+        let report = instantiate(&template, &dest, &options).unwrap();
+        assert_eq!((report.files, report.directories), (2, 1));
+        assert_eq!(
+            fs::read_to_string(dest.join("app/README")).unwrap(),
+            "# app"
+        );
+        assert!(report.warnings.is_empty());
+    }
+
+    #[test]
+    fn files_over_the_size_limit_are_copied_as_they_are() {
+        let dir = TempDir::new().unwrap();
+        let template = template(dir.path(), &[("big", "{{name}} {{name}}")]);
+        let dest = dir.path().join("out");
+        let options = TemplateOptions::new()
+            .var("name", "x")
+            .max_substitute_size(4);
+        instantiate(&template, &dest, &options).unwrap();
+        assert_eq!(
+            fs::read_to_string(dest.join("big")).unwrap(),
+            "{{name}} {{name}}"
+        );
+    }
+
+    #[test]
+    fn the_manifest_supplies_defaults_and_requires_variables() {
+        let dir = TempDir::new().unwrap();
+        let manifest = concat!(
+            "[variables.name]\nrequired = true\n",
+            "[variables.license]\ndefault = \"MIT\"\n",
+        );
+        let template = template(
+            dir.path(),
+            &[
+                (MANIFEST_NAME, manifest),
+                ("LICENSE", "{{license}} for {{name}}"),
+            ],
+        );
+        let dest = dir.path().join("out");
+        let err = instantiate(&template, &dest, &TemplateOptions::new()).unwrap_err();
+        assert!(matches!(&err, FmanError::InvalidInput(m) if m.contains("name")));
+        assert!(!dest.exists());
+
+        let options = TemplateOptions::new().var("name", "app");
+        instantiate(&template, &dest, &options).unwrap();
+        assert_eq!(
+            fs::read_to_string(dest.join("LICENSE")).unwrap(),
+            "MIT for app"
+        );
+        assert!(!dest.join(MANIFEST_NAME).exists());
+    }
+
+    #[test]
+    fn unknown_placeholders_warn_or_fail_when_strict() {
+        let dir = TempDir::new().unwrap();
+        let template = template(dir.path(), &[("a", "{{missing}}")]);
+        let dest = dir.path().join("out");
+        let report = instantiate(&template, &dest, &TemplateOptions::new()).unwrap();
+        assert_eq!(
+            report.warnings,
+            vec!["unknown placeholder {{missing}} in a"]
+        );
+
+        let strict = dir.path().join("strict");
+        let options = TemplateOptions::new().strict_vars(true);
+        assert!(instantiate(&template, &strict, &options).is_err());
+        assert!(!strict.exists());
+    }
+
+    #[test]
+    fn clashing_and_invalid_names_are_refused_before_writing() {
+        let dir = TempDir::new().unwrap();
+        let template = template(dir.path(), &[("{{a}}", "1"), ("{{b}}", "2")]);
+        let dest = dir.path().join("out");
+        let options = TemplateOptions::new().var("a", "same").var("b", "same");
+        let err = instantiate(&template, &dest, &options).unwrap_err();
+        assert!(matches!(&err, FmanError::InvalidInput(m) if m.contains("both render")));
+
+        let options = TemplateOptions::new().var("a", "..").var("b", "fine");
+        let err = instantiate(&template, &dest, &options).unwrap_err();
+        assert!(matches!(&err, FmanError::InvalidInput(m) if m.contains("invalid name")));
+        assert!(!dest.exists());
+    }
+
+    #[test]
+    fn existing_files_are_kept_unless_forced() {
+        let dir = TempDir::new().unwrap();
+        let template = template(dir.path(), &[("a", "new"), ("b", "new")]);
+        let dest = dir.path().join("out");
+        fs::create_dir(&dest).unwrap();
+        fs::write(dest.join("b"), "old").unwrap();
+        let err = instantiate(&template, &dest, &TemplateOptions::new()).unwrap_err();
+        assert_eq!(err.exit_code(), 3);
+        assert!(!dest.join("a").exists());
+        instantiate(&template, &dest, &TemplateOptions::new().force(true)).unwrap();
+        assert_eq!(fs::read_to_string(dest.join("b")).unwrap(), "new");
+    }
+}
@@ -0,0 +1,86 @@
+//! Wall-clock timing of operations, split into the data phase (moving file
+//! contents) and everything else.
+//!
+//! Timings are taken with [`Instant`](std::time::Instant), a vDSO read on
+//! the common platforms, twice per file; that is noise next to opening the
+//! file even for empty ones.
+
+use std::time::Duration;
+
+#[cfg(feature = "json")]
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timing {
+    /// From the start of the operation to its end.
+    pub wall: Duration,
+    /// Spent reading and writing file contents.
+    pub data: Duration,
+}
+
+impl Timing {
+    /// Time outside the data phase: validation, opening, creating
+    /// directories, setting attributes.
+    pub fn metadata(&self) -> Duration {
+        self.wall.saturating_sub(self.data)
+    }
+
+    /// Bytes per second over the data phase, if it took any measurable
+    /// time.
+    pub fn throughput(&self, bytes: u64) -> Option<f64> {
+        let seconds = self.data.as_secs_f64();
+        (seconds > 0.0).then(|| bytes as f64 / seconds)
+    }
+}
+
+/// Serialized as seconds: `wall_seconds`, `data_seconds` and
+/// `metadata_seconds`.
+#[cfg(feature = "json")]
+impl Serialize for Timing {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut timing = serializer.serialize_struct("Timing", 3)?;
+        timing.serialize_field("wall_seconds", &self.wall.as_secs_f64())?;
+        timing.serialize_field("data_seconds", &self.data.as_secs_f64())?;
+        timing.serialize_field("metadata_seconds", &self.metadata().as_secs_f64())?;
+        timing.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timing(wall_ms: u64, data_ms: u64) -> Timing {
+        Timing {
+            wall: Duration::from_millis(wall_ms),
+            data: Duration::from_millis(data_ms),
+        }
+    }
+
+    #[test]
+    fn metadata_is_the_wall_time_outside_the_data_phase() {
+        assert_eq!(timing(500, 200).metadata(), Duration::from_millis(300));
+        assert_eq!(timing(100, 200).metadata(), Duration::ZERO);
+    }
+
+    #[test]
+    fn throughput_is_bytes_over_data_time() {
+        assert_eq!(timing(1000, 250).throughput(1000), Some(4000.0));
+        assert_eq!(timing(1000, 0).throughput(1000), None);
+        assert_eq!(timing(0, 500).throughput(0), Some(0.0));
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn timing_serializes_as_seconds() {
+        let value = serde_json::to_value(timing(1500, 500)).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "wall_seconds": 1.5,
+                "data_seconds": 0.5,
+                "metadata_seconds": 1.0,
+            })
+        );
+    }
+}
@@ -0,0 +1,162 @@
+//! Creating files and bumping their timestamps, like `touch`.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::SystemTime;
+
+#[cfg(feature = "json")]
+use serde::Serialize;
+
+use crate::error::{FmanError, FmanResult};
+use crate::fs::Fs;
+use crate::validate::ensure_parents_are_dirs;
+
+/// What [`touch_path`] did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(Serialize), serde(rename_all = "kebab-case"))]
+pub enum Touched {
+    /// The file did not exist and was created empty.
+    Created,
+    /// Its access and modification times were set to now.
+    Updated,
+    /// It did not exist and creation was not wanted.
+    Skipped,
+}
+
+/// Create `path` as an empty file, or set its access and modification
+/// times to now if it exists; a directory's times are set too. With
+/// `no_create` a missing path is left missing.
+///
+/// A missing parent directory fails with `NotFound` naming that directory.
+pub fn touch_path(path: &Path, no_create: bool, filesystem: &dyn Fs) -> FmanResult<Touched> {
+    if fs::symlink_metadata(path).is_err() {
+        if no_create {
+            return Ok(Touched::Skipped);
+        }
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            ensure_parents_are_dirs(path)?;
+            if fs::metadata(parent).is_err() {
+                return Err(FmanError::missing_path(parent));
+            }
+        }
+        match filesystem.create_file(path, true) {
+            Ok(_) => return Ok(Touched::Created),
+            // Created by someone else since we looked; touch it instead.
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    let now = SystemTime::now();
+    filesystem.set_times(path, Some(now), Some(now))?;
+    Ok(Touched::Updated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs::File;
+    use std::time::Duration;
+
+    use tempfile::TempDir;
+
+    use crate::fs::{DryRunFs, FsOp, RealFs};
+
+    fn age(path: &Path) {
+        let old = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+    }
+
+    fn modified(path: &Path) -> SystemTime {
+        fs::metadata(path).unwrap().modified().unwrap()
+    }
+
+    #[test]
+    fn a_missing_file_is_created_empty() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("new");
+        assert_eq!(touch_path(&path, false, &RealFs).unwrap(), Touched::Created);
+        assert_eq!(fs::read(&path).unwrap(), b"");
+    }
+
+    #[test]
+    fn an_existing_file_gets_the_current_time_and_keeps_its_contents() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("old");
+        fs::write(&path, "keep").unwrap();
+        age(&path);
+        let before = SystemTime::now() - Duration::from_secs(60);
+        assert_eq!(touch_path(&path, false, &RealFs).unwrap(), Touched::Updated);
+        assert!(modified(&path) > before);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "keep");
+    }
+
+    #[test]
+    fn no_create_only_bumps_times() {
+        let dir = TempDir::new().unwrap();
+        let missing = dir.path().join("missing");
+        assert_eq!(
+            touch_path(&missing, true, &RealFs).unwrap(),
+            Touched::Skipped
+        );
+        assert!(!missing.exists());
+
+        let path = dir.path().join("old");
+        fs::write(&path, "x").unwrap();
+        age(&path);
+        assert_eq!(touch_path(&path, true, &RealFs).unwrap(), Touched::Updated);
+        assert!(modified(&path) > SystemTime::now() - Duration::from_secs(60));
+    }
+
+    #[test]
+    fn a_directory_gets_new_times_too() {
+        let dir = TempDir::new().unwrap();
+        let sub = dir.path().join("sub");
+        fs::create_dir(&sub).unwrap();
+        assert_eq!(touch_path(&sub, false, &RealFs).unwrap(), Touched::Updated);
+        assert!(sub.is_dir());
+    }
+
+    #[test]
+    fn a_missing_parent_is_named() {
+        let dir = TempDir::new().unwrap();
+        let parent = dir.path().join("no/such");
+        let err = touch_path(&parent.join("file"), false, &RealFs).unwrap_err();
+        assert!(err.is_not_found(), "{err}");
+        assert!(
+            err.to_string().contains(&parent.display().to_string()),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn a_file_in_place_of_a_parent_is_invalid() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("file");
+        fs::write(&file, "").unwrap();
+        let err = touch_path(&file.join("child"), false, &RealFs).unwrap_err();
+        assert_eq!(err.exit_code(), 4, "{err}");
+    }
+
+    #[test]
+    fn changes_go_through_the_filesystem() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("new");
+        let dry_run = DryRunFs::new();
+        touch_path(&path, false, &dry_run).unwrap();
+        assert_eq!(
+            dry_run.ops(),
+            [FsOp::CreateFile {
+                path: path.clone(),
+                create_new: true
+            }]
+        );
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,472 @@
+//! A freedesktop.org-style trash can.
+//!
+//! Trashed items live in `$XDG_DATA_HOME/Trash/files` (falling back to
+//! `~/.local/share/Trash`) with a matching `info/<name>.trashinfo` that
+//! records the original path and deletion time. Deletion times are written
+//! and read as UTC. Platforms without the freedesktop.org layout, where
+//! the system trash (the Recycle Bin on Windows) is not something fman
+//! can fill, get a can of the same shape in `~/.fman-trash`.
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::cleanup::{Cleanup, CleanupRegistry};
+use crate::du;
+use crate::error::{FmanError, FmanResult};
+use crate::format;
+use crate::fs::{Fs, SharedFs};
+use crate::mv::{MoveOptions, move_path};
+use crate::naming::NamingContext;
+
+const INFO_EXTENSION: &str = ".trashinfo";
+
+/// The trash can below the home directory, when `XDG_DATA_HOME` does not
+/// name another place.
+#[cfg(all(unix, not(target_os = "macos")))]
+const HOME_TRASH: &str = ".local/share/Trash";
+#[cfg(not(all(unix, not(target_os = "macos"))))]
+const HOME_TRASH: &str = ".fman-trash";
+
+/// Root of the trash can: the directory holding `files` and `info`.
+pub fn trash_root() -> FmanResult<PathBuf> {
+    #[cfg(all(unix, not(target_os = "macos")))]
+    if let Some(data) = std::env::var_os("XDG_DATA_HOME").filter(|v| !v.is_empty()) {
+        return Ok(PathBuf::from(data).join("Trash"));
+    }
+    let home = std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .filter(|v| !v.is_empty())
+        .ok_or_else(|| FmanError::not_found("home directory (HOME is not set)"))?;
+    Ok(PathBuf::from(home).join(HOME_TRASH))
+}
+
+/// Move `path` into the trash, returning its new location.
+///
+/// Name collisions in the trash get a numeric suffix from `naming`
+/// (`name.2`, `name.3`, ...); the `.trashinfo` is reserved with
+/// `create_new` first so concurrent trashing of equal names cannot collide.
+/// When the trash is on another filesystem the item is copied there and
+/// then removed, as [`move_path`] does. Every change is made through
+/// `filesystem`.
+pub fn trash_file(
+    path: &Path,
+    filesystem: &SharedFs,
+    naming: &NamingContext,
+) -> FmanResult<PathBuf> {
+    if fs::symlink_metadata(path).is_err() {
+        return Err(FmanError::missing_path(path));
+    }
+    let original = std::path::absolute(path)?;
+    let name = original
+        .file_name()
+        .ok_or_else(|| FmanError::InvalidInput(format!("cannot trash {}", path.display())))?
+        .to_string_lossy()
+        .into_owned();
+
+    let root = trash_root()?;
+    let (files, info) = (root.join("files"), root.join("info"));
+    for dir in [&files, &info] {
+        filesystem
+            .create_dir_all(dir)
+            .map_err(|e| FmanError::io_at(dir, e))?;
+    }
+
+    let deleted_at = format::format_time(SystemTime::now(), "%Y-%m-%dT%H:%M:%S");
+    let contents = format!(
+        "[Trash Info]\nPath={}\nDeletionDate={deleted_at}\n",
+        percent_encode(&original.to_string_lossy())
+    );
+
+    for candidate in naming.numbered_candidates(&name, INFO_EXTENSION.len()) {
+        let target = files.join(&candidate);
+        if fs::symlink_metadata(&target).is_ok() {
+            continue;
+        }
+        let info_path = info.join(format!("{candidate}{INFO_EXTENSION}"));
+        let info_file = match filesystem.create_file(&info_path, true) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e.into()),
+        };
+        let registry = CleanupRegistry::new();
+        registry.push(Cleanup::RemoveFile(info_path));
+        let moved = info_file
+            .map_or(Ok(()), |mut file| file.write_all(contents.as_bytes()))
+            .map_err(FmanError::from)
+            .and_then(|()| move_path(&original, &target, &MoveOptions::new(), filesystem));
+        return match moved {
+            Ok(_) => Ok(target),
+            Err(e) => registry.unwind(filesystem.as_ref(), None, e),
+        };
+    }
+    Err(naming.exhausted(&name))
+}
+
+/// One item in the trash.
+#[derive(Debug, Clone)]
+pub struct TrashItem {
+    /// Location of the payload under `files/`.
+    pub path: PathBuf,
+    pub info_path: PathBuf,
+    pub original: Option<PathBuf>,
+    pub deleted_at: Option<SystemTime>,
+    /// Apparent size of the payload, recursively for directories.
+    pub size: u64,
+    /// Why the `.trashinfo` could not be used, if it couldn't.
+    pub info_error: Option<String>,
+}
+
+/// Read every item in the trash at `root`.
+pub fn list_items(root: &Path) -> FmanResult<Vec<TrashItem>> {
+    let files = root.join("files");
+    if !files.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut items = Vec::new();
+    for entry in fs::read_dir(&files)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let info_path = root.join("info").join(format!("{name}{INFO_EXTENSION}"));
+        let size = du::disk_usage(&path).map_or(0, |r| r.total);
+        let mut item = TrashItem {
+            path,
+            info_path,
+            original: None,
+            deleted_at: None,
+            size,
+            info_error: None,
+        };
+        match fs::read_to_string(&item.info_path) {
+            Ok(text) => match parse_info(&text) {
+                Ok((original, deleted_at)) => {
+                    item.original = Some(original);
+                    item.deleted_at = Some(deleted_at);
+                }
+                Err(e) => item.info_error = Some(e),
+            },
+            Err(e) => item.info_error = Some(format!("missing .trashinfo: {e}")),
+        }
+        items.push(item);
+    }
+    items.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(items)
+}
+
+fn parse_info(text: &str) -> Result<(PathBuf, SystemTime), String> {
+    let mut lines = text.lines().map(str::trim).filter(|l| !l.is_empty());
+    if lines.next() != Some("[Trash Info]") {
+        return Err("missing [Trash Info] header".to_string());
+    }
+    let (mut original, mut deleted_at) = (None, None);
+    for line in lines {
+        match line.split_once('=') {
+            Some(("Path", value)) => original = Some(PathBuf::from(percent_decode(value))),
+            Some(("DeletionDate", value)) => {
+                deleted_at = Some(
+                    format::parse_time(value)
+                        .ok_or_else(|| format!("unparsable DeletionDate {value:?}"))?,
+                );
+            }
+            _ => {}
+        }
+    }
+    match (original, deleted_at) {
+        (Some(original), Some(deleted_at)) => Ok((original, deleted_at)),
+        (None, _) => Err("no Path entry".to_string()),
+        (_, None) => Err("no DeletionDate entry".to_string()),
+    }
+}
+
+/// Which items `trash empty` removes.
+#[derive(Debug, Clone, Default)]
+pub struct EmptyFilter {
+    /// Only items deleted at least this long ago.
+    pub older_than: Option<Duration>,
+    /// Only items whose payload is larger than this many bytes.
+    pub larger_than: Option<u64>,
+    /// Also remove items whose `.trashinfo` is missing or unparsable. Their
+    /// age is unknown, so the age filter does not protect them.
+    pub force: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct EmptyPlan {
+    pub remove: Vec<TrashItem>,
+    /// Items left alone because their `.trashinfo` was unusable.
+    pub invalid: Vec<TrashItem>,
+}
+
+impl EmptyPlan {
+    pub fn bytes(&self) -> u64 {
+        self.remove.iter().map(|item| item.size).sum()
+    }
+}
+
+/// Decide which of `items` to remove, as of `now`.
+pub fn plan_empty(items: Vec<TrashItem>, filter: &EmptyFilter, now: SystemTime) -> EmptyPlan {
+    let mut plan = EmptyPlan::default();
+    for item in items {
+        if filter.larger_than.is_some_and(|min| item.size <= min) {
+            continue;
+        }
+        if item.info_error.is_some() {
+            if filter.force {
+                plan.remove.push(item);
+            } else {
+                plan.invalid.push(item);
+            }
+            continue;
+        }
+        let old_enough = match (filter.older_than, item.deleted_at) {
+            (None, _) => true,
+            (Some(age), Some(deleted_at)) => now
+                .duration_since(deleted_at)
+                .is_ok_and(|elapsed| elapsed >= age),
+            (Some(_), None) => false,
+        };
+        if old_enough {
+            plan.remove.push(item);
+        }
+    }
+    plan
+}
+
+/// Permanently delete the planned items through `filesystem`, returning
+/// the bytes reclaimed.
+pub fn execute_empty(plan: &EmptyPlan, filesystem: &dyn Fs) -> FmanResult<u64> {
+    let mut reclaimed = 0;
+    for item in &plan.remove {
+        let path = &item.path;
+        let removed = match fs::symlink_metadata(path) {
+            Ok(meta) if meta.is_dir() => filesystem.remove_dir_all(path),
+            Ok(_) => filesystem.remove_file(path),
+            Err(e) => Err(e),
+        };
+        removed.map_err(|e| FmanError::io_at(path, e))?;
+        match filesystem.remove_file(&item.info_path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                return Err(FmanError::io_at(&item.info_path, e));
+            }
+            _ => {}
+        }
+        reclaimed += item.size;
+    }
+    Ok(reclaimed)
+}
+
+fn percent_encode(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-_.~".contains(&byte) {
+            out.push(byte as char);
+        } else {
+            out.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    out
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && let Some(hex) = text.get(i + 1..i + 3)
+            && let Ok(byte) = u8::from_str_radix(hex, 16)
+        {
+            out.push(byte);
+            i += 3;
+            continue;
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    use crate::fs::DryRunFs;
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    fn item(name: &str, size: u64, deleted_days_ago: Option<u64>, now: SystemTime) -> TrashItem {
+        TrashItem {
+            path: PathBuf::from("files").join(name),
+            info_path: PathBuf::from("info").join(format!("{name}{INFO_EXTENSION}")),
+            original: deleted_days_ago.map(|_| PathBuf::from("/home/me").join(name)),
+            deleted_at: deleted_days_ago.map(|days| now - DAY * days as u32),
+            size,
+            info_error: deleted_days_ago
+                .is_none()
+                .then(|| "no DeletionDate entry".to_string()),
+        }
+    }
+
+    fn names(items: &[TrashItem]) -> Vec<String> {
+        items
+            .iter()
+            .map(|item| {
+                item.path
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect()
+    }
+
+    fn items(now: SystemTime) -> Vec<TrashItem> {
+        vec![
+            item("old-big", 2000, Some(40), now),
+            item("old-small", 10, Some(30), now),
+            item("new-big", 2000, Some(29), now),
+            item("broken", 5000, None, now),
+        ]
+    }
+
+    #[test]
+    fn without_filters_everything_valid_goes() {
+        let now = SystemTime::now();
+        let plan = plan_empty(items(now), &EmptyFilter::default(), now);
+        assert_eq!(names(&plan.remove), ["old-big", "old-small", "new-big"]);
+        assert_eq!(names(&plan.invalid), ["broken"]);
+        assert_eq!(plan.bytes(), 4010);
+    }
+
+    #[test]
+    fn the_age_filter_keeps_recent_items() {
+        let now = SystemTime::now();
+        let filter = EmptyFilter {
+            older_than: Some(DAY * 30),
+            ..EmptyFilter::default()
+        };
+        let plan = plan_empty(items(now), &filter, now);
+        assert_eq!(names(&plan.remove), ["old-big", "old-small"]);
+    }
+
+    #[test]
+    fn the_size_filter_keeps_items_up_to_the_limit() {
+        let now = SystemTime::now();
+        let filter = EmptyFilter {
+            older_than: Some(DAY * 30),
+            larger_than: Some(10),
+            force: false,
+        };
+        let plan = plan_empty(items(now), &filter, now);
+        assert_eq!(names(&plan.remove), ["old-big"]);
+        assert_eq!(names(&plan.invalid), ["broken"]);
+    }
+
+    #[test]
+    fn force_removes_items_without_a_usable_info() {
+        let now = SystemTime::now();
+        let filter = EmptyFilter {
+            older_than: Some(DAY * 365),
+            force: true,
+            ..EmptyFilter::default()
+        };
+        let plan = plan_empty(items(now), &filter, now);
+        assert_eq!(names(&plan.remove), ["broken"]);
+        assert!(plan.invalid.is_empty());
+    }
+
+    #[test]
+    fn a_deletion_in_the_future_is_not_old() {
+        let now = SystemTime::now();
+        let mut future = item("future", 1, Some(0), now);
+        future.deleted_at = Some(now + DAY);
+        let filter = EmptyFilter {
+            older_than: Some(Duration::ZERO),
+            ..EmptyFilter::default()
+        };
+        assert!(plan_empty(vec![future], &filter, now).remove.is_empty());
+    }
+
+    #[test]
+    fn info_files_are_read_back() {
+        let (original, deleted_at) = parse_info(
+            "[Trash Info]\nPath=/home/me/a%20b%25.txt\nDeletionDate=2000-02-29T12:34:56\n",
+        )
+        .unwrap();
+        assert_eq!(original, PathBuf::from("/home/me/a b%.txt"));
+        assert_eq!(
+            deleted_at,
+            SystemTime::UNIX_EPOCH + Duration::from_secs(951827696)
+        );
+        assert_eq!(percent_decode(&percent_encode("/a b/ü%")), "/a b/ü%");
+        assert!(parse_info("Path=/a\n").unwrap_err().contains("header"));
+        assert!(
+            parse_info("[Trash Info]\nPath=/a\nDeletionDate=yesterday\n")
+                .unwrap_err()
+                .contains("unparsable")
+        );
+        assert!(
+            parse_info("[Trash Info]\nDeletionDate=2000-02-29T12:34:56\n")
+                .unwrap_err()
+                .contains("no Path")
+        );
+    }
+
+    #[test]
+    fn items_are_listed_with_their_info() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("files/tree")).unwrap();
+        fs::create_dir_all(root.join("info")).unwrap();
+        fs::write(root.join("files/tree/a"), "1234").unwrap();
+        fs::write(root.join("files/orphan"), "12").unwrap();
+        fs::write(
+            root.join("info/tree.trashinfo"),
+            "[Trash Info]\nPath=/x/tree\nDeletionDate=2000-02-29T12:34:56\n",
+        )
+        .unwrap();
+        let items = list_items(root).unwrap();
+        assert_eq!(names(&items), ["orphan", "tree"]);
+        assert!(items[0].info_error.as_deref().unwrap().contains("missing"));
+        assert_eq!(items[0].size, 2);
+        assert_eq!(items[1].original, Some(PathBuf::from("/x/tree")));
+        assert_eq!(items[1].size, 4);
+        assert!(list_items(&root.join("nowhere")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn emptying_removes_payload_and_info() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("files/tree")).unwrap();
+        fs::create_dir_all(root.join("info")).unwrap();
+        fs::write(root.join("files/tree/a"), "1234").unwrap();
+        fs::write(root.join("files/f"), "12").unwrap();
+        fs::write(root.join("info/f.trashinfo"), "").unwrap();
+        let plan = EmptyPlan {
+            remove: list_items(root).unwrap(),
+            invalid: Vec::new(),
+        };
+
+        let dry_run = DryRunFs::new();
+        assert_eq!(execute_empty(&plan, &dry_run).unwrap(), 6);
+        assert!(root.join("files/f").exists());
+
+        assert_eq!(execute_empty(&plan, &crate::fs::RealFs).unwrap(), 6);
+        assert_eq!(fs::read_dir(root.join("files")).unwrap().count(), 0);
+        assert_eq!(fs::read_dir(root.join("info")).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn percent_encoding_round_trips() {
+        let path = "/home/me/a b/50%/naïve#1.txt";
+        let encoded = percent_encode(path);
+        assert_eq!(encoded, "/home/me/a%20b/50%25/na%C3%AFve%231.txt");
+        assert_eq!(percent_decode(&encoded), path);
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz"), "%zz");
+    }
+}
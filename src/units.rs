@@ -0,0 +1,70 @@
+//! Parsing and formatting of human-friendly durations and sizes.
+
+use std::time::Duration;
+
+use crate::error::{FmanError, FmanResult};
+
+/// Parse a duration such as `500ms`, `5s`, `2m`, `1h`, `30d` or `2w`.
+/// A bare number is taken as seconds.
+pub fn parse_duration(input: &str) -> FmanResult<Duration> {
+    let s = input.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (digits, unit) = s.split_at(split);
+    let value: u64 = digits
+        .parse()
+        .map_err(|_| FmanError::InvalidInput(format!("invalid duration {input:?}")))?;
+    let secs = match unit {
+        "ms" => return Ok(Duration::from_millis(value)),
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86_400,
+        "w" => 7 * 86_400,
+        _ => {
+            return Err(FmanError::InvalidInput(format!(
+                "invalid duration {input:?} (units: ms, s, m, h, d, w)"
+            )));
+        }
+    };
+    Ok(Duration::from_secs(value.saturating_mul(secs)))
+}
+
+/// Parse a size such as `512`, `64K`, `1.5M` or `1GiB` (binary units).
+pub fn parse_size(input: &str) -> FmanResult<u64> {
+    let s = input.trim();
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let invalid = || FmanError::InvalidInput(format!("invalid size {input:?}"));
+    let value: f64 = number.parse().map_err(|_| invalid())?;
+    let shift = match unit.trim_end_matches("iB").trim_end_matches('B') {
+        "" => 0,
+        "K" | "k" => 10,
+        "M" | "m" => 20,
+        "G" | "g" => 30,
+        "T" | "t" => 40,
+        "P" | "p" => 50,
+        _ => return Err(invalid()),
+    };
+    Ok((value * (1u64 << shift) as f64) as u64)
+}
+
+/// Format a byte count with binary units, e.g. `512B`, `1.5K`, `20G`.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 6] = ["K", "M", "G", "T", "P", "E"];
+    if bytes < 1024 {
+        return format!("{bytes}B");
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if value < 10.0 {
+        format!("{value:.1}{}", UNITS[unit])
+    } else {
+        format!("{value:.0}{}", UNITS[unit])
+    }
+}
@@ -0,0 +1,225 @@
+use std::fs;
+use std::path::Path;
+
+use crate::error::{FmanError, FmanResult};
+
+/// Fails with `NotFound` if `path` does not exist.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(level = "debug", ret, err(level = "debug"))
+)]
+pub fn ensure_exists(path: &Path) -> FmanResult<()> {
+    if !path.exists() {
+        return Err(FmanError::missing_path(path));
+    }
+    Ok(())
+}
+
+/// Fails with `InvalidInput` if `path` is not a regular file.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(level = "debug", ret, err(level = "debug"))
+)]
+pub fn ensure_is_file(path: &Path) -> FmanResult<()> {
+    if !path.is_file() {
+        return Err(FmanError::InvalidInput(format!(
+            "{} is not a file",
+            path.display()
+        )));
+    }
+    Ok(())
+}
+
+/// Fails with `InvalidInput` if `path` is not a directory.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(level = "debug", ret, err(level = "debug"))
+)]
+pub fn ensure_is_dir(path: &Path) -> FmanResult<()> {
+    if !path.is_dir() {
+        return Err(FmanError::InvalidInput(format!(
+            "{} is not a directory",
+            path.display()
+        )));
+    }
+    Ok(())
+}
+
+/// Fails with `NotADirectory`, naming the offending component, if an
+/// existing ancestor of `path` is not a directory, so that nothing can be
+/// created at `path`. The nearest existing ancestor decides; symlinks to
+/// directories count as directories.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(level = "debug", ret, err(level = "debug"))
+)]
+pub fn ensure_parents_are_dirs(path: &Path) -> FmanResult<()> {
+    for ancestor in path.ancestors().skip(1) {
+        if ancestor.as_os_str().is_empty() {
+            break;
+        }
+        match fs::metadata(ancestor) {
+            Ok(meta) if meta.is_dir() => break,
+            Ok(_) => return Err(FmanError::NotADirectory(ancestor.display().to_string())),
+            Err(_) if fs::symlink_metadata(ancestor).is_ok() => {
+                // A dangling link: it cannot hold anything either.
+                return Err(FmanError::NotADirectory(ancestor.display().to_string()));
+            }
+            Err(_) => {}
+        }
+    }
+    Ok(())
+}
+
+/// Fails with `AlreadyExists` if something is already at `path`.
+///
+/// Symlinks are not followed: a link counts as existing even when its
+/// target is missing, so nothing is ever written through a dangling link.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(level = "debug", ret, err(level = "debug"))
+)]
+pub fn ensure_not_exists(path: &Path) -> FmanResult<()> {
+    if fs::symlink_metadata(path).is_ok() {
+        return Err(FmanError::AlreadyExists(path.display().to_string()));
+    }
+    Ok(())
+}
+
+/// Fails with `SameFile` if `dst` already refers to the same file as `src`,
+/// either by path (after resolving symlinks) or, on Unix, by device and
+/// inode, which also catches hard links.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(level = "debug", ret, err(level = "debug"))
+)]
+pub fn ensure_not_same_file(src: &Path, dst: &Path) -> FmanResult<()> {
+    let (Ok(src_meta), Ok(dst_meta)) = (fs::metadata(src), fs::metadata(dst)) else {
+        return Ok(());
+    };
+    if same_inode(&src_meta, &dst_meta)
+        || matches!((src.canonicalize(), dst.canonicalize()), (Ok(a), Ok(b)) if a == b)
+    {
+        return Err(FmanError::SameFile(dst.display().to_string()));
+    }
+    Ok(())
+}
+
+/// Fails with `SameFile` if `src` and `dst` describe the same file on disk.
+/// Used when one side is an open handle with no path to compare; only
+/// detectable on Unix, by device and inode.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(level = "debug", skip(src, dst), ret, err(level = "debug"))
+)]
+pub fn ensure_not_same_inode(
+    src: &fs::Metadata,
+    dst: &fs::Metadata,
+    dst_name: &str,
+) -> FmanResult<()> {
+    if same_inode(src, dst) {
+        return Err(FmanError::SameFile(dst_name.to_string()));
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+pub(crate) fn same_inode(a: &fs::Metadata, b: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    a.dev() == b.dev() && a.ino() == b.ino()
+}
+
+#[cfg(not(unix))]
+pub(crate) fn same_inode(_: &fs::Metadata, _: &fs::Metadata) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    fn assert_same_file(result: FmanResult<()>) {
+        assert!(
+            matches!(result, Err(FmanError::SameFile(_))),
+            "expected SameFile, got {result:?}"
+        );
+    }
+
+    #[test]
+    fn the_same_path_is_the_same_file() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("a.txt");
+        fs::write(&file, "a").unwrap();
+        assert_same_file(ensure_not_same_file(&file, &file));
+        assert_same_file(ensure_not_same_file(
+            &file,
+            &dir.path().join(".").join("a.txt"),
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_hard_link_is_the_same_file() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("a.txt");
+        let link = dir.path().join("b.txt");
+        fs::write(&file, "a").unwrap();
+        fs::hard_link(&file, &link).unwrap();
+        assert_same_file(ensure_not_same_file(&file, &link));
+    }
+
+    #[test]
+    fn different_or_missing_files_pass() {
+        let dir = TempDir::new().unwrap();
+        let (a, b) = (dir.path().join("a.txt"), dir.path().join("b.txt"));
+        fs::write(&a, "same").unwrap();
+        ensure_not_same_file(&a, &b).unwrap();
+        fs::write(&b, "same").unwrap();
+        ensure_not_same_file(&a, &b).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_dangling_symlink_exists() {
+        let dir = TempDir::new().unwrap();
+        let link = dir.path().join("link");
+        std::os::unix::fs::symlink(dir.path().join("missing"), &link).unwrap();
+        assert!(matches!(
+            ensure_not_exists(&link),
+            Err(FmanError::AlreadyExists(_))
+        ));
+        assert!(matches!(
+            ensure_parents_are_dirs(&link.join("child")),
+            Err(FmanError::NotADirectory(_))
+        ));
+    }
+
+    #[test]
+    fn a_file_among_the_parents_is_named() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("file");
+        fs::write(&file, "").unwrap();
+        for path in [file.join("b.txt"), file.join("x/y/b.txt")] {
+            match ensure_parents_are_dirs(&path) {
+                Err(FmanError::NotADirectory(component)) => {
+                    assert_eq!(component, file.display().to_string())
+                }
+                other => panic!("{path:?}: {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn missing_or_directory_parents_are_fine() {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir(dir.path().join("d")).unwrap();
+        ensure_parents_are_dirs(&dir.path().join("d/b.txt")).unwrap();
+        ensure_parents_are_dirs(&dir.path().join("d/new/deeper/b.txt")).unwrap();
+        ensure_parents_are_dirs(Path::new("relative.txt")).unwrap();
+        // The path itself may be anything.
+        fs::write(dir.path().join("d/f"), "").unwrap();
+        ensure_parents_are_dirs(&dir.path().join("d/f")).unwrap();
+    }
+}
@@ -0,0 +1,575 @@
+//! Depth-first directory traversal shared by the recursive commands.
+
+use std::ffi::OsStr;
+use std::fs::{self, FileType, Metadata};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::cachedir::{self, ExcludeCaches};
+use crate::error::FmanResult;
+use crate::spill::{ExternalSorter, Sorted};
+
+/// Default [`WalkOrder::SortedLowMemory`] bound.
+pub const DEFAULT_MAX_ENTRIES_IN_MEMORY: usize = 100_000;
+
+/// Which symlinks an operation follows, by where they are met.
+///
+/// Depth 0 is the path the user gave; deeper entries were found while
+/// recursing. The variants match `cp -P`, `-H` and `-L`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    /// Never follow: links are handled as links (`-P`).
+    Never,
+    /// Follow links given on the command line, keep links found while
+    /// recursing (`-H`).
+    #[default]
+    CommandLine,
+    /// Follow every link (`-L`).
+    Always,
+}
+
+impl SymlinkPolicy {
+    /// Whether a symlink met at `depth` is followed.
+    pub fn follows(self, depth: usize) -> bool {
+        match self {
+            SymlinkPolicy::Never => false,
+            SymlinkPolicy::CommandLine => depth == 0,
+            SymlinkPolicy::Always => true,
+        }
+    }
+}
+
+/// In which order a [`Walk`] yields the children of each directory.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WalkOrder {
+    /// By name, reading each directory fully into memory to sort it.
+    #[default]
+    Sorted,
+    /// As the directory returns them, holding none of them; memory use
+    /// depends only on the depth.
+    Unsorted,
+    /// By name, holding at most `max_in_memory` names per open directory
+    /// and sorting the rest through temporary files.
+    SortedLowMemory { max_in_memory: usize },
+}
+
+/// One entry produced by [`Walk`].
+#[derive(Debug, Clone)]
+pub struct WalkEntry {
+    path: PathBuf,
+    depth: usize,
+    file_type: FileType,
+    followed: bool,
+}
+
+impl WalkEntry {
+    /// The entry's path: the walk root joined with its relative path.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// 1 for direct children of the root, 2 for grandchildren, and so on.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// The entry's type. A symlink the walk follows reports its target's
+    /// type; any other symlink reports itself.
+    pub fn file_type(&self) -> FileType {
+        self.file_type
+    }
+
+    /// Metadata of the entry, through the link if the walk follows it.
+    pub fn metadata(&self) -> FmanResult<Metadata> {
+        if self.followed {
+            return Ok(fs::metadata(&self.path)?);
+        }
+        Ok(fs::symlink_metadata(&self.path)?)
+    }
+}
+
+/// Pre-order walk below a root directory, children sorted by name unless
+/// another [`WalkOrder`] is chosen.
+///
+/// The root itself is not yielded. Symlinks are followed according to the
+/// walk's [`SymlinkPolicy`] (by default only a symlinked root); a followed
+/// link to an ancestor directory yields an error instead of looping. A
+/// directory that cannot be read yields an error and the walk carries on
+/// with its siblings.
+pub struct Walk {
+    root: PathBuf,
+    symlinks: SymlinkPolicy,
+    order: WalkOrder,
+    max_depth: Option<usize>,
+    exclude_caches: Option<ExcludeCaches>,
+    skip_hidden: bool,
+    progress: Option<Progress>,
+    started: bool,
+    /// Open directories, innermost last.
+    stack: Vec<Level>,
+    pending_error: Option<io::Error>,
+}
+
+struct Level {
+    dir: PathBuf,
+    /// Depth of the directory's children.
+    depth: usize,
+    /// Canonical path of the directory, kept for loop detection when
+    /// following every link.
+    canonical: Option<PathBuf>,
+    entries: Pending,
+}
+
+/// The entries of an open directory still to be yielded.
+enum Pending {
+    /// Reversed so the next entry can be popped.
+    Sorted(Vec<WalkEntry>),
+    Unsorted(fs::ReadDir),
+    /// Names merged from spilled runs, stat-ed as they come out.
+    Spilled(Sorted),
+}
+
+struct Progress {
+    every: u64,
+    seen: u64,
+    report: Box<dyn FnMut(u64) + Send>,
+}
+
+impl Walk {
+    pub fn new(root: impl AsRef<Path>) -> Self {
+        Walk {
+            root: root.as_ref().to_path_buf(),
+            symlinks: SymlinkPolicy::default(),
+            order: WalkOrder::default(),
+            max_depth: None,
+            exclude_caches: None,
+            skip_hidden: false,
+            progress: None,
+            started: false,
+            stack: Vec::new(),
+            pending_error: None,
+        }
+    }
+
+    /// Do not yield entries deeper than `depth` (1 = direct children only).
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    /// Prune directories below the root tagged as caches (see
+    /// [`cachedir`]), keeping what `mode` says of them.
+    pub fn exclude_caches(mut self, mode: ExcludeCaches) -> Self {
+        self.exclude_caches = Some(mode);
+        self
+    }
+
+    /// Leave out entries below the root whose names start with a dot, and
+    /// everything inside such directories.
+    pub fn skip_hidden(mut self, skip: bool) -> Self {
+        self.skip_hidden = skip;
+        self
+    }
+
+    /// Follow symlinks according to `policy`.
+    pub fn symlinks(mut self, policy: SymlinkPolicy) -> Self {
+        self.symlinks = policy;
+        self
+    }
+
+    /// Yield each directory's children in `order`.
+    pub fn order(mut self, order: WalkOrder) -> Self {
+        self.order = order;
+        self
+    }
+
+    /// Call `report` with the number of entries yielded so far after every
+    /// `every` of them, so long walks can show activity.
+    pub fn progress(mut self, every: u64, report: impl FnMut(u64) + Send + 'static) -> Self {
+        self.progress = Some(Progress {
+            every: every.max(1),
+            seen: 0,
+            report: Box::new(report),
+        });
+        self
+    }
+
+    fn start(&mut self) {
+        let root_is_link = fs::symlink_metadata(&self.root).is_ok_and(|m| m.is_symlink());
+        if (root_is_link && !self.symlinks.follows(0)) || self.max_depth == Some(0) {
+            return;
+        }
+        let root = self.root.clone();
+        self.push_dir(&root, 0);
+    }
+
+    fn push_dir(&mut self, dir: &Path, depth: usize) {
+        let entries = match self.order {
+            WalkOrder::Sorted => read_sorted(dir, depth + 1, self.symlinks).map(|mut entries| {
+                entries.reverse();
+                Pending::Sorted(entries)
+            }),
+            WalkOrder::Unsorted => fs::read_dir(dir).map(Pending::Unsorted),
+            WalkOrder::SortedLowMemory { max_in_memory } => {
+                sort_spilling(dir, max_in_memory).map(Pending::Spilled)
+            }
+        };
+        match entries {
+            Ok(entries) => {
+                let canonical = (self.symlinks == SymlinkPolicy::Always)
+                    .then(|| dir.canonicalize().ok())
+                    .flatten();
+                self.stack.push(Level {
+                    dir: dir.to_path_buf(),
+                    depth: depth + 1,
+                    canonical,
+                    entries,
+                });
+            }
+            // Name the directory; a bare "Permission denied" from deep in
+            // a walk says nothing about where.
+            Err(e) => {
+                self.pending_error = Some(io::Error::new(
+                    e.kind(),
+                    format!("cannot read {}: {e}", dir.display()),
+                ))
+            }
+        }
+    }
+
+    fn count(&mut self) {
+        if let Some(progress) = &mut self.progress {
+            progress.seen += 1;
+            if progress.seen % progress.every == 0 {
+                (progress.report)(progress.seen);
+            }
+        }
+    }
+
+    /// Open the cache directory `entry` as holding nothing but its tag.
+    fn push_tag(&mut self, entry: &WalkEntry) {
+        let tag = entry.path.join(cachedir::TAG_NAME);
+        let depth = entry.depth + 1;
+        let tag = fs::symlink_metadata(&tag)
+            .and_then(|meta| make_entry(tag, meta.file_type(), depth, self.symlinks));
+        match tag {
+            Ok(tag) => self.stack.push(Level {
+                dir: entry.path.clone(),
+                depth,
+                canonical: None,
+                entries: Pending::Sorted(vec![tag]),
+            }),
+            Err(e) => self.pending_error = Some(e),
+        }
+    }
+
+    /// Whether descending into the followed link `entry` would revisit a
+    /// directory that is already open.
+    fn is_loop(&self, entry: &WalkEntry) -> bool {
+        let Ok(target) = entry.path.canonicalize() else {
+            return false;
+        };
+        self.stack
+            .iter()
+            .any(|level| level.canonical.as_ref() == Some(&target))
+    }
+}
+
+impl Iterator for Walk {
+    type Item = FmanResult<WalkEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.started {
+            self.started = true;
+            self.start();
+        }
+        if let Some(e) = self.pending_error.take() {
+            return Some(Err(e.into()));
+        }
+        loop {
+            let symlinks = self.symlinks;
+            let level = self.stack.last_mut()?;
+            let entry = match level.next(symlinks) {
+                None => {
+                    self.stack.pop();
+                    continue;
+                }
+                Some(Err(e)) => return Some(Err(e.into())),
+                Some(Ok(entry)) => entry,
+            };
+            if self.skip_hidden && is_hidden(&entry.path) {
+                continue;
+            }
+            let below_max = self.max_depth.is_none_or(|max| entry.depth < max);
+            if entry.file_type.is_dir()
+                && let Some(mode) = self.exclude_caches
+                && cachedir::is_cache_dir(&entry.path)
+            {
+                match mode {
+                    ExcludeCaches::All => continue,
+                    ExcludeCaches::Tag if below_max => self.push_tag(&entry),
+                    ExcludeCaches::Tag | ExcludeCaches::Contents => {}
+                }
+                self.count();
+                return Some(Ok(entry));
+            }
+            self.count();
+            let descend = entry.file_type.is_dir() && below_max;
+            if descend {
+                if entry.followed && self.is_loop(&entry) {
+                    self.pending_error = Some(io::Error::other(format!(
+                        "symlink loop at {}; not descending",
+                        entry.path.display()
+                    )));
+                } else {
+                    self.push_dir(&entry.path, entry.depth);
+                }
+            }
+            return Some(Ok(entry));
+        }
+    }
+}
+
+/// Whether the name of `path` starts with a dot.
+fn is_hidden(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| name.as_encoded_bytes().starts_with(b"."))
+}
+
+impl Level {
+    fn next(&mut self, symlinks: SymlinkPolicy) -> Option<io::Result<WalkEntry>> {
+        match &mut self.entries {
+            Pending::Sorted(entries) => entries.pop().map(Ok),
+            Pending::Unsorted(read_dir) => {
+                let entry = read_dir.next()?;
+                Some(entry.and_then(|entry| {
+                    make_entry(entry.path(), entry.file_type()?, self.depth, symlinks)
+                }))
+            }
+            Pending::Spilled(names) => {
+                let name = names.next()?;
+                Some(name.and_then(|name| {
+                    // SAFETY: the bytes came from `as_encoded_bytes` in this
+                    // process and went through the spill file unchanged.
+                    let name = unsafe { OsStr::from_encoded_bytes_unchecked(&name) };
+                    let path = self.dir.join(name);
+                    let file_type = fs::symlink_metadata(&path)?.file_type();
+                    make_entry(path, file_type, self.depth, symlinks)
+                }))
+            }
+        }
+    }
+}
+
+fn make_entry(
+    path: PathBuf,
+    mut file_type: FileType,
+    depth: usize,
+    symlinks: SymlinkPolicy,
+) -> io::Result<WalkEntry> {
+    let mut followed = false;
+    if file_type.is_symlink()
+        && symlinks.follows(depth)
+        && let Ok(meta) = fs::metadata(&path)
+    {
+        // Dangling links stay links.
+        file_type = meta.file_type();
+        followed = true;
+    }
+    Ok(WalkEntry {
+        path,
+        depth,
+        file_type,
+        followed,
+    })
+}
+
+fn read_sorted(dir: &Path, depth: usize, symlinks: SymlinkPolicy) -> io::Result<Vec<WalkEntry>> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        entries.push(make_entry(
+            entry.path(),
+            entry.file_type()?,
+            depth,
+            symlinks,
+        )?);
+    }
+    entries.sort_by(|a, b| a.path.file_name().cmp(&b.path.file_name()));
+    Ok(entries)
+}
+
+/// The names in `dir` in sorted order, at most `max_in_memory` held at
+/// once while sorting.
+fn sort_spilling(dir: &Path, max_in_memory: usize) -> io::Result<Sorted> {
+    let mut sorter = ExternalSorter::new(max_in_memory);
+    for entry in fs::read_dir(dir)? {
+        sorter.push(entry?.file_name().as_encoded_bytes().to_vec())?;
+    }
+    sorter.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc, Mutex};
+
+    use tempfile::TempDir;
+
+    /// A tree with files and directories out of creation order, and a
+    /// hidden directory.
+    fn tree() -> TempDir {
+        let dir = TempDir::new().unwrap();
+        for path in ["c/z", "c/a/deep", "b", "a", ".hidden/x"] {
+            let path = dir.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        dir
+    }
+
+    fn relative(walk: Walk, root: &Path) -> Vec<String> {
+        walk.map(|entry| {
+            let entry = entry.unwrap();
+            entry
+                .path()
+                .strip_prefix(root)
+                .unwrap()
+                .to_string_lossy()
+                .into_owned()
+        })
+        .collect()
+    }
+
+    #[test]
+    fn the_symlink_policy_follows_by_depth() {
+        assert!(!SymlinkPolicy::Never.follows(0));
+        assert!(SymlinkPolicy::CommandLine.follows(0));
+        assert!(!SymlinkPolicy::CommandLine.follows(1));
+        assert!(SymlinkPolicy::Always.follows(0));
+        assert!(SymlinkPolicy::Always.follows(5));
+    }
+
+    #[test]
+    fn a_walk_is_pre_order_and_sorted_by_name() {
+        let dir = tree();
+        assert_eq!(
+            relative(Walk::new(dir.path()), dir.path()),
+            [
+                ".hidden",
+                ".hidden/x",
+                "a",
+                "b",
+                "c",
+                "c/a",
+                "c/a/deep",
+                "c/z"
+            ]
+        );
+    }
+
+    #[test]
+    fn low_memory_sorting_matches_the_in_memory_order() {
+        let dir = TempDir::new().unwrap();
+        for i in (0..300).rev() {
+            fs::write(dir.path().join(format!("f{i}")), "").unwrap();
+        }
+        fs::create_dir(dir.path().join("f150d")).unwrap();
+        fs::write(dir.path().join("f150d/inner"), "").unwrap();
+        let sorted = relative(Walk::new(dir.path()), dir.path());
+        for max_in_memory in [1, 16, 1000] {
+            let walk = Walk::new(dir.path()).order(WalkOrder::SortedLowMemory { max_in_memory });
+            assert_eq!(relative(walk, dir.path()), sorted, "{max_in_memory}");
+        }
+    }
+
+    #[test]
+    fn an_unsorted_walk_yields_the_same_entries() {
+        let dir = tree();
+        let mut sorted = relative(Walk::new(dir.path()), dir.path());
+        let walk = Walk::new(dir.path()).order(WalkOrder::Unsorted);
+        let mut unsorted = relative(walk, dir.path());
+        let position = |entries: &[String], name: &str| entries.iter().position(|e| e == name);
+        assert!(position(&unsorted, "c") < position(&unsorted, "c/a/deep"));
+        sorted.sort();
+        unsorted.sort();
+        assert_eq!(unsorted, sorted);
+    }
+
+    #[test]
+    fn depths_and_the_max_depth() {
+        let dir = tree();
+        let depths: Vec<usize> = Walk::new(dir.path())
+            .skip_hidden(true)
+            .map(|entry| entry.unwrap().depth())
+            .collect();
+        assert_eq!(depths, [1, 1, 1, 2, 3, 2]);
+        let walk = Walk::new(dir.path()).max_depth(1).skip_hidden(true);
+        assert_eq!(relative(walk, dir.path()), ["a", "b", "c"]);
+        assert_eq!(Walk::new(dir.path()).max_depth(0).count(), 0);
+    }
+
+    #[test]
+    fn progress_is_reported_every_n_entries() {
+        let dir = tree();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let reported = Arc::clone(&seen);
+        let walk = Walk::new(dir.path()).progress(3, move |n| reported.lock().unwrap().push(n));
+        assert_eq!(walk.count(), 8);
+        assert_eq!(*seen.lock().unwrap(), [3, 6]);
+    }
+
+    #[test]
+    fn an_unreadable_root_is_an_error_naming_it() {
+        let dir = TempDir::new().unwrap();
+        let missing = dir.path().join("missing");
+        let results: Vec<_> = Walk::new(&missing).collect();
+        assert_eq!(results.len(), 1);
+        let err = results[0].as_ref().unwrap_err().to_string();
+        assert!(
+            err.contains("cannot read") && err.contains("missing"),
+            "{err}"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_are_followed_according_to_the_policy() {
+        let dir = tree();
+        let root = dir.path().join("root");
+        fs::create_dir(&root).unwrap();
+        std::os::unix::fs::symlink(dir.path().join("c"), root.join("link")).unwrap();
+        let walk = |policy| relative(Walk::new(&root).symlinks(policy), &root);
+        assert_eq!(walk(SymlinkPolicy::Never), ["link"]);
+        assert_eq!(walk(SymlinkPolicy::CommandLine), ["link"]);
+        assert_eq!(
+            walk(SymlinkPolicy::Always),
+            ["link", "link/a", "link/a/deep", "link/z"]
+        );
+
+        let linked_root = dir.path().join("linked-root");
+        std::os::unix::fs::symlink(&root, &linked_root).unwrap();
+        assert!(
+            Walk::new(&linked_root)
+                .symlinks(SymlinkPolicy::Never)
+                .next()
+                .is_none()
+        );
+        assert_eq!(relative(Walk::new(&linked_root), &linked_root), ["link"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_followed_link_to_an_ancestor_is_an_error_not_a_loop() {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir(dir.path().join("d")).unwrap();
+        std::os::unix::fs::symlink(dir.path(), dir.path().join("d/up")).unwrap();
+        let results: Vec<_> = Walk::new(dir.path())
+            .symlinks(SymlinkPolicy::Always)
+            .collect();
+        assert_eq!(results.len(), 3);
+        let err = results[2].as_ref().unwrap_err().to_string();
+        assert!(err.contains("symlink loop"), "{err}");
+    }
+}
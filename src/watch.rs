@@ -0,0 +1,1068 @@
+//! `fman watch`: reporting changes below a directory as they happen, or
+//! running a command whenever files there change (`--run`).
+//!
+//! Changes are found by comparing snapshots of the tree. With the `watch`
+//! feature a snapshot is taken whenever the platform's notifications (see
+//! the `notify` crate) report activity below the root; without it, or
+//! where notifications cannot be set up, one is taken every poll interval
+//! instead. A change starts a batch, which ends once a whole batch window
+//! passes without further changes; the command then runs with the batch's
+//! paths. Runs happen on
+//! the watching thread, so they never overlap, and everything that changes
+//! while one runs is gathered into the single batch that follows it.
+//! Reported events are the batch's net effect, so an editor's temporary
+//! file that comes and goes within one batch never shows up.
+
+use std::collections::{BTreeSet, HashMap};
+use std::ffi::{OsStr, OsString};
+use std::fs::FileType;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+#[cfg(feature = "watch")]
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "json")]
+use serde::Serialize;
+
+use crate::cancel::CancelToken;
+use crate::clock::Clock;
+use crate::error::FmanResult;
+use crate::glob;
+use crate::validate::{ensure_exists, ensure_is_dir};
+use crate::walk::{SymlinkPolicy, Walk};
+
+/// Argument replaced by all of a batch's paths, one argument each.
+pub const ALL_PATHS: &str = "{}...";
+/// Placeholder that runs the command once per path, replaced by it.
+pub const EACH_PATH: &str = "{}";
+
+/// Starts a command and waits for it; replaceable in embedders and tests.
+pub trait CommandRunner {
+    /// Run `argv` to completion, returning its exit code, or `None` when it
+    /// was ended by a signal.
+    fn run(&mut self, argv: &[OsString]) -> io::Result<Option<i32>>;
+}
+
+/// Runs commands as child processes sharing fman's stdio.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProcessRunner;
+
+impl CommandRunner for ProcessRunner {
+    fn run(&mut self, argv: &[OsString]) -> io::Result<Option<i32>> {
+        let (program, args) = argv
+            .split_first()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "empty command"))?;
+        Ok(Command::new(program).args(args).status()?.code())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WatchOptions {
+    poll: Duration,
+    batch: Duration,
+    ignore: Vec<String>,
+    initial_run: bool,
+    recursive: bool,
+    pattern: Option<String>,
+    cancel: Option<CancelToken>,
+    polling: bool,
+}
+
+impl Default for WatchOptions {
+    fn default() -> Self {
+        WatchOptions {
+            poll: Duration::from_millis(250),
+            batch: Duration::from_millis(500),
+            ignore: Vec::new(),
+            initial_run: false,
+            recursive: true,
+            pattern: None,
+            cancel: None,
+            polling: false,
+        }
+    }
+}
+
+impl WatchOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How often the tree is compared against the last snapshot when
+    /// polling; with notifications, how often the cancel token is checked.
+    pub fn poll(mut self, interval: Duration) -> Self {
+        self.poll = interval;
+        self
+    }
+
+    /// How long the tree must stay unchanged before a batch is run.
+    pub fn batch(mut self, window: Duration) -> Self {
+        self.batch = window;
+        self
+    }
+
+    /// Leave out paths with a component matching the wildcard `pattern`,
+    /// such as `target` or `*.swp`.
+    pub fn ignore(mut self, pattern: impl Into<String>) -> Self {
+        self.ignore.push(pattern.into());
+        self
+    }
+
+    /// Run the command once at startup, with the root as its only path.
+    pub fn initial_run(mut self, initial_run: bool) -> Self {
+        self.initial_run = initial_run;
+        self
+    }
+
+    /// Watch the whole tree (the default), or only the root's own entries.
+    pub fn recursive(mut self, recursive: bool) -> Self {
+        self.recursive = recursive;
+        self
+    }
+
+    /// Only report events for paths whose file name matches the wildcard
+    /// `pattern`.
+    pub fn pattern(mut self, pattern: impl Into<String>) -> Self {
+        self.pattern = Some(pattern.into());
+        self
+    }
+
+    /// Stop watching, without an error, once `token` is cancelled; it is
+    /// checked every poll interval.
+    pub fn cancel(mut self, token: CancelToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    /// Poll even where change notifications are available, as for network
+    /// filesystems that do not deliver them.
+    pub fn polling(mut self, polling: bool) -> Self {
+        self.polling = polling;
+        self
+    }
+
+    fn cancelled(&self) -> bool {
+        self.cancel.as_ref().is_some_and(CancelToken::is_cancelled)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(Serialize), serde(rename_all = "kebab-case"))]
+pub enum WatchEventKind {
+    Created,
+    Modified,
+    Removed,
+}
+
+impl WatchEventKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            WatchEventKind::Created => "created",
+            WatchEventKind::Modified => "modified",
+            WatchEventKind::Removed => "removed",
+        }
+    }
+}
+
+/// A path that changed over one batch.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "json", derive(Serialize))]
+pub struct WatchEvent {
+    pub kind: WatchEventKind,
+    pub path: PathBuf,
+}
+
+/// One run of the command.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "json", derive(Serialize))]
+pub struct RunReport {
+    /// Seconds since the Unix epoch at the start of the run.
+    pub timestamp: u64,
+    pub argv: Vec<String>,
+    /// The changed paths the run was for.
+    pub paths: Vec<PathBuf>,
+    /// Whether this is the `--initial-run` at startup.
+    pub initial: bool,
+    /// `None` if the command was ended by a signal or could not start.
+    pub exit_code: Option<i32>,
+    pub success: bool,
+    /// Why the command could not be started.
+    pub error: Option<String>,
+    pub seconds: f64,
+}
+
+/// Watch `root` and run `command` through `runner` for each batch of
+/// changes, timed by `clock`, until `on_run` returns `false`.
+///
+/// See [`expand_command`] for how the changed paths are passed.
+pub fn watch_and_run(
+    root: &Path,
+    command: &[OsString],
+    options: &WatchOptions,
+    clock: &dyn Clock,
+    runner: &mut dyn CommandRunner,
+    mut on_run: impl FnMut(&RunReport) -> bool,
+) -> FmanResult<()> {
+    ensure_exists(root)?;
+    ensure_is_dir(root)?;
+    let waker = Waker::new(root, options);
+    let mut snapshot = Snapshot::take(root, options);
+    let mut run_batch = |paths: Vec<PathBuf>, initial: bool| {
+        for argv in expand_command(command, &paths) {
+            if !on_run(&run_once(argv, &paths, initial, clock, runner)) {
+                return false;
+            }
+        }
+        true
+    };
+    if options.initial_run && !run_batch(vec![root.to_path_buf()], true) {
+        return Ok(());
+    }
+    while let Some((latest, changed)) = next_batch(root, options, clock, &waker, &snapshot) {
+        snapshot = latest;
+        if !run_batch(changed.into_iter().collect(), false) {
+            break;
+        }
+        // Whatever changed during the run is measured against the snapshot
+        // from before it, so it all comes out as one batch.
+    }
+    Ok(())
+}
+
+/// Watch `root` and call `on_event` with each path created, modified or
+/// removed, batch by batch in path order, timed by `clock`, until
+/// `on_event` returns `false` or the options' cancel token is cancelled.
+pub fn watch_events(
+    root: &Path,
+    options: &WatchOptions,
+    clock: &dyn Clock,
+    mut on_event: impl FnMut(&WatchEvent) -> bool,
+) -> FmanResult<()> {
+    ensure_exists(root)?;
+    ensure_is_dir(root)?;
+    let waker = Waker::new(root, options);
+    let mut snapshot = Snapshot::take(root, options);
+    while let Some((latest, _)) = next_batch(root, options, clock, &waker, &snapshot) {
+        let events = snapshot.events(&latest, options.pattern.as_deref());
+        snapshot = latest;
+        if !events.iter().all(&mut on_event) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Wait for the tree to change from `snapshot` and then settle for a batch
+/// window, returning it as settled and every path that changed on the
+/// way; `None` once the options' cancel token is cancelled.
+fn next_batch(
+    root: &Path,
+    options: &WatchOptions,
+    clock: &dyn Clock,
+    waker: &Waker,
+    snapshot: &Snapshot,
+) -> Option<(Snapshot, BTreeSet<PathBuf>)> {
+    loop {
+        waker.wait(options.poll, options, clock);
+        if options.cancelled() {
+            return None;
+        }
+        let mut latest = Snapshot::take(root, options);
+        let mut changed = snapshot.changes(&latest);
+        if changed.is_empty() {
+            continue;
+        }
+        loop {
+            waker.settle(options.batch, options, clock);
+            if options.cancelled() {
+                return None;
+            }
+            let next = Snapshot::take(root, options);
+            let more = latest.changes(&next);
+            latest = next;
+            if more.is_empty() {
+                return Some((latest, changed));
+            }
+            changed.extend(more);
+        }
+    }
+}
+
+/// What tells the watcher to look at the tree again.
+enum Waker {
+    /// Every interval, whatever happened.
+    Poll,
+    /// Whenever a notification arrives; the watcher stops with the waker.
+    #[cfg(feature = "watch")]
+    Notify {
+        _watcher: notify::RecommendedWatcher,
+        activity: Receiver<()>,
+    },
+}
+
+impl Waker {
+    /// Notifications for `root` if the options allow them and the
+    /// platform can deliver them, polling otherwise.
+    fn new(root: &Path, options: &WatchOptions) -> Self {
+        #[cfg(feature = "watch")]
+        if !options.polling
+            && let Some(waker) = Self::notify(root, options.recursive)
+        {
+            return waker;
+        }
+        let _ = (root, options);
+        Waker::Poll
+    }
+
+    #[cfg(feature = "watch")]
+    fn notify(root: &Path, recursive: bool) -> Option<Self> {
+        use notify::{Event, RecursiveMode, Watcher};
+
+        let (sender, activity) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+            // Taking a snapshot opens and reads the tree; reporting that
+            // as activity would wake the watcher for ever.
+            if !event.is_ok_and(|event| event.kind.is_access()) {
+                let _ = sender.send(());
+            }
+        })
+        .ok()?;
+        let mode = if recursive {
+            RecursiveMode::Recursive
+        } else {
+            RecursiveMode::NonRecursive
+        };
+        watcher.watch(root, mode).ok()?;
+        Some(Waker::Notify {
+            _watcher: watcher,
+            activity,
+        })
+    }
+
+    /// Return once something may have changed: after `interval` when
+    /// polling, at the next notification otherwise. Either way it returns
+    /// at least every `interval` once the options' cancel token is
+    /// cancelled.
+    fn wait(&self, interval: Duration, options: &WatchOptions, clock: &dyn Clock) {
+        match self {
+            Waker::Poll => clock.sleep(interval),
+            #[cfg(feature = "watch")]
+            Waker::Notify { activity, .. } => loop {
+                match activity.recv_timeout(interval) {
+                    Ok(()) => return,
+                    Err(RecvTimeoutError::Timeout) if !options.cancelled() => {}
+                    Err(RecvTimeoutError::Timeout) => return,
+                    Err(RecvTimeoutError::Disconnected) => return clock.sleep(interval),
+                }
+            },
+        }
+        let _ = options;
+    }
+
+    /// Return once `window` has passed without notifications, or simply
+    /// after `window` when polling.
+    fn settle(&self, window: Duration, options: &WatchOptions, clock: &dyn Clock) {
+        match self {
+            Waker::Poll => clock.sleep(window),
+            #[cfg(feature = "watch")]
+            Waker::Notify { activity, .. } => loop {
+                match activity.recv_timeout(window) {
+                    Ok(()) if !options.cancelled() => {}
+                    Ok(()) | Err(RecvTimeoutError::Timeout) => return,
+                    Err(RecvTimeoutError::Disconnected) => return clock.sleep(window),
+                }
+            },
+        }
+        let _ = options;
+    }
+}
+
+/// The command lines to run for `paths`, in order.
+///
+/// An argument that is exactly [`ALL_PATHS`] is replaced by every path,
+/// giving one run. Otherwise, if any argument contains [`EACH_PATH`], the
+/// command runs once per path with the placeholder replaced by it. A
+/// command with neither runs once, as given.
+pub fn expand_command(command: &[OsString], paths: &[PathBuf]) -> Vec<Vec<OsString>> {
+    if command.iter().any(|arg| arg == ALL_PATHS) {
+        let argv = command
+            .iter()
+            .flat_map(|arg| {
+                if arg == ALL_PATHS {
+                    paths
+                        .iter()
+                        .map(|path| path.clone().into_os_string())
+                        .collect()
+                } else {
+                    vec![arg.clone()]
+                }
+            })
+            .collect();
+        return vec![argv];
+    }
+    let per_path = |arg: &OsString| arg.to_string_lossy().contains(EACH_PATH);
+    if !command.iter().any(per_path) {
+        return vec![command.to_vec()];
+    }
+    paths
+        .iter()
+        .map(|path| {
+            command
+                .iter()
+                .map(|arg| {
+                    if per_path(arg) {
+                        substitute(arg, path.as_os_str())
+                    } else {
+                        arg.clone()
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn substitute(arg: &OsStr, path: &OsStr) -> OsString {
+    let arg = arg.to_string_lossy();
+    let mut parts = arg.split(EACH_PATH);
+    let mut out = OsString::from(parts.next().unwrap_or_default());
+    for part in parts {
+        out.push(path);
+        out.push(part);
+    }
+    out
+}
+
+fn run_once(
+    argv: Vec<OsString>,
+    paths: &[PathBuf],
+    initial: bool,
+    clock: &dyn Clock,
+    runner: &mut dyn CommandRunner,
+) -> RunReport {
+    let timestamp = clock
+        .now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let started = Instant::now();
+    let result = runner.run(&argv);
+    let mut report = RunReport {
+        timestamp,
+        argv: argv
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect(),
+        paths: paths.to_vec(),
+        initial,
+        exit_code: None,
+        success: false,
+        error: None,
+        seconds: started.elapsed().as_secs_f64(),
+    };
+    match result {
+        Ok(code) => {
+            report.exit_code = code;
+            report.success = code == Some(0);
+        }
+        Err(e) => report.error = Some(e.to_string()),
+    }
+    report
+}
+
+/// What a path looked like when a [`Snapshot`] was taken. A directory's
+/// own mtime and size change with every entry added or removed, which the
+/// entries report themselves, so only its type counts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stamp {
+    Dir,
+    Other {
+        file_type: FileType,
+        len: u64,
+        modified: Option<SystemTime>,
+    },
+}
+
+/// The paths below a root, minus ignored ones.
+struct Snapshot(HashMap<PathBuf, Stamp>);
+
+impl Snapshot {
+    /// Entries that vanish or cannot be read while the tree is walked are
+    /// left out; they show up as changes once they settle.
+    fn take(root: &Path, options: &WatchOptions) -> Self {
+        let mut stamps = HashMap::new();
+        let mut walk = Walk::new(root).symlinks(SymlinkPolicy::Never);
+        if !options.recursive {
+            walk = walk.max_depth(1);
+        }
+        for entry in walk.flatten() {
+            let relative = entry.path().strip_prefix(root).unwrap_or(entry.path());
+            if is_ignored(relative, &options.ignore) {
+                continue;
+            }
+            let Ok(meta) = entry.metadata() else {
+                continue;
+            };
+            let stamp = if meta.is_dir() {
+                Stamp::Dir
+            } else {
+                Stamp::Other {
+                    file_type: meta.file_type(),
+                    len: meta.len(),
+                    modified: meta.modified().ok(),
+                }
+            };
+            stamps.insert(entry.path().to_path_buf(), stamp);
+        }
+        Snapshot(stamps)
+    }
+
+    /// Paths created, removed or modified between `self` and `later`.
+    fn changes(&self, later: &Snapshot) -> BTreeSet<PathBuf> {
+        let removed = self.0.keys().filter(|path| !later.0.contains_key(*path));
+        let touched = later
+            .0
+            .iter()
+            .filter(|(path, stamp)| self.0.get(*path) != Some(stamp))
+            .map(|(path, _)| path);
+        removed.chain(touched).cloned().collect()
+    }
+
+    /// What happened to each path between `self` and `later`, in path
+    /// order, for file names matching `pattern` if given.
+    fn events(&self, later: &Snapshot, pattern: Option<&str>) -> Vec<WatchEvent> {
+        let selected = |path: &Path| {
+            pattern.is_none_or(|pattern| {
+                path.file_name()
+                    .is_some_and(|name| glob::matches(pattern, &name.to_string_lossy()))
+            })
+        };
+        self.changes(later)
+            .into_iter()
+            .filter(|path| selected(path))
+            .map(|path| {
+                let kind = match (self.0.contains_key(&path), later.0.contains_key(&path)) {
+                    (false, _) => WatchEventKind::Created,
+                    (true, true) => WatchEventKind::Modified,
+                    (true, false) => WatchEventKind::Removed,
+                };
+                WatchEvent { kind, path }
+            })
+            .collect()
+    }
+}
+
+fn is_ignored(relative: &Path, ignore: &[String]) -> bool {
+    relative.components().any(|component| {
+        let name = component.as_os_str().to_string_lossy();
+        ignore.iter().any(|pattern| glob::matches(pattern, &name))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    /// A clock whose every sleep performs the next scripted step instead of
+    /// waiting, cancelling `token` once the script runs out.
+    struct ScriptedClock {
+        steps: RefCell<VecDeque<Box<dyn Fn()>>>,
+        token: CancelToken,
+    }
+
+    impl ScriptedClock {
+        fn new(token: &CancelToken, steps: Vec<Box<dyn Fn()>>) -> Self {
+            ScriptedClock {
+                steps: RefCell::new(steps.into()),
+                token: token.clone(),
+            }
+        }
+    }
+
+    impl Clock for ScriptedClock {
+        fn now(&self) -> SystemTime {
+            UNIX_EPOCH
+        }
+
+        fn sleep(&self, _: Duration) {
+            match self.steps.borrow_mut().pop_front() {
+                Some(step) => step(),
+                None => self.token.cancel(),
+            }
+        }
+    }
+
+    /// Every event `watch_events` reports for `root` while `steps` run,
+    /// polling.
+    fn events(root: &Path, options: WatchOptions, steps: Vec<Box<dyn Fn()>>) -> Vec<String> {
+        let token = CancelToken::new();
+        let clock = ScriptedClock::new(&token, steps);
+        let options = options.polling(true).cancel(token);
+        let mut seen = Vec::new();
+        watch_events(root, &options, &clock, |event| {
+            let path = event.path.strip_prefix(root).unwrap();
+            seen.push(format!("{}\t{}", event.kind.as_str(), path.display()));
+            true
+        })
+        .unwrap();
+        seen
+    }
+
+    fn step(f: impl Fn() + 'static) -> Box<dyn Fn()> {
+        Box::new(f)
+    }
+
+    fn idle() -> Box<dyn Fn()> {
+        step(|| {})
+    }
+
+    #[test]
+    fn reports_created_modified_and_removed_paths() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().to_path_buf();
+        fs::write(root.join("old"), "1").unwrap();
+        fs::write(root.join("gone"), "1").unwrap();
+        let (a, b, c) = (root.clone(), root.clone(), root.clone());
+        let seen = events(
+            &root,
+            WatchOptions::new(),
+            vec![
+                step(move || fs::write(a.join("new"), "1").unwrap()),
+                step(move || fs::write(b.join("old"), "22").unwrap()),
+                step(move || fs::remove_file(c.join("gone")).unwrap()),
+                idle(),
+            ],
+        );
+        assert_eq!(seen, ["removed\tgone", "created\tnew", "modified\told"]);
+    }
+
+    #[test]
+    fn a_file_that_comes_and_goes_within_a_batch_is_not_reported() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().to_path_buf();
+        let (a, b, c) = (root.clone(), root.clone(), root.clone());
+        let seen = events(
+            &root,
+            WatchOptions::new(),
+            vec![
+                step(move || fs::write(a.join("kept"), "1").unwrap()),
+                step(move || fs::write(b.join("kept.swp"), "1").unwrap()),
+                step(move || fs::remove_file(c.join("kept.swp")).unwrap()),
+                idle(),
+            ],
+        );
+        assert_eq!(seen, ["created\tkept"]);
+    }
+
+    #[test]
+    fn pattern_and_recursion_select_events() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().to_path_buf();
+        fs::create_dir(root.join("sub")).unwrap();
+        let write = |root: &Path| {
+            let root = root.to_path_buf();
+            step(move || {
+                fs::write(root.join("a.rs"), "1").unwrap();
+                fs::write(root.join("a.txt"), "1").unwrap();
+                fs::write(root.join("sub/b.rs"), "1").unwrap();
+            })
+        };
+        let seen = events(
+            &root,
+            WatchOptions::new().pattern("*.rs"),
+            vec![write(&root), idle()],
+        );
+        assert_eq!(seen, ["created\ta.rs", "created\tsub/b.rs"]);
+
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().to_path_buf();
+        fs::create_dir(root.join("sub")).unwrap();
+        let seen = events(
+            &root,
+            WatchOptions::new().recursive(false),
+            vec![write(&root), idle()],
+        );
+        assert_eq!(seen, ["created\ta.rs", "created\ta.txt"]);
+    }
+
+    #[test]
+    fn ignored_paths_are_left_out() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().to_path_buf();
+        let a = root.clone();
+        let seen = events(
+            &root,
+            WatchOptions::new().ignore("target"),
+            vec![
+                step(move || {
+                    fs::create_dir(a.join("target")).unwrap();
+                    fs::write(a.join("target/out"), "1").unwrap();
+                    fs::write(a.join("src"), "1").unwrap();
+                }),
+                idle(),
+            ],
+        );
+        assert_eq!(seen, ["created\tsrc"]);
+    }
+
+    #[test]
+    fn expand_command_passes_paths() {
+        let command = |args: &[&str]| args.iter().map(OsString::from).collect::<Vec<_>>();
+        let paths = [PathBuf::from("a"), PathBuf::from("b")];
+        assert_eq!(
+            expand_command(&command(&["ls", "{}..."]), &paths),
+            [command(&["ls", "a", "b"])]
+        );
+        assert_eq!(
+            expand_command(&command(&["cat", "--file={}"]), &paths),
+            [command(&["cat", "--file=a"]), command(&["cat", "--file=b"])]
+        );
+        assert_eq!(
+            expand_command(&command(&["make"]), &paths),
+            [command(&["make"])]
+        );
+    }
+
+    #[cfg(feature = "watch")]
+    #[test]
+    fn notifications_wake_the_watcher() {
+        use crate::clock::SystemClock;
+
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().to_path_buf();
+        let token = CancelToken::new();
+        let options = WatchOptions::new()
+            .batch(Duration::from_millis(50))
+            // Long enough that only a notification can find the change in
+            // time.
+            .poll(Duration::from_secs(60))
+            .cancel(token.clone());
+        let target = root.join("new");
+        let writer = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(200));
+            fs::write(target, "1").unwrap();
+        });
+        let started = Instant::now();
+        let mut seen = Vec::new();
+        watch_events(&root, &options, &SystemClock, |event| {
+            seen.push((event.kind, event.path.clone()));
+            false
+        })
+        .unwrap();
+        writer.join().unwrap();
+        assert_eq!(seen, [(WatchEventKind::Created, root.join("new"))]);
+        assert!(started.elapsed() < Duration::from_secs(30));
+    }
+
+    /// Records every command line it is asked to run, performing the next
+    /// of its `during` steps while "running" to mimic changes made
+    /// meanwhile, and exiting with the next of its `codes` (0 after).
+    #[derive(Default)]
+    struct RecordingRunner {
+        runs: Vec<Vec<String>>,
+        during: VecDeque<Box<dyn Fn()>>,
+        codes: VecDeque<io::Result<Option<i32>>>,
+    }
+
+    impl CommandRunner for RecordingRunner {
+        fn run(&mut self, argv: &[OsString]) -> io::Result<Option<i32>> {
+            self.runs.push(
+                argv.iter()
+                    .map(|a| a.to_string_lossy().into_owned())
+                    .collect(),
+            );
+            if let Some(step) = self.during.pop_front() {
+                step();
+            }
+            self.codes.pop_front().unwrap_or(Ok(Some(0)))
+        }
+    }
+
+    /// Every run `watch_and_run` reports for `root` while `steps` run,
+    /// with paths relative to `root`, polling.
+    fn runs(
+        root: &Path,
+        command: &[&str],
+        options: WatchOptions,
+        steps: Vec<Box<dyn Fn()>>,
+        runner: &mut RecordingRunner,
+    ) -> Vec<RunReport> {
+        let token = CancelToken::new();
+        let clock = ScriptedClock::new(&token, steps);
+        let options = options.polling(true).cancel(token);
+        let command: Vec<OsString> = command.iter().map(OsString::from).collect();
+        let mut reports = Vec::new();
+        watch_and_run(root, &command, &options, &clock, runner, |report| {
+            reports.push(report.clone());
+            true
+        })
+        .unwrap();
+        reports
+    }
+
+    fn relative(root: &Path, report: &RunReport) -> Vec<String> {
+        report
+            .paths
+            .iter()
+            .map(|path| path.strip_prefix(root).unwrap().display().to_string())
+            .collect()
+    }
+
+    fn write(root: &Path, name: &'static str) -> Box<dyn Fn()> {
+        let path = root.join(name);
+        step(move || fs::write(&path, name).unwrap())
+    }
+
+    #[test]
+    fn changes_within_a_batch_window_make_one_run() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().to_path_buf();
+        let mut runner = RecordingRunner::default();
+        let reports = runs(
+            &root,
+            &["make", "build"],
+            WatchOptions::new(),
+            vec![write(&root, "a"), write(&root, "b"), idle()],
+            &mut runner,
+        );
+        assert_eq!(runner.runs, [["make", "build"]]);
+        let [report] = reports.as_slice() else {
+            panic!("{reports:?}")
+        };
+        assert_eq!(relative(&root, report), ["a", "b"]);
+        assert!(report.success && !report.initial);
+    }
+
+    #[test]
+    fn a_quiet_window_ends_a_batch() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().to_path_buf();
+        let mut runner = RecordingRunner::default();
+        let reports = runs(
+            &root,
+            &["echo", "{}..."],
+            WatchOptions::new(),
+            vec![write(&root, "a"), idle(), write(&root, "b"), idle()],
+            &mut runner,
+        );
+        assert_eq!(reports.len(), 2);
+        assert_eq!(relative(&root, &reports[0]), ["a"]);
+        assert_eq!(relative(&root, &reports[1]), ["b"]);
+    }
+
+    #[test]
+    fn changes_during_a_run_are_coalesced_into_the_next_batch() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().to_path_buf();
+        let (b, c) = (root.clone(), root.clone());
+        let mut runner = RecordingRunner {
+            during: VecDeque::from([step(move || {
+                fs::write(b.join("b"), "1").unwrap();
+                fs::write(c.join("c"), "1").unwrap();
+            })]),
+            ..RecordingRunner::default()
+        };
+        let reports = runs(
+            &root,
+            &["build"],
+            WatchOptions::new(),
+            vec![write(&root, "a"), idle(), idle(), idle()],
+            &mut runner,
+        );
+        assert_eq!(reports.len(), 2, "{reports:?}");
+        assert_eq!(relative(&root, &reports[0]), ["a"]);
+        assert_eq!(relative(&root, &reports[1]), ["b", "c"]);
+    }
+
+    /// The command lines run for one batch that changes `a` and `b`.
+    fn run_for_a_and_b(command: &[&str]) -> (Vec<Vec<String>>, PathBuf, TempDir) {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().to_path_buf();
+        let both = {
+            let root = root.clone();
+            step(move || {
+                fs::write(root.join("a"), "1").unwrap();
+                fs::write(root.join("b"), "1").unwrap();
+            })
+        };
+        let mut runner = RecordingRunner::default();
+        runs(
+            &root,
+            command,
+            WatchOptions::new(),
+            vec![both, idle()],
+            &mut runner,
+        );
+        (runner.runs, root, dir)
+    }
+
+    #[test]
+    fn paths_are_substituted_into_the_command() {
+        let (runs, root, _dir) = run_for_a_and_b(&["lint", "{}..."]);
+        let path = |name: &str| root.join(name).display().to_string();
+        assert_eq!(runs, [vec!["lint".to_string(), path("a"), path("b")]]);
+
+        let (runs, root, _dir) = run_for_a_and_b(&["fmt", "--file={}"]);
+        let path = |name: &str| root.join(name).display().to_string();
+        assert_eq!(
+            runs,
+            [
+                vec!["fmt".to_string(), format!("--file={}", path("a"))],
+                vec!["fmt".to_string(), format!("--file={}", path("b"))],
+            ]
+        );
+    }
+
+    #[test]
+    fn an_initial_run_comes_first() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().to_path_buf();
+        let mut runner = RecordingRunner::default();
+        let reports = runs(
+            &root,
+            &["build"],
+            WatchOptions::new().initial_run(true),
+            vec![write(&root, "a"), idle()],
+            &mut runner,
+        );
+        assert_eq!(reports.len(), 2);
+        assert!(reports[0].initial && !reports[1].initial);
+        assert_eq!(reports[0].paths, std::slice::from_ref(&root));
+    }
+
+    #[test]
+    fn exit_statuses_are_reported() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().to_path_buf();
+        let mut runner = RecordingRunner {
+            codes: VecDeque::from([
+                Ok(Some(3)),
+                Ok(None),
+                Err(io::Error::new(io::ErrorKind::NotFound, "no such program")),
+            ]),
+            ..RecordingRunner::default()
+        };
+        let reports = runs(
+            &root,
+            &["build"],
+            WatchOptions::new(),
+            vec![
+                write(&root, "a"),
+                idle(),
+                write(&root, "b"),
+                idle(),
+                write(&root, "c"),
+                idle(),
+            ],
+            &mut runner,
+        );
+        let outcomes: Vec<_> = reports
+            .iter()
+            .map(|r| (r.exit_code, r.success, r.error.is_some()))
+            .collect();
+        assert_eq!(
+            outcomes,
+            [
+                (Some(3), false, false),
+                (None, false, false),
+                (None, false, true)
+            ]
+        );
+        assert_eq!(reports[0].argv, ["build"]);
+    }
+
+    #[test]
+    fn ignored_changes_start_no_run() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().to_path_buf();
+        fs::create_dir(root.join("target")).unwrap();
+        let mut runner = RecordingRunner::default();
+        let reports = runs(
+            &root,
+            &["build"],
+            WatchOptions::new().ignore("target"),
+            vec![write(&root, "target/out"), idle(), idle()],
+            &mut runner,
+        );
+        assert!(reports.is_empty() && runner.runs.is_empty());
+    }
+
+    #[test]
+    fn returning_false_stops_the_watch() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().to_path_buf();
+        let token = CancelToken::new();
+        let clock = ScriptedClock::new(
+            &token,
+            vec![write(&root, "a"), idle(), write(&root, "b"), idle()],
+        );
+        let options = WatchOptions::new().polling(true).cancel(token.clone());
+        let mut runner = RecordingRunner::default();
+        let mut count = 0;
+        watch_and_run(
+            &root,
+            &[OsString::from("x")],
+            &options,
+            &clock,
+            &mut runner,
+            |_| {
+                count += 1;
+                false
+            },
+        )
+        .unwrap();
+        assert_eq!(count, 1);
+        assert!(!token.is_cancelled());
+    }
+
+    #[test]
+    fn returning_false_stops_the_events() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().to_path_buf();
+        let a = root.clone();
+        let token = CancelToken::new();
+        let clock = ScriptedClock::new(
+            &token,
+            vec![
+                step(move || {
+                    fs::write(a.join("x"), "1").unwrap();
+                    fs::write(a.join("y"), "1").unwrap();
+                }),
+                idle(),
+            ],
+        );
+        let options = WatchOptions::new().polling(true).cancel(token.clone());
+        let mut seen = 0;
+        watch_events(&root, &options, &clock, |_| {
+            seen += 1;
+            false
+        })
+        .unwrap();
+        assert_eq!(seen, 1);
+        assert!(!token.is_cancelled());
+    }
+
+    #[test]
+    fn the_root_must_be_a_directory() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("f");
+        fs::write(&file, "").unwrap();
+        let clock = ScriptedClock::new(&CancelToken::new(), Vec::new());
+        let err = watch_events(&file, &WatchOptions::new(), &clock, |_| true).unwrap_err();
+        assert_eq!(err.exit_code(), 4);
+        let err = watch_events(
+            &dir.path().join("missing"),
+            &WatchOptions::new(),
+            &clock,
+            |_| true,
+        )
+        .unwrap_err();
+        assert!(err.is_not_found(), "{err}");
+    }
+}
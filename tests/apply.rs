@@ -0,0 +1,133 @@
+mod common;
+
+use common::Scratch;
+
+/// Sources for [`plan`], `src/0` to `src/9`.
+fn sources(scratch: &Scratch) {
+    for i in 0..10 {
+        scratch.write(&format!("src/{i}"), &format!("file {i}\n"));
+    }
+}
+
+/// Fifty steps over ten independent chains, each of which only works in
+/// order: a directory, a copy into it, a copy of the copy, a rename of
+/// that, and removing the source.
+fn plan(scratch: &Scratch) {
+    let mut steps = Vec::new();
+    for i in 0..10 {
+        steps.push(format!(r#"{{"op":"create-dir-all","path":"out/{i}"}}"#));
+        steps.push(format!(
+            r#"{{"op":"copy","from":"src/{i}","to":"out/{i}/a","create_new":true}}"#
+        ));
+        steps.push(format!(
+            r#"{{"op":"copy","from":"out/{i}/a","to":"out/{i}/b","create_new":true}}"#
+        ));
+        steps.push(format!(
+            r#"{{"op":"rename","from":"out/{i}/b","to":"out/{i}/c"}}"#
+        ));
+        steps.push(format!(r#"{{"op":"remove-file","path":"src/{i}"}}"#));
+    }
+    assert_eq!(steps.len(), 50);
+    scratch.write("plan.jsonl", &(steps.join("\n") + "\n"));
+}
+
+#[test]
+fn parallel_apply_ends_like_sequential_apply() {
+    let sequential = Scratch::new();
+    sources(&sequential);
+    plan(&sequential);
+    sequential
+        .run(&["apply", "--sequential", "plan.jsonl"])
+        .success();
+
+    let parallel = Scratch::new();
+    sources(&parallel);
+    plan(&parallel);
+    parallel
+        .run(&["apply", "--jobs", "4", "plan.jsonl"])
+        .success();
+
+    assert_eq!(parallel.tree(), sequential.tree());
+    assert_eq!(parallel.read("out/7/c"), "file 7\n");
+    assert!(!parallel.exists("out/7/b"));
+    assert!(!parallel.exists("src/7"));
+}
+
+#[test]
+fn parallel_uses_the_default_job_count() {
+    let scratch = Scratch::new();
+    sources(&scratch);
+    plan(&scratch);
+    scratch
+        .run(&["apply", "--parallel", "plan.jsonl"])
+        .success();
+    assert_eq!(scratch.read("out/0/a"), "file 0\n");
+}
+
+#[test]
+fn sequential_conflicts_with_jobs() {
+    let scratch = Scratch::new();
+    plan(&scratch);
+    scratch
+        .run(&["apply", "--sequential", "--jobs", "2", "plan.jsonl"])
+        .fails_with(1);
+}
+
+#[test]
+fn a_bad_plan_changes_nothing() {
+    let scratch = Scratch::new();
+    scratch.write(
+        "plan.jsonl",
+        "{\"op\":\"create-dir\",\"path\":\"d\"}\nnot json\n",
+    );
+    let run = scratch.run(&["apply", "plan.jsonl"]).fails_with(4);
+    assert!(run.stderr().contains("plan line 2"), "{}", run.stderr());
+    assert!(!scratch.exists("d"));
+}
+
+#[test]
+fn a_failing_step_stops_the_plan() {
+    let scratch = Scratch::new();
+    scratch.write("taken", "mine");
+    scratch.write("src", "theirs");
+    scratch.write(
+        "plan.jsonl",
+        concat!(
+            "{\"op\":\"copy\",\"from\":\"src\",\"to\":\"taken\",\"create_new\":true}\n",
+            "{\"op\":\"remove-file\",\"path\":\"src\"}\n",
+        ),
+    );
+    scratch
+        .run(&["apply", "-j", "2", "plan.jsonl"])
+        .fails_with(3);
+    assert_eq!(scratch.read("taken"), "mine");
+    assert!(scratch.exists("src"));
+}
+
+#[test]
+fn dry_run_applies_nothing() {
+    let scratch = Scratch::new();
+    sources(&scratch);
+    plan(&scratch);
+    let before = scratch.snapshot();
+    scratch
+        .run(&["--dry-run", "apply", "-j", "4", "plan.jsonl"])
+        .success();
+    assert_eq!(scratch.snapshot(), before);
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn json_reports_the_plan() {
+    let scratch = Scratch::new();
+    sources(&scratch);
+    plan(&scratch);
+    let run = scratch
+        .run(&["--json", "apply", "-j", "4", "plan.jsonl"])
+        .success();
+    let report = run.json();
+    assert_eq!(report["operation"], "apply");
+    assert_eq!(report["steps"], 50);
+    assert_eq!(report["waves"], 4);
+    assert_eq!(report["jobs"], 4);
+}
@@ -0,0 +1,96 @@
+#![cfg(feature = "archive")]
+
+mod common;
+
+use common::Scratch;
+
+#[test]
+fn the_extension_picks_the_format() {
+    let scratch = Scratch::new();
+    scratch.write("s/a", "1");
+    for (name, magic) in [
+        ("t.tar.gz", &b"\x1f\x8b"[..]),
+        ("t.tgz", &b"\x1f\x8b"[..]),
+        ("t.zip", &b"PK\x03\x04"[..]),
+    ] {
+        scratch.run(&["archive", "s", name]).success();
+        let bytes = std::fs::read(scratch.path(name)).unwrap();
+        assert!(bytes.starts_with(magic), "{name}");
+    }
+    scratch.run(&["archive", "s", "t.tar"]).success();
+    let bytes = std::fs::read(scratch.path("t.tar")).unwrap();
+    assert_eq!(&bytes[257..262], b"ustar");
+}
+
+#[test]
+fn an_unknown_extension_is_refused() {
+    let scratch = Scratch::new();
+    scratch.write("s/a", "1");
+    let run = scratch.run(&["archive", "s", "t.rar"]).fails_with(4);
+    assert!(
+        run.stderr().contains(".tar, .tar.gz or .zip"),
+        "{}",
+        run.stderr()
+    );
+    assert!(!scratch.exists("t.rar"));
+}
+
+#[test]
+fn a_single_file_unpacks_under_its_own_name() {
+    let scratch = Scratch::new();
+    scratch.write("notes.txt", "hello");
+    scratch.run(&["archive", "notes.txt", "n.zip"]).success();
+    scratch.run(&["extract", "n.zip", "out"]).success();
+    assert_eq!(scratch.read("out/notes.txt"), "hello");
+}
+
+#[test]
+fn a_missing_source_is_not_found() {
+    let scratch = Scratch::new();
+    scratch.run(&["archive", "missing", "t.tar"]).fails_with(2);
+    assert!(!scratch.exists("t.tar"));
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinks_round_trip_as_symlinks() {
+    let scratch = Scratch::new();
+    scratch.write("s/a", "1");
+    std::os::unix::fs::symlink("a", scratch.path("s/link")).unwrap();
+    scratch.run(&["archive", "s", "t.tar.gz"]).success();
+    scratch.run(&["extract", "t.tar.gz", "out"]).success();
+    let link = scratch.path("out/s/link");
+    assert!(link.symlink_metadata().unwrap().file_type().is_symlink());
+    assert_eq!(std::fs::read_link(link).unwrap(), std::path::Path::new("a"));
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn json_reports_the_counts() {
+    let scratch = Scratch::new();
+    scratch.write("s/a", "12");
+    scratch.write("s/sub/b", "345");
+    let json = scratch.run(&["--json", "archive", "s", "t.zip"]).json();
+    assert_eq!(json["format"], "zip");
+    assert_eq!(json["entries"], 4);
+    assert_eq!(json["bytes"], 5);
+}
+
+#[cfg(unix)]
+#[test]
+fn an_unreadable_directory_fails_but_the_rest_is_stored() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let scratch = Scratch::new();
+    scratch.write("s/a", "1");
+    scratch.write("s/locked/hidden", "2");
+    let locked = scratch.path("s/locked");
+    std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o000)).unwrap();
+    let run = scratch.run_unprivileged(&["archive", "s", "t.tar"]);
+    std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let run = run.fails_with(5);
+    assert!(run.stderr().contains("locked"), "{}", run.stderr());
+    scratch.run(&["extract", "t.tar", "out"]).success();
+    assert_eq!(scratch.read("out/s/a"), "1");
+    assert!(!scratch.exists("out/s/locked/hidden"));
+}
@@ -0,0 +1,98 @@
+#![cfg(feature = "async")]
+
+use std::fs;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use fman::asynch::{copy_file_force, copy_file_safe, copy_file_with_options};
+use fman::{CopyOptions, CopyStatus, FmanError, OverwritePolicy};
+use tempfile::TempDir;
+
+#[tokio::test]
+async fn copy_file_safe_returns_the_path_written() {
+    let dir = TempDir::new().unwrap();
+    let src = dir.path().join("a.txt");
+    fs::write(&src, "a").unwrap();
+    fs::create_dir(dir.path().join("into")).unwrap();
+
+    let written = copy_file_safe(&src, dir.path().join("b.txt"))
+        .await
+        .unwrap();
+    assert_eq!(written, dir.path().join("b.txt"));
+    let into = copy_file_safe(&src, dir.path().join("into")).await.unwrap();
+    assert_eq!(into, dir.path().join("into/a.txt"));
+    assert_eq!(fs::read_to_string(into).unwrap(), "a");
+}
+
+#[tokio::test]
+async fn copy_file_safe_refuses_to_overwrite() {
+    let dir = TempDir::new().unwrap();
+    let (src, dst) = (dir.path().join("a"), dir.path().join("b"));
+    fs::write(&src, "new").unwrap();
+    fs::write(&dst, "old").unwrap();
+    let err = copy_file_safe(&src, &dst).await.unwrap_err();
+    assert!(matches!(err, FmanError::AlreadyExists(_)), "{err}");
+    assert_eq!(fs::read_to_string(&dst).unwrap(), "old");
+}
+
+#[tokio::test]
+async fn copy_file_force_overwrites_and_returns_the_path() {
+    let dir = TempDir::new().unwrap();
+    let (src, dst) = (dir.path().join("a"), dir.path().join("b"));
+    fs::write(&src, "new").unwrap();
+    fs::write(&dst, "old").unwrap();
+    assert_eq!(copy_file_force(&src, &dst).await.unwrap(), dst);
+    assert_eq!(fs::read_to_string(&dst).unwrap(), "new");
+}
+
+#[tokio::test]
+async fn errors_are_the_synchronous_ones() {
+    let dir = TempDir::new().unwrap();
+    let err = copy_file_safe(dir.path().join("missing"), dir.path().join("b"))
+        .await
+        .unwrap_err();
+    assert!(err.is_not_found(), "{err}");
+    assert_eq!(err.exit_code(), 2);
+
+    fs::write(dir.path().join("a"), "a").unwrap();
+    let err = copy_file_safe(dir.path().join("a"), dir.path().join("no/dir/"))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, FmanError::DestinationDirMissing(_)), "{err}");
+}
+
+#[tokio::test]
+async fn copy_file_with_options_applies_them() {
+    let dir = TempDir::new().unwrap();
+    let (src, dst) = (dir.path().join("a"), dir.path().join("b"));
+    fs::write(&src, "new").unwrap();
+    fs::write(&dst, "old").unwrap();
+    let options = CopyOptions::new().on_conflict(OverwritePolicy::Skip);
+    let report = copy_file_with_options(&src, &dst, &options).await.unwrap();
+    assert_eq!(report.status, CopyStatus::Skipped);
+    assert_eq!(fs::read_to_string(&dst).unwrap(), "old");
+
+    let options = CopyOptions::new().backup(true).verify(true);
+    let report = copy_file_with_options(&src, &dst, &options).await.unwrap();
+    assert!(report.verified);
+    assert_eq!(report.backup, Some(dir.path().join("b.bak")));
+    assert_eq!(fs::read_to_string(&dst).unwrap(), "new");
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn the_copy_runs_off_the_executor_thread() {
+    let dir = TempDir::new().unwrap();
+    let src = dir.path().join("a");
+    fs::write(&src, "a").unwrap();
+    let copied_on = Arc::new(Mutex::new(None));
+    let seen = Arc::clone(&copied_on);
+    let options = CopyOptions::new().transform(move |_| {
+        *seen.lock().unwrap() = Some(thread::current().id());
+        Ok(None)
+    });
+    copy_file_with_options(&src, dir.path().join("b"), &options)
+        .await
+        .unwrap();
+    let copied_on = copied_on.lock().unwrap().expect("transform not called");
+    assert_ne!(copied_on, thread::current().id());
+}
@@ -0,0 +1,130 @@
+mod common;
+
+use std::fs::{self, File, Permissions};
+use std::os::unix::fs::PermissionsExt;
+use std::time::{Duration, SystemTime};
+
+use common::Scratch;
+
+const FILES: [&str; 2] = ["s/a", "s/sub/b"];
+
+fn mode(scratch: &Scratch, rel: &str) -> u32 {
+    fs::metadata(scratch.path(rel))
+        .unwrap()
+        .permissions()
+        .mode()
+        & 0o777
+}
+
+fn modified(scratch: &Scratch, rel: &str) -> SystemTime {
+    fs::metadata(scratch.path(rel)).unwrap().modified().unwrap()
+}
+
+fn set(scratch: &Scratch, rel: &str, mode: u32, secs: u64) {
+    let path = scratch.path(rel);
+    fs::set_permissions(&path, Permissions::from_mode(mode)).unwrap();
+    File::options()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+        .unwrap();
+}
+
+/// A source tree with distinctive metadata, and a copy of its data in `d`
+/// whose metadata has been scrambled.
+fn scrambled(scratch: &Scratch) {
+    scratch.write("s/a", "alpha");
+    scratch.write("s/sub/b", "beta");
+    for file in FILES {
+        set(scratch, file, 0o640, 1_000_000_000);
+    }
+    scratch.write("d/a", "alpha");
+    scratch.write("d/sub/b", "beta");
+    set(scratch, "d/a", 0o777, 1_700_000_000);
+    set(scratch, "d/sub/b", 0o600, 1_700_000_000);
+}
+
+fn tree_hash(scratch: &Scratch) -> String {
+    scratch.run(&["hash", "-r", "d"]).success().stdout()
+}
+
+#[test]
+fn attributes_only_restores_metadata_and_leaves_the_data() {
+    let scratch = Scratch::new();
+    scrambled(&scratch);
+    let before = tree_hash(&scratch);
+    scratch
+        .run(&["copy", "-r", "--attributes-only=mode,times", "s", "d"])
+        .success();
+    for file in ["a", "sub/b"] {
+        let (src, dst) = (format!("s/{file}"), format!("d/{file}"));
+        assert_eq!(mode(&scratch, &dst), mode(&scratch, &src), "{file}");
+        assert_eq!(modified(&scratch, &dst), modified(&scratch, &src), "{file}");
+    }
+    assert_eq!(tree_hash(&scratch), before);
+    assert_eq!(scratch.read("d/a"), "alpha");
+}
+
+#[test]
+fn attributes_only_defaults_to_every_attribute() {
+    let scratch = Scratch::new();
+    scrambled(&scratch);
+    scratch
+        .run(&["copy", "-r", "--attributes-only", "s", "d"])
+        .success();
+    assert_eq!(mode(&scratch, "d/a"), 0o640);
+}
+
+#[test]
+fn a_missing_destination_fails_and_is_not_created() {
+    let scratch = Scratch::new();
+    scrambled(&scratch);
+    scratch.write("s/new", "data");
+    scratch
+        .run(&["copy", "-r", "--attributes-only", "s", "d"])
+        .fails_with(2);
+    assert!(!scratch.exists("d/new"));
+}
+
+#[test]
+fn ignore_missing_skips_missing_destinations() {
+    let scratch = Scratch::new();
+    scrambled(&scratch);
+    scratch.write("s/new", "data");
+    scratch
+        .run(&[
+            "copy",
+            "-r",
+            "--attributes-only",
+            "--ignore-missing",
+            "s",
+            "d",
+        ])
+        .success();
+    assert!(!scratch.exists("d/new"));
+    assert_eq!(mode(&scratch, "d/a"), 0o640);
+}
+
+#[test]
+fn ignore_missing_needs_attributes_only() {
+    let scratch = Scratch::new();
+    scrambled(&scratch);
+    scratch
+        .run(&["copy", "-r", "--ignore-missing", "s", "e"])
+        .fails_with(1);
+}
+
+#[test]
+fn a_single_file_gets_its_attributes_in_place() {
+    let scratch = Scratch::new();
+    scrambled(&scratch);
+    scratch
+        .run(&["copy", "--attributes-only=mode", "s/a", "d/a"])
+        .success();
+    assert_eq!(mode(&scratch, "d/a"), 0o640);
+    assert_eq!(
+        modified(&scratch, "d/a"),
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+    );
+}
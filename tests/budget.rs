@@ -0,0 +1,112 @@
+mod common;
+
+use common::Scratch;
+
+/// Three 10-byte files under `s`, copied in the order `a`, `b`, `c`.
+fn tens(scratch: &Scratch) {
+    for name in ["a", "b", "c"] {
+        scratch.write(&format!("s/{name}"), "xxxxxxxxxx");
+    }
+}
+
+#[test]
+fn copy_stops_before_the_file_that_would_cross_the_limit() {
+    let scratch = Scratch::new();
+    tens(&scratch);
+    let run = scratch
+        .run(&["copy", "-r", "--max-total-bytes", "25", "s", "d"])
+        .fails_with(10);
+    assert!(
+        run.stderr()
+            .contains("write limit of 25B reached with 20B written"),
+        "{}",
+        run.stderr()
+    );
+    assert!(scratch.exists("d/a") && scratch.exists("d/b"));
+    assert!(!scratch.exists("d/c"));
+}
+
+#[test]
+fn copy_within_the_limit_reports_what_it_wrote() {
+    let scratch = Scratch::new();
+    tens(&scratch);
+    let run = scratch
+        .run(&["copy", "-r", "--max-total-bytes", "30", "s", "d"])
+        .success();
+    assert!(
+        run.stderr().contains("wrote 30B of the 30B limit"),
+        "{}",
+        run.stderr()
+    );
+    assert_eq!(scratch.read("d/c"), "xxxxxxxxxx");
+}
+
+#[test]
+fn sizes_take_units() {
+    let scratch = Scratch::new();
+    tens(&scratch);
+    scratch
+        .run(&["copy", "-r", "--max-total-bytes", "1K", "s", "d"])
+        .success();
+    scratch
+        .run(&["copy", "-r", "--max-total-bytes", "lots", "s", "e"])
+        .fails_with(1);
+}
+
+#[test]
+fn sync_stops_at_the_limit() {
+    let scratch = Scratch::new();
+    tens(&scratch);
+    scratch
+        .run(&["sync", "--max-total-bytes", "25", "s", "d"])
+        .fails_with(10);
+    assert!(scratch.exists("d/a") && scratch.exists("d/b"));
+    assert!(!scratch.exists("d/c"));
+}
+
+#[test]
+fn apply_stops_at_the_limit() {
+    let scratch = Scratch::new();
+    tens(&scratch);
+    let steps: Vec<String> = ["a", "b", "c"]
+        .iter()
+        .map(|name| {
+            format!(
+                "{{\"op\":\"copy\",\"from\":\"s/{name}\",\"to\":\"{name}\",\"create_new\":true}}"
+            )
+        })
+        .collect();
+    scratch.write("plan.jsonl", &(steps.join("\n") + "\n"));
+    scratch
+        .run(&["apply", "--max-total-bytes", "25", "plan.jsonl"])
+        .fails_with(10);
+    assert!(scratch.exists("a") && scratch.exists("b"));
+    assert!(!scratch.exists("c"));
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn json_errors_carry_the_limit_and_what_was_written() {
+    let scratch = Scratch::new();
+    tens(&scratch);
+    let run = scratch
+        .run(&["--json", "copy", "-r", "--max-total-bytes", "25", "s", "d"])
+        .fails_with(10);
+    let error = run.json();
+    assert_eq!(error["kind"], "quota-exceeded");
+    assert_eq!(error["limit"], 25);
+    assert_eq!(error["written"], 20);
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn json_reports_carry_the_budget() {
+    let scratch = Scratch::new();
+    tens(&scratch);
+    let run = scratch
+        .run(&["--json", "sync", "--max-total-bytes", "100", "s", "d"])
+        .success();
+    let report = run.json();
+    assert_eq!(report["budget"]["limit"], 100);
+    assert_eq!(report["budget"]["written"], 30);
+}
@@ -0,0 +1,85 @@
+mod common;
+
+use common::Scratch;
+
+fn tree(scratch: &Scratch) {
+    scratch.write("s/a", "1");
+    scratch.write("s/sub/b", "2");
+}
+
+#[test]
+fn a_kept_checkpoint_skips_finished_files_on_the_next_run() {
+    let scratch = Scratch::new();
+    tree(&scratch);
+    let args = [
+        "copy",
+        "-r",
+        "--checkpoint",
+        "cp",
+        "--keep-checkpoint",
+        "s",
+        "d",
+    ];
+    scratch.run(&args).success();
+    let recorded = scratch.read("cp");
+    assert_eq!(recorded.lines().count(), 3, "{recorded}");
+
+    // Copying `a` or `sub/b` again would fail: they exist in `d`.
+    scratch.write("s/c", "3");
+    scratch.run(&args).success();
+    assert_eq!(scratch.read("d/c"), "3");
+    assert!(!scratch.exists("d/s"), "the rerun nested the source");
+
+    #[cfg(feature = "json")]
+    {
+        scratch.write("s/e", "4");
+        let mut args = args.to_vec();
+        args.insert(0, "--json");
+        let third = scratch.run(&args).success().json();
+        assert_eq!(third["files"], 1);
+        assert_eq!(third["resumed"], 3);
+    }
+}
+
+#[test]
+fn the_checkpoint_is_removed_once_the_copy_completes() {
+    let scratch = Scratch::new();
+    tree(&scratch);
+    scratch
+        .run(&["copy", "-r", "--checkpoint", "cp", "s", "d"])
+        .success();
+    assert!(!scratch.exists("cp"));
+    assert_eq!(scratch.read("d/sub/b"), "2");
+}
+
+#[test]
+fn a_checkpoint_for_another_copy_is_refused() {
+    let scratch = Scratch::new();
+    tree(&scratch);
+    scratch
+        .run(&[
+            "copy",
+            "-r",
+            "--checkpoint",
+            "cp",
+            "--keep-checkpoint",
+            "s",
+            "d",
+        ])
+        .success();
+    let run = scratch
+        .run(&["copy", "-r", "--checkpoint", "cp", "s", "e"])
+        .fails_with(4);
+    assert!(run.stderr().contains("different copy"), "{}", run.stderr());
+    assert!(!scratch.exists("e/a"));
+    assert!(scratch.exists("cp"));
+}
+
+#[test]
+fn keep_checkpoint_needs_a_checkpoint() {
+    let scratch = Scratch::new();
+    tree(&scratch);
+    scratch
+        .run(&["copy", "-r", "--keep-checkpoint", "s", "d"])
+        .fails_with(1);
+}
@@ -0,0 +1,76 @@
+mod common;
+
+use common::Scratch;
+
+#[test]
+fn an_invalid_mode_is_rejected_before_any_change() {
+    let scratch = Scratch::new();
+    scratch.write("f", "");
+    let stderr = scratch.run(&["chmod", "999", "f"]).fails_with(4).stderr();
+    assert!(stderr.contains("invalid mode \"999\""), "{stderr}");
+}
+
+#[test]
+fn owner_write_toggles_read_only() {
+    let scratch = Scratch::new();
+    scratch.write("f", "");
+    let readonly = || {
+        std::fs::metadata(scratch.path("f"))
+            .unwrap()
+            .permissions()
+            .readonly()
+    };
+    scratch.run(&["chmod", "u-w", "f"]).success();
+    assert!(readonly());
+    scratch.run(&["chmod", "u+w", "f"]).success();
+    assert!(!readonly());
+}
+
+#[test]
+fn a_missing_target_fails_but_the_others_change() {
+    let scratch = Scratch::new();
+    scratch.write("f", "");
+    scratch.run(&["chmod", "u-w", "missing", "f"]).fails_with(2);
+    assert!(
+        std::fs::metadata(scratch.path("f"))
+            .unwrap()
+            .permissions()
+            .readonly()
+    );
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::common::Scratch;
+
+    fn mode(scratch: &Scratch, rel: &str) -> u32 {
+        std::fs::metadata(scratch.path(rel))
+            .unwrap()
+            .permissions()
+            .mode()
+            & 0o7777
+    }
+
+    #[test]
+    fn chmod_sets_the_mode_of_every_target() {
+        let scratch = Scratch::new();
+        scratch.write("a", "");
+        scratch.write("b", "");
+        scratch.run(&["chmod", "640", "a", "b"]).success();
+        assert_eq!(mode(&scratch, "a"), 0o640);
+        assert_eq!(mode(&scratch, "b"), 0o640);
+    }
+
+    #[test]
+    fn recursive_reaches_the_whole_tree() {
+        let scratch = Scratch::new();
+        scratch.write("d/a", "");
+        scratch.write("d/sub/b", "");
+        scratch.run(&["chmod", "-R", "0750", "d"]).success();
+        for rel in ["d", "d/a", "d/sub", "d/sub/b"] {
+            assert_eq!(mode(&scratch, rel), 0o750, "{rel}");
+        }
+    }
+}
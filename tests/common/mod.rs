@@ -0,0 +1,267 @@
+//! Helpers shared by the CLI tests: a scratch directory and a way to run
+//! the `fman` binary inside it.
+
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Output, Stdio};
+
+use tempfile::TempDir;
+
+/// A scratch directory that `fman` runs in, removed when dropped.
+pub struct Scratch {
+    dir: TempDir,
+}
+
+impl Scratch {
+    pub fn new() -> Self {
+        Scratch {
+            dir: TempDir::new().expect("create scratch directory"),
+        }
+    }
+
+    pub fn path(&self, relative: &str) -> PathBuf {
+        self.dir.path().join(relative)
+    }
+
+    /// Write `contents` to `relative`, creating its parent directories.
+    pub fn write(&self, relative: &str, contents: &str) -> PathBuf {
+        let path = self.path(relative);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).expect("create parent directories");
+        }
+        fs::write(&path, contents).expect("write file");
+        path
+    }
+
+    pub fn read(&self, relative: &str) -> String {
+        fs::read_to_string(self.path(relative)).expect("read file")
+    }
+
+    pub fn exists(&self, relative: &str) -> bool {
+        fs::symlink_metadata(self.path(relative)).is_ok()
+    }
+
+    /// Every entry below the scratch directory with its kind, contents,
+    /// permissions and modification time, in path order.
+    pub fn snapshot(&self) -> Vec<String> {
+        let mut entries = Vec::new();
+        snapshot_into(self.dir.path(), self.dir.path(), &mut entries);
+        entries.sort();
+        entries
+    }
+
+    /// Every entry below the scratch directory with its kind and contents,
+    /// in path order: what two runs must agree on when their timing differs.
+    pub fn tree(&self) -> Vec<String> {
+        let mut entries = Vec::new();
+        tree_into(self.dir.path(), self.dir.path(), &mut entries);
+        entries.sort();
+        entries
+    }
+
+    /// Run `fman` with `args` in the scratch directory, without any
+    /// configuration or `FMAN_*` settings from the environment and with
+    /// its home, and so its trash can, in [`Scratch::home`].
+    pub fn run(&self, args: &[&str]) -> Run {
+        Run(self.command(args).output().expect("run fman"))
+    }
+
+    /// Run `fman` like [`Scratch::run`] with `vars` set in its environment.
+    pub fn run_with_env(&self, args: &[&str], vars: &[(&str, &str)]) -> Run {
+        let mut command = self.command(args);
+        command.envs(vars.iter().copied());
+        Run(command.output().expect("run fman"))
+    }
+
+    /// Run `fman` like [`Scratch::run`], writing `input` to its stdin.
+    pub fn run_with_input(&self, args: &[&str], input: &str) -> Run {
+        use std::io::Write;
+
+        let mut child = self
+            .command(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("start fman");
+        let mut stdin = child.stdin.take().expect("piped stdin");
+        // fman may exit without reading it all.
+        let _ = stdin.write_all(input.as_bytes());
+        drop(stdin);
+        Run(child.wait_with_output().expect("run fman"))
+    }
+
+    /// Start `fman` like [`Scratch::run`] with its stdout piped, for
+    /// commands that keep running.
+    pub fn spawn(&self, args: &[&str]) -> Child {
+        self.command(args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("start fman")
+    }
+
+    /// Run `fman` like [`Scratch::run`], but as a user permission bits
+    /// apply to. Under root, the scratch directory is handed to `nobody`
+    /// along with a copy of the binary, which may sit where `nobody`
+    /// cannot reach it, and `fman` runs as `nobody`.
+    #[cfg(unix)]
+    pub fn run_unprivileged(&self, args: &[&str]) -> Run {
+        use std::os::unix::process::CommandExt;
+
+        // SAFETY: geteuid has no preconditions.
+        if unsafe { libc::geteuid() } != 0 {
+            return self.run(args);
+        }
+        let binary = self.path(".bin/fman");
+        if !binary.exists() {
+            fs::create_dir_all(binary.parent().unwrap()).expect("create .bin");
+            fs::copy(env!("CARGO_BIN_EXE_fman"), &binary).expect("copy fman");
+        }
+        chown_tree(self.dir.path(), NOBODY);
+        let mut command = self.command_for(&binary, args);
+        command.uid(NOBODY).gid(NOBODY);
+        Run(command.output().expect("run fman"))
+    }
+
+    fn command(&self, args: &[&str]) -> Command {
+        self.command_for(Path::new(env!("CARGO_BIN_EXE_fman")), args)
+    }
+
+    fn command_for(&self, program: &Path, args: &[&str]) -> Command {
+        let mut command = Command::new(program);
+        command
+            .args(args)
+            .current_dir(self.dir.path())
+            .env("XDG_CONFIG_HOME", self.dir.path().join(".no-config"))
+            .env("APPDATA", self.dir.path().join(".no-config"))
+            .env("HOME", self.home())
+            .env("USERPROFILE", self.home())
+            .env_remove("XDG_DATA_HOME");
+        for (key, _) in std::env::vars_os() {
+            if key.to_string_lossy().starts_with("FMAN_") {
+                command.env_remove(key);
+            }
+        }
+        command
+    }
+
+    /// The home directory `fman` runs with.
+    pub fn home(&self) -> PathBuf {
+        self.dir.path().join(".home")
+    }
+
+    pub fn root(&self) -> &Path {
+        self.dir.path()
+    }
+}
+
+/// The outcome of one `fman` run.
+pub struct Run(pub Output);
+
+impl Run {
+    pub fn code(&self) -> i32 {
+        self.0.status.code().expect("fman exited normally")
+    }
+
+    pub fn stdout(&self) -> String {
+        String::from_utf8_lossy(&self.0.stdout).into_owned()
+    }
+
+    pub fn stderr(&self) -> String {
+        String::from_utf8_lossy(&self.0.stderr).into_owned()
+    }
+
+    /// Panic with both streams unless the run succeeded.
+    #[track_caller]
+    pub fn success(self) -> Self {
+        assert!(
+            self.0.status.success(),
+            "fman failed ({:?})\nstdout: {}\nstderr: {}",
+            self.0.status,
+            self.stdout(),
+            self.stderr()
+        );
+        self
+    }
+
+    /// Panic unless the run failed with exit code `code`.
+    #[track_caller]
+    pub fn fails_with(self, code: i32) -> Self {
+        assert_eq!(
+            self.code(),
+            code,
+            "stdout: {}\nstderr: {}",
+            self.stdout(),
+            self.stderr()
+        );
+        self
+    }
+
+    pub fn json(&self) -> serde_json::Value {
+        serde_json::from_str(&self.stdout()).expect("stdout is one JSON document")
+    }
+}
+
+/// The uid and gid of `nobody`.
+#[cfg(unix)]
+const NOBODY: u32 = 65534;
+
+/// Give `path` and everything below it to `id`.
+#[cfg(unix)]
+fn chown_tree(path: &Path, id: u32) {
+    std::os::unix::fs::lchown(path, Some(id), Some(id)).expect("chown");
+    if fs::symlink_metadata(path).expect("read metadata").is_dir() {
+        for entry in fs::read_dir(path).expect("read directory") {
+            chown_tree(&entry.expect("read directory entry").path(), id);
+        }
+    }
+}
+
+fn snapshot_into(root: &Path, dir: &Path, entries: &mut Vec<String>) {
+    for entry in fs::read_dir(dir).expect("read directory") {
+        let path = entry.expect("read directory entry").path();
+        let meta = fs::symlink_metadata(&path).expect("read metadata");
+        let relative = path.strip_prefix(root).unwrap().display().to_string();
+        let contents = if meta.is_file() {
+            format!("{:?}", fs::read(&path).expect("read file"))
+        } else if meta.is_symlink() {
+            fs::read_link(&path).unwrap().display().to_string()
+        } else {
+            String::new()
+        };
+        entries.push(format!(
+            "{relative} {:?} {:?} {:?} {contents}",
+            meta.file_type(),
+            meta.permissions(),
+            meta.modified().ok()
+        ));
+        if meta.is_dir() {
+            snapshot_into(root, &path, entries);
+        }
+    }
+}
+
+fn tree_into(root: &Path, dir: &Path, entries: &mut Vec<String>) {
+    for entry in fs::read_dir(dir).expect("read directory") {
+        let path = entry.expect("read directory entry").path();
+        let meta = fs::symlink_metadata(&path).expect("read metadata");
+        let relative = path.strip_prefix(root).unwrap().display().to_string();
+        if meta.is_dir() {
+            entries.push(format!("{relative}/"));
+            tree_into(root, &path, entries);
+        } else if meta.is_symlink() {
+            entries.push(format!(
+                "{relative} -> {}",
+                fs::read_link(&path).unwrap().display()
+            ));
+        } else {
+            entries.push(format!(
+                "{relative} {:?}",
+                fs::read_to_string(&path).unwrap()
+            ));
+        }
+    }
+}
@@ -0,0 +1,46 @@
+mod common;
+
+use common::Scratch;
+
+#[test]
+fn identical_files_exit_zero() {
+    let scratch = Scratch::new();
+    scratch.write("a", "same");
+    scratch.write("b", "same");
+    let run = scratch.run(&["compare", "a", "b"]).success();
+    assert_eq!(run.stdout(), "a and b are identical\n");
+}
+
+#[test]
+fn different_files_exit_one() {
+    let scratch = Scratch::new();
+    scratch.write("a", "same");
+    scratch.write("b", "diff");
+    scratch.write("c", "longer");
+    for other in ["b", "c"] {
+        let run = scratch.run(&["compare", "a", other]).fails_with(1);
+        assert_eq!(run.stdout(), format!("a and {other} differ\n"));
+    }
+}
+
+#[test]
+fn compare_needs_two_existing_files() {
+    let scratch = Scratch::new();
+    scratch.write("a", "same");
+    std::fs::create_dir(scratch.path("d")).unwrap();
+    scratch.run(&["compare", "a", "d"]).fails_with(4);
+    scratch.run(&["compare", "a", "missing"]).fails_with(2);
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn compare_reports_the_verdict_as_json() {
+    let scratch = Scratch::new();
+    scratch.write("a", "same");
+    let report = scratch
+        .run(&["--json", "compare", "a", "a"])
+        .success()
+        .json();
+    assert_eq!(report["operation"], "compare");
+    assert_eq!(report["equal"], true);
+}
@@ -0,0 +1,52 @@
+mod common;
+
+use common::Scratch;
+
+fn completions(shell: &str) -> String {
+    Scratch::new()
+        .run(&["completions", shell])
+        .success()
+        .stdout()
+}
+
+#[test]
+fn every_shell_gets_a_script_naming_the_subcommands() {
+    for shell in ["bash", "zsh", "fish", "powershell"] {
+        let script = completions(shell);
+        for name in ["copy", "move", "completions"] {
+            assert!(script.contains(name), "{shell} script lacks {name}");
+        }
+    }
+}
+
+#[test]
+fn paths_complete_as_files() {
+    let zsh = completions("zsh");
+    assert!(zsh.contains(":dst:_files"), "{zsh}");
+    let fish = completions("fish");
+    assert!(
+        fish.lines()
+            .any(|line| line.contains("using_subcommand copy")
+                && line.contains("-l checkpoint")
+                && line.ends_with("-r -F")),
+        "{fish}"
+    );
+}
+
+#[test]
+fn subcommand_flags_are_offered() {
+    let bash = completions("bash");
+    let copy = bash
+        .split("fman__subcmd__copy)")
+        .nth(1)
+        .expect("no copy section");
+    assert!(copy.contains("--force"), "{copy}");
+    assert!(copy.contains("--on-conflict"), "{copy}");
+}
+
+#[test]
+fn an_unknown_shell_is_a_usage_error() {
+    let run = Scratch::new().run(&["completions", "tcsh"]).fails_with(1);
+    assert!(run.stderr().contains("possible values"), "{}", run.stderr());
+    assert!(run.stdout().is_empty());
+}
@@ -0,0 +1,123 @@
+mod common;
+
+use std::time::{Duration, SystemTime};
+
+use common::Scratch;
+
+/// Write the configuration fman reads by default.
+fn configure(scratch: &Scratch, text: &str) {
+    scratch.write(".no-config/fman/config.toml", text);
+}
+
+#[test]
+fn without_a_file_the_built_in_defaults_apply() {
+    let scratch = Scratch::new();
+    scratch.write("a", "new");
+    scratch.write("b", "old");
+    scratch.run(&["copy", "a", "b"]).fails_with(3);
+    assert_eq!(scratch.read("b"), "old");
+}
+
+#[test]
+fn the_file_overrides_the_built_in_default() {
+    let scratch = Scratch::new();
+    configure(&scratch, "force = true\n");
+    scratch.write("a", "new");
+    scratch.write("b", "old");
+    scratch.run(&["copy", "a", "b"]).success();
+    assert_eq!(scratch.read("b"), "new");
+}
+
+#[test]
+fn a_flag_overrides_the_file() {
+    let scratch = Scratch::new();
+    configure(&scratch, "force = true\n");
+    scratch.write("a", "new");
+    scratch.write("b", "old");
+    scratch.run(&["copy", "--no-force", "a", "b"]).fails_with(3);
+    assert_eq!(scratch.read("b"), "old");
+}
+
+#[test]
+fn preserve_comes_from_the_file_unless_a_flag_says() {
+    let scratch = Scratch::new();
+    configure(&scratch, "preserve = true\n");
+    scratch.write("a", "a");
+    let old = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+    std::fs::File::options()
+        .write(true)
+        .open(scratch.path("a"))
+        .unwrap()
+        .set_modified(old)
+        .unwrap();
+    let modified = |name| {
+        std::fs::metadata(scratch.path(name))
+            .unwrap()
+            .modified()
+            .unwrap()
+    };
+    scratch.run(&["copy", "a", "kept"]).success();
+    assert_eq!(modified("kept"), old);
+    scratch
+        .run(&["copy", "--no-preserve", "a", "fresh"])
+        .success();
+    assert_ne!(modified("fresh"), old);
+}
+
+#[test]
+fn backups_take_the_configured_suffix() {
+    let scratch = Scratch::new();
+    configure(
+        &scratch,
+        "backup = true\nbackup_suffix = \".orig\"\nforce = true\n",
+    );
+    scratch.write("a", "new");
+    scratch.write("b", "old");
+    scratch.run(&["copy", "a", "b"]).success();
+    assert_eq!(scratch.read("b"), "new");
+    assert_eq!(scratch.read("b.orig"), "old");
+    assert!(!scratch.exists("b.bak"));
+}
+
+#[test]
+fn the_overwrite_policy_applies_without_flags() {
+    let scratch = Scratch::new();
+    configure(&scratch, "overwrite = \"skip\"\n");
+    scratch.write("a", "new");
+    scratch.write("b", "old");
+    scratch.run(&["copy", "a", "b"]).success();
+    assert_eq!(scratch.read("b"), "old");
+    scratch.run(&["copy", "-f", "a", "b"]).success();
+    assert_eq!(scratch.read("b"), "new");
+}
+
+#[test]
+fn config_names_another_file() {
+    let scratch = Scratch::new();
+    configure(&scratch, "force = false\n");
+    scratch.write("mine.toml", "force = true\n");
+    scratch.write("a", "new");
+    scratch.write("b", "old");
+    scratch
+        .run(&["--config", "mine.toml", "copy", "a", "b"])
+        .success();
+    assert_eq!(scratch.read("b"), "new");
+    scratch
+        .run(&["--config", "missing.toml", "copy", "a", "c"])
+        .fails_with(2);
+    assert!(!scratch.exists("c"));
+}
+
+#[test]
+fn a_malformed_file_fails_with_its_position() {
+    let scratch = Scratch::new();
+    configure(&scratch, "force = true\nbackup = maybe\n");
+    scratch.write("a", "a");
+    let run = scratch.run(&["copy", "a", "b"]).fails_with(4);
+    assert!(
+        run.stderr().contains("config.toml:2:10"),
+        "{}",
+        run.stderr()
+    );
+    assert!(!scratch.exists("b"));
+}
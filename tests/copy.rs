@@ -0,0 +1,670 @@
+mod common;
+
+use common::Scratch;
+
+#[test]
+fn several_sources_are_copied_into_a_directory() {
+    let scratch = Scratch::new();
+    scratch.write("a.txt", "a");
+    scratch.write("b.txt", "b");
+    scratch.write("c.txt", "c");
+    std::fs::create_dir(scratch.path("target")).unwrap();
+    scratch
+        .run(&["copy", "a.txt", "b.txt", "c.txt", "target/"])
+        .success();
+    assert_eq!(scratch.read("target/a.txt"), "a");
+    assert_eq!(scratch.read("target/b.txt"), "b");
+    assert_eq!(scratch.read("target/c.txt"), "c");
+}
+
+#[test]
+fn several_sources_need_an_existing_directory() {
+    let scratch = Scratch::new();
+    scratch.write("a.txt", "a");
+    scratch.write("b.txt", "b");
+    let run = scratch
+        .run(&["copy", "a.txt", "b.txt", "missing"])
+        .fails_with(4);
+    assert!(run.stderr().contains("missing"), "{}", run.stderr());
+    #[cfg(feature = "json")]
+    {
+        let run = scratch
+            .run(&["--json", "copy", "a.txt", "b.txt", "missing"])
+            .fails_with(4);
+        assert_eq!(run.json()["kind"], "invalid-input");
+    }
+    assert!(!scratch.exists("missing"));
+}
+
+#[test]
+fn several_sources_stop_at_the_first_failure_and_keep_earlier_copies() {
+    let scratch = Scratch::new();
+    scratch.write("a.txt", "a");
+    scratch.write("c.txt", "c");
+    std::fs::create_dir(scratch.path("target")).unwrap();
+    let run = scratch
+        .run(&["copy", "a.txt", "gone.txt", "c.txt", "target"])
+        .fails_with(2);
+    assert!(run.stderr().contains("gone.txt"), "{}", run.stderr());
+    assert_eq!(scratch.read("target/a.txt"), "a");
+    assert!(!scratch.exists("target/c.txt"));
+}
+
+#[test]
+fn a_single_source_may_name_the_destination_file() {
+    let scratch = Scratch::new();
+    scratch.write("a.txt", "a");
+    scratch.run(&["copy", "a.txt", "renamed.txt"]).success();
+    assert_eq!(scratch.read("renamed.txt"), "a");
+}
+
+#[test]
+fn parents_creates_nested_destination_directories() {
+    let scratch = Scratch::new();
+    scratch.write("report.txt", "r");
+    scratch
+        .run(&["copy", "-p", "report.txt", "archive/2024/june/"])
+        .success();
+    assert_eq!(scratch.read("archive/2024/june/report.txt"), "r");
+    scratch
+        .run(&["copy", "--parents", "report.txt", "other/dir/named.txt"])
+        .success();
+    assert_eq!(scratch.read("other/dir/named.txt"), "r");
+}
+
+#[test]
+fn parents_dry_run_reports_the_file_inside_the_planned_directory() {
+    let scratch = Scratch::new();
+    scratch.write("a.txt", "a");
+    let run = scratch
+        .run(&["--dry-run", "copy", "a.txt", "q/", "--parents"])
+        .success();
+    assert_eq!(run.stdout(), "mkdir q\ncopy a.txt -> q/a.txt\n");
+    #[cfg(feature = "json")]
+    {
+        let run = scratch
+            .run(&["--dry-run", "--json", "copy", "a.txt", "q/r/", "--parents"])
+            .success();
+        assert_eq!(run.json()["destination"], "q/r/a.txt");
+    }
+    assert!(!scratch.exists("q"));
+
+    scratch.run(&["copy", "a.txt", "q/", "--parents"]).success();
+    assert_eq!(scratch.read("q/a.txt"), "a");
+}
+
+#[test]
+fn a_missing_destination_directory_is_named_without_parents() {
+    let scratch = Scratch::new();
+    scratch.write("report.txt", "r");
+    let run = scratch
+        .run(&["copy", "report.txt", "archive/june/"])
+        .fails_with(2);
+    assert!(run.stderr().contains("archive/june"), "{}", run.stderr());
+    #[cfg(feature = "json")]
+    {
+        let run = scratch
+            .run(&["--json", "copy", "report.txt", "archive/june/"])
+            .fails_with(2);
+        assert_eq!(run.json()["kind"], "destination-dir-missing");
+    }
+    assert!(!scratch.exists("archive"));
+}
+
+#[test]
+fn parents_with_force_replaces_an_existing_file() {
+    let scratch = Scratch::new();
+    scratch.write("report.txt", "new");
+    scratch.write("archive/june/report.txt", "old");
+    scratch
+        .run(&["copy", "-p", "report.txt", "archive/june/"])
+        .fails_with(3);
+    assert_eq!(scratch.read("archive/june/report.txt"), "old");
+    scratch
+        .run(&["copy", "-p", "--force", "report.txt", "archive/june/"])
+        .success();
+    assert_eq!(scratch.read("archive/june/report.txt"), "new");
+}
+
+#[test]
+fn on_conflict_policies_against_an_existing_destination() {
+    let scratch = Scratch::new();
+    scratch.write("f.txt", "new");
+    scratch.write("d/f.txt", "old");
+
+    scratch
+        .run(&["copy", "f.txt", "d", "--on-conflict", "error"])
+        .fails_with(3);
+    assert_eq!(scratch.read("d/f.txt"), "old");
+
+    scratch
+        .run(&["copy", "f.txt", "d", "--on-conflict", "skip"])
+        .success();
+    assert_eq!(scratch.read("d/f.txt"), "old");
+    #[cfg(feature = "json")]
+    {
+        let run = scratch
+            .run(&["--json", "copy", "f.txt", "d", "--on-conflict", "skip"])
+            .success();
+        assert_eq!(run.json()["status"], "skipped");
+    }
+
+    scratch
+        .run(&["copy", "f.txt", "d", "--on-conflict", "rename"])
+        .success();
+    assert_eq!(scratch.read("d/f (1).txt"), "new");
+    assert_eq!(scratch.read("d/f.txt"), "old");
+
+    scratch
+        .run(&["copy", "f.txt", "d", "--on-conflict", "overwrite"])
+        .success();
+    assert_eq!(scratch.read("d/f.txt"), "new");
+}
+
+#[test]
+fn on_conflict_skip_still_fails_for_a_missing_source() {
+    let scratch = Scratch::new();
+    scratch.write("d/f.txt", "old");
+    scratch
+        .run(&["copy", "f.txt", "d", "--on-conflict", "skip"])
+        .fails_with(2);
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn on_conflict_skip_counts_skipped_files_in_a_tree() {
+    let scratch = Scratch::new();
+    scratch.write("s/a", "1");
+    scratch.write("s/b", "2");
+    std::fs::create_dir(scratch.path("d")).unwrap();
+    scratch.run(&["copy", "-r", "s", "d"]).success();
+    let run = scratch
+        .run(&["--json", "copy", "-r", "s", "d", "--on-conflict", "skip"])
+        .success();
+    assert_eq!(run.json()["skipped"], 2);
+}
+
+#[test]
+fn force_cannot_be_combined_with_on_conflict() {
+    let scratch = Scratch::new();
+    scratch.write("f.txt", "new");
+    scratch
+        .run(&["copy", "--force", "--on-conflict", "skip", "f.txt", "g.txt"])
+        .fails_with(1);
+}
+
+/// Copies onto the source itself fail with invalid-input, even with
+/// --force, and leave the file as it was.
+fn assert_copy_onto_itself_fails(scratch: &Scratch, args: &[&str]) {
+    let mut full = vec!["copy"];
+    full.extend_from_slice(args);
+    let run = scratch.run(&full).fails_with(4);
+    assert!(
+        run.stderr()
+            .contains("source and destination are the same file"),
+        "{}",
+        run.stderr()
+    );
+    #[cfg(feature = "json")]
+    {
+        full.insert(0, "--json");
+        let run = scratch.run(&full).fails_with(4);
+        assert_eq!(run.json()["kind"], "invalid-input", "{}", run.stdout());
+    }
+    assert_eq!(scratch.read("a.txt"), "keep me");
+}
+
+#[test]
+fn copying_into_the_sources_own_directory_is_rejected() {
+    let scratch = Scratch::new();
+    scratch.write("a.txt", "keep me");
+    assert_copy_onto_itself_fails(&scratch, &["a.txt", "."]);
+    assert_copy_onto_itself_fails(&scratch, &["--force", "a.txt", "."]);
+}
+
+#[test]
+fn copying_onto_the_same_path_is_rejected() {
+    let scratch = Scratch::new();
+    scratch.write("a.txt", "keep me");
+    assert_copy_onto_itself_fails(&scratch, &["a.txt", "./a.txt"]);
+    assert_copy_onto_itself_fails(&scratch, &["--force", "a.txt", "./a.txt"]);
+    assert_copy_onto_itself_fails(&scratch, &["--force", "--atomic", "a.txt", "a.txt"]);
+}
+
+#[cfg(unix)]
+#[test]
+fn copying_onto_a_hard_link_of_the_source_is_rejected() {
+    let scratch = Scratch::new();
+    scratch.write("a.txt", "keep me");
+    std::fs::hard_link(scratch.path("a.txt"), scratch.path("b.txt")).unwrap();
+    assert_copy_onto_itself_fails(&scratch, &["a.txt", "b.txt"]);
+    assert_copy_onto_itself_fails(&scratch, &["--force", "a.txt", "b.txt"]);
+    assert_copy_onto_itself_fails(&scratch, &["--force", "--backup", "a.txt", "b.txt"]);
+    assert_eq!(scratch.read("b.txt"), "keep me");
+}
+
+#[cfg(unix)]
+#[test]
+fn a_dangling_symlink_destination_is_kept_unless_forced() {
+    let scratch = Scratch::new();
+    scratch.write("src", "data");
+    std::os::unix::fs::symlink("outside", scratch.path("link")).unwrap();
+    scratch.run(&["copy", "src", "link"]).fails_with(3);
+    assert!(!scratch.exists("outside"));
+    scratch.run(&["copy", "--force", "src", "link"]).success();
+    assert!(!scratch.exists("outside"));
+    assert!(
+        std::fs::symlink_metadata(scratch.path("link"))
+            .unwrap()
+            .is_file()
+    );
+    assert_eq!(scratch.read("link"), "data");
+}
+
+#[test]
+fn a_read_only_copy_is_write_protected() {
+    let scratch = Scratch::new();
+    scratch.write("a", "archived");
+    scratch.run(&["copy", "--read-only", "a", "b"]).success();
+    assert!(
+        std::fs::metadata(scratch.path("b"))
+            .unwrap()
+            .permissions()
+            .readonly()
+    );
+    scratch.write("a", "changed");
+    scratch.run(&["copy", "a", "b"]).fails_with(3);
+    assert_eq!(scratch.read("b"), "archived");
+}
+
+#[cfg(unix)]
+#[test]
+fn sparse_copies_keep_length_and_contents() {
+    let scratch = Scratch::new();
+    let src = std::fs::File::create(scratch.path("disk.img")).unwrap();
+    src.set_len(4 << 20).unwrap();
+    std::os::unix::fs::FileExt::write_at(&src, b"data", 1 << 20).unwrap();
+    scratch
+        .run(&["copy", "--sparse", "disk.img", "copy.img"])
+        .success();
+    let (src, dst) = (
+        std::fs::read(scratch.path("disk.img")).unwrap(),
+        std::fs::read(scratch.path("copy.img")).unwrap(),
+    );
+    assert_eq!(dst.len(), 4 << 20);
+    assert!(src == dst);
+}
+
+#[test]
+fn reflink_auto_copies_wherever_it_runs() {
+    let scratch = Scratch::new();
+    scratch.write("a", "data");
+    scratch.run(&["copy", "--reflink=auto", "a", "b"]).success();
+    assert_eq!(scratch.read("b"), "data");
+    scratch
+        .run(&["copy", "--reflink=never", "a", "c"])
+        .success();
+    assert_eq!(scratch.read("c"), "data");
+    scratch
+        .run(&["copy", "--reflink=sometimes", "a", "d"])
+        .fails_with(1);
+    assert!(!scratch.exists("d"));
+}
+
+#[test]
+fn atomic_copies_leave_only_the_destination() {
+    let scratch = Scratch::new();
+    scratch.write("a", "new");
+    scratch.write("b", "old");
+    scratch.run(&["copy", "--atomic", "a", "b"]).fails_with(3);
+    assert_eq!(scratch.read("b"), "old");
+    scratch.run(&["copy", "--atomic", "-f", "a", "b"]).success();
+    assert_eq!(scratch.read("b"), "new");
+    scratch.run(&["copy", "--atomic", "a", "c"]).success();
+    assert_eq!(scratch.read("c"), "new");
+    let names: Vec<String> = std::fs::read_dir(scratch.root())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|name| name.starts_with(".fman-tmp-"))
+        .collect();
+    assert!(names.is_empty(), "{names:?}");
+}
+
+#[test]
+fn atomic_and_immutable_conflict() {
+    let scratch = Scratch::new();
+    scratch.write("a", "1");
+    scratch
+        .run(&["copy", "--atomic", "--immutable=best-effort", "a", "b"])
+        .fails_with(1);
+    assert!(!scratch.exists("b"));
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn an_unprivileged_immutable_copy_is_permission_denied() {
+    let scratch = Scratch::new();
+    scratch.write("a", "1");
+    let run = scratch
+        .run_unprivileged(&["copy", "--immutable", "a", "b"])
+        .fails_with(5);
+    assert!(
+        run.stderr().contains("CAP_LINUX_IMMUTABLE"),
+        "{}",
+        run.stderr()
+    );
+    #[cfg(feature = "json")]
+    {
+        let run = scratch
+            .run_unprivileged(&["--json", "copy", "--immutable", "a", "c"])
+            .fails_with(5);
+        assert_eq!(run.json()["kind"], "permission-denied", "{}", run.stdout());
+    }
+}
+
+#[test]
+fn no_clobber_leaves_an_existing_destination() {
+    let scratch = Scratch::new();
+    scratch.write("a", "new");
+    scratch.write("b", "old");
+    scratch.run(&["copy", "-n", "a", "b"]).success();
+    assert_eq!(scratch.read("b"), "old");
+    scratch.run(&["copy", "--no-clobber", "a", "c"]).success();
+    assert_eq!(scratch.read("c"), "new");
+    scratch.run(&["copy", "-n", "-f", "a", "b"]).fails_with(1);
+}
+
+#[test]
+fn a_destination_ending_in_dot_dot_means_the_parent_directory() {
+    let scratch = Scratch::new();
+    scratch.write("s/file.txt", "x");
+    scratch.write("d/sub/keep", "");
+    scratch.run(&["copy", "s/file.txt", "d/sub/.."]).success();
+    assert_eq!(scratch.read("d/file.txt"), "x");
+    assert!(!scratch.exists("d/sub/file.txt"));
+}
+
+#[test]
+fn a_destination_of_dot_slash_means_the_current_directory() {
+    let scratch = Scratch::new();
+    scratch.write("s/file.txt", "x");
+    scratch.run(&["copy", "s/file.txt", "./"]).success();
+    assert_eq!(scratch.read("file.txt"), "x");
+}
+
+#[test]
+fn a_dot_dot_through_a_missing_directory_names_both_paths() {
+    let scratch = Scratch::new();
+    scratch.write("file.txt", "x");
+    let run = scratch
+        .run(&["copy", "file.txt", "missing/.."])
+        .fails_with(4);
+    let absolute = scratch.root().canonicalize().unwrap().join("missing/..");
+    assert!(run.stderr().contains("missing/.."), "{}", run.stderr());
+    assert!(run.stderr().contains("does not exist"), "{}", run.stderr());
+    assert!(
+        run.stderr().contains(&absolute.display().to_string()),
+        "{}",
+        run.stderr()
+    );
+}
+
+#[test]
+fn errors_about_a_dot_dot_destination_name_it_as_given() {
+    let scratch = Scratch::new();
+    scratch.write("a", "a");
+    scratch.write("d/b", "new");
+    scratch.write("b", "old");
+    let run = scratch.run(&["copy", "a", "d/.."]).fails_with(4);
+    assert!(run.stderr().contains("same file"), "{}", run.stderr());
+    assert!(run.stderr().contains("given as d/.."), "{}", run.stderr());
+    let run = scratch.run(&["copy", "d/b", "d/.."]).fails_with(3);
+    assert!(run.stderr().contains("given as d/.."), "{}", run.stderr());
+    assert_eq!(scratch.read("b"), "old");
+    let run = scratch.run(&["move", "a", "d/.."]).fails_with(4);
+    assert!(run.stderr().contains("given as d/.."), "{}", run.stderr());
+    assert_eq!(scratch.read("a"), "a");
+}
+
+#[test]
+fn moving_to_a_dot_dot_destination_resolves_it_too() {
+    let scratch = Scratch::new();
+    scratch.write("d/sub/file.txt", "x");
+    scratch
+        .run(&["move", "d/sub/file.txt", "d/sub/.."])
+        .success();
+    assert_eq!(scratch.read("d/file.txt"), "x");
+}
+
+#[test]
+fn preserve_keeps_the_modification_time() {
+    let scratch = Scratch::new();
+    let src = scratch.write("a", "a");
+    let old = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::new(1_000_000_000, 5_000);
+    std::fs::File::options()
+        .write(true)
+        .open(&src)
+        .unwrap()
+        .set_modified(old)
+        .unwrap();
+    scratch.run(&["copy", "--preserve", "a", "kept"]).success();
+    scratch.run(&["copy", "a", "fresh"]).success();
+    let modified = |name: &str| {
+        std::fs::metadata(scratch.path(name))
+            .unwrap()
+            .modified()
+            .unwrap()
+    };
+    assert_eq!(modified("kept"), old);
+    assert_ne!(modified("fresh"), old);
+}
+
+#[test]
+fn no_preserve_overrides_preserve() {
+    let scratch = Scratch::new();
+    let src = scratch.write("a", "a");
+    let old = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000);
+    std::fs::File::options()
+        .write(true)
+        .open(&src)
+        .unwrap()
+        .set_modified(old)
+        .unwrap();
+    scratch
+        .run(&["copy", "--preserve", "--no-preserve", "a", "b"])
+        .success();
+    assert_ne!(
+        std::fs::metadata(scratch.path("b"))
+            .unwrap()
+            .modified()
+            .unwrap(),
+        old
+    );
+}
+
+#[test]
+fn backup_keeps_every_earlier_destination() {
+    let scratch = Scratch::new();
+    scratch.write("b.txt", "v0");
+    for (round, flag) in ["v1", "v2", "v3"]
+        .into_iter()
+        .zip(["--backup", "-b", "--backup"])
+    {
+        scratch.write("a.txt", round);
+        scratch.run(&["copy", flag, "a.txt", "b.txt"]).success();
+    }
+    assert_eq!(scratch.read("b.txt"), "v3");
+    assert_eq!(scratch.read("b.txt.bak"), "v0");
+    assert_eq!(scratch.read("b.txt.bak.1"), "v1");
+    assert_eq!(scratch.read("b.txt.bak.2"), "v2");
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn the_json_report_names_the_backup() {
+    let scratch = Scratch::new();
+    scratch.write("a.txt", "new");
+    scratch.write("b.txt", "old");
+    let run = scratch
+        .run(&["--json", "copy", "--backup", "a.txt", "b.txt"])
+        .success();
+    let backup = run.json()["backup"].as_str().unwrap().to_string();
+    assert!(backup.ends_with("b.txt.bak"), "{backup}");
+    assert_eq!(scratch.read("b.txt.bak"), "old");
+}
+
+#[test]
+fn no_backup_overrides_backup() {
+    let scratch = Scratch::new();
+    scratch.write("a.txt", "new");
+    scratch.write("b.txt", "old");
+    scratch
+        .run(&["copy", "--backup", "--no-backup", "a.txt", "b.txt"])
+        .fails_with(3);
+    assert_eq!(scratch.read("b.txt"), "old");
+    assert!(!scratch.exists("b.txt.bak"));
+}
+
+#[test]
+fn interactive_asks_before_overwriting() {
+    let scratch = Scratch::new();
+    scratch.write("a.txt", "new");
+    scratch.write("dst/a.txt", "old");
+    for answer in ["y\n", "YES\n"] {
+        scratch.write("dst/a.txt", "old");
+        let run = scratch.run_with_input(&["copy", "a.txt", "dst/", "-i"], answer);
+        let stderr = run.success().stderr();
+        assert!(stderr.contains("overwrite dst/a.txt? [y/N]"), "{stderr}");
+        assert_eq!(scratch.read("dst/a.txt"), "new");
+    }
+}
+
+#[test]
+fn interactive_skips_on_any_other_answer() {
+    let scratch = Scratch::new();
+    scratch.write("a.txt", "new");
+    scratch.write("b.txt", "old");
+    for answer in ["n\n", "\n", "yep\n", ""] {
+        let run = scratch.run_with_input(&["copy", "--interactive", "a.txt", "b.txt"], answer);
+        let stderr = run.success().stderr();
+        assert!(stderr.contains("skipped b.txt"), "{stderr}");
+        assert_eq!(scratch.read("b.txt"), "old");
+    }
+}
+
+#[test]
+fn interactive_does_not_ask_about_a_new_destination() {
+    let scratch = Scratch::new();
+    scratch.write("a.txt", "new");
+    let run = scratch.run_with_input(&["copy", "-i", "a.txt", "b.txt"], "n\n");
+    let stderr = run.success().stderr();
+    assert!(!stderr.contains("overwrite"), "{stderr}");
+    assert_eq!(scratch.read("b.txt"), "new");
+}
+
+#[test]
+fn an_existing_destination_still_fails_without_interactive() {
+    let scratch = Scratch::new();
+    scratch.write("a.txt", "new");
+    scratch.write("b.txt", "old");
+    let run = scratch.run_with_input(&["copy", "a.txt", "b.txt"], "y\n");
+    let stderr = run.fails_with(3).stderr();
+    assert!(!stderr.contains("overwrite b.txt?"), "{stderr}");
+    assert_eq!(scratch.read("b.txt"), "old");
+}
+
+#[test]
+fn verify_reports_the_copy_as_checked() {
+    let scratch = Scratch::new();
+    let data: String = (0..300_000)
+        .map(|i| char::from(b'a' + (i % 23) as u8))
+        .collect();
+    scratch.write("a.txt", &data);
+    scratch
+        .run(&["copy", "--verify", "a.txt", "b.txt"])
+        .success();
+    assert_eq!(scratch.read("b.txt"), data);
+    #[cfg(feature = "json")]
+    {
+        let run = scratch
+            .run(&["--json", "copy", "--verify", "a.txt", "c.txt"])
+            .success();
+        assert_eq!(run.json()["verified"], true);
+    }
+}
+
+#[test]
+fn a_dry_run_skips_verification() {
+    let scratch = Scratch::new();
+    scratch.write("a.txt", "data");
+    scratch
+        .run(&["--dry-run", "copy", "--verify", "a.txt", "b.txt"])
+        .success();
+    assert!(!scratch.exists("b.txt"));
+    #[cfg(feature = "json")]
+    {
+        let run = scratch
+            .run(&["--json", "--dry-run", "copy", "--verify", "a.txt", "b.txt"])
+            .success();
+        assert_eq!(run.json()["verified"], false);
+    }
+}
+
+#[cfg(unix)]
+fn read_only_dir(scratch: &Scratch, dir: &str) {
+    use std::os::unix::fs::PermissionsExt;
+
+    std::fs::create_dir_all(scratch.path(dir)).unwrap();
+    std::fs::set_permissions(scratch.path(dir), std::fs::Permissions::from_mode(0o555)).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn a_read_only_destination_is_permission_denied() {
+    let scratch = Scratch::new();
+    scratch.write("a.txt", "a");
+    read_only_dir(&scratch, "locked");
+    let run = scratch
+        .run_unprivileged(&["copy", "a.txt", "locked/a.txt"])
+        .fails_with(5);
+    assert!(
+        run.stderr().contains("permission denied: locked/a.txt"),
+        "{}",
+        run.stderr()
+    );
+    assert!(!scratch.exists("locked/a.txt"));
+}
+
+#[cfg(unix)]
+#[test]
+fn an_unreadable_source_is_permission_denied() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let scratch = Scratch::new();
+    scratch.write("secret", "s");
+    std::fs::set_permissions(
+        scratch.path("secret"),
+        std::fs::Permissions::from_mode(0o000),
+    )
+    .unwrap();
+    let run = scratch
+        .run_unprivileged(&["copy", "secret", "out"])
+        .fails_with(5);
+    assert!(run.stderr().contains("secret"), "{}", run.stderr());
+    assert!(!scratch.exists("out"));
+}
+
+#[cfg(all(unix, feature = "json"))]
+#[test]
+fn json_names_the_permission_denied_kind() {
+    let scratch = Scratch::new();
+    scratch.write("a.txt", "a");
+    read_only_dir(&scratch, "locked");
+    let run = scratch
+        .run_unprivileged(&["--json", "copy", "a.txt", "locked/a.txt"])
+        .fails_with(5);
+    let json = run.json();
+    assert_eq!(json["status"], "error");
+    assert_eq!(json["kind"], "permission-denied");
+    assert_eq!(json["message"], "permission denied: locked/a.txt");
+}
@@ -0,0 +1,189 @@
+mod common;
+
+use common::Scratch;
+
+/// A tree with a nested file and an empty directory.
+fn tree(scratch: &Scratch) {
+    scratch.write("s/a", "1");
+    scratch.write("s/sub/b", "2");
+    std::fs::create_dir(scratch.path("s/empty")).unwrap();
+}
+
+#[test]
+fn recursive_copy_recreates_the_tree() {
+    let scratch = Scratch::new();
+    tree(&scratch);
+    scratch.run(&["copy", "-r", "s", "d"]).success();
+    assert_eq!(scratch.read("d/a"), "1");
+    assert_eq!(scratch.read("d/sub/b"), "2");
+    assert!(scratch.path("d/empty").is_dir());
+}
+
+#[test]
+fn recursive_copy_into_an_existing_directory_nests_the_source() {
+    let scratch = Scratch::new();
+    tree(&scratch);
+    std::fs::create_dir(scratch.path("d")).unwrap();
+    scratch.run(&["copy", "--recursive", "s", "d"]).success();
+    assert_eq!(scratch.read("d/s/sub/b"), "2");
+    assert!(scratch.path("d/s/empty").is_dir());
+}
+
+#[test]
+fn a_conflicting_file_fails_and_keeps_what_was_copied() {
+    let scratch = Scratch::new();
+    tree(&scratch);
+    scratch.write("s/sub/c", "3");
+    scratch.write("d/s/sub/b", "mine");
+    let run = scratch.run(&["copy", "-r", "s", "d"]).fails_with(3);
+    assert!(run.stderr().contains("already exists"), "{}", run.stderr());
+    assert_eq!(scratch.read("d/s/a"), "1");
+    assert!(scratch.path("d/s/empty").is_dir());
+    assert_eq!(scratch.read("d/s/sub/b"), "mine");
+    assert!(!scratch.exists("d/s/sub/c"));
+}
+
+#[test]
+fn force_overwrites_conflicting_files() {
+    let scratch = Scratch::new();
+    tree(&scratch);
+    scratch.write("d/s/a", "mine");
+    scratch.run(&["copy", "-r", "--force", "s", "d"]).success();
+    assert_eq!(scratch.read("d/s/a"), "1");
+}
+
+#[test]
+fn copying_a_directory_into_itself_is_rejected() {
+    let scratch = Scratch::new();
+    tree(&scratch);
+    scratch.run(&["copy", "-r", "s", "s/sub"]).fails_with(4);
+    #[cfg(feature = "json")]
+    {
+        let run = scratch
+            .run(&["--json", "copy", "-r", "s", "s/sub"])
+            .fails_with(4);
+        assert_eq!(run.json()["kind"], "invalid-input");
+    }
+    assert!(!scratch.exists("s/sub/s"));
+}
+
+#[test]
+fn a_directory_needs_recursive() {
+    let scratch = Scratch::new();
+    tree(&scratch);
+    scratch.run(&["copy", "s", "d"]).fails_with(4);
+    assert!(!scratch.exists("d"));
+}
+
+#[test]
+fn ignore_vanished_still_needs_the_source() {
+    let scratch = Scratch::new();
+    scratch
+        .run(&["copy", "-r", "--ignore-vanished", "missing", "d"])
+        .fails_with(2);
+    tree(&scratch);
+    scratch
+        .run(&["copy", "-r", "--ignore-vanished", "s", "d"])
+        .success();
+    assert_eq!(scratch.read("d/sub/b"), "2");
+    #[cfg(feature = "json")]
+    {
+        let report = scratch
+            .run(&["--json", "copy", "-r", "--ignore-vanished", "s", "e"])
+            .success()
+            .json();
+        assert_eq!(report["vanished"], 0);
+        assert_eq!(report["files"], 2);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn sanitize_windows_names_rewrites_reserved_characters() {
+    let scratch = Scratch::new();
+    scratch.write("s/what?/a:b.", "1");
+    scratch
+        .run(&["copy", "-r", "--sanitize-windows-names", "s", "d"])
+        .success();
+    assert_eq!(scratch.read("d/what_/a_b"), "1");
+}
+
+#[test]
+fn lowercase_names_lowercases_the_tree() {
+    let scratch = Scratch::new();
+    scratch.write("s/Sub/README.md", "1");
+    scratch
+        .run(&["copy", "-r", "--lowercase-names", "s", "d"])
+        .success();
+    assert_eq!(scratch.read("d/sub/readme.md"), "1");
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn names_colliding_after_the_transform_fail_naming_both() {
+    let scratch = Scratch::new();
+    scratch.write("s/Notes", "1");
+    scratch.write("s/notes", "2");
+    let run = scratch
+        .run(&["copy", "-r", "--lowercase-names", "s", "d"])
+        .fails_with(3);
+    assert!(
+        run.stderr().contains("both map to notes"),
+        "{}",
+        run.stderr()
+    );
+    assert!(run.stderr().contains("Notes"), "{}", run.stderr());
+}
+
+#[cfg(unix)]
+#[test]
+fn hardlink_counts_links_instead_of_bytes() {
+    use std::os::unix::fs::MetadataExt;
+    let scratch = Scratch::new();
+    tree(&scratch);
+    #[cfg(not(feature = "json"))]
+    scratch
+        .run(&["copy", "-r", "--hardlink", "s", "d"])
+        .success();
+    #[cfg(feature = "json")]
+    {
+        let report = scratch
+            .run(&["--json", "copy", "-r", "--hardlink", "s", "d"])
+            .success()
+            .json();
+        assert_eq!(report["links"], 2);
+        assert_eq!(report["files"], 0);
+        assert_eq!(report["bytes"], 0);
+    }
+    let ino = |rel| std::fs::metadata(scratch.path(rel)).unwrap().ino();
+    assert_eq!(ino("d/sub/b"), ino("s/sub/b"));
+    assert!(scratch.path("d/empty").is_dir());
+}
+
+#[cfg(unix)]
+#[test]
+fn symlink_relative_points_back_at_the_sources() {
+    let scratch = Scratch::new();
+    tree(&scratch);
+    scratch
+        .run(&["copy", "-r", "--symlink", "--relative", "s", "d"])
+        .success();
+    let target = std::fs::read_link(scratch.path("d/sub/b")).unwrap();
+    assert_eq!(target, std::path::Path::new("../../s/sub/b"));
+    scratch.run(&["copy", "--symlink", "s/a", "abs"]).success();
+    let target = std::fs::read_link(scratch.path("abs")).unwrap();
+    assert!(target.is_absolute(), "{}", target.display());
+    assert_eq!(scratch.read("abs"), "1");
+}
+
+#[test]
+fn relative_needs_symlink() {
+    let scratch = Scratch::new();
+    tree(&scratch);
+    scratch
+        .run(&["copy", "-r", "--relative", "s", "d"])
+        .fails_with(1);
+    scratch
+        .run(&["copy", "--hardlink", "--symlink", "s/a", "b"])
+        .fails_with(1);
+}
@@ -0,0 +1,108 @@
+mod common;
+
+use common::Scratch;
+
+fn logs(scratch: &Scratch) {
+    scratch.write("logs/a.txt", "a");
+    scratch.write("logs/b.txt", "b");
+    scratch.write("logs/c.log", "c");
+    std::fs::create_dir(scratch.path("backup")).unwrap();
+}
+
+#[test]
+fn every_match_is_copied_into_the_directory() {
+    let scratch = Scratch::new();
+    logs(&scratch);
+    scratch.run(&["copy", "logs/*.txt", "backup/"]).success();
+    assert_eq!(scratch.read("backup/a.txt"), "a");
+    assert_eq!(scratch.read("backup/b.txt"), "b");
+    assert!(!scratch.exists("backup/c.log"));
+}
+
+#[test]
+fn question_marks_and_sets_expand_too() {
+    let scratch = Scratch::new();
+    logs(&scratch);
+    scratch.run(&["copy", "logs/[ac].???", "backup"]).success();
+    assert!(scratch.exists("backup/a.txt"));
+    assert!(scratch.exists("backup/c.log"));
+    assert!(!scratch.exists("backup/b.txt"));
+}
+
+#[test]
+fn no_match_is_an_error_naming_the_pattern() {
+    let scratch = Scratch::new();
+    logs(&scratch);
+    let run = scratch.run(&["copy", "logs/*.csv", "backup"]).fails_with(4);
+    assert!(
+        run.stderr().contains("no files match pattern logs/*.csv"),
+        "{}",
+        run.stderr()
+    );
+}
+
+#[test]
+fn the_destination_must_be_an_existing_directory() {
+    let scratch = Scratch::new();
+    logs(&scratch);
+    scratch
+        .run(&["copy", "logs/*.txt", "missing"])
+        .fails_with(4);
+    scratch.write("file", "");
+    scratch.run(&["copy", "logs/*.txt", "file"]).fails_with(4);
+    assert_eq!(scratch.read("file"), "");
+}
+
+#[test]
+fn an_existing_name_with_brackets_is_copied_literally() {
+    let scratch = Scratch::new();
+    scratch.write("report[1].txt", "literal");
+    scratch.write("report1.txt", "pattern match");
+    scratch.run(&["copy", "report[1].txt", "out.txt"]).success();
+    assert_eq!(scratch.read("out.txt"), "literal");
+}
+
+#[test]
+fn recursive_copies_matching_directories() {
+    let scratch = Scratch::new();
+    scratch.write("src/one/f", "1");
+    scratch.write("src/two/g", "2");
+    scratch.write("src/skip/h", "3");
+    std::fs::create_dir(scratch.path("d")).unwrap();
+    scratch.run(&["copy", "-r", "src/t*", "d"]).success();
+    assert_eq!(scratch.read("d/two/g"), "2");
+    assert!(!scratch.exists("d/one"));
+    assert!(!scratch.exists("d/skip"));
+}
+
+#[test]
+fn a_failing_match_does_not_stop_the_others() {
+    let scratch = Scratch::new();
+    logs(&scratch);
+    scratch.write("backup/a.txt", "already there");
+    scratch.run(&["copy", "logs/*.txt", "backup"]).fails_with(3);
+    assert_eq!(scratch.read("backup/a.txt"), "already there");
+    assert_eq!(scratch.read("backup/b.txt"), "b");
+}
+
+#[test]
+fn checkpoint_needs_a_single_source() {
+    let scratch = Scratch::new();
+    logs(&scratch);
+    scratch
+        .run(&["copy", "-r", "--checkpoint", "cp", "logs/*", "backup"])
+        .fails_with(4);
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn json_reports_every_copy_under_the_pattern() {
+    let scratch = Scratch::new();
+    logs(&scratch);
+    let report = scratch
+        .run(&["--json", "copy", "logs/*.txt", "backup"])
+        .success()
+        .json();
+    assert_eq!(report["pattern"], "logs/*.txt");
+    assert_eq!(report["copies"].as_array().unwrap().len(), 2);
+}
@@ -0,0 +1,316 @@
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use fman::backend::CopyStrategy;
+use fman::fs::{DryRunFs, RecordingFs};
+use fman::naming::NamingContext;
+use fman::preserve::Attribute;
+use fman::walk::SymlinkPolicy;
+use fman::{CopyOptions, CopyStatus, FmanError, OverwritePolicy, Reflink, copy_file_with_options};
+use tempfile::TempDir;
+
+/// A directory holding `a` ("new") and `b` ("old").
+fn scratch() -> TempDir {
+    let dir = TempDir::new().unwrap();
+    fs::write(dir.path().join("a"), "new").unwrap();
+    fs::write(dir.path().join("b"), "old").unwrap();
+    dir
+}
+
+fn read(dir: &TempDir, name: &str) -> String {
+    fs::read_to_string(dir.path().join(name)).unwrap()
+}
+
+fn set_modified(path: &Path, time: SystemTime) {
+    fs::File::options()
+        .write(true)
+        .open(path)
+        .unwrap()
+        .set_modified(time)
+        .unwrap();
+}
+
+fn copy(
+    dir: &TempDir,
+    src: &str,
+    dst: &str,
+    options: &CopyOptions,
+) -> fman::FmanResult<fman::CopyReport> {
+    copy_file_with_options(dir.path().join(src), dir.path().join(dst), options)
+}
+
+#[test]
+fn the_defaults_refuse_to_overwrite() {
+    let dir = scratch();
+    let err = copy(&dir, "a", "b", &CopyOptions::default()).unwrap_err();
+    assert!(matches!(err, FmanError::AlreadyExists(_)), "{err}");
+    let report = copy(&dir, "a", "c", &CopyOptions::new()).unwrap();
+    assert_eq!(report.status, CopyStatus::Copied);
+    assert_eq!(report.bytes, 3);
+    assert_eq!(report.destination, dir.path().join("c"));
+}
+
+#[test]
+fn force_and_on_conflict_decide_about_an_existing_file() {
+    let dir = scratch();
+    let options = CopyOptions::new().force(true);
+    copy(&dir, "a", "b", &options.clone().force(false)).unwrap_err();
+    assert_eq!(read(&dir, "b"), "old");
+
+    let report = copy(
+        &dir,
+        "a",
+        "b",
+        &CopyOptions::new().on_conflict(OverwritePolicy::Skip),
+    )
+    .unwrap();
+    assert_eq!(report.status, CopyStatus::Skipped);
+    assert_eq!(read(&dir, "b"), "old");
+
+    let options = CopyOptions::new()
+        .on_conflict(OverwritePolicy::RenameNew)
+        .naming(Arc::new(NamingContext::seeded(1)));
+    let report = copy(&dir, "a", "b", &options).unwrap();
+    assert_ne!(report.destination, dir.path().join("b"));
+    assert_eq!(fs::read_to_string(&report.destination).unwrap(), "new");
+
+    copy(&dir, "a", "b", &CopyOptions::new().force(true)).unwrap();
+    assert_eq!(read(&dir, "b"), "new");
+}
+
+#[test]
+fn confirm_overwrite_is_asked_about_the_destination() {
+    let dir = scratch();
+    let asked = Arc::new(Mutex::new(Vec::new()));
+    let seen = Arc::clone(&asked);
+    let options = CopyOptions::new().confirm_overwrite(move |path| {
+        seen.lock().unwrap().push(path.to_path_buf());
+        false
+    });
+    let report = copy(&dir, "a", "b", &options).unwrap();
+    assert_eq!(report.status, CopyStatus::Skipped);
+    assert_eq!(*asked.lock().unwrap(), [dir.path().join("b")]);
+    assert_eq!(read(&dir, "b"), "old");
+}
+
+#[test]
+fn update_replaces_only_older_destinations() {
+    let dir = scratch();
+    let now = SystemTime::now();
+    set_modified(&dir.path().join("b"), now + Duration::from_secs(60));
+    let options = CopyOptions::new().update(true);
+    assert_eq!(
+        copy(&dir, "a", "b", &options).unwrap().status,
+        CopyStatus::Skipped
+    );
+    assert_eq!(read(&dir, "b"), "old");
+
+    let window = options.clone().modify_window(Duration::from_secs(120));
+    assert_eq!(
+        copy(&dir, "a", "b", &window).unwrap().status,
+        CopyStatus::Skipped
+    );
+
+    set_modified(&dir.path().join("b"), now - Duration::from_secs(60));
+    assert_eq!(
+        copy(&dir, "a", "b", &options).unwrap().status,
+        CopyStatus::Copied
+    );
+    assert_eq!(read(&dir, "b"), "new");
+}
+
+#[test]
+fn backup_keeps_the_replaced_file_under_its_suffix() {
+    let dir = scratch();
+    let report = copy(&dir, "a", "b", &CopyOptions::new().backup(true)).unwrap();
+    assert_eq!(report.backup, Some(dir.path().join("b.bak")));
+    assert_eq!(read(&dir, "b.bak"), "old");
+
+    let options = CopyOptions::new().backup(true).backup_suffix("~");
+    let report = copy(&dir, "b.bak", "b", &options).unwrap();
+    assert_eq!(report.backup, Some(dir.path().join("b~")));
+    assert_eq!(read(&dir, "b~"), "new");
+    assert_eq!(read(&dir, "b"), "old");
+}
+
+#[test]
+fn parents_creates_the_missing_directories() {
+    let dir = scratch();
+    let err = copy(&dir, "a", "x/y/", &CopyOptions::new()).unwrap_err();
+    assert!(matches!(err, FmanError::DestinationDirMissing(_)), "{err}");
+    let report = copy(&dir, "a", "x/y/", &CopyOptions::new().parents(true)).unwrap();
+    assert_eq!(report.destination, dir.path().join("x/y/a"));
+    assert_eq!(read(&dir, "x/y/a"), "new");
+}
+
+#[test]
+fn preserve_keeps_the_modification_time() {
+    let dir = scratch();
+    let old = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+    set_modified(&dir.path().join("a"), old);
+    let modified = |name: &str| {
+        fs::metadata(dir.path().join(name))
+            .unwrap()
+            .modified()
+            .unwrap()
+    };
+    copy(&dir, "a", "kept", &CopyOptions::new().preserve(true)).unwrap();
+    assert_eq!(modified("kept"), old);
+    copy(&dir, "a", "fresh", &CopyOptions::new()).unwrap();
+    assert_ne!(modified("fresh"), old);
+}
+
+#[test]
+fn verify_and_atomic_report_a_checked_whole_file() {
+    let dir = scratch();
+    let report = copy(&dir, "a", "c", &CopyOptions::new().verify(true)).unwrap();
+    assert!(report.verified);
+    assert!(!copy(&dir, "a", "d", &CopyOptions::new()).unwrap().verified);
+
+    copy(&dir, "a", "b", &CopyOptions::new().atomic(true).force(true)).unwrap();
+    assert_eq!(read(&dir, "b"), "new");
+    let leftovers: Vec<_> = fs::read_dir(dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .filter(|name| name.to_string_lossy().starts_with(".fman-tmp-"))
+        .collect();
+    assert!(leftovers.is_empty(), "{leftovers:?}");
+}
+
+#[test]
+fn read_only_clears_write_permission() {
+    let dir = scratch();
+    copy(&dir, "a", "c", &CopyOptions::new().read_only(true)).unwrap();
+    assert!(
+        fs::metadata(dir.path().join("c"))
+            .unwrap()
+            .permissions()
+            .readonly()
+    );
+    assert!(
+        !fs::metadata(dir.path().join("a"))
+            .unwrap()
+            .permissions()
+            .readonly()
+    );
+}
+
+#[test]
+fn transform_rewrites_the_contents() {
+    let dir = scratch();
+    let options = CopyOptions::new()
+        .transform(|src| Ok(Some(fs::read_to_string(src)?.to_uppercase().into_bytes())));
+    copy(&dir, "a", "c", &options).unwrap();
+    assert_eq!(read(&dir, "c"), "NEW");
+}
+
+#[test]
+fn progress_ends_with_the_full_size() {
+    let dir = scratch();
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    let seen = Arc::clone(&calls);
+    let options =
+        CopyOptions::new().progress(move |done, total| seen.lock().unwrap().push((done, total)));
+    copy(&dir, "a", "c", &options).unwrap();
+    assert_eq!(calls.lock().unwrap().last(), Some(&(3, 3)));
+}
+
+#[test]
+fn data_path_options_all_produce_the_same_copy() {
+    let dir = scratch();
+    let big: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+    fs::write(dir.path().join("big"), &big).unwrap();
+    let cases = [
+        ("buffer", CopyOptions::new().buffer_size(7)),
+        ("stream", CopyOptions::new().stream(true)),
+        ("small", CopyOptions::new().small_file_threshold(1 << 20)),
+        ("huge", CopyOptions::new().huge_file_threshold(1024)),
+        ("sparse", CopyOptions::new().sparse(true)),
+        ("reflink", CopyOptions::new().reflink(Reflink::Auto)),
+    ];
+    for (name, options) in cases {
+        let report = copy(&dir, "big", name, &options).unwrap();
+        assert_eq!(report.bytes, big.len() as u64, "{name}");
+        assert_eq!(fs::read(dir.path().join(name)).unwrap(), big, "{name}");
+    }
+    let report = copy(
+        &dir,
+        "big",
+        "chunks",
+        &CopyOptions::new().huge_file_threshold(1024),
+    )
+    .unwrap();
+    assert_eq!(report.strategy, CopyStrategy::ParallelChunks);
+    assert!(
+        copy(&dir, "big", "streamed", &CopyOptions::new().stream(true))
+            .unwrap()
+            .streamed
+    );
+}
+
+#[test]
+fn fs_can_plan_the_copy_without_making_it() {
+    let dir = scratch();
+    let planned = Arc::new(DryRunFs::new());
+    let report = copy(&dir, "a", "c", &CopyOptions::new().fs(planned.clone())).unwrap();
+    assert_eq!(report.destination, dir.path().join("c"));
+    assert!(!dir.path().join("c").exists());
+    assert!(!planned.ops().is_empty());
+
+    let recorded = Arc::new(RecordingFs::new());
+    copy(&dir, "a", "c", &CopyOptions::new().fs(recorded.clone())).unwrap();
+    assert_eq!(read(&dir, "c"), "new");
+    assert!(!recorded.ops().is_empty());
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinks_decides_whether_a_link_is_followed() {
+    let dir = scratch();
+    std::os::unix::fs::symlink("a", dir.path().join("link")).unwrap();
+    let options = CopyOptions::new().symlinks(SymlinkPolicy::Never);
+    let report = copy(&dir, "link", "kept", &options).unwrap();
+    assert_eq!(report.link_target.as_deref(), Some(Path::new("a")));
+    assert!(
+        fs::symlink_metadata(dir.path().join("kept"))
+            .unwrap()
+            .is_symlink()
+    );
+
+    let report = copy(&dir, "link", "followed", &CopyOptions::new()).unwrap();
+    assert_eq!(report.link_target, None);
+    assert!(
+        fs::symlink_metadata(dir.path().join("followed"))
+            .unwrap()
+            .is_file()
+    );
+}
+
+#[test]
+fn attributes_only_touches_no_data() {
+    let dir = scratch();
+    let old = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+    set_modified(&dir.path().join("a"), old);
+    let options = CopyOptions::new().attributes_only(&[Attribute::Times]);
+    let report = copy(&dir, "a", "b", &options).unwrap();
+    assert_eq!(report.status, CopyStatus::Attributes);
+    assert_eq!(read(&dir, "b"), "old");
+    assert_eq!(
+        fs::metadata(dir.path().join("b"))
+            .unwrap()
+            .modified()
+            .unwrap(),
+        old
+    );
+
+    assert!(
+        copy(&dir, "a", "missing", &options)
+            .unwrap_err()
+            .is_not_found()
+    );
+    let report = copy(&dir, "a", "missing", &options.clone().ignore_missing(true)).unwrap();
+    assert_eq!(report.status, CopyStatus::Skipped);
+    assert!(!dir.path().join("missing").exists());
+}
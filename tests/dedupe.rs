@@ -0,0 +1,99 @@
+mod common;
+
+use common::Scratch;
+
+/// `d/a` and `d/sub/b` the same, `d/c` different but the same size.
+fn duplicates(scratch: &Scratch) {
+    scratch.write("d/a", "twin");
+    scratch.write("d/sub/b", "twin");
+    scratch.write("d/c", "solo");
+}
+
+#[test]
+fn dedupe_prints_each_group() {
+    let scratch = Scratch::new();
+    duplicates(&scratch);
+    scratch.write("d/x", "pair!");
+    scratch.write("d/y", "pair!");
+    let run = scratch.run(&["dedupe", "d"]).success();
+    let sep = std::path::MAIN_SEPARATOR;
+    assert_eq!(
+        run.stdout(),
+        format!("d{sep}a\nd{sep}sub{sep}b\n\nd{sep}x\nd{sep}y\n")
+    );
+    assert!(run.stderr().contains("2 groups"), "{}", run.stderr());
+}
+
+#[test]
+fn delete_without_force_asks_first() {
+    let scratch = Scratch::new();
+    duplicates(&scratch);
+    let run = scratch
+        .run_with_input(&["dedupe", "--delete", "d"], "n\n")
+        .success();
+    assert!(
+        run.stderr().contains("delete 1 duplicate files"),
+        "{}",
+        run.stderr()
+    );
+    assert!(scratch.exists("d/sub/b"));
+    scratch
+        .run_with_input(&["dedupe", "--delete", "d"], "y\n")
+        .success();
+    assert!(!scratch.exists("d/sub/b"));
+    assert!(scratch.exists("d/a"));
+}
+
+#[test]
+fn delete_with_force_keeps_the_first_in_path_order() {
+    let scratch = Scratch::new();
+    duplicates(&scratch);
+    scratch
+        .run(&["dedupe", "--delete", "--force", "d"])
+        .success();
+    assert_eq!(scratch.read("d/a"), "twin");
+    assert!(!scratch.exists("d/sub/b"));
+    assert_eq!(scratch.read("d/c"), "solo");
+}
+
+#[test]
+fn a_dry_run_deletes_nothing() {
+    let scratch = Scratch::new();
+    duplicates(&scratch);
+    scratch
+        .run(&["--dry-run", "dedupe", "--delete", "d"])
+        .success();
+    assert!(scratch.exists("d/sub/b"));
+}
+
+#[test]
+fn force_needs_delete() {
+    let scratch = Scratch::new();
+    duplicates(&scratch);
+    scratch.run(&["dedupe", "--force", "d"]).fails_with(1);
+}
+
+#[test]
+fn empty_files_are_grouped_with_include_empty() {
+    let scratch = Scratch::new();
+    scratch.write("d/e1", "");
+    scratch.write("d/e2", "");
+    let plain = scratch.run(&["dedupe", "d"]).success();
+    assert_eq!(plain.stdout(), "");
+    let grouped = scratch.run(&["dedupe", "--include-empty", "d"]).success();
+    assert_eq!(grouped.stdout().lines().count(), 2);
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn dedupe_json_reports_groups_and_removals() {
+    let scratch = Scratch::new();
+    duplicates(&scratch);
+    let report = scratch
+        .run(&["--json", "dedupe", "--delete", "--force", "d"])
+        .success()
+        .json();
+    assert_eq!(report["report"]["groups"][0]["size"], 4);
+    assert_eq!(report["report"]["redundant_bytes"], 4);
+    assert_eq!(report["removed"]["removed"].as_array().unwrap().len(), 1);
+}
@@ -0,0 +1,133 @@
+mod common;
+
+use common::Scratch;
+
+#[test]
+fn delete_removes_a_file() {
+    let scratch = Scratch::new();
+    scratch.write("f", "x");
+    let run = scratch.run(&["-v", "delete", "f"]).success();
+    assert!(!scratch.exists("f"));
+    assert_eq!(run.stdout(), "removed f\n");
+}
+
+#[test]
+fn a_directory_needs_recursive() {
+    let scratch = Scratch::new();
+    scratch.write("d/a", "1");
+    let run = scratch.run(&["delete", "d"]).fails_with(4);
+    assert!(run.stderr().contains("--recursive"), "{}", run.stderr());
+    assert!(scratch.exists("d/a"));
+    scratch.run(&["delete", "-r", "d"]).success();
+    assert!(!scratch.exists("d"));
+}
+
+#[test]
+fn a_missing_target_fails_unless_forced() {
+    let scratch = Scratch::new();
+    scratch.run(&["delete", "missing"]).fails_with(2);
+    scratch.run(&["delete", "-f", "missing"]).success();
+}
+
+#[test]
+fn a_pattern_deletes_every_match() {
+    let scratch = Scratch::new();
+    scratch.write("logs/a.tmp", "1");
+    scratch.write("logs/b.tmp", "2");
+    scratch.write("logs/keep.log", "3");
+    scratch.run(&["delete", "--yes", "logs/*.tmp"]).success();
+    assert!(!scratch.exists("logs/a.tmp"));
+    assert!(!scratch.exists("logs/b.tmp"));
+    assert!(scratch.exists("logs/keep.log"));
+}
+
+#[cfg(unix)]
+#[test]
+fn a_symlink_is_removed_not_its_target() {
+    let scratch = Scratch::new();
+    scratch.write("d/a", "1");
+    std::os::unix::fs::symlink("d", scratch.path("link")).unwrap();
+    scratch.run(&["delete", "link"]).success();
+    assert!(!scratch.exists("link"));
+    assert!(scratch.exists("d/a"));
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn json_counts_what_was_removed() {
+    let scratch = Scratch::new();
+    scratch.write("d/a", "1");
+    scratch.write("d/sub/b", "2");
+    let report = scratch
+        .run(&["--json", "delete", "-r", "d"])
+        .success()
+        .json();
+    assert_eq!(report["operation"], "delete");
+    assert_eq!(report["files"], 2);
+    assert_eq!(report["directories"], 2);
+}
+
+#[test]
+fn a_missing_target_is_fatal_even_when_ignoring_vanished_files() {
+    let scratch = Scratch::new();
+    for flag in ["--ignore-vanished", "--no-ignore-vanished"] {
+        scratch
+            .run(&["delete", "-r", flag, "missing"])
+            .fails_with(2);
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn report_open_names_the_process_holding_the_file() {
+    let scratch = Scratch::new();
+    scratch.write("held", "12345");
+    let _handle = std::fs::File::open(scratch.path("held")).unwrap();
+    let run = scratch.run(&["delete", "--report-open", "held"]).success();
+    assert!(!scratch.exists("held"));
+    let pid = format!("(pid {})", std::process::id());
+    assert!(run.stderr().contains(&pid), "{}", run.stderr());
+    assert!(run.stderr().contains("5B stays in use"), "{}", run.stderr());
+}
+
+/// A module cache as Go leaves it: files in directories with no write
+/// permission.
+#[cfg(unix)]
+fn read_only_tree(scratch: &Scratch) {
+    use std::fs::{self, Permissions};
+    use std::os::unix::fs::PermissionsExt;
+
+    scratch.write("cache/pkg@v1/a.go", "a");
+    scratch.write("cache/pkg@v1/sub/b.go", "b");
+    for dir in ["cache/pkg@v1/sub", "cache/pkg@v1"] {
+        fs::set_permissions(scratch.path(dir), Permissions::from_mode(0o555)).unwrap();
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn a_read_only_directory_stops_a_delete_and_is_named() {
+    let scratch = Scratch::new();
+    read_only_tree(&scratch);
+    let run = scratch
+        .run_unprivileged(&["delete", "-r", "cache"])
+        .fails_with(5);
+    let stderr = run.stderr();
+    assert!(
+        stderr.contains("cache/pkg@v1 is read-only") && stderr.contains("--force"),
+        "{stderr}"
+    );
+    assert!(scratch.exists("cache/pkg@v1/a.go"));
+    assert!(scratch.exists("cache/pkg@v1/sub/b.go"));
+}
+
+#[cfg(unix)]
+#[test]
+fn force_deletes_through_read_only_directories() {
+    let scratch = Scratch::new();
+    read_only_tree(&scratch);
+    scratch
+        .run_unprivileged(&["delete", "-r", "-f", "cache"])
+        .success();
+    assert!(!scratch.exists("cache"));
+}
@@ -0,0 +1,123 @@
+#![cfg(unix)]
+
+mod common;
+
+use std::os::unix::fs::symlink;
+use std::path::Path;
+
+use common::Scratch;
+
+/// `f`, a directory `d` holding `x` and a link `inner -> ../f`, and links
+/// `lf -> f` and `ld -> d` to pass on the command line.
+fn links() -> Scratch {
+    let scratch = Scratch::new();
+    scratch.write("f", "file");
+    scratch.write("d/x", "x");
+    symlink("../f", scratch.path("d/inner")).unwrap();
+    symlink("f", scratch.path("lf")).unwrap();
+    symlink("d", scratch.path("ld")).unwrap();
+    scratch
+}
+
+fn link_target(scratch: &Scratch, rel: &str) -> Option<String> {
+    std::fs::read_link(scratch.path(rel))
+        .ok()
+        .map(|target| target.display().to_string())
+}
+
+fn is_dir(scratch: &Scratch, rel: &str) -> bool {
+    let meta = std::fs::symlink_metadata(scratch.path(rel)).unwrap();
+    meta.is_dir()
+}
+
+#[test]
+fn no_dereference_copies_command_line_links_as_links() {
+    let scratch = links();
+    scratch.run(&["copy", "-P", "lf", "out-f"]).success();
+    scratch.run(&["copy", "-r", "-P", "ld", "out-d"]).success();
+    assert_eq!(link_target(&scratch, "out-f").as_deref(), Some("f"));
+    assert_eq!(link_target(&scratch, "out-d").as_deref(), Some("d"));
+}
+
+#[test]
+fn dereference_command_line_follows_only_the_given_links() {
+    let scratch = links();
+    for flag in ["-H", "--dereference-command-line"] {
+        let (out_f, out_d) = (format!("f{flag}"), format!("d{flag}"));
+        scratch.run(&["copy", flag, "lf", &out_f]).success();
+        scratch.run(&["copy", "-r", flag, "ld", &out_d]).success();
+        assert_eq!(link_target(&scratch, &out_f), None);
+        assert_eq!(scratch.read(&out_f), "file");
+        assert!(is_dir(&scratch, &out_d));
+        assert_eq!(scratch.read(&format!("{out_d}/x")), "x");
+        assert_eq!(
+            link_target(&scratch, &format!("{out_d}/inner")).as_deref(),
+            Some("../f")
+        );
+    }
+}
+
+#[test]
+fn following_command_line_links_is_the_default() {
+    let scratch = links();
+    scratch.run(&["copy", "-r", "ld", "out"]).success();
+    assert!(is_dir(&scratch, "out"));
+    assert_eq!(link_target(&scratch, "out/inner").as_deref(), Some("../f"));
+}
+
+#[test]
+fn dereference_follows_every_link() {
+    let scratch = links();
+    scratch.run(&["copy", "-L", "lf", "out-f"]).success();
+    scratch.run(&["copy", "-r", "-L", "ld", "out-d"]).success();
+    assert_eq!(link_target(&scratch, "out-f"), None);
+    assert_eq!(scratch.read("out-f"), "file");
+    assert!(is_dir(&scratch, "out-d"));
+    assert_eq!(link_target(&scratch, "out-d/inner"), None);
+    assert_eq!(scratch.read("out-d/inner"), "file");
+    assert!(Path::new(&scratch.path("f")).exists());
+}
+
+#[test]
+fn the_last_mode_given_wins() {
+    let scratch = links();
+    scratch.run(&["copy", "-L", "-P", "lf", "out"]).success();
+    assert_eq!(link_target(&scratch, "out").as_deref(), Some("f"));
+}
+
+#[test]
+fn a_dangling_link_is_copied_as_a_link_with_no_dereference() {
+    let scratch = Scratch::new();
+    symlink("gone", scratch.path("dangling")).unwrap();
+    scratch
+        .run(&["copy", "--no-dereference", "dangling", "out"])
+        .success();
+    assert_eq!(link_target(&scratch, "out").as_deref(), Some("gone"));
+}
+
+#[test]
+fn a_dangling_link_is_not_found_when_followed() {
+    let scratch = Scratch::new();
+    symlink("gone", scratch.path("dangling")).unwrap();
+    for flags in [&[][..], &["--dereference"]] {
+        let mut args = vec!["copy"];
+        args.extend_from_slice(flags);
+        args.extend(["dangling", "out"]);
+        let stderr = scratch.run(&args).fails_with(2).stderr();
+        assert!(stderr.contains("a dangling symlink to gone"), "{stderr}");
+        assert!(stderr.contains("--no-dereference"), "{stderr}");
+        assert!(std::fs::symlink_metadata(scratch.path("out")).is_err());
+    }
+}
+
+#[test]
+fn no_dereference_does_not_overwrite_without_force() {
+    let scratch = links();
+    scratch.write("out", "keep");
+    scratch.run(&["copy", "-P", "lf", "out"]).fails_with(3);
+    assert_eq!(scratch.read("out"), "keep");
+    scratch
+        .run(&["copy", "-P", "--force", "lf", "out"])
+        .success();
+    assert_eq!(link_target(&scratch, "out").as_deref(), Some("f"));
+}
@@ -0,0 +1,72 @@
+mod common;
+
+use common::Scratch;
+
+/// A tree for the commands below to work on.
+fn tree() -> Scratch {
+    let scratch = Scratch::new();
+    scratch.write("a.txt", "a");
+    scratch.write("dir/b.txt", "b");
+    scratch.write("dir/sub/c.txt", "c");
+    scratch.write("tpl/{{name}}.txt", "hello {{name}}");
+    scratch.write("tpl/nested/d.txt", "d");
+    scratch
+}
+
+/// Run `args` under `--dry-run` and check that it succeeded, printed a
+/// plan and left the tree exactly as it was.
+#[track_caller]
+fn assert_dry_run(args: &[&str]) -> String {
+    let scratch = tree();
+    let before = scratch.snapshot();
+    let mut full = vec!["--dry-run"];
+    full.extend_from_slice(args);
+    let run = scratch.run(&full).success();
+    assert_eq!(scratch.snapshot(), before, "{args:?} changed the tree");
+    run.stdout()
+}
+
+#[test]
+fn copy_changes_nothing() {
+    let plan = assert_dry_run(&["copy", "a.txt", "new.txt"]);
+    assert!(plan.contains("copy a.txt -> new.txt"), "{plan}");
+    assert_dry_run(&["copy", "-r", "dir", "copy-of-dir"]);
+    assert_dry_run(&["copy", "--parents", "a.txt", "x/y/a.txt"]);
+}
+
+#[test]
+fn move_and_rename_change_nothing() {
+    let plan = assert_dry_run(&["move", "a.txt", "dir"]);
+    assert!(plan.contains("move a.txt -> dir/a.txt"), "{plan}");
+    assert_dry_run(&["rename", "a.txt", "z.txt"]);
+}
+
+#[test]
+fn delete_changes_nothing() {
+    assert_dry_run(&["delete", "a.txt"]);
+    assert_dry_run(&["delete", "-r", "dir"]);
+}
+
+#[test]
+fn attribute_changes_change_nothing() {
+    assert_dry_run(&["chmod", "600", "a.txt"]);
+    assert_dry_run(&["touch", "a.txt", "new.txt"]);
+    assert_dry_run(&["link", "-s", "a.txt", "l"]);
+}
+
+#[test]
+fn template_changes_nothing() {
+    let plan = assert_dry_run(&["template", "tpl", "out", "--var", "name=x"]);
+    assert!(plan.contains("mkdir out"), "{plan}");
+    assert!(
+        plan.contains("copy tpl/{{name}}.txt -> out/x.txt"),
+        "{plan}"
+    );
+    assert!(plan.contains("out/nested/d.txt"), "{plan}");
+}
+
+#[cfg(feature = "archive")]
+#[test]
+fn archive_changes_nothing() {
+    assert_dry_run(&["archive", "dir", "dir.tar"]);
+}
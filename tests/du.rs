@@ -0,0 +1,135 @@
+mod common;
+
+use common::Scratch;
+
+fn tree(scratch: &Scratch) {
+    scratch.write("d/big/a", &"x".repeat(100));
+    scratch.write("d/small/b", &"x".repeat(10));
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn watch_streams_growth_per_sample() {
+    use std::io::{BufRead, BufReader};
+
+    let scratch = Scratch::new();
+    tree(&scratch);
+    let mut child = scratch.spawn(&["du", "--watch", "--json-stream", "--interval", "200ms", "d"]);
+    let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+    let sample = |line: Option<std::io::Result<String>>| -> serde_json::Value {
+        serde_json::from_str(&line.expect("a sample").unwrap()).unwrap()
+    };
+
+    let first = sample(lines.next());
+    assert_eq!(first["operation"], "du");
+    assert_eq!(first["total"], 110);
+    assert_eq!(first["total_delta"], 0);
+
+    scratch.write("d/small/c", &"x".repeat(500));
+    // A sample already under way may still miss the new file.
+    let grown = (0..100)
+        .map(|_| sample(lines.next()))
+        .find(|next| next["total"] != 110)
+        .expect("growth within 100 samples");
+    child.kill().unwrap();
+    child.wait().unwrap();
+    assert_eq!(grown["total"], 610, "{grown}");
+    assert_eq!(grown["total_delta"], 500, "{grown}");
+    let small = grown["entries"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|entry| entry["path"].as_str().unwrap().ends_with("small"))
+        .unwrap();
+    assert_eq!(small["delta"], 500, "{grown}");
+}
+
+#[test]
+fn top_keeps_the_largest_children() {
+    let scratch = Scratch::new();
+    tree(&scratch);
+    let run = scratch.run(&["du", "--top", "1", "d"]).success();
+    let stdout = run.stdout();
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 2, "{stdout}");
+    assert!(lines[0].ends_with("big"), "{stdout}");
+    assert!(lines[1].ends_with('d'), "{stdout}");
+}
+
+#[test]
+fn watch_conflicts_with_cache_xattr() {
+    let scratch = Scratch::new();
+    tree(&scratch);
+    scratch
+        .run(&["du", "--watch", "--cache-xattr", "d"])
+        .fails_with(1);
+}
+
+#[cfg(not(feature = "json"))]
+#[test]
+fn json_stream_needs_the_json_feature() {
+    let scratch = Scratch::new();
+    tree(&scratch);
+    scratch
+        .run(&["du", "--watch", "--json-stream", "d"])
+        .fails_with(1);
+}
+
+/// Whether `path` can hold user extended attributes.
+#[cfg(all(target_os = "linux", feature = "json"))]
+fn has_user_xattrs(path: &std::path::Path) -> bool {
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = std::ffi::CString::new(path.as_os_str().as_bytes()).unwrap();
+    // SAFETY: both strings are NUL-terminated and the value is one byte.
+    unsafe {
+        libc::setxattr(
+            c_path.as_ptr(),
+            c"user.fman.probe".as_ptr(),
+            b"1".as_ptr().cast(),
+            1,
+            0,
+        ) == 0
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "json"))]
+#[test]
+fn cache_xattr_skips_unchanged_directories_until_cleared() {
+    let scratch = Scratch::new();
+    tree(&scratch);
+    if !has_user_xattrs(&scratch.path("d")) {
+        return;
+    }
+    let stats = |run: common::Run| run.success().json()["xattr_cache"].clone();
+
+    let first = stats(scratch.run(&["--json", "du", "--cache-xattr", "d"]));
+    assert_eq!(
+        (first["misses"].as_u64(), first["hits"].as_u64()),
+        (Some(2), Some(0))
+    );
+    let second = stats(scratch.run(&["--json", "du", "--cache-xattr", "d"]));
+    assert_eq!(second["hits"], 2);
+    assert_eq!(second["files_stated"], 0);
+
+    scratch.write("d/small/c", "xyz");
+    let report = scratch
+        .run(&["--json", "du", "--cache-xattr", "d"])
+        .success()
+        .json();
+    assert_eq!(report["total"], 113);
+    assert_eq!(report["xattr_cache"]["misses"], 1);
+
+    scratch.run(&["du", "--clear-cache", "d"]).success();
+    let cleared = stats(scratch.run(&["--json", "du", "--cache-xattr", "d"]));
+    assert_eq!(cleared["hits"], 0);
+}
+
+#[test]
+fn clear_cache_conflicts_with_cache_xattr() {
+    let scratch = Scratch::new();
+    tree(&scratch);
+    scratch
+        .run(&["du", "--clear-cache", "--cache-xattr", "d"])
+        .fails_with(1);
+}
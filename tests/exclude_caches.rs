@@ -0,0 +1,172 @@
+mod common;
+
+use std::fs;
+use std::path::Path;
+
+use common::Scratch;
+
+const SIGNATURE: &str = "Signature: 8a477f597d28d172789f06886806bc55";
+
+/// An untagged directory, a tagged one with a nested directory, and a
+/// decoy whose tag has the wrong signature.
+fn tree(scratch: &Scratch) {
+    scratch.write("s/top", "t");
+    scratch.write("s/plain/a", "a");
+    scratch.write("s/cache/CACHEDIR.TAG", &format!("{SIGNATURE}\n# a cache\n"));
+    scratch.write("s/cache/blob", &"b".repeat(1000));
+    scratch.write("s/cache/sub/x", "x");
+    scratch.write(
+        "s/decoy/CACHEDIR.TAG",
+        "Signature: 8a477f597d28d172789f06886806bc56\n",
+    );
+    scratch.write("s/decoy/c", "c");
+}
+
+/// Paths below `root`, directories with a trailing slash.
+fn listing(scratch: &Scratch, root: &str) -> Vec<String> {
+    fn walk(base: &Path, dir: &Path, out: &mut Vec<String>) {
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            let rel = path
+                .strip_prefix(base)
+                .unwrap()
+                .to_string_lossy()
+                .into_owned();
+            if path.is_dir() {
+                out.push(format!("{rel}/"));
+                walk(base, &path, out);
+            } else {
+                out.push(rel);
+            }
+        }
+    }
+    let base = scratch.path(root);
+    let mut out = Vec::new();
+    walk(&base, &base, &mut out);
+    out.sort();
+    out
+}
+
+const UNTAGGED: [&str; 6] = [
+    "decoy/",
+    "decoy/CACHEDIR.TAG",
+    "decoy/c",
+    "plain/",
+    "plain/a",
+    "top",
+];
+
+/// What each mode keeps, on top of [`UNTAGGED`].
+fn kept_of_cache(mode: &str) -> Vec<&'static str> {
+    match mode {
+        "all" => vec![],
+        "tag" => vec!["cache/", "cache/CACHEDIR.TAG"],
+        "contents" => vec!["cache/"],
+        _ => unreachable!(),
+    }
+}
+
+fn expected(mode: &str) -> Vec<String> {
+    let mut paths: Vec<String> = UNTAGGED
+        .iter()
+        .chain(&kept_of_cache(mode))
+        .map(ToString::to_string)
+        .collect();
+    paths.sort();
+    paths
+}
+
+#[test]
+fn copy_keeps_what_each_mode_says_of_a_tagged_directory() {
+    for mode in ["all", "tag", "contents"] {
+        let scratch = Scratch::new();
+        tree(&scratch);
+        let flag = format!("--exclude-caches={mode}");
+        scratch.run(&["copy", "-r", &flag, "s", "d"]).success();
+        assert_eq!(listing(&scratch, "d"), expected(mode), "{mode}");
+    }
+}
+
+#[test]
+fn the_bare_flag_leaves_the_whole_directory_out() {
+    let scratch = Scratch::new();
+    tree(&scratch);
+    scratch
+        .run(&["copy", "-r", "--exclude-caches", "s", "d"])
+        .success();
+    assert_eq!(listing(&scratch, "d"), expected("all"));
+}
+
+#[test]
+fn without_the_flag_caches_are_copied() {
+    let scratch = Scratch::new();
+    tree(&scratch);
+    scratch.run(&["copy", "-r", "s", "d"]).success();
+    assert_eq!(listing(&scratch, "d"), listing(&scratch, "s"));
+}
+
+#[test]
+fn a_nested_tagged_directory_is_pruned_too() {
+    let scratch = Scratch::new();
+    tree(&scratch);
+    scratch.write("s/plain/deep/CACHEDIR.TAG", SIGNATURE);
+    scratch.write("s/plain/deep/y", "y");
+    scratch
+        .run(&["copy", "-r", "--exclude-caches", "s", "d"])
+        .success();
+    assert!(scratch.exists("d/plain/a"));
+    assert!(!scratch.exists("d/plain/deep"));
+}
+
+/// Sync carries files, not directories, so a cache emptied by `contents`
+/// leaves nothing behind.
+#[test]
+fn sync_keeps_the_files_each_mode_says_of_a_tagged_directory() {
+    let files = |paths: Vec<String>| -> Vec<String> {
+        paths
+            .into_iter()
+            .filter(|path| !path.ends_with('/'))
+            .collect()
+    };
+    for mode in ["all", "tag", "contents"] {
+        let scratch = Scratch::new();
+        tree(&scratch);
+        let flag = format!("--exclude-caches={mode}");
+        scratch.run(&["sync", &flag, "s", "d"]).success();
+        assert_eq!(
+            files(listing(&scratch, "d")),
+            files(expected(mode)),
+            "{mode}"
+        );
+    }
+}
+
+#[test]
+fn an_unknown_mode_is_a_usage_error() {
+    let scratch = Scratch::new();
+    tree(&scratch);
+    scratch
+        .run(&["copy", "-r", "--exclude-caches=some", "s", "d"])
+        .fails_with(1);
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn du_counts_what_each_mode_keeps() {
+    let tag_len = (SIGNATURE.len() + "\n# a cache\n".len()) as u64;
+    for (mode, cache) in [("all", None), ("tag", Some(tag_len)), ("contents", Some(0))] {
+        let scratch = Scratch::new();
+        tree(&scratch);
+        let flag = format!("--exclude-caches={mode}");
+        let report = scratch.run(&["--json", "du", &flag, "s"]).success().json();
+        let untagged = 1 + 1 + 44 + 1;
+        assert_eq!(report["total"], untagged + cache.unwrap_or(0), "{mode}");
+        let listed = report["entries"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|entry| entry["path"].as_str().unwrap().ends_with("cache"))
+            .map(|entry| entry["bytes"].as_u64().unwrap());
+        assert_eq!(listed, cache, "{mode}");
+    }
+}
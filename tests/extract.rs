@@ -0,0 +1,161 @@
+#![cfg(feature = "archive")]
+
+mod common;
+
+use std::fs::File;
+
+use common::Scratch;
+use tar::EntryType;
+
+/// A tar at `name` holding `entries` of (name, kind, contents or link
+/// target), with names written as given so that hostile ones can be tried.
+fn hostile_tar(scratch: &Scratch, name: &str, entries: &[(&str, EntryType, &str)]) {
+    let mut builder = tar::Builder::new(File::create(scratch.path(name)).unwrap());
+    for &(entry, kind, contents) in entries {
+        let mut header = tar::Header::new_gnu();
+        header.as_gnu_mut().unwrap().name[..entry.len()].copy_from_slice(entry.as_bytes());
+        header.set_entry_type(kind);
+        header.set_mode(0o644);
+        let contents = if kind == EntryType::Regular {
+            contents.as_bytes()
+        } else {
+            header.set_link_name(contents).unwrap();
+            &[]
+        };
+        header.set_size(contents.len() as u64);
+        header.set_cksum();
+        builder.append(&header, contents).unwrap();
+    }
+    builder.finish().unwrap();
+}
+
+#[test]
+fn archive_and_extract_round_trip() {
+    let scratch = Scratch::new();
+    scratch.write("s/a", "1");
+    scratch.write("s/sub/b", "2");
+    for name in ["t.tar", "t.tar.gz", "t.zip"] {
+        scratch.run(&["archive", "s", name]).success();
+        let out = format!("out-{name}");
+        scratch.run(&["extract", name, &out]).success();
+        assert_eq!(scratch.read(&format!("{out}/s/a")), "1");
+        assert_eq!(scratch.read(&format!("{out}/s/sub/b")), "2");
+    }
+}
+
+#[test]
+fn an_entry_outside_the_destination_is_refused() {
+    let scratch = Scratch::new();
+    hostile_tar(
+        &scratch,
+        "t.tar",
+        &[
+            ("ok", EntryType::Regular, "fine"),
+            ("../evil", EntryType::Regular, "evil"),
+        ],
+    );
+    let run = scratch.run(&["extract", "t.tar", "out/in"]).fails_with(4);
+    assert!(
+        run.stderr().contains("outside the destination"),
+        "{}",
+        run.stderr()
+    );
+    assert!(!scratch.exists("out"));
+    assert!(!scratch.exists("evil"));
+}
+
+#[test]
+fn an_existing_file_needs_force() {
+    let scratch = Scratch::new();
+    scratch.write("s", "new");
+    scratch.run(&["archive", "s", "t.tar"]).success();
+    scratch.write("out/s", "old");
+    scratch.run(&["extract", "t.tar", "out"]).fails_with(3);
+    assert_eq!(scratch.read("out/s"), "old");
+    scratch.run(&["extract", "-f", "t.tar", "out"]).success();
+    assert_eq!(scratch.read("out/s"), "new");
+}
+
+#[test]
+fn an_existing_archive_needs_force() {
+    let scratch = Scratch::new();
+    scratch.write("s", "1");
+    scratch.write("t.zip", "keep");
+    scratch.run(&["archive", "s", "t.zip"]).fails_with(3);
+    assert_eq!(scratch.read("t.zip"), "keep");
+    scratch.run(&["archive", "-f", "s", "t.zip"]).success();
+}
+
+#[test]
+fn a_symlink_escape_is_refused() {
+    let scratch = Scratch::new();
+    hostile_tar(
+        &scratch,
+        "t.tar",
+        &[
+            ("up", EntryType::Symlink, ".."),
+            ("up/evil", EntryType::Regular, "evil"),
+        ],
+    );
+    scratch.run(&["extract", "t.tar", "out"]).fails_with(4);
+    assert!(!scratch.exists("out"));
+    assert!(!scratch.exists("evil"));
+
+    hostile_tar(
+        &scratch,
+        "t.tar",
+        &[("passwd", EntryType::Link, "../../etc/passwd")],
+    );
+    scratch.run(&["extract", "t.tar", "out"]).fails_with(4);
+    assert!(!scratch.exists("out"));
+}
+
+#[test]
+fn a_zip_escape_is_refused() {
+    let scratch = Scratch::new();
+    let mut zip = zip::ZipWriter::new(File::create(scratch.path("t.zip")).unwrap());
+    zip.start_file("../../evil", zip::write::SimpleFileOptions::default())
+        .unwrap();
+    std::io::Write::write_all(&mut zip, b"evil").unwrap();
+    zip.finish().unwrap();
+    scratch.run(&["extract", "t.zip", "out"]).fails_with(4);
+    assert!(!scratch.exists("out"));
+}
+
+#[test]
+fn the_format_is_detected_from_the_contents() {
+    let scratch = Scratch::new();
+    scratch.write("s/a", "1");
+    scratch.run(&["archive", "s", "t.tar.gz"]).success();
+    std::fs::rename(scratch.path("t.tar.gz"), scratch.path("download")).unwrap();
+    scratch.run(&["extract", "download", "out"]).success();
+    assert_eq!(scratch.read("out/s/a"), "1");
+}
+
+#[test]
+fn the_destination_defaults_to_the_current_directory() {
+    let scratch = Scratch::new();
+    scratch.write("s/a", "1");
+    scratch.run(&["archive", "s", "t.zip"]).success();
+    std::fs::remove_dir_all(scratch.path("s")).unwrap();
+    scratch.run(&["extract", "t.zip"]).success();
+    assert_eq!(scratch.read("s/a"), "1");
+}
+
+#[test]
+fn a_missing_destination_is_created() {
+    let scratch = Scratch::new();
+    scratch.write("s/a", "1");
+    scratch.run(&["archive", "s", "t.tar"]).success();
+    scratch.run(&["extract", "t.tar", "x/y/z"]).success();
+    assert_eq!(scratch.read("x/y/z/s/a"), "1");
+}
+
+#[test]
+fn a_missing_archive_is_not_found() {
+    let scratch = Scratch::new();
+    scratch
+        .run(&["extract", "missing.tar", "out"])
+        .fails_with(2);
+    assert!(!scratch.exists("out"));
+}
@@ -0,0 +1,194 @@
+mod common;
+
+use common::Scratch;
+
+/// `r/a`, `r/sub/b` and `r/sub/deeper/c`, of 1, 2 and 3 bytes.
+fn nested(scratch: &Scratch) {
+    scratch.write("r/a", "1");
+    scratch.write("r/sub/b", "22");
+    scratch.write("r/sub/deeper/c", "333");
+}
+
+fn lines(stdout: &str) -> Vec<&str> {
+    stdout.lines().collect()
+}
+
+#[cfg(unix)]
+#[test]
+fn paths_are_printed_as_given_relative_or_absolute() {
+    let scratch = Scratch::new();
+    nested(&scratch);
+    let as_given = scratch.run(&["find", "r"]).success().stdout();
+    assert_eq!(
+        lines(&as_given),
+        ["r/a", "r/sub", "r/sub/b", "r/sub/deeper", "r/sub/deeper/c"]
+    );
+    let relative = scratch.run(&["find", "--relative", "r"]).success().stdout();
+    assert_eq!(
+        lines(&relative),
+        ["a", "sub", "sub/b", "sub/deeper", "sub/deeper/c"]
+    );
+    let absolute = scratch.run(&["find", "--absolute", "r"]).success().stdout();
+    let root = scratch.root().canonicalize().unwrap().join("r");
+    let expected: Vec<String> = ["a", "sub", "sub/b", "sub/deeper", "sub/deeper/c"]
+        .iter()
+        .map(|rel| root.join(rel).display().to_string())
+        .collect();
+    assert_eq!(lines(&absolute), expected);
+    scratch
+        .run(&["find", "--relative", "--absolute", "r"])
+        .fails_with(1);
+}
+
+#[test]
+fn the_root_is_included_only_when_asked() {
+    let scratch = Scratch::new();
+    nested(&scratch);
+    let stdout = scratch
+        .run(&[
+            "find",
+            "--relative",
+            "--include-root",
+            "--max-depth",
+            "1",
+            "r",
+        ])
+        .success()
+        .stdout();
+    assert_eq!(lines(&stdout), [".", "a", "sub"]);
+}
+
+#[cfg(unix)]
+#[test]
+fn a_format_is_terminated_by_nul_with_print0() {
+    let scratch = Scratch::new();
+    nested(&scratch);
+    let stdout = scratch
+        .run(&[
+            "find",
+            "--type",
+            "f",
+            "--format",
+            "{depth}:{size}:{relpath}",
+            "--print0",
+            "r",
+        ])
+        .success()
+        .stdout();
+    assert_eq!(stdout, "1:1:a\x002:2:sub/b\x003:3:sub/deeper/c\x00");
+}
+
+#[test]
+fn an_unknown_format_field_is_rejected() {
+    let scratch = Scratch::new();
+    nested(&scratch);
+    scratch
+        .run(&["find", "--format", "{owner}", "r"])
+        .fails_with(4);
+}
+
+#[test]
+fn name_and_type_filter_the_results() {
+    let scratch = Scratch::new();
+    nested(&scratch);
+    scratch.write("r/sub/x.log", "");
+    let stdout = scratch
+        .run(&["find", "--relative", "--name", "*.log", "r"])
+        .success()
+        .stdout();
+    assert_eq!(lines(&stdout).len(), 1);
+    assert!(stdout.ends_with("x.log\n"), "{stdout}");
+    let dirs = scratch
+        .run(&["find", "--relative", "--type", "d", "r"])
+        .success()
+        .stdout();
+    assert_eq!(lines(&dirs).len(), 2);
+}
+
+#[test]
+fn max_depth_limits_the_descent() {
+    let scratch = Scratch::new();
+    nested(&scratch);
+    let depth = |n: &str| {
+        let stdout = scratch
+            .run(&["find", "--relative", "--max-depth", n, "r"])
+            .success()
+            .stdout();
+        stdout.lines().map(str::to_string).collect::<Vec<_>>()
+    };
+    assert_eq!(depth("0"), Vec::<String>::new());
+    assert_eq!(depth("1"), ["a", "sub"]);
+    assert_eq!(
+        depth("2"),
+        [
+            "a",
+            "sub",
+            &format!("sub{}b", std::path::MAIN_SEPARATOR),
+            &format!("sub{}deeper", std::path::MAIN_SEPARATOR)
+        ]
+    );
+}
+
+#[test]
+fn output_is_sorted_within_each_directory() {
+    let scratch = Scratch::new();
+    for name in ["r/zeta", "r/alpha", "r/mid/b", "r/mid/a", "r/beta"] {
+        scratch.write(name, "");
+    }
+    let stdout = scratch
+        .run(&["find", "--relative", "--type", "f", "r"])
+        .success()
+        .stdout();
+    let sep = std::path::MAIN_SEPARATOR;
+    assert_eq!(
+        lines(&stdout),
+        [
+            "alpha",
+            "beta",
+            &format!("mid{sep}a"),
+            &format!("mid{sep}b"),
+            "zeta"
+        ]
+    );
+}
+
+#[test]
+fn find_of_a_file_or_a_missing_root_fails() {
+    let scratch = Scratch::new();
+    nested(&scratch);
+    scratch.run(&["find", "r/a"]).fails_with(4);
+    scratch.run(&["find", "missing"]).fails_with(2);
+}
+
+#[cfg(unix)]
+#[test]
+fn an_unreadable_directory_is_reported_and_skipped() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let scratch = Scratch::new();
+    nested(&scratch);
+    scratch.write("r/locked/hidden", "");
+    scratch.write("r/z", "");
+    let locked = scratch.path("r/locked");
+    std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o000)).unwrap();
+    let run = scratch.run_unprivileged(&["find", "--relative", "--type", "f", "r"]);
+    std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let run = run.success();
+    let stdout = run.stdout();
+    assert_eq!(lines(&stdout), ["a", "sub/b", "sub/deeper/c", "z"]);
+    let stderr = run.stderr();
+    assert!(stderr.contains("locked"), "{stderr}");
+}
+
+#[cfg(unix)]
+#[test]
+fn type_l_selects_symlinks() {
+    let scratch = Scratch::new();
+    nested(&scratch);
+    std::os::unix::fs::symlink("a", scratch.path("r/link")).unwrap();
+    let stdout = scratch
+        .run(&["find", "--relative", "--type", "l", "r"])
+        .success()
+        .stdout();
+    assert_eq!(lines(&stdout), ["link"]);
+}
@@ -0,0 +1,70 @@
+mod common;
+
+use common::Scratch;
+
+#[test]
+fn fs_info_lists_every_field() {
+    let scratch = Scratch::new();
+    let stdout = scratch.run(&["fs-info"]).success().stdout();
+    let labels: Vec<&str> = stdout
+        .lines()
+        .map(|line| line.split(':').next().unwrap())
+        .collect();
+    assert_eq!(
+        labels,
+        [
+            "path",
+            "mount point",
+            "filesystem",
+            "total",
+            "used",
+            "available",
+            "read-only",
+            "case-sensitive",
+            "mtime resolution",
+            "reflinks",
+            "sparse files",
+            "xattrs",
+            "symlinks",
+            "ownership",
+        ]
+    );
+    assert!(stdout.contains("read-only:        no"), "{stdout}");
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn fs_info_json_has_the_whole_report() {
+    let scratch = Scratch::new();
+    scratch.write("f", "x");
+    let report = scratch.run(&["--json", "fs-info", "f"]).success().json();
+    assert_eq!(report["operation"], "fs-info");
+    let path = scratch.path("f").canonicalize().unwrap();
+    assert_eq!(report["path"], path.to_str().unwrap());
+    for field in [
+        "mount_point",
+        "fs_type",
+        "source",
+        "total_bytes",
+        "used_bytes",
+        "available_bytes",
+        "read_only",
+        "case_sensitive",
+        "timestamp_granularity_ns",
+        "reflink",
+        "sparse_files",
+        "xattrs",
+        "symlinks",
+        "ownership",
+    ] {
+        assert!(report.get(field).is_some(), "missing {field}: {report}");
+    }
+    assert!(report["total_bytes"].as_u64().unwrap() > 0);
+    assert_eq!(report["read_only"], false);
+}
+
+#[test]
+fn fs_info_of_a_missing_path_fails() {
+    let scratch = Scratch::new();
+    scratch.run(&["fs-info", "missing"]).fails_with(2);
+}
@@ -0,0 +1,247 @@
+mod common;
+
+use common::Scratch;
+
+#[test]
+fn compare_reports_both_digests_of_a_mismatch() {
+    let scratch = Scratch::new();
+    scratch.write("f1", "a\n");
+    scratch.write("f2", "a\n");
+    scratch.write("f3", "b\n");
+    let run = scratch
+        .run(&["hash", "--compare", "f1", "f2", "f3"])
+        .fails_with(1);
+    let stderr = run.stderr();
+    assert!(
+        stderr.contains(
+            "f3 (0263829989b6fd954f72baaf2fc64bc2e2f01d692d4de72986ea808f6e99813f) differs from \
+             f1 (87428fc522803d31065e7bce3cf03fe475096631e5e07bbd7a0fde60c4cf25c7)"
+        ),
+        "{stderr}"
+    );
+    assert!(!stderr.contains("f2 ("), "{stderr}");
+}
+
+#[test]
+fn compare_succeeds_when_all_match() {
+    let scratch = Scratch::new();
+    scratch.write("f1", "same");
+    scratch.write("f2", "same");
+    scratch.run(&["hash", "--compare", "f1", "f2"]).success();
+}
+
+#[test]
+fn compare_prints_each_digest() {
+    let scratch = Scratch::new();
+    scratch.write("f1", "same");
+    scratch.write("f2", "same");
+    let stdout = scratch
+        .run(&["hash", "--compare", "f1", "f2"])
+        .success()
+        .stdout();
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 2, "{lines:?}");
+    assert!(lines[0].ends_with("  f1") && lines[1].ends_with("  f2"));
+    assert_eq!(lines[0][..64], lines[1][..64]);
+}
+
+#[test]
+fn fast_compare_checks_bytes() {
+    let scratch = Scratch::new();
+    scratch.write("f1", "same");
+    scratch.write("f2", "same");
+    scratch.write("f3", "sane");
+    let stdout = scratch
+        .run(&["hash", "--compare", "--fast", "f1", "f2"])
+        .success()
+        .stdout();
+    assert_eq!(stdout, "");
+    scratch
+        .run(&["hash", "--compare", "--fast", "f1", "f3"])
+        .fails_with(1);
+    scratch
+        .run(&["hash", "--compare", "--fast", "f1", "f2", "f3"])
+        .fails_with(4);
+}
+
+#[test]
+fn compare_needs_recursive_for_directories() {
+    let scratch = Scratch::new();
+    for root in ["x", "y"] {
+        scratch.write(&format!("{root}/a"), "1");
+        scratch.write(&format!("{root}/sub/b"), "2");
+    }
+    scratch.run(&["hash", "--compare", "x", "y"]).fails_with(4);
+    scratch
+        .run(&["hash", "--compare", "--recursive", "x", "y"])
+        .success();
+    scratch.write("y/sub/b", "3");
+    scratch
+        .run(&["hash", "--compare", "--recursive", "x", "y"])
+        .fails_with(1);
+}
+
+const HELLO: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+/// A valid pair, a corrupted file, an orphaned sidecar and a file with no
+/// sidecar.
+fn sidecars(scratch: &Scratch) {
+    scratch.write("t/good", "hello");
+    scratch.write("t/good.sha256", &format!("{HELLO}  good\n"));
+    scratch.write("t/bad", "tampered");
+    scratch.write("t/bad.sha256", &format!("{HELLO}\n"));
+    scratch.write("t/orphan.sha256", &format!("{HELLO}\n"));
+    scratch.write("t/loose", "loose");
+}
+
+#[test]
+fn verify_sidecars_classifies_each_file_and_fails_on_a_mismatch() {
+    let scratch = Scratch::new();
+    sidecars(&scratch);
+    let run = scratch.run(&["hash", "--verify-sidecars", "t"]);
+    assert_ne!(run.code(), 0);
+    let stdout = run.stdout();
+    let lines: Vec<&str> = stdout.lines().collect();
+    let bad = std::path::Path::new("t").join("bad");
+    let good = std::path::Path::new("t").join("good");
+    let orphan = std::path::Path::new("t").join("orphan.sha256");
+    assert_eq!(
+        lines,
+        [
+            format!("{}: FAILED", bad.display()),
+            format!("{}: OK", good.display()),
+            format!("{}: ORPHANED", orphan.display()),
+        ]
+    );
+    assert!(
+        run.stderr().contains("1 ok, 1 failed, 1 orphaned"),
+        "{}",
+        run.stderr()
+    );
+}
+
+#[test]
+fn verify_sidecars_passes_without_failures() {
+    let scratch = Scratch::new();
+    sidecars(&scratch);
+    std::fs::remove_file(scratch.path("t/bad")).unwrap();
+    std::fs::remove_file(scratch.path("t/bad.sha256")).unwrap();
+    // An orphan alone is reported, not fatal.
+    scratch.run(&["hash", "--verify-sidecars", "t"]).success();
+}
+
+#[cfg(unix)]
+#[test]
+fn verify_sidecars_fails_an_unreadable_directory_and_checks_the_rest() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let scratch = Scratch::new();
+    sidecars(&scratch);
+    scratch.write("t/locked/hidden", "hello");
+    let locked = scratch.path("t/locked");
+    std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o000)).unwrap();
+    let run = scratch.run_unprivileged(&["hash", "--verify-sidecars", "t"]);
+    std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o755)).unwrap();
+    assert_eq!(run.code(), 10);
+    let stdout = run.stdout();
+    assert!(stdout.contains("good: OK"), "{stdout}");
+    assert!(
+        stdout.contains("t: FAILED") && stdout.contains("locked"),
+        "{stdout}"
+    );
+    assert!(
+        run.stderr().contains("1 ok, 2 failed, 1 orphaned"),
+        "{}",
+        run.stderr()
+    );
+}
+
+#[test]
+fn require_coverage_fails_on_an_uncovered_file() {
+    let scratch = Scratch::new();
+    sidecars(&scratch);
+    std::fs::remove_file(scratch.path("t/bad")).unwrap();
+    std::fs::remove_file(scratch.path("t/bad.sha256")).unwrap();
+    let run = scratch.run(&["hash", "--verify-sidecars", "t", "--require-coverage"]);
+    assert_ne!(run.code(), 0);
+    assert!(
+        run.stdout().contains("loose: UNCOVERED"),
+        "{}",
+        run.stdout()
+    );
+    assert!(
+        run.stderr().contains("1 files have no sidecar"),
+        "{}",
+        run.stderr()
+    );
+}
+
+#[test]
+fn require_coverage_needs_verify_sidecars() {
+    let scratch = Scratch::new();
+    scratch.write("f", "");
+    scratch
+        .run(&["hash", "--require-coverage", "f"])
+        .fails_with(1);
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn verify_sidecars_json_counts_each_status() {
+    let scratch = Scratch::new();
+    sidecars(&scratch);
+    let run = scratch.run(&[
+        "--json",
+        "hash",
+        "--verify-sidecars",
+        "t",
+        "--require-coverage",
+    ]);
+    assert_ne!(run.code(), 0);
+    // The report, then the error.
+    let stdout = run.stdout();
+    let report: serde_json::Value = serde_json::from_str(stdout.lines().next().unwrap()).unwrap();
+    assert_eq!(report["counts"]["ok"], 1);
+    assert_eq!(report["counts"]["failed"], 1);
+    assert_eq!(report["counts"]["orphaned"], 1);
+    assert_eq!(report["counts"]["uncovered"], 1);
+}
+
+#[test]
+fn hash_prints_a_line_per_file_like_sha256sum() {
+    let scratch = Scratch::new();
+    scratch.write("a", "hello");
+    scratch.write("b c", "");
+    let stdout = scratch.run(&["hash", "a", "b c"]).success().stdout();
+    assert_eq!(
+        stdout,
+        "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824  a\n\
+         e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855  b c\n"
+    );
+}
+
+#[test]
+fn hash_algo_selects_the_digest() {
+    let scratch = Scratch::new();
+    scratch.write("a", "hello");
+    let md5 = scratch
+        .run(&["hash", "--algo", "md5", "a"])
+        .success()
+        .stdout();
+    assert_eq!(md5, "5d41402abc4b2a76b9719d911017c592  a\n");
+    let sha1 = scratch
+        .run(&["hash", "--algo", "sha1", "a"])
+        .success()
+        .stdout();
+    assert_eq!(sha1, "aaf4c61ddcc5e8a2dabede0f3b482cd9aea9434d  a\n");
+    scratch.run(&["hash", "--algo", "crc32", "a"]).fails_with(1);
+}
+
+#[test]
+fn hash_rejects_a_directory_and_a_missing_file() {
+    let scratch = Scratch::new();
+    std::fs::create_dir(scratch.path("d")).unwrap();
+    let stderr = scratch.run(&["hash", "d"]).fails_with(4).stderr();
+    assert!(stderr.contains("--recursive"), "{stderr}");
+    scratch.run(&["hash", "missing"]).fails_with(2);
+}
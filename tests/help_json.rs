@@ -0,0 +1,105 @@
+mod common;
+
+use serde_json::Value;
+
+use common::Scratch;
+
+fn describe() -> Value {
+    Scratch::new().run(&["--help-json"]).success().json()
+}
+
+fn subcommand<'a>(root: &'a Value, name: &str) -> &'a Value {
+    root["subcommands"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|sub| sub["name"] == name)
+        .unwrap_or_else(|| panic!("no subcommand {name}"))
+}
+
+fn arg<'a>(command: &'a Value, long: &str) -> &'a Value {
+    command["args"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|arg| arg["long"] == long)
+        .unwrap_or_else(|| panic!("{} has no --{long}", command["name"]))
+}
+
+#[test]
+fn copy_lists_force_and_its_conflicts() {
+    let root = describe();
+    assert_eq!(root["format_version"], 1);
+    let copy = subcommand(&root, "copy");
+    let force = arg(copy, "force");
+    assert_eq!(force["short"], "f");
+    assert_eq!(force["takes_value"], false);
+    let conflicts = force["conflicts_with"].as_array().unwrap();
+    assert!(
+        conflicts.contains(&Value::from("no_clobber")),
+        "{conflicts:?}"
+    );
+    let no_clobber = arg(copy, "no-clobber");
+    assert!(
+        no_clobber["conflicts_with"]
+            .as_array()
+            .unwrap()
+            .contains(&Value::from("force"))
+    );
+}
+
+#[test]
+fn every_listed_subcommand_is_described() {
+    let root = describe();
+    let help = Scratch::new().run(&["--help"]).success().stdout();
+    let listed: Vec<&str> = help
+        .lines()
+        .skip_while(|line| !line.starts_with("Commands:"))
+        .skip(1)
+        .take_while(|line| line.starts_with("  "))
+        .filter_map(|line| line.split_whitespace().next())
+        .collect();
+    assert!(
+        listed.contains(&"copy") && listed.contains(&"apply"),
+        "{listed:?}"
+    );
+    for name in listed {
+        subcommand(&root, name);
+    }
+}
+
+#[test]
+fn values_defaults_and_repetition_are_described() {
+    let root = describe();
+    let copy = subcommand(&root, "copy");
+    let on_conflict = arg(copy, "on-conflict");
+    assert_eq!(on_conflict["value_names"], serde_json::json!(["POLICY"]));
+    assert_eq!(
+        on_conflict["possible_values"],
+        serde_json::json!(["error", "overwrite", "skip", "rename"])
+    );
+    assert_eq!(
+        arg(copy, "retry-delay")["defaults"],
+        serde_json::json!(["1s"])
+    );
+    let rewrite = arg(subcommand(&root, "ln"), "rewrite");
+    assert_eq!(rewrite["repeatable"], true);
+    assert_eq!(rewrite["required"], true);
+}
+
+#[test]
+fn shared_groups_and_global_flags_appear_under_each_subcommand() {
+    let root = describe();
+    let delete = subcommand(&root, "delete");
+    assert_eq!(arg(delete, "yes")["short"], "y");
+    arg(delete, "max-matches-without-confirm");
+    for name in ["copy", "delete", "sync"] {
+        assert_eq!(arg(subcommand(&root, name), "dry-run")["global"], true);
+    }
+}
+
+#[test]
+fn help_json_is_hidden_from_help() {
+    let help = Scratch::new().run(&["--help"]).success().stdout();
+    assert!(!help.contains("--help-json"));
+}
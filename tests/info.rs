@@ -0,0 +1,52 @@
+mod common;
+
+use common::Scratch;
+
+#[test]
+fn info_prints_a_summary_of_a_file() {
+    let scratch = Scratch::new();
+    scratch.write("f.txt", "hello");
+    let stdout = scratch.run(&["info", "f.txt"]).success().stdout();
+    assert!(stdout.contains("path:        f.txt\n"), "{stdout}");
+    assert!(stdout.contains("type:        file\n"), "{stdout}");
+    assert!(stdout.contains("size:        5 ("), "{stdout}");
+    assert!(stdout.contains("modified:    "), "{stdout}");
+    assert!(stdout.contains("read-only:   no\n"), "{stdout}");
+    if cfg!(unix) {
+        assert!(stdout.contains("permissions: 0"), "{stdout}");
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn info_shows_a_symlink_and_its_target() {
+    let scratch = Scratch::new();
+    scratch.write("f.txt", "hello");
+    std::os::unix::fs::symlink("f.txt", scratch.path("link")).unwrap();
+    let stdout = scratch.run(&["info", "link"]).success().stdout();
+    assert!(
+        stdout.contains("type:        symlink -> f.txt\n"),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn info_of_a_missing_path_is_not_found() {
+    let scratch = Scratch::new();
+    let stderr = scratch.run(&["info", "gone"]).fails_with(2).stderr();
+    assert!(stderr.contains("gone"), "{stderr}");
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn info_json_has_every_field() {
+    let scratch = Scratch::new();
+    std::fs::create_dir(scratch.path("d")).unwrap();
+    let report = scratch.run(&["--json", "info", "d"]).success().json();
+    assert_eq!(report["kind"], "dir");
+    assert_eq!(report["read_only"], false);
+    assert_eq!(report["link_target"], serde_json::Value::Null);
+    for field in ["path", "size", "mode", "modified"] {
+        assert!(!report[field].is_null(), "{field}: {report}");
+    }
+}
@@ -0,0 +1,98 @@
+mod common;
+
+use common::Scratch;
+
+/// `count` files `f0`.. with their names as contents, and an empty `d`.
+fn sources(scratch: &Scratch, count: usize) -> Vec<String> {
+    std::fs::create_dir(scratch.path("d")).unwrap();
+    (0..count)
+        .map(|i| {
+            let name = format!("f{i}");
+            scratch.write(&name, &name);
+            name
+        })
+        .collect()
+}
+
+fn copy_args<'a>(jobs: &'a str, names: &'a [String], extra: &[&'a str]) -> Vec<&'a str> {
+    let mut args = vec!["copy", "--jobs", jobs];
+    args.extend(extra);
+    args.extend(names.iter().map(String::as_str));
+    args.push("d");
+    args
+}
+
+#[test]
+fn every_source_is_copied() {
+    let scratch = Scratch::new();
+    let names = sources(&scratch, 40);
+    scratch.run(&copy_args("8", &names, &[])).success();
+    for name in &names {
+        assert_eq!(scratch.read(&format!("d/{name}")), *name);
+    }
+}
+
+#[test]
+fn every_failure_is_reported_and_the_rest_copied() {
+    let scratch = Scratch::new();
+    let mut names = sources(&scratch, 4);
+    names.insert(1, "missing1".to_string());
+    names.push("missing2".to_string());
+    let run = scratch.run(&copy_args("3", &names, &[])).fails_with(2);
+    assert!(run.stderr().contains("2 failures"), "{}", run.stderr());
+    assert!(run.stderr().contains("missing1"), "{}", run.stderr());
+    assert!(run.stderr().contains("missing2"), "{}", run.stderr());
+    for i in 0..4 {
+        assert!(scratch.exists(&format!("d/f{i}")));
+    }
+}
+
+#[test]
+fn one_job_stops_at_the_first_failure() {
+    let scratch = Scratch::new();
+    let mut names = sources(&scratch, 3);
+    names.insert(1, "missing".to_string());
+    let run = scratch.run(&copy_args("1", &names, &[])).fails_with(2);
+    assert!(
+        run.stderr().contains("1 of 4 sources copied"),
+        "{}",
+        run.stderr()
+    );
+    assert!(scratch.exists("d/f0"));
+    assert!(!scratch.exists("d/f1"));
+}
+
+#[test]
+fn two_sources_with_one_destination_are_rejected_up_front() {
+    let scratch = Scratch::new();
+    let mut names = sources(&scratch, 3);
+    scratch.write("x/f0", "other");
+    names.push("x/f0".to_string());
+    let run = scratch.run(&copy_args("4", &names, &[])).fails_with(4);
+    assert!(run.stderr().contains("same path"), "{}", run.stderr());
+    assert_eq!(std::fs::read_dir(scratch.path("d")).unwrap().count(), 0);
+}
+
+#[test]
+fn verbose_lines_do_not_interleave() {
+    let scratch = Scratch::new();
+    let names = sources(&scratch, 40);
+    let run = scratch.run(&copy_args("8", &names, &["-v"])).success();
+    let mut lines: Vec<String> = run.stdout().lines().map(str::to_string).collect();
+    lines.sort();
+    let mut expected: Vec<String> = names
+        .iter()
+        .map(|name| format!("{name} -> d/{name}"))
+        .collect();
+    expected.sort();
+    assert_eq!(lines, expected);
+}
+
+#[test]
+fn zero_jobs_and_jobs_with_interactive_are_usage_errors() {
+    let scratch = Scratch::new();
+    let names = sources(&scratch, 2);
+    scratch.run(&copy_args("0", &names, &[])).fails_with(1);
+    scratch.run(&copy_args("2", &names, &["-i"])).fails_with(1);
+    assert_eq!(std::fs::read_dir(scratch.path("d")).unwrap().count(), 0);
+}
@@ -0,0 +1,99 @@
+#![cfg(feature = "json")]
+
+mod common;
+
+use common::Scratch;
+
+#[test]
+fn every_result_names_its_operation() {
+    let scratch = Scratch::new();
+    scratch.write("dir/a.txt", "a");
+    for (args, operation) in [
+        (&["--json", "info", "dir/a.txt"][..], "info"),
+        (&["--json", "du", "dir"], "du"),
+        (&["--json", "fs-info", "dir"], "fs-info"),
+        (&["--json", "copy", "dir/a.txt", "b.txt"], "copy"),
+        (&["--json", "touch", "c.txt"], "touch"),
+        (&["--json", "hash", "b.txt"], "hash"),
+        (&["--json", "move", "b.txt", "m.txt"], "move"),
+        (&["--json", "rename", "m.txt", "r.txt"], "rename"),
+        (&["--json", "chmod", "644", "r.txt"], "chmod"),
+        (&["--json", "link", "r.txt", "l.txt"], "link"),
+        (&["--json", "ls", "dir"], "ls"),
+        (&["--json", "find", "dir"], "find"),
+        (&["--json", "compare", "r.txt", "l.txt"], "compare"),
+        (&["--json", "delete", "l.txt"], "delete"),
+        (&["--json", "sync", "dir", "out"], "sync"),
+    ] {
+        let run = scratch.run(args).success();
+        assert_eq!(run.json()["operation"], operation, "{args:?}");
+    }
+}
+
+#[test]
+fn copy_reports_source_destination_bytes_and_status() {
+    let scratch = Scratch::new();
+    scratch.write("a.txt", "abc");
+    std::fs::create_dir(scratch.path("d")).unwrap();
+    let json = scratch
+        .run(&["--json", "copy", "a.txt", "d"])
+        .success()
+        .json();
+    assert_eq!(json["source"], "a.txt");
+    assert_eq!(json["destination"], "d/a.txt");
+    assert_eq!(json["bytes"], 3);
+    assert_eq!(json["status"], "copied");
+}
+
+#[test]
+fn an_error_is_only_printed_as_json() {
+    let scratch = Scratch::new();
+    let run = scratch
+        .run(&["--json", "copy", "missing", "b"])
+        .fails_with(2);
+    assert_eq!(run.stderr(), "");
+    let json = run.json();
+    assert_eq!(json["status"], "error");
+    assert_eq!(json["kind"], "not-found");
+    assert!(json["message"].as_str().unwrap().contains("missing"));
+}
+
+#[test]
+fn human_output_is_the_default() {
+    let scratch = Scratch::new();
+    let run = scratch.run(&["copy", "missing", "b"]).fails_with(2);
+    assert_eq!(run.stdout(), "");
+    assert!(
+        run.stderr().starts_with("error: not found: missing"),
+        "{}",
+        run.stderr()
+    );
+}
+
+#[test]
+fn a_refused_copy_names_its_kind() {
+    let scratch = Scratch::new();
+    scratch.write("a.txt", "a");
+    scratch.write("b.txt", "b");
+    let json = scratch
+        .run(&["--json", "copy", "a.txt", "b.txt"])
+        .fails_with(3)
+        .json();
+    assert_eq!(json["status"], "error");
+    assert_eq!(json["kind"], "already-exists");
+    assert_eq!(scratch.read("b.txt"), "b");
+}
+
+#[test]
+fn a_skipped_copy_reports_its_status() {
+    let scratch = Scratch::new();
+    scratch.write("a.txt", "a");
+    scratch.write("b.txt", "b");
+    let json = scratch
+        .run(&["--json", "copy", "--no-clobber", "a.txt", "b.txt"])
+        .success()
+        .json();
+    assert_eq!(json["operation"], "copy");
+    assert_eq!(json["status"], "skipped");
+    assert_eq!(scratch.read("b.txt"), "b");
+}
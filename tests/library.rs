@@ -0,0 +1,155 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use fman::ops::HashAlgorithm;
+use fman::{
+    FmanError, Touched, copy_file_force, copy_file_safe, delete_file, file_info, hash_file,
+    rename_file, touch,
+};
+use tempfile::TempDir;
+
+#[test]
+fn copy_file_safe_returns_the_path_written() {
+    let dir = TempDir::new().unwrap();
+    let src = dir.path().join("a.txt");
+    fs::write(&src, "a").unwrap();
+    fs::create_dir(dir.path().join("into")).unwrap();
+
+    let written = copy_file_safe(&src, dir.path().join("b.txt")).unwrap();
+    assert_eq!(written, dir.path().join("b.txt"));
+    let into = copy_file_safe(&src, dir.path().join("into")).unwrap();
+    assert_eq!(into, dir.path().join("into/a.txt"));
+    assert_eq!(fs::read_to_string(into).unwrap(), "a");
+}
+
+#[test]
+fn copy_file_safe_refuses_to_overwrite() {
+    let dir = TempDir::new().unwrap();
+    let (src, dst) = (dir.path().join("a"), dir.path().join("b"));
+    fs::write(&src, "new").unwrap();
+    fs::write(&dst, "old").unwrap();
+    let err = copy_file_safe(&src, &dst).unwrap_err();
+    assert!(matches!(err, FmanError::AlreadyExists(_)), "{err}");
+    assert_eq!(fs::read_to_string(&dst).unwrap(), "old");
+}
+
+#[test]
+fn copy_file_force_overwrites_and_returns_the_path() {
+    let dir = TempDir::new().unwrap();
+    let (src, dst) = (dir.path().join("a"), dir.path().join("b"));
+    fs::write(&src, "new").unwrap();
+    fs::write(&dst, "old").unwrap();
+    assert_eq!(copy_file_force(&src, &dst).unwrap(), dst);
+    assert_eq!(fs::read_to_string(&dst).unwrap(), "new");
+}
+
+#[test]
+fn delete_file_is_idempotent_when_forced() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("a");
+    fs::write(&path, "").unwrap();
+    delete_file(&path, false).unwrap();
+    assert!(!path.exists());
+    delete_file(&path, true).unwrap();
+    assert!(delete_file(&path, false).unwrap_err().is_not_found());
+}
+
+#[test]
+fn rename_file_returns_the_new_path() {
+    let dir = TempDir::new().unwrap();
+    let (old, taken) = (dir.path().join("a"), dir.path().join("b"));
+    fs::write(&old, "a").unwrap();
+    fs::write(&taken, "b").unwrap();
+    let err = rename_file(&old, "b", false).unwrap_err();
+    assert!(matches!(err, FmanError::AlreadyExists(_)), "{err}");
+    let err = rename_file(&old, "../c", false).unwrap_err();
+    assert!(matches!(err, FmanError::InvalidInput(_)), "{err}");
+    assert_eq!(
+        rename_file(&old, "ünï", false).unwrap(),
+        dir.path().join("ünï")
+    );
+    assert_eq!(
+        rename_file(dir.path().join("ünï"), "b", true).unwrap(),
+        taken
+    );
+    assert_eq!(fs::read_to_string(&taken).unwrap(), "a");
+}
+
+#[test]
+fn public_functions_take_any_path_type() {
+    let dir = TempDir::new().unwrap();
+    let src: PathBuf = dir.path().join("a");
+    fs::write(&src, "a").unwrap();
+    let dst: &Path = &dir.path().join("b");
+    let as_str = dir.path().join("c").to_str().unwrap().to_owned();
+
+    assert_eq!(copy_file_safe(src.clone(), dst).unwrap(), dst);
+    assert_eq!(
+        copy_file_force(dst, as_str.as_str()).unwrap(),
+        Path::new(&as_str)
+    );
+    assert_eq!(file_info(&as_str).unwrap().size, 1);
+    assert_eq!(
+        hash_file(&as_str, HashAlgorithm::Md5).unwrap(),
+        "0cc175b9c0f1b6a831c399e269772661"
+    );
+    delete_file(as_str, false).unwrap();
+    assert!(!dir.path().join("c").exists());
+    assert_eq!(touch(src, true).unwrap(), Touched::Updated);
+}
+
+#[cfg(unix)]
+#[test]
+fn non_utf8_names_round_trip() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let dir = TempDir::new().unwrap();
+    let src = dir.path().join(OsStr::from_bytes(b"caf\xe9"));
+    fs::write(&src, "a").unwrap();
+    let dst = dir.path().join(OsStr::from_bytes(b"\xff\xfe"));
+    assert_eq!(copy_file_safe(&src, &dst).unwrap(), dst);
+    assert_eq!(fs::read(&dst).unwrap(), b"a");
+    let err = copy_file_safe(&src, &dst).unwrap_err();
+    assert!(matches!(err, FmanError::AlreadyExists(_)), "{err}");
+    assert!(err.to_string().contains('\u{fffd}'), "{err}");
+}
+
+#[test]
+fn copying_a_file_onto_itself_is_invalid_input_even_when_forced() {
+    let dir = TempDir::new().unwrap();
+    let src = dir.path().join("a");
+    fs::write(&src, "keep me").unwrap();
+    let same = [
+        src.clone(),
+        dir.path().join("./a"),
+        dir.path().to_path_buf(),
+    ];
+    for dst in &same {
+        for result in [copy_file_safe(&src, dst), copy_file_force(&src, dst)] {
+            let err = result.unwrap_err();
+            assert!(matches!(err, FmanError::InvalidInput(_)), "{dst:?}: {err}");
+            assert!(err.to_string().contains("the same file"), "{err}");
+        }
+    }
+    #[cfg(unix)]
+    {
+        let link = dir.path().join("link");
+        std::os::unix::fs::symlink("a", &link).unwrap();
+        let hard = dir.path().join("hard");
+        fs::hard_link(&src, &hard).unwrap();
+        for dst in [link, hard] {
+            let err = copy_file_force(&src, &dst).unwrap_err();
+            assert!(matches!(err, FmanError::InvalidInput(_)), "{dst:?}: {err}");
+        }
+    }
+    assert_eq!(fs::read_to_string(&src).unwrap(), "keep me");
+}
+
+#[test]
+fn hash_file_rejects_a_directory() {
+    let dir = TempDir::new().unwrap();
+    let err = hash_file(dir.path(), HashAlgorithm::Sha256).unwrap_err();
+    assert!(matches!(err, FmanError::InvalidInput(_)), "{err}");
+    assert_eq!(err.exit_code(), 4);
+}
@@ -0,0 +1,86 @@
+mod common;
+
+use std::fs;
+
+use common::Scratch;
+
+#[test]
+fn link_makes_a_hard_link_by_default() {
+    let scratch = Scratch::new();
+    scratch.write("a", "data");
+    scratch.run(&["link", "a", "b"]).success();
+    fs::write(scratch.path("a"), "changed").unwrap();
+    assert_eq!(scratch.read("b"), "changed");
+}
+
+#[test]
+fn link_refuses_an_existing_destination_without_force() {
+    let scratch = Scratch::new();
+    scratch.write("a", "data");
+    scratch.write("b", "old");
+    scratch.run(&["link", "a", "b"]).fails_with(3);
+    assert_eq!(scratch.read("b"), "old");
+    scratch.run(&["link", "--force", "a", "b"]).success();
+    assert_eq!(scratch.read("b"), "data");
+}
+
+#[test]
+fn link_into_a_directory_keeps_the_name() {
+    let scratch = Scratch::new();
+    scratch.write("a", "data");
+    fs::create_dir(scratch.path("d")).unwrap();
+    scratch.run(&["link", "a", "d"]).success();
+    assert_eq!(scratch.read("d/a"), "data");
+}
+
+#[test]
+fn link_of_a_missing_source_is_not_found() {
+    let scratch = Scratch::new();
+    scratch.run(&["link", "-s", "gone", "b"]).fails_with(2);
+    assert!(fs::symlink_metadata(scratch.path("b")).is_err());
+}
+
+#[test]
+fn a_hard_link_to_a_directory_is_rejected() {
+    let scratch = Scratch::new();
+    fs::create_dir(scratch.path("d")).unwrap();
+    scratch.run(&["link", "d", "e"]).fails_with(4);
+}
+
+#[test]
+fn relative_needs_symbolic() {
+    let scratch = Scratch::new();
+    scratch.write("a", "data");
+    scratch.run(&["link", "--relative", "a", "b"]).fails_with(1);
+    assert!(!scratch.exists("b"));
+}
+
+#[cfg(unix)]
+#[test]
+fn symbolic_links_by_absolute_or_relative_path() {
+    let scratch = Scratch::new();
+    scratch.write("a", "data");
+    fs::create_dir(scratch.path("d")).unwrap();
+    scratch.run(&["link", "-s", "a", "abs"]).success();
+    scratch.run(&["link", "-s", "-r", "a", "d/rel"]).success();
+    let absolute = fs::read_link(scratch.path("abs")).unwrap();
+    assert_eq!(absolute, scratch.path("a").canonicalize().unwrap());
+    assert_eq!(
+        fs::read_link(scratch.path("d/rel")).unwrap(),
+        std::path::Path::new("../a")
+    );
+    assert_eq!(scratch.read("d/rel"), "data");
+}
+
+#[cfg(all(unix, feature = "json"))]
+#[test]
+fn link_reports_the_symlink_target_as_json() {
+    let scratch = Scratch::new();
+    scratch.write("a", "data");
+    let report = scratch
+        .run(&["--json", "link", "--symbolic", "--relative", "a", "b"])
+        .success()
+        .json();
+    assert_eq!(report["destination"], "b");
+    assert_eq!(report["link_target"], "a");
+}
@@ -0,0 +1,130 @@
+mod common;
+
+use common::Scratch;
+
+fn tree(scratch: &Scratch) {
+    scratch.write("d/b", "22");
+    scratch.write("d/sub/a", "1");
+}
+
+#[test]
+fn format_prints_each_entry_with_the_template() {
+    let scratch = Scratch::new();
+    tree(&scratch);
+    let run = scratch
+        .run(&[
+            "ls",
+            "-R",
+            "--format",
+            r"{depth}\t{kind}\t{name}\t{size}",
+            "d",
+        ])
+        .success();
+    let stdout = run.stdout();
+    let mut lines = stdout.lines();
+    assert_eq!(lines.next(), Some("1\tfile\tb\t2"));
+    assert!(lines.next().unwrap().starts_with("1\tdir\tsub\t"));
+    assert_eq!(lines.next(), Some("2\tfile\ta\t1"));
+    assert_eq!(lines.next(), None);
+}
+
+#[test]
+fn an_unknown_field_fails_before_listing() {
+    let scratch = Scratch::new();
+    tree(&scratch);
+    let run = scratch
+        .run(&["ls", "--format", "{nope}", "d"])
+        .fails_with(4);
+    assert!(run.stdout().is_empty());
+    assert!(
+        run.stderr().contains("unknown placeholder {nope}"),
+        "{}",
+        run.stderr()
+    );
+}
+
+#[test]
+fn long_and_format_conflict() {
+    let scratch = Scratch::new();
+    tree(&scratch);
+    scratch
+        .run(&["ls", "--long", "--format", "{name}", "d"])
+        .fails_with(1);
+}
+
+#[test]
+fn unsorted_lists_every_entry() {
+    let scratch = Scratch::new();
+    tree(&scratch);
+    let run = scratch
+        .run(&["ls", "-R", "--unsorted", "--format", "{path}", "d"])
+        .success();
+    let mut paths: Vec<String> = run.stdout().lines().map(str::to_string).collect();
+    paths.sort();
+    assert_eq!(paths, ["d/b", "d/sub", "d/sub/a"]);
+}
+
+#[test]
+fn low_memory_sorting_matches_the_in_memory_listing() {
+    let scratch = Scratch::new();
+    std::fs::create_dir(scratch.path("big")).unwrap();
+    // Reverse creation order so that directory order is not already sorted.
+    for i in (0..50_000).rev() {
+        std::fs::File::create(scratch.path(&format!("big/{i:x}"))).unwrap();
+    }
+    scratch.write("big/sub/inner", "");
+    let in_memory = scratch.run(&["ls", "-R", "big"]).success().stdout();
+    assert_eq!(in_memory.lines().count(), 50_002);
+    let spilled = scratch
+        .run(&[
+            "ls",
+            "-R",
+            "--low-memory",
+            "--max-entries-in-memory",
+            "1000",
+            "big",
+        ])
+        .success()
+        .stdout();
+    assert!(spilled == in_memory, "low-memory listing differs");
+}
+
+#[test]
+fn max_entries_in_memory_needs_low_memory() {
+    let scratch = Scratch::new();
+    tree(&scratch);
+    scratch
+        .run(&["ls", "--max-entries-in-memory", "10", "d"])
+        .fails_with(1);
+}
+
+#[test]
+fn dotfiles_show_only_with_all() {
+    let scratch = Scratch::new();
+    tree(&scratch);
+    scratch.write("d/.hidden", "h");
+    let plain = scratch.run(&["list", "d"]).success().stdout();
+    assert_eq!(plain.lines().collect::<Vec<_>>(), ["b", "sub"]);
+    let all = scratch.run(&["list", "--all", "d"]).success().stdout();
+    assert_eq!(all.lines().collect::<Vec<_>>(), [".hidden", "b", "sub"]);
+}
+
+#[test]
+fn long_adds_size_and_time_columns() {
+    let scratch = Scratch::new();
+    tree(&scratch);
+    let stdout = scratch.run(&["ls", "--long", "d"]).success().stdout();
+    let first = stdout.lines().next().unwrap();
+    let columns: Vec<&str> = first.split_whitespace().collect();
+    assert_eq!(columns[0], "2");
+    assert_eq!(columns.last(), Some(&"b"));
+    assert!(columns.len() >= 3, "{first}");
+}
+
+#[test]
+fn a_file_or_a_missing_path_cannot_be_listed() {
+    let scratch = Scratch::new();
+    tree(&scratch);
+    scratch.run(&["ls", "d/b"]).fails_with(4);
+    scratch.run(&["ls", "missing"]).fails_with(2);
+}
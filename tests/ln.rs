@@ -0,0 +1,125 @@
+mod common;
+
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+
+use common::Scratch;
+
+/// Links under `links` into `old`, which has moved to `new`: `abs` and
+/// `rel` can be fixed, `unmatched` and `missing` cannot.
+fn farm(scratch: &Scratch) -> String {
+    scratch.write("new/a", "a");
+    scratch.write("new/b", "b");
+    fs::create_dir(scratch.path("links")).unwrap();
+    symlink(scratch.path("old/a"), scratch.path("links/abs")).unwrap();
+    symlink("../old/b", scratch.path("links/rel")).unwrap();
+    symlink(scratch.path("elsewhere/a"), scratch.path("links/unmatched")).unwrap();
+    symlink(scratch.path("old/gone"), scratch.path("links/missing")).unwrap();
+    format!(
+        "{}={}",
+        scratch.path("old").display(),
+        scratch.path("new").display()
+    )
+}
+
+fn target(scratch: &Scratch, link: &str) -> PathBuf {
+    fs::read_link(scratch.path(link)).unwrap()
+}
+
+#[test]
+fn fix_dangling_repairs_the_links_a_rule_points_somewhere_real() {
+    let scratch = Scratch::new();
+    let rule = farm(&scratch);
+    let run = scratch
+        .run(&[
+            "ln",
+            "--fix-dangling",
+            "links",
+            "--rewrite",
+            &rule,
+            "--rewrite",
+            "../old=../new",
+        ])
+        .success();
+    assert_eq!(target(&scratch, "links/abs"), scratch.path("new/a"));
+    assert_eq!(target(&scratch, "links/rel"), Path::new("../new/b"));
+    assert_eq!(scratch.read("links/rel"), "b");
+    assert_eq!(target(&scratch, "links/missing"), scratch.path("old/gone"));
+    let stderr = run.stderr();
+    assert!(stderr.contains("unmatched") && stderr.contains("no rewrite rule matches"));
+    assert!(stderr.contains("missing") && stderr.contains("does not exist either"));
+}
+
+#[test]
+fn dry_run_lists_the_repairs_and_changes_nothing() {
+    let scratch = Scratch::new();
+    let rule = farm(&scratch);
+    let run = scratch
+        .run(&[
+            "--dry-run",
+            "ln",
+            "--fix-dangling",
+            "links",
+            "--rewrite",
+            &rule,
+        ])
+        .success();
+    assert!(run.stdout().contains("abs"), "{}", run.stdout());
+    assert_eq!(target(&scratch, "links/abs"), scratch.path("old/a"));
+}
+
+#[test]
+fn a_rewrite_rule_is_required_and_checked() {
+    let scratch = Scratch::new();
+    farm(&scratch);
+    scratch
+        .run(&["ln", "--fix-dangling", "links"])
+        .fails_with(1);
+    scratch
+        .run(&["ln", "--fix-dangling", "links", "--rewrite", "no-equals"])
+        .fails_with(1);
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn json_reports_fixed_and_unfixed_links() {
+    let scratch = Scratch::new();
+    let rule = farm(&scratch);
+    let report = scratch
+        .run(&[
+            "--json",
+            "ln",
+            "--fix-dangling",
+            "links",
+            "--rewrite",
+            &rule,
+        ])
+        .success()
+        .json();
+    assert_eq!(report["operation"], "ln");
+    assert_eq!(report["links"], 4);
+    assert_eq!(report["fixed"].as_array().unwrap().len(), 1);
+    assert_eq!(report["unfixed"].as_array().unwrap().len(), 3);
+}
+
+#[test]
+fn an_unreadable_directory_fails_but_the_rest_is_fixed() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let scratch = Scratch::new();
+    let rule = farm(&scratch);
+    fs::create_dir(scratch.path("links/locked")).unwrap();
+    symlink(scratch.path("old/a"), scratch.path("links/locked/hidden")).unwrap();
+    let locked = scratch.path("links/locked");
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+    let run = scratch.run_unprivileged(&["ln", "--fix-dangling", "links", "--rewrite", &rule]);
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+    let run = run.fails_with(5);
+    assert!(run.stderr().contains("locked"), "{}", run.stderr());
+    assert_eq!(target(&scratch, "links/abs"), scratch.path("new/a"));
+    assert_eq!(
+        target(&scratch, "links/locked/hidden"),
+        scratch.path("old/a")
+    );
+}
@@ -0,0 +1,192 @@
+#![cfg(unix)]
+
+mod common;
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::Scratch;
+use fman::lock::{FileLock, LockMode};
+
+fn hold(scratch: &Scratch, mode: LockMode) -> FileLock {
+    FileLock::acquire(&scratch.path("lock"), mode, None).unwrap()
+}
+
+#[test]
+fn runs_the_command_under_the_lock() {
+    let scratch = Scratch::new();
+    scratch
+        .run(&["lock", "lock", "--", "sh", "-c", "echo ran > out"])
+        .success();
+    assert_eq!(scratch.read("out"), "ran\n");
+    assert!(scratch.exists("lock"));
+}
+
+#[test]
+fn passes_on_the_commands_exit_code() {
+    let scratch = Scratch::new();
+    scratch
+        .run(&["lock", "lock", "--", "sh", "-c", "exit 7"])
+        .fails_with(7);
+}
+
+#[test]
+fn a_command_that_cannot_run_is_named_and_exits_like_env() {
+    let scratch = Scratch::new();
+    let run = scratch
+        .run(&["lock", "lock", "--", "fman-no-such-command", "x"])
+        .fails_with(127);
+    assert!(
+        run.stderr().contains("cannot run fman-no-such-command"),
+        "{}",
+        run.stderr()
+    );
+    scratch.write("script", "#!/bin/sh\n");
+    let run = scratch
+        .run(&["lock", "lock", "--", "./script"])
+        .fails_with(126);
+    assert!(
+        run.stderr().contains("cannot run ./script"),
+        "{}",
+        run.stderr()
+    );
+}
+
+#[test]
+fn nonblocking_fails_with_its_own_code_when_the_lock_is_held() {
+    let scratch = Scratch::new();
+    let _held = hold(&scratch, LockMode::Exclusive);
+    let run = scratch
+        .run(&["lock", "--nonblocking", "lock", "--", "touch", "ran"])
+        .fails_with(75);
+    assert!(run.stderr().contains("lock"), "{}", run.stderr());
+    assert!(!scratch.exists("ran"));
+}
+
+#[test]
+fn timeout_gives_up_after_about_that_long() {
+    let scratch = Scratch::new();
+    let _held = hold(&scratch, LockMode::Exclusive);
+    let started = Instant::now();
+    scratch
+        .run(&["lock", "--timeout", "300ms", "lock", "--", "touch", "ran"])
+        .fails_with(75);
+    assert!(started.elapsed() >= Duration::from_millis(300));
+    assert!(!scratch.exists("ran"));
+}
+
+#[test]
+fn waits_for_the_lock_without_a_timeout() {
+    let scratch = Scratch::new();
+    let held = hold(&scratch, LockMode::Exclusive);
+    let releaser = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(200));
+        drop(held);
+    });
+    let started = Instant::now();
+    scratch.run(&["lock", "lock", "--", "true"]).success();
+    assert!(started.elapsed() >= Duration::from_millis(200));
+    releaser.join().unwrap();
+}
+
+#[test]
+fn shared_locks_run_alongside_each_other_but_not_an_exclusive_one() {
+    let scratch = Scratch::new();
+    let shared = hold(&scratch, LockMode::Shared);
+    scratch
+        .run(&["lock", "--shared", "--nonblocking", "lock", "--", "true"])
+        .success();
+    scratch
+        .run(&["lock", "--nonblocking", "lock", "--", "true"])
+        .fails_with(75);
+    drop(shared);
+
+    let _exclusive = hold(&scratch, LockMode::Exclusive);
+    scratch
+        .run(&["lock", "--shared", "--nonblocking", "lock", "--", "true"])
+        .fails_with(75);
+}
+
+#[test]
+fn the_lock_is_released_when_the_command_ends() {
+    let scratch = Scratch::new();
+    scratch.run(&["lock", "lock", "--", "true"]).success();
+    FileLock::acquire(
+        &scratch.path("lock"),
+        LockMode::Exclusive,
+        Some(Duration::ZERO),
+    )
+    .unwrap();
+}
+
+#[test]
+fn nonblocking_and_timeout_conflict() {
+    Scratch::new()
+        .run(&[
+            "lock",
+            "--nonblocking",
+            "--timeout",
+            "1s",
+            "lock",
+            "--",
+            "true",
+        ])
+        .fails_with(1);
+}
+
+#[test]
+fn sync_waits_for_its_lock() {
+    let scratch = Scratch::new();
+    scratch.write("s/a", "a");
+    let held = hold(&scratch, LockMode::Exclusive);
+    let mut sync = scratch.spawn(&["sync", "--lock", "lock", "s", "d"]);
+    thread::sleep(Duration::from_millis(300));
+    assert!(!scratch.exists("d/a"), "synced while the lock was held");
+    drop(held);
+    assert!(sync.wait().unwrap().success());
+    assert_eq!(scratch.read("d/a"), "a");
+}
+
+#[test]
+fn apply_waits_for_its_lock() {
+    let scratch = Scratch::new();
+    scratch.write(
+        "plan.jsonl",
+        "{\"op\":\"create-dir-all\",\"path\":\"out\"}\n",
+    );
+    let held = hold(&scratch, LockMode::Exclusive);
+    let mut apply = scratch.spawn(&["apply", "--lock", "lock", "plan.jsonl"]);
+    thread::sleep(Duration::from_millis(300));
+    assert!(!scratch.exists("out"), "applied while the lock was held");
+    drop(held);
+    assert!(apply.wait().unwrap().success());
+    assert!(scratch.exists("out"));
+}
+
+#[test]
+fn a_read_only_lock_file_can_be_shared_but_not_held_exclusively() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let scratch = Scratch::new();
+    scratch.write("lock", "");
+    std::fs::set_permissions(scratch.path("lock"), std::fs::Permissions::from_mode(0o444)).unwrap();
+    scratch
+        .run_unprivileged(&["lock", "--shared", "lock", "--", "true"])
+        .success();
+    let run = scratch
+        .run_unprivileged(&["lock", "lock", "--", "true"])
+        .fails_with(5);
+    assert!(
+        run.stderr().contains("permission denied: lock"),
+        "{}",
+        run.stderr()
+    );
+    #[cfg(feature = "json")]
+    {
+        let json = scratch
+            .run_unprivileged(&["--json", "lock", "lock", "--", "true"])
+            .fails_with(5)
+            .json();
+        assert_eq!(json["kind"], "permission-denied");
+    }
+}
@@ -0,0 +1,71 @@
+mod common;
+
+use common::Scratch;
+
+#[cfg(feature = "tracing")]
+#[test]
+fn debug_logs_each_validation_step_and_the_copy() {
+    let scratch = Scratch::new();
+    scratch.write("a", "abc");
+    let run = scratch
+        .run(&["--log-level", "debug", "copy", "a", "b"])
+        .success();
+    let stderr = run.stderr();
+    for step in ["ensure_exists", "ensure_is_file", "ensure_not_exists"] {
+        assert!(
+            stderr
+                .lines()
+                .any(|line| line.contains("DEBUG") && line.contains(step)),
+            "{step} missing from:\n{stderr}"
+        );
+    }
+    assert!(
+        stderr
+            .lines()
+            .any(|line| line.contains("INFO") && line.contains("src=a dst=b bytes=3")),
+        "{stderr}"
+    );
+    assert_eq!(run.stdout(), "");
+}
+
+#[cfg(feature = "tracing")]
+#[test]
+fn a_failure_is_logged_as_an_error() {
+    let scratch = Scratch::new();
+    scratch.write("a", "a");
+    scratch.write("b", "b");
+    let run = scratch
+        .run(&["--log-level", "error", "copy", "a", "b"])
+        .fails_with(3);
+    let stderr = run.stderr();
+    assert!(
+        stderr
+            .lines()
+            .any(|line| line.contains("ERROR") && line.contains("already exists: b")),
+        "{stderr}"
+    );
+    assert!(
+        !stderr.contains("DEBUG") && !stderr.contains("INFO"),
+        "{stderr}"
+    );
+}
+
+#[cfg(feature = "tracing")]
+#[test]
+fn nothing_is_logged_without_a_level() {
+    let scratch = Scratch::new();
+    scratch.write("a", "a");
+    let run = scratch.run(&["copy", "a", "b"]).success();
+    assert_eq!(run.stderr(), "");
+}
+
+#[cfg(not(feature = "tracing"))]
+#[test]
+fn log_level_needs_the_tracing_feature() {
+    let scratch = Scratch::new();
+    scratch.write("a", "a");
+    scratch
+        .run(&["--log-level", "debug", "copy", "a", "b"])
+        .fails_with(1);
+    assert!(!scratch.exists("b"));
+}
@@ -0,0 +1,55 @@
+#![cfg(unix)]
+
+mod common;
+
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+
+use common::Scratch;
+
+/// A tree `s` of five pipes, none of which a recursive copy will read.
+fn pipes() -> Scratch {
+    let scratch = Scratch::new();
+    std::fs::create_dir(scratch.path("s")).unwrap();
+    for i in 1..=5 {
+        let path = CString::new(scratch.path(&format!("s/p{i}")).as_os_str().as_bytes()).unwrap();
+        // SAFETY: `path` is a valid NUL-terminated string.
+        assert_eq!(unsafe { libc::mkfifo(path.as_ptr(), 0o644) }, 0);
+    }
+    scratch
+}
+
+#[test]
+fn failures_are_digested_by_kind() {
+    let scratch = pipes();
+    let run = scratch.run(&["copy", "-r", "s", "d"]).fails_with(4);
+    let stderr = run.stderr();
+    assert!(
+        stderr.contains("5 failures\n  invalid-input (5):\n"),
+        "{stderr}"
+    );
+    assert!(stderr.contains("and 2 more"), "{stderr}");
+}
+
+#[test]
+fn max_errors_caps_what_is_kept_but_not_the_log() {
+    let scratch = pipes();
+    let run = scratch
+        .run(&[
+            "--max-errors",
+            "2",
+            "--error-log",
+            "errors.log",
+            "copy",
+            "-r",
+            "s",
+            "d",
+        ])
+        .fails_with(10);
+    let stderr = run.stderr();
+    assert!(stderr.contains("5 failures"), "{stderr}");
+    assert!(stderr.contains("and 3 more"), "{stderr}");
+    let log = scratch.read("errors.log");
+    assert_eq!(log.lines().count(), 5, "{log}");
+    assert!(log.lines().all(|line| line.starts_with("invalid-input: ")));
+}
@@ -0,0 +1,219 @@
+mod common;
+
+use std::fs::{self, File, Permissions};
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::SystemTime;
+
+use common::Scratch;
+use fman::fs::{Fs, RealFs};
+use fman::metadata::MetadataPolicy;
+use fman::ops::{self, CopyOptions, CopyRequest};
+
+/// The real filesystem, except that changing permissions fails with
+/// `ENOTSUP`, as on some SMB mounts.
+#[derive(Debug, Default)]
+struct NoChmodFs {
+    chmods: AtomicUsize,
+}
+
+impl NoChmodFs {
+    fn refuse(&self) -> io::Result<()> {
+        self.chmods.fetch_add(1, Ordering::SeqCst);
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+impl Fs for NoChmodFs {
+    fn create_file(&self, path: &Path, create_new: bool) -> io::Result<Option<File>> {
+        RealFs.create_file(path, create_new)
+    }
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        RealFs.rename(from, to)
+    }
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        RealFs.remove_file(path)
+    }
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        RealFs.remove_dir(path)
+    }
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        RealFs.remove_dir_all(path)
+    }
+    fn create_dir(&self, path: &Path) -> io::Result<()> {
+        RealFs.create_dir(path)
+    }
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        RealFs.create_dir_all(path)
+    }
+    fn set_permissions(&self, _: &Path, _: Permissions) -> io::Result<()> {
+        self.refuse()
+    }
+    fn set_times(
+        &self,
+        path: &Path,
+        accessed: Option<SystemTime>,
+        modified: Option<SystemTime>,
+    ) -> io::Result<()> {
+        RealFs.set_times(path, accessed, modified)
+    }
+    fn set_owner(&self, path: &Path, uid: Option<u32>, gid: Option<u32>) -> io::Result<()> {
+        RealFs.set_owner(path, uid, gid)
+    }
+    fn set_xattr(&self, path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+        RealFs.set_xattr(path, name, value)
+    }
+    fn remove_xattr(&self, path: &Path, name: &str) -> io::Result<()> {
+        RealFs.remove_xattr(path, name)
+    }
+    fn symlink(&self, target: &Path, link: &Path) -> io::Result<()> {
+        RealFs.symlink(target, link)
+    }
+    fn hard_link(&self, original: &Path, link: &Path) -> io::Result<()> {
+        RealFs.hard_link(original, link)
+    }
+    fn set_file_permissions(&self, _: &Path, _: &File, _: Permissions) -> io::Result<()> {
+        self.refuse()
+    }
+    fn set_file_times(
+        &self,
+        path: &Path,
+        file: &File,
+        accessed: Option<SystemTime>,
+        modified: Option<SystemTime>,
+    ) -> io::Result<()> {
+        RealFs.set_file_times(path, file, accessed, modified)
+    }
+    fn set_file_owner(
+        &self,
+        path: &Path,
+        file: &File,
+        uid: Option<u32>,
+        gid: Option<u32>,
+    ) -> io::Result<()> {
+        RealFs.set_file_owner(path, file, uid, gid)
+    }
+}
+
+/// Twenty read-only files, whose mode a fresh copy never has.
+fn read_only_tree(scratch: &Scratch) {
+    for i in 0..20 {
+        let path = scratch.write(&format!("s/{i:02}"), &format!("file {i}\n"));
+        let mut permissions = fs::metadata(&path).unwrap().permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(&path, permissions).unwrap();
+    }
+}
+
+#[test]
+fn unsupported_chmod_warns_once_then_stops_trying() {
+    let scratch = Scratch::new();
+    read_only_tree(&scratch);
+    let filesystem = Arc::new(NoChmodFs::default());
+    let options = CopyOptions::new()
+        .fs(filesystem.clone())
+        .metadata_policy(MetadataPolicy::new().max_warnings(1).probe_attempts(3));
+    let report =
+        ops::copy_dir(&CopyRequest::new(scratch.path("s"), scratch.path("d")).options(options))
+            .unwrap();
+
+    assert_eq!(report.files, 20);
+    for i in 0..20 {
+        assert_eq!(scratch.read(&format!("d/{i:02}")), format!("file {i}\n"));
+    }
+    let metadata = report.metadata.expect("attribute failures are reported");
+    assert_eq!(metadata.warnings.len(), 1);
+    assert_eq!(metadata.warnings[0].attribute.as_str(), "mode");
+    assert_eq!(metadata.disabled.len(), 1);
+    assert_eq!(metadata.omitted, 1);
+    assert_eq!(metadata.skipped, 17);
+    assert_eq!(filesystem.chmods.load(Ordering::SeqCst), 3);
+}
+
+#[test]
+fn strict_metadata_keeps_unsupported_chmod_an_error() {
+    let scratch = Scratch::new();
+    read_only_tree(&scratch);
+    let options = CopyOptions::new()
+        .fs(Arc::new(NoChmodFs::default()))
+        .metadata_policy(MetadataPolicy::strict());
+    let err =
+        ops::copy_dir(&CopyRequest::new(scratch.path("s"), scratch.path("d")).options(options))
+            .unwrap_err();
+    assert_eq!(err.exit_code(), 10);
+}
+
+/// The owner `run_unprivileged` copies files as.
+#[cfg(unix)]
+fn unprivileged_uid() -> u32 {
+    // SAFETY: geteuid has no preconditions.
+    match unsafe { libc::geteuid() } {
+        0 => 65534,
+        uid => uid,
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn a_copy_that_cannot_change_owners_warns_and_succeeds() {
+    let scratch = Scratch::new();
+    for i in 0..12 {
+        scratch.write(&format!("s/{i:02}"), "x");
+    }
+    let map = format!("{}:0", unprivileged_uid());
+    let run = scratch
+        .run_unprivileged(&["copy", "-r", "--ownership-map", &map, "s", "d"])
+        .success();
+    let stderr = run.stderr();
+    assert_eq!(
+        stderr.matches("cannot set ownership").count(),
+        5,
+        "{stderr}"
+    );
+    assert_eq!(
+        stderr.matches("stopped setting ownership").count(),
+        1,
+        "{stderr}"
+    );
+    assert_eq!(scratch.read("d/11"), "x");
+}
+
+#[cfg(unix)]
+#[test]
+fn strict_metadata_fails_a_copy_that_cannot_change_owners() {
+    let scratch = Scratch::new();
+    scratch.write("a", "x");
+    let map = format!("{}:0", unprivileged_uid());
+    scratch
+        .run_unprivileged(&[
+            "copy",
+            "--strict-metadata",
+            "--ownership-map",
+            &map,
+            "a",
+            "b",
+        ])
+        .fails_with(5);
+}
+
+#[cfg(all(unix, feature = "json"))]
+#[test]
+fn a_denied_owner_change_is_a_permission_error() {
+    let scratch = Scratch::new();
+    scratch.write("a", "x");
+    let map = format!("{}:0", unprivileged_uid());
+    let run = scratch
+        .run_unprivileged(&[
+            "--json",
+            "copy",
+            "--strict-metadata",
+            "--ownership-map",
+            &map,
+            "a",
+            "b",
+        ])
+        .fails_with(5);
+    assert_eq!(run.json()["kind"], "permission-denied", "{}", run.stdout());
+}
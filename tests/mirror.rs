@@ -0,0 +1,103 @@
+#![cfg(unix)]
+
+mod common;
+
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use common::Scratch;
+
+fn chmod(path: &Path, mode: u32) {
+    fs::set_permissions(path, fs::Permissions::from_mode(mode)).unwrap();
+}
+
+fn mode(path: &Path) -> u32 {
+    fs::metadata(path).unwrap().permissions().mode() & 0o7777
+}
+
+fn trees() -> Scratch {
+    let scratch = Scratch::new();
+    scratch.write("ref/a", "reference");
+    scratch.write("ref/gone", "");
+    scratch.write("target/a", "restored");
+    chmod(&scratch.path("ref/a"), 0o640);
+    chmod(&scratch.path("target/a"), 0o666);
+    scratch
+}
+
+#[test]
+fn modes_are_mirrored_and_gaps_reported() {
+    let scratch = trees();
+    let run = scratch
+        .run(&["mirror-permissions", "--what", "mode", "ref", "target"])
+        .success();
+    assert_eq!(mode(&scratch.path("target/a")), 0o640);
+    assert_eq!(scratch.read("target/a"), "restored");
+    assert!(
+        run.stderr().contains("gone: missing from target"),
+        "{}",
+        run.stderr()
+    );
+}
+
+#[test]
+fn a_dry_run_shows_before_and_after() {
+    let scratch = trees();
+    let stdout = scratch
+        .run(&[
+            "--dry-run",
+            "mirror-permissions",
+            "--what",
+            "mode",
+            "ref",
+            "target",
+        ])
+        .success()
+        .stdout();
+    assert!(stdout.contains("a: mode 0666 -> 0640"), "{stdout}");
+    assert_eq!(mode(&scratch.path("target/a")), 0o666);
+}
+
+#[test]
+fn an_unknown_attribute_is_rejected() {
+    let scratch = trees();
+    scratch
+        .run(&["mirror-permissions", "--what", "colour", "ref", "target"])
+        .fails_with(4);
+    assert_eq!(mode(&scratch.path("target/a")), 0o666);
+}
+
+#[test]
+fn an_owner_that_cannot_be_set_still_leaves_modes_mirrored() {
+    // SAFETY: geteuid has no preconditions.
+    if unsafe { libc::geteuid() } != 0 {
+        // Only root can make a reference owned by someone else.
+        return;
+    }
+    // Outside the scratch tree `run_unprivileged` hands to its user, so
+    // the reference stays root's.
+    let reference = Scratch::new();
+    reference.write("ref/a", "");
+    reference.write("ref/b", "");
+    chmod(&reference.path(""), 0o755);
+    chmod(&reference.path("ref"), 0o755);
+    chmod(&reference.path("ref/a"), 0o640);
+    chmod(&reference.path("ref/b"), 0o600);
+    let scratch = Scratch::new();
+    scratch.write("target/a", "");
+    scratch.write("target/b", "");
+    chmod(&scratch.path("target/a"), 0o666);
+    chmod(&scratch.path("target/b"), 0o666);
+    let reference = reference.path("ref");
+    let run = scratch
+        .run_unprivileged(&["mirror-permissions", reference.to_str().unwrap(), "target"])
+        .fails_with(5);
+    let stderr = run.stderr();
+    assert!(
+        stderr.contains("3 failures\n  permission-denied (3):\n"),
+        "{stderr}"
+    );
+    assert_eq!(mode(&scratch.path("target/a")), 0o640);
+    assert_eq!(mode(&scratch.path("target/b")), 0o600);
+}
@@ -0,0 +1,54 @@
+mod common;
+
+use std::fs::File;
+use std::time::{Duration, SystemTime};
+
+use common::Scratch;
+
+fn set_modified(scratch: &Scratch, rel: &str, when: SystemTime) {
+    File::options()
+        .write(true)
+        .open(scratch.path(rel))
+        .unwrap()
+        .set_modified(when)
+        .unwrap();
+}
+
+/// `s/f` with a sub-second mtime, and `d/f` with the same size but other
+/// contents, dated as a filesystem keeping whole seconds would store it.
+fn truncated_copy(scratch: &Scratch) {
+    let base = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+    scratch.write("s/f", "source");
+    scratch.write("d/f", "stored");
+    set_modified(scratch, "s/f", base + Duration::from_millis(900));
+    set_modified(scratch, "d/f", base);
+}
+
+#[test]
+fn update_within_the_window_copies_nothing() {
+    let scratch = Scratch::new();
+    truncated_copy(&scratch);
+    scratch
+        .run(&["copy", "--update", "--modify-window", "2s", "s/f", "d/f"])
+        .success();
+    assert_eq!(scratch.read("d/f"), "stored");
+    scratch.run(&["copy", "--update", "s/f", "d/f"]).success();
+    assert_eq!(scratch.read("d/f"), "source");
+}
+
+#[test]
+fn sync_within_the_window_copies_nothing() {
+    let scratch = Scratch::new();
+    truncated_copy(&scratch);
+    let run = scratch
+        .run(&["sync", "--modify-window", "2s", "s", "d"])
+        .success();
+    assert!(
+        run.stderr().contains("0 copied, 0 updated, 1 skipped"),
+        "{}",
+        run.stderr()
+    );
+    assert_eq!(scratch.read("d/f"), "stored");
+    scratch.run(&["sync", "s", "d"]).success();
+    assert_eq!(scratch.read("d/f"), "source");
+}
@@ -0,0 +1,171 @@
+mod common;
+
+use common::Scratch;
+
+#[test]
+fn move_renames_a_file() {
+    let scratch = Scratch::new();
+    scratch.write("a.txt", "data");
+    scratch.run(&["move", "a.txt", "b.txt"]).success();
+    assert!(!scratch.exists("a.txt"));
+    assert_eq!(scratch.read("b.txt"), "data");
+}
+
+#[test]
+fn move_into_a_directory_keeps_the_name() {
+    let scratch = Scratch::new();
+    scratch.write("a.txt", "data");
+    scratch.write("d/other", "");
+    scratch.run(&["move", "a.txt", "d"]).success();
+    assert_eq!(scratch.read("d/a.txt"), "data");
+}
+
+#[test]
+fn moving_a_file_onto_itself_keeps_it() {
+    let scratch = Scratch::new();
+    scratch.write("a.txt", "data");
+    for dst in ["a.txt", "./a.txt", "."] {
+        let run = scratch
+            .run(&["move", "--force", "a.txt", dst])
+            .fails_with(4);
+        assert!(run.stderr().contains("same file"), "{}", run.stderr());
+        assert_eq!(scratch.read("a.txt"), "data");
+    }
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn moving_a_file_onto_itself_is_a_same_file_error() {
+    let scratch = Scratch::new();
+    scratch.write("a.txt", "data");
+    let run = scratch
+        .run(&["--json", "move", "a.txt", "a.txt"])
+        .fails_with(4);
+    assert_eq!(run.json()["kind"], "same-file", "{}", run.stdout());
+    assert_eq!(scratch.read("a.txt"), "data");
+}
+
+#[test]
+fn an_existing_destination_needs_force() {
+    let scratch = Scratch::new();
+    scratch.write("a.txt", "new");
+    scratch.write("b.txt", "old");
+    scratch.run(&["move", "a.txt", "b.txt"]).fails_with(3);
+    assert_eq!(scratch.read("b.txt"), "old");
+    scratch
+        .run(&["move", "--force", "a.txt", "b.txt"])
+        .success();
+    assert_eq!(scratch.read("b.txt"), "new");
+    assert!(!scratch.exists("a.txt"));
+}
+
+/// A directory on another filesystem than the scratch directory, where
+/// there is one.
+#[cfg(target_os = "linux")]
+fn other_filesystem(scratch: &Scratch) -> Option<tempfile::TempDir> {
+    use std::os::unix::fs::MetadataExt;
+    let other = tempfile::TempDir::new_in("/dev/shm").ok()?;
+    let device = |path: &std::path::Path| std::fs::metadata(path).unwrap().dev();
+    (device(other.path()) != device(scratch.root())).then_some(other)
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn copy_verify_moves_a_tree_across_filesystems() {
+    let scratch = Scratch::new();
+    let Some(other) = other_filesystem(&scratch) else {
+        return;
+    };
+    scratch.write("s/a", "1");
+    scratch.write("s/sub/b", "2");
+    let dst = other.path().join("d");
+    scratch
+        .run(&["move", "--copy-verify", "s", dst.to_str().unwrap()])
+        .success();
+    assert!(!scratch.exists("s"));
+    assert_eq!(std::fs::read_to_string(dst.join("sub/b")).unwrap(), "2");
+}
+
+#[test]
+fn copy_verify_can_come_from_the_config_file() {
+    let scratch = Scratch::new();
+    scratch.write("config.toml", "copy_verify = true\n");
+    scratch.write("a", "data");
+    let run = scratch
+        .run(&["--config", "config.toml", "move", "a", "b"])
+        .success();
+    assert!(
+        !run.stderr().contains("unknown configuration key"),
+        "{}",
+        run.stderr()
+    );
+    scratch
+        .run(&[
+            "--config",
+            "config.toml",
+            "move",
+            "--no-copy-verify",
+            "b",
+            "c",
+        ])
+        .success();
+    assert_eq!(scratch.read("c"), "data");
+}
+
+#[test]
+fn a_trailing_slash_destination_must_be_a_directory() {
+    let scratch = Scratch::new();
+    scratch.write("f", "x");
+    scratch.write("other", "y");
+    let run = scratch.run(&["move", "f", "backup/"]).fails_with(2);
+    assert!(run.stderr().contains("backup"), "{}", run.stderr());
+    assert!(!scratch.exists("backup"));
+    let run = scratch.run(&["move", "f", "other/"]).fails_with(4);
+    assert!(run.stderr().contains("not a directory"), "{}", run.stderr());
+    assert_eq!(scratch.read("other"), "y");
+    assert_eq!(scratch.read("f"), "x");
+
+    scratch.write("real/.keep", "");
+    scratch.run(&["move", "f", "real/"]).success();
+    assert_eq!(scratch.read("real/f"), "x");
+}
+
+#[test]
+fn a_directory_moved_to_a_trailing_slash_name_is_renamed() {
+    let scratch = Scratch::new();
+    scratch.write("d/in", "z");
+    scratch.run(&["move", "d", "renamed/"]).success();
+    assert_eq!(scratch.read("renamed/in"), "z");
+    assert!(!scratch.exists("d"));
+}
+
+#[test]
+fn a_dot_dot_through_a_missing_directory_is_named_in_full() {
+    let scratch = Scratch::new();
+    scratch.write("file.txt", "x");
+    let run = scratch
+        .run(&["move", "file.txt", "missing/../"])
+        .fails_with(4);
+    assert!(run.stderr().contains("missing/.."), "{}", run.stderr());
+    assert!(run.stderr().contains("does not exist"), "{}", run.stderr());
+    assert!(scratch.exists("file.txt"));
+}
+
+#[cfg(unix)]
+#[test]
+fn moving_out_of_a_read_only_directory_is_permission_denied() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let scratch = Scratch::new();
+    scratch.write("locked/a.txt", "a");
+    std::fs::set_permissions(
+        scratch.path("locked"),
+        std::fs::Permissions::from_mode(0o555),
+    )
+    .unwrap();
+    let run = scratch
+        .run_unprivileged(&["move", "locked/a.txt", "b.txt"])
+        .fails_with(5);
+    assert!(run.stderr().contains("locked/a.txt"), "{}", run.stderr());
+    assert!(scratch.exists("locked/a.txt"));
+}
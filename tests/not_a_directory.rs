@@ -0,0 +1,52 @@
+mod common;
+
+use common::Scratch;
+
+#[track_caller]
+fn assert_names_the_file(scratch: &Scratch, args: &[&str]) {
+    let run = scratch.run(args).fails_with(4);
+    assert!(
+        run.stderr().contains("not a directory: file"),
+        "{}",
+        run.stderr()
+    );
+}
+
+fn scratch() -> Scratch {
+    let scratch = Scratch::new();
+    scratch.write("a.txt", "data");
+    scratch.write("s/b", "tree");
+    scratch.write("file", "");
+    scratch
+}
+
+#[test]
+fn copy_into_a_file_parent_names_it() {
+    let scratch = scratch();
+    assert_names_the_file(&scratch, &["copy", "a.txt", "file/b.txt"]);
+    assert_names_the_file(&scratch, &["copy", "--parents", "a.txt", "file/x/y/b.txt"]);
+    assert_names_the_file(&scratch, &["copy", "-r", "s", "file/x/d"]);
+    assert_eq!(scratch.read("file"), "");
+}
+
+#[test]
+fn move_into_a_file_parent_names_it() {
+    let scratch = scratch();
+    assert_names_the_file(&scratch, &["move", "a.txt", "file/b.txt"]);
+    assert_names_the_file(&scratch, &["move", "s", "file/x/d"]);
+    assert_eq!(scratch.read("a.txt"), "data");
+    assert_eq!(scratch.read("s/b"), "tree");
+}
+
+#[test]
+fn directory_parents_still_work() {
+    let scratch = scratch();
+    scratch.run(&["copy", "a.txt", "s/c.txt"]).success();
+    scratch
+        .run(&["copy", "--parents", "a.txt", "new/deeper/c.txt"])
+        .success();
+    scratch.run(&["copy", "-r", "s", "out"]).success();
+    scratch.run(&["move", "a.txt", "out/a.txt"]).success();
+    assert_eq!(scratch.read("new/deeper/c.txt"), "data");
+    assert_eq!(scratch.read("out/a.txt"), "data");
+}
@@ -0,0 +1,168 @@
+mod common;
+
+use common::Scratch;
+
+#[test]
+fn verbose_prints_each_copy() {
+    let scratch = Scratch::new();
+    scratch.write("a", "a");
+    let run = scratch.run(&["-v", "copy", "a", "b"]).success();
+    assert_eq!(run.stdout(), "a -> b\n");
+}
+
+#[test]
+fn quiet_wins_over_verbose_given_on_another_level() {
+    let scratch = Scratch::new();
+    scratch.write("a", "a");
+    let run = scratch.run(&["-q", "copy", "-v", "a", "b"]).success();
+    assert_eq!(run.stdout(), "");
+    assert_eq!(run.stderr(), "");
+    assert_eq!(scratch.read("b"), "a");
+}
+
+#[test]
+fn quiet_and_verbose_on_one_level_are_a_usage_error() {
+    let scratch = Scratch::new();
+    scratch.write("a", "a");
+    scratch.run(&["-q", "-v", "copy", "a", "b"]).fails_with(1);
+    assert!(!scratch.exists("b"));
+}
+
+#[cfg(not(feature = "json"))]
+#[test]
+fn json_needs_the_json_feature() {
+    let scratch = Scratch::new();
+    scratch.write("a", "a");
+    scratch.run(&["--json", "copy", "a", "b"]).fails_with(1);
+}
+
+#[test]
+fn verbose_prints_the_resolved_destination() {
+    let scratch = Scratch::new();
+    scratch.write("a", "a");
+    std::fs::create_dir(scratch.path("dir")).unwrap();
+    let run = scratch.run(&["-v", "copy", "a", "dir"]).success();
+    assert_eq!(
+        run.stdout(),
+        format!("a -> {}\n", std::path::Path::new("dir").join("a").display())
+    );
+}
+
+#[test]
+fn without_verbose_nothing_is_printed() {
+    let scratch = Scratch::new();
+    scratch.write("a", "a");
+    let run = scratch.run(&["copy", "a", "b"]).success();
+    assert_eq!(run.stdout(), "");
+    assert_eq!(run.stderr(), "");
+}
+
+#[test]
+fn verbose_prints_moves_and_deletes() {
+    let scratch = Scratch::new();
+    scratch.write("a", "a");
+    let moved = scratch.run(&["--verbose", "move", "a", "b"]).success();
+    assert_eq!(moved.stdout(), "a -> b\n");
+    let deleted = scratch.run(&["--verbose", "delete", "b"]).success();
+    assert_eq!(deleted.stdout(), "removed b\n");
+}
+
+#[test]
+fn verbose_errors_go_to_stderr_only() {
+    let scratch = Scratch::new();
+    scratch.write("a", "a");
+    scratch.write("b", "b");
+    let run = scratch.run(&["-v", "copy", "a", "b"]).fails_with(3);
+    assert_eq!(run.stdout(), "");
+    assert!(run.stderr().contains("exists"), "{}", run.stderr());
+}
+
+#[test]
+fn verbose_is_silent_about_what_was_not_done() {
+    let scratch = Scratch::new();
+    scratch.write("a", "a");
+    let forced = scratch
+        .run(&["-v", "delete", "--force", "missing"])
+        .success();
+    assert_eq!(forced.stdout(), "");
+    scratch.run(&["copy", "a", "b"]).success();
+    let updated = scratch.run(&["-v", "copy", "--update", "a", "b"]).success();
+    assert_eq!(updated.stdout(), "");
+}
+
+#[test]
+fn verbose_lines_are_left_out_of_a_dry_run() {
+    let scratch = Scratch::new();
+    scratch.write("a", "a");
+    let run = scratch
+        .run(&["-v", "--dry-run", "copy", "a", "b"])
+        .success();
+    // Only the plan, not a verbose line besides it.
+    assert!(
+        !run.stdout().lines().any(|line| line == "a -> b"),
+        "{}",
+        run.stdout()
+    );
+    assert!(!scratch.exists("b"));
+}
+
+#[test]
+fn quiet_silences_summaries() {
+    let scratch = Scratch::new();
+    scratch.write("src/a", "a");
+    let run = scratch.run(&["sync", "src", "dst"]).success();
+    assert!(run.stderr().contains("1 copied"), "{}", run.stderr());
+    scratch.write("src/b", "b");
+    let run = scratch.run(&["-q", "sync", "src", "dst"]).success();
+    assert_eq!(run.stderr(), "");
+    assert_eq!(run.stdout(), "");
+    assert_eq!(scratch.read("dst/b"), "b");
+}
+
+#[test]
+fn quiet_silences_skip_notices() {
+    let scratch = Scratch::new();
+    scratch.write("a", "new");
+    scratch.write("b", "old");
+    let run = scratch
+        .run_with_input(&["-q", "copy", "-i", "a", "b"], "n\n")
+        .success();
+    assert!(!run.stderr().contains("skipped"), "{}", run.stderr());
+    assert_eq!(scratch.read("b"), "old");
+}
+
+#[test]
+fn quiet_silences_warnings() {
+    let scratch = Scratch::new();
+    scratch.write("a", "a");
+    scratch.write("fman.toml", "colour = \"always\"\n");
+    let run = scratch
+        .run(&["--config", "fman.toml", "copy", "a", "b"])
+        .success();
+    assert!(run.stderr().starts_with("warning: "), "{}", run.stderr());
+    let run = scratch
+        .run(&["-q", "--config", "fman.toml", "copy", "a", "c"])
+        .success();
+    assert_eq!(run.stderr(), "");
+}
+
+#[test]
+fn quiet_keeps_errors() {
+    let scratch = Scratch::new();
+    let run = scratch.run(&["-q", "copy", "missing", "b"]).fails_with(2);
+    assert!(
+        run.stderr().starts_with("error: not found: missing"),
+        "{}",
+        run.stderr()
+    );
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn quiet_keeps_the_json_result() {
+    let scratch = Scratch::new();
+    scratch.write("a", "abc");
+    let run = scratch.run(&["-q", "--json", "copy", "a", "b"]).success();
+    assert_eq!(run.json()["bytes"], 3);
+    assert_eq!(run.stderr(), "");
+}
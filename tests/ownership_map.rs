@@ -0,0 +1,115 @@
+#![cfg(unix)]
+
+mod common;
+
+use std::os::unix::fs::MetadataExt;
+
+use common::Scratch;
+
+/// This process's uid and gid: mapping them to themselves exercises the
+/// chown path without privileges.
+fn ids() -> (u32, u32) {
+    // SAFETY: getuid and getgid have no preconditions.
+    unsafe { (libc::getuid(), libc::getgid()) }
+}
+
+fn owner(scratch: &Scratch, rel: &str) -> (u32, u32) {
+    let meta = std::fs::metadata(scratch.path(rel)).unwrap();
+    (meta.uid(), meta.gid())
+}
+
+#[test]
+fn mapped_owners_are_given_the_new_ids() {
+    let scratch = Scratch::new();
+    scratch.write("s/a", "1");
+    scratch.write("s/sub/b", "2");
+    let (uid, gid) = ids();
+    let spec = format!("{uid}:{uid},{gid}:{gid}");
+    scratch
+        .run(&[
+            "copy",
+            "-r",
+            "--ownership-map",
+            &spec,
+            "--require-mapped",
+            "s",
+            "d",
+        ])
+        .success();
+    assert_eq!(owner(&scratch, "d/a"), (uid, gid));
+    assert_eq!(owner(&scratch, "d/sub/b"), (uid, gid));
+}
+
+#[test]
+fn a_map_file_is_read() {
+    let scratch = Scratch::new();
+    scratch.write("a", "1");
+    let (uid, gid) = ids();
+    scratch.write(
+        "map.toml",
+        &format!("[users]\n{uid} = {uid}\n\n[groups]\n{gid} = {gid}\n"),
+    );
+    scratch
+        .run(&[
+            "copy",
+            "--ownership-map-file",
+            "map.toml",
+            "--require-mapped",
+            "a",
+            "b",
+        ])
+        .success();
+    assert_eq!(owner(&scratch, "b"), (uid, gid));
+}
+
+#[test]
+fn require_mapped_fails_on_an_unmapped_owner() {
+    let scratch = Scratch::new();
+    scratch.write("a", "1");
+    let (uid, _) = ids();
+    let other = uid.wrapping_add(12345);
+    let run = scratch
+        .run(&[
+            "copy",
+            "--ownership-map",
+            &format!("{other}:{uid}"),
+            "--require-mapped",
+            "a",
+            "b",
+        ])
+        .fails_with(4);
+    assert!(
+        run.stderr()
+            .contains(&format!("uid {uid} is not in the ownership map")),
+        "{}",
+        run.stderr()
+    );
+}
+
+#[test]
+fn unmapped_owners_are_copied_as_usual() {
+    let scratch = Scratch::new();
+    scratch.write("a", "1");
+    let (uid, gid) = ids();
+    let other = uid.wrapping_add(12345);
+    scratch
+        .run(&["copy", "--ownership-map", &format!("{other}:0"), "a", "b"])
+        .success();
+    assert_eq!(owner(&scratch, "b"), (uid, gid));
+}
+
+#[test]
+fn bad_maps_are_rejected() {
+    let scratch = Scratch::new();
+    scratch.write("a", "1");
+    scratch
+        .run(&["copy", "--ownership-map", "nonsense", "a", "b"])
+        .fails_with(4);
+    scratch
+        .run(&["copy", "--ownership-map", "1:no-such-user-here", "a", "b"])
+        .fails_with(2);
+    scratch
+        .run(&["copy", "--require-mapped", "a", "b"])
+        .fails_with(1);
+    assert!(!scratch.exists("b"));
+}
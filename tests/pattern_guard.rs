@@ -0,0 +1,99 @@
+mod common;
+
+use common::Scratch;
+
+fn logs(scratch: &Scratch, count: usize) {
+    for i in 0..count {
+        scratch.write(&format!("logs/{i:02}.log"), "");
+    }
+}
+
+#[test]
+fn too_many_matches_without_a_terminal_fail_untouched() {
+    let scratch = Scratch::new();
+    logs(&scratch, 51);
+    let run = scratch.run(&["delete", "logs/*.log"]).fails_with(4);
+    assert!(
+        run.stderr().contains("51 entries, more than 50"),
+        "{}",
+        run.stderr()
+    );
+    assert_eq!(scratch.tree().len(), 52);
+}
+
+#[test]
+fn yes_acts_on_any_number_of_matches() {
+    let scratch = Scratch::new();
+    logs(&scratch, 51);
+    scratch.run(&["delete", "--yes", "logs/*.log"]).success();
+    assert_eq!(scratch.tree(), ["logs/"]);
+}
+
+#[test]
+fn the_threshold_is_configurable() {
+    let scratch = Scratch::new();
+    logs(&scratch, 3);
+    scratch
+        .run(&["delete", "--max-matches-without-confirm", "2", "logs/*.log"])
+        .fails_with(4);
+    scratch
+        .run(&["delete", "--max-matches-without-confirm", "3", "logs/*.log"])
+        .success();
+    assert_eq!(scratch.tree(), ["logs/"]);
+}
+
+#[test]
+fn preview_lists_the_sorted_matches() {
+    let scratch = Scratch::new();
+    logs(&scratch, 12);
+    let run = scratch
+        .run(&["delete", "--preview", "logs/*.log"])
+        .success();
+    let stderr = run.stderr();
+    let mut lines = stderr.lines();
+    let pattern = std::path::Path::new("logs").join("*.log");
+    assert_eq!(
+        lines.next(),
+        Some(format!("{}: 12 matches", pattern.display()).as_str())
+    );
+    let first = std::path::Path::new("logs").join("00.log");
+    assert_eq!(
+        lines.next(),
+        Some(format!("  {}", first.display()).as_str())
+    );
+    assert!(stderr.contains("  ... 2 more"), "{stderr}");
+
+    logs(&scratch, 2);
+    let run = scratch
+        .run(&["delete", "--preview", "--no-preview", "logs/*.log"])
+        .success();
+    assert!(run.stderr().is_empty(), "{}", run.stderr());
+}
+
+#[test]
+fn move_is_guarded_too() {
+    let scratch = Scratch::new();
+    logs(&scratch, 4);
+    std::fs::create_dir(scratch.path("old")).unwrap();
+    let run = scratch
+        .run(&[
+            "move",
+            "--max-matches-without-confirm",
+            "3",
+            "logs/*.log",
+            "old",
+        ])
+        .fails_with(4);
+    assert!(run.stderr().contains("--yes to move"), "{}", run.stderr());
+    scratch
+        .run(&[
+            "move",
+            "-y",
+            "--max-matches-without-confirm",
+            "3",
+            "logs/*.log",
+            "old",
+        ])
+        .success();
+    assert!(scratch.exists("old/03.log"));
+}
@@ -0,0 +1,98 @@
+mod common;
+
+use common::Scratch;
+
+#[test]
+fn rename_changes_only_the_name() {
+    let scratch = Scratch::new();
+    scratch.write("dir/a.txt", "data");
+    scratch.run(&["rename", "dir/a.txt", "b.txt"]).success();
+    assert_eq!(scratch.read("dir/b.txt"), "data");
+    assert!(!scratch.exists("dir/a.txt"));
+}
+
+#[test]
+fn rename_refuses_to_replace_a_sibling_without_force() {
+    let scratch = Scratch::new();
+    scratch.write("a.txt", "a");
+    scratch.write("b.txt", "b");
+    let stderr = scratch
+        .run(&["rename", "a.txt", "b.txt"])
+        .fails_with(3)
+        .stderr();
+    assert!(stderr.contains("b.txt"), "{stderr}");
+    assert_eq!(scratch.read("b.txt"), "b");
+    scratch
+        .run(&["rename", "--force", "a.txt", "b.txt"])
+        .success();
+    assert_eq!(scratch.read("b.txt"), "a");
+    assert!(!scratch.exists("a.txt"));
+}
+
+#[test]
+fn rename_rejects_a_path_for_the_new_name() {
+    let scratch = Scratch::new();
+    scratch.write("a.txt", "a");
+    std::fs::create_dir(scratch.path("sub")).unwrap();
+    let stderr = scratch
+        .run(&["rename", "a.txt", "sub/b.txt"])
+        .fails_with(4)
+        .stderr();
+    assert!(stderr.contains("not a bare file name"), "{stderr}");
+    assert!(scratch.exists("a.txt"));
+    assert!(!scratch.exists("sub/b.txt"));
+}
+
+#[test]
+fn rename_handles_unicode_names() {
+    let scratch = Scratch::new();
+    scratch.write("café.txt", "☕");
+    scratch
+        .run(&["rename", "café.txt", "コーヒー.txt"])
+        .success();
+    assert_eq!(scratch.read("コーヒー.txt"), "☕");
+}
+
+#[test]
+fn rename_of_a_missing_path_is_not_found() {
+    let scratch = Scratch::new();
+    scratch.run(&["rename", "gone", "b"]).fails_with(2);
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn rename_reports_both_paths_as_json() {
+    let scratch = Scratch::new();
+    scratch.write("a", "a");
+    let report = scratch
+        .run(&["--json", "rename", "a", "b"])
+        .success()
+        .json();
+    assert_eq!(report["operation"], "rename");
+    assert_eq!(report["source"], "a");
+    assert_eq!(report["destination"], "b");
+}
+
+#[cfg(unix)]
+#[test]
+fn a_file_in_a_read_only_directory_is_permission_denied() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let scratch = Scratch::new();
+    scratch.write("dir/a.txt", "data");
+    let dir = scratch.path("dir");
+    std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o555)).unwrap();
+    let run = scratch.run_unprivileged(&["rename", "dir/a.txt", "b.txt"]);
+    #[cfg(feature = "json")]
+    let json = scratch.run_unprivileged(&["--json", "rename", "dir/a.txt", "b.txt"]);
+    std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let run = run.fails_with(5);
+    assert!(
+        run.stderr().contains("permission denied: dir/a.txt"),
+        "{}",
+        run.stderr()
+    );
+    #[cfg(feature = "json")]
+    assert_eq!(json.fails_with(5).json()["kind"], "permission-denied");
+    assert_eq!(scratch.read("dir/a.txt"), "data");
+}
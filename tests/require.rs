@@ -0,0 +1,78 @@
+mod common;
+
+use common::Scratch;
+
+/// Whether `fs-info` says the scratch filesystem can make reflinks.
+fn has_reflinks(scratch: &Scratch) -> bool {
+    let stdout = scratch.run(&["fs-info"]).success().stdout();
+    !stdout.contains("reflinks:         no")
+}
+
+#[test]
+fn copy_fails_up_front_on_a_missing_capability() {
+    let scratch = Scratch::new();
+    if has_reflinks(&scratch) {
+        return;
+    }
+    scratch.write("a", "data");
+    let run = scratch.run(&["copy", "--require", "reflink,symlink", "a", "b"]);
+    let stderr = run.fails_with(4).stderr();
+    assert!(
+        stderr.contains("lacks required capabilities: reflink"),
+        "{stderr}"
+    );
+    assert!(!stderr.contains("symlink"), "{stderr}");
+    assert!(!scratch.exists("b"));
+}
+
+#[test]
+fn recursive_copy_creates_nothing_on_a_missing_capability() {
+    let scratch = Scratch::new();
+    if has_reflinks(&scratch) {
+        return;
+    }
+    scratch.write("src/x", "1");
+    scratch.write("src/sub/y", "2");
+    let run = scratch.run(&["copy", "-r", "--require", "reflink", "src", "out/dst"]);
+    run.fails_with(4);
+    assert!(!scratch.exists("out"));
+}
+
+#[test]
+fn sync_copies_nothing_on_a_missing_capability() {
+    let scratch = Scratch::new();
+    if has_reflinks(&scratch) {
+        return;
+    }
+    scratch.write("a/f", "1");
+    scratch.write("a/g", "2");
+    std::fs::create_dir(scratch.path("b")).unwrap();
+    let run = scratch.run(&["sync", "--require", "reflink", "a", "b"]);
+    let stderr = run.fails_with(4).stderr();
+    assert!(stderr.contains("reflink"), "{stderr}");
+    assert_eq!(std::fs::read_dir(scratch.path("b")).unwrap().count(), 0);
+}
+
+#[cfg(unix)]
+#[test]
+fn a_present_capability_lets_the_copy_run() {
+    let scratch = Scratch::new();
+    scratch.write("a", "data");
+    scratch
+        .run(&["copy", "--require", "symlink", "a", "b"])
+        .success();
+    assert_eq!(scratch.read("b"), "data");
+}
+
+#[test]
+fn an_unknown_capability_is_rejected() {
+    let scratch = Scratch::new();
+    scratch.write("a", "data");
+    let run = scratch.run(&["copy", "--require", "teleport", "a", "b"]);
+    let stderr = run.fails_with(4).stderr();
+    assert!(
+        stderr.contains("unknown capability \"teleport\""),
+        "{stderr}"
+    );
+    assert!(!scratch.exists("b"));
+}
@@ -0,0 +1,104 @@
+mod common;
+
+use std::fs::File;
+use std::time::{Duration, SystemTime};
+
+use common::Scratch;
+
+/// `s/fresh` modified a second ago and `s/old` an hour ago.
+fn tree(scratch: &Scratch) {
+    let now = SystemTime::now();
+    for (name, age) in [("fresh", 1), ("old", 3600)] {
+        scratch.write(&format!("s/{name}"), name);
+        let file = File::options()
+            .write(true)
+            .open(scratch.path(&format!("s/{name}")))
+            .unwrap();
+        file.set_modified(now - Duration::from_secs(age)).unwrap();
+    }
+}
+
+#[test]
+fn only_settled_files_are_copied() {
+    let scratch = Scratch::new();
+    tree(&scratch);
+    scratch
+        .run(&["copy", "-r", "--skip-active", "10m", "s", "d"])
+        .success();
+    assert_eq!(scratch.read("d/old"), "old");
+    assert!(!scratch.exists("d/fresh"));
+    #[cfg(feature = "json")]
+    {
+        let report = scratch
+            .run(&["--json", "copy", "-r", "--skip-active", "10m", "s", "j"])
+            .success()
+            .json();
+        assert_eq!(report["files"], 1);
+        assert_eq!(report["skipped_active"], 1);
+    }
+}
+
+#[test]
+fn the_retry_skips_files_still_being_written() {
+    let scratch = Scratch::new();
+    tree(&scratch);
+    let args = [
+        "copy",
+        "-r",
+        "--skip-active",
+        "10m",
+        "--retry-active-at-end",
+        "s",
+        "d",
+    ];
+    scratch.run(&args).success();
+    assert_eq!(scratch.read("d/old"), "old");
+    assert!(!scratch.exists("d/fresh"));
+    #[cfg(feature = "json")]
+    {
+        let mut args = args.to_vec();
+        args.insert(0, "--json");
+        *args.last_mut().unwrap() = "j";
+        let report = scratch.run(&args).success().json();
+        assert_eq!(report["retried_active"], 0);
+        assert_eq!(report["skipped_active"], 1);
+    }
+
+    let run = scratch
+        .run(&["copy", "-r", "--skip-active", "10m", "s", "e"])
+        .success();
+    assert!(
+        run.stderr().contains("skipped 1 files still being written"),
+        "{}",
+        run.stderr()
+    );
+}
+
+#[test]
+fn a_short_window_copies_everything() {
+    let scratch = Scratch::new();
+    tree(&scratch);
+    scratch
+        .run(&["copy", "-r", "--skip-active", "0s", "s", "d"])
+        .success();
+    assert_eq!(scratch.read("d/fresh"), "fresh");
+    assert_eq!(scratch.read("d/old"), "old");
+    #[cfg(feature = "json")]
+    {
+        let report = scratch
+            .run(&["--json", "copy", "-r", "--skip-active", "0s", "s", "j"])
+            .success()
+            .json();
+        assert_eq!(report["files"], 2);
+        assert_eq!(report["skipped_active"], 0);
+    }
+}
+
+#[test]
+fn retry_needs_skip_active() {
+    let scratch = Scratch::new();
+    tree(&scratch);
+    scratch
+        .run(&["copy", "-r", "--retry-active-at-end", "s", "d"])
+        .fails_with(1);
+}
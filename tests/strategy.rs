@@ -0,0 +1,94 @@
+mod common;
+
+use common::Scratch;
+
+/// Files of 100 bytes, 5 KB and 200 KB, one for each strategy with the
+/// thresholds below.
+fn mixed_tree(scratch: &Scratch) {
+    for (name, len) in [
+        ("tiny", 100),
+        ("sub/mid", 5_000),
+        ("sub/deeper/huge", 200_000),
+    ] {
+        let contents: String = (0..len)
+            .map(|i| char::from(b'a' + (i % 26) as u8))
+            .collect();
+        scratch.write(&format!("s/{name}"), &contents);
+    }
+}
+
+const THRESHOLDS: [&str; 4] = [
+    "--small-file-threshold",
+    "1K",
+    "--huge-file-threshold",
+    "64K",
+];
+
+#[test]
+fn a_mixed_tree_copies_exactly_whatever_the_strategy() {
+    let scratch = Scratch::new();
+    mixed_tree(&scratch);
+    let mut args = vec!["copy", "-r", "s", "d"];
+    args.extend(THRESHOLDS);
+    scratch.run(&args).success();
+    for name in ["tiny", "sub/mid", "sub/deeper/huge"] {
+        assert_eq!(
+            scratch.read(&format!("d/{name}")),
+            scratch.read(&format!("s/{name}")),
+            "{name}"
+        );
+    }
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn each_file_reports_its_strategy() {
+    let scratch = Scratch::new();
+    mixed_tree(&scratch);
+    let mut args = vec![
+        "--json",
+        "copy",
+        "-r",
+        "--min-duration-report",
+        "0s",
+        "s",
+        "d",
+    ];
+    args.extend(THRESHOLDS);
+    let report = scratch.run(&args).success().json();
+    let mut strategies: Vec<(String, String)> = report["slow_files"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|file| {
+            let path = file["path"].as_str().unwrap().replace('\\', "/");
+            (path, file["strategy"].as_str().unwrap().to_string())
+        })
+        .collect();
+    strategies.sort();
+    assert_eq!(
+        strategies,
+        [
+            ("s/sub/deeper/huge".into(), "parallel-chunks".into()),
+            ("s/sub/mid".into(), "kernel".into()),
+            ("s/tiny".into(), "buffered".into()),
+        ]
+    );
+}
+
+#[test]
+fn a_single_file_reports_its_strategy() {
+    let scratch = Scratch::new();
+    mixed_tree(&scratch);
+    #[cfg(feature = "json")]
+    {
+        let mut args = vec!["--json", "copy", "s/sub/mid", "m"];
+        args.extend(THRESHOLDS);
+        let report = scratch.run(&args).success().json();
+        assert_eq!(report["strategy"], "kernel");
+    }
+    let mut args = vec!["copy", "--min-duration-report", "0s", "s/tiny", "t"];
+    args.extend(THRESHOLDS);
+    let run = scratch.run(&args).success();
+    assert!(run.stderr().contains("buffered)"), "{}", run.stderr());
+}
@@ -0,0 +1,76 @@
+mod common;
+
+use std::fs;
+
+use common::Scratch;
+
+#[cfg(unix)]
+#[test]
+fn a_named_pipe_is_copied_in_full() {
+    use std::os::unix::ffi::OsStrExt;
+
+    let scratch = Scratch::new();
+    let pipe = scratch.path("pipe");
+    let c_path = std::ffi::CString::new(pipe.as_os_str().as_bytes()).unwrap();
+    // SAFETY: `c_path` is a valid NUL-terminated string.
+    assert_eq!(unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) }, 0);
+    let data = "line\n".repeat(50_000);
+    let writer = {
+        let data = data.clone();
+        std::thread::spawn(move || fs::write(pipe, data).unwrap())
+    };
+    let mut args = vec!["copy", "pipe", "out"];
+    if cfg!(feature = "json") {
+        args.insert(0, "--json");
+    }
+    let run = scratch.run(&args).success();
+    writer.join().unwrap();
+    assert_eq!(scratch.read("out"), data);
+    #[cfg(feature = "json")]
+    {
+        let report = run.json();
+        assert_eq!(report["streamed"], true);
+        assert_eq!(report["bytes"], data.len());
+    }
+    #[cfg(not(feature = "json"))]
+    assert!(run.stdout().is_empty(), "{}", run.stdout());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn a_proc_file_is_copied_with_its_real_length() {
+    let scratch = Scratch::new();
+    scratch
+        .run(&["copy", "/proc/self/cmdline", "cmdline"])
+        .success();
+    let copied = fs::read(scratch.path("cmdline")).unwrap();
+    assert!(!copied.is_empty());
+    #[cfg(feature = "json")]
+    {
+        let report = scratch
+            .run(&["--json", "copy", "/proc/self/cmdline", "again"])
+            .success()
+            .json();
+        assert_eq!(report["streamed"], true);
+        assert_eq!(
+            report["bytes"],
+            fs::read(scratch.path("again")).unwrap().len()
+        );
+    }
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn stream_forces_the_plain_loop_for_regular_files() {
+    let scratch = Scratch::new();
+    scratch.write("f", "hello");
+    let report = scratch
+        .run(&["--json", "copy", "--stream", "f", "g"])
+        .success()
+        .json();
+    assert_eq!(report["streamed"], true);
+    assert_eq!(report["strategy"], "buffered");
+    assert_eq!(report["bytes"], 5);
+    let report = scratch.run(&["--json", "copy", "f", "h"]).success().json();
+    assert_eq!(report["streamed"], false);
+}
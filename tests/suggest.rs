@@ -0,0 +1,50 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::Scratch;
+
+#[test]
+fn a_typo_gets_a_suggestion() {
+    let scratch = Scratch::new();
+    scratch.write("report.txt", "x");
+    std::fs::create_dir(scratch.path("dst")).unwrap();
+    let run = scratch.run(&["copy", "reprot.txt", "dst"]).fails_with(2);
+    assert!(
+        run.stderr().contains("did you mean 'report.txt'?"),
+        "{}",
+        run.stderr()
+    );
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn json_errors_list_the_suggestions() {
+    let scratch = Scratch::new();
+    scratch.write("d/report.txt", "x");
+    let run = scratch
+        .run(&["--json", "delete", "d/reprot.txt"])
+        .fails_with(2);
+    let error: serde_json::Value = serde_json::from_str(&run.stdout()).unwrap();
+    let expected = std::path::Path::new("d").join("report.txt");
+    assert_eq!(
+        error["suggestions"],
+        serde_json::json!([expected.to_str().unwrap()])
+    );
+}
+
+#[test]
+fn a_huge_parent_is_not_scanned() {
+    let scratch = Scratch::new();
+    std::fs::create_dir(scratch.path("big")).unwrap();
+    for i in 0..100_000 {
+        std::fs::File::create(scratch.path(&format!("big/{i}"))).unwrap();
+    }
+    scratch.write("big/report.txt", "x");
+    let started = Instant::now();
+    let run = scratch
+        .run(&["copy", "big/reprot.txt", "out"])
+        .fails_with(2);
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert!(!run.stderr().contains("did you mean"), "{}", run.stderr());
+}
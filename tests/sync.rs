@@ -0,0 +1,188 @@
+mod common;
+
+use std::fs::File;
+use std::time::{Duration, SystemTime};
+
+use common::Scratch;
+
+/// Write `rel` and date it `secs` seconds after an arbitrary point, so runs
+/// do not depend on how fast the test goes.
+fn write_at(scratch: &Scratch, rel: &str, contents: &str, secs: u64) {
+    scratch.write(rel, contents);
+    let when = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000 + secs);
+    File::options()
+        .write(true)
+        .open(scratch.path(rel))
+        .unwrap()
+        .set_modified(when)
+        .unwrap();
+}
+
+fn sync(scratch: &Scratch) -> common::Run {
+    scratch.run(&[
+        "sync",
+        "--bidirectional",
+        "--state-file",
+        "state.json",
+        "a",
+        "b",
+    ])
+}
+
+#[test]
+fn one_way_sync_fills_in_the_destination() {
+    let scratch = Scratch::new();
+    write_at(&scratch, "a/x", "1", 0);
+    write_at(&scratch, "a/sub/y", "2", 0);
+    write_at(&scratch, "b/extra", "3", 0);
+    scratch.run(&["sync", "a", "b"]).success();
+    assert_eq!(scratch.read("b/x"), "1");
+    assert_eq!(scratch.read("b/sub/y"), "2");
+    assert_eq!(scratch.read("b/extra"), "3");
+    assert!(!scratch.exists("a/extra"));
+}
+
+#[test]
+fn edits_on_both_sides_are_merged() {
+    let scratch = Scratch::new();
+    for name in ["notes", "todo", "old", "clash"] {
+        write_at(&scratch, &format!("a/{name}"), name, 0);
+    }
+    write_at(&scratch, "b/from-b", "b", 0);
+    sync(&scratch).success();
+    assert_eq!(scratch.read("a/from-b"), "b");
+    assert_eq!(scratch.read("b/notes"), "notes");
+    assert!(scratch.exists("state.json"));
+
+    write_at(&scratch, "a/notes", "notes edited on a", 100);
+    write_at(&scratch, "b/todo", "todo edited on b", 100);
+    std::fs::remove_file(scratch.path("b/old")).unwrap();
+    write_at(&scratch, "a/clash", "clash from a", 100);
+    write_at(&scratch, "b/clash", "clash from b, later", 200);
+    let run = sync(&scratch).success();
+    assert!(
+        run.stderr().contains("1 deleted, 1 conflicts"),
+        "{}",
+        run.stderr()
+    );
+
+    for side in ["a", "b"] {
+        assert_eq!(scratch.read(&format!("{side}/notes")), "notes edited on a");
+        assert_eq!(scratch.read(&format!("{side}/todo")), "todo edited on b");
+        assert!(!scratch.exists(&format!("{side}/old")));
+        assert_eq!(
+            scratch.read(&format!("{side}/clash")),
+            "clash from b, later"
+        );
+        let copies: Vec<String> = std::fs::read_dir(scratch.path(side))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name.starts_with("clash.conflict-"))
+            .collect();
+        let [copy] = copies.as_slice() else {
+            panic!("{side}: {copies:?}")
+        };
+        assert_eq!(scratch.read(&format!("{side}/{copy}")), "clash from a");
+    }
+
+    // A third run finds nothing to do.
+    let run = sync(&scratch).success();
+    assert!(
+        run.stderr().contains("0 copied, 0 updated"),
+        "{}",
+        run.stderr()
+    );
+}
+
+#[test]
+fn without_a_state_file_deletions_are_undone() {
+    let scratch = Scratch::new();
+    write_at(&scratch, "a/x", "1", 0);
+    scratch
+        .run(&["sync", "--bidirectional", "a", "b"])
+        .success();
+    std::fs::remove_file(scratch.path("b/x")).unwrap();
+    scratch
+        .run(&["sync", "--bidirectional", "a", "b"])
+        .success();
+    assert_eq!(scratch.read("b/x"), "1");
+}
+
+#[test]
+fn a_state_file_needs_bidirectional() {
+    let scratch = Scratch::new();
+    write_at(&scratch, "a/x", "1", 0);
+    scratch
+        .run(&["sync", "--state-file", "state.json", "a", "b"])
+        .fails_with(1);
+}
+
+#[test]
+fn one_way_sync_summarizes_and_skips_what_is_in_step() {
+    let scratch = Scratch::new();
+    write_at(&scratch, "a/new", "1", 0);
+    write_at(&scratch, "a/changed", "22", 100);
+    write_at(&scratch, "a/same", "3", 0);
+    write_at(&scratch, "b/changed", "2", 0);
+    write_at(&scratch, "b/same", "3", 0);
+    let first = scratch.run(&["sync", "a", "b"]).success();
+    assert!(
+        first.stderr().contains("1 copied, 1 updated, 1 skipped"),
+        "{}",
+        first.stderr()
+    );
+    assert_eq!(scratch.read("b/changed"), "22");
+    let second = scratch.run(&["sync", "a", "b"]).success();
+    assert!(
+        second.stderr().contains("0 copied, 0 updated, 3 skipped"),
+        "{}",
+        second.stderr()
+    );
+}
+
+#[test]
+fn a_dry_run_sync_shows_the_plan_and_changes_nothing() {
+    let scratch = Scratch::new();
+    write_at(&scratch, "a/sub/new", "1", 0);
+    std::fs::create_dir(scratch.path("b")).unwrap();
+    let run = scratch.run(&["--dry-run", "sync", "a", "b"]).success();
+    let sep = std::path::MAIN_SEPARATOR;
+    let plan = run.stdout();
+    assert!(
+        plan.contains(&format!("copy a{sep}sub{sep}new -> b{sep}sub{sep}new")),
+        "{plan}"
+    );
+    assert!(!plan.lines().any(|line| line == "mkdir b"), "{plan}");
+    assert!(!scratch.exists("b/sub"));
+}
+
+#[cfg(unix)]
+#[test]
+fn an_unreadable_directory_fails_but_the_rest_is_synced() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let scratch = Scratch::new();
+    write_at(&scratch, "a/x", "1", 0);
+    write_at(&scratch, "a/locked/y", "2", 0);
+    sync(&scratch).success();
+    let baseline = scratch.read("state.json");
+
+    write_at(&scratch, "a/new", "3", 100);
+    let locked = scratch.path("a/locked");
+    std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o000)).unwrap();
+    let run = scratch.run_unprivileged(&[
+        "sync",
+        "--bidirectional",
+        "--state-file",
+        "state.json",
+        "a",
+        "b",
+    ]);
+    std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let run = run.fails_with(5);
+    assert!(run.stderr().contains("locked"), "{}", run.stderr());
+    assert_eq!(scratch.read("b/new"), "3");
+    // What could not be read is not taken for deleted.
+    assert_eq!(scratch.read("b/locked/y"), "2");
+    assert_eq!(scratch.read("state.json"), baseline);
+}
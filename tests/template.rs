@@ -0,0 +1,49 @@
+mod common;
+
+use common::Scratch;
+
+#[test]
+fn template_substitutes_variables() {
+    let scratch = Scratch::new();
+    scratch.write("tpl/{{name}}/main.txt", "hello {{name}}");
+    scratch
+        .run(&["template", "tpl", "out", "--var", "name=app"])
+        .success();
+    assert_eq!(scratch.read("out/app/main.txt"), "hello app");
+}
+
+#[test]
+fn unknown_placeholders_warn_unless_strict() {
+    let scratch = Scratch::new();
+    scratch.write("tpl/a", "{{missing}}");
+    let run = scratch.run(&["template", "tpl", "out"]).success();
+    assert!(
+        run.stderr().contains("unknown placeholder {{missing}}"),
+        "{}",
+        run.stderr()
+    );
+    scratch
+        .run(&["template", "--strict-vars", "tpl", "strict"])
+        .fails_with(4);
+    assert!(!scratch.exists("strict"));
+}
+
+#[test]
+fn a_bad_variable_definition_is_a_usage_error() {
+    let scratch = Scratch::new();
+    scratch.write("tpl/a", "x");
+    scratch
+        .run(&["template", "tpl", "out", "--var", "novalue"])
+        .fails_with(1);
+}
+
+#[test]
+fn existing_files_need_force() {
+    let scratch = Scratch::new();
+    scratch.write("tpl/a", "new");
+    scratch.write("out/a", "old");
+    scratch.run(&["template", "tpl", "out"]).fails_with(3);
+    assert_eq!(scratch.read("out/a"), "old");
+    scratch.run(&["template", "-f", "tpl", "out"]).success();
+    assert_eq!(scratch.read("out/a"), "new");
+}
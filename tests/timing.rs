@@ -0,0 +1,93 @@
+mod common;
+
+use common::Scratch;
+
+#[cfg(feature = "json")]
+#[test]
+fn a_copy_reports_its_phases_and_throughput() {
+    let scratch = Scratch::new();
+    scratch.write("f", &"x".repeat(10_000));
+    let report = scratch.run(&["--json", "copy", "f", "g"]).success().json();
+    let timing = &report["timing"];
+    let seconds = |field: &str| timing[field].as_f64().unwrap();
+    let (wall, data, metadata) = (
+        seconds("wall_seconds"),
+        seconds("data_seconds"),
+        seconds("metadata_seconds"),
+    );
+    assert!(data <= wall, "{timing}");
+    assert!((wall - data - metadata).abs() < 1e-6, "{timing}");
+    if data > 0.0 {
+        let rate = report["bytes_per_second"].as_f64().unwrap();
+        assert!((rate - 10_000.0 / data).abs() / rate < 1e-6, "{report}");
+    }
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn files_under_the_threshold_are_not_reported() {
+    let scratch = Scratch::new();
+    scratch.write("s/a", "1");
+    scratch.write("s/b", "2");
+    let args = [
+        "--json",
+        "copy",
+        "-r",
+        "--min-duration-report",
+        "1h",
+        "s",
+        "d",
+    ];
+    let report = scratch.run(&args).success().json();
+    assert_eq!(report["slow_files"], serde_json::json!([]));
+    assert!(report["timing"]["wall_seconds"].as_f64().unwrap() > 0.0);
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn every_file_is_slow_at_a_zero_threshold() {
+    let scratch = Scratch::new();
+    scratch.write("s/a", "1");
+    scratch.write("s/b", "22");
+    let args = [
+        "--json",
+        "copy",
+        "-r",
+        "--min-duration-report",
+        "0s",
+        "s",
+        "d",
+    ];
+    let report = scratch.run(&args).success().json();
+    let mut slow: Vec<(String, u64)> = report["slow_files"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|file| {
+            assert!(file["seconds"].as_f64().unwrap() >= 0.0);
+            let path = file["path"].as_str().unwrap().replace('\\', "/");
+            (path, file["bytes"].as_u64().unwrap())
+        })
+        .collect();
+    slow.sort();
+    assert_eq!(slow, [("s/a".into(), 1), ("s/b".into(), 2)]);
+}
+
+#[test]
+fn a_slow_file_is_named_on_stderr() {
+    let scratch = Scratch::new();
+    scratch.write("s/b", "22");
+    let run = scratch
+        .run(&["copy", "--min-duration-report", "0s", "s/b", "c"])
+        .success();
+    assert!(run.stderr().contains("slow: s/b took"), "{}", run.stderr());
+}
+
+#[test]
+fn a_bad_threshold_is_a_usage_error() {
+    let scratch = Scratch::new();
+    scratch.write("f", "x");
+    scratch
+        .run(&["copy", "--min-duration-report", "soon", "f", "g"])
+        .fails_with(1);
+}
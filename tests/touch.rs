@@ -0,0 +1,62 @@
+mod common;
+
+use std::fs::File;
+use std::time::{Duration, SystemTime};
+
+use common::Scratch;
+
+fn aged(scratch: &Scratch, rel: &str) {
+    scratch.write(rel, "keep");
+    File::options()
+        .write(true)
+        .open(scratch.path(rel))
+        .unwrap()
+        .set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000))
+        .unwrap();
+}
+
+fn recent(scratch: &Scratch, rel: &str) -> bool {
+    let modified = std::fs::metadata(scratch.path(rel))
+        .unwrap()
+        .modified()
+        .unwrap();
+    modified > SystemTime::now() - Duration::from_secs(60)
+}
+
+#[test]
+fn touch_creates_and_updates_several_paths() {
+    let scratch = Scratch::new();
+    aged(&scratch, "old");
+    let run = scratch.run(&["-v", "touch", "old", "new"]).success();
+    assert_eq!(run.stdout(), "created new\n");
+    assert_eq!(scratch.read("new"), "");
+    assert_eq!(scratch.read("old"), "keep");
+    assert!(recent(&scratch, "old"));
+}
+
+#[test]
+fn no_create_leaves_missing_files_missing() {
+    let scratch = Scratch::new();
+    aged(&scratch, "old");
+    scratch
+        .run(&["touch", "--no-create", "old", "missing"])
+        .success();
+    assert!(!scratch.exists("missing"));
+    assert!(recent(&scratch, "old"));
+    scratch.run(&["touch", "-c", "other"]).success();
+    assert!(!scratch.exists("other"));
+}
+
+#[test]
+fn a_missing_parent_is_not_found_and_named() {
+    let scratch = Scratch::new();
+    let stderr = scratch.run(&["touch", "nodir/file"]).fails_with(2).stderr();
+    assert!(stderr.contains("nodir"), "{stderr}");
+}
+
+#[test]
+fn one_failure_does_not_stop_the_others() {
+    let scratch = Scratch::new();
+    scratch.run(&["touch", "nodir/file", "ok"]).fails_with(2);
+    assert!(scratch.exists("ok"));
+}
@@ -0,0 +1,313 @@
+mod common;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use common::Scratch;
+
+fn trash_root(scratch: &Scratch) -> PathBuf {
+    if cfg!(all(unix, not(target_os = "macos"))) {
+        scratch.home().join(".local/share/Trash")
+    } else {
+        scratch.home().join(".fman-trash")
+    }
+}
+
+/// Trash `old1`, `old2` and `new`, then date the first two back to 2000.
+fn trash_three(scratch: &Scratch) -> PathBuf {
+    for (name, contents) in [("old1", "1"), ("old2", "22"), ("new", "333")] {
+        scratch.write(name, contents);
+        scratch.run(&["delete", "--trash", name]).success();
+    }
+    let root = trash_root(scratch);
+    for name in ["old1", "old2"] {
+        let info = root.join(format!("info/{name}.trashinfo"));
+        let text = fs::read_to_string(&info).unwrap();
+        let (head, _) = text.split_once("DeletionDate=").unwrap();
+        fs::write(&info, format!("{head}DeletionDate=2000-01-01T00:00:00\n")).unwrap();
+    }
+    root
+}
+
+fn remaining(root: &Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(root.join("files"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn trashing_records_where_a_file_came_from() {
+    let scratch = Scratch::new();
+    scratch.write("a b.txt", "data");
+    scratch.run(&["delete", "--trash", "a b.txt"]).success();
+    assert!(!scratch.exists("a b.txt"));
+    let root = trash_root(&scratch);
+    assert_eq!(
+        fs::read_to_string(root.join("files/a b.txt")).unwrap(),
+        "data"
+    );
+    let info = fs::read_to_string(root.join("info/a b.txt.trashinfo")).unwrap();
+    assert!(info.starts_with("[Trash Info]\n"), "{info}");
+    assert!(info.contains("a%20b.txt\n"), "{info}");
+    assert!(info.contains("DeletionDate="), "{info}");
+}
+
+#[test]
+fn empty_removes_only_old_items() {
+    let scratch = Scratch::new();
+    let root = trash_three(&scratch);
+    let run = scratch
+        .run(&["trash", "empty", "--older-than", "30d", "--yes"])
+        .success();
+    assert_eq!(run.stdout(), "reclaimed 3B in 2 items\n");
+    assert_eq!(remaining(&root), ["new"]);
+    assert!(!root.join("info/old1.trashinfo").exists());
+    assert!(root.join("info/new.trashinfo").exists());
+}
+
+#[test]
+fn empty_filters_by_size() {
+    let scratch = Scratch::new();
+    let root = trash_three(&scratch);
+    scratch
+        .run(&[
+            "trash",
+            "empty",
+            "--older-than",
+            "30d",
+            "--larger-than",
+            "1",
+            "--yes",
+        ])
+        .success();
+    assert_eq!(remaining(&root), ["new", "old1"]);
+}
+
+#[test]
+fn a_dry_run_removes_nothing() {
+    let scratch = Scratch::new();
+    let root = trash_three(&scratch);
+    let stdout = scratch
+        .run(&["--dry-run", "trash", "empty", "--older-than", "30d"])
+        .success()
+        .stdout();
+    assert!(
+        stdout.ends_with("would reclaim 3B in 2 items\n"),
+        "{stdout}"
+    );
+    assert_eq!(remaining(&root), ["new", "old1", "old2"]);
+}
+
+#[test]
+fn empty_asks_first() {
+    let scratch = Scratch::new();
+    let root = trash_three(&scratch);
+    let run = scratch.run(&["trash", "empty"]).success();
+    assert!(
+        run.stderr().contains("permanently remove 3 items"),
+        "{}",
+        run.stderr()
+    );
+    assert!(run.stderr().contains("aborted"), "{}", run.stderr());
+    assert_eq!(remaining(&root), ["new", "old1", "old2"]);
+}
+
+#[test]
+fn items_without_a_usable_info_need_force() {
+    let scratch = Scratch::new();
+    let root = trash_three(&scratch);
+    fs::write(root.join("info/old1.trashinfo"), "garbage").unwrap();
+    fs::remove_file(root.join("info/new.trashinfo")).unwrap();
+    let run = scratch.run(&["trash", "empty", "--yes"]).success();
+    assert!(run.stderr().contains("use --force"), "{}", run.stderr());
+    assert_eq!(remaining(&root), ["new", "old1"]);
+    scratch
+        .run(&["trash", "empty", "--yes", "--force"])
+        .success();
+    assert!(remaining(&root).is_empty());
+}
+
+#[test]
+fn equal_names_get_a_numeric_suffix_in_the_trash() {
+    let scratch = Scratch::new();
+    for (dir, contents) in [("x", "1"), ("y", "2"), ("z", "3")] {
+        scratch.write(&format!("{dir}/same.txt"), contents);
+        scratch
+            .run(&["delete", "--trash", &format!("{dir}/same.txt")])
+            .success();
+    }
+    let root = trash_root(&scratch);
+    assert_eq!(remaining(&root), ["same.txt", "same.txt.2", "same.txt.3"]);
+    for (name, contents, dir) in [("same.txt", "1", "x"), ("same.txt.3", "3", "z")] {
+        assert_eq!(
+            fs::read_to_string(root.join("files").join(name)).unwrap(),
+            contents
+        );
+        let info = fs::read_to_string(root.join(format!("info/{name}.trashinfo"))).unwrap();
+        assert!(info.contains(&format!("/{dir}/same.txt\n")), "{info}");
+    }
+}
+
+#[test]
+fn a_directory_is_trashed_whole() {
+    let scratch = Scratch::new();
+    scratch.write("d/a", "a");
+    scratch.write("d/sub/b", "b");
+    scratch.run(&["delete", "--trash", "-r", "d"]).success();
+    assert!(!scratch.exists("d"));
+    let root = trash_root(&scratch);
+    assert_eq!(fs::read_to_string(root.join("files/d/sub/b")).unwrap(), "b");
+}
+
+#[test]
+fn trashing_a_missing_path_creates_nothing() {
+    let scratch = Scratch::new();
+    scratch.run(&["delete", "--trash", "gone"]).fails_with(2);
+    assert!(!trash_root(&scratch).exists());
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+#[test]
+fn xdg_data_home_decides_where_the_trash_is() {
+    let scratch = Scratch::new();
+    scratch.write("f", "data");
+    let data = scratch.path("data");
+    scratch
+        .run_with_env(
+            &["delete", "--trash", "f"],
+            &[("XDG_DATA_HOME", data.to_str().unwrap())],
+        )
+        .success();
+    assert_eq!(
+        fs::read_to_string(data.join("Trash/files/f")).unwrap(),
+        "data"
+    );
+    assert!(!trash_root(&scratch).exists());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn a_trash_on_another_filesystem_gets_a_copy() {
+    use std::os::unix::fs::MetadataExt;
+
+    let scratch = Scratch::new();
+    let Ok(other) = tempfile::TempDir::new_in("/dev/shm") else {
+        return;
+    };
+    let device = |path: &Path| fs::metadata(path).unwrap().dev();
+    if device(other.path()) == device(scratch.root()) {
+        return;
+    }
+    scratch.write("d/f", "data");
+    scratch
+        .run_with_env(
+            &["delete", "--trash", "-r", "d"],
+            &[("XDG_DATA_HOME", other.path().to_str().unwrap())],
+        )
+        .success();
+    assert!(!scratch.exists("d"));
+    let moved = other.path().join("Trash/files/d/f");
+    assert_eq!(fs::read_to_string(moved).unwrap(), "data");
+    assert!(other.path().join("Trash/info/d.trashinfo").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn a_trash_that_cannot_be_created_is_permission_denied() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let scratch = Scratch::new();
+    scratch.write("a", "1");
+    let parent = trash_root(&scratch).parent().unwrap().to_path_buf();
+    fs::create_dir_all(&parent).unwrap();
+    fs::set_permissions(&parent, fs::Permissions::from_mode(0o555)).unwrap();
+    let run = scratch.run_unprivileged(&["delete", "--trash", "a"]);
+    #[cfg(feature = "json")]
+    let json = scratch.run_unprivileged(&["--json", "delete", "--trash", "a"]);
+    fs::set_permissions(&parent, fs::Permissions::from_mode(0o755)).unwrap();
+    let run = run.fails_with(5);
+    assert!(
+        run.stderr().contains("permission denied"),
+        "{}",
+        run.stderr()
+    );
+    #[cfg(feature = "json")]
+    assert_eq!(json.fails_with(5).json()["kind"], "permission-denied");
+    assert_eq!(scratch.read("a"), "1");
+}
+
+#[cfg(unix)]
+#[test]
+fn an_item_that_cannot_be_removed_is_permission_denied() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let scratch = Scratch::new();
+    let root = trash_three(&scratch);
+    let files = root.join("files");
+    fs::set_permissions(&files, fs::Permissions::from_mode(0o555)).unwrap();
+    let args = ["trash", "empty", "--older-than", "30d", "--yes"];
+    let run = scratch.run_unprivileged(&args);
+    #[cfg(feature = "json")]
+    let json = scratch.run_unprivileged(&[&["--json"][..], &args].concat());
+    fs::set_permissions(&files, fs::Permissions::from_mode(0o755)).unwrap();
+    let run = run.fails_with(5);
+    assert!(
+        run.stderr().contains("permission denied"),
+        "{}",
+        run.stderr()
+    );
+    assert!(run.stderr().contains("old1"), "{}", run.stderr());
+    #[cfg(feature = "json")]
+    assert_eq!(json.fails_with(5).json()["kind"], "permission-denied");
+    assert_eq!(remaining(&root), ["new", "old1", "old2"]);
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn empty_reports_json_whatever_it_removes() {
+    let scratch = Scratch::new();
+    let root = trash_three(&scratch);
+    fs::write(root.join("info/old2.trashinfo"), "garbage").unwrap();
+
+    let run = scratch
+        .run(&[
+            "--json",
+            "--dry-run",
+            "trash",
+            "empty",
+            "--older-than",
+            "30d",
+        ])
+        .success();
+    assert!(run.stderr().is_empty(), "{}", run.stderr());
+    let json = run.json();
+    assert_eq!(json["operation"], "trash-empty");
+    assert_eq!(json["removed"], 1);
+    assert_eq!(json["reclaimed_bytes"], 1);
+    let skipped = json["skipped"].as_array().unwrap();
+    assert_eq!(skipped.len(), 1);
+    assert!(
+        skipped[0]["path"].as_str().unwrap().ends_with("old2"),
+        "{json}"
+    );
+    assert_eq!(remaining(&root), ["new", "old1", "old2"]);
+
+    let json = scratch
+        .run(&["--json", "trash", "empty", "--older-than", "30d", "--yes"])
+        .success()
+        .json();
+    assert_eq!(json["removed"], 1);
+    assert_eq!(json["reclaimed_bytes"], 1);
+    assert_eq!(remaining(&root), ["new", "old2"]);
+
+    let json = scratch
+        .run(&["--json", "trash", "empty", "--older-than", "30d", "--yes"])
+        .success()
+        .json();
+    assert_eq!(json["operation"], "trash-empty");
+    assert_eq!(json["removed"], 0);
+    assert_eq!(json["skipped"].as_array().unwrap().len(), 1);
+}
@@ -0,0 +1,173 @@
+mod common;
+
+use std::io::{BufRead, BufReader};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::Scratch;
+
+/// Run `fman watch` with `args` on `dir`, call `change` until the watcher
+/// prints something, and return the lines it printed once quiet.
+fn watch(scratch: &Scratch, args: &[&str], change: impl Fn(u32)) -> Vec<String> {
+    let mut full = vec!["watch", "dir", "--batch", "100ms"];
+    full.extend_from_slice(args);
+    let mut child = scratch.spawn(&full);
+    let stdout = child.stdout.take().unwrap();
+    let (sender, lines) = mpsc::channel();
+    thread::spawn(move || {
+        for line in BufReader::new(stdout).lines() {
+            if sender.send(line.unwrap()).is_err() {
+                break;
+            }
+        }
+    });
+    let mut seen = Vec::new();
+    let deadline = Instant::now() + Duration::from_secs(20);
+    // The watcher reports nothing from before it started, so keep
+    // changing things until it does.
+    let mut attempt = 0;
+    while seen.is_empty() && Instant::now() < deadline {
+        change(attempt);
+        attempt += 1;
+        if let Ok(line) = lines.recv_timeout(Duration::from_millis(500)) {
+            seen.push(line);
+        }
+    }
+    while let Ok(line) = lines.recv_timeout(Duration::from_millis(500)) {
+        seen.push(line);
+    }
+    child.kill().unwrap();
+    child.wait().unwrap();
+    seen
+}
+
+#[test]
+fn prints_one_event_tab_path_line_per_change() {
+    let scratch = Scratch::new();
+    scratch.write("dir/keep", "");
+    let seen = watch(&scratch, &[], |attempt| {
+        scratch.write(&format!("dir/file{attempt}.txt"), "x");
+    });
+    assert!(!seen.is_empty());
+    for line in &seen {
+        let (event, path) = line.split_once('\t').expect("EVENT<TAB>PATH");
+        assert_eq!(event, "created", "{line}");
+        assert!(
+            path.starts_with("dir/file") && path.ends_with(".txt"),
+            "{line}"
+        );
+    }
+}
+
+#[test]
+fn pattern_filters_and_recursive_reaches_subdirectories() {
+    let scratch = Scratch::new();
+    scratch.write("dir/sub/keep", "");
+    let seen = watch(&scratch, &["-r", "--pattern", "*.rs"], |attempt| {
+        scratch.write(&format!("dir/sub/skip{attempt}.txt"), "x");
+        scratch.write(&format!("dir/sub/main{attempt}.rs"), "x");
+    });
+    assert!(!seen.is_empty());
+    for line in &seen {
+        assert!(line.starts_with("created\tdir/sub/main"), "{line}");
+        assert!(line.ends_with(".rs"), "{line}");
+    }
+}
+
+#[test]
+fn a_missing_directory_is_not_found() {
+    let scratch = Scratch::new();
+    scratch.run(&["watch", "nowhere"]).fails_with(2);
+}
+
+#[cfg(all(unix, feature = "json"))]
+#[test]
+fn run_reports_each_command_and_its_exit_status() {
+    let scratch = Scratch::new();
+    scratch.write("dir/keep", "");
+    let seen = watch(&scratch, &["--json", "--run", "false"], |attempt| {
+        scratch.write(&format!("dir/file{attempt}"), "x");
+    });
+    assert!(!seen.is_empty());
+    for line in &seen {
+        let run: serde_json::Value = serde_json::from_str(line).unwrap();
+        assert_eq!(run["argv"], serde_json::json!(["false"]));
+        assert_eq!(run["exit_code"], 1);
+        assert_eq!(run["success"], false);
+        assert!(
+            run["paths"][0].as_str().unwrap().starts_with("dir/file"),
+            "{line}"
+        );
+    }
+}
+
+#[cfg(all(unix, feature = "json"))]
+#[test]
+fn an_initial_run_happens_before_any_change() {
+    let scratch = Scratch::new();
+    scratch.write("dir/keep", "");
+    let seen = watch(
+        &scratch,
+        &["--json", "--initial-run", "--", "touch", "ran"],
+        |_| {},
+    );
+    let run: serde_json::Value = serde_json::from_str(&seen[0]).unwrap();
+    assert_eq!(run["initial"], true);
+    assert_eq!(run["success"], true);
+    assert!(scratch.exists("ran"));
+}
+
+#[test]
+fn an_empty_command_is_rejected() {
+    let scratch = Scratch::new();
+    scratch.write("dir/keep", "");
+    scratch.run(&["watch", "dir", "--run", " "]).fails_with(4);
+    scratch
+        .run(&["--dry-run", "watch", "dir", "--run", "true"])
+        .fails_with(4);
+}
+
+#[test]
+fn a_file_is_not_a_directory_to_watch() {
+    let scratch = Scratch::new();
+    scratch.write("f", "");
+    scratch.run(&["watch", "f"]).fails_with(4);
+}
+
+#[cfg(unix)]
+#[test]
+fn ctrl_c_shuts_the_watcher_down_cleanly() {
+    let scratch = Scratch::new();
+    scratch.write("dir/keep", "");
+    let mut child = scratch.spawn(&["watch", "dir", "--batch", "100ms"]);
+    let stdout = child.stdout.take().unwrap();
+    let (sender, lines) = mpsc::channel();
+    thread::spawn(move || {
+        for line in BufReader::new(stdout).lines() {
+            let _ = sender.send(line.unwrap());
+        }
+    });
+    // An event printed means the interrupt handler is in place.
+    let deadline = Instant::now() + Duration::from_secs(20);
+    let mut attempt = 0;
+    while lines.recv_timeout(Duration::from_millis(500)).is_err() {
+        assert!(Instant::now() < deadline, "the watcher printed nothing");
+        scratch.write(&format!("dir/file{attempt}"), "x");
+        attempt += 1;
+    }
+    // SAFETY: signalling our own child, which has not been waited for.
+    unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGINT) };
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let status = loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            break status;
+        }
+        if Instant::now() > deadline {
+            child.kill().unwrap();
+            panic!("the watcher ignored Ctrl-C");
+        }
+        thread::sleep(Duration::from_millis(50));
+    };
+    assert_eq!(status.code(), Some(0));
+}